@@ -1,19 +1,72 @@
 //! This module includes mock implementations of cryptographic primitives.
 
+use std::fmt;
+use std::ops::BitOr;
+
+use crate::{hash, TransactionId};
+
 /// Represents a simulated cryptographic signature.
 #[derive(Clone, Eq, Hash, PartialEq, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Signature {
     /// Represents a valid signature associated with a specific address.
     /// The application should verify that the signature is from the correct sender, though no actual cryptographic operations are performed.
     Valid(Address),
     /// Represents an invalid signature.
     Invalid,
+    /// Represents signatures by several addresses, as needed to spend a multisig coin.
+    Multi(Vec<Address>),
+    /// A signature by `signer` over only the parts of the transaction `sighash` selects, whose
+    /// digest it carries. It stays valid while those parts are unchanged, see `Transaction::signature_commits`.
+    Committed { signer: Address, sighash: SigHash, digest: TransactionId },
+    /// A signature by `signer` that reveals `preimage`, as needed to redeem a hash-locked coin.
+    Preimage { signer: Address, preimage: Vec<u8> },
+}
+
+/// Which parts of a transaction a `Signature::Committed` signs, modelled on Bitcoin's sighash flags.
+///
+/// `ALL`, `NONE`, and `SINGLE` select all outputs, no outputs, or the output at the signed input's
+/// index. Combined with `ANYONECANPAY`, the signature covers only its own input instead of all of
+/// them, so others can add inputs (and, without `ALL`, outputs) after it was made.
+#[derive(Copy, Clone, Eq, Hash, PartialEq, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SigHash(pub u8);
+
+impl SigHash {
+    pub const ALL: SigHash = SigHash(0x01);
+    pub const NONE: SigHash = SigHash(0x02);
+    pub const SINGLE: SigHash = SigHash(0x03);
+    pub const ANYONECANPAY: SigHash = SigHash(0x80);
+
+    /// The output selection, i.e. the flags without `ANYONECANPAY`.
+    pub fn outputs(self) -> SigHash {
+        SigHash(self.0 & !Self::ANYONECANPAY.0)
+    }
+
+    /// Whether the signature covers only its own input.
+    pub fn anyone_can_pay(self) -> bool {
+        self.0 & Self::ANYONECANPAY.0 != 0
+    }
+
+    /// Whether the flags name one of the three output selections, with or without `ANYONECANPAY`.
+    pub fn is_defined(self) -> bool {
+        matches!(self.outputs(), SigHash::ALL | SigHash::NONE | SigHash::SINGLE)
+    }
+}
+
+impl BitOr for SigHash {
+    type Output = SigHash;
+
+    fn bitor(self, other: SigHash) -> SigHash {
+        SigHash(self.0 | other.0)
+    }
 }
 
 /// Represents a public identifier that can own a coin.
 /// A valid signature from the corresponding address is required to spend a coin.
 /// This enum includes predefined variants for common names and a custom variant for other cases.
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Address {
     Alice,
     Bob,
@@ -21,4 +74,117 @@ pub enum Address {
     Dave,
     Eve,
     Custom(u64),
+    /// A coin owned by this address needs signatures from at least `threshold` of the `signers`.
+    /// Construct it with `Address::multisig` so that the same signer set always yields the same address.
+    Multisig { threshold: usize, signers: Vec<Address> },
+    /// A hash-time-locked coin. `recipient` can spend it by revealing a preimage of `hash` (see
+    /// `hash_preimage`), and `refund` can spend it in any block from `timeout_height` on.
+    HashLock { recipient: Box<Address>, refund: Box<Address>, hash: u64, timeout_height: u64 },
+}
+
+/// The hash a `Address::HashLock` locks a coin with. Like the signatures, it is mocked: the hash
+/// is not cryptographic, only deterministic.
+pub fn hash_preimage(preimage: &[u8]) -> u64 {
+    hash(&preimage)
+}
+
+impl Address {
+    /// The `threshold`-of-n multisig address of the given signers, which are sorted and deduplicated.
+    pub fn multisig(threshold: usize, signers: impl IntoIterator<Item = Address>) -> Self {
+        let mut signers: Vec<Address> = signers.into_iter().collect();
+        signers.sort();
+        signers.dedup();
+        Address::Multisig { threshold, signers }
+    }
+
+    /// Whether the signature authorizes spending a coin owned by this address.
+    /// The timeout of a hash lock is not checked here, see `is_satisfied_at`.
+    pub fn is_satisfied_by(&self, signature: &Signature) -> bool {
+        match (self, signature) {
+            (Address::Multisig { threshold, signers }, Signature::Multi(signed_by)) => {
+                let valid = signers.iter().filter(|signer| signed_by.contains(signer)).count();
+                valid >= *threshold
+            }
+            (Address::HashLock { recipient, hash, .. }, Signature::Preimage { signer, preimage }) => {
+                **recipient == *signer && hash_preimage(preimage) == *hash
+            }
+            (Address::HashLock { refund, .. }, Signature::Valid(signer) | Signature::Committed { signer, .. }) => {
+                **refund == *signer
+            }
+            (address, Signature::Valid(signer)) => address == signer,
+            (address, Signature::Committed { signer, .. }) => address == signer,
+            _ => false,
+        }
+    }
+
+    /// Whether the signature authorizes spending a coin owned by this address in a block at `height`,
+    /// which is only different from `is_satisfied_by` for the refund of a hash lock before its timeout.
+    pub fn is_satisfied_at(&self, signature: &Signature, height: u64) -> bool {
+        match self {
+            Address::HashLock { timeout_height, .. } if !matches!(signature, Signature::Preimage { .. }) => {
+                height >= *timeout_height && self.is_satisfied_by(signature)
+            }
+            _ => self.is_satisfied_by(signature),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Custom(id) => write!(f, "Custom({id})"),
+            Address::Multisig { threshold, signers } => {
+                let signers: Vec<String> = signers.iter().map(Address::to_string).collect();
+                write!(f, "Multisig({threshold} of {})", signers.join(" "))
+            }
+            Address::HashLock { recipient, refund, hash, timeout_height } => {
+                write!(f, "HashLock({recipient} with {hash:016x}, {refund} from {timeout_height})")
+            }
+            named => write!(f, "{named:?}"),
+        }
+    }
+}
+
+#[test]
+fn multisig_needs_threshold_signatures() {
+    let escrow = Address::multisig(2, [Address::Charlie, Address::Alice, Address::Bob, Address::Alice]);
+    assert_eq!(escrow, Address::multisig(2, [Address::Alice, Address::Bob, Address::Charlie]));
+    assert_eq!(escrow.to_string(), "Multisig(2 of Alice Bob Charlie)");
+
+    assert!(!escrow.is_satisfied_by(&Signature::Valid(Address::Alice)));
+    assert!(!escrow.is_satisfied_by(&Signature::Multi(vec![Address::Alice, Address::Alice])));
+    assert!(!escrow.is_satisfied_by(&Signature::Multi(vec![Address::Alice, Address::Eve])));
+    assert!(escrow.is_satisfied_by(&Signature::Multi(vec![Address::Charlie, Address::Alice])));
+    assert!(Address::Bob.is_satisfied_by(&Signature::Valid(Address::Bob)));
+}
+
+#[test]
+fn sighash_flags_combine() {
+    let flags = SigHash::SINGLE | SigHash::ANYONECANPAY;
+    assert_eq!(flags, SigHash(0x83));
+    assert_eq!(flags.outputs(), SigHash::SINGLE);
+    assert!(flags.anyone_can_pay() && flags.is_defined());
+    assert!(!SigHash::ALL.anyone_can_pay());
+    assert!(!SigHash(0x04).is_defined());
+}
+
+#[test]
+fn hash_locks_need_the_preimage_or_the_timeout() {
+    let lock = Address::HashLock {
+        recipient: Box::new(Address::Bob),
+        refund: Box::new(Address::Alice),
+        hash: hash_preimage(b"secret"),
+        timeout_height: 10,
+    };
+    let redeem = |signer, preimage: &[u8]| Signature::Preimage { signer, preimage: preimage.to_vec() };
+
+    assert!(lock.is_satisfied_by(&redeem(Address::Bob, b"secret")));
+    assert!(lock.is_satisfied_at(&redeem(Address::Bob, b"secret"), 0));
+    assert!(!lock.is_satisfied_by(&redeem(Address::Bob, b"guess")));
+    assert!(!lock.is_satisfied_by(&redeem(Address::Alice, b"secret")));
+    assert!(!lock.is_satisfied_by(&Signature::Valid(Address::Bob)));
+    assert!(lock.is_satisfied_by(&Signature::Valid(Address::Alice)));
+    assert!(!lock.is_satisfied_at(&Signature::Valid(Address::Alice), 9));
+    assert!(lock.is_satisfied_at(&Signature::Valid(Address::Alice), 10));
+    assert_eq!(lock.to_string(), format!("HashLock(Bob with {:016x}, Alice from 10)", hash_preimage(b"secret")));
 }
@@ -3,22 +3,66 @@
 //! A block contains:
 //! * A cryptographic link to its parent,
 //! * A number (height) that is one greater than its parent,
+//! * A timestamp set by the block producer,
+//! * A mock difficulty standing in for the proof of work that went into it,
 //! * A body of transactions that facilitate the movement of bones within the economy.
+//!
+//! The first transaction in the body may be a coinbase that mints new bones for the block producer.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::{hash, Coin, CoinId, Transaction};
+
+/// The number of new bones a block producer may mint in the block's coinbase, on top of the collected tips.
+pub const BLOCK_REWARD: u64 = 50;
 
-use crate::{hash, Transaction};
+/// The number of blocks that must be built on top of a coinbase before its coins may be spent.
+pub const COINBASE_MATURITY: u64 = 10;
+
+/// The largest total weight of the transactions in a block.
+pub const MAX_BLOCK_WEIGHT: usize = 256 * 1024;
 
 /// A block in the Bonecoin blockchains.
 /// Unlike traditional blockchains, there is no Header/Body separation here.
-#[derive(Hash, Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
+#[derive(Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Block {
     /// The parent block identifier, creating a cryptographic link within the blockchain.
     pub parent: BlockId,
     /// The height of this block in the chian. (Genesis is 0.)
     pub number: u64,
+    /// The time at which the block was produced, in seconds since the unix epoch. (Genesis is 0.)
+    /// Nothing guarantees timestamps increase along a chain, so consumers should not rely on it.
+    pub timestamp: u64,
+    /// The work the block counts for under the most work fork choice rule. Nothing is actually mined;
+    /// the producer simply claims it. Blocks of difficulty 1 have the ids they had before blocks had one.
+    #[cfg_attr(feature = "serde", serde(default = "unit_difficulty"))]
+    pub difficulty: u64,
     /// The list of user transactions included in the block.
     pub body: Vec<Transaction>,
 }
 
+/// The difficulty of blocks serialized before they carried one.
+#[cfg(feature = "serde")]
+fn unit_difficulty() -> u64 {
+    1
+}
+
+/// Difficulty 1 is left out of the hash, so blocks from before difficulties keep their ids.
+impl Hash for Block {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.parent.hash(state);
+        self.number.hash(state);
+        self.timestamp.hash(state);
+        if self.difficulty != 1 {
+            self.difficulty.hash(state);
+        }
+        self.body.hash(state);
+    }
+}
+
 impl Block {
     /// Calculates the identifier of this block.
     pub fn id(&self) -> BlockId {
@@ -31,11 +75,82 @@ impl Block {
         Self {
             parent: BlockId(0),
             number: 0,
+            timestamp: 0,
+            difficulty: 1,
             body: Vec::new(),
         }
     }
+
+    /// Return the coinbase transaction of this block, if it has one.
+    pub fn coinbase(&self) -> Option<&Transaction> {
+        self.body.first().filter(|tx| tx.is_coinbase())
+    }
+
+    /// The total weight of the block's transactions.
+    pub fn weight(&self) -> usize {
+        self.body.iter().map(Transaction::weight).sum()
+    }
+
+    /// Whether every transaction stays within the transaction limits and the block within `MAX_BLOCK_WEIGHT`.
+    pub fn is_within_limits(&self) -> bool {
+        self.body.iter().all(Transaction::is_within_limits) && self.weight() <= MAX_BLOCK_WEIGHT
+    }
+
+    /// Check the coinbase rules of this block.
+    ///
+    /// Only the first transaction may consume no inputs, it must then be a coinbase creating only
+    /// native bones, and it may create at most `BLOCK_REWARD` plus the tips left by the remaining transactions.
+    /// Since a block does not contain the value of the coins it consumes, the caller
+    /// provides `coin_value` to look up the native bones held by coins created in earlier blocks
+    /// (zero for coins carrying an issued asset).
+    pub fn coinbase_is_valid(&self, coin_value: impl Fn(&CoinId) -> Option<u64>) -> bool {
+        if self.body.iter().enumerate().any(|(index, tx)| tx.inputs.is_empty() && (index > 0 || !tx.is_coinbase())) {
+            return false;
+        }
+
+        // Coins may be spent in the same block that creates them.
+        let mut created_here = HashMap::new();
+        let mut tips: u64 = 0;
+        for (index, tx) in self.body.iter().enumerate() {
+            let created = tx.iter_output_coins_and_ids(self.number);
+            created_here.extend(created.map(|(coin_id, coin)| (coin_id, coin.native_value())));
+
+            if index == 0 && tx.is_coinbase() {
+                continue;
+            }
+
+            // amounts adding up past u64::MAX break the rules rather than wrap around
+            let consumed = tx.iter_input_coin_ids().try_fold(0u64, |total, coin_id| {
+                let value = created_here.get(&coin_id).copied().or_else(|| coin_value(&coin_id))?;
+                total.checked_add(value)
+            });
+            let produced = tx.outputs.iter().map(Coin::native_value).try_fold(0u64, u64::checked_add);
+            let tip = consumed.zip(produced).and_then(|(consumed, produced)| consumed.checked_sub(produced));
+            match tip.and_then(|tip| tips.checked_add(tip)) {
+                Some(total) => tips = total,
+                None => return false,
+            }
+        }
+
+        let minted = match self.coinbase() {
+            Some(tx) => tx.outputs.iter().map(|coin| coin.value).try_fold(0u64, u64::checked_add),
+            None => Some(0),
+        };
+        match (minted, BLOCK_REWARD.checked_add(tips)) {
+            (Some(minted), Some(allowed)) => minted <= allowed,
+            _ => false,
+        }
+    }
 }
 
 /// A unique identifier for a block. It is a wrapper around the hash of the block.
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Debug, Ord, PartialOrd)]
-pub struct BlockId(u64);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockId(pub(crate) u64);
+
+impl fmt::Display for BlockId {
+    /// Formats the underlying hash as fixed width hex, e.g. for logs and exports.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
@@ -0,0 +1,81 @@
+//! Bloom filters a light wallet hands to a node to learn which transactions concern it.
+//!
+//! The filter answers "maybe" for every item inserted into it and, with a chosen probability, for
+//! items that were not. A node matching transactions against it returns everything the wallet needs
+//! plus some unrelated transactions, so it cannot tell which of them actually belong to the wallet.
+//! Items are hashed from their canonical encoding, so wallets and nodes built separately agree on
+//! what a filter matches.
+
+use crate::codec::Encode;
+use crate::Transaction;
+
+/// A probabilistic set of addresses and coin ids.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// An empty filter sized so that, once `expected_items` items are inserted, an item that was not
+    /// inserted matches with probability `false_positive_rate`.
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 1.0);
+        let items = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = (-items * rate.ln() / (ln2 * ln2)).ceil().max(64.0);
+        let hashes = (bit_count / items * ln2).round().clamp(1.0, 32.0) as u32;
+        BloomFilter {
+            bits: vec![0; (bit_count as usize).div_ceil(64)],
+            hashes,
+        }
+    }
+
+    /// Add an item to the filter.
+    pub fn insert(&mut self, item: &impl Encode) {
+        for bit in self.bit_indexes(&item.encode()) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Whether the item may have been inserted. Never false for an item that was.
+    pub fn contains(&self, item: &impl Encode) -> bool {
+        self.bit_indexes(&item.encode()).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Whether the transaction pays an address in the filter or spends a coin in it.
+    pub fn matches_transaction(&self, transaction: &Transaction) -> bool {
+        transaction.outputs.iter().any(|coin| self.contains(&coin.owner))
+            || transaction.inputs.iter().any(|input| self.contains(&input.coin_id))
+    }
+
+    /// The number of bits in the filter.
+    pub fn bit_count(&self) -> usize {
+        self.bits.len() * 64
+    }
+
+    /// Kirsch-Mitzenmacher double hashing over two FNV-1a hashes with different offsets.
+    fn bit_indexes(&self, bytes: &[u8]) -> impl Iterator<Item = usize> {
+        let first = fnv1a(0xcbf29ce484222325, bytes);
+        let second = fnv1a(0x84222325cbf29ce4, bytes) | 1;
+        let bit_count = self.bit_count() as u64;
+        (0..u64::from(self.hashes)).map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % bit_count) as usize)
+    }
+}
+
+fn fnv1a(offset: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(offset, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3))
+}
+
+#[test]
+fn inserted_items_always_match() {
+    use crate::Address;
+
+    let mut filter = BloomFilter::new(50, 0.01);
+    for i in 0..50 {
+        filter.insert(&Address::Custom(i));
+    }
+    assert!((0..50).all(|i| filter.contains(&Address::Custom(i))));
+    let false_positives = (1_000..11_000).filter(|i| filter.contains(&Address::Custom(*i))).count();
+    assert!(false_positives < 300, "{false_positives} false positives in 10000");
+}
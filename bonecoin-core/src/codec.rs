@@ -0,0 +1,597 @@
+//! The canonical binary encoding of bonecoin types.
+//!
+//! The encoding is deliberately simple:
+//! * Integers are fixed width little endian. `usize` is always encoded as a `u64`.
+//! * Sequences and strings are prefixed with their length as a `u64`.
+//! * Enums are prefixed with a one byte tag, followed by the fields of the variant.
+//! * Structs are the concatenation of their fields in declaration order.
+//! * Transactions of version 1 leave their version out, as before transactions were versioned.
+//!   Other versions are prefixed with `u64::MAX`, which no input count can be, and the version.
+//!   Blocks likewise leave out difficulty 1, and put `u64::MAX` and any other difficulty before the body.
+//!
+//! Every value has exactly one encoding, so encoded bytes can be compared and hashed directly.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::{
+    Address, AssetId, Block, BlockId, ChainFixture, Coin, CoinId, Input, SigHash, Signature, SignedMessage, Transaction, TransactionId,
+};
+
+/// Errors that can occur while decoding bytes into a value.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum DecodeError {
+    /// The input ended before the value was complete.
+    UnexpectedEnd,
+    /// An enum tag that does not correspond to any variant.
+    InvalidTag(u8),
+    /// A string that is not valid UTF-8.
+    InvalidUtf8,
+    /// The value was decoded but bytes were left over.
+    TrailingBytes,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::InvalidTag(tag) => write!(f, "invalid tag {tag}"),
+            DecodeError::InvalidUtf8 => write!(f, "invalid UTF-8 in string"),
+            DecodeError::TrailingBytes => write!(f, "trailing bytes after the value"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A type with a canonical binary encoding.
+pub trait Encode {
+    /// Append the encoding of `self` to `out`.
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    /// Return the encoding of `self`.
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+}
+
+/// A type that can be decoded from its canonical binary encoding.
+pub trait Decode: Sized {
+    /// Decode a value from the front of `input`, advancing it past the consumed bytes.
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError>;
+
+    /// Decode a value that must span the entire input.
+    fn decode(mut bytes: &[u8]) -> Result<Self, DecodeError> {
+        let value = Self::decode_from(&mut bytes)?;
+        if !bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(value)
+    }
+}
+
+/// Split `n` bytes off the front of the input.
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < n {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (head, rest) = input.split_at(n);
+    *input = rest;
+    Ok(head)
+}
+
+impl Encode for u8 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Decode for u8 {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(take(input, 1)?[0])
+    }
+}
+
+impl Encode for u16 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u16 {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let bytes = take(input, 2)?;
+        Ok(u16::from_le_bytes(bytes.try_into().expect("took exactly 2 bytes")))
+    }
+}
+
+impl Encode for u64 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u64 {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let bytes = take(input, 8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("took exactly 8 bytes")))
+    }
+}
+
+impl Encode for usize {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode_to(out);
+    }
+}
+
+impl Decode for usize {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(u64::decode_from(input)? as usize)
+    }
+}
+
+impl Encode for bool {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for String {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.len().encode_to(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = usize::decode_from(input)?;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode_from(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.len().encode_to(out);
+        for item in self {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = usize::decode_from(input)?;
+        // don't trust the length prefix for the allocation; every item takes at least one byte
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode_from(input)?);
+        }
+        Ok(items)
+    }
+}
+
+/// Sets are encoded as sorted sequences, which keeps the encoding canonical.
+impl<T: Encode + Ord> Encode for BTreeSet<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.len().encode_to(out);
+        for item in self {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Decode + Ord> Decode for BTreeSet<T> {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Vec::<T>::decode_from(input)?.into_iter().collect())
+    }
+}
+
+/// Maps are encoded as sequences of pairs sorted by key, which keeps the encoding canonical.
+impl<K: Encode + Ord, V: Encode> Encode for BTreeMap<K, V> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.len().encode_to(out);
+        for (key, value) in self {
+            key.encode_to(out);
+            value.encode_to(out);
+        }
+    }
+}
+
+impl<K: Decode + Ord, V: Decode> Decode for BTreeMap<K, V> {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Vec::<(K, V)>::decode_from(input)?.into_iter().collect())
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.0.encode_to(out);
+        self.1.encode_to(out);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode_from(input)?, B::decode_from(input)?))
+    }
+}
+
+impl Encode for Address {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Address::Alice => out.push(0),
+            Address::Bob => out.push(1),
+            Address::Charlie => out.push(2),
+            Address::Dave => out.push(3),
+            Address::Eve => out.push(4),
+            Address::Custom(id) => {
+                out.push(5);
+                id.encode_to(out);
+            }
+            Address::Multisig { threshold, signers } => {
+                out.push(6);
+                threshold.encode_to(out);
+                signers.encode_to(out);
+            }
+            Address::HashLock { recipient, refund, hash, timeout_height } => {
+                out.push(7);
+                recipient.encode_to(out);
+                refund.encode_to(out);
+                hash.encode_to(out);
+                timeout_height.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Address {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(Address::Alice),
+            1 => Ok(Address::Bob),
+            2 => Ok(Address::Charlie),
+            3 => Ok(Address::Dave),
+            4 => Ok(Address::Eve),
+            5 => Ok(Address::Custom(u64::decode_from(input)?)),
+            6 => Ok(Address::Multisig {
+                threshold: usize::decode_from(input)?,
+                signers: Vec::decode_from(input)?,
+            }),
+            7 => Ok(Address::HashLock {
+                recipient: Box::new(Address::decode_from(input)?),
+                refund: Box::new(Address::decode_from(input)?),
+                hash: u64::decode_from(input)?,
+                timeout_height: u64::decode_from(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for Signature {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Signature::Valid(address) => {
+                out.push(0);
+                address.encode_to(out);
+            }
+            Signature::Invalid => out.push(1),
+            Signature::Multi(signers) => {
+                out.push(2);
+                signers.encode_to(out);
+            }
+            Signature::Committed { signer, sighash, digest } => {
+                out.push(3);
+                signer.encode_to(out);
+                sighash.0.encode_to(out);
+                digest.encode_to(out);
+            }
+            Signature::Preimage { signer, preimage } => {
+                out.push(4);
+                signer.encode_to(out);
+                preimage.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Signature {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(Signature::Valid(Address::decode_from(input)?)),
+            1 => Ok(Signature::Invalid),
+            2 => Ok(Signature::Multi(Vec::decode_from(input)?)),
+            3 => Ok(Signature::Committed {
+                signer: Address::decode_from(input)?,
+                sighash: SigHash(u8::decode_from(input)?),
+                digest: TransactionId::decode_from(input)?,
+            }),
+            4 => Ok(Signature::Preimage {
+                signer: Address::decode_from(input)?,
+                preimage: Vec::decode_from(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for BlockId {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.0.encode_to(out);
+    }
+}
+
+impl Decode for BlockId {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(BlockId(u64::decode_from(input)?))
+    }
+}
+
+impl Encode for CoinId {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.0.encode_to(out);
+    }
+}
+
+impl Decode for CoinId {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(CoinId(u64::decode_from(input)?))
+    }
+}
+
+impl Encode for AssetId {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.0.encode_to(out);
+    }
+}
+
+impl Decode for AssetId {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(AssetId(u64::decode_from(input)?))
+    }
+}
+
+impl Encode for TransactionId {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.0.encode_to(out);
+    }
+}
+
+impl Decode for TransactionId {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(TransactionId(u64::decode_from(input)?))
+    }
+}
+
+impl Encode for Coin {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.value.encode_to(out);
+        self.owner.encode_to(out);
+        self.asset_id.encode_to(out);
+    }
+}
+
+impl Decode for Coin {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Coin {
+            value: u64::decode_from(input)?,
+            owner: Address::decode_from(input)?,
+            asset_id: Option::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for SignedMessage {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.address.encode_to(out);
+        self.message.encode_to(out);
+        self.signature.encode_to(out);
+    }
+}
+
+impl Decode for SignedMessage {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(SignedMessage {
+            address: Address::decode_from(input)?,
+            message: Vec::decode_from(input)?,
+            signature: Signature::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for Input {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.coin_id.encode_to(out);
+        self.signature.encode_to(out);
+    }
+}
+
+impl Decode for Input {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Input {
+            coin_id: CoinId::decode_from(input)?,
+            signature: Signature::decode_from(input)?,
+        })
+    }
+}
+
+/// Marks an encoding carrying a field the type's first encoding left out. No length can be this large.
+const EXTENDED: u64 = u64::MAX;
+
+/// Decode the field marked by `EXTENDED` at the front of the input, or return `default` if it is left out.
+/// The default itself has only the encoding that leaves it out.
+fn decode_extension<T: Decode + PartialEq + Into<u64> + Copy>(input: &mut &[u8], default: T) -> Result<T, DecodeError> {
+    let mut peek = *input;
+    if u64::decode_from(&mut peek)? != EXTENDED {
+        return Ok(default);
+    }
+    *input = peek;
+    let value = T::decode_from(input)?;
+    if value == default {
+        return Err(DecodeError::InvalidTag(value.into() as u8));
+    }
+    Ok(value)
+}
+
+impl Encode for Transaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        if self.version != 1 {
+            EXTENDED.encode_to(out);
+            self.version.encode_to(out);
+        }
+        self.inputs.encode_to(out);
+        self.outputs.encode_to(out);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Transaction {
+            version: decode_extension(input, 1u16)?,
+            inputs: Vec::decode_from(input)?,
+            outputs: Vec::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.parent.encode_to(out);
+        self.number.encode_to(out);
+        self.timestamp.encode_to(out);
+        if self.difficulty != 1 {
+            EXTENDED.encode_to(out);
+            self.difficulty.encode_to(out);
+        }
+        self.body.encode_to(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Block {
+            parent: BlockId::decode_from(input)?,
+            number: u64::decode_from(input)?,
+            timestamp: u64::decode_from(input)?,
+            difficulty: decode_extension(input, 1u64)?,
+            body: Vec::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for ChainFixture {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.blocks.encode_to(out);
+        self.best_block.encode_to(out);
+        self.invalidated.encode_to(out);
+        self.now.encode_to(out);
+    }
+}
+
+impl Decode for ChainFixture {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(ChainFixture {
+            blocks: Vec::decode_from(input)?,
+            best_block: BlockId::decode_from(input)?,
+            invalidated: Vec::decode_from(input)?,
+            now: u64::decode_from(input)?,
+        })
+    }
+}
+
+#[test]
+fn block_round_trip() {
+    let tx = Transaction {
+        version: 1,
+        inputs: vec![Input::dummy()],
+        outputs: vec![
+            Coin {
+                value: 7,
+                owner: Address::Custom(42),
+                asset_id: None,
+            },
+            Coin {
+                value: 3,
+                owner: Address::Eve,
+                asset_id: None,
+            },
+        ],
+    };
+    let block = Block {
+        parent: Block::genesis().id(),
+        number: 1,
+        timestamp: 99,
+        difficulty: 1,
+        body: vec![tx.clone(), Transaction::coinbase(Address::Bob, 50)],
+    };
+
+    let bytes = block.encode();
+    assert_eq!(Block::decode(&bytes), Ok(block.clone()));
+    let harder = Block { difficulty: 5, ..block.clone() };
+    assert_eq!(Block::decode(&harder.encode()), Ok(harder.clone()));
+    assert_ne!(harder.id(), block.id());
+    assert_eq!(Transaction::decode(&tx.encode()), Ok(tx));
+}
+
+#[test]
+fn only_later_transaction_versions_are_encoded() {
+    let v1 = Transaction {
+        version: 1,
+        inputs: vec![Input::dummy()],
+        outputs: Vec::new(),
+    };
+    let v2 = Transaction { version: 2, ..v1.clone() };
+    assert_eq!(v1.encode(), (v1.inputs.clone(), v1.outputs.clone()).encode());
+    assert_eq!(Transaction::decode(&v1.encode()), Ok(v1.clone()));
+    assert_eq!(Transaction::decode(&v2.encode()), Ok(v2.clone()));
+    assert_ne!(v1.id(), v2.id());
+
+    let mut prefixed_v1 = (u64::MAX, 1u16).encode();
+    prefixed_v1.extend(v1.encode());
+    assert_eq!(Transaction::decode(&prefixed_v1), Err(DecodeError::InvalidTag(1)));
+}
+
+#[test]
+fn rejects_malformed_input() {
+    let bytes = Address::Custom(1).encode();
+    assert_eq!(Address::decode(&bytes[..4]), Err(DecodeError::UnexpectedEnd));
+    assert_eq!(Address::decode(&[9]), Err(DecodeError::InvalidTag(9)));
+    assert_eq!(Address::decode(&[0, 0]), Err(DecodeError::TrailingBytes));
+    assert_eq!(String::decode(&[1, 0, 0, 0, 0, 0, 0, 0, 0xff]), Err(DecodeError::InvalidUtf8));
+}
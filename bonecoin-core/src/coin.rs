@@ -1,21 +1,90 @@
 //! The basic `Coin` and `CoinId` types that represent bonecoin notes.
 
-use crate::Address;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::{hash, Address};
 
 /// Each coin has a value denominated in bones and an owner's public address.
 /// Creating a coin with zero value is invalid, as it could be freely generated and would waste space in the blockchain's state.
 /// 
 /// A coin is often identified by it's CoinId. Many coins have the same amount and owner.
 /// Therefore a coin's unique CoinId can only be known in the context of the transaction that created it.
-#[derive(Hash, Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
+#[derive(Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Coin {
     /// The value of this coin denominated in bones.
     pub value: u64,
     /// The address that owns this coin and has the authority to spend it.
     pub owner: Address,
+    /// The asset this coin carries, or `None` for native bones.
+    /// Only native bones count towards tips and the block reward.
+    pub asset_id: Option<AssetId>,
+}
+
+/// Native coins hash as they did before coins carried assets, so the ids of their transactions are unchanged.
+/// An asset id is hashed behind a tag, so it can never be read as the fields of the next coin.
+impl Hash for Coin {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+        self.owner.hash(state);
+        if let Some(asset_id) = &self.asset_id {
+            ASSET_TAG.hash(state);
+            asset_id.hash(state);
+        }
+    }
+}
+
+/// Marks the asset id in the hash of a coin carrying one.
+const ASSET_TAG: u8 = 0xA5;
+
+impl Coin {
+    /// Whether this coin carries native bones rather than an issued asset.
+    pub fn is_native(&self) -> bool {
+        self.asset_id.is_none()
+    }
+
+    /// The number of native bones this coin holds, which is zero for coins carrying an issued asset.
+    pub fn native_value(&self) -> u64 {
+        if self.is_native() {
+            self.value
+        } else {
+            0
+        }
+    }
 }
 
 /// A unique identifier for a coin, encapsulating a hash value.
 /// A CoinId is cryptographically linked to the transaction that created the coin, as well its output index within that transaction.
 #[derive(Copy, Hash, Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CoinId(pub(crate) u64);
+
+impl fmt::Display for CoinId {
+    /// Formats the underlying hash as fixed width hex, e.g. for logs and exports.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Identifies an asset issued on top of bonecoin, i.e. a colored coin.
+///
+/// An asset is issued by a transaction spending some coin, and its id is derived from that coin's id.
+/// Since every coin can only be spent once, every asset id can only be issued once.
+#[derive(Copy, Hash, Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AssetId(pub(crate) u64);
+
+impl AssetId {
+    /// The id of the asset issued by the transaction whose first input spends `coin_id`.
+    pub fn issued_by(coin_id: &CoinId) -> Self {
+        AssetId(hash(&("asset", coin_id)))
+    }
+}
+
+impl fmt::Display for AssetId {
+    /// Formats the underlying hash as fixed width hex, e.g. for logs and exports.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
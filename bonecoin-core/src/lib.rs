@@ -4,28 +4,39 @@
 //! A bonecoin has a value and an owner.
 //! Each transaction consumes some bonecoins and creates new bonecoins.
 //! The total value of the coins consumed must be less than or equal to the total value of the coins created.
+//! The only exception is the coinbase, the first transaction of a block, which mints the block reward.
+//! Coins may also carry an issued asset instead of bones, whose value is conserved separately.
 //! A block has some header information, and an ordered list of transaction that move bones around.
 
-use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
 mod address;
 mod block;
+mod bloom;
+pub mod codec;
 mod coin;
+mod message;
 mod node;
+pub mod rng;
+mod sip;
+pub mod test_vectors;
 mod transaction;
 mod wallet;
 
-pub use address::{Address, Signature};
-pub use block::{Block, BlockId};
-pub use coin::{Coin, CoinId};
-pub use node::{MockNode, NodeEndpoint};
-pub use transaction::{Input, Transaction, TransactionId};
-pub use wallet::{WalletApi, WalletError, WalletResult};
+pub use address::{hash_preimage, Address, SigHash, Signature};
+pub use block::{Block, BlockId, BLOCK_REWARD, COINBASE_MATURITY, MAX_BLOCK_WEIGHT};
+pub use bloom::BloomFilter;
+pub use coin::{AssetId, Coin, CoinId};
+pub use message::{message_digest, verify_message, SignedMessage};
+pub use node::{ChainFixture, ForkChoice, MockNode, NodeEndpoint, OverBudget, TieBreak};
+pub use transaction::{
+    Input, Transaction, TransactionId, MAX_TX_INPUTS, MAX_TX_OUTPUTS, MAX_TX_WEIGHT, SUPPORTED_TRANSACTION_VERSIONS, TRANSACTION_VERSION,
+};
+pub use wallet::{TransactionAuthor, WalletApi, WalletError, WalletReader, WalletResult, WalletSync};
 
-/// Simple internal helper to do some hashing.
+/// Simple internal helper to do some hashing, with a hash function fixed across platforms and toolchains.
 fn hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
+    let mut s = sip::SipHasher13::new();
     t.hash(&mut s);
     s.finish()
 }
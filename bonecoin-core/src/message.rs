@@ -0,0 +1,48 @@
+//! Messages signed by an address, proving control of it without a transaction.
+//!
+//! A wallet signs a message with the same key it spends the address's coins with, so anyone can
+//! check that whoever controls the address vouched for the message, e.g. to answer a support
+//! ticket or claim an airdrop. Like transaction signatures, message signatures are mocked: the
+//! signature names its signers but is not bound to the message by any cryptography. Signers are
+//! still asked with `message_digest`, which no transaction id is computed the same way as.
+
+use crate::{hash, Address, Signature, TransactionId};
+
+/// A message together with a signature by the address it claims to come from.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignedMessage {
+    pub address: Address,
+    pub message: Vec<u8>,
+    pub signature: Signature,
+}
+
+/// The digest a signer signs to sign `message` as `address`.
+pub fn message_digest(address: &Address, message: &[u8]) -> TransactionId {
+    TransactionId(hash(&("bonecoin signed message", address, message)))
+}
+
+/// Whether the message's signature proves control of its address: it carries the signatures the
+/// address needs to spend its coins.
+pub fn verify_message(signed: &SignedMessage) -> bool {
+    signed.address.is_satisfied_by(&signed.signature)
+}
+
+#[test]
+fn messages_verify_only_with_the_address_signature() {
+    let signed = |address: Address, signature| SignedMessage {
+        address,
+        message: b"I control this address".to_vec(),
+        signature,
+    };
+    assert!(verify_message(&signed(Address::Alice, Signature::Valid(Address::Alice))));
+    assert!(!verify_message(&signed(Address::Alice, Signature::Valid(Address::Bob))));
+    assert!(!verify_message(&signed(Address::Alice, Signature::Invalid)));
+
+    let escrow = Address::multisig(2, [Address::Alice, Address::Bob, Address::Charlie]);
+    assert!(!verify_message(&signed(escrow.clone(), Signature::Multi(vec![Address::Alice]))));
+    assert!(verify_message(&signed(escrow, Signature::Multi(vec![Address::Alice, Address::Charlie]))));
+
+    assert_ne!(message_digest(&Address::Alice, b"a"), message_digest(&Address::Bob, b"a"));
+    assert_ne!(message_digest(&Address::Alice, b"a"), message_digest(&Address::Alice, b"b"));
+}
@@ -2,8 +2,12 @@
 //! This interface is useful for tools like wallets, indexers, block explorers, etc.
 //! Additionally, it includes a mock Bonecoin node useful for writing unit tests.
 
-use crate::{Block, BlockId, Transaction};
-use std::{collections::HashMap, cell::Cell};
+use crate::{
+    Address, Block, BlockId, BloomFilter, Coin, CoinId, Input, Signature, Transaction, TransactionId, BLOCK_REWARD, COINBASE_MATURITY,
+    TRANSACTION_VERSION,
+};
+use crate::rng::SplitMix64;
+use std::{collections::{HashMap, HashSet}, cell::{Cell, RefCell}, time::Duration};
 /// Defines a common interface for a wallet to interact with a Bonecoin node.
 pub trait NodeEndpoint {
     /// Query the id of of the node's best block at a given height.
@@ -11,6 +15,109 @@ pub trait NodeEndpoint {
 
     /// Fetch the entire body of a block given its block id.
     fn entire_block(&self, id: &BlockId) -> Option<Block>;
+
+    /// Fetch the transactions of a block that match the filter, in block order.
+    ///
+    /// Nodes that support filtering should override this so only the matches cross the wire.
+    /// The default fetches the entire block and filters it locally.
+    fn relevant_transactions(&self, id: &BlockId, filter: &BloomFilter) -> Option<Vec<Transaction>> {
+        let block = self.entire_block(id)?;
+        Some(block.body.into_iter().filter(|tx| filter.matches_transaction(tx)).collect())
+    }
+
+    /// Hand a transaction to the node for relay and mining. Returns false if the node did not accept it,
+    /// which it must not for a transaction of an unsupported version or beyond the transaction limits.
+    ///
+    /// Nodes that cannot relay transactions keep the default, which accepts nothing.
+    fn submit_transaction(&self, _transaction: &Transaction) -> bool {
+        false
+    }
+
+    /// The total difficulty of the block and all its ancestors, genesis included.
+    ///
+    /// Nodes that do not track work keep the default, which reports nothing.
+    fn cumulative_work(&self, _id: &BlockId) -> Option<u64> {
+        None
+    }
+
+    /// The first of the `known` blocks that is on the node's best chain, with its height, or `None`
+    /// if none of them is. Callers pass a block locator: the ids of the blocks they hold, newest first,
+    /// so the answer is the point where their chain and the node's fork.
+    ///
+    /// Nodes should override this to answer in one round trip. The default fetches each block in turn
+    /// to learn its height.
+    fn common_ancestor(&self, known: &[BlockId]) -> Option<(BlockId, u64)> {
+        known.iter().find_map(|id| {
+            let number = self.entire_block(id)?.number;
+            (self.best_block_at_height(number) == Some(*id)).then_some((*id, number))
+        })
+    }
+
+    /// Find a transaction on the node's best chain, with the id and height of the block including it.
+    ///
+    /// Nodes without a transaction index keep the default, which finds nothing.
+    fn transaction(&self, _id: &TransactionId) -> Option<(BlockId, u64, Transaction)> {
+        None
+    }
+
+    /// Whether a coin exists on the node's best chain and is not spent there. A coin that was never
+    /// created is reported as not unspent.
+    ///
+    /// Nodes without a UTXO set keep the default, which does not know.
+    fn is_unspent(&self, _coin_id: &CoinId) -> Option<bool> {
+        None
+    }
+}
+
+/// How a `MockNode` picks its best block as blocks are added.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum ForkChoice {
+    /// Only `set_best` and `add_block_as_best` change the best block.
+    #[default]
+    Manual,
+    /// An added block becomes best if it is higher than the current best block.
+    /// At equal height, the tie break decides.
+    LongestChain(TieBreak),
+    /// An added block becomes best if its chain has more cumulative work than the current best block's.
+    /// At equal work, the tie break decides.
+    MostWork(TieBreak),
+}
+
+/// Which of two equally good blocks the longest chain and most work rules prefer.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum TieBreak {
+    /// Keep the block that was added first, like most real nodes.
+    #[default]
+    FirstSeen,
+    /// Prefer the block with the lower id, so the outcome does not depend on the order blocks arrive in.
+    LowestId,
+}
+
+/// Everything a `MockNode` knows about its chain, to store a scenario once and load it in many tests.
+///
+/// Encode it with the crate's codec, or with serde when the `serde` feature is enabled.
+/// The fork choice rule is not part of the chain, so a loaded node uses `ForkChoice::Manual`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChainFixture {
+    /// Every block except genesis, in the order the node received them, so each comes after its parent.
+    pub blocks: Vec<Block>,
+    /// The block the node considered best.
+    pub best_block: BlockId,
+    /// The blocks marked invalid with `invalidate_block`, sorted.
+    pub invalidated: Vec<BlockId>,
+    /// The mock clock.
+    pub now: u64,
+}
+
+/// What a `MockNode` does with calls beyond its call budget.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum OverBudget {
+    /// Panic, failing the test at the call that broke the budget.
+    #[default]
+    Panic,
+    /// Answer `None`, like a node that stopped responding.
+    Fail,
 }
 
 /// A mock Bonecoin node useful for writing unit tests.
@@ -23,19 +130,52 @@ pub struct MockNode {
     /// The only validity assumption is that every block in the DB as has a parent in the DB (except genesis).
     blocks: HashMap<BlockId, Block>,
     /// The id of the block that the node currently considers best.
-    /// There is no fork choice rule or anything else to update this automatically.
-    /// The user must update it manually.
+    /// With the default `ForkChoice::Manual` there is no fork choice rule or anything else to update
+    /// this automatically, and the user must update it manually.
     best_block: BlockId,
+    /// How `add_block` updates the best block.
+    fork_choice: ForkChoice,
+    /// Every block id in the order the blocks were added, for the first seen tie break.
+    arrival: Vec<BlockId>,
+    /// The blocks including each transaction, on any fork, in the order they were added.
+    tx_index: HashMap<TransactionId, Vec<BlockId>>,
+    /// Blocks marked invalid with `invalidate_block`. Their descendants are invalid too.
+    invalidated: HashSet<BlockId>,
+    /// Blocks added in strict mode that break the coinbase rules. They stay invalid along with their descendants.
+    rejected: HashSet<BlockId>,
     /// The number of times the mock node has been queried over the NodeEndpoint interface.
     /// In testing scenarios, this is useful. For example, an inefficient wallet, may re-sync
     /// from scratch every single time, and this will catch it.
     calls_so_far: Cell<u64>,
+    /// The mock clock used to timestamp new blocks.
+    /// It never advances on its own; tests move it with `set_time` or `advance_time`.
+    now: u64,
+    /// Every call over the NodeEndpoint interface, including block fetches, for the call budget.
+    endpoint_calls: Cell<u64>,
+    /// The most endpoint calls allowed, and what happens to the calls beyond it.
+    call_budget: Option<(u64, OverBudget)>,
+    /// The simulated time each endpoint call takes.
+    latency: Duration,
+    /// Whether calls actually sleep for the latency, rather than only adding it to `elapsed`.
+    sleep_on_latency: bool,
+    /// The simulated time spent answering endpoint calls.
+    elapsed: Cell<Duration>,
+    /// Every transaction accepted by `submit_transaction`, in submission order. They are not mined
+    /// on their own; tests put them into blocks.
+    submitted: RefCell<Vec<Transaction>>,
+    /// Whether the node keeps a UTXO set, see `set_strict`.
+    strict: bool,
+    /// The unspent coins of the best chain, with the best block they were computed for.
+    utxos: RefCell<Option<(BlockId, HashSet<CoinId>)>>,
 }
 
 impl NodeEndpoint for MockNode {
     fn best_block_at_height(&self, h: u64) -> Option<BlockId> {
         // Record the call
         self.calls_so_far.set(self.calls_so_far.get() + 1);
+        if !self.answer_call() {
+            return None;
+        }
 
         // Look up the best block overall to begin with
         let mut b = self.blocks.get(&self.best_block).expect("best block should be in db");
@@ -56,7 +196,77 @@ impl NodeEndpoint for MockNode {
     }
 
     fn entire_block(&self, id: &BlockId) -> Option<Block> {
-        self.blocks.get(id).cloned()
+        if !self.answer_call() {
+            return None;
+        }
+        self.blocks.get(id).filter(|_| self.is_valid(id)).cloned()
+    }
+
+    fn relevant_transactions(&self, id: &BlockId, filter: &BloomFilter) -> Option<Vec<Transaction>> {
+        if !self.answer_call() {
+            return None;
+        }
+        let block = self.blocks.get(id).filter(|_| self.is_valid(id))?;
+        Some(block.body.iter().filter(|tx| filter.matches_transaction(tx)).cloned().collect())
+    }
+
+    fn submit_transaction(&self, transaction: &Transaction) -> bool {
+        if !self.answer_call() || !transaction.has_supported_version() || !transaction.is_within_limits() {
+            return false;
+        }
+        if self.strict && !transaction.iter_input_coin_ids().all(|coin_id| self.unspent(&coin_id)) {
+            return false;
+        }
+        self.submitted.borrow_mut().push(transaction.clone());
+        true
+    }
+
+    fn cumulative_work(&self, id: &BlockId) -> Option<u64> {
+        if !self.answer_call() || !self.blocks.contains_key(id) || !self.is_valid(id) {
+            return None;
+        }
+        Some(self.work(id))
+    }
+
+    fn common_ancestor(&self, known: &[BlockId]) -> Option<(BlockId, u64)> {
+        self.calls_so_far.set(self.calls_so_far.get() + 1);
+        if !self.answer_call() {
+            return None;
+        }
+
+        // Collect the best chain once, then look the locator up in it.
+        let mut best_chain = HashMap::new();
+        let mut b = &self.blocks[&self.best_block];
+        loop {
+            best_chain.insert(b.id(), b.number);
+            if b.number == 0 {
+                break;
+            }
+            b = &self.blocks[&b.parent];
+        }
+        known.iter().find_map(|id| best_chain.get(id).map(|number| (*id, *number)))
+    }
+
+    fn transaction(&self, id: &TransactionId) -> Option<(BlockId, u64, Transaction)> {
+        self.calls_so_far.set(self.calls_so_far.get() + 1);
+        if !self.answer_call() {
+            return None;
+        }
+        let best_number = self.blocks[&self.best_block].number;
+        let block_id = self.tx_index.get(id)?.iter().find(|block_id| {
+            self.blocks[*block_id].number <= best_number && self.descends_from(&self.best_block, block_id)
+        })?;
+        let block = &self.blocks[block_id];
+        let transaction = block.body.iter().find(|tx| tx.id() == *id)?;
+        Some((*block_id, block.number, transaction.clone()))
+    }
+
+    fn is_unspent(&self, coin_id: &CoinId) -> Option<bool> {
+        self.calls_so_far.set(self.calls_so_far.get() + 1);
+        if !self.answer_call() || !self.strict {
+            return None;
+        }
+        Some(self.unspent(coin_id))
     }
 }
 
@@ -70,13 +280,47 @@ impl MockNode {
         Self {
             blocks,
             best_block,
+            fork_choice: ForkChoice::Manual,
+            arrival: vec![best_block],
+            tx_index: HashMap::new(),
+            invalidated: HashSet::new(),
+            rejected: HashSet::new(),
             calls_so_far: Cell::new(0),
+            now: 0,
+            endpoint_calls: Cell::new(0),
+            call_budget: None,
+            latency: Duration::ZERO,
+            sleep_on_latency: false,
+            elapsed: Cell::new(Duration::ZERO),
+            submitted: RefCell::new(Vec::new()),
+            strict: false,
+            utxos: RefCell::new(None),
+        }
+    }
+
+    /// Creates a node holding only the genesis block that follows the longest chain on its own,
+    /// keeping the first seen block on ties.
+    pub fn with_longest_chain_rule() -> Self {
+        Self::with_fork_choice(ForkChoice::LongestChain(TieBreak::FirstSeen))
+    }
+
+    /// Creates a node holding only the genesis block with the given fork choice rule.
+    pub fn with_fork_choice(fork_choice: ForkChoice) -> Self {
+        Self {
+            fork_choice,
+            ..Self::new()
         }
     }
 
     /// Add a new block to the chain built on top of the specified parent.
     /// Returns the ID of the newly built block.
+    /// Under `ForkChoice::LongestChain` or `ForkChoice::MostWork`, the block also becomes best if the rule prefers it.
     pub fn add_block(&mut self, parent_id: BlockId, body: Vec<Transaction>) -> BlockId {
+        self.add_block_with_difficulty(parent_id, 1, body)
+    }
+
+    /// Add a new block like `add_block`, claiming the given difficulty.
+    pub fn add_block_with_difficulty(&mut self, parent_id: BlockId, difficulty: u64, body: Vec<Transaction>) -> BlockId {
         let parent_b = self
             .blocks
             .get(&parent_id)
@@ -84,20 +328,165 @@ impl MockNode {
         let b = Block {
             parent: parent_id,
             number: parent_b.number + 1,
+            timestamp: self.now,
+            difficulty,
             body,
         };
 
-        let id = b.id();
-        self.blocks.insert(b.id(), b);
+        let id = self.insert_block(b);
+        if let Some(tie_break) = self.tie_break() {
+            if self.is_valid(&id) && self.prefers(tie_break, &id, &self.best_block) {
+                self.best_block = id;
+            }
+        }
+
+        id
+    }
 
+    /// Store a block whose parent is known, indexing its transactions. Returns its id.
+    /// In strict mode, a block breaking the coinbase rules is stored but never becomes valid.
+    fn insert_block(&mut self, b: Block) -> BlockId {
+        let id = b.id();
+        if self.blocks.contains_key(&id) {
+            return id;
+        }
+        if self.strict && !self.follows_coinbase_rules(&b) {
+            self.rejected.insert(id);
+        }
+        for tx in &b.body {
+            self.tx_index.entry(tx.id()).or_default().push(id);
+        }
+        self.blocks.insert(id, b);
+        self.arrival.push(id);
         id
     }
 
+    /// The tie break of the fork choice rule, or `None` if the best block is only moved by hand.
+    fn tie_break(&self) -> Option<TieBreak> {
+        match self.fork_choice {
+            ForkChoice::Manual => None,
+            ForkChoice::LongestChain(tie_break) | ForkChoice::MostWork(tie_break) => Some(tie_break),
+        }
+    }
+
+    /// Whether the fork choice rule prefers block `a` over block `b`, with `b` seen first.
+    /// Blocks are compared by cumulative work under `ForkChoice::MostWork`, and by height otherwise.
+    fn prefers(&self, tie_break: TieBreak, a: &BlockId, b: &BlockId) -> bool {
+        let (a_rank, b_rank) = match self.fork_choice {
+            ForkChoice::MostWork(_) => (self.work(a), self.work(b)),
+            _ => (self.blocks[a].number, self.blocks[b].number),
+        };
+        match tie_break {
+            _ if a_rank != b_rank => a_rank > b_rank,
+            TieBreak::FirstSeen => false,
+            TieBreak::LowestId => a < b,
+        }
+    }
+
+    /// The total difficulty of a known block and its ancestors, genesis included.
+    fn work(&self, id: &BlockId) -> u64 {
+        let mut b = &self.blocks[id];
+        let mut work = b.difficulty;
+        while b.number > 0 {
+            b = &self.blocks[&b.parent];
+            work = work.saturating_add(b.difficulty);
+        }
+        work
+    }
+
+    /// Whether a block mints no more than its coinbase may, see `Block::coinbase_is_valid`.
+    /// Blocks without a transaction consuming no inputs mint nothing and pass without a look at their parents.
+    fn follows_coinbase_rules(&self, b: &Block) -> bool {
+        if b.body.iter().all(|tx| !tx.inputs.is_empty()) {
+            return true;
+        }
+        let coin_values = self.coin_values_on_chain(b.parent);
+        b.coinbase_is_valid(|coin_id| coin_values.get(coin_id).copied())
+    }
+
+    /// Whether neither the block nor any of its ancestors has been invalidated or breaks the coinbase rules.
+    fn is_valid(&self, id: &BlockId) -> bool {
+        let mut id = *id;
+        loop {
+            if self.invalidated.contains(&id) || self.rejected.contains(&id) {
+                return false;
+            }
+            match self.blocks.get(&id) {
+                Some(b) if b.number > 0 => id = b.parent,
+                _ => return true,
+            }
+        }
+    }
+
+    /// Whether `id` is `ancestor` or one of its descendants.
+    fn descends_from(&self, id: &BlockId, ancestor: &BlockId) -> bool {
+        let target = self.blocks[ancestor].number;
+        let mut b = &self.blocks[id];
+        while b.number > target {
+            b = &self.blocks[&b.parent];
+        }
+        b.id() == *ancestor
+    }
+
+    /// Mark a block invalid, like a node operator rejecting it by hand.
+    /// The block and its descendants are no longer served, and can only become best again once reconsidered.
+    /// If the best block was among them, the best block moves back to the last valid ancestor,
+    /// or to the best valid block if a fork choice rule is set.
+    pub fn invalidate_block(&mut self, id: BlockId) {
+        assert!(self.blocks.contains_key(&id), "MockNode cannot invalidate a block that is not known.");
+        assert!(id != Block::genesis().id(), "MockNode cannot invalidate the genesis block.");
+        self.invalidated.insert(id);
+        while !self.is_valid(&self.best_block) {
+            self.best_block = self.blocks[&self.best_block].parent;
+        }
+        self.choose_best_valid();
+    }
+
+    /// Undo `invalidate_block` for this block, its ancestors, and its descendants.
+    /// The best block moves to the highest block through the reconsidered one if that is higher than
+    /// the current best, or to the best valid block if a fork choice rule is set.
+    pub fn reconsider_block(&mut self, id: BlockId) {
+        assert!(self.blocks.contains_key(&id), "MockNode cannot reconsider a block that is not known.");
+        let related: Vec<BlockId> = self
+            .invalidated
+            .iter()
+            .filter(|invalid| self.descends_from(invalid, &id) || self.descends_from(&id, invalid))
+            .copied()
+            .collect();
+        for invalid in related {
+            self.invalidated.remove(&invalid);
+        }
+
+        let highest = self
+            .arrival
+            .iter()
+            .filter(|tip| self.is_valid(tip) && self.descends_from(tip, &id))
+            .fold(self.best_block, |best, tip| {
+                if self.prefers(TieBreak::FirstSeen, tip, &best) { *tip } else { best }
+            });
+        self.best_block = highest;
+        self.choose_best_valid();
+    }
+
+    /// Unless the fork choice is manual, move the best block to the valid block the rule prefers most.
+    fn choose_best_valid(&mut self) {
+        if let Some(tie_break) = self.tie_break() {
+            let mut best = self.best_block;
+            for id in self.arrival.iter().filter(|id| self.is_valid(id)) {
+                if self.prefers(tie_break, id, &best) {
+                    best = *id;
+                }
+            }
+            self.best_block = best;
+        }
+    }
+
     /// Sets a new block as the mock node's best.
-    /// There is no longest chain rule or anything else.
-    /// This method is the primary way to change the node's best block.
+    /// This overrides the fork choice rule, which only looks at blocks as they are added.
+    /// Under `ForkChoice::Manual`, this method is the primary way to change the node's best block.
     pub fn set_best(&mut self, new_best: BlockId) {
         if self.blocks.contains_key(&new_best) {
+            assert!(self.is_valid(&new_best), "MockNode cannot set best block to an invalidated block.");
             self.best_block = new_best;
         } else {
             panic!("MockNode cannot set best block to a block that is not known.");
@@ -111,10 +500,261 @@ impl MockNode {
         id
     }
 
+    /// Adds a new block whose body is prefixed with a coinbase paying `miner` the block reward
+    /// plus the tips left by the given transactions. The block is not marked as best.
+    ///
+    /// Panics if a transaction consumes a coin that does not exist on the parent's chain
+    /// (or earlier in the same body), since its tip cannot be computed.
+    pub fn add_block_with_coinbase(
+        &mut self,
+        parent_id: BlockId,
+        miner: Address,
+        body: Vec<Transaction>,
+    ) -> BlockId {
+        let number = self
+            .blocks
+            .get(&parent_id)
+            .expect("Cannot build child block on a block that is not known.")
+            .number
+            + 1;
+
+        let mut coin_values = self.coin_values_on_chain(parent_id);
+        let mut tips = 0;
+        for tx in &body {
+            let consumed: u64 = tx
+                .iter_input_coin_ids()
+                .map(|coin_id| coin_values.get(&coin_id).copied().expect("Input coin should exist on chain."))
+                .sum();
+            let produced: u64 = tx.outputs.iter().map(Coin::native_value).sum();
+            tips += consumed.saturating_sub(produced);
+            coin_values.extend(tx.iter_output_coins_and_ids(number).map(|(id, coin)| (id, coin.native_value())));
+        }
+
+        // The coinbase comes first, so every other transaction's coin ids are unaffected by it.
+        let mut full_body = vec![Transaction::coinbase(miner, BLOCK_REWARD + tips)];
+        full_body.extend(body);
+        self.add_block(parent_id, full_body)
+    }
+
+    /// Collect the native bones held by every coin ever created on the chain ending at `tip`.
+    /// Spent coins are included, which is good enough for computing tips in tests.
+    fn coin_values_on_chain(&self, tip: BlockId) -> HashMap<CoinId, u64> {
+        let mut values = HashMap::new();
+        let mut b = self.blocks.get(&tip).expect("tip should be in db");
+        loop {
+            for tx in &b.body {
+                values.extend(tx.iter_output_coins_and_ids(b.number).map(|(id, coin)| (id, coin.native_value())));
+            }
+            if b.number == 0 {
+                break;
+            }
+            b = self.blocks.get(&b.parent).expect("Every block in the db also has its parent in the db.");
+        }
+        values
+    }
+
+    /// Build `blocks` blocks on top of the best block, each becoming best, with coins moving randomly between `addresses`.
+    /// The same arguments on the same node always produce the same chain. Returns the id of the last block.
+    ///
+    /// Every block has a coinbase paying the reward and tips to one of the addresses, followed by up to
+    /// `txs_per_block` transactions. Each spends one or two coins created by earlier generated blocks,
+    /// never an immature coinbase, and pays a random amount to one address and change to another,
+    /// leaving a small tip. Blocks are short of transactions until enough coins exist to spend.
+    ///
+    /// Panics if `addresses` is empty.
+    pub fn generate_chain(&mut self, seed: u64, blocks: usize, txs_per_block: usize, addresses: &[Address]) -> BlockId {
+        assert!(!addresses.is_empty(), "Generating a chain needs at least one address to pay.");
+        let mut rng = SplitMix64::new(seed);
+        let pick = |rng: &mut SplitMix64, n: usize| (rng.next_u64() % n as u64) as usize;
+        // unspent generated coins with the height that created them and whether they are coinbase coins
+        let mut unspent: Vec<(CoinId, Coin, u64, bool)> = Vec::new();
+        let mut tip = self.best_block;
+
+        for _ in 0..blocks {
+            let number = self.blocks[&tip].number + 1;
+            let mut body = Vec::new();
+            let mut tips = 0;
+            for _ in 0..txs_per_block {
+                let spendable: Vec<usize> = (0..unspent.len())
+                    .filter(|i| !unspent[*i].3 || unspent[*i].2 + COINBASE_MATURITY <= number)
+                    .collect();
+                if spendable.is_empty() {
+                    break;
+                }
+                let mut chosen = vec![spendable[pick(&mut rng, spendable.len())]];
+                if spendable.len() > 1 && rng.next_u64() & 1 == 0 {
+                    let second = spendable[pick(&mut rng, spendable.len())];
+                    if second != chosen[0] {
+                        chosen.push(second);
+                    }
+                }
+                // remove the highest index first so the other stays valid
+                chosen.sort_unstable_by(|a, b| b.cmp(a));
+                let spent: Vec<(CoinId, Coin, u64, bool)> = chosen.into_iter().map(|i| unspent.swap_remove(i)).collect();
+
+                let total: u64 = spent.iter().map(|(_, coin, _, _)| coin.value).sum();
+                let tip_paid = rng.next_u64() % (total / 20 + 1);
+                let payment = 1 + rng.next_u64() % (total - tip_paid);
+                let mut outputs = vec![Coin {
+                    value: payment,
+                    owner: addresses[pick(&mut rng, addresses.len())].clone(),
+                    asset_id: None,
+                }];
+                let change = total - tip_paid - payment;
+                if change > 0 {
+                    outputs.push(Coin {
+                        value: change,
+                        owner: addresses[pick(&mut rng, addresses.len())].clone(),
+                        asset_id: None,
+                    });
+                }
+                let tx = Transaction {
+                    version: TRANSACTION_VERSION,
+                    inputs: spent
+                        .iter()
+                        .map(|(coin_id, coin, _, _)| Input {
+                            coin_id: *coin_id,
+                            signature: Signature::Valid(coin.owner.clone()),
+                        })
+                        .collect(),
+                    outputs,
+                };
+                tips += tip_paid;
+                body.push(tx);
+            }
+
+            let coinbase = Transaction::coinbase(addresses[pick(&mut rng, addresses.len())].clone(), BLOCK_REWARD + tips);
+            body.insert(0, coinbase);
+            for (index, tx) in body.iter().enumerate() {
+                unspent.extend(tx.iter_output_coins_and_ids(number).map(|(coin_id, coin)| (coin_id, coin, number, index == 0)));
+            }
+            tip = self.add_block(tip, body);
+            self.set_best(tip);
+        }
+        tip
+    }
+
+    /// Capture the node's whole fork tree, best block, invalidated blocks, and clock.
+    pub fn export_chain(&self) -> ChainFixture {
+        let mut invalidated: Vec<BlockId> = self.invalidated.iter().copied().collect();
+        invalidated.sort();
+        ChainFixture {
+            blocks: self.arrival[1..].iter().map(|id| self.blocks[id].clone()).collect(),
+            best_block: self.best_block,
+            invalidated,
+            now: self.now,
+        }
+    }
+
+    /// Create a node holding exactly the chain captured by `export_chain`.
+    ///
+    /// Panics if a block's parent is not earlier in the fixture, or the best block is not in it.
+    pub fn from_fixture(fixture: ChainFixture) -> Self {
+        let mut node = Self::new();
+        for b in fixture.blocks {
+            assert!(node.blocks.contains_key(&b.parent), "Every fixture block must come after its parent.");
+            node.insert_block(b);
+        }
+        node.invalidated = fixture.invalidated.into_iter().collect();
+        node.set_best(fixture.best_block);
+        node.now = fixture.now;
+        node
+    }
+
+    /// Set the mock clock. Blocks added from now on carry this timestamp.
+    pub fn set_time(&mut self, now: u64) {
+        self.now = now;
+    }
+
+    /// Move the mock clock forward by the given number of seconds.
+    pub fn advance_time(&mut self, seconds: u64) {
+        self.now += seconds;
+    }
+
     /// Check how many times the node has been queried
     pub fn how_many_queries(&self) -> u64 {
         self.calls_so_far.get()
     }
+
+    /// Check how many calls of any kind the node has answered or refused over the NodeEndpoint interface.
+    pub fn how_many_calls(&self) -> u64 {
+        self.endpoint_calls.get()
+    }
+
+    /// Allow at most `budget` more endpoint calls of any kind; `over` decides what happens to the rest.
+    pub fn set_call_budget(&mut self, budget: u64, over: OverBudget) {
+        self.call_budget = Some((self.endpoint_calls.get() + budget, over));
+    }
+
+    /// Remove the call budget.
+    pub fn clear_call_budget(&mut self) {
+        self.call_budget = None;
+    }
+
+    /// Make every endpoint call take `latency` of simulated time, see `elapsed`.
+    /// With `sleep` set, calls also block the calling thread for that long.
+    pub fn set_latency(&mut self, latency: Duration, sleep: bool) {
+        self.latency = latency;
+        self.sleep_on_latency = sleep;
+    }
+
+    /// In strict mode the node keeps the UTXO set of its best chain, answers `is_unspent` from it,
+    /// and refuses submitted transactions spending coins that are not in it. Blocks added in strict mode
+    /// that break the coinbase rules are kept but invalid, like blocks passed to `invalidate_block`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Whether a coin is in the UTXO set of the best chain, which is rebuilt after the best block moves.
+    fn unspent(&self, coin_id: &CoinId) -> bool {
+        let mut utxos = self.utxos.borrow_mut();
+        if utxos.as_ref().is_none_or(|(tip, _)| *tip != self.best_block) {
+            let mut chain = vec![&self.blocks[&self.best_block]];
+            while let Some(b) = chain.last().filter(|b| b.number > 0) {
+                chain.push(&self.blocks[&b.parent]);
+            }
+            let mut unspent = HashSet::new();
+            for block in chain.into_iter().rev() {
+                for tx in &block.body {
+                    for spent in tx.iter_input_coin_ids() {
+                        unspent.remove(&spent);
+                    }
+                    unspent.extend(tx.iter_output_coins_and_ids(block.number).map(|(id, _)| id));
+                }
+            }
+            *utxos = Some((self.best_block, unspent));
+        }
+        utxos.as_ref().is_some_and(|(_, unspent)| unspent.contains(coin_id))
+    }
+
+    /// The simulated time spent answering endpoint calls so far.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed.get()
+    }
+
+    /// The transactions submitted to the node so far, in submission order, including repeats.
+    pub fn submitted_transactions(&self) -> Vec<Transaction> {
+        self.submitted.borrow().clone()
+    }
+
+    /// Account for an endpoint call. Returns false if the call is over budget and should fail.
+    fn answer_call(&self) -> bool {
+        let calls = self.endpoint_calls.get() + 1;
+        self.endpoint_calls.set(calls);
+        if let Some((budget, over)) = self.call_budget {
+            if calls > budget {
+                match over {
+                    OverBudget::Panic => panic!("MockNode call budget exceeded: call {calls} of at most {budget}."),
+                    OverBudget::Fail => return false,
+                }
+            }
+        }
+        self.elapsed.set(self.elapsed.get() + self.latency);
+        if self.sleep_on_latency {
+            std::thread::sleep(self.latency);
+        }
+        true
+    }
 }
 
 #[test]
@@ -157,6 +797,111 @@ fn add_block_as_best_helper_works() {
     assert_eq!(node.best_block_at_height(1), Some(b1_id));
 }
 
+#[test]
+fn blocks_are_stamped_with_mock_clock() {
+    let mut node = MockNode::new();
+    node.set_time(1_000);
+    let b1_id = node.add_block_as_best(Block::genesis().id(), vec![]);
+    node.advance_time(30);
+    let b2_id = node.add_block_as_best(b1_id, vec![]);
+
+    assert_eq!(node.entire_block(&b1_id).unwrap().timestamp, 1_000);
+    assert_eq!(node.entire_block(&b2_id).unwrap().timestamp, 1_030);
+}
+
+#[test]
+fn coinbase_collects_reward_and_tips() {
+    let mut node = MockNode::new();
+    let b1_id = node.add_block_with_coinbase(Block::genesis().id(), Address::Alice, vec![]);
+    let b1 = node.entire_block(&b1_id).unwrap();
+    let reward = b1.coinbase().expect("block should have a coinbase");
+    assert_eq!(reward.outputs[0].value, BLOCK_REWARD);
+
+    // Spend the reward leaving a tip of 5, which the next producer collects.
+    let spend = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![crate::Input {
+            coin_id: reward.coin_id(1, 0),
+            signature: crate::Signature::Valid(Address::Alice),
+        }],
+        outputs: vec![crate::Coin {
+            value: BLOCK_REWARD - 5,
+            owner: Address::Bob,
+            asset_id: None,
+        }],
+    };
+    let b2_id = node.add_block_with_coinbase(b1_id, Address::Charlie, vec![spend]);
+    let b2 = node.entire_block(&b2_id).unwrap();
+
+    assert_eq!(b2.coinbase().unwrap().outputs[0].value, BLOCK_REWARD + 5);
+    assert!(b2.coinbase_is_valid(|id| (*id == reward.coin_id(1, 0)).then_some(BLOCK_REWARD)));
+}
+
+#[test]
+fn strict_mode_rejects_blocks_breaking_the_coinbase_rules() {
+    let mut node = MockNode::with_longest_chain_rule();
+    node.set_strict(true);
+    let b1_id = node.add_block_with_coinbase(Block::genesis().id(), Address::Alice, vec![]);
+    assert_eq!(node.best_block, b1_id);
+
+    let asset = crate::Coin {
+        value: 10,
+        owner: Address::Bob,
+        asset_id: Some(crate::AssetId::issued_by(&CoinId(7))),
+    };
+    let asset_mint = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: Vec::new(),
+        outputs: vec![asset],
+    };
+    assert!(!asset_mint.is_coinbase());
+    let too_much = node.add_block(b1_id, vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD + 1)]);
+    let second = node.add_block(b1_id, vec![Transaction::coinbase(Address::Alice, 1), Transaction::coinbase(Address::Bob, 1)]);
+    let minted_asset = node.add_block(b1_id, vec![asset_mint]);
+    for id in [too_much, second, minted_asset] {
+        assert_eq!(node.best_block, b1_id);
+        assert_eq!(node.entire_block(&id), None);
+        // a descendant of a rejected block is invalid as well
+        let child = node.add_block(id, vec![]);
+        assert_eq!(node.entire_block(&child), None);
+    }
+
+    let b2_id = node.add_block(b1_id, vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+    assert_eq!(node.best_block, b2_id);
+
+    // without strict mode, blocks are served whatever they mint
+    let mut lenient = MockNode::new();
+    let b1_id = lenient.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD + 1)]);
+    assert!(lenient.entire_block(&b1_id).is_some());
+}
+
+#[test]
+fn strict_mode_rejects_blocks_whose_amounts_overflow() {
+    let mut node = MockNode::with_longest_chain_rule();
+    node.set_strict(true);
+    let b1_id = node.add_block_with_coinbase(Block::genesis().id(), Address::Alice, vec![]);
+    let reward = node.entire_block(&b1_id).unwrap().body[0].coin_id(1, 0);
+
+    // outputs adding up past u64::MAX would wrap around to a single bone
+    let coin = |value| crate::Coin { value, owner: Address::Bob, asset_id: None };
+    let wrapped_coinbase = Transaction {
+        outputs: vec![coin(u64::MAX), coin(2)],
+        ..Transaction::coinbase(Address::Bob, 1)
+    };
+    let wrapped_payment = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input { coin_id: reward, signature: Signature::Valid(Address::Alice) }],
+        outputs: vec![coin(u64::MAX), coin(2)],
+    };
+    let minted = node.add_block(b1_id, vec![wrapped_coinbase]);
+    // the payment would leave a tip of 49 bones for the coinbase to claim
+    let tipped = node.add_block(b1_id, vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD + 49), wrapped_payment]);
+    for id in [minted, tipped] {
+        assert_eq!(node.best_block, b1_id);
+        assert_eq!(node.entire_block(&id), None);
+    }
+}
+
 #[test]
 fn reports_correct_ancestors_even_after_reorg() {
     let mut node = MockNode::new();
@@ -186,4 +931,233 @@ fn reports_correct_ancestors_even_after_reorg() {
     assert_eq!(node.best_block_at_height(1), Some(b1_id));
     assert_eq!(node.best_block_at_height(2), Some(b2_id));
     assert_eq!(node.best_block_at_height(3), None);
-}
\ No newline at end of file
+}
+
+#[test]
+fn common_ancestor_finds_the_fork_point_of_a_locator() {
+    /// Serves the node's blocks with the default common ancestor search.
+    struct Plain<'a>(&'a MockNode);
+    impl NodeEndpoint for Plain<'_> {
+        fn best_block_at_height(&self, h: u64) -> Option<BlockId> {
+            self.0.best_block_at_height(h)
+        }
+        fn entire_block(&self, id: &BlockId) -> Option<Block> {
+            self.0.entire_block(id)
+        }
+    }
+
+    let mut node = MockNode::new();
+    let a1_id = node.add_block_as_best(Block::genesis().id(), vec![]);
+    let a2_id = node.add_block_as_best(a1_id, vec![]);
+    let a3_id = node.add_block_as_best(a2_id, vec![]);
+    let b2_id = node.add_block(a1_id, vec![Transaction::coinbase(Address::Bob, BLOCK_REWARD)]);
+    let locator = [a3_id, a2_id, a1_id, Block::genesis().id()];
+    assert_eq!(node.common_ancestor(&locator), Some((a3_id, 3)));
+
+    let b3_id = node.add_block(b2_id, vec![]);
+    node.set_best(b3_id);
+    let queries = node.how_many_queries();
+    assert_eq!(node.common_ancestor(&locator), Some((a1_id, 1)));
+    assert_eq!(node.how_many_queries(), queries + 1);
+    assert_eq!(Plain(&node).common_ancestor(&locator), Some((a1_id, 1)));
+    assert_eq!(node.common_ancestor(&[a3_id, a2_id]), None);
+    assert_eq!(Plain(&node).common_ancestor(&[a3_id, a2_id]), None);
+}
+
+#[test]
+fn transactions_are_found_on_the_best_chain_only() {
+    let mut node = MockNode::new();
+    let paid = Transaction::coinbase(Address::Bob, BLOCK_REWARD);
+    let a1_id = node.add_block_as_best(Block::genesis().id(), vec![paid.clone()]);
+    let b1_id = node.add_block(Block::genesis().id(), vec![Transaction::coinbase(Address::Eve, 1), paid.clone()]);
+    assert_eq!(node.transaction(&paid.id()), Some((a1_id, 1, paid.clone())));
+
+    node.set_best(b1_id);
+    assert_eq!(node.transaction(&paid.id()), Some((b1_id, 1, paid.clone())));
+    node.invalidate_block(b1_id);
+    assert_eq!(node.transaction(&paid.id()), None);
+    assert_eq!(node.transaction(&Transaction::coinbase(Address::Alice, 1).id()), None);
+
+    let mut loaded = MockNode::from_fixture(node.export_chain());
+    loaded.set_best(a1_id);
+    assert_eq!(loaded.transaction(&paid.id()), Some((a1_id, 1, paid)));
+}
+
+#[test]
+fn strict_mode_answers_from_the_utxo_set_of_the_best_chain() {
+    let mut node = MockNode::new();
+    let reward = Transaction::coinbase(Address::Alice, BLOCK_REWARD);
+    let coin_id = reward.coin_id(1, 0);
+    let b1_id = node.add_block_as_best(Block::genesis().id(), vec![reward]);
+    assert_eq!(node.is_unspent(&coin_id), None);
+
+    node.set_strict(true);
+    assert_eq!(node.is_unspent(&coin_id), Some(true));
+    let spend = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input {
+            coin_id,
+            signature: Signature::Valid(Address::Alice),
+        }],
+        outputs: Vec::new(),
+    };
+    let b2_id = node.add_block_as_best(b1_id, vec![spend.clone()]);
+    assert_eq!(node.is_unspent(&coin_id), Some(false));
+    assert!(!node.submit_transaction(&spend));
+
+    node.invalidate_block(b2_id);
+    assert_eq!(node.is_unspent(&coin_id), Some(true));
+    assert!(node.submit_transaction(&spend));
+    assert_eq!(node.is_unspent(&CoinId(7)), Some(false));
+}
+
+#[test]
+fn longest_chain_rule_follows_the_highest_block() {
+    let mut node = MockNode::with_longest_chain_rule();
+    let a1_id = node.add_block(Block::genesis().id(), vec![]);
+    assert_eq!(node.best_block, a1_id);
+
+    // a competing block at the same height does not replace the first one seen
+    let b1_id = node.add_block(Block::genesis().id(), vec![Transaction::coinbase(Address::Bob, BLOCK_REWARD)]);
+    assert_eq!(node.best_block, a1_id);
+    let b2_id = node.add_block(b1_id, vec![]);
+    assert_eq!(node.best_block, b2_id);
+    assert_eq!(node.best_block_at_height(1), Some(b1_id));
+
+    // an orphaned branch catching up with lower blocks does not move the tip
+    node.add_block(a1_id, vec![]);
+    assert_eq!(node.best_block, b2_id);
+}
+
+#[test]
+fn most_work_rule_follows_the_heaviest_chain() {
+    let mut node = MockNode::with_fork_choice(ForkChoice::MostWork(TieBreak::FirstSeen));
+    let a1_id = node.add_block(Block::genesis().id(), vec![]);
+    let a2_id = node.add_block(a1_id, vec![]);
+    assert_eq!((node.best_block, node.cumulative_work(&a2_id)), (a2_id, Some(3)));
+
+    // a single heavier block outweighs the longer chain
+    let b1_id = node.add_block_with_difficulty(Block::genesis().id(), 5, vec![]);
+    assert_eq!((node.best_block, node.cumulative_work(&b1_id)), (b1_id, Some(6)));
+    node.add_block(a2_id, vec![]);
+    assert_eq!(node.best_block, b1_id);
+
+    node.invalidate_block(b1_id);
+    assert_eq!(node.cumulative_work(&b1_id), None);
+    assert_eq!(node.best_block_at_height(3).map(|id| node.cumulative_work(&id)), Some(Some(4)));
+}
+
+#[test]
+fn lowest_id_tie_break_ignores_arrival_order() {
+    let mut first = MockNode::with_fork_choice(ForkChoice::LongestChain(TieBreak::LowestId));
+    let mut second = MockNode::with_fork_choice(ForkChoice::LongestChain(TieBreak::LowestId));
+    let a = vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)];
+    let b = vec![Transaction::coinbase(Address::Bob, BLOCK_REWARD)];
+    let a_id = first.add_block(Block::genesis().id(), a.clone());
+    let b_id = first.add_block(Block::genesis().id(), b.clone());
+    second.add_block(Block::genesis().id(), b);
+    second.add_block(Block::genesis().id(), a);
+
+    assert_eq!(first.best_block, a_id.min(b_id));
+    assert_eq!(second.best_block, a_id.min(b_id));
+}
+
+#[test]
+fn invalidated_blocks_leave_the_best_chain_until_reconsidered() {
+    let mut node = MockNode::new();
+    let b1_id = node.add_block_as_best(Block::genesis().id(), vec![]);
+    let b2_id = node.add_block_as_best(b1_id, vec![]);
+    let b3_id = node.add_block_as_best(b2_id, vec![]);
+
+    node.invalidate_block(b2_id);
+    assert_eq!(node.best_block, b1_id);
+    assert_eq!(node.entire_block(&b3_id), None);
+    let c2_id = node.add_block_as_best(b1_id, vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+    assert_eq!(node.best_block_at_height(2), Some(c2_id));
+
+    // the reconsidered chain is higher, so the node returns to it
+    node.reconsider_block(b3_id);
+    assert_eq!(node.best_block, b3_id);
+    assert_eq!(node.entire_block(&b2_id).map(|b| b.id()), Some(b2_id));
+}
+
+#[test]
+fn longest_chain_rule_skips_invalid_blocks() {
+    let mut node = MockNode::with_longest_chain_rule();
+    let a1_id = node.add_block(Block::genesis().id(), vec![]);
+    let a2_id = node.add_block(a1_id, vec![]);
+    let b1_id = node.add_block(Block::genesis().id(), vec![Transaction::coinbase(Address::Bob, BLOCK_REWARD)]);
+
+    node.invalidate_block(a1_id);
+    assert_eq!(node.best_block, b1_id);
+    node.add_block(a2_id, vec![]);
+    assert_eq!(node.best_block, b1_id);
+    node.reconsider_block(a1_id);
+    assert_eq!(node.best_block_at_height(3).map(|id| node.blocks[&id].parent), Some(a2_id));
+}
+
+#[test]
+fn fixtures_reproduce_the_fork_tree() {
+    use crate::codec::{Decode, Encode};
+
+    let mut node = MockNode::new();
+    node.set_time(500);
+    let a1_id = node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+    let a2_id = node.add_block_as_best(a1_id, vec![]);
+    let b2_id = node.add_block(a1_id, vec![Transaction::coinbase(Address::Bob, BLOCK_REWARD)]);
+    node.add_block(b2_id, vec![]);
+    node.invalidate_block(b2_id);
+
+    let fixture = node.export_chain();
+    let loaded = MockNode::from_fixture(ChainFixture::decode(&fixture.encode()).unwrap());
+    assert_eq!(loaded.best_block, a2_id);
+    assert_eq!(loaded.blocks, node.blocks);
+    assert_eq!(loaded.entire_block(&b2_id), None);
+    assert_eq!(loaded.now, 500);
+    assert_eq!(loaded.export_chain(), fixture);
+}
+
+#[test]
+fn generated_chains_are_reproducible_and_valid() {
+    let addresses = [Address::Alice, Address::Bob, Address::Custom(7)];
+    let mut node = MockNode::new();
+    let tip = node.generate_chain(3, 40, 4, &addresses);
+    let mut again = MockNode::new();
+    assert_eq!(again.generate_chain(3, 40, 4, &addresses), tip);
+    assert_ne!(MockNode::new().generate_chain(4, 40, 4, &addresses), tip);
+
+    // replay the chain, checking every input exists and every coinbase is within reward plus tips
+    let mut values: HashMap<CoinId, u64> = HashMap::new();
+    let mut transactions = 0;
+    for height in 1..=40 {
+        let block = node.entire_block(&node.best_block_at_height(height).unwrap()).unwrap();
+        assert!(block.coinbase_is_valid(|coin_id| values.get(coin_id).copied()));
+        for tx in &block.body {
+            for coin_id in tx.iter_input_coin_ids() {
+                assert!(values.remove(&coin_id).is_some(), "input spends a coin that does not exist");
+            }
+            values.extend(tx.iter_output_coins_and_ids(height).map(|(coin_id, coin)| (coin_id, coin.value)));
+        }
+        transactions += block.body.len() - 1;
+    }
+    assert_eq!(node.best_block, tip);
+    assert!(transactions > 40, "only {transactions} transactions generated");
+}
+
+#[test]
+fn call_budget_and_latency_are_enforced() {
+    let mut node = MockNode::new();
+    node.set_latency(Duration::from_millis(40), false);
+    node.set_call_budget(2, OverBudget::Fail);
+    assert!(node.best_block_at_height(0).is_some());
+    assert!(node.entire_block(&Block::genesis().id()).is_some());
+    assert_eq!(node.best_block_at_height(0), None);
+    assert_eq!((node.how_many_calls(), node.how_many_queries()), (3, 2));
+    assert_eq!(node.elapsed(), Duration::from_millis(80));
+
+    node.clear_call_budget();
+    assert!(node.best_block_at_height(0).is_some());
+    node.set_call_budget(0, OverBudget::Panic);
+    let over = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| node.best_block_at_height(0)));
+    assert!(over.is_err());
+}
@@ -0,0 +1,158 @@
+//! SipHash-1-3 with fixed zero keys, the hash behind every coin, transaction, and block id.
+//!
+//! This is the algorithm and keying the standard library's `DefaultHasher::new` uses today, written
+//! out here because the standard library does not promise to keep it. Integers are fed in little
+//! endian and `usize` as 64 bits, so an id is the same on every platform and every toolchain.
+
+use std::hash::Hasher;
+
+/// A streaming SipHash-1-3 hasher keyed with zeros.
+#[derive(Clone, Debug)]
+pub(crate) struct SipHasher13 {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    // bytes not yet compressed, and how many of them there are
+    tail: u64,
+    ntail: usize,
+    // total bytes written, whose low byte is part of the final block
+    length: usize,
+}
+
+impl SipHasher13 {
+    pub(crate) fn new() -> Self {
+        Self {
+            v0: 0x736f6d6570736575,
+            v1: 0x646f72616e646f6d,
+            v2: 0x6c7967656e657261,
+            v3: 0x7465646279746573,
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, block: u64) {
+        self.v3 ^= block;
+        self.round();
+        self.v0 ^= block;
+    }
+}
+
+impl Hasher for SipHasher13 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.length += bytes.len();
+        for &byte in bytes {
+            self.tail |= u64::from(byte) << (8 * self.ntail);
+            self.ntail += 1;
+            if self.ntail == 8 {
+                let block = self.tail;
+                self.compress(block);
+                self.tail = 0;
+                self.ntail = 0;
+            }
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.write(&[i]);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i8(&mut self, i: i8) {
+        self.write_u8(i as u8);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        let block = ((self.length as u64 & 0xff) << 56) | self.tail;
+        state.compress(block);
+        state.v2 ^= 0xff;
+        state.round();
+        state.round();
+        state.round();
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+#[test]
+fn matches_the_standard_library_siphash() {
+    // what `DefaultHasher::new` gave for the bytes 0, 1, .., n-1 when this was written
+    let known = [
+        (0, 0xd1fba762150c532c),
+        (1, 0x68a914128e01e473),
+        (7, 0x2f098ab0c751325a),
+        (8, 0xead411e67ebe2eea),
+        (9, 0x75927f9d95124362),
+        (15, 0xf30eb725bb91c9ea),
+        (16, 0x8972188433a5c5b7),
+        (63, 0x385d3e39e5f37359),
+        (64, 0x75e05fd5bbc870c6),
+        (255, 0x5dc1f93ea135eb43),
+    ];
+    let bytes: Vec<u8> = (0..=255).collect();
+    for (n, expected) in known {
+        let mut hasher = SipHasher13::new();
+        hasher.write(&bytes[..n]);
+        assert_eq!(hasher.finish(), expected, "{n} bytes");
+    }
+    // split writes hash like one write of the same bytes
+    let mut split = SipHasher13::new();
+    split.write(&bytes[..5]);
+    split.write_u32(u32::from_le_bytes([5, 6, 7, 8]));
+    split.write(&bytes[9..64]);
+    assert_eq!(split.finish(), 0x75e05fd5bbc870c6);
+}
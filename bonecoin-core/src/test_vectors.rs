@@ -0,0 +1,271 @@
+//! Known-good encodings and ids of representative chain values.
+//!
+//! Wallets persist coin ids, transaction ids, and block ids, and exchange values in their canonical
+//! encoding. `check` recomputes both for a fixed set of values and compares them with the vectors
+//! embedded here, so a change to the encoding or to id hashing fails loudly instead of silently
+//! orphaning stored state. Ids are hashed with SipHash-1-3 under zero keys, implemented in this crate
+//! rather than taken from the standard library, so the vectors hold on every platform and toolchain.
+//!
+//! When a format change is intended, print `regenerated_source()` and replace `EMBEDDED` with it.
+
+use std::fmt;
+
+use crate::codec::{Decode, DecodeError, Encode};
+use crate::{Address, AssetId, Block, Coin, CoinId, Input, Signature, Transaction, BLOCK_REWARD, TRANSACTION_VERSION};
+
+/// The encoding and id of one representative value.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TestVector {
+    /// What the value is, unique among the vectors.
+    pub name: &'static str,
+    /// The canonical encoding of the value.
+    pub encoding: Vec<u8>,
+    /// The value's id: the block id, transaction id, or coin id as a number.
+    pub id: u64,
+}
+
+/// A way the current code disagrees with the embedded vectors.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum VectorMismatch {
+    /// A representative value has no embedded vector, or an embedded vector no value.
+    Missing(&'static str),
+    /// The value now encodes differently.
+    Encoding(&'static str),
+    /// The value now hashes to a different id.
+    Id(&'static str),
+    /// The embedded encoding no longer decodes to the value.
+    Decoding(&'static str, DecodeError),
+}
+
+impl fmt::Display for VectorMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VectorMismatch::Missing(name) => write!(f, "no test vector matches {name:?}"),
+            VectorMismatch::Encoding(name) => write!(f, "the encoding of {name:?} changed"),
+            VectorMismatch::Id(name) => write!(f, "the id of {name:?} changed"),
+            VectorMismatch::Decoding(name, e) => write!(f, "the embedded encoding of {name:?} does not decode: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for VectorMismatch {}
+
+/// The representative values, each with its name.
+enum Value {
+    Block(Block),
+    Transaction(Transaction),
+    CoinId(CoinId),
+}
+
+impl Value {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Value::Block(block) => block.encode(),
+            Value::Transaction(transaction) => transaction.encode(),
+            Value::CoinId(coin_id) => coin_id.encode(),
+        }
+    }
+
+    fn id(&self) -> u64 {
+        match self {
+            Value::Block(block) => block.id().0,
+            Value::Transaction(transaction) => transaction.id().0,
+            Value::CoinId(coin_id) => coin_id.0,
+        }
+    }
+
+    /// Decode `bytes` as the same kind of value and compare.
+    fn decodes_from(&self, bytes: &[u8]) -> Result<bool, DecodeError> {
+        Ok(match self {
+            Value::Block(block) => Block::decode(bytes)? == *block,
+            Value::Transaction(transaction) => Transaction::decode(bytes)? == *transaction,
+            Value::CoinId(coin_id) => CoinId::decode(bytes)? == *coin_id,
+        })
+    }
+}
+
+/// Values covering every address, signature, and coin variant.
+fn representative_values() -> Vec<(&'static str, Value)> {
+    let coinbase = Transaction::coinbase(Address::Alice, BLOCK_REWARD + 3);
+    let payment = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![
+            Input::dummy(),
+            Input {
+                coin_id: coinbase.coin_id(1, 0),
+                signature: Signature::Valid(Address::Alice),
+            },
+        ],
+        outputs: vec![
+            Coin {
+                value: 40,
+                owner: Address::Bob,
+                asset_id: None,
+            },
+            Coin {
+                value: 7,
+                owner: Address::Custom(0x0123_4567_89ab_cdef),
+                asset_id: None,
+            },
+        ],
+    };
+    let escrow = Address::multisig(2, [Address::Charlie, Address::Dave, Address::Eve]);
+    let issuance = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input {
+            coin_id: payment.coin_id(2, 0),
+            signature: Signature::Multi(vec![Address::Charlie, Address::Eve]),
+        }],
+        outputs: vec![
+            Coin {
+                value: 1_000_000,
+                owner: escrow,
+                asset_id: Some(AssetId::issued_by(&payment.coin_id(2, 0))),
+            },
+            Coin {
+                value: 37,
+                owner: Address::Dave,
+                asset_id: None,
+            },
+        ],
+    };
+    let first = Block {
+        parent: Block::genesis().id(),
+        number: 1,
+        timestamp: 1_700_000_000,
+        difficulty: 1,
+        body: vec![coinbase.clone()],
+    };
+    let second = Block {
+        parent: first.id(),
+        number: 2,
+        timestamp: 1_700_000_600,
+        difficulty: 1,
+        body: vec![Transaction::coinbase(Address::Bob, BLOCK_REWARD), payment.clone(), issuance.clone()],
+    };
+
+    vec![
+        ("genesis block", Value::Block(Block::genesis())),
+        ("coinbase", Value::Transaction(coinbase.clone())),
+        ("payment", Value::Transaction(payment.clone())),
+        ("asset issuance to a multisig", Value::Transaction(issuance.clone())),
+        ("block 1", Value::Block(first)),
+        ("block 2", Value::Block(second)),
+        ("coin 0 of the coinbase at height 1", Value::CoinId(coinbase.coin_id(1, 0))),
+        ("coin 1 of the payment at height 2", Value::CoinId(payment.coin_id(2, 1))),
+        ("coin 0 of the issuance at height 2", Value::CoinId(issuance.coin_id(2, 0))),
+    ]
+}
+
+/// The vectors as `(name, hex encoding, id)`, generated by `regenerated_source`.
+#[rustfmt::skip]
+const EMBEDDED: &[(&str, &str, u64)] = &[
+    ("genesis block", "0000000000000000000000000000000000000000000000000000000000000000", 0xb85bed2614339b3d),
+    ("coinbase", "0000000000000000010000000000000035000000000000000000", 0x5a2fefcebd81630e),
+    ("payment", "020000000000000025e2c84e43a8090001e40e02520fcf86ad0000020000000000000028000000000000000100070000000000000005efcdab896745230100", 0x23d07b7aa7ad3fa2),
+    ("asset issuance to a multisig", "01000000000000000d5c217cfbf7aa4b0202000000000000000204020000000000000040420f0000000000060200000000000000030000000000000002030401997b52f49df7dd8e25000000000000000300", 0x91e30a57a2439432),
+    ("block 1", "3d9b331426ed5bb8010000000000000000f153650000000001000000000000000000000000000000010000000000000035000000000000000000", 0xe60a9baa00e0e25d),
+    ("block 2", "5de2e000aa9b0ae6020000000000000058f353650000000003000000000000000000000000000000010000000000000032000000000000000100020000000000000025e2c84e43a8090001e40e02520fcf86ad0000020000000000000028000000000000000100070000000000000005efcdab89674523010001000000000000000d5c217cfbf7aa4b0202000000000000000204020000000000000040420f0000000000060200000000000000030000000000000002030401997b52f49df7dd8e25000000000000000300", 0xfdbac9c721b63188),
+    ("coin 0 of the coinbase at height 1", "e40e02520fcf86ad", 0xad86cf0f52020ee4),
+    ("coin 1 of the payment at height 2", "c6555d3c3c1b8a90", 0x908a1b3c3c5d55c6),
+    ("coin 0 of the issuance at height 2", "84ec4ac559a65dbc", 0xbc5da659c54aec84),
+];
+
+/// The vectors embedded in this module.
+pub fn embedded() -> Vec<TestVector> {
+    EMBEDDED
+        .iter()
+        .map(|(name, hex, id)| TestVector {
+            name,
+            encoding: (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("embedded vectors are valid hex"))
+                .collect(),
+            id: *id,
+        })
+        .collect()
+}
+
+/// The vectors computed by the current code.
+pub fn generate() -> Vec<TestVector> {
+    representative_values()
+        .into_iter()
+        .map(|(name, value)| TestVector {
+            name,
+            encoding: value.encode(),
+            id: value.id(),
+        })
+        .collect()
+}
+
+/// Compare the current code with the embedded vectors, reporting every mismatch.
+pub fn check() -> Result<(), Vec<VectorMismatch>> {
+    let embedded = embedded();
+    let values = representative_values();
+    let mut mismatches = Vec::new();
+    for (name, value) in &values {
+        let Some(vector) = embedded.iter().find(|vector| vector.name == *name) else {
+            mismatches.push(VectorMismatch::Missing(name));
+            continue;
+        };
+        if value.encode() != vector.encoding {
+            mismatches.push(VectorMismatch::Encoding(name));
+        }
+        if value.id() != vector.id {
+            mismatches.push(VectorMismatch::Id(name));
+        }
+        match value.decodes_from(&vector.encoding) {
+            Err(e) => mismatches.push(VectorMismatch::Decoding(name, e)),
+            Ok(false) => mismatches.push(VectorMismatch::Encoding(name)),
+            Ok(true) => {}
+        }
+    }
+    for vector in &embedded {
+        if !values.iter().any(|(name, _)| *name == vector.name) {
+            mismatches.push(VectorMismatch::Missing(vector.name));
+        }
+    }
+    mismatches.dedup();
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(mismatches)
+    }
+}
+
+/// Rust source for `EMBEDDED` holding the vectors the current code generates.
+pub fn regenerated_source() -> String {
+    let mut source = String::from("const EMBEDDED: &[(&str, &str, u64)] = &[\n");
+    for vector in generate() {
+        let hex: String = vector.encoding.iter().map(|byte| format!("{byte:02x}")).collect();
+        source.push_str(&format!("    ({:?}, \"{hex}\", 0x{:016x}),\n", vector.name, vector.id));
+    }
+    source.push_str("];\n");
+    source
+}
+
+#[test]
+fn encodings_and_ids_match_the_vectors() {
+    if let Err(mismatches) = check() {
+        let reasons: Vec<String> = mismatches.iter().map(VectorMismatch::to_string).collect();
+        panic!("{}\nIf the change is intended, replace EMBEDDED with:\n{}", reasons.join("\n"), regenerated_source());
+    }
+}
+
+#[test]
+fn coins_carrying_an_asset_never_hash_like_native_coins() {
+    let asset = AssetId::issued_by(&Input::dummy().coin_id);
+    let coin = |value, owner, asset_id| Coin { value, owner, asset_id };
+    let carrying = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input::dummy()],
+        outputs: vec![coin(7, Address::Bob, Some(asset)), coin(5, Address::Alice, None)],
+    };
+    // the asset id moved into the value of a native coin, and the last owner into its place
+    let forged = Transaction {
+        outputs: vec![coin(7, Address::Bob, None), coin(asset.0, Address::Custom(0), None)],
+        ..carrying.clone()
+    };
+
+    assert_ne!(carrying.id(), forged.id());
+}
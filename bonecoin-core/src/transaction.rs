@@ -2,28 +2,169 @@
 //! The transaction type is the core in the transaction graph that is the history of the bonecoin economic system.
 //! Every valid transaction in the history of bonecoin will be included in this graph.
 
-use crate::{hash, Coin, CoinId, Signature};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::codec::Encode;
+use crate::{hash, Address, AssetId, Coin, CoinId, SigHash, Signature};
 
 /// A Bonecoin Transaction
 ///
 /// In order for a bonecoin transaction to be valid:
-/// * It must consume at least one input.
+/// * It must consume at least one input, unless it is the block's coinbase.
 /// * it must consume more bones than it creates (or an equal number).
+/// * For every issued asset it must create exactly as much as it consumes, except for the asset it issues itself.
 /// * Signatures must be valid.
 /// 
+/// * Its version must be one of `SUPPORTED_TRANSACTION_VERSIONS`.
+/// * It must stay within `MAX_TX_INPUTS`, `MAX_TX_OUTPUTS`, and `MAX_TX_WEIGHT`.
+///
 /// The wallet does not need to check incoming transactions, but it does need to ensure that it is not creating invalid transactions for its users.
-#[derive(Clone, Hash, Eq, PartialEq, Debug, Ord, PartialOrd)]
+#[derive(Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Transaction {
+    /// The format of the transaction. Versions other than 1 are part of the transaction's id.
+    #[cfg_attr(feature = "serde", serde(default = "first_version"))]
+    pub version: u16,
     pub inputs: Vec<Input>,
     pub outputs: Vec<Coin>,
 }
 
+/// The version of the transactions authored today.
+pub const TRANSACTION_VERSION: u16 = 1;
+
+/// The transaction versions a valid chain may contain.
+pub const SUPPORTED_TRANSACTION_VERSIONS: &[u16] = &[TRANSACTION_VERSION];
+
+/// The most inputs a transaction may consume.
+pub const MAX_TX_INPUTS: usize = 64;
+
+/// The most outputs a transaction may create.
+pub const MAX_TX_OUTPUTS: usize = 64;
+
+/// The largest weight a transaction may have.
+pub const MAX_TX_WEIGHT: usize = 16 * 1024;
+
+/// The version of transactions serialized before they carried one.
+#[cfg(feature = "serde")]
+fn first_version() -> u16 {
+    1
+}
+
+/// Version 1 transactions hash as they did before transactions were versioned, so their ids are unchanged.
+/// Every other version is hashed first, so the same inputs and outputs under two versions have different ids.
+impl Hash for Transaction {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if self.version != 1 {
+            self.version.hash(state);
+        }
+        self.inputs.hash(state);
+        self.outputs.hash(state);
+    }
+}
+
 impl Transaction {
+    /// Construct a coinbase transaction that mints `value` bones to `owner`.
+    /// The value must not exceed the block reward plus the tips collected in the block.
+    pub fn coinbase(owner: Address, value: u64) -> Self {
+        Self {
+            version: TRANSACTION_VERSION,
+            inputs: Vec::new(),
+            outputs: vec![Coin {
+                value,
+                owner,
+                asset_id: None,
+            }],
+        }
+    }
+
+    /// Whether the transaction's version is one a valid chain may contain.
+    pub fn has_supported_version(&self) -> bool {
+        SUPPORTED_TRANSACTION_VERSIONS.contains(&self.version)
+    }
+
+    /// The weight of the transaction: the length of its canonical encoding, in bytes.
+    pub fn weight(&self) -> usize {
+        self.encode().len()
+    }
+
+    /// Whether the transaction stays within the input, output, and weight limits.
+    pub fn is_within_limits(&self) -> bool {
+        self.inputs.len() <= MAX_TX_INPUTS && self.outputs.len() <= MAX_TX_OUTPUTS && self.weight() <= MAX_TX_WEIGHT
+    }
+
+    /// Whether this transaction has the shape of a coinbase: it consumes no inputs and creates only native bones.
+    /// Only the first transaction of a block is allowed to have this shape.
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.is_empty() && self.outputs.iter().all(Coin::is_native)
+    }
+
+    /// The asset this transaction is allowed to issue, derived from its first input.
+    /// A coinbase cannot issue an asset.
+    pub fn issued_asset(&self) -> Option<AssetId> {
+        self.inputs.first().map(|input| AssetId::issued_by(&input.coin_id))
+    }
+
+    /// Whether the transaction conserves the value of every issued asset it touches.
+    /// Native bones are not checked here, since their surplus is the tip.
+    /// Since a transaction does not contain the coins it consumes, the caller provides `coin_of`
+    /// to look them up; an input it cannot resolve makes the transaction invalid.
+    pub fn conserves_assets(&self, coin_of: impl Fn(&CoinId) -> Option<Coin>) -> bool {
+        let mut balance: HashMap<AssetId, i128> = HashMap::new();
+        for coin_id in self.iter_input_coin_ids() {
+            let Some(coin) = coin_of(&coin_id) else {
+                return false;
+            };
+            if let Some(asset_id) = coin.asset_id {
+                *balance.entry(asset_id).or_default() += coin.value as i128;
+            }
+        }
+        for coin in &self.outputs {
+            if let Some(asset_id) = coin.asset_id {
+                *balance.entry(asset_id).or_default() -= coin.value as i128;
+            }
+        }
+        let issued = self.issued_asset();
+        balance
+            .into_iter()
+            .all(|(asset_id, surplus)| surplus == 0 || (surplus < 0 && Some(asset_id) == issued))
+    }
+
     /// Calculate the id of this transaction
     pub fn id(&self) -> TransactionId {
         TransactionId(hash(self))
     }
 
+    /// The digest a `Signature::Committed` on input `index` with the given flags signs: the selected
+    /// inputs' coin ids and the selected outputs. Signatures are left out, so signing does not change it.
+    /// Under `SINGLE`, an input without an output at its index commits to no output.
+    pub fn signature_digest(&self, index: usize, sighash: SigHash) -> TransactionId {
+        let inputs: Vec<CoinId> = if sighash.anyone_can_pay() {
+            self.inputs.get(index).map(|input| input.coin_id).into_iter().collect()
+        } else {
+            self.iter_input_coin_ids().collect()
+        };
+        let outputs: &[Coin] = match sighash.outputs() {
+            SigHash::ALL => &self.outputs,
+            SigHash::SINGLE => self.outputs.get(index..=index).unwrap_or_default(),
+            _ => &[],
+        };
+        TransactionId(hash(&(sighash, index, inputs, outputs)))
+    }
+
+    /// Whether the signature on input `index` still signs this transaction. Only committed
+    /// signatures carry a digest to check; mocked signatures of the other kinds always pass.
+    pub fn signature_commits(&self, index: usize) -> bool {
+        match self.inputs.get(index).map(|input| &input.signature) {
+            Some(Signature::Committed { sighash, digest, .. }) => {
+                sighash.is_defined() && self.signature_digest(index, *sighash) == *digest
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
     /// Calculate the id of a coin created by this transaction.
     /// Since a transaction can create multiple coins, you must specify the index
     /// of the coin in this transaction and the block number in which this transaction is included.
@@ -61,6 +202,7 @@ impl Transaction {
 /// The wallet does not need to verify signatures when importing transactions; that is the blockchain's responsibility.
 /// However, the wallet must provide valid signatures when creating transactions.
 #[derive(Clone, Eq, Hash, PartialEq, Debug, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Input {
     /// Specifies which coin is being spent.
     pub coin_id: CoinId,
@@ -82,4 +224,105 @@ impl Input {
 
 /// A unique identifier for a transaction. It is a wrapper around the hash of the transaction.
 #[derive(Copy, Hash, Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
-pub struct TransactionId(u64);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransactionId(pub(crate) u64);
+
+impl fmt::Display for TransactionId {
+    /// Formats the underlying hash as fixed width hex, e.g. for logs and exports.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+#[test]
+fn only_the_issued_asset_may_be_created_from_nothing() {
+    let input = Input::dummy();
+    let own = AssetId::issued_by(&input.coin_id);
+    let other = AssetId::issued_by(&CoinId(7));
+    let coin_of = |_: &CoinId| {
+        Some(Coin {
+            value: 10,
+            owner: Address::Alice,
+            asset_id: Some(other),
+        })
+    };
+    let tx = |outputs: Vec<(u64, AssetId)>| Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![input.clone()],
+        outputs: outputs
+            .into_iter()
+            .map(|(value, asset_id)| Coin {
+                value,
+                owner: Address::Bob,
+                asset_id: Some(asset_id),
+            })
+            .collect(),
+    };
+
+    assert!(tx(vec![(10, other), (500, own)]).conserves_assets(coin_of));
+    assert!(!tx(vec![(11, other)]).conserves_assets(coin_of));
+    assert!(!tx(vec![(9, other)]).conserves_assets(coin_of));
+    assert!(!tx(vec![(10, other)]).conserves_assets(|_| None));
+}
+
+#[test]
+fn committed_signatures_survive_only_unselected_changes() {
+    let coin = |value| Coin {
+        value,
+        owner: Address::Bob,
+        asset_id: None,
+    };
+    let mut tx = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input::dummy()],
+        outputs: vec![coin(5)],
+    };
+    let sign = |tx: &Transaction, sighash| Signature::Committed {
+        signer: Address::Alice,
+        sighash,
+        digest: tx.signature_digest(0, sighash),
+    };
+
+    tx.inputs[0].signature = sign(&tx, SigHash::ALL);
+    assert!(tx.signature_commits(0));
+    tx.outputs.push(coin(6));
+    assert!(!tx.signature_commits(0));
+
+    // SINGLE | ANYONECANPAY lets others add inputs and outputs after the signed pair
+    tx.inputs[0].signature = sign(&tx, SigHash::SINGLE | SigHash::ANYONECANPAY);
+    tx.inputs.push(Input { coin_id: CoinId(7), signature: Signature::Invalid });
+    tx.outputs.push(coin(7));
+    assert!(tx.signature_commits(0));
+    tx.outputs[0].value = 4;
+    assert!(!tx.signature_commits(0));
+
+    // NONE without ANYONECANPAY still fixes the inputs
+    tx.inputs[0].signature = sign(&tx, SigHash::NONE);
+    tx.outputs.clear();
+    assert!(tx.signature_commits(0));
+    tx.inputs.pop();
+    assert!(!tx.signature_commits(0));
+    tx.inputs[0].signature = Signature::Committed { signer: Address::Alice, sighash: SigHash(0x05), digest: TransactionId(0) };
+    assert!(!tx.signature_commits(0));
+}
+
+#[test]
+fn limits_bound_inputs_outputs_and_weight() {
+    let coin = Coin {
+        value: 1,
+        owner: Address::Custom(1),
+        asset_id: None,
+    };
+    let mut tx = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input::dummy(); MAX_TX_INPUTS],
+        outputs: vec![coin.clone(); MAX_TX_OUTPUTS],
+    };
+    assert_eq!(tx.weight(), tx.encode().len());
+    assert!(tx.is_within_limits());
+    tx.outputs.push(coin);
+    assert!(!tx.is_within_limits());
+    tx.outputs.pop();
+    tx.inputs[0].signature = Signature::Multi(vec![Address::Custom(2); MAX_TX_WEIGHT / 8]);
+    assert!(!tx.is_within_limits());
+}
@@ -3,14 +3,15 @@
 //! this interface to provide a common interface to downstream wallet implementors
 
 use std::collections::HashSet;
+use std::fmt;
 
-use crate::{Address, BlockId, Coin, CoinId, NodeEndpoint, Transaction};
-
-/// A common interface to be implemented by wallet providers.
-pub trait WalletApi {
-    /// Create a new instance of the wallet that owns the given addresses
-    fn new(addresses: impl Iterator<Item = Address>) -> Self;
+use crate::{Address, BlockId, Coin, CoinId, NodeEndpoint, Transaction, TransactionId};
 
+/// The queries a wallet answers from its local database.
+///
+/// Code that only reads balances should bound on this trait alone, which also makes it easy to
+/// stand in a mock reader or a read-only view of a wallet.
+pub trait WalletReader {
     /// Get the height of the best block that the wallet is aware of.
     fn best_height(&self) -> u64;
 
@@ -18,18 +19,22 @@ pub trait WalletApi {
     fn best_hash(&self) -> BlockId;
 
     /// Calculate the total number of bones owned by this address.
+    /// Coins carrying an issued asset are not bones and are not counted.
     fn total_assets_of(&self, address: Address) -> WalletResult<u64>;
 
     /// Calculate the total number of bones owned by all addresses in the entire wallet.
     fn net_worth(&self) -> u64;
 
-    /// Return the set of all UTXOs owned by the given address that
+    /// Return the set of all UTXOs holding native bones owned by the given address that
     /// the wallet knows about along with their amounts.
     fn all_coins_of(&self, address: Address) -> WalletResult<HashSet<(CoinId, u64)>>;
 
     /// Query the owner and value of a specific coin by its CoinId (aka its hash).
     fn coin_details(&self, coin_id: &CoinId) -> WalletResult<Coin>;
+}
 
+/// Transaction construction from the coins the wallet knows about.
+pub trait TransactionAuthor {
     /// Construct a transaction that consumes specific inputs and creates specific outputs.
     fn create_manual_transaction(
         &self,
@@ -48,6 +53,12 @@ pub trait WalletApi {
         payment_amount: u64,
         burn_aka_tip: u64,
     ) -> WalletResult<Transaction>;
+}
+
+/// Creating a wallet and keeping it in step with a node.
+pub trait WalletSync {
+    /// Create a new instance of the wallet that owns the given addresses
+    fn new(addresses: impl Iterator<Item = Address>) -> Self;
 
     /// Synchronizes the wallet with the node. The wallet fully trusts the node and does not verify the information provided by the node.
     ///
@@ -56,30 +67,143 @@ pub trait WalletApi {
     fn sync<Node: NodeEndpoint>(&mut self, node: &Node);
 }
 
+/// A common interface to be implemented by wallet providers.
+///
+/// This is the combination of the reader, author, and sync traits, implemented for every type
+/// that implements all three.
+pub trait WalletApi: WalletReader + TransactionAuthor + WalletSync {}
+
+impl<W: WalletReader + TransactionAuthor + WalletSync> WalletApi for W {}
+
 /// Various errors that can occur during wallet operations.
 /// 
 /// The first several can happen during querying or transaction creation.
 /// The latter several can only happen during transaction creation.
-#[derive(Eq, PartialEq, Debug, Ord, PartialOrd)]
+#[derive(Clone, Eq, PartialEq, Debug, Ord, PartialOrd)]
 pub enum WalletError {
     /// The address being queried is not tracked by this wallet.
-    ForeignAddress,
+    ForeignAddress(Address),
     /// The specified coin is not known to this wallet.
     /// This could be because the wallet is not fully synced or the coin is not owned by this wallet's addresses.
-    UnknownCoin,
+    UnknownCoin(CoinId),
     /// The wallet does not own any addresses and the requested action requires an owned address.
     NoOwnedAddresses,
+    /// The specified transaction is not known to this wallet.
+    UnknownTransaction(TransactionId),
 
     /// The number of bones required by this transaction exceeds the number of bones consumed (or available to be consumed).
     /// The wallet prevents users from constructing invalid transactions.
-    InsufficientFunds,
+    InsufficientFunds { needed: u64, available: u64 },
     /// You are attempting to create a coin with zero value.
     /// The wallet will not allow the user to construct an invalid transaction.
     ZeroCoinValue,
     /// Attempting to create a transaction with zero inputs.
     /// The wallet will not allow the user to construct an invalid transaction.
     ZeroInputs,
+    /// Attempting to spend a coinbase coin before `COINBASE_MATURITY` blocks have been built on top of it.
+    ImmatureCoin(CoinId),
+    /// The transaction would break a spending limit or recipient rule configured on the wallet.
+    PolicyViolation,
+    /// The transaction exceeds the wallet's approval threshold and must go through a second approval.
+    ApprovalRequired,
+    /// The transaction creates more or less of an issued asset than it consumes.
+    /// Only the asset issued by the transaction itself may be created from nothing.
+    AssetNotConserved,
+    /// The node did not accept the transaction for broadcast.
+    BroadcastFailed(TransactionId),
+    /// The transaction would consume more inputs, create more outputs, or weigh more than a transaction may.
+    /// The wallet will not allow the user to construct an invalid transaction.
+    TooLarge,
+    /// The coin belongs to an address the wallet only receives on, whose coins are not spent without an explicit override.
+    AddressSpendingDisabled(Address),
+    /// The transaction would return more change coins than the wallet's selection constraints allow.
+    TooManyChangeOutputs { allowed: usize },
+    /// The change policy asks for a fresh change address, but every owned address has received a coin or been handed out.
+    NoFreshAddress,
 }
 
+impl fmt::Display for WalletError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletError::ForeignAddress(address) => write!(f, "address {address} is not owned by this wallet"),
+            WalletError::UnknownCoin(coin_id) => write!(f, "coin {coin_id} is not known to this wallet"),
+            WalletError::NoOwnedAddresses => write!(f, "the wallet does not own any addresses"),
+            WalletError::UnknownTransaction(tx_id) => write!(f, "transaction {tx_id} is not known to this wallet"),
+            WalletError::InsufficientFunds { needed, available } => {
+                write!(f, "insufficient funds: {needed} bones needed but only {available} available")
+            }
+            WalletError::ZeroCoinValue => write!(f, "cannot create a coin with zero value"),
+            WalletError::ZeroInputs => write!(f, "cannot create a transaction without inputs"),
+            WalletError::ImmatureCoin(coin_id) => write!(f, "coinbase coin {coin_id} is not mature yet"),
+            WalletError::PolicyViolation => write!(f, "the transaction violates the wallet's spending policy"),
+            WalletError::ApprovalRequired => write!(f, "the transaction needs a second approval"),
+            WalletError::AssetNotConserved => write!(f, "the transaction does not conserve its issued assets"),
+            WalletError::BroadcastFailed(tx_id) => write!(f, "the node did not accept transaction {tx_id}"),
+            WalletError::TooLarge => write!(f, "the transaction exceeds the transaction size limits"),
+            WalletError::AddressSpendingDisabled(address) => write!(f, "address {address} is receive-only"),
+            WalletError::TooManyChangeOutputs { allowed } => {
+                write!(f, "the transaction needs change, but the selection constraints allow {allowed} change outputs")
+            }
+            WalletError::NoFreshAddress => write!(f, "every owned address has received a coin or been handed out"),
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
 /// A convenient type alias to return from fallible wallet methods.
 pub type WalletResult<T> = Result<T, WalletError>;
+
+#[test]
+fn a_mock_reader_stands_in_for_a_wallet() {
+    /// Answers every query as if Alice held a single coin.
+    struct OneCoin;
+
+    impl WalletReader for OneCoin {
+        fn best_height(&self) -> u64 {
+            1
+        }
+
+        fn best_hash(&self) -> BlockId {
+            crate::Block::genesis().id()
+        }
+
+        fn total_assets_of(&self, address: Address) -> WalletResult<u64> {
+            match address {
+                Address::Alice => Ok(10),
+                other => Err(WalletError::ForeignAddress(other)),
+            }
+        }
+
+        fn net_worth(&self) -> u64 {
+            10
+        }
+
+        fn all_coins_of(&self, address: Address) -> WalletResult<HashSet<(CoinId, u64)>> {
+            self.total_assets_of(address).map(|value| HashSet::from([(CoinId(1), value)]))
+        }
+
+        fn coin_details(&self, coin_id: &CoinId) -> WalletResult<Coin> {
+            match coin_id {
+                CoinId(1) => Ok(Coin {
+                    value: 10,
+                    owner: Address::Alice,
+                    asset_id: None,
+                }),
+                other => Err(WalletError::UnknownCoin(*other)),
+            }
+        }
+    }
+
+    // read-only code can take any reader, including a trait object
+    fn share_of(reader: &dyn WalletReader, address: Address) -> WalletResult<u64> {
+        Ok(reader.total_assets_of(address)? * 100 / reader.net_worth())
+    }
+
+    assert_eq!(share_of(&OneCoin, Address::Alice), Ok(100));
+    assert_eq!(
+        OneCoin.coin_details(&CoinId(2)).unwrap_err().to_string(),
+        format!("coin {} is not known to this wallet", CoinId(2))
+    );
+    assert_eq!(share_of(&OneCoin, Address::Bob), Err(WalletError::ForeignAddress(Address::Bob)));
+}
@@ -0,0 +1,68 @@
+//! Compare the memory held by `MemoryStore` and `SledStore` for the same coin set.
+//!
+//! Run with `cargo run --release --example store_memory --features sled -- [coins]`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bonecoin_core::*;
+use utxo_wallet::{MemoryStore, SledStore, StoreBatch, WalletStore};
+
+/// Counts the bytes currently allocated on the heap.
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const COINS_PER_BLOCK: u64 = 1000;
+
+/// Commit `coins` coins in blocks of `COINS_PER_BLOCK` and return the heap bytes the store holds afterwards.
+fn fill(store: &mut dyn WalletStore, coins: u64) -> usize {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let source = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input::dummy()],
+        outputs: vec![],
+    };
+    for height in 0..coins.div_ceil(COINS_PER_BLOCK) {
+        let mut batch = StoreBatch::default();
+        for i in height * COINS_PER_BLOCK..((height + 1) * COINS_PER_BLOCK).min(coins) {
+            let coin = Coin {
+                value: i + 1,
+                owner: Address::Alice,
+                asset_id: None,
+            };
+            batch.changes.insert(source.coin_id(height + 1, i as usize), Some(coin));
+        }
+        store.commit(&batch, (height + 1, Block::genesis().id())).expect("commit failed");
+    }
+    ALLOCATED.load(Ordering::Relaxed).saturating_sub(before)
+}
+
+fn main() {
+    let coins = std::env::args().nth(1).and_then(|arg| arg.parse().ok()).unwrap_or(200_000);
+
+    let mut memory = MemoryStore::new();
+    println!("MemoryStore: {:>12} bytes for {coins} coins", fill(&mut memory, coins));
+    drop(memory);
+
+    let path = std::env::temp_dir().join(format!("store_memory_{}", std::process::id()));
+    let mut sled = SledStore::open(&path).expect("failed to open the database");
+    println!("SledStore:   {:>12} bytes for {coins} coins", fill(&mut sled, coins));
+    drop(sled);
+    let _ = std::fs::remove_dir_all(path);
+}
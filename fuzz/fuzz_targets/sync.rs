@@ -0,0 +1,9 @@
+//! Syncs a wallet through the chain mutations the input decodes to. Run with `cargo fuzz run sync`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    utxo_wallet::fuzz::sync_mutations(data);
+});
@@ -0,0 +1,293 @@
+//! Cost-basis accounting over the wallet history.
+//!
+//! Bones entering the wallet from outside form lots. Bones leaving the wallet (payments and tips)
+//! are disposals, matched against the open lots first-in-first-out or last-in-first-out.
+//! Change and other self transfers only dispose of their tip: the rest of the consumed lots
+//! carries over to the change outputs rather than being closed and reopened.
+//!
+//! The income report sums the same history without lots. Internal transfers are netted out of its
+//! income and expenses, and only their tips are listed, separately.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+use bonecoin_core::TransactionId;
+
+use crate::{Direction, TxFilter, Wallet};
+
+/// The order in which disposals consume open lots.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LotMatching {
+    /// Dispose of the oldest lots first.
+    Fifo,
+    /// Dispose of the newest lots first.
+    Lifo,
+}
+
+/// Bones acquired by the wallet in a single transaction that have not been disposed of yet.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Lot {
+    /// The transaction that brought the bones into the wallet.
+    pub acquired_by: TransactionId,
+    /// The height of the block that included that transaction.
+    pub acquired_at_height: u64,
+    /// The number of bones still held from this lot.
+    pub amount: u64,
+}
+
+/// The part of a disposal matched against a single lot.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct LotDisposal {
+    /// The transaction that sent the bones out of the wallet.
+    pub disposed_by: TransactionId,
+    /// The height of the block that included that transaction.
+    pub disposed_at_height: u64,
+    /// The transaction that acquired the matched lot.
+    pub acquired_by: TransactionId,
+    /// The height at which the matched lot was acquired.
+    pub acquired_at_height: u64,
+    /// The number of bones disposed of from the matched lot.
+    pub amount: u64,
+}
+
+/// The result of replaying the history through the lot matcher.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct LotReport {
+    /// Every disposal in chain order, split per matched lot.
+    pub disposals: Vec<LotDisposal>,
+    /// The lots that are still (partially) held, oldest first.
+    pub open_lots: Vec<Lot>,
+}
+
+impl LotReport {
+    /// Render the disposals as CSV with a header row.
+    pub fn disposals_csv(&self) -> String {
+        let mut csv = String::from("disposed_by,disposed_at_height,acquired_by,acquired_at_height,amount\n");
+        for d in &self.disposals {
+            writeln!(
+                csv,
+                "{},{},{},{},{}",
+                d.disposed_by, d.disposed_at_height, d.acquired_by, d.acquired_at_height, d.amount
+            )
+            .expect("writing to a String cannot fail");
+        }
+        csv
+    }
+}
+
+/// The bones that entered and left the wallet over a range of heights.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct IncomeReport {
+    /// The bones received from outside the wallet, net of the wallet coins the same transactions consumed.
+    pub income: u64,
+    /// The bones paid out of the wallet, tips included, net of the change, leaving out internal transfers.
+    pub expenses: u64,
+    /// The number of internal transfers in the range.
+    pub internal_transfers: usize,
+    /// The tips the internal transfers burned, the only bones they took out of the wallet.
+    pub internal_tips: u64,
+}
+
+impl IncomeReport {
+    /// The change in the wallet's bones over the range: income less expenses and internal tips.
+    pub fn net(&self) -> i128 {
+        self.income as i128 - self.expenses as i128 - self.internal_tips as i128
+    }
+}
+
+impl Wallet {
+    /// Sum the income and expenses of the history entries included at `heights`.
+    pub fn income_report(&self, heights: RangeInclusive<u64>) -> IncomeReport {
+        let filter = TxFilter {
+            height: Some(heights),
+            ..TxFilter::default()
+        };
+        let mut report = IncomeReport::default();
+        for entry in self.history.find(&filter, &self.labels.transactions) {
+            if entry.direction == Direction::Internal {
+                report.internal_transfers += 1;
+                report.internal_tips += entry.expense();
+            } else {
+                report.income += entry.income();
+                report.expenses += entry.expense();
+            }
+        }
+        report
+    }
+
+    /// The tips burned by the wallet's transactions included from `start_height` to `end_height`,
+    /// inclusive. Entries that do not know their tip count as burning nothing.
+    pub fn fees_paid_between(&self, start_height: u64, end_height: u64) -> u64 {
+        let filter = TxFilter {
+            height: Some(start_height..=end_height),
+            ..TxFilter::default()
+        };
+        self.history.find(&filter, &self.labels.transactions).iter().filter_map(|entry| entry.burned).sum()
+    }
+
+    /// Replay the wallet history and match every disposal against the acquired lots.
+    pub fn lot_report(&self, matching: LotMatching) -> LotReport {
+        let mut lots: VecDeque<Lot> = VecDeque::new();
+        let mut disposals = Vec::new();
+
+        for entry in self.history.entries() {
+            let received = entry.value_received();
+            let spent = entry.value_spent();
+
+            if received > spent {
+                lots.push_back(Lot {
+                    acquired_by: entry.tx_id,
+                    acquired_at_height: entry.height,
+                    amount: received - spent,
+                });
+                continue;
+            }
+
+            let mut to_dispose = spent - received;
+            while to_dispose > 0 {
+                let lot = match matching {
+                    LotMatching::Fifo => lots.front_mut(),
+                    LotMatching::Lifo => lots.back_mut(),
+                };
+                // the wallet may spend coins it never saw arrive, e.g. after a partial resync
+                let Some(lot) = lot else { break };
+
+                let amount = to_dispose.min(lot.amount);
+                disposals.push(LotDisposal {
+                    disposed_by: entry.tx_id,
+                    disposed_at_height: entry.height,
+                    acquired_by: lot.acquired_by,
+                    acquired_at_height: lot.acquired_at_height,
+                    amount,
+                });
+                lot.amount -= amount;
+                to_dispose -= amount;
+
+                if lot.amount == 0 {
+                    match matching {
+                        LotMatching::Fifo => lots.pop_front(),
+                        LotMatching::Lifo => lots.pop_back(),
+                    };
+                }
+            }
+        }
+
+        LotReport {
+            disposals,
+            open_lots: lots.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// Alice receives 100 bones in block 1 and 50 in block 2, then pays Charlie 70 out of the first
+    /// coin in block 3 with a tip of 2 and 28 in change. Returns the synced wallet and the three
+    /// transactions.
+    fn two_lots_and_a_payment() -> (Wallet, [Transaction; 3]) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+
+        let receive_1 = mint([(Address::Alice, 100)]);
+        let receive_2 = mint([(Address::Alice, 50)]);
+        let pay = spend(receive_1.coin_id(1, 0), Address::Alice, [(Address::Charlie, 70), (Address::Alice, 28)]);
+
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![receive_1.clone()]);
+        let b2_id = node.add_block_as_best(b1_id, vec![receive_2.clone()]);
+        node.add_block_as_best(b2_id, vec![pay.clone()]);
+        wallet.sync(&node);
+        (wallet, [receive_1, receive_2, pay])
+    }
+
+    #[test]
+    fn fifo_disposes_of_the_oldest_lot_first() {
+        let (wallet, [receive_1, _, pay]) = two_lots_and_a_payment();
+        assert_eq!(
+            wallet.lot_report(LotMatching::Fifo).disposals,
+            vec![LotDisposal {
+                disposed_by: pay.id(),
+                disposed_at_height: 3,
+                acquired_by: receive_1.id(),
+                acquired_at_height: 1,
+                amount: 72,
+            }]
+        );
+    }
+
+    #[test]
+    fn change_keeps_the_rest_of_its_lot_open() {
+        let (wallet, _) = two_lots_and_a_payment();
+        let fifo = wallet.lot_report(LotMatching::Fifo);
+        assert_eq!(
+            fifo.open_lots.iter().map(|lot| (lot.acquired_at_height, lot.amount)).collect::<Vec<_>>(),
+            vec![(1, 28), (2, 50)]
+        );
+    }
+
+    #[test]
+    fn lifo_disposes_of_the_newest_lot_first() {
+        let (wallet, _) = two_lots_and_a_payment();
+        let lifo = wallet.lot_report(LotMatching::Lifo);
+        assert_eq!(
+            lifo.disposals.iter().map(|d| (d.acquired_at_height, d.amount)).collect::<Vec<_>>(),
+            vec![(2, 50), (1, 22)]
+        );
+        assert_eq!(lifo.open_lots.len(), 1);
+        assert_eq!(lifo.open_lots[0].amount, 78);
+    }
+
+    #[test]
+    fn disposals_are_exported_as_csv() {
+        let (wallet, [_, receive_2, pay]) = two_lots_and_a_payment();
+        let csv = wallet.lot_report(LotMatching::Lifo).disposals_csv();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("disposed_by,disposed_at_height,acquired_by,acquired_at_height,amount"));
+        assert_eq!(lines.next(), Some(format!("{},3,{},2,50", pay.id(), receive_2.id()).as_str()));
+        assert_eq!(lines.count(), 1);
+    }
+
+    /// A wallet of Alice and Bob synced to four blocks: Alice receives 100 bones, consolidates
+    /// them to Bob with a tip of 3, Bob pays Eve 40 with 50 in change to Alice, and Alice spends
+    /// that change along with a coin of someone else's into 60 for herself.
+    fn with_an_internal_transfer() -> Wallet {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice_and_bob();
+        let funding = mint([(Address::Alice, 100)]);
+        let consolidation = spend(funding.coin_id(1, 0), Address::Alice, [(Address::Bob, 97)]);
+        let payment = spend(consolidation.coin_id(2, 0), Address::Bob, [(Address::Eve, 40), (Address::Alice, 50)]);
+        let mut joint = spend(payment.coin_id(3, 1), Address::Alice, [(Address::Alice, 60)]);
+        joint.inputs.push(Input::dummy());
+        let mut parent = Block::genesis().id();
+        for tx in [funding, consolidation, payment, joint] {
+            parent = node.add_block_as_best(parent, vec![tx]);
+        }
+        wallet.sync(&node);
+        wallet
+    }
+
+    #[test]
+    fn spends_between_the_wallets_addresses_are_internal() {
+        let wallet = with_an_internal_transfer();
+        let directions: Vec<Direction> = wallet.history().iter().map(|entry| entry.direction).collect();
+        assert_eq!(directions, [Direction::Incoming, Direction::Internal, Direction::Outgoing, Direction::SelfTransfer]);
+    }
+
+    #[test]
+    fn internal_transfers_cost_only_their_tip() {
+        let wallet = with_an_internal_transfer();
+        assert_eq!(wallet.history()[1].expense(), 3);
+        assert!(wallet.export_history(ExportFormat::Csv).contains(",internal,97,100,"));
+    }
+
+    #[test]
+    fn income_reports_net_out_internal_transfers() {
+        let wallet = with_an_internal_transfer();
+        let report = wallet.income_report(1..=4);
+        assert_eq!(report, IncomeReport { income: 110, expenses: 47, internal_transfers: 1, internal_tips: 3 });
+        assert_eq!(report.net(), wallet.net_worth() as i128);
+        assert_eq!(wallet.income_report(2..=2), IncomeReport { internal_transfers: 1, internal_tips: 3, ..IncomeReport::default() });
+    }
+}
@@ -0,0 +1,262 @@
+//! Named accounts grouping the wallet's addresses, such as "operations" and "savings".
+//!
+//! An account is a set of owned addresses, and an address belongs to at most one account. Balances,
+//! history, and sends can be restricted to an account: `send_from_account` only spends the account's
+//! coins and returns the change to it, so its balance never pays for another account's spending.
+//! `transfer_between_accounts` moves bones from one account to another in a single self-spend.
+//! Addresses in no account are only spent by the wallet-wide functions. Accounts are part of the
+//! exported state.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use bonecoin_core::*;
+
+use crate::{HistoryEntry, Wallet};
+
+/// Errors that can occur while managing or spending from accounts.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum AccountError {
+    /// The wallet cannot build the transaction, or does not own an address.
+    Wallet(WalletError),
+    /// There is no account with this name.
+    UnknownAccount(String),
+    /// An account with this name already exists.
+    AccountExists(String),
+    /// The address already belongs to another account.
+    AddressInAccount { address: Address, account: String },
+    /// An account needs at least one address.
+    NoAddresses,
+}
+
+impl From<WalletError> for AccountError {
+    fn from(e: WalletError) -> Self {
+        AccountError::Wallet(e)
+    }
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::Wallet(e) => write!(f, "{e}"),
+            AccountError::UnknownAccount(name) => write!(f, "there is no account named {name:?}"),
+            AccountError::AccountExists(name) => write!(f, "an account named {name:?} already exists"),
+            AccountError::AddressInAccount { address, account } => write!(f, "address {address:?} already belongs to account {account:?}"),
+            AccountError::NoAddresses => write!(f, "an account needs at least one address"),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+impl Wallet {
+    /// Create an account of owned addresses that belong to no other account.
+    pub fn create_account(&mut self, name: impl Into<String>, addresses: impl IntoIterator<Item = Address>) -> Result<(), AccountError> {
+        let name = name.into();
+        if self.accounts.contains_key(&name) {
+            return Err(AccountError::AccountExists(name));
+        }
+        let addresses: BTreeSet<Address> = addresses.into_iter().collect();
+        if addresses.is_empty() {
+            return Err(AccountError::NoAddresses);
+        }
+        for address in &addresses {
+            if !self.addresses.contains(address) {
+                return Err(WalletError::ForeignAddress(address.clone()).into());
+            }
+            if let Some(account) = self.account_of(address) {
+                return Err(AccountError::AddressInAccount {
+                    address: address.clone(),
+                    account: account.to_string(),
+                });
+            }
+        }
+        self.accounts.insert(name, addresses);
+        Ok(())
+    }
+
+    /// Remove an account, returning its addresses. Their coins stay in the wallet.
+    pub fn remove_account(&mut self, name: &str) -> Result<BTreeSet<Address>, AccountError> {
+        self.accounts.remove(name).ok_or_else(|| AccountError::UnknownAccount(name.to_string()))
+    }
+
+    /// The names of the accounts, in order.
+    pub fn accounts(&self) -> Vec<&str> {
+        self.accounts.keys().map(String::as_str).collect()
+    }
+
+    /// The addresses of an account.
+    pub fn account_addresses(&self, name: &str) -> Result<&BTreeSet<Address>, AccountError> {
+        self.accounts.get(name).ok_or_else(|| AccountError::UnknownAccount(name.to_string()))
+    }
+
+    /// The account the address belongs to, if any.
+    pub fn account_of(&self, address: &Address) -> Option<&str> {
+        self.accounts.iter().find(|(_, addresses)| addresses.contains(address)).map(|(name, _)| name.as_str())
+    }
+
+    /// The bones held by the account's addresses, immature coinbase coins included.
+    pub fn account_balance(&self, name: &str) -> Result<u64, AccountError> {
+        let addresses = self.account_addresses(name)?;
+        Ok(self.coins.values().filter(|coin| coin.is_native() && addresses.contains(&coin.owner)).map(|coin| coin.value).sum())
+    }
+
+    /// The transactions that created or consumed a coin of the account, oldest first.
+    pub fn account_history(&self, name: &str) -> Result<Vec<&HistoryEntry>, AccountError> {
+        let addresses = self.account_addresses(name)?;
+        Ok(self
+            .history
+            .entries()
+            .iter()
+            .filter(|entry| entry.addresses().into_iter().any(|address| addresses.contains(address)))
+            .collect())
+    }
+
+    /// Like `create_automatic_transaction`, but only spending the account's coins and returning the
+    /// change to the account.
+    pub fn send_from_account(&self, name: &str, recipient: Address, payment_amount: u64, burn_aka_tip: u64) -> Result<Transaction, AccountError> {
+        let addresses = self.account_addresses(name)?;
+        let change = self.account_deposit_address(addresses);
+        let transaction = self.build_restricted_transaction(
+            recipient,
+            payment_amount,
+            burn_aka_tip,
+            &|_, coin| addresses.contains(&coin.owner),
+            Some(&change),
+            false,
+        )?;
+        self.check_policy(&transaction)?;
+        Ok(transaction)
+    }
+
+    /// Construct a self-spend moving `amount` bones from one account to the other, paid to the
+    /// address the receiving account takes change on.
+    pub fn transfer_between_accounts(&self, from: &str, to: &str, amount: u64, burn_aka_tip: u64) -> Result<Transaction, AccountError> {
+        let recipient = self.account_deposit_address(self.account_addresses(to)?);
+        self.send_from_account(from, recipient, amount, burn_aka_tip)
+    }
+
+    /// The address of an account that receives its change and transfers: its smallest address that
+    /// is not receive-only, or its smallest address if they all are.
+    fn account_deposit_address(&self, addresses: &BTreeSet<Address>) -> Address {
+        addresses
+            .iter()
+            .find(|address| !self.receive_only.contains(*address))
+            .or_else(|| addresses.first())
+            .cloned()
+            .expect("accounts are never empty")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    use std::collections::BTreeSet;
+
+    /// A wallet of Alice, Bob and Charlie, synced to a block paying them 30, 20 and 100 bones,
+    /// with Alice and Bob in an "operations" account and Charlie in "savings". Returns the node,
+    /// the wallet and the funding transaction.
+    fn two_accounts() -> (MockNode, Wallet, Transaction) {
+        let mut node = MockNode::new();
+        let mut wallet = Wallet::new([Address::Alice, Address::Bob, Address::Charlie].into_iter());
+        let funding = mint([(Address::Alice, 30), (Address::Bob, 20), (Address::Charlie, 100)]);
+        node.add_block_as_best(Block::genesis().id(), vec![funding.clone()]);
+        wallet.sync(&node);
+        wallet.create_account("operations", [Address::Alice, Address::Bob]).unwrap();
+        wallet.create_account("savings", [Address::Charlie]).unwrap();
+        (node, wallet, funding)
+    }
+
+    /// Transfer 70 bones from savings to operations, mine the transfer and sync to it.
+    fn transfer_and_sync(node: &mut MockNode, wallet: &mut Wallet) -> Transaction {
+        let transfer = wallet.transfer_between_accounts("savings", "operations", 70, 0).unwrap();
+        node.add_block_as_best(wallet.best_hash(), vec![transfer.clone()]);
+        wallet.sync(node);
+        transfer
+    }
+
+    #[test]
+    fn accounts_need_a_new_name_and_owned_addresses_in_no_other_account() {
+        let (_, mut wallet, _) = two_accounts();
+        assert_eq!(wallet.create_account("operations", [Address::Charlie]), Err(AccountError::AccountExists("operations".to_string())));
+        assert_eq!(wallet.create_account("payroll", []), Err(AccountError::NoAddresses));
+        assert_eq!(
+            wallet.create_account("payroll", [Address::Eve]),
+            Err(AccountError::Wallet(WalletError::ForeignAddress(Address::Eve)))
+        );
+        assert_eq!(
+            wallet.create_account("payroll", [Address::Bob, Address::Charlie]),
+            Err(AccountError::AddressInAccount { address: Address::Bob, account: "operations".to_string() })
+        );
+    }
+
+    #[test]
+    fn accounts_are_listed_by_name() {
+        let (_, wallet, _) = two_accounts();
+        assert_eq!(wallet.accounts(), ["operations", "savings"]);
+        assert_eq!(wallet.account_of(&Address::Bob), Some("operations"));
+    }
+
+    #[test]
+    fn account_balances_count_the_coins_of_their_addresses() {
+        let (_, wallet, _) = two_accounts();
+        assert_eq!(wallet.account_balance("operations"), Ok(50));
+        assert_eq!(wallet.account_balance("savings"), Ok(100));
+        assert_eq!(wallet.account_balance("payroll"), Err(AccountError::UnknownAccount("payroll".to_string())));
+    }
+
+    #[test]
+    fn sends_spend_only_the_accounts_coins_and_keep_the_change_in_it() {
+        let (_, wallet, funding) = two_accounts();
+        assert_eq!(
+            wallet.send_from_account("operations", Address::Eve, 60, 0),
+            Err(AccountError::Wallet(WalletError::InsufficientFunds { needed: 60, available: 50 }))
+        );
+        let tx = wallet.send_from_account("operations", Address::Eve, 40, 1).unwrap();
+        let spent: HashSet<CoinId> = tx.iter_input_coin_ids().collect();
+        assert_eq!(spent, HashSet::from([funding.coin_id(1, 0), funding.coin_id(1, 1)]));
+        assert_eq!(tx.outputs[1], Coin { value: 9, owner: Address::Alice, asset_id: None });
+    }
+
+    #[test]
+    fn transfers_are_single_self_spends_between_the_accounts() {
+        let (mut node, mut wallet, funding) = two_accounts();
+        let transfer = transfer_and_sync(&mut node, &mut wallet);
+        assert_eq!(transfer.iter_input_coin_ids().collect::<Vec<_>>(), [funding.coin_id(1, 2)]);
+        assert_eq!(
+            transfer.outputs,
+            [
+                Coin { value: 70, owner: Address::Alice, asset_id: None },
+                Coin { value: 30, owner: Address::Charlie, asset_id: None },
+            ]
+        );
+        assert_eq!(wallet.account_balance("operations"), Ok(120));
+        assert_eq!(wallet.account_balance("savings"), Ok(30));
+    }
+
+    #[test]
+    fn account_history_marks_transfers_internal() {
+        let (mut node, mut wallet, funding) = two_accounts();
+        let transfer = transfer_and_sync(&mut node, &mut wallet);
+        let history = wallet.account_history("savings").unwrap();
+        assert_eq!(history.iter().map(|entry| entry.tx_id).collect::<Vec<_>>(), [funding.id(), transfer.id()]);
+        assert_eq!(history[1].direction, Direction::Internal);
+        assert_eq!(wallet.account_history("operations").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn accounts_survive_export() {
+        let (_, wallet, _) = two_accounts();
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.account_addresses("operations").unwrap(), &BTreeSet::from([Address::Alice, Address::Bob]));
+    }
+
+    #[test]
+    fn removed_accounts_leave_their_coins_in_the_wallet() {
+        let (_, mut wallet, _) = two_accounts();
+        assert_eq!(wallet.remove_account("savings"), Ok(BTreeSet::from([Address::Charlie])));
+        assert_eq!(wallet.account_of(&Address::Charlie), None);
+        assert_eq!(wallet.net_worth(), 150);
+    }
+}
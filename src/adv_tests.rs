@@ -2,7 +2,7 @@
 
 use super::*;
 
-/// Helper functions as in the tests.rs file 
+// Helper functions as in the tests.rs file
 
 fn wallet_with_alice() -> Wallet {
     Wallet::new(vec![Address::Alice].into_iter())
@@ -14,20 +14,24 @@ fn wallet_with_alice_and_bob() -> Wallet {
 
 fn marker_tx() -> Transaction {
     Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![Coin {
             value: 123,
             owner: Address::Custom(123),
+            asset_id: None,
         }],
     }
 }
 
 fn marker_tx_v(value: u64) -> Transaction {
     Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![Coin {
             value,
             owner: Address::Custom(value),
+            asset_id: None,
         }],
     }
 }
@@ -40,17 +44,21 @@ fn test_reorgs_with_utxos_in_chain_history() {
     let coin_1 = Coin {
         value: 50,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin_2 = Coin {
         value: 100,
         owner: Address::Alice,
+        asset_id: None,
     };
 
     let tx_1 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin_1.clone()],
     };
     let tx_2 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin_2.clone()],
     };
@@ -73,8 +81,10 @@ fn test_reorgs_with_utxos_in_chain_history() {
     let new_coin = Coin {
         value: 200,
         owner: Address::Alice,
+        asset_id: None,
     };
     let tx_new = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![new_coin.clone()],
     };
@@ -89,7 +99,6 @@ fn test_reorgs_with_utxos_in_chain_history() {
     assert_eq!(wallet.net_worth(), 250);
 }
 
-
 #[test]
 fn reports_correct_ancestors_even_after_reorg_in_the_middle() {
     let mut node = MockNode::new();
@@ -101,7 +110,7 @@ fn reports_correct_ancestors_even_after_reorg_in_the_middle() {
 
     // Now the old blcoks that will be discarted
     let old_b4_id = node.add_block_as_best(b3_id, vec![]);
-    let old_b5_id = node.add_block_as_best(old_b4_id, vec![marker_tx_v(0123)]);
+    let old_b5_id = node.add_block_as_best(old_b4_id, vec![marker_tx_v(123)]);
     let old_b6_id = node.add_block_as_best(old_b5_id, vec![marker_tx_v(456)]);
 
     // assert_eq!(node.best_block, old_b6_id);
@@ -139,8 +148,10 @@ fn reports_correct_ancestors_even_after_reorg_in_the_middle_with_atomic() {
     let coin_0 = Coin {
         value: 100,
         owner: Address::Alice,
+        asset_id: None,
     };
     let tx_mint = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![],
         outputs: vec![coin_0.clone()],
     };
@@ -151,16 +162,20 @@ fn reports_correct_ancestors_even_after_reorg_in_the_middle_with_atomic() {
     let coin_1 = Coin {
         value: 4,
         owner: Address::Bob,
+        asset_id: None,
     };
     let coin_2 = Coin {
         value: 6,
         owner: Address::Bob,
+        asset_id: None,
     };
     let coin_3 = Coin {
         value: 90,
         owner: Address::Alice,
+        asset_id: None,
     };
     let tx_alice_bob_0 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input {
             coin_id: coin_id_0,
             signature: Signature::Invalid,
@@ -183,20 +198,20 @@ fn reports_correct_ancestors_even_after_reorg_in_the_middle_with_atomic() {
     assert_eq!(wallet.net_worth(), 100);
 
     let mut expected_alice_hash_set = HashSet::new();
-    expected_alice_hash_set.insert((coin_id_3, 90 as u64));
+    expected_alice_hash_set.insert((coin_id_3, 90));
     assert_eq!(
         wallet.all_coins_of(Address::Alice).unwrap(),
         expected_alice_hash_set
     );
 
     let mut expected_bob_hash_set = HashSet::new();
-    expected_bob_hash_set.insert((coin_id_1, 4 as u64));
-    expected_bob_hash_set.insert((coin_id_2, 6 as u64));
+    expected_bob_hash_set.insert((coin_id_1, 4));
+    expected_bob_hash_set.insert((coin_id_2, 6));
     assert_eq!(wallet.all_coins_of(Address::Bob), Ok(expected_bob_hash_set));
 
     assert_eq!(
         wallet.coin_details(&coin_id_0),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_0))
     );
     assert_eq!(wallet.coin_details(&coin_id_1), Ok(coin_1));
     assert_eq!(wallet.coin_details(&coin_id_2), Ok(coin_2.clone()));
@@ -204,17 +219,20 @@ fn reports_correct_ancestors_even_after_reorg_in_the_middle_with_atomic() {
 
     // Now the old blcoks that will be discarted
     let old_b4_id = node.add_block_as_best(b3_id, vec![]); //bob 9, alice 91
-    let old_b5_id = node.add_block_as_best(old_b4_id, vec![marker_tx_v(0123)]);
+    let old_b5_id = node.add_block_as_best(old_b4_id, vec![marker_tx_v(123)]);
 
     let coin_4 = Coin {
         value: 1,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin_5 = Coin {
         value: 3,
         owner: Address::Bob,
+        asset_id: None,
     };
     let tx_alice_bob_1 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input {
             coin_id: coin_id_1,
             signature: Signature::Invalid,
@@ -228,12 +246,15 @@ fn reports_correct_ancestors_even_after_reorg_in_the_middle_with_atomic() {
     let coin_6 = Coin {
         value: 73,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin_7 = Coin {
         value: 20,
         owner: Address::Bob,
+        asset_id: None,
     };
     let tx_alice_bob_2 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![
             Input {
                 coin_id: coin_id_3,
@@ -267,36 +288,36 @@ fn reports_correct_ancestors_even_after_reorg_in_the_middle_with_atomic() {
     assert_eq!(wallet.net_worth(), 100);
 
     let mut expected_alice_hash_set = HashSet::new();
-    expected_alice_hash_set.insert((coin_id_6, 73 as u64));
-    expected_alice_hash_set.insert((coin_id_4, 1 as u64));
+    expected_alice_hash_set.insert((coin_id_6, 73));
+    expected_alice_hash_set.insert((coin_id_4, 1));
     assert_eq!(
         wallet.all_coins_of(Address::Alice).unwrap(),
         expected_alice_hash_set
     );
 
     let mut expected_bob_hash_set = HashSet::new();
-    expected_bob_hash_set.insert((coin_id_7, 20 as u64));
-    expected_bob_hash_set.insert((coin_id_2, 6 as u64));
-    // expected_bob_hash_set.insert((coin_id_5, 3 as u64));
+    expected_bob_hash_set.insert((coin_id_7, 20));
+    expected_bob_hash_set.insert((coin_id_2, 6));
+    // expected_bob_hash_set.insert((coin_id_5, 3));
     assert_eq!(wallet.all_coins_of(Address::Bob), Ok(expected_bob_hash_set));
 
     assert_eq!(
         wallet.coin_details(&coin_id_0),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_0))
     );
     assert_eq!(
         wallet.coin_details(&coin_id_1),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_1))
     );
     assert_eq!(wallet.coin_details(&coin_id_2), Ok(coin_2));
     assert_eq!(
         wallet.coin_details(&coin_id_3),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_3))
     );
     assert_eq!(wallet.coin_details(&coin_id_4), Ok(coin_4));
     assert_eq!(
         wallet.coin_details(&coin_id_5),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_5))
     );
     assert_eq!(wallet.coin_details(&coin_id_6), Ok(coin_6));
     assert_eq!(wallet.coin_details(&coin_id_7), Ok(coin_7));
@@ -304,13 +325,16 @@ fn reports_correct_ancestors_even_after_reorg_in_the_middle_with_atomic() {
     let coin_8 = Coin {
         value: 7,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin_9 = Coin {
         value: 3,
         owner: Address::Bob,
+        asset_id: None,
     };
 
     let tx_alice_bob_3 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![
             Input {
                 coin_id: coin_id_1,
@@ -342,50 +366,49 @@ fn reports_correct_ancestors_even_after_reorg_in_the_middle_with_atomic() {
     assert_eq!(wallet.best_hash(), b5_id);
 
     let mut expected_alice_hash_set = HashSet::new();
-    expected_alice_hash_set.insert((coin_id_3, 90 as u64));
-    expected_alice_hash_set.insert((coin_id_8, 7 as u64));
+    expected_alice_hash_set.insert((coin_id_3, 90));
+    expected_alice_hash_set.insert((coin_id_8, 7));
     assert_eq!(
         wallet.all_coins_of(Address::Alice).unwrap(),
         expected_alice_hash_set
     );
 
     let mut expected_bob_hash_set = HashSet::new();
-    expected_bob_hash_set.insert((coin_id_9, 3 as u64));
+    expected_bob_hash_set.insert((coin_id_9, 3));
 
     assert_eq!(
         wallet.coin_details(&coin_id_0),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_0))
     );
     assert_eq!(
         wallet.coin_details(&coin_id_1),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_1))
     );
     assert_eq!(
         wallet.coin_details(&coin_id_2),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_2))
     );
     assert_eq!(wallet.coin_details(&coin_id_3), Ok(coin_3.clone()));
     assert_eq!(
         wallet.coin_details(&coin_id_4),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_4))
     );
     assert_eq!(
         wallet.coin_details(&coin_id_5),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_5))
     );
     assert_eq!(
         wallet.coin_details(&coin_id_6),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_6))
     );
     assert_eq!(
         wallet.coin_details(&coin_id_7),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(coin_id_7))
     );
     assert_eq!(wallet.coin_details(&coin_id_8), Ok(coin_8));
     assert_eq!(wallet.coin_details(&coin_id_9), Ok(coin_9));
 }
 
-
 #[test]
 fn reorg_hard_test_hehe() {
     // Create node and wallet
@@ -395,29 +418,36 @@ fn reorg_hard_test_hehe() {
     let coin1 = Coin {
         value: 100,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin2 = Coin {
         value: 90,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin3 = Coin {
         value: 80,
         owner: Address::Bob,
+        asset_id: None,
     };
     let coin4 = Coin {
         value: 70,
         owner: Address::Bob,
+        asset_id: None,
     };
     let coin5 = Coin {
         value: 800,
         owner: Address::Alice,
+        asset_id: None,
     };
 
     let coin6 = Coin {
         value: 15,
         owner: Address::Alice,
+        asset_id: None,
     };
     let mint_tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![],
         outputs: vec![
             coin1.clone(),
@@ -436,6 +466,7 @@ fn reorg_hard_test_hehe() {
     let alice_15_bucks_coin = mint_tx.coin_id(1, 5);
     let block_1 = node.add_block(Block::genesis().id(), vec![mint_tx]);
     let tx1 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input {
             coin_id: alice_100_bucks_coin,
             signature: Signature::Invalid,
@@ -443,12 +474,14 @@ fn reorg_hard_test_hehe() {
         outputs: vec![Coin {
             value: 50,
             owner: Address::Bob,
+            asset_id: None,
         }],
     };
 
     let bob_coin_created_at_block_2 = tx1.coin_id(2, 0);
     let block2 = node.add_block(block_1, vec![tx1]);
     let tx2_1 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![
             Input {
                 coin_id: bob_80_bucks_coin,
@@ -462,10 +495,12 @@ fn reorg_hard_test_hehe() {
         outputs: vec![Coin {
             value: 880,
             owner: Address::Alice,
+            asset_id: None,
         }],
     };
     let alice_coin_created_and_destroyed_at_block_3 = tx2_1.coin_id(3, 0);
     let tx2_2 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input {
             coin_id: alice_coin_created_and_destroyed_at_block_3,
             signature: Signature::Invalid,
@@ -473,11 +508,13 @@ fn reorg_hard_test_hehe() {
         outputs: vec![Coin {
             value: 300,
             owner: Address::Bob,
+            asset_id: None,
         }],
     };
     let bob_coin_created_at_block_3 = tx2_2.coin_id(3, 0);
     let block3 = node.add_block(block2, vec![tx2_1, tx2_2]);
     let tx3 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input {
             coin_id: alice_15_bucks_coin,
             signature: Signature::Invalid,
@@ -485,6 +522,7 @@ fn reorg_hard_test_hehe() {
         outputs: vec![Coin {
             value: 10,
             owner: Address::Alice,
+            asset_id: None,
         }],
     };
     let alice_coin_created_at_block_4 = tx3.coin_id(4, 0);
@@ -523,6 +561,7 @@ fn reorg_hard_test_hehe() {
 
     // Let's reorg the last_two_blocks
     let tx2_1 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![
             Input {
                 coin_id: bob_80_bucks_coin,
@@ -536,12 +575,14 @@ fn reorg_hard_test_hehe() {
         outputs: vec![Coin {
             value: 880,
             owner: Address::Alice,
+            asset_id: None,
         }],
     };
 
     let alice_coin_created_at_block_3 = tx2_1.coin_id(3, 0);
     let block_3 = node.add_block(block2, vec![tx2_1]);
     let tx3 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input {
             coin_id: alice_90_bucks_coin,
             signature: Signature::Invalid,
@@ -549,6 +590,7 @@ fn reorg_hard_test_hehe() {
         outputs: vec![Coin {
             value: 30,
             owner: Address::Alice,
+            asset_id: None,
         }],
     };
     let alice_coin_created_at_block_4 = tx3.coin_id(4, 0);
@@ -592,27 +634,63 @@ fn total_assets_of_should_not_return_no_owned_address() {
 
     assert_eq!(
         wallet.total_assets_of(Address::Bob),
-        Err(WalletError::ForeignAddress)
+        Err(WalletError::ForeignAddress(Address::Bob))
     );
 
     assert_eq!(
         wallet.all_coins_of(Address::Bob),
-        Err(WalletError::ForeignAddress)
+        Err(WalletError::ForeignAddress(Address::Bob))
     );
 
     // just get a coin id
     let dummy_tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![],
         outputs: vec![Coin {
             value: 100,
             owner: Address::Alice,
+            asset_id: None,
         }],
     };
     let dummy_coin = dummy_tx.coin_id(1, 0);
 
     assert_eq!(
         wallet.coin_details(&dummy_coin),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(dummy_coin))
+    );
+}
+
+#[test]
+fn empty_wallet_refuses_to_author_transactions() {
+    let (node, _) = make_one_block_blockchain();
+    let mut wallet = Wallet::new(vec![].into_iter());
+    wallet.sync(&node);
+    assert!(wallet.is_empty());
+    assert!(!wallet_with_alice().is_empty());
+
+    assert_eq!(
+        wallet.create_automatic_transaction(Address::Bob, 10, 0),
+        Err(WalletError::NoOwnedAddresses)
+    );
+    assert_eq!(
+        wallet.create_manual_transaction(vec![marker_tx().coin_id(1, 0)], vec![]),
+        Err(WalletError::NoOwnedAddresses)
+    );
+    assert_eq!(
+        wallet.plan_automatic_transaction(Address::Bob, 10, 0),
+        Err(WalletError::NoOwnedAddresses)
+    );
+}
+
+#[test]
+fn overflowing_amounts_are_insufficient_funds() {
+    let (_, wallet) = make_one_block_blockchain();
+    assert_eq!(
+        wallet.create_automatic_transaction(Address::Charlie, u64::MAX, 1),
+        Err(WalletError::InsufficientFunds {
+            needed: u64::MAX,
+            available: 235,
+        })
     );
 }
 
@@ -624,9 +702,11 @@ fn spend_utxo_in_same_block() {
     let coin1 = Coin {
         value: 100,
         owner: Address::Alice,
+        asset_id: None,
     };
 
     let mint_tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![],
         outputs: vec![coin1.clone()],
     };
@@ -634,6 +714,7 @@ fn spend_utxo_in_same_block() {
     let alice_100_bucks_coin = mint_tx.coin_id(1, 0);
 
     let tx2 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input {
             coin_id: alice_100_bucks_coin,
             signature: Signature::Valid(Address::Alice),
@@ -641,12 +722,14 @@ fn spend_utxo_in_same_block() {
         outputs: vec![Coin {
             value: 100,
             owner: Address::Bob,
+            asset_id: None,
         }],
     };
 
     let bob_100_bucks_coin = tx2.coin_id(1, 0);
 
     let tx3 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input {
             coin_id: bob_100_bucks_coin,
             // invalid signature, but wallet shouldn't care
@@ -655,6 +738,7 @@ fn spend_utxo_in_same_block() {
         outputs: vec![Coin {
             value: 100,
             owner: Address::Custom(100),
+            asset_id: None,
         }],
     };
 
@@ -691,14 +775,17 @@ fn perf_sync_100_blocks() {
     let mut block75 = last_block;
     for i in 1..=100 {
         let tx1 = Transaction {
+            version: TRANSACTION_VERSION,
             inputs: vec![],
             outputs: vec![Coin {
                 value: 10,
                 owner: Address::Alice,
+                asset_id: None,
             }],
         };
         let alice_coin = tx1.coin_id(i, 0);
         let tx2 = Transaction {
+            version: TRANSACTION_VERSION,
             inputs: vec![Input {
                 coin_id: alice_coin,
                 signature: Signature::Valid(Address::Alice),
@@ -707,10 +794,12 @@ fn perf_sync_100_blocks() {
                 Coin {
                     value: 2,
                     owner: Address::Bob,
+                    asset_id: None,
                 },
                 Coin {
                     value: 3,
                     owner: Address::Alice,
+                    asset_id: None,
                 },
             ],
         };
@@ -754,14 +843,17 @@ fn pref_sync_1000_blocks() {
     let mut block850 = last_block;
     for i in 1..=1000 {
         let tx1 = Transaction {
+            version: TRANSACTION_VERSION,
             inputs: vec![],
             outputs: vec![Coin {
                 value: 10,
                 owner: Address::Alice,
+                asset_id: None,
             }],
         };
         let alice_coin = tx1.coin_id(i, 0);
         let tx2 = Transaction {
+            version: TRANSACTION_VERSION,
             inputs: vec![Input {
                 coin_id: alice_coin,
                 signature: Signature::Valid(Address::Alice),
@@ -770,10 +862,12 @@ fn pref_sync_1000_blocks() {
                 Coin {
                     value: 2,
                     owner: Address::Bob,
+                    asset_id: None,
                 },
                 Coin {
                     value: 3,
                     owner: Address::Alice,
+                    asset_id: None,
                 },
             ],
         };
@@ -808,18 +902,23 @@ fn make_one_block_blockchain() -> (MockNode, Wallet) {
     let coin_alice_1 = Coin {
         value: 100,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin_alice_2 = Coin {
         value: 15,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin_bob_1 = Coin {
         value: 120,
         owner: Address::Bob,
+        asset_id: None,
     };
 
+    // a dummy input keeps this from being a coinbase, so the coins are spendable right away
     let tx_mint = Transaction {
-        inputs: vec![],
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input::dummy()],
         outputs: vec![
             coin_alice_1.clone(),
             coin_alice_2.clone(),
@@ -836,8 +935,6 @@ fn make_one_block_blockchain() -> (MockNode, Wallet) {
     (node, wallet)
 }
 
-
-
 #[test]
 fn transaction_simple() {
     let (_, wallet) = make_one_block_blockchain();
@@ -852,7 +949,15 @@ fn transaction_automatic_insufficient_funds() {
 
     // now check a failing transaction due to insufficient funds
     let result = wallet.create_automatic_transaction(Address::Charlie, wallet.net_worth() - 3, 4);
-    assert_eq!(result, Err(WalletError::InsufficientFunds));
+    assert_eq!(
+        result,
+        Err(WalletError::InsufficientFunds {
+            needed: 236,
+            available: 235,
+        })
+    );
+    let error: Box<dyn std::error::Error> = result.unwrap_err().into();
+    assert_eq!(error.to_string(), "insufficient funds: 236 bones needed but only 235 available");
 }
 
 #[test]
@@ -882,10 +987,6 @@ fn process_new_block() {
     assert_eq!(wallet.net_worth(), (100 + 15 + 120 - 26 - 2));
 }
 
-
-
-
-
 #[test]
 fn transaction_with_zero_value_fails() {
     let (_, wallet) = make_one_block_blockchain();
@@ -902,6 +1003,7 @@ fn transaction_with_zero_value_fails() {
         vec![Coin {
             value: 0,
             owner: Address::Eve,
+            asset_id: None,
         }],
     );
     assert_eq!(result, Err(WalletError::ZeroCoinValue));
@@ -911,7 +1013,6 @@ fn transaction_with_zero_value_fails() {
     assert_eq!(result, Err(WalletError::ZeroCoinValue));
 }
 
-
 #[test]
 fn sneak_in_no_inputs() {
     let (_, wallet) = make_one_block_blockchain();
@@ -921,6 +1022,7 @@ fn sneak_in_no_inputs() {
         vec![Coin {
             value: 10,
             owner: Address::Charlie, // output to Bob
+            asset_id: None,
         }],
     );
     assert_eq!(result, Err(WalletError::ZeroInputs));
@@ -936,7 +1038,6 @@ fn transaction_with_no_change_tx() {
     assert!(result.unwrap().outputs.len() == 1);
 }
 
-
 // Create manual transaction
 // ... with owner address to not be in the wallet
 #[test]
@@ -945,9 +1046,11 @@ fn check_manual_transaction_with_wrong_input_addresses() {
     let coin = Coin {
         value: COIN_VALUE,
         owner: Address::Bob,
+        asset_id: None,
     };
 
     let tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin.clone()],
     };
@@ -960,11 +1063,10 @@ fn check_manual_transaction_with_wrong_input_addresses() {
 
     assert_eq!(
         wallet.create_manual_transaction(vec![tx.coin_id(1, 0)], vec![]),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(tx.coin_id(1, 0)))
     );
 }
 
-
 // Reorg performance tests to make sure they aren't just syncing from genesis each time.
 #[test]
 fn reorg_performance() {
@@ -1030,16 +1132,20 @@ fn dont_save_coins_not_owned_by_our_wallet_addresses() {
     let coin1 = Coin {
         value: COIN_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin2 = Coin {
         value: COIN_VALUE,
         owner: Address::Bob,
+        asset_id: None,
     };
     let coin3 = Coin {
         value: COIN_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     let tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin1.clone(), coin2.clone()],
     };
@@ -1049,6 +1155,7 @@ fn dont_save_coins_not_owned_by_our_wallet_addresses() {
         signature: Signature::Invalid,
     };
     let tx2 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![input],
         outputs: vec![coin3],
     };
@@ -1069,3 +1176,93 @@ fn dont_save_coins_not_owned_by_our_wallet_addresses() {
     assert!(wallet.total_assets_of(Address::Alice) == Ok(200));
     assert!(wallet.net_worth() == 200);
 }
+
+#[test]
+fn split_parts_must_add_up_to_at_most_the_coin() {
+    let (_, wallet) = make_one_block_blockchain();
+    let coin_id = *wallet.coins.iter().find(|(_, coin)| coin.value == 100).unwrap().0;
+    assert_eq!(
+        wallet.create_split_transaction(coin_id, vec![50, 51]),
+        Err(WalletError::InsufficientFunds {
+            needed: 101,
+            available: 100,
+        })
+    );
+}
+
+#[test]
+fn split_parts_must_not_be_empty() {
+    let (_, wallet) = make_one_block_blockchain();
+    let coin_id = *wallet.coins.iter().find(|(_, coin)| coin.value == 100).unwrap().0;
+    assert_eq!(wallet.create_split_transaction(coin_id, vec![50, 0]), Err(WalletError::ZeroCoinValue));
+    assert_eq!(wallet.create_split_transaction(coin_id, vec![]), Err(WalletError::ZeroCoinValue));
+}
+
+#[test]
+fn only_coins_of_the_wallet_are_split() {
+    let (_, wallet) = make_one_block_blockchain();
+    assert_eq!(
+        wallet.create_split_transaction(marker_tx().coin_id(1, 0), vec![1]),
+        Err(WalletError::UnknownCoin(marker_tx().coin_id(1, 0)))
+    );
+}
+
+#[test]
+fn split_coins_stay_with_the_owner_and_the_rest_is_the_tip() {
+    let (mut node, mut wallet) = make_one_block_blockchain();
+    let coin_id = *wallet.coins.iter().find(|(_, coin)| coin.value == 100).unwrap().0;
+
+    let split = wallet.create_split_transaction(coin_id, vec![40, 30, 20]).unwrap();
+    assert!(split.outputs.iter().all(|coin| coin.owner == Address::Alice));
+    let worth = wallet.net_worth();
+    node.add_block_as_best(node.best_block_at_height(1).unwrap(), vec![split]);
+    wallet.sync(&node);
+    assert_eq!(wallet.net_worth(), worth - 10);
+    let alice_coins: Vec<u64> = wallet.all_coins_of(Address::Alice).unwrap().into_iter().map(|(_, v)| v).collect();
+    assert_eq!(alice_coins.len(), 4);
+    assert_eq!(alice_coins.iter().sum::<u64>(), 105);
+}
+
+#[test]
+fn wallet_follows_invalidated_and_reconsidered_blocks() {
+    let mut node = MockNode::new();
+    let b1_id = node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+    let b2_id = node.add_block_as_best(b1_id, vec![Transaction::coinbase(Address::Alice, 20)]);
+    let mut wallet = wallet_with_alice();
+    wallet.sync(&node);
+    assert_eq!(wallet.net_worth(), BLOCK_REWARD + 20);
+
+    node.invalidate_block(b2_id);
+    wallet.sync(&node);
+    assert_eq!((wallet.best_hash(), wallet.net_worth()), (b1_id, BLOCK_REWARD));
+
+    node.reconsider_block(b2_id);
+    wallet.sync(&node);
+    assert_eq!((wallet.best_hash(), wallet.net_worth()), (b2_id, BLOCK_REWARD + 20));
+}
+
+#[test]
+fn syncing_a_long_chain_with_reorgs_stays_within_call_budget() {
+    let addresses = [Address::Alice, Address::Bob, Address::Charlie, Address::Eve];
+    let mut node = MockNode::new();
+    node.generate_chain(11, 1_000, 3, &addresses);
+    node.set_latency(std::time::Duration::from_millis(20), false);
+    let mut wallet = wallet_with_alice_and_bob();
+
+    // one id lookup and one body fetch per block, plus the final lookup that finds no block
+    node.set_call_budget(2 * 1_000 + 5, OverBudget::Panic);
+    wallet.sync(&node);
+    assert_eq!(wallet.best_height(), 1_000);
+
+    for reorg in 0..3 {
+        // fork 5 blocks below the tip and outgrow the old branch by one block
+        let fork_point = node.best_block_at_height(wallet.best_height() - 5).unwrap();
+        node.set_best(fork_point);
+        node.generate_chain(100 + reorg, 6, 3, &addresses);
+        node.set_call_budget(3 * 6 + 5, OverBudget::Panic);
+        wallet.sync(&node);
+        assert_eq!(wallet.best_hash(), node.best_block_at_height(wallet.best_height()).unwrap());
+    }
+    assert_eq!(wallet.best_height(), 1_003);
+    assert!(node.elapsed() < std::time::Duration::from_secs(45));
+}
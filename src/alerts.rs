@@ -0,0 +1,240 @@
+//! Alert rules checked after every sync, for monitoring a wallet without writing a plugin.
+//!
+//! Each rule that a sync trips raises a `WalletEvent::Alert`, delivered like every other event.
+//! Coins a reorg took away and the new branch gave back are not alerted about again, and a low
+//! balance is alerted about when it falls below the threshold, not again at every sync while it
+//! stays there. The rules are part of the exported state.
+
+use std::collections::BTreeSet;
+
+use bonecoin_core::*;
+
+use crate::{SyncReport, Wallet, WalletEvent};
+
+/// A condition worth alerting about.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum AlertRule {
+    /// The wallet's bones fell below this many.
+    BalanceBelow(u64),
+    /// The wallet received a single coin of more than this many bones.
+    IncomingAbove(u64),
+    /// A coin owned by this address was spent.
+    SpendFrom(Address),
+    /// A reorg rolled the wallet back by more than this many blocks.
+    ReorgDeeperThan(u64),
+}
+
+/// What tripped an alert rule.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Alert {
+    /// The wallet's `balance` fell below `threshold` bones.
+    LowBalance { balance: u64, threshold: u64 },
+    /// The block at `height` gave the wallet a coin of `value` bones.
+    LargeIncoming { coin_id: CoinId, value: u64, height: u64 },
+    /// The block at `height` spent a coin owned by the flagged `address`.
+    FlaggedSpend { address: Address, coin_id: CoinId, height: u64 },
+    /// A reorg rolled the wallet back by `depth` blocks from `from_height`.
+    DeepReorg { depth: u64, from_height: u64 },
+}
+
+/// The alert rules and what the last check saw.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AlertRules {
+    pub(crate) rules: Vec<AlertRule>,
+    /// The balance at the last check, if there was one.
+    balance: Option<u64>,
+}
+
+impl Wallet {
+    /// Check the rule after every sync from now on. A rule that is already set is not added twice.
+    pub fn add_alert_rule(&mut self, rule: AlertRule) {
+        if !self.alerts.rules.contains(&rule) {
+            self.alerts.rules.push(rule);
+        }
+    }
+
+    /// Stop checking the rule. Returns whether it was set.
+    pub fn remove_alert_rule(&mut self, rule: &AlertRule) -> bool {
+        let before = self.alerts.rules.len();
+        self.alerts.rules.retain(|set| set != rule);
+        self.alerts.rules.len() < before
+    }
+
+    /// The alert rules, in the order they were added.
+    pub fn alert_rules(&self) -> &[AlertRule] {
+        &self.alerts.rules
+    }
+
+    /// Raise an alert for every rule the sync behind `report` tripped. `reorgs_before` is the
+    /// length of the reorg log when the sync started.
+    pub(crate) fn raise_alerts(&mut self, report: &SyncReport, reorgs_before: usize) {
+        if self.alerts.rules.is_empty() {
+            return;
+        }
+        let reorgs = &self.reorgs.records[reorgs_before..];
+        let readded: BTreeSet<CoinId> = reorgs.iter().flat_map(|record| &record.coins_readded).copied().collect();
+        let balance = self.net_worth();
+        let mut alerts = Vec::new();
+        for rule in &self.alerts.rules {
+            match rule {
+                AlertRule::BalanceBelow(threshold) => {
+                    if balance < *threshold && self.alerts.balance.is_none_or(|before| before >= *threshold) {
+                        alerts.push(Alert::LowBalance { balance, threshold: *threshold });
+                    }
+                }
+                AlertRule::IncomingAbove(limit) => {
+                    for block in &report.applied {
+                        alerts.extend(
+                            block
+                                .credited
+                                .iter()
+                                .filter(|(coin_id, coin)| coin.is_native() && coin.value > *limit && !readded.contains(coin_id))
+                                .map(|(coin_id, coin)| Alert::LargeIncoming { coin_id: *coin_id, value: coin.value, height: block.height }),
+                        );
+                    }
+                }
+                AlertRule::SpendFrom(address) => {
+                    for block in &report.applied {
+                        alerts.extend(block.debited.iter().filter(|(_, coin)| coin.owner == *address).map(|(coin_id, _)| {
+                            Alert::FlaggedSpend { address: address.clone(), coin_id: *coin_id, height: block.height }
+                        }));
+                    }
+                }
+                AlertRule::ReorgDeeperThan(max_depth) => {
+                    alerts.extend(
+                        reorgs
+                            .iter()
+                            .filter(|record| record.depth() > *max_depth)
+                            .map(|record| Alert::DeepReorg { depth: record.depth(), from_height: record.from_height }),
+                    );
+                }
+            }
+        }
+        self.alerts.balance = Some(balance);
+        for alert in alerts {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(alert = ?alert, "alert raised");
+            self.emit(WalletEvent::Alert(alert));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    fn alerts(wallet: &mut Wallet) -> Vec<Alert> {
+        wallet
+            .take_events()
+            .into_iter()
+            .filter_map(|event| match event {
+                WalletEvent::Alert(alert) => Some(alert),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// A wallet of Alice and Bob alerting about balances below 40, coins above 30, spends from
+    /// Bob and reorgs deeper than a block, synced to a first block paying 50 bones to Alice and
+    /// 10 to Bob. Returns the node, the wallet and the funding transaction.
+    fn alerting() -> (MockNode, Wallet, Transaction) {
+        let mut wallet = Wallet::builder().addresses([Address::Alice, Address::Bob]).coinbase_maturity(0).build().unwrap();
+        wallet.add_alert_rule(AlertRule::BalanceBelow(40));
+        wallet.add_alert_rule(AlertRule::IncomingAbove(30));
+        wallet.add_alert_rule(AlertRule::SpendFrom(Address::Bob));
+        wallet.add_alert_rule(AlertRule::ReorgDeeperThan(1));
+        wallet.add_alert_rule(AlertRule::ReorgDeeperThan(1));
+
+        let mut node = MockNode::new();
+        let funding = mint([(Address::Alice, 50), (Address::Bob, 10)]);
+        node.add_block_as_best(Block::genesis().id(), vec![funding.clone()]);
+        wallet.sync(&node);
+        (node, wallet, funding)
+    }
+
+    /// Sync `wallet` to a second block spending both funding coins, 40 bones to Eve and 20 back
+    /// to Alice, and an empty third one. Returns the id of the third block.
+    fn spend_both(node: &mut MockNode, wallet: &mut Wallet, funding: &Transaction) -> BlockId {
+        let spend = Transaction {
+            inputs: vec![
+                Input { coin_id: funding.coin_id(1, 0), signature: Signature::Valid(Address::Alice) },
+                Input { coin_id: funding.coin_id(1, 1), signature: Signature::Valid(Address::Bob) },
+            ],
+            ..mint([(Address::Eve, 40), (Address::Alice, 20)])
+        };
+        let b2 = node.add_block_as_best(wallet.best_hash(), vec![spend]);
+        let b3 = node.add_block_as_best(b2, vec![]);
+        wallet.sync(node);
+        b3
+    }
+
+    /// Switch to a three block branch from the first block rewarding Charlie, and sync `wallet` to it.
+    fn reorg_two_blocks(node: &mut MockNode, wallet: &mut Wallet) {
+        let b1 = node.best_block_at_height(1).unwrap();
+        let c2 = node.add_block(b1, vec![Transaction::coinbase(Address::Charlie, 50)]);
+        let c3 = node.add_block(c2, vec![]);
+        node.add_block_as_best(c3, vec![]);
+        wallet.sync(node);
+    }
+
+    #[test]
+    fn rules_are_added_once() {
+        let (_, wallet, _) = alerting();
+        assert_eq!(wallet.alert_rules().len(), 4);
+    }
+
+    #[test]
+    fn large_incoming_coins_raise_alerts() {
+        let (_, mut wallet, funding) = alerting();
+        assert_eq!(alerts(&mut wallet), [Alert::LargeIncoming { coin_id: funding.coin_id(1, 0), value: 50, height: 1 }]);
+    }
+
+    #[test]
+    fn low_balances_and_spends_from_flagged_addresses_raise_alerts() {
+        let (mut node, mut wallet, funding) = alerting();
+        wallet.take_events();
+        spend_both(&mut node, &mut wallet, &funding);
+        assert_eq!(
+            alerts(&mut wallet),
+            [
+                Alert::LowBalance { balance: 20, threshold: 40 },
+                Alert::FlaggedSpend { address: Address::Bob, coin_id: funding.coin_id(1, 1), height: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn deep_reorgs_raise_alerts_but_balances_staying_low_do_not() {
+        let (mut node, mut wallet, funding) = alerting();
+        spend_both(&mut node, &mut wallet, &funding);
+        wallet.take_events();
+        reorg_two_blocks(&mut node, &mut wallet);
+        assert_eq!(alerts(&mut wallet), [Alert::DeepReorg { depth: 2, from_height: 3 }]);
+        assert_eq!(wallet.net_worth(), 60);
+    }
+
+    #[test]
+    fn the_rules_survive_export() {
+        let (_, wallet, _) = alerting();
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.alert_rules(), wallet.alert_rules());
+    }
+
+    #[test]
+    fn removed_rules_stop_raising_alerts() {
+        let (mut node, mut wallet, funding) = alerting();
+        let b3 = spend_both(&mut node, &mut wallet, &funding);
+        reorg_two_blocks(&mut node, &mut wallet);
+        let mut restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert!(restored.remove_alert_rule(&AlertRule::SpendFrom(Address::Bob)));
+        assert!(!restored.remove_alert_rule(&AlertRule::SpendFrom(Address::Bob)));
+
+        // without undo records, the restored wallet rescans from genesis
+        node.set_best(b3);
+        restored.sync(&node);
+        assert_eq!(
+            alerts(&mut restored),
+            [Alert::LowBalance { balance: 20, threshold: 40 }, Alert::DeepReorg { depth: 4, from_height: 4 }]
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Balances, coin selection, and history for issued assets (colored coins).
+//!
+//! Native bones keep going through the regular `WalletApi` methods, which ignore asset coins.
+//! Asset transfers select coins of the requested asset for the payment and native coins for the
+//! tip, so the two are never mixed up.
+
+use std::collections::{HashMap, HashSet};
+
+use bonecoin_core::*;
+
+use crate::{HistoryEntry, Wallet};
+
+impl Wallet {
+    /// Calculate the total value of the given asset (`None` for bones) owned by this address.
+    pub fn total_assets_of_asset(&self, address: Address, asset_id: Option<AssetId>) -> WalletResult<u64> {
+        if !self.addresses.contains(&address) {
+            return Err(WalletError::ForeignAddress(address));
+        }
+        Ok(self
+            .coins
+            .values()
+            .filter(|coin| coin.owner == address && coin.asset_id == asset_id)
+            .map(|coin| coin.value)
+            .sum())
+    }
+
+    /// The coins of the given asset owned by this address, with their amounts.
+    /// `all_coins_of` lists the native coins.
+    pub fn asset_coins_of(&self, address: Address, asset_id: AssetId) -> WalletResult<HashSet<(CoinId, u64)>> {
+        if !self.addresses.contains(&address) {
+            return Err(WalletError::ForeignAddress(address));
+        }
+        Ok(self
+            .coins
+            .iter()
+            .filter(|(_, coin)| coin.owner == address && coin.asset_id == Some(asset_id))
+            .map(|(coin_id, coin)| (*coin_id, coin.value))
+            .collect())
+    }
+
+    /// The wallet's total holdings of every issued asset it owns coins of.
+    pub fn asset_balances(&self) -> HashMap<AssetId, u64> {
+        let mut balances = HashMap::new();
+        for coin in self.coins.values() {
+            if let Some(asset_id) = coin.asset_id {
+                *balances.entry(asset_id).or_default() += coin.value;
+            }
+        }
+        balances
+    }
+
+    /// The history entries that moved the given asset into or out of the wallet, oldest first.
+    pub fn asset_history(&self, asset_id: AssetId) -> Vec<&HistoryEntry> {
+        self.history.entries().iter().filter(|entry| entry.touches_asset(asset_id)).collect()
+    }
+
+    /// Construct a transaction issuing `amount` units of a new asset to the wallet's first address.
+    /// The id of the new asset is derived from the first coin the transaction spends.
+    pub fn issue_asset(&self, amount: u64, burn_aka_tip: u64) -> WalletResult<(AssetId, Transaction)> {
+        if amount == 0 {
+            return Err(WalletError::ZeroCoinValue);
+        }
+        let owner = self.primary_address()?;
+
+        // an issuance needs an input to derive the asset id from, even without a tip
+        let (mut inputs, bones) = self.select_asset_coins(None, burn_aka_tip.max(1))?;
+        let mut outputs = vec![Coin {
+            value: amount,
+            owner,
+            asset_id: None,
+        }];
+        outputs.extend(self.change_output(bones - burn_aka_tip, None)?);
+        inputs.sort_by_key(|input| input.coin_id);
+        let asset_id = AssetId::issued_by(&inputs[0].coin_id);
+        outputs[0].asset_id = Some(asset_id);
+
+        let mut transaction = Transaction { version: TRANSACTION_VERSION, inputs, outputs };
+        self.sign_inputs(&mut transaction);
+        self.check_policy(&transaction)?;
+        Ok((asset_id, transaction))
+    }
+
+    /// Construct a transaction paying `amount` units of an issued asset to `recipient`.
+    /// The tip is paid in bones; change of both the asset and the bones goes back to the wallet.
+    pub fn create_asset_transaction(
+        &self,
+        asset_id: AssetId,
+        recipient: Address,
+        amount: u64,
+        burn_aka_tip: u64,
+    ) -> WalletResult<Transaction> {
+        if amount == 0 {
+            return Err(WalletError::ZeroCoinValue);
+        }
+        let (mut inputs, asset_selected) = self.select_asset_coins(Some(asset_id), amount)?;
+        let mut outputs = vec![Coin {
+            value: amount,
+            owner: recipient,
+            asset_id: Some(asset_id),
+        }];
+        outputs.extend(self.change_output(asset_selected - amount, Some(asset_id))?);
+        if burn_aka_tip > 0 {
+            let (tip_inputs, bones) = self.select_asset_coins(None, burn_aka_tip)?;
+            inputs.extend(tip_inputs);
+            outputs.extend(self.change_output(bones - burn_aka_tip, None)?);
+        }
+
+        let mut transaction = Transaction { version: TRANSACTION_VERSION, inputs, outputs };
+        self.sign_inputs(&mut transaction);
+        self.check_policy(&transaction)?;
+        Ok(transaction)
+    }
+
+    /// Select spendable coins of a single asset (`None` for bones) worth at least `needed`.
+    pub(crate) fn select_asset_coins(&self, asset_id: Option<AssetId>, needed: u64) -> WalletResult<(Vec<Input>, u64)> {
+        let mut inputs = Vec::new();
+        let mut selected = 0;
+        let mut coins: Vec<(&CoinId, &Coin)> = self.coins.iter().collect();
+        coins.sort_by_key(|(coin_id, _)| **coin_id);
+        for (&coin_id, coin) in coins {
+            if selected >= needed {
+                break;
+            }
+            if coin.asset_id != asset_id || !self.is_auto_spendable(&coin_id, coin) {
+                continue;
+            }
+            inputs.push(Input {
+                coin_id,
+                signature: Signature::Invalid,
+            });
+            selected += coin.value;
+        }
+        if selected < needed {
+            let available = self
+                .coins
+                .iter()
+                .filter(|(coin_id, coin)| coin.asset_id == asset_id && self.is_auto_spendable(coin_id, coin))
+                .map(|(_, coin)| coin.value)
+                .sum();
+            return Err(WalletError::InsufficientFunds { needed, available });
+        }
+        Ok((inputs, selected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// The one-block wallet after issuing 1,000 units of an asset with a tip of 2 bones in block 2.
+    /// Returns the node, the wallet, the asset, and the native worth before the issuance.
+    fn wallet_with_an_issued_asset() -> (MockNode, Wallet, AssetId, u64) {
+        let (mut node, mut wallet) = make_one_block_blockchain();
+        let bones = wallet.net_worth();
+        let (asset_id, issuance) = wallet.issue_asset(1_000, 2).unwrap();
+        node.add_block_as_best(node.best_block_at_height(1).unwrap(), vec![issuance]);
+        wallet.sync(&node);
+        (node, wallet, asset_id, bones)
+    }
+
+    fn issuer_of(wallet: &Wallet, asset_id: AssetId) -> Address {
+        wallet.coins.values().find(|coin| coin.asset_id == Some(asset_id)).unwrap().owner.clone()
+    }
+
+    #[test]
+    fn issued_assets_are_counted_apart_from_bones() {
+        let (_, wallet, asset_id, bones) = wallet_with_an_issued_asset();
+        let issuer = issuer_of(&wallet, asset_id);
+
+        assert_eq!(wallet.net_worth(), bones - 2);
+        assert_eq!(wallet.asset_balances(), HashMap::from([(asset_id, 1_000)]));
+        assert_eq!(wallet.total_assets_of_asset(issuer.clone(), Some(asset_id)), Ok(1_000));
+        assert_eq!(wallet.total_assets_of_asset(issuer.clone(), None), wallet.total_assets_of(issuer));
+    }
+
+    #[test]
+    fn asset_coins_are_listed_with_the_asset_and_never_with_the_bones() {
+        let (_, wallet, asset_id, _) = wallet_with_an_issued_asset();
+        let issuer = issuer_of(&wallet, asset_id);
+
+        let asset_coins = wallet.asset_coins_of(issuer.clone(), asset_id).unwrap();
+        assert_eq!(asset_coins.iter().map(|(_, value)| value).sum::<u64>(), 1_000);
+        let native_coins = wallet.all_coins_of(issuer.clone()).unwrap();
+        assert!(native_coins.is_disjoint(&asset_coins));
+        assert_eq!(native_coins.iter().map(|(_, value)| value).sum::<u64>(), wallet.total_assets_of(issuer.clone()).unwrap());
+        assert_eq!(wallet.snapshot().all_coins_of(issuer), Ok(native_coins));
+    }
+
+    #[test]
+    fn automatic_bone_payments_never_spend_asset_coins() {
+        let (_, wallet, _, bones) = wallet_with_an_issued_asset();
+        let payment = wallet.create_automatic_transaction(Address::Charlie, bones - 2, 0).unwrap();
+        assert!(payment.inputs.iter().all(|input| wallet.coins[&input.coin_id].is_native()));
+    }
+
+    #[test]
+    fn asset_transfers_conserve_the_asset_and_pay_the_tip_in_bones() {
+        let (mut node, mut wallet, asset_id, bones) = wallet_with_an_issued_asset();
+        let transfer = wallet.create_asset_transaction(asset_id, Address::Charlie, 400, 1).unwrap();
+        assert!(transfer.conserves_assets(|coin_id| wallet.coins.get(coin_id).cloned()));
+        node.add_block_as_best(node.best_block_at_height(2).unwrap(), vec![transfer]);
+        wallet.sync(&node);
+
+        assert_eq!(wallet.asset_balances(), HashMap::from([(asset_id, 600)]));
+        assert_eq!(wallet.net_worth(), bones - 3);
+        let asset_history = wallet.asset_history(asset_id);
+        assert_eq!(asset_history.len(), 2);
+        assert_eq!(asset_history[1].asset_value_spent(Some(asset_id)), 1_000);
+        assert_eq!(asset_history[1].asset_value_received(Some(asset_id)), 600);
+        assert_eq!(asset_history[1].value_spent() - asset_history[1].value_received(), 1);
+    }
+
+    #[test]
+    fn manual_transactions_may_not_create_asset_units() {
+        let (_, wallet, asset_id, _) = wallet_with_an_issued_asset();
+        let asset_coin = *wallet.coins.iter().find(|(_, coin)| !coin.is_native()).unwrap().0;
+        let inflated = wallet.create_manual_transaction(
+            vec![asset_coin],
+            vec![Coin {
+                value: 1_001,
+                owner: Address::Bob,
+                asset_id: Some(asset_id),
+            }],
+        );
+        assert_eq!(inflated, Err(WalletError::AssetNotConserved));
+    }
+}
@@ -0,0 +1,274 @@
+//! Checking the wallet's coins against the node's chain, and repairing what disagrees.
+//!
+//! `audit_against_node` replays the node's best chain up to the wallet's best block and compares the
+//! coins of the wallet's addresses left unspent with the coins the wallet holds, without changing the
+//! wallet. A coin the wallet holds may have been spent on the chain, never created by it, or created
+//! with a different value or owner; a coin the chain left to the wallet may be missing from it.
+//!
+//! `repair` reconciles the wallet with the chain by rewinding only to the block below the lowest
+//! disagreement and syncing forward from there, so blocks below it are not fetched again. Coins the
+//! chain never created and nothing in the history explains are dropped directly.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use bonecoin_core::*;
+
+use crate::{SyncError, Wallet};
+
+/// Errors that can occur while auditing, repairing, or rescanning the wallet.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum AuditError {
+    /// The wallet's best block at this height is not on the node's best chain. Sync first.
+    NotOnNodeChain { height: u64 },
+    /// The node could not provide the block at this height.
+    BlockUnavailable { height: u64 },
+    /// Syncing the rewound wallet failed.
+    Sync(SyncError),
+}
+
+impl From<SyncError> for AuditError {
+    fn from(e: SyncError) -> Self {
+        AuditError::Sync(e)
+    }
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::NotOnNodeChain { height } => write!(f, "the wallet's block at height {height} is not on the node's best chain"),
+            AuditError::BlockUnavailable { height } => write!(f, "the node could not provide the block at height {height}"),
+            AuditError::Sync(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+/// Where the wallet's coins disagree with the node's chain, found by `audit_against_node`.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct AuditReport {
+    /// The best block the chain was audited up to, the wallet's.
+    pub height: u64,
+    /// Coins the wallet holds that a block of the chain spent, with the height of that block.
+    pub spent: BTreeMap<CoinId, u64>,
+    /// Coins the wallet holds that the chain never created.
+    pub phantom: BTreeSet<CoinId>,
+    /// Coins the wallet holds that the chain created differently, with the chain's coin and the
+    /// height that created it.
+    pub altered: BTreeMap<CoinId, (Coin, u64)>,
+    /// Unspent coins the chain gave the wallet's addresses that the wallet does not hold, with the
+    /// height that created them.
+    pub missing: BTreeMap<CoinId, (Coin, u64)>,
+}
+
+impl AuditReport {
+    /// Whether the wallet's coins are exactly the chain's.
+    pub fn is_clean(&self) -> bool {
+        self.spent.is_empty() && self.phantom.is_empty() && self.altered.is_empty() && self.missing.is_empty()
+    }
+}
+
+impl Wallet {
+    /// Compare the coins the wallet holds with the coins its addresses own unspent on the node's best
+    /// chain, up to the wallet's best block, without changing the wallet.
+    ///
+    /// Every block up to the wallet's best block is fetched. Fails if the wallet's best block is not
+    /// on the node's chain, which sync handles, or if the node cannot provide a block.
+    pub fn audit_against_node<Node: NodeEndpoint>(&self, node: &Node) -> Result<AuditReport, AuditError> {
+        let height = self.best_block_height;
+        if node.best_block_at_height(height).unwrap_or(Block::genesis().id()) != self.best_block_hash {
+            return Err(AuditError::NotOnNodeChain { height });
+        }
+
+        // the chain's unspent coins of the wallet's addresses, and where the wallet's coins were spent
+        let mut unspent: HashMap<CoinId, (Coin, u64)> = HashMap::new();
+        let mut spent: BTreeMap<CoinId, u64> = BTreeMap::new();
+        for block_height in 1..=height {
+            let block = node
+                .best_block_at_height(block_height)
+                .and_then(|block_id| node.entire_block(&block_id))
+                .ok_or(AuditError::BlockUnavailable { height: block_height })?;
+            for transaction in &block.body {
+                for coin_id in transaction.iter_input_coin_ids() {
+                    if unspent.remove(&coin_id).is_some() && self.coins.contains_key(&coin_id) {
+                        spent.insert(coin_id, block_height);
+                    }
+                }
+                for (coin_id, coin) in transaction.iter_output_coins_and_ids(block_height) {
+                    if self.owns(&coin.owner) {
+                        unspent.insert(coin_id, (coin, block_height));
+                    }
+                }
+            }
+        }
+
+        let mut report = AuditReport {
+            height,
+            spent,
+            ..AuditReport::default()
+        };
+        for (coin_id, coin) in self.coins.iter() {
+            match unspent.remove(coin_id) {
+                Some((expected, _)) if expected == *coin => {}
+                Some(created) => {
+                    report.altered.insert(*coin_id, created);
+                }
+                None if report.spent.contains_key(coin_id) => {}
+                None => {
+                    report.phantom.insert(*coin_id);
+                }
+            }
+        }
+        report.missing.extend(unspent);
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(height, clean = report.is_clean(), "wallet audited");
+        Ok(report)
+    }
+
+    /// Audit the wallet against the node and reconcile it with the node's chain, returning what the
+    /// audit found.
+    ///
+    /// A wallet off the node's chain is synced first. The wallet is then rewound to the block below
+    /// the lowest height any disagreement comes from and synced forward again, like a reorg there.
+    /// Coins the chain never created are dropped, along with the history that received them.
+    pub fn repair<Node: NodeEndpoint>(&mut self, node: &Node) -> Result<AuditReport, AuditError> {
+        let report = match self.audit_against_node(node) {
+            Err(AuditError::NotOnNodeChain { .. }) => {
+                self.try_sync(node)?;
+                self.audit_against_node(node)?
+            }
+            result => result?,
+        };
+        if report.is_clean() {
+            return Ok(report);
+        }
+
+        let received_at: HashMap<CoinId, u64> = self
+            .history
+            .entries()
+            .iter()
+            .flat_map(|entry| entry.received.iter().map(move |(coin_id, _)| (*coin_id, entry.height)))
+            .collect();
+        let mut rescan_from: Option<u64> = None;
+        for coin_id in &report.phantom {
+            match received_at.get(coin_id) {
+                Some(height) => rescan_from = Some(rescan_from.map_or(*height, |from| from.min(*height))),
+                None => {
+                    self.coins.remove(coin_id);
+                    self.coinbase_heights.remove(coin_id);
+                    self.outpoints.remove(coin_id);
+                }
+            }
+        }
+        let heights = report
+            .spent
+            .values()
+            .chain(report.altered.values().map(|(_, height)| height))
+            .chain(report.missing.values().map(|(_, height)| height));
+        for height in heights {
+            rescan_from = Some(rescan_from.map_or(*height, |from| from.min(*height)));
+        }
+
+        if let Some(from) = rescan_from {
+            let below = from - 1;
+            let hash = match below {
+                0 => Block::genesis().id(),
+                _ => node.best_block_at_height(below).ok_or(AuditError::BlockUnavailable { height: below })?,
+            };
+            #[cfg(feature = "tracing")]
+            tracing::warn!(from_height = from, "repairing wallet by rescanning");
+            self.rewind_to(below, hash);
+        }
+        self.try_sync(node)?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::test_support::*;
+
+    /// A wallet of Alice synced to three blocks: Alice mines 50 bones in block 1, mines 60 and
+    /// pays the 50 to Bob in block 2, and mines 70 in block 3. Returns the node, the wallet,
+    /// block 2, and the coinbases of blocks 1 and 3.
+    fn audited_wallet() -> (MockNode, Wallet, BlockId, Transaction, Transaction) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+
+        let first = Transaction::coinbase(Address::Alice, 50);
+        let pay_bob = spend(first.coin_id(1, 0), Address::Alice, [(Address::Bob, 50)]);
+        let third = Transaction::coinbase(Address::Alice, 70);
+
+        let b1 = node.add_block_as_best(Block::genesis().id(), vec![first.clone()]);
+        let b2 = node.add_block_as_best(b1, vec![Transaction::coinbase(Address::Alice, 60), pay_bob]);
+        node.add_block_as_best(b2, vec![third.clone()]);
+        wallet.sync(&node);
+        (node, wallet, b2, first, third)
+    }
+
+    /// Brings back the coin the chain spent, adds one it never created, and drops one it created.
+    /// Returns the coin that was never created.
+    fn drift(wallet: &mut Wallet, first: &Transaction, third: &Transaction) -> CoinId {
+        let phantom = marker_tx().coin_id(9, 0);
+        wallet.coins.insert(first.coin_id(1, 0), first.outputs[0].clone());
+        wallet.coins.insert(phantom, Coin { value: 7, owner: Address::Alice, asset_id: None });
+        wallet.outpoints.insert(phantom, (marker_tx().id(), 0));
+        wallet.coins.remove(&third.coin_id(3, 0));
+        phantom
+    }
+
+    #[test]
+    fn synced_wallets_audit_clean() {
+        let (node, wallet, _, _, _) = audited_wallet();
+
+        assert!(wallet.audit_against_node(&node).unwrap().is_clean());
+    }
+
+    #[test]
+    fn audits_report_coins_out_of_step_with_the_chain() {
+        let (node, mut wallet, _, first, third) = audited_wallet();
+        let phantom = drift(&mut wallet, &first, &third);
+        assert_eq!(wallet.net_worth(), 117);
+
+        let report = wallet.audit_against_node(&node).unwrap();
+        assert_eq!(report.height, 3);
+        assert_eq!(report.spent, BTreeMap::from([(first.coin_id(1, 0), 2)]));
+        assert_eq!(report.phantom, BTreeSet::from([phantom]));
+        assert_eq!(report.missing, BTreeMap::from([(third.coin_id(3, 0), (third.outputs[0].clone(), 3))]));
+        assert!(report.altered.is_empty());
+        // the audit changed nothing
+        assert_eq!(wallet.net_worth(), 117);
+    }
+
+    #[test]
+    fn repair_rescans_only_the_coins_out_of_step() {
+        let (node, mut wallet, _, first, third) = audited_wallet();
+        let entries = wallet.history.entries().len();
+        drift(&mut wallet, &first, &third);
+        let report = wallet.audit_against_node(&node).unwrap();
+
+        let queries = node.how_many_queries();
+        assert_eq!(wallet.repair(&node), Ok(report));
+        assert!(node.how_many_queries() - queries < 10);
+        assert!(wallet.audit_against_node(&node).unwrap().is_clean());
+        assert_eq!(wallet.check_invariants(), Ok(()));
+        assert_eq!((wallet.net_worth(), wallet.history.entries().len()), (130, entries));
+    }
+
+    #[test]
+    fn wallets_off_the_node_chain_are_synced_before_the_audit() {
+        let (mut node, mut wallet, b2, _, _) = audited_wallet();
+
+        let fork = node.add_block(b2, vec![Transaction::coinbase(Address::Bob, 80)]);
+        let tip = node.add_block(fork, vec![]);
+        node.set_best(tip);
+
+        assert_eq!(wallet.audit_against_node(&node), Err(AuditError::NotOnNodeChain { height: 3 }));
+        assert!(wallet.repair(&node).unwrap().is_clean());
+        assert_eq!((wallet.best_height(), wallet.net_worth()), (4, 60));
+    }
+}
@@ -0,0 +1,258 @@
+//! Automatic backups: snapshots written to a directory as the wallet syncs, oldest ones rotated out.
+//!
+//! With a `BackupPolicy` set, every sync that moved the wallet at least `every_blocks` blocks, or
+//! changed its net worth by at least `balance_change` bones, since the last backup writes a new one.
+//! A backup file is named by the time it was written and the wallet's height, so the names sort
+//! oldest first, and holds a checksum followed by the `export_state` snapshot.
+//! `restore_from_backup` refuses files whose checksum does not match. Only the newest `keep` backups
+//! are kept; files in the directory that are not backups are left alone.
+//!
+//! A failed automatic backup does not fail the sync. The error is kept for `last_backup_error`,
+//! and the next sync tries again.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bonecoin_core::codec::{Decode, Encode};
+use bonecoin_core::*;
+
+use crate::{StateError, Wallet};
+
+/// The start of every backup file name.
+const BACKUP_PREFIX: &str = "wallet-";
+/// The end of every backup file name.
+const BACKUP_SUFFIX: &str = ".backup";
+
+/// When and where the wallet backs itself up.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct BackupPolicy {
+    /// The directory the backups are written to, created if it does not exist.
+    pub dir: PathBuf,
+    /// Back up once the wallet synced this many blocks past the last backup. Zero never does.
+    pub every_blocks: u64,
+    /// Back up once the wallet's net worth moved by this many bones since the last backup, in
+    /// either direction. Zero never does.
+    pub balance_change: u64,
+    /// The number of backups kept, newest first. Zero keeps all of them.
+    pub keep: usize,
+}
+
+impl BackupPolicy {
+    /// Back up to `dir` every 100 blocks, keeping the newest 10 backups.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        BackupPolicy {
+            dir: dir.into(),
+            every_blocks: 100,
+            balance_change: 0,
+            keep: 10,
+        }
+    }
+}
+
+/// The backup policy of a wallet with what it last backed up.
+pub(crate) struct Backups {
+    policy: BackupPolicy,
+    height: u64, // the wallet's height at the last backup, or when the policy was set
+    net_worth: u64, // the wallet's net worth then
+    last_error: Option<StateError>,
+}
+
+impl Wallet {
+    /// Back up automatically as `policy` says, from the wallet's current state on. Fails if the
+    /// backup directory cannot be created.
+    pub fn set_backup_policy(&mut self, policy: BackupPolicy) -> Result<(), StateError> {
+        fs::create_dir_all(&policy.dir).map_err(io)?;
+        self.backups = Some(Backups {
+            policy,
+            height: self.best_block_height,
+            net_worth: self.net_worth(),
+            last_error: None,
+        });
+        Ok(())
+    }
+
+    /// Stop backing up automatically. The backups written so far are kept.
+    pub fn clear_backup_policy(&mut self) {
+        self.backups = None;
+    }
+
+    /// The error of the last automatic backup, if it failed.
+    pub fn last_backup_error(&self) -> Option<&StateError> {
+        self.backups.as_ref()?.last_error.as_ref()
+    }
+
+    /// The backups in the policy's directory, oldest first, or none without a policy.
+    pub fn backup_files(&self) -> Result<Vec<PathBuf>, StateError> {
+        match &self.backups {
+            Some(backups) => list_backups(&backups.policy.dir),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Write a backup now, whether or not one is due, and rotate out the oldest ones. Returns the
+    /// path of the new backup. Fails without a backup policy.
+    pub fn backup_now(&mut self) -> Result<PathBuf, StateError> {
+        let backups = self.backups.as_ref().ok_or(StateError::NoBackupPolicy)?;
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_millis());
+        let path = backups.policy.dir.join(format!("{BACKUP_PREFIX}{millis:020}-{:020}{BACKUP_SUFFIX}", self.best_block_height));
+        let snapshot = self.export_state();
+        let mut bytes = hash_preimage(&snapshot).encode();
+        bytes.extend_from_slice(&snapshot);
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        fs::write(&partial, bytes).map_err(io)?;
+        fs::rename(&partial, &path).map_err(io)?;
+
+        let keep = backups.policy.keep;
+        let all = list_backups(&backups.policy.dir)?;
+        if keep > 0 && all.len() > keep {
+            for old in &all[..all.len() - keep] {
+                fs::remove_file(old).map_err(io)?;
+            }
+        }
+        let (height, net_worth) = (self.best_block_height, self.net_worth());
+        if let Some(backups) = &mut self.backups {
+            backups.height = height;
+            backups.net_worth = net_worth;
+        }
+        Ok(path)
+    }
+
+    /// Rebuild a wallet from a backup file, failing with `StateError::ChecksumMismatch` if the file
+    /// was damaged since it was written.
+    pub fn restore_from_backup(path: impl AsRef<Path>) -> Result<Wallet, StateError> {
+        let bytes = fs::read(path).map_err(io)?;
+        let mut snapshot = bytes.as_slice();
+        let checksum = u64::decode_from(&mut snapshot)?;
+        if hash_preimage(snapshot) != checksum {
+            return Err(StateError::ChecksumMismatch);
+        }
+        Wallet::import_state(snapshot)
+    }
+
+    /// Write a backup if the policy says one is due, after a sync.
+    pub(crate) fn backup_if_due(&mut self) {
+        let Some(backups) = &self.backups else { return };
+        let policy = &backups.policy;
+        let blocks_due = policy.every_blocks > 0 && self.best_block_height.abs_diff(backups.height) >= policy.every_blocks;
+        let balance_due = policy.balance_change > 0 && self.net_worth().abs_diff(backups.net_worth) >= policy.balance_change;
+        if !blocks_due && !balance_due {
+            return;
+        }
+        let result = self.backup_now();
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(path) => tracing::info!(height = self.best_block_height, path = %path.display(), "wallet backed up"),
+            Err(e) => tracing::warn!(height = self.best_block_height, error = %e, "wallet backup failed"),
+        }
+        if let Some(backups) = &mut self.backups {
+            backups.last_error = result.err();
+        }
+    }
+}
+
+/// The backup files in `dir`, oldest first.
+fn list_backups(dir: &Path) -> Result<Vec<PathBuf>, StateError> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(io)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_SUFFIX))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn io(e: std::io::Error) -> StateError {
+    StateError::Io(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::test_support::*;
+
+    /// A wallet of Alice backing up to a fresh directory every 2 blocks or on a balance change of
+    /// 100 bones, keeping 2 backups. The directory also holds a file that is not a backup.
+    /// Returns the directory, an empty node, and the wallet.
+    fn backing_up(name: &str) -> (PathBuf, MockNode, Wallet) {
+        let dir = unique_temp_path(name);
+        let mut wallet = wallet_with_alice();
+        wallet
+            .set_backup_policy(BackupPolicy { dir: dir.clone(), every_blocks: 2, balance_change: 100, keep: 2 })
+            .unwrap();
+        fs::write(dir.join("notes.txt"), "not a backup").unwrap();
+        (dir, MockNode::new(), wallet)
+    }
+
+    /// Mines a block on `parent` paying Alice `value` bones.
+    fn pay_alice_on(node: &mut MockNode, parent: &mut BlockId, value: u64) {
+        *parent = node.add_block_as_best(*parent, vec![mint([(Address::Custom(value), value)]), Transaction::coinbase(Address::Alice, value)]);
+    }
+
+    #[test]
+    fn backing_up_needs_a_policy() {
+        assert_eq!(wallet_with_alice().backup_now(), Err(StateError::NoBackupPolicy));
+    }
+
+    #[test]
+    fn backups_are_written_every_few_blocks_and_on_large_payments() {
+        let (dir, mut node, mut wallet) = backing_up("backups_are_written");
+        let mut parent = Block::genesis().id();
+
+        pay_alice_on(&mut node, &mut parent, 50);
+        wallet.sync(&node);
+        assert!(wallet.backup_files().unwrap().is_empty());
+
+        pay_alice_on(&mut node, &mut parent, 50);
+        wallet.sync(&node);
+        assert_eq!(wallet.backup_files().unwrap().len(), 1);
+
+        pay_alice_on(&mut node, &mut parent, 100);
+        wallet.sync(&node);
+        assert_eq!(wallet.backup_files().unwrap().len(), 2);
+        assert_eq!(wallet.last_backup_error(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn old_backups_are_rotated_out() {
+        let (dir, mut node, mut wallet) = backing_up("backups_are_rotated");
+        let mut parent = Block::genesis().id();
+
+        for value in [50, 50, 100, 1, 1] {
+            pay_alice_on(&mut node, &mut parent, value);
+            wallet.sync(&node);
+        }
+
+        assert_eq!(wallet.backup_files().unwrap().len(), 2);
+        assert!(dir.join("notes.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wallets_are_restored_from_backups_that_pass_their_checksum() {
+        let (dir, mut node, mut wallet) = backing_up("backups_are_restored");
+        let mut parent = Block::genesis().id();
+        for value in [50, 50, 100] {
+            pay_alice_on(&mut node, &mut parent, value);
+            wallet.sync(&node);
+        }
+        let files = wallet.backup_files().unwrap();
+
+        let restored = Wallet::restore_from_backup(files.last().unwrap()).unwrap();
+        assert_eq!((restored.best_hash(), restored.net_worth()), (wallet.best_hash(), 200));
+
+        let mut damaged = fs::read(&files[0]).unwrap();
+        *damaged.last_mut().unwrap() ^= 1;
+        fs::write(&files[0], damaged).unwrap();
+        assert_eq!(Wallet::restore_from_backup(&files[0]).err(), Some(StateError::ChecksumMismatch));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+//! Standard workloads for comparing wallet implementations.
+//!
+//! `run` builds a workload's chain on a `MockNode`, creates a wallet through `WalletSync::new`, and
+//! measures the part of the workload being compared: how long it took and how many queries the node
+//! answered. Building the chain and any syncing a workload needs beforehand are not measured. Any
+//! `WalletApi` implementation can be run, so reports of different wallets for the same workload
+//! compare like for like. `Workload::standard` lists the sizes reports are meant to be compared at.
+//! The module is only compiled for the crate's own tests and when the `bench` feature is enabled.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use bonecoin_core::*;
+
+/// The address the benchmarked wallet owns.
+const OWNER: Address = Address::Alice;
+/// The address of everyone else.
+const OTHER: Address = Address::Bob;
+/// The transactions of a block in the wide wallet workloads.
+const TRANSACTIONS_PER_BLOCK: u64 = 16;
+
+/// A standardized task for a wallet.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Workload {
+    /// Sync a fresh wallet over a chain of `blocks` blocks, each paying the wallet a coinbase and
+    /// spending the previous block's coinbase.
+    LinearSync { blocks: u64 },
+    /// Sync a wallet over a chain of `depth` blocks, then measure the sync after the node switches
+    /// to a fork from genesis one block longer that pays the wallet nothing.
+    DeepReorg { depth: u64 },
+    /// Sync a fresh wallet over blocks giving it `coins` one-bone coins, then query its net worth.
+    WideWallet { coins: u64 },
+    /// Build `payments` automatic transactions from a synced wallet holding `coins` one-bone coins,
+    /// each paying half as many bones as a transaction may have inputs, which `coins` must cover.
+    HeavySelection { coins: u64, payments: u64 },
+}
+
+impl Workload {
+    /// The workloads at the sizes reports are compared at.
+    pub fn standard() -> Vec<Workload> {
+        vec![
+            Workload::LinearSync { blocks: 10_000 },
+            Workload::DeepReorg { depth: 1_000 },
+            Workload::WideWallet { coins: 1_000_000 },
+            Workload::HeavySelection { coins: 100_000, payments: 100 },
+        ]
+    }
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Workload::LinearSync { blocks } => write!(f, "linear sync of {blocks} blocks"),
+            Workload::DeepReorg { depth } => write!(f, "reorg {depth} blocks deep"),
+            Workload::WideWallet { coins } => write!(f, "wide wallet of {coins} coins"),
+            Workload::HeavySelection { coins, payments } => write!(f, "{payments} payments from {coins} coins"),
+        }
+    }
+}
+
+/// What running a workload against a wallet measured.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BenchReport {
+    pub workload: Workload,
+    /// The time the measured part took.
+    pub elapsed: Duration,
+    /// The queries the node answered during the measured part.
+    pub queries: u64,
+    /// Whether the wallet ended up where the workload expects, so a fast but wrong wallet stands out.
+    pub correct: bool,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:?}, {} queries", self.workload, self.elapsed, self.queries)?;
+        if !self.correct {
+            write!(f, ", WRONG RESULT")?;
+        }
+        Ok(())
+    }
+}
+
+/// Run every workload against a fresh wallet of type `W`.
+pub fn run_all<W: WalletApi>(workloads: &[Workload]) -> Vec<BenchReport> {
+    workloads.iter().map(|workload| run::<W>(*workload)).collect()
+}
+
+/// Run the workload against a fresh wallet of type `W` owning `Address::Alice`.
+pub fn run<W: WalletApi>(workload: Workload) -> BenchReport {
+    let mut node = MockNode::new();
+    let mut wallet = W::new([OWNER].into_iter());
+    match workload {
+        Workload::LinearSync { blocks } => {
+            let mut parent = Block::genesis().id();
+            let mut previous: Option<Transaction> = None;
+            for height in 1..=blocks {
+                let coinbase = Transaction::coinbase(OWNER, BLOCK_REWARD);
+                let mut body = vec![coinbase.clone()];
+                body.extend(previous.map(|previous| payment(previous.coin_id(height - 1, 0), BLOCK_REWARD)));
+                parent = node.add_block_as_best(parent, body);
+                previous = Some(coinbase);
+            }
+            let report = measure(workload, &node, || wallet.sync(&node));
+            // every coinbase but the newest paid one bone away
+            let expected = blocks * BLOCK_REWARD - blocks.saturating_sub(1);
+            BenchReport {
+                correct: wallet.best_hash() == parent && wallet.net_worth() == expected,
+                ..report
+            }
+        }
+        Workload::DeepReorg { depth } => {
+            let mut parent = Block::genesis().id();
+            for _ in 0..depth {
+                parent = node.add_block_as_best(parent, vec![Transaction::coinbase(OWNER, BLOCK_REWARD)]);
+            }
+            wallet.sync(&node);
+            let mut fork = Block::genesis().id();
+            for _ in 0..=depth {
+                fork = node.add_block(fork, vec![Transaction::coinbase(OTHER, BLOCK_REWARD)]);
+            }
+            node.set_best(fork);
+            let report = measure(workload, &node, || wallet.sync(&node));
+            BenchReport {
+                correct: wallet.best_hash() == fork && wallet.net_worth() == 0,
+                ..report
+            }
+        }
+        Workload::WideWallet { coins } => {
+            let tip = add_wide_blocks(&mut node, coins);
+            let mut net_worth = 0;
+            let report = measure(workload, &node, || {
+                wallet.sync(&node);
+                net_worth = wallet.net_worth();
+            });
+            BenchReport {
+                correct: wallet.best_hash() == tip && net_worth == coins,
+                ..report
+            }
+        }
+        Workload::HeavySelection { coins, payments } => {
+            add_wide_blocks(&mut node, coins);
+            wallet.sync(&node);
+            let amount = (MAX_TX_INPUTS / 2) as u64;
+            let mut built = 0;
+            let report = measure(workload, &node, || {
+                for _ in 0..payments {
+                    built += u64::from(wallet.create_automatic_transaction(OTHER, amount, 0).is_ok());
+                }
+            });
+            BenchReport {
+                correct: built == payments,
+                ..report
+            }
+        }
+    }
+}
+
+/// Time `task` and count the queries the node answers meanwhile.
+fn measure(workload: Workload, node: &MockNode, task: impl FnOnce()) -> BenchReport {
+    let queries = node.how_many_queries();
+    let start = Instant::now();
+    task();
+    BenchReport {
+        workload,
+        elapsed: start.elapsed(),
+        queries: node.how_many_queries() - queries,
+        correct: true,
+    }
+}
+
+/// A transaction spending `coin_id` that pays one bone away and the rest of `value` back to the wallet.
+fn payment(coin_id: CoinId, value: u64) -> Transaction {
+    Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input {
+            coin_id,
+            signature: Signature::Valid(OWNER),
+        }],
+        outputs: vec![
+            Coin {
+                value: 1,
+                owner: OTHER,
+                asset_id: None,
+            },
+            Coin {
+                value: value - 1,
+                owner: OWNER,
+                asset_id: None,
+            },
+        ],
+    }
+}
+
+/// Add blocks giving the wallet `coins` one-bone coins in total, and return the last one.
+///
+/// Each transaction pays the wallet as many coins as it may have outputs but one, and the last
+/// output, different in every transaction of a block, to someone else so the transactions differ.
+fn add_wide_blocks(node: &mut MockNode, coins: u64) -> BlockId {
+    let per_transaction = (MAX_TX_OUTPUTS - 1) as u64;
+    let mut parent = Block::genesis().id();
+    let mut remaining = coins;
+    while remaining > 0 {
+        let mut body = Vec::new();
+        for index in 0..TRANSACTIONS_PER_BLOCK {
+            if remaining == 0 {
+                break;
+            }
+            let paid = remaining.min(per_transaction);
+            remaining -= paid;
+            let mut outputs: Vec<Coin> = (0..paid)
+                .map(|_| Coin {
+                    value: 1,
+                    owner: OWNER,
+                    asset_id: None,
+                })
+                .collect();
+            outputs.push(Coin {
+                value: index + 1,
+                owner: OTHER,
+                asset_id: None,
+            });
+            body.push(Transaction {
+                version: TRANSACTION_VERSION,
+                inputs: vec![Input::dummy()],
+                outputs,
+            });
+        }
+        parent = node.add_block_as_best(parent, body);
+    }
+    parent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Wallet;
+
+    #[test]
+    fn every_workload_runs_correctly_in_order() {
+        let workloads = [
+            Workload::LinearSync { blocks: 20 },
+            Workload::DeepReorg { depth: 5 },
+            Workload::WideWallet { coins: 2_000 },
+            Workload::HeavySelection { coins: 500, payments: 3 },
+        ];
+        let reports = run_all::<Wallet>(&workloads);
+        assert!(reports.iter().all(|report| report.correct), "{reports:?}");
+        assert_eq!(reports.iter().map(|report| report.workload).collect::<Vec<_>>(), workloads);
+    }
+
+    #[test]
+    fn reports_count_the_queries_to_the_node() {
+        // a fresh sync asks for every block and one past the end
+        assert!(run::<Wallet>(Workload::LinearSync { blocks: 20 }).queries >= 21);
+        assert_eq!(run::<Wallet>(Workload::HeavySelection { coins: 50, payments: 3 }).queries, 0);
+    }
+
+    #[test]
+    fn reports_name_their_workload() {
+        assert!(run::<Wallet>(Workload::DeepReorg { depth: 5 }).to_string().starts_with("reorg 5 blocks deep: "));
+    }
+}
@@ -0,0 +1,295 @@
+//! The application's state and how keys change it.
+
+use std::collections::{BTreeSet, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use bonecoin_core::*;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use utxo_wallet::{PaymentUri, PaymentUriError, Wallet, WalletEvent};
+
+use crate::chain::{lock, SyncStatus};
+
+/// The most lines the event log keeps.
+pub const EVENT_LOG: usize = 100;
+
+/// The address named by a command line argument or typed recipient: `Alice` through `Eve`,
+/// `Custom(5)`, or a bare number for a custom address.
+pub fn parse_address(name: &str) -> Option<Address> {
+    let name = name.trim();
+    let custom = name.strip_prefix("Custom(").and_then(|rest| rest.strip_suffix(')')).unwrap_or(name);
+    match name {
+        "Alice" => Some(Address::Alice),
+        "Bob" => Some(Address::Bob),
+        "Charlie" => Some(Address::Charlie),
+        "Dave" => Some(Address::Dave),
+        "Eve" => Some(Address::Eve),
+        _ => custom.parse().ok().map(Address::Custom),
+    }
+}
+
+/// Which question the send flow is asking.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SendStep {
+    /// Asking for an address or a `bone:` payment URI, which answers the amount too if it names one.
+    Recipient,
+    Amount,
+    Tip,
+    /// Picking the coins the transaction may spend; none picked lets the wallet choose.
+    Coins,
+    /// Showing the built transaction before it is submitted.
+    Confirm,
+}
+
+/// The send flow's answers so far.
+pub struct SendForm {
+    pub step: SendStep,
+    pub recipient: String,
+    /// The address the recipient answer named.
+    pub payee: Option<Address>,
+    /// The payment URI the recipient answer was, if it was one.
+    pub request: Option<PaymentUri>,
+    pub amount: String,
+    pub tip: String,
+    /// The coins the transaction may spend, largest first.
+    pub coins: Vec<(CoinId, Coin)>,
+    pub selected: BTreeSet<CoinId>,
+    /// The coin the cursor is on while picking coins.
+    pub cursor: usize,
+    /// The transaction built from the answers, once they are complete.
+    pub transaction: Option<Transaction>,
+    /// Why the last answer was not accepted.
+    pub error: Option<String>,
+}
+
+impl SendForm {
+    /// The answer being typed at the current step, if it is typed.
+    fn input(&mut self) -> Option<&mut String> {
+        match self.step {
+            SendStep::Recipient => Some(&mut self.recipient),
+            SendStep::Amount => Some(&mut self.amount),
+            SendStep::Tip => Some(&mut self.tip),
+            SendStep::Coins | SendStep::Confirm => None,
+        }
+    }
+}
+
+pub struct App {
+    pub wallet: Arc<Mutex<Wallet>>,
+    pub node: Arc<Mutex<MockNode>>,
+    /// Where the latest auto-sync left the wallet, once there was one.
+    pub sync: Option<SyncStatus>,
+    /// What happened so far, newest first.
+    pub events: VecDeque<String>,
+    /// Transactions sent from here that the wallet has not seen mined yet.
+    pub pending: Vec<Transaction>,
+    /// How many history rows are scrolled past.
+    pub scroll: usize,
+    pub send: Option<SendForm>,
+    pub quit: bool,
+}
+
+impl App {
+    pub fn new(wallet: Arc<Mutex<Wallet>>, node: Arc<Mutex<MockNode>>) -> Self {
+        App {
+            wallet,
+            node,
+            sync: None,
+            events: VecDeque::new(),
+            pending: Vec::new(),
+            scroll: 0,
+            send: None,
+            quit: false,
+        }
+    }
+
+    pub fn log(&mut self, line: String) {
+        self.events.push_front(line);
+        self.events.truncate(EVENT_LOG);
+    }
+
+    /// Take in where the auto-sync got to, and forget the pending sends it settled: mined ones,
+    /// and ones a conflicting transaction spent the coins of.
+    pub fn on_sync(&mut self, status: SyncStatus) {
+        if let Err(e) = &status.outcome {
+            self.log(format!("sync failed: {e}"));
+        }
+        let wallet = lock(&self.wallet);
+        let mined: HashSet<TransactionId> = wallet.history().iter().map(|entry| entry.tx_id).collect();
+        let coins: HashSet<CoinId> = wallet.all_coins().map(|(coin_id, _)| *coin_id).collect();
+        self.pending
+            .retain(|transaction| !mined.contains(&transaction.id()) && transaction.iter_input_coin_ids().all(|coin_id| coins.contains(&coin_id)));
+        drop(wallet);
+        self.sync = Some(status);
+    }
+
+    pub fn on_event(&mut self, event: WalletEvent) {
+        let line = match event {
+            WalletEvent::Synced { height, block_id } => format!("synced to block {height} ({block_id})"),
+            WalletEvent::RolledBack { to_height } => format!("reorg, rolled back to block {to_height}"),
+            WalletEvent::CoinReceived { coin_id, coin } => format!("received {} bones on {} in coin {coin_id}", coin.value, coin.owner),
+            WalletEvent::CoinSpent { coin_id, tx_id } => format!("coin {coin_id} spent by {tx_id}"),
+            event => format!("{event:?}"),
+        };
+        self.log(line);
+    }
+
+    pub fn on_key(&mut self, key: KeyEvent) {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit = true;
+            return;
+        }
+        if self.send.is_some() {
+            self.on_send_key(key);
+            return;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Char('s') => self.open_send(),
+            KeyCode::Down => self.scroll += 1,
+            KeyCode::Up => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageDown => self.scroll += 10,
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Home => self.scroll = 0,
+            _ => {}
+        }
+    }
+
+    /// Start the send flow, offering the mature coins no pending send spends.
+    fn open_send(&mut self) {
+        let wallet = lock(&self.wallet);
+        let mut coins: Vec<(CoinId, Coin)> = wallet
+            .all_coins()
+            .filter(|(coin_id, _)| !wallet.is_pending_spend(coin_id) && wallet.is_mature(coin_id))
+            .map(|(coin_id, coin)| (*coin_id, coin.clone()))
+            .collect();
+        coins.sort_by(|a, b| b.1.value.cmp(&a.1.value).then(a.0.cmp(&b.0)));
+        self.send = Some(SendForm {
+            step: SendStep::Recipient,
+            recipient: String::new(),
+            payee: None,
+            request: None,
+            amount: String::new(),
+            tip: String::new(),
+            coins,
+            selected: BTreeSet::new(),
+            cursor: 0,
+            transaction: None,
+            error: None,
+        });
+    }
+
+    fn on_send_key(&mut self, key: KeyEvent) {
+        let Some(form) = self.send.as_mut() else { return };
+        if let Some(input) = form.input() {
+            match key.code {
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    return;
+                }
+                KeyCode::Backspace => {
+                    input.pop();
+                    return;
+                }
+                _ => {}
+            }
+        }
+        match (form.step, key.code) {
+            (SendStep::Recipient, KeyCode::Esc) => self.send = None,
+            (_, KeyCode::Esc) => {
+                form.error = None;
+                form.transaction = None;
+                form.step = match form.step {
+                    SendStep::Amount => SendStep::Recipient,
+                    SendStep::Tip => SendStep::Amount,
+                    SendStep::Coins => SendStep::Tip,
+                    _ => SendStep::Coins,
+                };
+            }
+            (SendStep::Coins, KeyCode::Down) => form.cursor = (form.cursor + 1).min(form.coins.len().saturating_sub(1)),
+            (SendStep::Coins, KeyCode::Up) => form.cursor = form.cursor.saturating_sub(1),
+            (SendStep::Coins, KeyCode::Char(' ')) => {
+                if let Some((coin_id, _)) = form.coins.get(form.cursor) {
+                    if !form.selected.remove(coin_id) {
+                        form.selected.insert(*coin_id);
+                    }
+                }
+            }
+            (_, KeyCode::Enter) => self.advance(),
+            _ => {}
+        }
+    }
+
+    /// Check the answer to the current step and move on to the next, building the transaction
+    /// after the coins are picked and submitting it once confirmed.
+    fn advance(&mut self) {
+        let Some(form) = self.send.as_mut() else { return };
+        let next = match form.step {
+            SendStep::Recipient => match PaymentUri::parse(form.recipient.trim()) {
+                Ok(request) => {
+                    form.payee = Some(request.address.clone());
+                    let next = match request.amount {
+                        Some(amount) => {
+                            form.amount = amount.to_string();
+                            SendStep::Tip
+                        }
+                        None => SendStep::Amount,
+                    };
+                    form.request = Some(request);
+                    Ok(next)
+                }
+                Err(PaymentUriError::WrongScheme) => {
+                    form.payee = parse_address(&form.recipient);
+                    form.request = None;
+                    form.payee.as_ref().map(|_| SendStep::Amount).ok_or(format!("{:?} is not an address or payment URI", form.recipient))
+                }
+                Err(e) => Err(e.to_string()),
+            },
+            SendStep::Amount => match form.amount.trim().parse::<u64>() {
+                Ok(amount) if amount > 0 => Ok(SendStep::Tip),
+                _ => Err("the amount must be a positive number of bones".to_string()),
+            },
+            SendStep::Tip => match form.tip.trim() {
+                "" => Ok(SendStep::Coins),
+                tip => match tip.parse::<u64>() {
+                    Ok(_) => Ok(SendStep::Coins),
+                    Err(_) => Err("the tip must be a number of bones".to_string()),
+                },
+            },
+            SendStep::Coins => {
+                let recipient = form.payee.clone().expect("checked at its step");
+                let amount = form.amount.trim().parse().expect("checked at its step");
+                let tip = form.tip.trim().parse().unwrap_or(0);
+                let allowed: HashSet<CoinId> = match form.selected.is_empty() {
+                    true => form.coins.iter().map(|(coin_id, _)| *coin_id).collect(),
+                    false => form.selected.iter().copied().collect(),
+                };
+                match lock(&self.wallet).create_automatic_transaction_using(&allowed, recipient, amount, tip) {
+                    Ok(transaction) => {
+                        form.transaction = Some(transaction);
+                        Ok(SendStep::Confirm)
+                    }
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            SendStep::Confirm => {
+                let transaction = form.transaction.clone().expect("built before confirming");
+                if !lock(&self.node).submit_transaction(&transaction) {
+                    form.error = Some("the node did not accept the transaction".to_string());
+                    return;
+                }
+                lock(&self.wallet).reserve_inputs(&transaction);
+                self.send = None;
+                self.log(format!("sent transaction {}", transaction.id()));
+                self.pending.push(transaction);
+                return;
+            }
+        };
+        match next {
+            Ok(step) => {
+                form.step = step;
+                form.error = None;
+            }
+            Err(e) => form.error = Some(e),
+        }
+    }
+}
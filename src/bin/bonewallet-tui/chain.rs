@@ -0,0 +1,119 @@
+//! The simulated chain the application runs against, and the threads keeping it and the wallet
+//! moving: a miner adding a block every block time, and the auto-sync loop following it.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use bonecoin_core::*;
+use utxo_wallet::Wallet;
+
+/// The bones the first block pays each wallet address, so there is something to send.
+pub const FUNDING: u64 = 1_000;
+
+/// Lock a mutex shared with the other threads, whether or not one of them panicked holding it.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Build the starting chain: a block funding the wallet's addresses, then `blocks` generated blocks
+/// of traffic between the `others`. Returns the node, in strict mode so it refuses double spends,
+/// and its tip.
+pub fn build_chain(seed: u64, blocks: usize, wallet_addresses: &[Address], others: &[Address]) -> (MockNode, BlockId) {
+    let mut node = MockNode::new();
+    node.set_strict(true);
+    let funding = Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input::dummy()],
+        outputs: wallet_addresses.iter().map(|owner| Coin { value: FUNDING, owner: owner.clone(), asset_id: None }).collect(),
+    };
+    let first = node.add_block_as_best(Block::genesis().id(), vec![funding]);
+    let tip = match blocks {
+        0 => first,
+        _ => node.generate_chain(seed, blocks, 3, others),
+    };
+    (node, tip)
+}
+
+/// The height of the node's best block, searching up from a height known to be on its chain.
+pub fn tip_height(node: &MockNode, from: u64) -> u64 {
+    let mut height = from;
+    while node.best_block_at_height(height + 1).is_some() {
+        height += 1;
+    }
+    height
+}
+
+/// Sleep for `duration` in short steps, returning early with `false` once `stop` is set.
+fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) -> bool {
+    let step = Duration::from_millis(50);
+    let mut slept = Duration::ZERO;
+    while slept < duration {
+        if stop.load(Ordering::Relaxed) {
+            return false;
+        }
+        thread::sleep(step.min(duration - slept));
+        slept += step;
+    }
+    !stop.load(Ordering::Relaxed)
+}
+
+/// Add a block on top of `tip` every `block_time`, paying its coinbase to each of the `payees` in
+/// turn and including the transactions submitted since the last block. A submitted transaction
+/// spending a coin that is already spent, on the chain or earlier in the block, is left out.
+pub fn spawn_miner(node: Arc<Mutex<MockNode>>, mut tip: BlockId, payees: Vec<Address>, block_time: Duration, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut mined = 0;
+        let mut turn = 0;
+        while sleep_unless_stopped(block_time, &stop) {
+            let mut node = lock(&node);
+            let submitted = node.submitted_transactions();
+            let mut body = vec![Transaction::coinbase(payees[turn % payees.len()].clone(), BLOCK_REWARD)];
+            let mut spent = HashSet::new();
+            for transaction in &submitted[mined..] {
+                let inputs: Vec<CoinId> = transaction.iter_input_coin_ids().collect();
+                if inputs.iter().all(|coin_id| !spent.contains(coin_id) && node.is_unspent(coin_id) == Some(true)) {
+                    spent.extend(inputs);
+                    body.push(transaction.clone());
+                }
+            }
+            mined = submitted.len();
+            turn += 1;
+            tip = node.add_block_as_best(tip, body);
+        }
+    })
+}
+
+/// Where the auto-sync loop left the wallet after its latest sync.
+#[derive(Clone, Debug)]
+pub struct SyncStatus {
+    /// The height the wallet is synced to.
+    pub height: u64,
+    /// The height of the node's best block.
+    pub tip: u64,
+    /// How many blocks the sync applied and reverted, or why it failed.
+    pub outcome: Result<(usize, usize), String>,
+}
+
+/// Sync the wallet every `interval` and send where it got to, until `stop` is set or nobody
+/// listens anymore. The wallet is locked before the node, like everywhere else in the application.
+pub fn spawn_auto_sync(wallet: Arc<Mutex<Wallet>>, node: Arc<Mutex<MockNode>>, interval: Duration, status: Sender<SyncStatus>, stop: Arc<AtomicBool>) -> JoinHandle<()> {
+    thread::spawn(move || loop {
+        let update = {
+            let mut wallet = lock(&wallet);
+            let node = lock(&node);
+            let outcome = wallet
+                .try_sync(&*node)
+                .map(|report| (report.applied.len(), report.reverted.len()))
+                .map_err(|e| e.to_string());
+            let height = wallet.best_height();
+            SyncStatus { height, tip: tip_height(&node, height), outcome }
+        };
+        if status.send(update).is_err() || !sleep_unless_stopped(interval, &stop) {
+            break;
+        }
+    })
+}
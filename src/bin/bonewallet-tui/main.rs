@@ -0,0 +1,148 @@
+//! `bonewallet-tui`, a terminal wallet application.
+//!
+//! The application runs a wallet against a simulated chain: a `MockNode` starting with a block that
+//! funds the wallet's addresses and some generated traffic between other addresses, extended by a
+//! miner thread every block time. An auto-sync thread keeps the wallet synced to the node's tip and
+//! reports its progress, while the wallet's notifications feed the event log.
+//!
+//! The screen shows the balance of every address, sync progress, and the history with the sent
+//! transactions not mined yet on top. `s` opens the send flow, which asks for the recipient, the
+//! amount, and the tip, lets the coins to spend be picked by hand, and shows the transaction before
+//! submitting it. The recipient may be a `bone:` payment URI, which answers the amount if it names
+//! one. Nothing is persisted, since the simulated chain does not outlive the application.
+//!
+//! ```text
+//! bonewallet-tui [--seed N] [--blocks N] [--block-time SECONDS] [ADDRESS...]
+//! ```
+//!
+//! Addresses are `Alice` through `Eve`, `Custom(N)`, or a number for a custom address; the wallet
+//! owns `Alice` and `Bob` if none are given.
+
+mod app;
+mod chain;
+mod ui;
+
+#[cfg(test)]
+mod tests;
+
+use std::io;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bonecoin_core::*;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use ratatui::DefaultTerminal;
+use utxo_wallet::{Wallet, WalletEvent};
+
+use crate::app::{parse_address, App};
+use crate::chain::{build_chain, spawn_auto_sync, spawn_miner, SyncStatus};
+
+const USAGE: &str = "usage: bonewallet-tui [--seed N] [--blocks N] [--block-time SECONDS] [ADDRESS...]";
+
+/// How often the auto-sync thread syncs the wallet.
+const SYNC_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the screen waits for a key before redrawing.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+struct Options {
+    /// The seed of the generated traffic.
+    seed: u64,
+    /// The blocks of traffic generated before the application starts.
+    blocks: usize,
+    block_time: Duration,
+    addresses: Vec<Address>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            seed: 1,
+            blocks: 50,
+            block_time: Duration::from_secs(5),
+            addresses: Vec::new(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{name} needs a value"));
+            match arg.as_str() {
+                "--seed" => options.seed = value("--seed")?.parse().map_err(|_| "--seed must be a number".to_string())?,
+                "--blocks" => options.blocks = value("--blocks")?.parse().map_err(|_| "--blocks must be a number".to_string())?,
+                "--block-time" => {
+                    let seconds: f64 = value("--block-time")?.parse().map_err(|_| "--block-time must be a number of seconds".to_string())?;
+                    options.block_time = Duration::try_from_secs_f64(seconds).map_err(|_| "--block-time must be a number of seconds".to_string())?;
+                }
+                address => options.addresses.push(parse_address(address).ok_or(format!("{address:?} is not an address"))?),
+            }
+        }
+        if options.addresses.is_empty() {
+            options.addresses = vec![Address::Alice, Address::Bob];
+        }
+        Ok(options)
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    // the traffic and half the mined blocks go to addresses outside the wallet
+    let others: Vec<Address> = (1..=4).map(|n| Address::Custom(1_000 + n)).filter(|address| !options.addresses.contains(address)).collect();
+    let (node, tip) = build_chain(options.seed, options.blocks, &options.addresses, &others);
+    let mut wallet = Wallet::new(options.addresses.iter().cloned());
+    let notifications = wallet.notification_receiver();
+
+    let wallet = Arc::new(Mutex::new(wallet));
+    let node = Arc::new(Mutex::new(node));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (status, sync_updates) = mpsc::channel();
+    let payees = options.addresses.iter().chain(&others).cloned().collect();
+    let threads = [
+        spawn_miner(Arc::clone(&node), tip, payees, options.block_time, Arc::clone(&stop)),
+        spawn_auto_sync(Arc::clone(&wallet), Arc::clone(&node), SYNC_INTERVAL, status, Arc::clone(&stop)),
+    ];
+
+    let mut app = App::new(wallet, node);
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut app, &sync_updates, &notifications);
+    ratatui::restore();
+    stop.store(true, Ordering::Relaxed);
+    drop(sync_updates);
+    for thread in threads {
+        let _ = thread.join();
+    }
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("bonewallet-tui: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Draw the application and handle keys until it quits.
+fn run(terminal: &mut DefaultTerminal, app: &mut App, sync_updates: &Receiver<SyncStatus>, notifications: &Receiver<WalletEvent>) -> io::Result<()> {
+    while !app.quit {
+        for update in sync_updates.try_iter() {
+            app.on_sync(update);
+        }
+        for notification in notifications.try_iter() {
+            app.on_event(notification);
+        }
+        terminal.draw(|frame| ui::draw(frame, app))?;
+        if event::poll(REDRAW_INTERVAL)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    app.on_key(key);
+                }
+            }
+        }
+    }
+    Ok(())
+}
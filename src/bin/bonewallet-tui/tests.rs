@@ -0,0 +1,158 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bonecoin_core::*;
+use ratatui::backend::TestBackend;
+use ratatui::crossterm::event::{KeyCode, KeyEvent};
+use ratatui::Terminal;
+use utxo_wallet::Wallet;
+
+use crate::app::{App, SendStep};
+use crate::chain::{build_chain, lock, spawn_auto_sync, spawn_miner, tip_height, FUNDING};
+use crate::ui;
+
+/// An application whose wallet owns Alice and Bob, synced to a chain funding them.
+fn synced_app() -> App {
+    let (node, _) = build_chain(7, 10, &[Address::Alice, Address::Bob], &[Address::Custom(1001), Address::Custom(1002)]);
+    let mut wallet = Wallet::new([Address::Alice, Address::Bob].into_iter());
+    wallet.sync(&node);
+    App::new(Arc::new(Mutex::new(wallet)), Arc::new(Mutex::new(node)))
+}
+
+fn press(app: &mut App, keys: &str) {
+    for c in keys.chars() {
+        app.on_key(KeyEvent::from(KeyCode::Char(c)));
+    }
+}
+
+fn key(app: &mut App, code: KeyCode) {
+    app.on_key(KeyEvent::from(code));
+}
+
+#[test]
+fn send_flow_submits_a_transaction_spending_the_picked_coin() {
+    let mut app = synced_app();
+    press(&mut app, "s");
+    press(&mut app, "Mallory");
+    key(&mut app, KeyCode::Enter);
+    assert!(app.send.as_ref().unwrap().error.is_some());
+    for _ in 0.."Mallory".len() {
+        key(&mut app, KeyCode::Backspace);
+    }
+    press(&mut app, "Charlie");
+    key(&mut app, KeyCode::Enter);
+    press(&mut app, "300");
+    key(&mut app, KeyCode::Enter);
+    press(&mut app, "2");
+    key(&mut app, KeyCode::Enter);
+
+    // pick the second coin, Bob's, by hand
+    let form = app.send.as_ref().unwrap();
+    assert_eq!(form.step, SendStep::Coins);
+    assert_eq!(form.coins.len(), 2);
+    let (bobs, _) = form.coins.iter().find(|(_, coin)| coin.owner == Address::Bob).cloned().unwrap();
+    let at = form.coins.iter().position(|(coin_id, _)| *coin_id == bobs).unwrap();
+    for _ in 0..at {
+        key(&mut app, KeyCode::Down);
+    }
+    press(&mut app, " ");
+    key(&mut app, KeyCode::Enter);
+    assert_eq!(app.send.as_ref().unwrap().step, SendStep::Confirm);
+    key(&mut app, KeyCode::Enter);
+
+    assert!(app.send.is_none());
+    let submitted = lock(&app.node).submitted_transactions();
+    assert_eq!(submitted.len(), 1);
+    assert_eq!(submitted[0].iter_input_coin_ids().collect::<Vec<_>>(), vec![bobs]);
+    assert_eq!(submitted[0].outputs[0], Coin { value: 300, owner: Address::Charlie, asset_id: None });
+    assert_eq!(app.pending, submitted);
+    assert!(lock(&app.wallet).is_pending_spend(&bobs));
+
+    // the pending send's coin is not offered again
+    press(&mut app, "s");
+    assert_eq!(app.send.as_ref().unwrap().coins.len(), 1);
+    key(&mut app, KeyCode::Esc);
+    assert!(app.send.is_none());
+}
+
+#[test]
+fn auto_sync_follows_the_miner_and_settles_pending_sends() {
+    let mut app = synced_app();
+    press(&mut app, "s");
+    press(&mut app, "Charlie");
+    key(&mut app, KeyCode::Enter);
+    press(&mut app, "100");
+    for _ in 0..4 {
+        key(&mut app, KeyCode::Enter);
+    }
+    assert_eq!(app.pending.len(), 1);
+    let sent = app.pending[0].id();
+
+    let start = tip_height(&lock(&app.node), 0);
+    let stop = Arc::new(AtomicBool::new(false));
+    let (status, updates) = mpsc::channel();
+    let tip = lock(&app.node).best_block_at_height(start).unwrap();
+    let miner = spawn_miner(Arc::clone(&app.node), tip, vec![Address::Alice], Duration::from_millis(20), Arc::clone(&stop));
+    let syncer = spawn_auto_sync(Arc::clone(&app.wallet), Arc::clone(&app.node), Duration::from_millis(10), status, Arc::clone(&stop));
+    while !app.pending.is_empty() || app.sync.as_ref().is_none_or(|sync| sync.height < start + 2) {
+        app.on_sync(updates.recv_timeout(Duration::from_secs(10)).unwrap());
+    }
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    miner.join().unwrap();
+    syncer.join().unwrap();
+
+    let wallet = lock(&app.wallet);
+    let entry = wallet.history().iter().find(|entry| entry.tx_id == sent).unwrap();
+    assert_eq!(entry.direction, utxo_wallet::Direction::Outgoing);
+    // the miner paid Alice every block it added
+    assert_eq!(wallet.net_worth(), 2 * FUNDING - 100 + BLOCK_REWARD * (wallet.best_height() - start));
+}
+
+#[test]
+fn screen_shows_balances_and_the_send_flow() {
+    let mut app = synced_app();
+    let mut terminal = Terminal::new(TestBackend::new(100, 40)).unwrap();
+    terminal.draw(|frame| ui::draw(frame, &app)).unwrap();
+    let screen = format!("{:?}", terminal.backend().buffer());
+    assert!(screen.contains("Alice") && screen.contains("2000"), "{screen}");
+    assert!(screen.contains("waiting for the first sync"));
+
+    press(&mut app, "s");
+    press(&mut app, "Dave");
+    terminal.draw(|frame| ui::draw(frame, &app)).unwrap();
+    let screen = format!("{:?}", terminal.backend().buffer());
+    assert!(screen.contains("recipient Dave"), "{screen}");
+}
+
+#[test]
+fn send_flow_takes_the_amount_and_memo_from_a_payment_uri() {
+    let mut app = synced_app();
+    press(&mut app, "s");
+    press(&mut app, "bone:Charlie?amount=42&memo=lunch%20money");
+    key(&mut app, KeyCode::Enter);
+    let form = app.send.as_ref().unwrap();
+    assert_eq!(form.step, SendStep::Tip);
+    assert_eq!(form.payee, Some(Address::Charlie));
+    assert_eq!(form.request.as_ref().and_then(|request| request.memo.as_deref()), Some("lunch money"));
+
+    let mut terminal = Terminal::new(TestBackend::new(100, 40)).unwrap();
+    terminal.draw(|frame| ui::draw(frame, &app)).unwrap();
+    let screen = format!("{:?}", terminal.backend().buffer());
+    assert!(screen.contains("recipient Charlie") && screen.contains("lunch money"), "{screen}");
+
+    for _ in 0..3 {
+        key(&mut app, KeyCode::Enter);
+    }
+    let submitted = lock(&app.node).submitted_transactions();
+    assert_eq!(submitted[0].outputs[0], Coin { value: 42, owner: Address::Charlie, asset_id: None });
+
+    // a malformed URI is not taken for an address
+    press(&mut app, "s");
+    press(&mut app, "bone:Charlie?amount=lots");
+    key(&mut app, KeyCode::Enter);
+    let form = app.send.as_ref().unwrap();
+    assert_eq!(form.step, SendStep::Recipient);
+    assert!(form.error.as_ref().is_some_and(|error| error.contains("lots")));
+}
@@ -0,0 +1,184 @@
+//! Drawing the application: balances and sync progress on top, the history below them, the event
+//! log at the bottom, and the send flow over everything while it is open.
+
+use std::collections::HashSet;
+
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Clear, Gauge, List, ListItem, Paragraph, Row, Table, Wrap};
+use ratatui::Frame;
+
+use bonecoin_core::*;
+use utxo_wallet::Direction;
+
+use crate::app::{App, SendForm, SendStep};
+use crate::chain::lock;
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [top, history, events, help] =
+        Layout::vertical([Constraint::Length(8), Constraint::Min(6), Constraint::Length(7), Constraint::Length(1)]).areas(frame.area());
+    let [balances, sync] = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(top);
+
+    draw_balances(frame, app, balances);
+    draw_sync(frame, app, sync);
+    draw_history(frame, app, history);
+    let log: Vec<ListItem> = app.events.iter().map(|line| ListItem::new(line.as_str())).collect();
+    frame.render_widget(List::new(log).block(Block::default().borders(Borders::ALL).title(" Events ")), events);
+    let keys = match app.send {
+        Some(_) => "enter next  esc back  space pick coin  (the recipient may be a bone: payment URI)",
+        None => "s send  up/down scroll history  q quit",
+    };
+    frame.render_widget(Paragraph::new(keys).style(Style::default().fg(Color::DarkGray)), help);
+
+    if let Some(form) = &app.send {
+        draw_send(frame, form, centered(frame.area(), 60, 16));
+    }
+}
+
+fn draw_balances(frame: &mut Frame, app: &App, area: Rect) {
+    let wallet = lock(&app.wallet);
+    let mut addresses: Vec<Address> = wallet.owned_addresses().cloned().collect();
+    addresses.sort();
+    let mut rows: Vec<Row> = addresses
+        .into_iter()
+        .map(|address| {
+            let balance = wallet.total_assets_of(address.clone()).unwrap_or(0);
+            Row::new([address.to_string(), balance.to_string()])
+        })
+        .collect();
+    rows.push(Row::new(["total".to_string(), wallet.net_worth().to_string()]).style(Style::default().add_modifier(Modifier::BOLD)));
+    let table = Table::new(rows, [Constraint::Min(12), Constraint::Length(14)]).block(Block::default().borders(Borders::ALL).title(" Balances "));
+    frame.render_widget(table, area);
+}
+
+fn draw_sync(frame: &mut Frame, app: &App, area: Rect) {
+    let block = Block::default().borders(Borders::ALL).title(" Sync ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let [gauge, status] = Layout::vertical([Constraint::Length(1), Constraint::Min(1)]).areas(inner);
+
+    let Some(sync) = &app.sync else {
+        frame.render_widget(Paragraph::new("waiting for the first sync"), status);
+        return;
+    };
+    let ratio = match sync.tip {
+        0 => 1.0,
+        tip => sync.height as f64 / tip as f64,
+    };
+    let label = format!("block {} of {}", sync.height, sync.tip);
+    frame.render_widget(Gauge::default().gauge_style(Style::default().fg(Color::Green)).ratio(ratio.min(1.0)).label(label), gauge);
+    let line = match &sync.outcome {
+        Ok((0, 0)) => Line::from("up to date"),
+        Ok((applied, 0)) => Line::from(format!("applied {applied} blocks")),
+        Ok((applied, reverted)) => Line::from(format!("reorg: reverted {reverted} blocks, applied {applied}")),
+        Err(e) => Line::from(Span::styled(format!("sync failed: {e}"), Style::default().fg(Color::Red))),
+    };
+    let pending = Line::from(format!("{} sent transactions waiting to be mined", app.pending.len()));
+    frame.render_widget(Paragraph::new(vec![line, pending]).wrap(Wrap { trim: true }), status);
+}
+
+fn draw_history(frame: &mut Frame, app: &App, area: Rect) {
+    let wallet = lock(&app.wallet);
+    let owned: HashSet<&Address> = wallet.owned_addresses().collect();
+    let pending = app.pending.iter().map(|transaction| {
+        let paid: u64 = transaction.outputs.iter().filter(|coin| !owned.contains(&coin.owner)).map(|coin| coin.value).sum();
+        Row::new(["pending".to_string(), "Outgoing".to_string(), format!("-{paid}"), transaction.id().to_string()])
+            .style(Style::default().fg(Color::Yellow))
+    });
+    let mined = wallet.history().iter().rev().map(|entry| {
+        let received: u64 = entry.received.iter().map(|(_, coin)| coin.value).sum();
+        let spent: u64 = entry.spent.iter().map(|(_, coin)| coin.value).sum();
+        let net = match received >= spent {
+            true => format!("+{}", received - spent),
+            false => format!("-{}", spent - received),
+        };
+        let color = match entry.direction {
+            Direction::Incoming => Color::Green,
+            Direction::Outgoing => Color::Red,
+            _ => Color::Reset,
+        };
+        Row::new([entry.height.to_string(), format!("{:?}", entry.direction), net, entry.tx_id.to_string()]).style(Style::default().fg(color))
+    });
+    let rows: Vec<Row> = pending.chain(mined).skip(app.scroll).collect();
+    let widths = [Constraint::Length(8), Constraint::Length(14), Constraint::Length(10), Constraint::Min(16)];
+    let table = Table::new(rows, widths)
+        .header(Row::new(["height", "direction", "bones", "transaction"]).style(Style::default().add_modifier(Modifier::BOLD)))
+        .block(Block::default().borders(Borders::ALL).title(" History "));
+    frame.render_widget(table, area);
+}
+
+fn draw_send(frame: &mut Frame, form: &SendForm, area: Rect) {
+    frame.render_widget(Clear, area);
+    let block = Block::default().borders(Borders::ALL).title(" Send ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let field = |step: SendStep, name: &str, value: &str| {
+        let style = match form.step == step {
+            true => Style::default().add_modifier(Modifier::REVERSED),
+            false => Style::default(),
+        };
+        Line::from(vec![Span::raw(format!("{name:<10}")), Span::styled(value.to_string(), style)])
+    };
+    // past the first step, show the address a payment URI named rather than the URI
+    let recipient = match (&form.payee, form.step) {
+        (Some(payee), step) if step != SendStep::Recipient => payee.to_string(),
+        _ => form.recipient.clone(),
+    };
+    let mut lines = vec![
+        field(SendStep::Recipient, "recipient", &recipient),
+        field(SendStep::Amount, "amount", &form.amount),
+        field(SendStep::Tip, "tip", if form.tip.is_empty() && form.step != SendStep::Tip { "0" } else { &form.tip }),
+    ];
+    if let Some(request) = form.request.as_ref().filter(|_| form.step != SendStep::Recipient) {
+        if let Some(memo) = &request.memo {
+            lines.push(Line::from(format!("{:<10}{memo}", "memo")));
+        }
+        if let Some(invoice_id) = &request.invoice_id {
+            lines.push(Line::from(format!("{:<10}{invoice_id}", "invoice")));
+        }
+    }
+    lines.push(Line::from(""));
+    match form.step {
+        SendStep::Coins => {
+            lines.push(Line::from("pick coins to spend, or none to let the wallet choose:"));
+            // keep the cursor in view, leaving a line for an error
+            let room = (inner.height as usize).saturating_sub(lines.len() + 1).max(1);
+            let first = form.cursor.saturating_sub(room - 1);
+            for (index, (coin_id, coin)) in form.coins.iter().enumerate().skip(first).take(room) {
+                let mark = if form.selected.contains(coin_id) { "[x]" } else { "[ ]" };
+                let style = match index == form.cursor {
+                    true => Style::default().add_modifier(Modifier::REVERSED),
+                    false => Style::default(),
+                };
+                lines.push(Line::styled(format!("{mark} {:>8} {:<10} {coin_id}", coin.value, coin.owner.to_string()), style));
+            }
+        }
+        SendStep::Confirm => {
+            if let Some(transaction) = &form.transaction {
+                let inputs: Vec<CoinId> = transaction.iter_input_coin_ids().collect();
+                let consumed: u64 = form.coins.iter().filter(|(coin_id, _)| inputs.contains(coin_id)).map(|(_, coin)| coin.value).sum();
+                let produced: u64 = transaction.outputs.iter().map(|coin| coin.value).sum();
+                lines.push(Line::from(format!("spends {} coins worth {consumed}", transaction.inputs.len())));
+                for coin in &transaction.outputs {
+                    lines.push(Line::from(format!("  pays {} to {}", coin.value, coin.owner)));
+                }
+                lines.push(Line::from(format!("  burns {}", consumed.saturating_sub(produced))));
+                lines.push(Line::from("press enter to send"));
+            }
+        }
+        _ => {}
+    }
+    if let Some(error) = &form.error {
+        lines.push(Line::from(Span::styled(error.clone(), Style::default().fg(Color::Red))));
+    }
+    frame.render_widget(Paragraph::new(lines), inner);
+}
+
+/// A rectangle of the given size in the middle of `area`, shrunk to fit it.
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(area.x + (area.width - width) / 2, area.y + (area.height - height) / 2, width, height)
+}
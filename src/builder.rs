@@ -0,0 +1,291 @@
+//! Step by step construction of a wallet with non-default settings.
+//!
+//! `WalletBuilder` collects the addresses, config, and the pieces that are not plain settings
+//! (address scheme, signer, spending policy, store, coin cache), and checks them together in `build`.
+
+use std::fmt;
+use std::sync::Arc;
+
+use bonecoin_core::*;
+
+use crate::{
+    AddressScheme, ChangePolicy, CoinSelectionStrategy, CoinSpill, DustPolicy, HdKeychain, KeyScheme, SelectionConstraints, Signer,
+    SpendingPolicy, StoreError, Wallet, WalletConfig, WalletStore,
+};
+
+/// Errors that stop `WalletBuilder::build`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum BuildError {
+    /// The settings are invalid for the wallet's addresses.
+    Wallet(WalletError),
+    /// The store could not be read, or the coins could not be written to the coin cache's spill.
+    Store(StoreError),
+}
+
+impl From<WalletError> for BuildError {
+    fn from(e: WalletError) -> Self {
+        BuildError::Wallet(e)
+    }
+}
+
+impl From<StoreError> for BuildError {
+    fn from(e: StoreError) -> Self {
+        BuildError::Store(e)
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Wallet(e) => write!(f, "invalid wallet settings: {e}"),
+            BuildError::Store(e) => write!(f, "could not open the store: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Builds a `Wallet`. Everything not set keeps the default of `Wallet::new`.
+#[derive(Default)]
+pub struct WalletBuilder {
+    addresses: Vec<Address>,
+    config: WalletConfig,
+    policy: SpendingPolicy,
+    scheme: Option<Arc<dyn AddressScheme>>,
+    signer: Option<Arc<dyn Signer>>,
+    store: Option<Box<dyn WalletStore>>,
+    keychain: Option<HdKeychain>,
+    coin_cache: Option<(Arc<dyn CoinSpill>, usize)>,
+}
+
+impl WalletBuilder {
+    pub fn new() -> Self {
+        WalletBuilder::default()
+    }
+
+    /// Add an address owned by the wallet.
+    pub fn address(mut self, address: Address) -> Self {
+        self.addresses.push(address);
+        self
+    }
+
+    /// Add several addresses owned by the wallet.
+    pub fn addresses(mut self, addresses: impl IntoIterator<Item = Address>) -> Self {
+        self.addresses.extend(addresses);
+        self
+    }
+
+    /// Replace the whole config, including anything set through the other config methods so far.
+    pub fn config(mut self, config: WalletConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// The strategy used to select the inputs of automatic transactions.
+    pub fn coin_selection(mut self, strategy: CoinSelectionStrategy) -> Self {
+        self.config.selection = strategy;
+        self
+    }
+
+    /// Limits on the coins automatic transactions spend and the change they return.
+    pub fn selection_constraints(mut self, constraints: SelectionConstraints) -> Self {
+        self.config.selection_constraints = constraints;
+        self
+    }
+
+    /// Where change goes. A fixed change address must be one of the wallet's addresses.
+    pub fn change_policy(mut self, policy: ChangePolicy) -> Self {
+        self.config.change_policy = policy;
+        self
+    }
+
+    /// Treat bone change below `threshold` as dust and handle it as `policy` says, keeping the change
+    /// destination. A later `change_policy` replaces both.
+    pub fn dust_threshold(mut self, threshold: u64, policy: DustPolicy) -> Self {
+        self.config.change_policy.dust_threshold = threshold;
+        self.config.change_policy.dust_policy = policy;
+        self
+    }
+
+    /// How many blocks must be built on top of a coinbase coin before the wallet spends it.
+    pub fn coinbase_maturity(mut self, blocks: u64) -> Self {
+        self.config.coinbase_maturity = blocks;
+        self
+    }
+
+    /// The deepest reorg the wallet rolls back without resyncing from genesis.
+    pub fn max_reorg_depth(mut self, blocks: u64) -> Self {
+        self.config.undo_depth = blocks;
+        self
+    }
+
+    /// Check every synced block against the wallet's own coins, see `WalletConfig::strict_sync`.
+    pub fn strict_sync(mut self, strict: bool) -> Self {
+        self.config.strict_sync = strict;
+        self
+    }
+
+    /// Refuse to follow the node onto a chain with less work, see `WalletConfig::check_work`.
+    pub fn check_work(mut self, check: bool) -> Self {
+        self.config.check_work = check;
+        self
+    }
+
+    /// Skip the bodies of blocks that do not concern the wallet, see `WalletConfig::lazy_bodies`.
+    pub fn lazy_bodies(mut self, lazy: bool) -> Self {
+        self.config.lazy_bodies = lazy;
+        self
+    }
+
+    /// Limits on the transactions the wallet authors.
+    pub fn spending_policy(mut self, policy: SpendingPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Decides which coins the wallet owns and how it signs for them. Defaults to `KeyScheme`.
+    pub fn scheme(mut self, scheme: impl AddressScheme + 'static) -> Self {
+        self.scheme = Some(Arc::new(scheme));
+        self
+    }
+
+    /// Signs the inputs the wallet authors, e.g. on a hardware device. Defaults to `SoftwareSigner`.
+    pub fn signer(mut self, signer: impl Signer + 'static) -> Self {
+        self.signer = Some(Arc::new(signer));
+        self
+    }
+
+    /// Derive addresses from a keychain as well, see `Wallet::from_keychain`.
+    pub fn keychain(mut self, keychain: HdKeychain) -> Self {
+        self.keychain = Some(keychain);
+        self
+    }
+
+    /// Open the wallet on top of a store, see `Wallet::with_store`.
+    pub fn store(mut self, store: Box<dyn WalletStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Hold at most `max_cached_coins` coins in memory, see `Wallet::set_coin_cache`.
+    pub fn coin_cache(mut self, spill: impl CoinSpill + 'static, max_cached_coins: usize) -> Self {
+        self.coin_cache = Some((Arc::new(spill), max_cached_coins));
+        self
+    }
+
+    /// Check the settings together and create the wallet.
+    pub fn build(self) -> Result<Wallet, BuildError> {
+        let mut wallet = Wallet::with_config(self.addresses.into_iter(), self.config)?;
+        wallet.scheme = self.scheme.unwrap_or_else(|| Arc::new(KeyScheme));
+        if let Some(signer) = self.signer {
+            wallet.signer = signer;
+        }
+        wallet.set_spending_policy(self.policy);
+        if let Some(keychain) = self.keychain {
+            wallet.attach_keychain(keychain);
+        }
+        if let Some(store) = self.store {
+            wallet.attach_store(store)?;
+        }
+        if let Some((spill, max_cached_coins)) = self.coin_cache {
+            wallet.coins.attach_cache(spill, max_cached_coins)?;
+        }
+        Ok(wallet)
+    }
+}
+
+impl Wallet {
+    /// Start building a wallet with non-default settings.
+    pub fn builder() -> WalletBuilder {
+        WalletBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// Change that always goes to Bob.
+    fn change_to_bob() -> ChangePolicy {
+        ChangePolicy {
+            destination: ChangeDestination::Fixed(Address::Bob),
+            dust_threshold: 0,
+            dust_policy: DustPolicy::CreateChange,
+        }
+    }
+
+    /// A wallet of Alice and Bob built with every setting changed from its default, and a node
+    /// of three blocks, the first of which rewards Alice. Returns the node, the wallet, not synced
+    /// yet, and the reward.
+    fn built() -> (MockNode, Wallet, CoinId) {
+        let mut node = MockNode::new();
+        let b1_id = node.add_block_with_coinbase(Block::genesis().id(), Address::Alice, vec![]);
+        node.set_best(b1_id);
+        let reward = node.entire_block(&b1_id).unwrap().body[0].coin_id(1, 0);
+        let b2_id = node.add_block_as_best(b1_id, vec![]);
+        node.add_block_as_best(b2_id, vec![]);
+
+        let mut store = MemoryStore::new();
+        store.commit(&StoreBatch::default(), (0, Block::genesis().id())).unwrap();
+        let wallet = Wallet::builder()
+            .address(Address::Alice)
+            .addresses([Address::Bob])
+            .coin_selection(CoinSelectionStrategy::PrivacyPreserving)
+            .change_policy(change_to_bob())
+            .coinbase_maturity(2)
+            .max_reorg_depth(5)
+            .spending_policy(SpendingPolicy {
+                max_per_transaction: Some(30),
+                ..SpendingPolicy::default()
+            })
+            .store(Box::new(store))
+            .build()
+            .unwrap();
+        (node, wallet, reward)
+    }
+
+    #[test]
+    fn the_selection_change_and_reorg_settings_are_applied() {
+        let (_, wallet, _) = built();
+        assert_eq!(wallet.coin_selection(), CoinSelectionStrategy::PrivacyPreserving);
+        assert_eq!(wallet.change_policy(), &change_to_bob());
+        assert_eq!(wallet.config().undo_depth, 5);
+    }
+
+    #[test]
+    fn the_coinbase_maturity_is_applied() {
+        let (node, mut wallet, reward) = built();
+        wallet.sync(&node);
+        assert!(wallet.is_mature(&reward));
+    }
+
+    #[test]
+    fn the_spending_policy_is_applied() {
+        let (node, mut wallet, _) = built();
+        wallet.sync(&node);
+        assert_eq!(wallet.create_automatic_transaction(Address::Eve, 40, 0), Err(WalletError::PolicyViolation));
+        let payment = wallet.create_automatic_transaction(Address::Eve, 10, 0).unwrap();
+        assert_eq!(payment.outputs[1].owner, Address::Bob);
+    }
+
+    #[test]
+    fn the_store_is_applied() {
+        let (node, mut wallet, _) = built();
+        wallet.sync(&node);
+        assert_eq!(wallet.take_store().unwrap().unwrap().position(), Ok(Some((3, wallet.best_hash()))));
+    }
+
+    #[test]
+    fn change_to_a_foreign_address_is_refused() {
+        let foreign_change = Wallet::builder().address(Address::Alice).change_policy(change_to_bob()).build();
+        assert_eq!(foreign_change.err(), Some(BuildError::Wallet(WalletError::ForeignAddress(Address::Bob))));
+    }
+
+    #[test]
+    fn the_default_config_builds_the_same_wallet_as_new() {
+        assert_eq!(
+            Wallet::with_config(vec![Address::Bob].into_iter(), WalletConfig::default()).unwrap().config(),
+            Wallet::new(vec![Address::Bob].into_iter()).config()
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! Where the wallet sends the change of the transactions it builds.
+//!
+//! Every transaction builder asks `change_output` for its change coin, so the policy applies
+//! to automatic payments, withdrawal batches, asset transfers, and everything built on top of them
+//! alike. Bone change below the policy's dust threshold is not worth a coin of its own, and the
+//! dust policy decides where it goes instead.
+
+use std::sync::atomic::Ordering;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// Which owned address receives change.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub enum ChangeDestination {
+    /// The wallet's smallest owned address.
+    #[default]
+    Default,
+    /// Always this owned address.
+    Fixed(Address),
+    /// Cycle through the owned addresses in order, one per transaction built.
+    RoundRobin,
+    /// An owned address that has never received a coin nor been handed out by `next_receive_address`.
+    /// The keychain's derived addresses come first, in derivation order, so a wallet restored from
+    /// the seed finds the change. Building a transaction fails with `NoFreshAddress` when none is left.
+    Fresh,
+}
+
+/// What happens to bone change below the dust threshold.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum DustPolicy {
+    /// Burn it as part of the tip.
+    #[default]
+    AddToTip,
+    /// Pay it to the recipient on top of the payment: to the first payment of a withdrawal batch,
+    /// and to the tip where there is no bone payment to add it to or the amount paid must be exact,
+    /// like the funding of an escrow, channel, or HTLC.
+    AddToPayment,
+    /// Return it as a change coin anyway.
+    CreateChange,
+}
+
+/// How the wallet handles change. The default policy sends all change to the smallest owned address.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct ChangePolicy {
+    /// Which owned address receives change.
+    pub destination: ChangeDestination,
+    /// Bone change below this value is dust, handled as `dust_policy` says instead of creating a coin.
+    pub dust_threshold: u64,
+    /// What happens to dust change.
+    pub dust_policy: DustPolicy,
+}
+
+impl Wallet {
+    /// Replace the wallet's change policy. A fixed change address must be owned by the wallet.
+    pub fn set_change_policy(&mut self, policy: ChangePolicy) -> WalletResult<()> {
+        if let ChangeDestination::Fixed(address) = &policy.destination {
+            if !self.addresses.contains(address) {
+                return Err(WalletError::ForeignAddress(address.clone()));
+            }
+        }
+        self.config.change_policy = policy;
+        Ok(())
+    }
+
+    /// The wallet's current change policy.
+    pub fn change_policy(&self) -> &ChangePolicy {
+        &self.config.change_policy
+    }
+
+    /// Treat bone change below `threshold` as dust and handle it as `policy` says, keeping the
+    /// change destination.
+    pub fn set_dust_threshold(&mut self, threshold: u64, policy: DustPolicy) {
+        self.config.change_policy.dust_threshold = threshold;
+        self.config.change_policy.dust_policy = policy;
+    }
+
+    /// The change coin for `value` leftover units of an asset (`None` for bones), or `None` when there is
+    /// no change or the bones are dust the dust policy puts elsewhere.
+    pub(crate) fn change_output(&self, value: u64, asset_id: Option<AssetId>) -> WalletResult<Option<Coin>> {
+        if self.burns_change(value, asset_id) {
+            return Ok(None);
+        }
+        Ok(Some(Coin {
+            value,
+            owner: self.change_address()?,
+            asset_id,
+        }))
+    }
+
+    /// Like `change_output`, but paying the change to `owner` whatever the policy's destination.
+    pub(crate) fn change_output_to(&self, value: u64, asset_id: Option<AssetId>, owner: &Address) -> Option<Coin> {
+        (!self.burns_change(value, asset_id)).then(|| Coin {
+            value,
+            owner: owner.clone(),
+            asset_id,
+        })
+    }
+
+    /// The part of `value` leftover units the dust policy adds to the payment: all of them if they
+    /// are dust bones and the policy says so, otherwise none. `change_output` leaves them out either way.
+    pub(crate) fn dust_for_payment(&self, value: u64, asset_id: Option<AssetId>) -> u64 {
+        match self.config.change_policy.dust_policy {
+            DustPolicy::AddToPayment if self.is_dust(value, asset_id) => value,
+            _ => 0,
+        }
+    }
+
+    /// Whether `value` leftover units are bones below the dust threshold.
+    pub(crate) fn is_dust(&self, value: u64, asset_id: Option<AssetId>) -> bool {
+        value > 0 && asset_id.is_none() && value < self.config.change_policy.dust_threshold
+    }
+
+    /// Whether there is no change in `value` leftover units, or they are dust the policy keeps out of change.
+    fn burns_change(&self, value: u64, asset_id: Option<AssetId>) -> bool {
+        value == 0 || (self.is_dust(value, asset_id) && self.config.change_policy.dust_policy != DustPolicy::CreateChange)
+    }
+
+    /// The wallet's smallest owned address, used wherever the wallet needs an address of its own
+    /// and nothing more specific applies.
+    pub(crate) fn primary_address(&self) -> WalletResult<Address> {
+        self.addresses.iter().min().cloned().ok_or(WalletError::NoOwnedAddresses)
+    }
+
+    /// The owned address that should receive the next change coin. Unless the policy fixes one, it
+    /// is not a receive-only address while the wallet has others.
+    fn change_address(&self) -> WalletResult<Address> {
+        let mut owned: Vec<&Address> = self.addresses.iter().filter(|address| !self.receive_only.contains(*address)).collect();
+        if owned.is_empty() {
+            owned = self.addresses.iter().collect();
+        }
+        owned.sort();
+        let default = owned.first().copied().ok_or(WalletError::NoOwnedAddresses)?;
+
+        let address = match &self.config.change_policy.destination {
+            ChangeDestination::Default => default,
+            ChangeDestination::Fixed(address) => address,
+            ChangeDestination::RoundRobin => {
+                let turn = self.change_cursor.fetch_add(1, Ordering::Relaxed);
+                owned[turn % owned.len()]
+            }
+            ChangeDestination::Fresh => {
+                let fresh = |address: &&Address| !self.is_address_used(address) && !self.receiving.issued.contains(*address);
+                let derived = self.derived_addresses().iter().filter(|address| owned.contains(address));
+                derived.chain(owned.iter().copied()).find(fresh).ok_or(WalletError::NoFreshAddress)?
+            }
+        };
+        Ok(address.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    fn change_of(tx: &Transaction) -> Option<Address> {
+        tx.outputs.get(1).map(|coin| coin.owner.clone())
+    }
+
+    /// The one-block wallet with its change sent as `destination` says.
+    fn wallet_with_change_to(destination: ChangeDestination) -> Wallet {
+        let (_, mut wallet) = make_one_block_blockchain();
+        wallet
+            .set_change_policy(ChangePolicy {
+                destination,
+                ..Default::default()
+            })
+            .unwrap();
+        wallet
+    }
+
+    #[test]
+    fn change_goes_to_the_first_address_by_default() {
+        let (_, wallet) = make_one_block_blockchain();
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 1, 0).unwrap();
+        assert_eq!(change_of(&tx), Some(Address::Alice));
+    }
+
+    #[test]
+    fn fixed_change_addresses_must_belong_to_the_wallet() {
+        let (_, mut wallet) = make_one_block_blockchain();
+        assert_eq!(
+            wallet.set_change_policy(ChangePolicy {
+                destination: ChangeDestination::Fixed(Address::Eve),
+                ..Default::default()
+            }),
+            Err(WalletError::ForeignAddress(Address::Eve))
+        );
+    }
+
+    #[test]
+    fn fixed_change_goes_to_that_address() {
+        let wallet = wallet_with_change_to(ChangeDestination::Fixed(Address::Bob));
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 1, 0).unwrap();
+        assert_eq!(change_of(&tx), Some(Address::Bob));
+    }
+
+    #[test]
+    fn round_robin_change_rotates_through_the_addresses() {
+        let wallet = wallet_with_change_to(ChangeDestination::RoundRobin);
+        let owners: Vec<_> = (0..3)
+            .map(|_| change_of(&wallet.create_automatic_transaction(Address::Charlie, 1, 0).unwrap()))
+            .collect();
+        assert_eq!(owners, vec![Some(Address::Alice), Some(Address::Bob), Some(Address::Alice)]);
+    }
+
+    #[test]
+    fn fresh_change_goes_to_an_address_that_never_held_a_coin() {
+        let (node, _) = make_one_block_blockchain();
+        let mut wallet = Wallet::new(vec![Address::Alice, Address::Bob, Address::Dave].into_iter());
+        wallet.sync(&node);
+        wallet
+            .set_change_policy(ChangePolicy {
+                destination: ChangeDestination::Fresh,
+                ..Default::default()
+            })
+            .unwrap();
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 1, 0).unwrap();
+        assert_eq!(change_of(&tx), Some(Address::Dave));
+    }
+
+    #[test]
+    fn change_below_the_dust_threshold_is_added_to_the_tip() {
+        let (_, mut wallet) = make_one_block_blockchain();
+        wallet
+            .set_change_policy(ChangePolicy {
+                destination: ChangeDestination::Default,
+                dust_threshold: 1_000,
+                dust_policy: DustPolicy::AddToTip,
+            })
+            .unwrap();
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 1, 0).unwrap();
+        assert_eq!(tx.outputs.len(), 1);
+    }
+
+    #[test]
+    fn the_change_policy_survives_export() {
+        let (_, mut wallet) = make_one_block_blockchain();
+        wallet
+            .set_change_policy(ChangePolicy {
+                destination: ChangeDestination::Fixed(Address::Bob),
+                dust_threshold: 1_000,
+                dust_policy: DustPolicy::AddToTip,
+            })
+            .unwrap();
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.change_policy(), wallet.change_policy());
+    }
+
+    #[test]
+    fn fresh_change_skips_addresses_handed_out_for_receiving() {
+        let (node, _) = make_one_block_blockchain();
+        let mut wallet = Wallet::new(vec![Address::Alice, Address::Bob, Address::Dave, Address::Eve].into_iter());
+        wallet.sync(&node);
+        wallet.add_receive_addresses([Address::Dave]).unwrap();
+        assert_eq!(wallet.next_receive_address(), Some(Address::Dave));
+        wallet
+            .set_change_policy(ChangePolicy {
+                destination: ChangeDestination::Fresh,
+                ..Default::default()
+            })
+            .unwrap();
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 1, 0).unwrap();
+        assert_eq!(change_of(&tx), Some(Address::Eve));
+    }
+
+    #[test]
+    fn fresh_change_is_refused_rather_than_sent_to_a_used_address() {
+        let wallet = wallet_with_change_to(ChangeDestination::Fresh);
+        assert_eq!(
+            wallet.create_automatic_transaction(Address::Charlie, 1, 0),
+            Err(WalletError::NoFreshAddress)
+        );
+    }
+
+    #[test]
+    fn fresh_change_takes_the_next_derived_address_of_the_keychain() {
+        let keychain = HdKeychain::new(7);
+        let mut node = MockNode::new();
+        node.add_block_as_best(Block::genesis().id(), vec![mint([(keychain.derive(0), 100)])]);
+        let mut wallet = Wallet::from_keychain(keychain);
+        wallet.sync(&node);
+        wallet
+            .set_change_policy(ChangePolicy {
+                destination: ChangeDestination::Fresh,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 1, 0).unwrap();
+        assert_eq!(change_of(&tx), Some(keychain.derive(1)));
+        // an address issued for receiving is skipped
+        assert_eq!(wallet.next_receive_address(), Some(keychain.derive(1)));
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 1, 0).unwrap();
+        assert_eq!(change_of(&tx), Some(keychain.derive(2)));
+    }
+
+    /// A wallet of Alice holding one 100 bone coin, treating change under 10 bones as `policy`
+    /// says.
+    fn alice_with_dust_policy(policy: DustPolicy) -> Wallet {
+        let mut node = MockNode::new();
+        node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 100)])]);
+        let mut wallet = Wallet::builder().address(Address::Alice).dust_threshold(10, policy).build().unwrap();
+        wallet.sync(&node);
+        wallet
+    }
+
+    fn values(tx: &Transaction) -> Vec<u64> {
+        tx.outputs.iter().map(|coin| coin.value).collect()
+    }
+
+    #[test]
+    fn dust_is_added_to_the_payment() {
+        let wallet = alice_with_dust_policy(DustPolicy::AddToPayment);
+
+        let tx = wallet.create_automatic_transaction(Address::Bob, 94, 1).unwrap();
+        assert_eq!(tx.outputs, vec![Coin { value: 99, owner: Address::Bob, asset_id: None }]);
+        let plan = wallet.plan_automatic_transaction(Address::Bob, 94, 1).unwrap();
+        assert_eq!((plan.payment_amount, plan.effective_tip, plan.change), (99, 1, None));
+        assert_eq!(plan.dust, Some((5, DustPolicy::AddToPayment)));
+    }
+
+    #[test]
+    fn escrows_are_funded_with_exactly_their_amount() {
+        let wallet = alice_with_dust_policy(DustPolicy::AddToPayment);
+
+        // the dust goes to the tip instead
+        let escrow = wallet.create_escrow(Address::Alice, Address::Bob, Address::Charlie, 95).unwrap();
+        assert_eq!(values(&escrow.funding), [95]);
+    }
+
+    #[test]
+    fn dust_is_added_to_the_tip() {
+        let mut wallet = alice_with_dust_policy(DustPolicy::AddToPayment);
+        wallet.set_dust_threshold(10, DustPolicy::AddToTip);
+
+        let plan = wallet.plan_automatic_transaction(Address::Bob, 94, 1).unwrap();
+        assert_eq!((plan.payment_amount, plan.effective_tip, plan.change), (94, 6, None));
+        assert_eq!(plan.dust, Some((5, DustPolicy::AddToTip)));
+    }
+
+    #[test]
+    fn dust_is_kept_as_change() {
+        let wallet = alice_with_dust_policy(DustPolicy::CreateChange);
+
+        let tx = wallet.create_automatic_transaction(Address::Bob, 94, 1).unwrap();
+        assert_eq!(tx.outputs[1], Coin { value: 5, owner: Address::Alice, asset_id: None });
+        let plan = wallet.plan_automatic_transaction(Address::Bob, 94, 1).unwrap();
+        assert_eq!((plan.effective_tip, plan.dust), (1, Some((5, DustPolicy::CreateChange))));
+    }
+
+    #[test]
+    fn change_at_the_threshold_is_not_dust() {
+        let wallet = alice_with_dust_policy(DustPolicy::CreateChange);
+
+        assert_eq!(wallet.plan_automatic_transaction(Address::Bob, 89, 1).unwrap().dust, None);
+    }
+
+    #[test]
+    fn the_dust_policy_survives_export() {
+        let wallet = alice_with_dust_policy(DustPolicy::CreateChange);
+
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.change_policy(), wallet.change_policy());
+    }
+
+    #[test]
+    fn withdrawal_batches_add_the_dust_to_their_oldest_request() {
+        let mut wallet = alice_with_dust_policy(DustPolicy::AddToPayment);
+
+        wallet.queue_withdrawal(Address::Bob, 50).unwrap();
+        wallet.queue_withdrawal(Address::Charlie, 44).unwrap();
+        let batches = wallet.flush_withdrawals(10, 1).unwrap();
+        assert_eq!(values(&batches[0]), [55, 44]);
+    }
+}
@@ -0,0 +1,380 @@
+//! Two-party payment channels.
+//!
+//! The opener funds a 2-of-2 multisig coin owned by both parties. Once the funding is mined, the
+//! parties pay each other off-chain by exchanging `ChannelState`s: each state carries a balance
+//! transaction splitting the channel coin, and becomes binding once both parties have signed it.
+//! A party only signs a state that raises its own balance, unless it proposed that state itself.
+//! Either party can close the channel by finalizing the latest state both have signed.
+//!
+//! Coin ids depend on the height of the block that created them, so no state can be signed before
+//! the funding transaction is mined. Until the first payment, the opener relies on the acceptor to
+//! cooperate in order to get the funds back.
+
+use std::fmt;
+
+use bonecoin_core::*;
+
+use crate::{PartialTransaction, Wallet};
+
+/// Errors that can occur while operating a payment channel.
+#[derive(Eq, PartialEq, Debug)]
+pub enum ChannelError {
+    /// The wallet cannot perform the on-chain part of the operation.
+    Wallet(WalletError),
+    /// The wallet does not track a channel with this id.
+    UnknownChannel,
+    /// The funding transaction has not been confirmed with `confirm_channel` yet.
+    NotFunded,
+    /// The paying party's balance is too small for the payment.
+    InsufficientBalance,
+    /// The state is not a valid successor of the channel's latest state.
+    InvalidState,
+    /// The parties have not agreed on any state yet, so there is nothing to close with.
+    NoAgreedState,
+}
+
+impl From<WalletError> for ChannelError {
+    fn from(e: WalletError) -> Self {
+        ChannelError::Wallet(e)
+    }
+}
+
+impl fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelError::Wallet(e) => write!(f, "{e}"),
+            ChannelError::UnknownChannel => write!(f, "the wallet does not track this channel"),
+            ChannelError::NotFunded => write!(f, "the channel funding has not been confirmed"),
+            ChannelError::InsufficientBalance => write!(f, "the paying party's channel balance is too small"),
+            ChannelError::InvalidState => write!(f, "the state does not follow the channel's latest state"),
+            ChannelError::NoAgreedState => write!(f, "the parties have not agreed on any channel state"),
+        }
+    }
+}
+
+impl std::error::Error for ChannelError {}
+
+/// A payment channel between an opener and an acceptor. The id is the funding transaction's id.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Channel {
+    /// The id of the funding transaction, which identifies the channel.
+    pub id: TransactionId,
+    /// The party that funded the channel.
+    pub opener: Address,
+    /// The party that accepted the channel.
+    pub acceptor: Address,
+    /// The number of bones locked in the channel.
+    pub capacity: u64,
+    /// The transaction creating the channel coin as its first output.
+    pub funding: Transaction,
+    /// The height at which the funding transaction was mined, once known.
+    pub funded_at: Option<u64>,
+    /// The latest state signed by both parties.
+    pub latest: Option<ChannelState>,
+    /// A state this wallet proposed that the other party has not signed yet.
+    pub proposed: Option<ChannelState>,
+}
+
+/// A balance split of a channel, exchanged between the parties off-chain.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ChannelState {
+    /// The channel this state belongs to.
+    pub channel_id: TransactionId,
+    /// Increases by one with every payment.
+    pub sequence: u64,
+    /// The bones the opener gets when the channel closes in this state.
+    pub opener_balance: u64,
+    /// The bones the acceptor gets when the channel closes in this state.
+    pub acceptor_balance: u64,
+    /// The balance transaction, with the signatures collected so far.
+    pub transaction: PartialTransaction,
+}
+
+impl Channel {
+    /// The 2-of-2 multisig address holding the channel coin.
+    pub fn address(&self) -> Address {
+        Address::multisig(2, [self.opener.clone(), self.acceptor.clone()])
+    }
+
+    /// The balance of `party` in the latest agreed state. Before any payment, the opener holds everything.
+    pub fn balance_of(&self, party: &Address) -> u64 {
+        let (opener_balance, acceptor_balance) = match &self.latest {
+            Some(state) => (state.opener_balance, state.acceptor_balance),
+            None => (self.capacity, 0),
+        };
+        if *party == self.opener {
+            opener_balance
+        } else if *party == self.acceptor {
+            acceptor_balance
+        } else {
+            0
+        }
+    }
+
+    /// The unsigned state with the given balances, following the latest agreed state.
+    fn state(&self, funded_at: u64, opener_balance: u64, acceptor_balance: u64) -> ChannelState {
+        let mut outputs = Vec::new();
+        for (owner, value) in [(&self.opener, opener_balance), (&self.acceptor, acceptor_balance)] {
+            if value > 0 {
+                outputs.push(Coin {
+                    value,
+                    owner: owner.clone(),
+                    asset_id: None,
+                });
+            }
+        }
+        let transaction = Transaction {
+            version: TRANSACTION_VERSION,
+            inputs: vec![Input {
+                coin_id: self.funding.coin_id(funded_at, 0),
+                signature: Signature::Invalid,
+            }],
+            outputs,
+        };
+        let coin = Coin {
+            value: self.capacity,
+            owner: self.address(),
+            asset_id: None,
+        };
+        ChannelState {
+            channel_id: self.id,
+            sequence: self.latest.as_ref().map_or(1, |state| state.sequence + 1),
+            opener_balance,
+            acceptor_balance,
+            transaction: PartialTransaction::with_input_coins(transaction, vec![coin]),
+        }
+    }
+}
+
+impl Wallet {
+    /// Fund a channel of `capacity` bones from this wallet, which must own the opener address.
+    /// The returned channel is tracked by this wallet and must be handed to the acceptor.
+    pub fn open_channel(&mut self, opener: Address, acceptor: Address, capacity: u64) -> Result<Channel, ChannelError> {
+        if !self.addresses.contains(&opener) {
+            return Err(WalletError::ForeignAddress(opener).into());
+        }
+        let address = Address::multisig(2, [opener.clone(), acceptor.clone()]);
+        let funding = self.build_funding_transaction(address, capacity)?;
+        self.check_policy(&funding)?;
+        let channel = Channel {
+            id: funding.id(),
+            opener,
+            acceptor,
+            capacity,
+            funding,
+            funded_at: None,
+            latest: None,
+            proposed: None,
+        };
+        self.channels.insert(channel.id, channel.clone());
+        Ok(channel)
+    }
+
+    /// Start tracking a channel opened by another wallet. This wallet must own the acceptor address.
+    pub fn accept_channel(&mut self, channel: Channel) -> Result<(), ChannelError> {
+        if !self.addresses.contains(&channel.acceptor) {
+            return Err(WalletError::ForeignAddress(channel.acceptor).into());
+        }
+        let funds_channel = channel.funding.outputs.first().is_some_and(|coin| {
+            coin.value == channel.capacity && coin.owner == channel.address() && coin.is_native()
+        });
+        if channel.id != channel.funding.id() || !funds_channel || channel.latest.is_some() {
+            return Err(ChannelError::InvalidState);
+        }
+        self.channels.insert(channel.id, Channel { proposed: None, ..channel });
+        Ok(())
+    }
+
+    /// Record the height at which the channel's funding transaction was mined.
+    pub fn confirm_channel(&mut self, id: &TransactionId, funded_at: u64) -> Result<(), ChannelError> {
+        let channel = self.channels.get_mut(id).ok_or(ChannelError::UnknownChannel)?;
+        channel.funded_at = Some(funded_at);
+        Ok(())
+    }
+
+    /// Propose a state paying `amount` bones to the other party, signed by this wallet.
+    /// The state must be sent to the other party, whose countersigned copy is then passed to `update_channel`.
+    pub fn pay_channel(&mut self, id: &TransactionId, amount: u64) -> Result<ChannelState, ChannelError> {
+        let channel = self.channels.get(id).ok_or(ChannelError::UnknownChannel)?;
+        let funded_at = channel.funded_at.ok_or(ChannelError::NotFunded)?;
+        if amount == 0 {
+            return Err(WalletError::ZeroCoinValue.into());
+        }
+        let (payer, payee) = if self.addresses.contains(&channel.opener) {
+            (&channel.opener, &channel.acceptor)
+        } else {
+            (&channel.acceptor, &channel.opener)
+        };
+        let payer_balance = channel.balance_of(payer).checked_sub(amount).ok_or(ChannelError::InsufficientBalance)?;
+        let payee_balance = channel.balance_of(payee) + amount;
+        let mut state = if *payer == channel.opener {
+            channel.state(funded_at, payer_balance, payee_balance)
+        } else {
+            channel.state(funded_at, payee_balance, payer_balance)
+        };
+
+        self.sign_partial(&mut state.transaction);
+        self.channels.get_mut(id).expect("looked up above").proposed = Some(state.clone());
+        Ok(state)
+    }
+
+    /// Process a state received from the other party.
+    ///
+    /// A new state paying this wallet is countersigned, becomes the latest state, and is returned so
+    /// it can be sent back. The countersigned copy of a state this wallet proposed becomes the
+    /// latest state as is. Any other state is rejected.
+    pub fn update_channel(&mut self, state: ChannelState) -> Result<ChannelState, ChannelError> {
+        let channel = self.channels.get(&state.channel_id).ok_or(ChannelError::UnknownChannel)?;
+        let funded_at = channel.funded_at.ok_or(ChannelError::NotFunded)?;
+
+        let is_own_proposal = channel
+            .proposed
+            .as_ref()
+            .is_some_and(|proposed| proposed.transaction.unsigned() == state.transaction.unsigned());
+        let mut state = if is_own_proposal {
+            if !state.transaction.is_complete() {
+                return Err(ChannelError::InvalidState);
+            }
+            state
+        } else {
+            let me = if self.addresses.contains(&channel.opener) { &channel.opener } else { &channel.acceptor };
+            let expected = channel.state(funded_at, state.opener_balance, state.acceptor_balance);
+            let mine = if *me == channel.opener { state.opener_balance } else { state.acceptor_balance };
+            let valid = state.opener_balance.checked_add(state.acceptor_balance) == Some(channel.capacity)
+                && state.sequence == expected.sequence
+                && state.transaction.unsigned() == expected.transaction.unsigned()
+                && mine > channel.balance_of(me);
+            if !valid {
+                return Err(ChannelError::InvalidState);
+            }
+            state
+        };
+
+        self.sign_partial(&mut state.transaction);
+        let channel = self.channels.get_mut(&state.channel_id).expect("looked up above");
+        channel.latest = Some(state.clone());
+        channel.proposed = None;
+        Ok(state)
+    }
+
+    /// Close the channel cooperatively, returning the fully signed balance transaction of the latest state.
+    /// The wallet stops tracking the channel.
+    pub fn close_channel(&mut self, id: &TransactionId) -> Result<Transaction, ChannelError> {
+        let channel = self.channels.get(id).ok_or(ChannelError::UnknownChannel)?;
+        let latest = channel.latest.clone().ok_or(ChannelError::NoAgreedState)?;
+        let transaction = latest.transaction.finalize().ok_or(ChannelError::NoAgreedState)?;
+        self.channels.remove(id);
+        Ok(transaction)
+    }
+
+    /// The channel with the given id, if the wallet tracks it.
+    pub fn channel(&self, id: &TransactionId) -> Option<&Channel> {
+        self.channels.get(id)
+    }
+
+    /// The total bones this wallet holds across its open channels, per the latest agreed states.
+    pub fn channel_balance(&self) -> u64 {
+        self.channels
+            .values()
+            .map(|channel| {
+                [&channel.opener, &channel.acceptor]
+                    .into_iter()
+                    .filter(|party| self.addresses.contains(party))
+                    .map(|party| channel.balance_of(party))
+                    .sum::<u64>()
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// Alice opens a channel to Bob with 80 of her 100 bones and Bob accepts it, but the funding
+    /// is not mined yet. Returns the node, both wallets, and the channel.
+    fn opened_channel() -> (MockNode, Wallet, Wallet, Channel) {
+        let mut node = MockNode::new();
+        node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 100)])]);
+        let mut alice = wallet_with_alice();
+        let mut bob = Wallet::new(vec![Address::Bob].into_iter());
+        alice.sync(&node);
+
+        let channel = alice.open_channel(Address::Alice, Address::Bob, 80).unwrap();
+        bob.accept_channel(channel.clone()).unwrap();
+        (node, alice, bob, channel)
+    }
+
+    /// The opened channel with its funding mined in block 2 and confirmed by both sides.
+    fn funded_channel() -> (MockNode, Wallet, Wallet, TransactionId) {
+        let (mut node, mut alice, mut bob, channel) = opened_channel();
+        node.add_block_as_best(node.best_block_at_height(1).unwrap(), vec![channel.funding.clone()]);
+        alice.sync(&node);
+        alice.confirm_channel(&channel.id, 2).unwrap();
+        bob.confirm_channel(&channel.id, 2).unwrap();
+        (node, alice, bob, channel.id)
+    }
+
+    /// Alice pays Bob 30 over the funded channel, then Bob pays 5 back.
+    fn pay_back_and_forth(alice: &mut Wallet, bob: &mut Wallet, id: &TransactionId) {
+        let proposal = alice.pay_channel(id, 30).unwrap();
+        let signed = bob.update_channel(proposal).unwrap();
+        alice.update_channel(signed).unwrap();
+        let proposal = bob.pay_channel(id, 5).unwrap();
+        let signed = alice.update_channel(proposal).unwrap();
+        bob.update_channel(signed).unwrap();
+    }
+
+    #[test]
+    fn channels_take_payments_only_once_funded() {
+        let (_, mut alice, _, channel) = opened_channel();
+        assert_eq!(alice.pay_channel(&channel.id, 10), Err(ChannelError::NotFunded));
+    }
+
+    #[test]
+    fn the_funding_moves_bones_into_the_channel() {
+        let (_, alice, _, _) = funded_channel();
+        assert_eq!(alice.net_worth(), 20);
+        assert_eq!(alice.channel_balance(), 80);
+    }
+
+    #[test]
+    fn payments_move_the_balance_both_ways() {
+        let (_, mut alice, mut bob, id) = funded_channel();
+        pay_back_and_forth(&mut alice, &mut bob, &id);
+        assert_eq!(alice.channel_balance(), 55);
+        assert_eq!(bob.channel_balance(), 25);
+    }
+
+    #[test]
+    fn replayed_states_are_never_signed() {
+        let (_, mut alice, mut bob, id) = funded_channel();
+        pay_back_and_forth(&mut alice, &mut bob, &id);
+
+        // the replayed state does not pay bob anything new
+        let replayed = alice.channel(&id).unwrap().latest.clone().unwrap();
+        assert_eq!(bob.update_channel(replayed), Err(ChannelError::InvalidState));
+    }
+
+    #[test]
+    fn payments_cannot_exceed_the_channel_balance() {
+        let (_, mut alice, mut bob, id) = funded_channel();
+        pay_back_and_forth(&mut alice, &mut bob, &id);
+        assert_eq!(bob.pay_channel(&id, 26), Err(ChannelError::InsufficientBalance));
+    }
+
+    #[test]
+    fn closing_pays_out_the_latest_state_even_after_a_snapshot_round_trip() {
+        let (mut node, mut alice, mut bob, id) = funded_channel();
+        pay_back_and_forth(&mut alice, &mut bob, &id);
+
+        let mut alice = Wallet::import_state(&alice.export_state()).unwrap();
+        let close = alice.close_channel(&id).unwrap();
+        assert_eq!(alice.channel(&id), None);
+        node.add_block_as_best(node.best_block_at_height(2).unwrap(), vec![close]);
+        alice.sync(&node);
+        bob.sync(&node);
+        assert_eq!(alice.net_worth(), 75);
+        assert_eq!(bob.net_worth(), 25);
+    }
+}
@@ -0,0 +1,519 @@
+//! The wallet's unspent coins, with an ordered index by value.
+//!
+//! `CoinStore` offers the parts of the `HashMap` interface the wallet uses and keeps the value
+//! index in step with every change, so value range queries and sorted listings never have to
+//! sort the whole coin set. When the wallet has a `WalletStore`, the changes are also staged
+//! until the wallet commits them. With a coin cache, only the most recently used coins are held in
+//! memory and the rest are faulted in from the `CoinSpill` when they are read.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Index;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use bonecoin_core::*;
+
+use crate::{CoinCacheStats, CoinSpill, StoreBatch, StoreError, Wallet};
+
+/// Unspent coins keyed by id, indexed by asset and value.
+#[derive(Clone, Default)]
+pub(crate) struct CoinStore {
+    coins: HashMap<CoinId, Slot>,
+    by_value: BTreeMap<(Option<AssetId>, u64), BTreeSet<CoinId>>,
+    staged: Option<StoreBatch>, // changes not committed to the wallet's store yet, if it has one
+    shared: OnceLock<Arc<BTreeMap<CoinId, Coin>>>, // copy handed to snapshots, until the next change
+    cache: Option<CoinCache>, // bound on the coins held in memory, if the wallet has a coin cache
+}
+
+/// A coin of the store. Without a coin cache the coin is always held; with one, only while it is cached.
+struct Slot {
+    key: (Option<AssetId>, u64), // the coin's asset and value, for the value index
+    coin: OnceLock<Coin>,
+    spilled: bool, // whether the spill holds the coin, so it may be evicted
+    used: AtomicU64, // when the coin was last read, 0 while it is not cached
+}
+
+impl Clone for Slot {
+    fn clone(&self) -> Self {
+        Slot {
+            key: self.key,
+            coin: self.coin.clone(),
+            spilled: self.spilled,
+            used: AtomicU64::new(self.used.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// The most recently used coins of a store, with every coin written through to a spill.
+struct CoinCache {
+    spill: Arc<dyn CoinSpill>,
+    capacity: usize,
+    clock: AtomicU64,
+    recent: Mutex<BTreeMap<u64, CoinId>>, // the cached coins by when they were last read, oldest first
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Clone for CoinCache {
+    fn clone(&self) -> Self {
+        CoinCache {
+            spill: self.spill.clone(),
+            capacity: self.capacity,
+            clock: AtomicU64::new(self.clock.load(Ordering::Relaxed)),
+            recent: Mutex::new(self.recent().clone()),
+            hits: AtomicU64::new(self.hits.load(Ordering::Relaxed)),
+            misses: AtomicU64::new(self.misses.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl CoinCache {
+    /// Mark the coin as read just now.
+    fn touch(&self, coin_id: CoinId, slot: &Slot) {
+        let mut recent = self.recent();
+        let now = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        recent.remove(&slot.used.load(Ordering::Relaxed));
+        recent.insert(now, coin_id);
+        slot.used.store(now, Ordering::Relaxed);
+    }
+
+    /// An evicted coin. Only coins the spill was seen to hand back are evicted, so one it cannot hand
+    /// back any more breaks the spill's contract.
+    fn read_back(&self, coin_id: &CoinId) -> Coin {
+        match self.spill.get(coin_id) {
+            Ok(Some(coin)) => coin,
+            result => panic!("the spill lost evicted coin {coin_id}: {result:?}"),
+        }
+    }
+
+    /// The cached coins by when they were last read. A panic elsewhere leaves the map usable.
+    fn recent(&self) -> std::sync::MutexGuard<'_, BTreeMap<u64, CoinId>> {
+        self.recent.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl CoinStore {
+    pub(crate) fn get(&self, coin_id: &CoinId) -> Option<&Coin> {
+        self.load(coin_id, self.coins.get(coin_id)?)
+    }
+
+    /// The coin of a slot, faulted in from the spill if it is not cached.
+    fn load<'a>(&'a self, coin_id: &CoinId, slot: &'a Slot) -> Option<&'a Coin> {
+        let Some(cache) = &self.cache else {
+            return slot.coin.get();
+        };
+        if slot.coin.get().is_some() {
+            cache.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            cache.misses.fetch_add(1, Ordering::Relaxed);
+            let _ = slot.coin.set(cache.read_back(coin_id));
+        }
+        if slot.spilled {
+            cache.touch(*coin_id, slot);
+        }
+        slot.coin.get()
+    }
+
+    pub(crate) fn contains_key(&self, coin_id: &CoinId) -> bool {
+        self.coins.contains_key(coin_id)
+    }
+
+    /// Add or replace a coin. With a coin cache, the replaced coin is only returned if it was cached.
+    pub(crate) fn insert(&mut self, coin_id: CoinId, coin: Coin) -> Option<Coin> {
+        let replaced = self.take_slot(&coin_id).and_then(|old| old.coin.into_inner());
+        let key = (coin.asset_id, coin.value);
+        self.by_value.entry(key).or_default().insert(coin_id);
+        if let Some(staged) = &mut self.staged {
+            staged.insert(coin_id, coin.clone());
+        }
+        self.shared.take();
+        let mut slot = Slot {
+            key,
+            coin: OnceLock::new(),
+            spilled: false,
+            used: AtomicU64::new(0),
+        };
+        if let Some(cache) = &self.cache {
+            // a coin the spill does not hold stays in memory
+            slot.spilled = cache.spill.put(&coin_id, &coin).is_ok();
+            if slot.spilled {
+                cache.touch(coin_id, &slot);
+            }
+        }
+        let _ = slot.coin.set(coin);
+        self.coins.insert(coin_id, slot);
+        self.trim();
+        replaced
+    }
+
+    pub(crate) fn remove(&mut self, coin_id: &CoinId) -> Option<Coin> {
+        let slot = self.take_slot(coin_id)?;
+        self.shared.take();
+        if let Some(staged) = &mut self.staged {
+            staged.remove(*coin_id);
+        }
+        let Some(cache) = &self.cache else {
+            return slot.coin.into_inner();
+        };
+        if slot.spilled {
+            let _ = cache.spill.remove(coin_id); // a stale spilled coin is never read again
+        }
+        Some(slot.coin.into_inner().unwrap_or_else(|| cache.read_back(coin_id)))
+    }
+
+    /// Remove a slot from the map, the value index, and the cache.
+    fn take_slot(&mut self, coin_id: &CoinId) -> Option<Slot> {
+        let slot = self.coins.remove(coin_id)?;
+        self.unindex(*coin_id, slot.key);
+        if let Some(cache) = &self.cache {
+            cache.recent().remove(&slot.used.load(Ordering::Relaxed));
+        }
+        Some(slot)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        if let Some(cache) = &self.cache {
+            for (coin_id, _) in self.coins.iter().filter(|(_, slot)| slot.spilled) {
+                let _ = cache.spill.remove(coin_id);
+            }
+            cache.recent().clear();
+        }
+        self.coins.clear();
+        self.by_value.clear();
+        self.shared.take();
+        if let Some(staged) = &mut self.staged {
+            staged.clear();
+        }
+    }
+
+    /// The coins as an immutable map, copied only if they changed since the last call.
+    pub(crate) fn shared(&self) -> Arc<BTreeMap<CoinId, Coin>> {
+        self.shared
+            .get_or_init(|| Arc::new(self.iter().map(|(coin_id, coin)| (*coin_id, coin.clone())).collect()))
+            .clone()
+    }
+
+    /// Hold at most `capacity` coins in memory, writing every coin through to `spill` and faulting
+    /// evicted ones back in when they are read.
+    pub(crate) fn attach_cache(&mut self, spill: Arc<dyn CoinSpill>, capacity: usize) -> Result<(), StoreError> {
+        self.detach_cache();
+        let cache = CoinCache {
+            spill,
+            capacity,
+            clock: AtomicU64::new(0),
+            recent: Mutex::new(BTreeMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        };
+        for (coin_id, slot) in &mut self.coins {
+            if let Some(coin) = slot.coin.get() {
+                cache.spill.put(coin_id, coin)?;
+                slot.spilled = true;
+                cache.touch(*coin_id, slot);
+            }
+        }
+        self.cache = Some(cache);
+        self.trim();
+        Ok(())
+    }
+
+    /// Fault every coin in and hold them all in memory from now on.
+    pub(crate) fn detach_cache(&mut self) {
+        let Some(cache) = self.cache.take() else {
+            return;
+        };
+        for (coin_id, slot) in &mut self.coins {
+            slot.used.store(0, Ordering::Relaxed);
+            if slot.coin.get().is_none() {
+                let _ = slot.coin.set(cache.read_back(coin_id));
+            }
+        }
+    }
+
+    /// How well the coin cache is doing, or `None` if there is none.
+    pub(crate) fn cache_stats(&self) -> Option<CoinCacheStats> {
+        let cache = self.cache.as_ref()?;
+        Some(CoinCacheStats {
+            capacity: cache.capacity,
+            cached: cache.recent().len(),
+            hits: cache.hits.load(Ordering::Relaxed),
+            misses: cache.misses.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Evict the least recently read coins beyond the cache's capacity. A coin the spill does not
+    /// hand back as it was written stays in memory for good instead.
+    pub(crate) fn trim(&mut self) {
+        let Some(cache) = &self.cache else {
+            return;
+        };
+        let mut recent = cache.recent();
+        while recent.len() > cache.capacity {
+            let Some((_, coin_id)) = recent.pop_first() else {
+                break;
+            };
+            let Some(slot) = self.coins.get_mut(&coin_id) else {
+                continue;
+            };
+            slot.used.store(0, Ordering::Relaxed);
+            if cache.spill.get(&coin_id).is_ok_and(|spilled| spilled.as_ref() == slot.coin.get()) {
+                slot.coin.take();
+            } else {
+                slot.spilled = false;
+                let _ = cache.spill.remove(&coin_id);
+            }
+        }
+    }
+
+    /// Record every following change for the wallet's store.
+    pub(crate) fn start_staging(&mut self) {
+        self.staged = Some(StoreBatch::default());
+    }
+
+    pub(crate) fn stop_staging(&mut self) {
+        self.staged = None;
+    }
+
+    /// The changes recorded since the last call.
+    pub(crate) fn take_staged(&mut self) -> StoreBatch {
+        self.staged.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Put back changes that could not be committed, ahead of any recorded since.
+    pub(crate) fn restage(&mut self, mut batch: StoreBatch) {
+        if let Some(staged) = &mut self.staged {
+            if staged.clear {
+                return;
+            }
+            batch.changes.append(&mut staged.changes);
+            *staged = batch;
+        }
+    }
+
+    /// Every coin, faulting in the ones that are not cached.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&CoinId, &Coin)> + '_ {
+        self.coins.iter().filter_map(|(coin_id, slot)| Some((coin_id, self.load(coin_id, slot)?)))
+    }
+
+    /// Every coin id, without faulting any coin in.
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &CoinId> + '_ {
+        self.coins.keys()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.coins.len()
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &Coin> + '_ {
+        self.iter().map(|(_, coin)| coin)
+    }
+
+    /// The total value of the bone coins, from the value index alone.
+    pub(crate) fn native_total(&self) -> u64 {
+        self.by_value.range((None, 0)..=(None, u64::MAX)).map(|((_, value), ids)| value * ids.len() as u64).sum()
+    }
+
+    /// The coins of the given asset (`None` for bones) worth between `min` and `max`, cheapest first.
+    pub(crate) fn in_value_range(
+        &self,
+        asset_id: Option<AssetId>,
+        min: u64,
+        max: u64,
+    ) -> impl DoubleEndedIterator<Item = (&CoinId, &Coin)> + '_ {
+        // an empty range would make `BTreeMap::range` panic
+        (min <= max)
+            .then(|| self.by_value.range((asset_id, min)..=(asset_id, max)))
+            .into_iter()
+            .flatten()
+            .flat_map(|(_, ids)| ids.iter())
+            .filter_map(|coin_id| Some((coin_id, self.get(coin_id)?)))
+    }
+
+    /// Whether the value index lists exactly the coins in the map. Coins that are not cached are not read.
+    pub(crate) fn index_matches(&self) -> bool {
+        let mut rebuilt: BTreeMap<(Option<AssetId>, u64), BTreeSet<CoinId>> = BTreeMap::new();
+        for (coin_id, slot) in &self.coins {
+            if slot.coin.get().is_some_and(|coin| (coin.asset_id, coin.value) != slot.key) {
+                return false;
+            }
+            rebuilt.entry(slot.key).or_default().insert(*coin_id);
+        }
+        rebuilt == self.by_value
+    }
+
+    fn unindex(&mut self, coin_id: CoinId, key: (Option<AssetId>, u64)) {
+        if let Some(ids) = self.by_value.get_mut(&key) {
+            ids.remove(&coin_id);
+            if ids.is_empty() {
+                self.by_value.remove(&key);
+            }
+        }
+    }
+}
+
+impl Index<&CoinId> for CoinStore {
+    type Output = Coin;
+
+    fn index(&self, coin_id: &CoinId) -> &Coin {
+        self.get(coin_id).expect("coin should be in the store")
+    }
+}
+
+impl Extend<(CoinId, Coin)> for CoinStore {
+    fn extend<I: IntoIterator<Item = (CoinId, Coin)>>(&mut self, iter: I) {
+        for (coin_id, coin) in iter {
+            self.insert(coin_id, coin);
+        }
+    }
+}
+
+impl FromIterator<(CoinId, Coin)> for CoinStore {
+    fn from_iter<I: IntoIterator<Item = (CoinId, Coin)>>(iter: I) -> Self {
+        let mut store = CoinStore::default();
+        store.extend(iter);
+        store
+    }
+}
+
+impl IntoIterator for CoinStore {
+    type Item = (CoinId, Coin);
+    type IntoIter = std::vec::IntoIter<(CoinId, Coin)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let cache = self.cache;
+        let coins = self.coins.into_iter().map(|(coin_id, slot)| {
+            let coin = slot.coin.into_inner().unwrap_or_else(|| cache.as_ref().expect("only a coin cache evicts coins").read_back(&coin_id));
+            (coin_id, coin)
+        });
+        coins.collect::<Vec<_>>().into_iter()
+    }
+}
+
+impl Wallet {
+    /// Every unspent coin the wallet knows about, in no particular order.
+    pub fn all_coins(&self) -> impl Iterator<Item = (&CoinId, &Coin)> {
+        self.coins.iter()
+    }
+
+    /// Every address owned by the wallet, in no particular order.
+    pub fn owned_addresses(&self) -> impl Iterator<Item = &Address> {
+        self.addresses.iter()
+    }
+
+    /// Consume the wallet and hand out its unspent coins.
+    pub fn into_coins(self) -> impl Iterator<Item = (CoinId, Coin)> {
+        self.coins.into_iter()
+    }
+
+    /// The wallet's bone coins worth between `min` and `max` (inclusive), cheapest first.
+    pub fn coins_in_range(&self, min: u64, max: u64) -> Vec<(CoinId, u64)> {
+        self.coins.in_value_range(None, min, max).map(|(coin_id, coin)| (*coin_id, coin.value)).collect()
+    }
+
+    /// Like `coins_in_range`, restricted to the coins of one owned address.
+    pub fn coins_in_range_of(&self, address: &Address, min: u64, max: u64) -> WalletResult<Vec<(CoinId, u64)>> {
+        if !self.addresses.contains(address) {
+            return Err(WalletError::ForeignAddress(address.clone()));
+        }
+        Ok(self
+            .coins
+            .in_value_range(None, min, max)
+            .filter(|(_, coin)| coin.owner == *address)
+            .map(|(coin_id, coin)| (*coin_id, coin.value))
+            .collect())
+    }
+
+    /// The wallet's bone coins ordered by value. A `limit` of zero means no limit.
+    pub fn coins_sorted_by_value(&self, descending: bool, limit: usize) -> Vec<(CoinId, u64)> {
+        sorted_listing(self.coins.in_value_range(None, 0, u64::MAX), descending, limit, |_| true)
+    }
+
+    /// Like `coins_sorted_by_value`, restricted to the coins of one owned address.
+    pub fn coins_sorted_by_value_of(
+        &self,
+        address: &Address,
+        descending: bool,
+        limit: usize,
+    ) -> WalletResult<Vec<(CoinId, u64)>> {
+        if !self.addresses.contains(address) {
+            return Err(WalletError::ForeignAddress(address.clone()));
+        }
+        let coins = self.coins.in_value_range(None, 0, u64::MAX);
+        Ok(sorted_listing(coins, descending, limit, |coin| coin.owner == *address))
+    }
+}
+
+fn sorted_listing<'a>(
+    coins: impl DoubleEndedIterator<Item = (&'a CoinId, &'a Coin)>,
+    descending: bool,
+    limit: usize,
+    keep: impl Fn(&Coin) -> bool,
+) -> Vec<(CoinId, u64)> {
+    let limit = if limit == 0 { usize::MAX } else { limit };
+    let coins: Box<dyn Iterator<Item = (&CoinId, &Coin)>> = if descending { Box::new(coins.rev()) } else { Box::new(coins) };
+    coins
+        .filter(|(_, coin)| keep(coin))
+        .take(limit)
+        .map(|(coin_id, coin)| (*coin_id, coin.value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    fn values(coins: Vec<(CoinId, u64)>) -> Vec<u64> {
+        coins.into_iter().map(|(_, value)| value).collect()
+    }
+
+    #[test]
+    fn coins_are_found_by_value_range() {
+        let (_, wallet) = make_one_block_blockchain();
+        assert_eq!(values(wallet.coins_in_range(15, 100)), vec![15, 100]);
+        assert_eq!(values(wallet.coins_in_range(16, 119)), vec![100]);
+        assert_eq!(values(wallet.coins_in_range(100, 15)), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn coins_are_listed_sorted_by_value() {
+        let (_, wallet) = make_one_block_blockchain();
+        assert_eq!(values(wallet.coins_sorted_by_value(false, 0)), vec![15, 100, 120]);
+        assert_eq!(values(wallet.coins_sorted_by_value(true, 2)), vec![120, 100]);
+    }
+
+    #[test]
+    fn value_listings_are_restricted_to_owned_addresses() {
+        let (_, wallet) = make_one_block_blockchain();
+        assert_eq!(values(wallet.coins_sorted_by_value_of(&Address::Alice, true, 0).unwrap()), vec![100, 15]);
+        assert_eq!(values(wallet.coins_in_range_of(&Address::Bob, 0, 119).unwrap()), Vec::<u64>::new());
+        assert_eq!(wallet.coins_in_range_of(&Address::Charlie, 0, 10), Err(WalletError::ForeignAddress(Address::Charlie)));
+    }
+
+    #[test]
+    fn the_value_index_follows_replaced_and_removed_coins() {
+        let (_, mut wallet) = make_one_block_blockchain();
+        let (coin_id, _) = wallet.coins_sorted_by_value(true, 1)[0];
+        let coin = wallet.coins[&coin_id].clone();
+        wallet.coins.insert(coin_id, Coin { value: 5, ..coin });
+        assert_eq!(values(wallet.coins_sorted_by_value(false, 0)), vec![5, 15, 100]);
+        wallet.coins.remove(&coin_id);
+        assert_eq!(values(wallet.coins_in_range(0, u64::MAX)), vec![15, 100]);
+    }
+
+    #[test]
+    fn owned_addresses_are_enumerated() {
+        let (_, wallet) = make_one_block_blockchain();
+        let addresses: HashSet<_> = wallet.owned_addresses().cloned().collect();
+        assert_eq!(addresses, HashSet::from([Address::Alice, Address::Bob]));
+    }
+
+    #[test]
+    fn coins_are_enumerated_borrowed_or_owned() {
+        let (_, wallet) = make_one_block_blockchain();
+        let borrowed: HashSet<_> = wallet.all_coins().map(|(coin_id, coin)| (*coin_id, coin.clone())).collect();
+        assert_eq!(borrowed.iter().map(|(_, coin)| coin.value).sum::<u64>(), wallet.net_worth());
+
+        let owned: HashSet<_> = wallet.into_coins().collect();
+        assert_eq!(owned, borrowed);
+    }
+}
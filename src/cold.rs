@@ -0,0 +1,249 @@
+//! Spending from keys kept offline, with an online watch-only wallet doing everything else.
+//!
+//! The online wallet, created with `Wallet::watch_only`, syncs and builds transactions but cannot
+//! sign them. `prepare_cold_spend` turns a payment into a `SigningRequest`, which carries the coins
+//! being spent so the offline side needs no node. The request crosses the air gap as bytes in the
+//! canonical codec. An `OfflineSigner`, built from the addresses alone, answers it with a
+//! `SignatureBundle`, and the online wallet checks the signatures against its own request before
+//! `submit_signed` hands the transaction to the node.
+//!
+//! The address scheme is not part of the exported state, so a watch-only wallet restored with
+//! `import_state` signs again; requests are unsigned no matter which wallet prepares them.
+
+use std::fmt;
+
+use bonecoin_core::codec::{Decode, DecodeError, Encode};
+use bonecoin_core::*;
+
+use crate::scheme::WatchOnly;
+use crate::{KeyScheme, PartialTransaction, Wallet};
+
+/// An unsigned transaction waiting for the offline signer, together with the coins it spends.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SigningRequest {
+    pub(crate) partial: PartialTransaction,
+}
+
+impl SigningRequest {
+    /// The transaction to sign, without signatures.
+    pub fn transaction(&self) -> &Transaction {
+        self.partial.transaction()
+    }
+
+    /// The coins spent by the transaction, in input order, for the signer to review.
+    pub fn input_coins(&self) -> Vec<Coin> {
+        self.partial.coins.iter().flatten().cloned().collect()
+    }
+
+    /// The id of the unsigned transaction, which the signature bundle must name.
+    pub fn unsigned_id(&self) -> TransactionId {
+        self.partial.unsigned().id()
+    }
+}
+
+/// The signatures the offline signer made for a request, one per input.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SignatureBundle {
+    /// The id of the unsigned transaction that was signed.
+    pub unsigned_id: TransactionId,
+    /// The signature for each input, in input order. Inputs the signer could not sign are `Signature::Invalid`.
+    pub signatures: Vec<Signature>,
+}
+
+/// Errors that can occur while exchanging a cold spend between the two wallets.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum ColdSpendError {
+    /// The wallet could not build or submit the transaction.
+    Wallet(WalletError),
+    /// The exchanged bytes could not be decoded.
+    Decode(DecodeError),
+    /// The signatures were made for a different transaction.
+    WrongTransaction,
+    /// The input at this index is not signed by the owner of the coin it spends.
+    Unsigned(usize),
+}
+
+impl From<WalletError> for ColdSpendError {
+    fn from(e: WalletError) -> Self {
+        ColdSpendError::Wallet(e)
+    }
+}
+
+impl From<DecodeError> for ColdSpendError {
+    fn from(e: DecodeError) -> Self {
+        ColdSpendError::Decode(e)
+    }
+}
+
+impl fmt::Display for ColdSpendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColdSpendError::Wallet(e) => write!(f, "{e}"),
+            ColdSpendError::Decode(e) => write!(f, "cannot decode the exchanged bytes: {e}"),
+            ColdSpendError::WrongTransaction => write!(f, "the signatures are for a different transaction"),
+            ColdSpendError::Unsigned(index) => write!(f, "input {index} is not signed by its coin's owner"),
+        }
+    }
+}
+
+impl std::error::Error for ColdSpendError {}
+
+/// The offline half of a cold wallet: holds the keys, never talks to a node.
+pub struct OfflineSigner {
+    wallet: Wallet,
+}
+
+impl OfflineSigner {
+    /// A signer holding the keys of the given addresses.
+    pub fn new(addresses: impl Iterator<Item = Address>) -> Self {
+        OfflineSigner {
+            wallet: Wallet::with_scheme(addresses, KeyScheme),
+        }
+    }
+
+    /// Sign every input of the encoded request that spends a coin of this signer's addresses, and
+    /// return the encoded signature bundle.
+    pub fn sign(&self, request: &[u8]) -> Result<Vec<u8>, ColdSpendError> {
+        let request = SigningRequest::decode(request)?;
+        Ok(self.sign_request(&request).encode())
+    }
+
+    /// Sign every input of the request that spends a coin of this signer's addresses.
+    pub fn sign_request(&self, request: &SigningRequest) -> SignatureBundle {
+        let mut partial = request.partial.clone();
+        self.wallet.sign_partial(&mut partial);
+        SignatureBundle {
+            unsigned_id: request.unsigned_id(),
+            signatures: partial.transaction.inputs.into_iter().map(|input| input.signature).collect(),
+        }
+    }
+}
+
+impl Wallet {
+    /// An online wallet following the coins of the given addresses that never signs, for pairing
+    /// with an `OfflineSigner` holding their keys.
+    pub fn watch_only(addresses: impl Iterator<Item = Address>) -> Self {
+        Wallet::with_scheme(addresses, WatchOnly(KeyScheme))
+    }
+
+    /// Build a payment like `create_automatic_transaction` and return it as a request for the
+    /// offline signer.
+    pub fn prepare_cold_spend(&self, recipient: Address, amount: u64, tip: u64) -> WalletResult<SigningRequest> {
+        let transaction = self.create_automatic_transaction(recipient, amount, tip)?;
+        let coins = transaction.iter_input_coin_ids().map(|coin_id| self.coins[&coin_id].clone()).collect();
+        Ok(SigningRequest {
+            partial: PartialTransaction::with_input_coins(transaction, coins),
+        })
+    }
+
+    /// Put the signatures of an encoded bundle on the request's transaction, returning it once every
+    /// input is signed by the owner of its coin.
+    pub fn import_signatures(&self, request: &SigningRequest, bundle: &[u8]) -> Result<Transaction, ColdSpendError> {
+        let bundle = SignatureBundle::decode(bundle)?;
+        if bundle.unsigned_id != request.unsigned_id() || bundle.signatures.len() != request.transaction().inputs.len() {
+            return Err(ColdSpendError::WrongTransaction);
+        }
+        let mut transaction = request.partial.unsigned();
+        let signed = transaction.inputs.iter_mut().zip(bundle.signatures).zip(&request.partial.coins);
+        for (index, ((input, signature), coin)) in signed.enumerate() {
+            if !coin.as_ref().is_some_and(|coin| coin.owner.is_satisfied_by(&signature)) {
+                return Err(ColdSpendError::Unsigned(index));
+            }
+            input.signature = signature;
+        }
+        Ok(transaction)
+    }
+
+    /// Import the signatures of an encoded bundle like `import_signatures` and submit the signed
+    /// transaction to `node`.
+    pub fn submit_signed<Node: NodeEndpoint>(
+        &self,
+        request: &SigningRequest,
+        bundle: &[u8],
+        node: &Node,
+    ) -> Result<Transaction, ColdSpendError> {
+        let transaction = self.import_signatures(request, bundle)?;
+        if !node.submit_transaction(&transaction) {
+            return Err(WalletError::BroadcastFailed(transaction.id()).into());
+        }
+        Ok(transaction)
+    }
+
+    /// Whether the wallet signs the transactions it builds, which a watch-only wallet does not.
+    pub fn can_sign(&self) -> bool {
+        self.addresses.iter().all(|address| address.is_satisfied_by(&self.scheme.sign(&self.addresses, address)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    use bonecoin_core::codec::{Decode, Encode};
+
+    /// A watch-only wallet of Alice and Bob synced to a first block paying Alice 50 bones, and the
+    /// request it prepared to pay Charlie 30 of them. Returns the node, the wallet, the id of the
+    /// block and the request.
+    fn prepared() -> (MockNode, Wallet, BlockId, SigningRequest) {
+        let mut node = MockNode::new();
+        let mut hot = Wallet::watch_only(vec![Address::Alice, Address::Bob].into_iter());
+        let b1 = node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 50)])]);
+        hot.sync(&node);
+        let request = hot.prepare_cold_spend(Address::Charlie, 30, 1).unwrap();
+        (node, hot, b1, request)
+    }
+
+    fn cold_signer() -> OfflineSigner {
+        OfflineSigner::new(vec![Address::Alice, Address::Bob].into_iter())
+    }
+
+    #[test]
+    fn watch_only_wallets_cannot_sign() {
+        let (_, hot, _, _) = prepared();
+        assert!(!hot.can_sign());
+        assert!(wallet_with_alice().can_sign());
+    }
+
+    #[test]
+    fn cold_spends_are_prepared_unsigned() {
+        let (_, _, _, request) = prepared();
+        assert!(request.transaction().inputs.iter().all(|input| input.signature == Signature::Invalid));
+        assert_eq!(request.input_coins(), [Coin { value: 50, owner: Address::Alice, asset_id: None }]);
+    }
+
+    #[test]
+    fn signing_requests_survive_encoding() {
+        let (_, _, _, request) = prepared();
+        assert_eq!(SigningRequest::decode(&request.encode()), Ok(request));
+    }
+
+    #[test]
+    fn signers_without_the_keys_leave_the_inputs_unsigned() {
+        let (_, hot, _, request) = prepared();
+        let stranger = OfflineSigner::new(vec![Address::Dave].into_iter());
+        let foreign = stranger.sign(&request.encode()).unwrap();
+        assert_eq!(hot.import_signatures(&request, &foreign), Err(ColdSpendError::Unsigned(0)));
+    }
+
+    #[test]
+    fn signature_bundles_only_fit_their_own_request() {
+        let (_, hot, _, request) = prepared();
+        let other = hot.prepare_cold_spend(Address::Charlie, 20, 1).unwrap();
+        let bundle = cold_signer().sign(&request.encode()).unwrap();
+        assert_eq!(hot.import_signatures(&other, &bundle), Err(ColdSpendError::WrongTransaction));
+        assert!(matches!(hot.import_signatures(&request, &bundle[1..]), Err(ColdSpendError::Decode(_))));
+    }
+
+    #[test]
+    fn signed_spends_are_submitted_online() {
+        let (mut node, mut hot, b1, request) = prepared();
+        let bundle = cold_signer().sign(&request.encode()).unwrap();
+        let sent = hot.submit_signed(&request, &bundle, &node).unwrap();
+        assert_eq!(node.submitted_transactions(), std::slice::from_ref(&sent));
+        assert_eq!(sent.inputs[0].signature, Signature::Valid(Address::Alice));
+
+        node.add_block_as_best(b1, vec![sent]);
+        hot.sync(&node);
+        assert_eq!(hot.net_worth(), 19);
+    }
+}
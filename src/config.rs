@@ -0,0 +1,246 @@
+//! Wallet configuration: coin selection and its constraints, change, coinbase maturity, and bounds on the reorg and
+//! history information the wallet retains.
+//!
+//! Blocks deeper than `undo_depth` are treated as final: their undo records are dropped, and a reorg
+//! reaching below them can no longer be rolled back block by block. `Wallet::try_sync` reports such
+//! a reorg as `SyncError::ReorgTooDeep`, while `sync` falls back to resyncing from genesis.
+
+use std::fmt;
+
+use bonecoin_core::*;
+
+use crate::{ChangeDestination, ChangePolicy, CoinSelectionStrategy, SelectionConstraints, Wallet};
+
+/// The number of undo records kept by default.
+pub const DEFAULT_UNDO_DEPTH: u64 = 100;
+
+/// The wallet's settings. The default matches a wallet created with `Wallet::new`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct WalletConfig {
+    /// The strategy used to select the inputs of automatic transactions.
+    pub selection: CoinSelectionStrategy,
+    /// Limits on the coins automatic transactions spend and the change they return.
+    pub selection_constraints: SelectionConstraints,
+    /// Where change goes. A fixed change address must be owned by the wallet.
+    pub change_policy: ChangePolicy,
+    /// How many blocks must be built on top of a coinbase coin before the wallet spends it.
+    pub coinbase_maturity: u64,
+    /// How many of the most recent blocks can be rolled back without resyncing.
+    pub undo_depth: u64,
+    /// If set, the oldest history entries are dropped beyond this many.
+    /// Reports built from the history, such as lot accounting and the policy window, only see the kept entries.
+    pub max_history_entries: Option<usize>,
+    /// If set, the outpoint and coinbase height of a spent coin are dropped this many blocks after it was spent,
+    /// and never while the spending block can still be undone. Labels are kept.
+    pub prune_spent_after: Option<u64>,
+    /// Check every synced block against the wallet's own coins and report anything suspicious as a `SyncWarning`.
+    pub strict_sync: bool,
+    /// Refuse to sync onto a chain the node reports less cumulative work for than the chain the wallet
+    /// followed at its last sync, returning `SyncError::LessWork`. Nodes that do not report work are not checked.
+    pub check_work: bool,
+    /// Fetch only the bodies of blocks the node finds transactions matching the wallet's sync filter
+    /// in, and apply just the header of the others. Ignored by strict sync, which checks every block.
+    pub lazy_bodies: bool,
+}
+
+impl Default for WalletConfig {
+    fn default() -> Self {
+        WalletConfig {
+            selection: CoinSelectionStrategy::default(),
+            selection_constraints: SelectionConstraints::default(),
+            change_policy: ChangePolicy::default(),
+            coinbase_maturity: COINBASE_MATURITY,
+            undo_depth: DEFAULT_UNDO_DEPTH,
+            max_history_entries: None,
+            prune_spent_after: None,
+            strict_sync: false,
+            check_work: false,
+            lazy_bodies: false,
+        }
+    }
+}
+
+/// Errors that stop `Wallet::try_sync`, `Wallet::sync_verified`, and `Wallet::sync_resilient`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SyncError {
+    /// The node's chain forked below the wallet's undo records. The wallet was rolled back as far as
+    /// its records allowed, to this height, whose block is not on the node's chain either.
+    ReorgTooDeep { undone_to: u64 },
+    /// Two of the nodes given to `sync_verified` reported different blocks at this height.
+    NodesDisagree { height: u64 },
+    /// `sync_verified` needs at least two nodes to compare.
+    TooFewNodes { configured: usize },
+    /// The node's best chain has less cumulative work than the chain the wallet followed.
+    /// The wallet was left as it was.
+    LessWork { followed: u64, offered: u64 },
+    /// The node given to `sync_resilient` kept failing to answer. Every block up to this height was
+    /// applied whole, and none above it.
+    NodeUnavailable { height: u64 },
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::ReorgTooDeep { undone_to } => {
+                write!(f, "reorg reaches below height {undone_to}, the oldest block the wallet can undo to")
+            }
+            SyncError::NodesDisagree { height } => write!(f, "the nodes report different blocks at height {height}"),
+            SyncError::TooFewNodes { configured } => {
+                write!(f, "verified sync needs at least two nodes, {configured} configured")
+            }
+            SyncError::LessWork { followed, offered } => {
+                write!(f, "the node's best chain has {offered} work, less than the {followed} of the chain the wallet followed")
+            }
+            SyncError::NodeUnavailable { height } => write!(f, "the node stopped answering, the wallet is synced to height {height}"),
+        }
+    }
+}
+
+impl std::error::Error for SyncError {}
+
+impl Wallet {
+    /// Create a wallet owning the given addresses with the given config.
+    pub fn with_config(addresses: impl Iterator<Item = Address>, config: WalletConfig) -> WalletResult<Self> {
+        let mut wallet = Wallet::new(addresses);
+        wallet.set_config(config)?;
+        Ok(wallet)
+    }
+
+    /// Replace the wallet's config and prune what its retention limits no longer allow.
+    /// A fixed change address must be owned by the wallet.
+    pub fn set_config(&mut self, config: WalletConfig) -> WalletResult<()> {
+        if let ChangeDestination::Fixed(address) = &config.change_policy.destination {
+            if !self.addresses.contains(address) {
+                return Err(WalletError::ForeignAddress(address.clone()));
+            }
+        }
+        self.config = config;
+        self.prune_retained();
+        Ok(())
+    }
+
+    /// The wallet's current config.
+    pub fn config(&self) -> &WalletConfig {
+        &self.config
+    }
+
+    /// Drop the undo records, history entries, and spent coin details the config no longer allows.
+    pub(crate) fn prune_retained(&mut self) {
+        let undo_depth = usize::try_from(self.config.undo_depth).unwrap_or(usize::MAX);
+        let excess = self.undo.len().saturating_sub(undo_depth);
+        self.undo.drain(..excess);
+        self.headers.keep_newest(undo_depth);
+
+        // spent coins must stay restorable while their block can be undone
+        if let Some(after) = self.config.prune_spent_after {
+            let cutoff = self.best_block_height.saturating_sub(after.max(self.config.undo_depth));
+            if cutoff > self.pruned_spent_to {
+                let entries = self.history.entries();
+                let start = entries.partition_point(|entry| entry.height <= self.pruned_spent_to);
+                let end = entries.partition_point(|entry| entry.height <= cutoff);
+                for entry in &entries[start..end] {
+                    for (coin_id, _) in &entry.spent {
+                        self.outpoints.remove(coin_id);
+                        self.coinbase_heights.remove(coin_id);
+                    }
+                }
+                self.pruned_spent_to = cutoff;
+            }
+        }
+
+        if let Some(max) = self.config.max_history_entries {
+            let excess = self.history.entries().len().saturating_sub(max);
+            self.history.drop_oldest(excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice keeping 2 undo records and 2 history entries, and pruning spent coins
+    /// after a block, synced to five blocks: the first pays her a coin labelled "first", the
+    /// second spends it, and the others pay her 20, 30 and 40 bones. Returns the node, the wallet,
+    /// the id of the first block, and the first coin.
+    fn retaining_little() -> (MockNode, Wallet, BlockId, CoinId) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+        wallet
+            .set_config(WalletConfig {
+                undo_depth: 2,
+                max_history_entries: Some(2),
+                prune_spent_after: Some(1),
+                ..WalletConfig::default()
+            })
+            .unwrap();
+
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![pay_alice(10)]);
+        let first = pay_alice(10).coin_id(1, 0);
+        wallet.sync(&node);
+        wallet.set_coin_label(&first, "first").unwrap();
+        let spend = Transaction {
+            inputs: vec![Input {
+                coin_id: first,
+                signature: Signature::Invalid,
+            }],
+            ..mint([])
+        };
+        let mut parent = node.add_block_as_best(b1_id, vec![spend]);
+        for value in [20, 30, 40] {
+            parent = node.add_block_as_best(parent, vec![pay_alice(value)]);
+        }
+        wallet.sync(&node);
+        assert_eq!(wallet.best_height(), 5);
+        wallet.take_events();
+        (node, wallet, b1_id, first)
+    }
+
+    #[test]
+    fn history_keeps_the_newest_entries() {
+        let (_, wallet, _, _) = retaining_little();
+        assert_eq!(wallet.history().len(), 2);
+    }
+
+    #[test]
+    fn spent_coins_are_forgotten_once_their_spend_cannot_be_undone() {
+        let (_, wallet, _, first) = retaining_little();
+        // the spend at height 2 can no longer be undone, so the spent coin's details are gone
+        assert_eq!(wallet.coin_label(&first), None);
+    }
+
+    #[test]
+    fn try_sync_reports_reorgs_below_the_undo_records() {
+        let (mut node, mut wallet, b1_id, _) = retaining_little();
+        node.add_block_as_best(b1_id, vec![]);
+        assert_eq!(wallet.try_sync(&node), Err(SyncError::ReorgTooDeep { undone_to: 3 }));
+        assert_eq!(wallet.best_height(), 3);
+    }
+
+    #[test]
+    fn sync_resyncs_from_genesis_after_reorgs_below_the_undo_records() {
+        let (mut node, mut wallet, b1_id, first) = retaining_little();
+        let fork_id = node.add_block_as_best(b1_id, vec![]);
+        wallet.sync(&node);
+        assert_eq!((wallet.best_height(), wallet.best_hash()), (2, fork_id));
+        assert_eq!(wallet.net_worth(), 10);
+        assert_eq!(
+            wallet.take_events(),
+            vec![
+                WalletEvent::RolledBack { to_height: 0 },
+                WalletEvent::CoinReceived {
+                    coin_id: first,
+                    coin: pay_alice(10).outputs[0].clone()
+                },
+                WalletEvent::Synced { height: 2, block_id: fork_id },
+            ]
+        );
+    }
+
+    #[test]
+    fn the_config_survives_export() {
+        let (_, wallet, _, _) = retaining_little();
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.config(), wallet.config());
+    }
+}
@@ -0,0 +1,108 @@
+//! Merging many small coins into a few large ones.
+//!
+//! A wallet that receives many small payments ends up holding more coins than one transaction may
+//! spend. `consolidate_coins` merges them into change coins, smallest first, in as many transactions
+//! as `MAX_TX_INPUTS` requires. The transactions spend disjoint coins, so they can all be broadcast at once.
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+impl Wallet {
+    /// Construct transactions merging the wallet's mature native coins into one change coin per
+    /// transaction, each spending at most `MAX_TX_INPUTS` coins and burning `tip`.
+    ///
+    /// Coins are merged smallest first, leaving out coins of receive-only addresses. A coin left over on its own is not spent, and neither are
+    /// coins whose merged value is dust the change policy keeps out of change. Returns no transactions if
+    /// there is nothing to merge.
+    pub fn consolidate_coins(&self, tip: u64) -> WalletResult<Vec<Transaction>> {
+        let mut coins: Vec<(CoinId, u64)> = self
+            .coins
+            .iter()
+            .filter(|(coin_id, coin)| self.is_auto_spendable(coin_id, coin) && coin.is_native())
+            .map(|(coin_id, coin)| (*coin_id, coin.value))
+            .collect();
+        coins.sort_by_key(|(coin_id, value)| (*value, *coin_id));
+
+        let mut transactions = Vec::new();
+        for chunk in coins.chunks(MAX_TX_INPUTS).filter(|chunk| chunk.len() > 1) {
+            let total: u64 = chunk.iter().map(|(_, value)| value).sum();
+            if total <= tip {
+                return Err(WalletError::InsufficientFunds { needed: tip + 1, available: total });
+            }
+            let Some(merged) = self.change_output(total - tip, None)? else {
+                continue;
+            };
+            let inputs = chunk
+                .iter()
+                .map(|(coin_id, _)| Input {
+                    coin_id: *coin_id,
+                    signature: Signature::Invalid,
+                })
+                .collect();
+            let mut transaction = Transaction {
+                version: TRANSACTION_VERSION,
+                inputs,
+                outputs: vec![merged],
+            };
+            self.sign_inputs(&mut transaction);
+            self.check_policy(&transaction)?;
+            transactions.push(transaction);
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(coins = coins.len(), transactions = transactions.len(), "coins consolidated");
+        Ok(transactions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    use std::collections::HashSet;
+
+    /// A wallet of Alice synced to a block paying her 35 coins of 10 bones and 35 of 11, and the
+    /// node holding that block.
+    fn seventy_coins() -> (MockNode, Wallet) {
+        let mut node = MockNode::new();
+        let payouts = |value: u64| mint(std::iter::repeat_n((Address::Alice, value), 35));
+        node.add_block_as_best(Block::genesis().id(), vec![payouts(10), payouts(11)]);
+        let mut wallet = Wallet::builder().address(Address::Alice).coinbase_maturity(0).build().unwrap();
+        wallet.sync(&node);
+        assert_eq!(wallet.coins.len(), 70);
+        (node, wallet)
+    }
+
+    #[test]
+    fn consolidations_spend_at_most_the_input_limit_per_transaction() {
+        let (node, wallet) = seventy_coins();
+        let merges = wallet.consolidate_coins(2).unwrap();
+        assert_eq!(merges.iter().map(|tx| tx.inputs.len()).collect::<Vec<_>>(), vec![MAX_TX_INPUTS, 70 - MAX_TX_INPUTS]);
+        let spent: HashSet<CoinId> = merges.iter().flat_map(Transaction::iter_input_coin_ids).collect();
+        assert_eq!(spent.len(), 70);
+        let merged: u64 = merges.iter().map(|tx| tx.outputs[0].value).sum();
+        assert_eq!(merged, 35 * 10 + 35 * 11 - 2 * 2);
+        assert!(merges.iter().all(|tx| tx.is_within_limits() && node.submit_transaction(tx)));
+    }
+
+    #[test]
+    fn withdrawal_batches_pay_at_most_the_output_limit_per_transaction() {
+        let (node, mut wallet) = seventy_coins();
+        for _ in 0..70 {
+            wallet.queue_withdrawal(Address::Bob, 1).unwrap();
+        }
+        let batches = wallet.flush_withdrawals(100, 0).unwrap();
+        assert_eq!(batches.iter().map(|tx| tx.outputs.len()).collect::<Vec<_>>(), vec![MAX_TX_OUTPUTS, 8]);
+        assert!(batches.iter().all(|tx| tx.is_within_limits() && node.submit_transaction(tx)));
+    }
+
+    #[test]
+    fn transactions_past_the_limits_are_refused() {
+        let (node, wallet) = seventy_coins();
+        let too_wide = Transaction { inputs: vec![Input::dummy(); MAX_TX_INPUTS + 1], ..mint([]) };
+        assert!(!too_wide.is_within_limits());
+        assert!(!node.submit_transaction(&too_wide));
+        assert_eq!(wallet.create_automatic_transaction(Address::Bob, 700, 0), Err(WalletError::TooLarge));
+    }
+}
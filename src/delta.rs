@@ -0,0 +1,285 @@
+//! The changes a single block makes to the wallet, applied and undone as one unit.
+//!
+//! Sync first computes a block's `StateDelta` without touching the wallet, then applies it in one
+//! step, so a node failure while fetching or reading a block never leaves a half applied block behind.
+//! Applied deltas are kept as undo records: a reorg is rolled back by undoing them newest first
+//! until the wallet is back on the node's chain.
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use bonecoin_core::*;
+
+use crate::history::burned_by;
+use crate::{BlockReport, Direction, HistoryEntry, OutPoint, Wallet, WalletEvent};
+
+/// Everything applying one block changes in the wallet.
+#[derive(Clone, Debug)]
+pub(crate) struct StateDelta {
+    /// The height of the block.
+    pub(crate) height: u64,
+    /// The id of the block.
+    pub(crate) block_id: BlockId,
+    /// The sync position before the block, restored when the delta is undone.
+    pub(crate) parent: (u64, BlockId),
+    /// The number of transactions in the block.
+    pub(crate) transactions: usize,
+    /// Wallet coins the block spent, as they were before.
+    pub(crate) spent: BTreeMap<CoinId, Coin>,
+    /// Coins the block gave the wallet that are still unspent after it.
+    pub(crate) created: BTreeMap<CoinId, Coin>,
+    /// Coins minted by the block's coinbase, spent or not.
+    pub(crate) coinbase: Vec<CoinId>,
+    /// The outpoint of every coin the block gave the wallet, spent or not.
+    pub(crate) outpoints: Vec<(CoinId, OutPoint)>,
+    /// The block's transactions that touched the wallet, in block order.
+    pub(crate) history: Vec<HistoryEntry>,
+}
+
+impl Wallet {
+    /// Work out what applying the block on top of the current sync position changes, without changing anything.
+    pub(crate) fn block_delta(&self, block_id: BlockId, block: &Block) -> StateDelta {
+        let coinbase = block.coinbase().map(|tx| tx.id());
+        let mut delta = StateDelta {
+            height: block.number,
+            block_id,
+            parent: (self.best_block_height, self.best_block_hash),
+            transactions: block.body.len(),
+            spent: BTreeMap::new(),
+            created: BTreeMap::new(),
+            coinbase: Vec::new(),
+            outpoints: Vec::new(),
+            history: Vec::new(),
+        };
+
+        for transaction in &block.body {
+            let mut spent = Vec::new();
+            for input in &transaction.inputs {
+                // the coin may have been created earlier in the same block
+                if let Some(coin) = delta.created.remove(&input.coin_id) {
+                    spent.push((input.coin_id, coin));
+                } else if let Some(coin) = self.coins.get(&input.coin_id) {
+                    if let Entry::Vacant(entry) = delta.spent.entry(input.coin_id) {
+                        entry.insert(coin.clone());
+                        spent.push((input.coin_id, coin.clone()));
+                    }
+                }
+            }
+
+            let mut received = Vec::new();
+            let mut pays_foreign_address = false;
+            for (index, coin) in transaction.outputs.iter().enumerate() {
+                if !self.owns(&coin.owner) {
+                    pays_foreign_address = true;
+                    continue;
+                }
+                let coin_id = transaction.coin_id(block.number, index);
+                delta.created.insert(coin_id, coin.clone());
+                delta.outpoints.push((coin_id, (transaction.id(), index)));
+                received.push((coin_id, coin.clone()));
+                if coinbase == Some(transaction.id()) {
+                    delta.coinbase.push(coin_id); // tagged for maturity handling
+                }
+            }
+
+            // remember the transaction if it touched the wallet
+            if !spent.is_empty() || !received.is_empty() {
+                delta.history.push(HistoryEntry {
+                    tx_id: transaction.id(),
+                    block_id,
+                    height: block.number,
+                    timestamp: block.timestamp,
+                    direction: Direction::classify(!spent.is_empty(), spent.len() < transaction.inputs.len(), pays_foreign_address),
+                    burned: burned_by(transaction, &spent),
+                    received,
+                    spent,
+                });
+            }
+        }
+        delta
+    }
+
+    /// Apply a delta computed by `block_delta` for a block with this body, or by `header_delta` for
+    /// one whose body was skipped, and keep it as an undo record.
+    pub(crate) fn apply_delta(&mut self, body: &[Transaction], delta: StateDelta) {
+        let mut entries = delta.history.iter().peekable();
+        for transaction in body {
+            let authored = self.watchtower.is_empty() || self.is_authored(&transaction.id());
+            if !self.registered.is_empty() {
+                self.observe_registered(transaction, delta.height);
+            }
+            self.watch_htlcs(transaction, delta.height);
+            self.observe_watched(transaction, delta.height);
+            self.observe_withdrawals(transaction, delta.height);
+            if let Some(entry) = entries.next_if(|entry| entry.tx_id == transaction.id()) {
+                for (coin_id, _) in &entry.spent {
+                    self.emit(WalletEvent::CoinSpent { coin_id: *coin_id, tx_id: transaction.id() });
+                }
+                self.check_spend_authorized(entry, authored);
+                for (coin_id, coin) in &entry.received {
+                    self.emit(WalletEvent::CoinReceived { coin_id: *coin_id, coin: coin.clone() });
+                }
+            }
+        }
+
+        for coin_id in delta.spent.keys() {
+            self.coins.remove(coin_id);
+        }
+        self.coins.extend(delta.created.iter().map(|(coin_id, coin)| (*coin_id, coin.clone())));
+        self.coinbase_heights.extend(delta.coinbase.iter().map(|coin_id| (*coin_id, delta.height)));
+        self.outpoints.extend(delta.outpoints.iter().copied());
+        for entry in &delta.history {
+            self.mark_used(entry.received.iter().map(|(_, coin)| coin));
+            self.history.push(entry.clone());
+        }
+        self.best_block_height = delta.height;
+        self.best_block_hash = delta.block_id;
+        self.expire_registered();
+        self.headers.push(&delta);
+        self.notify_applied(&delta);
+        self.undo.push_back(delta);
+        self.prune_retained();
+    }
+
+    /// Undo the newest undo record, moving the wallet back to the block before it.
+    /// Returns what the undone block had changed, or `None` if there was no undo record left.
+    pub(crate) fn undo_last_block(&mut self) -> Option<BlockReport> {
+        let delta = self.undo.pop_back()?;
+        let report = BlockReport::new(&delta);
+        for coin_id in delta.created.keys() {
+            self.coins.remove(coin_id);
+        }
+        self.coins.extend(delta.spent);
+        for coin_id in &delta.coinbase {
+            self.coinbase_heights.remove(coin_id);
+        }
+        for (coin_id, _) in &delta.outpoints {
+            self.outpoints.remove(coin_id);
+        }
+        let (height, hash) = delta.parent;
+        self.history.truncate_above(height);
+        self.forget_tracking_above(height);
+        self.headers.truncate_above(height);
+        self.pruned_spent_to = self.pruned_spent_to.min(height);
+        self.best_block_height = height;
+        self.best_block_hash = hash;
+        self.notify_reverted(&report);
+        Some(report)
+    }
+
+    /// Undo blocks until the wallet's newest undo record is on the node's chain again, for a fork the
+    /// header window could not place. Returns what the undone blocks had changed, newest first, and
+    /// whether the node confirmed the wallet's new best block is on its chain.
+    ///
+    /// The node is asked for the fork point of the whole undo log at once, and every record above it
+    /// is undone; if none of them is on the node's chain, all of them are.
+    pub(crate) fn undo_orphaned_blocks<Node: NodeEndpoint>(&mut self, node: &Node) -> (Vec<BlockReport>, bool) {
+        let mut undone = Vec::new();
+        if self.undo.is_empty() {
+            return (undone, false);
+        }
+        let locator: Vec<BlockId> = self.undo.iter().rev().map(|delta| delta.block_id).collect();
+        let fork_point = node.common_ancestor(&locator).map(|(block_id, _)| block_id);
+        while self.undo.back().is_some_and(|delta| Some(delta.block_id) != fork_point) {
+            undone.extend(self.undo_last_block());
+        }
+        (undone, fork_point.is_some())
+    }
+
+    /// Undo blocks down to `height`, a fork point found in the header window.
+    /// Returns what the undone blocks had changed, newest first.
+    pub(crate) fn undo_to(&mut self, height: u64) -> Vec<BlockReport> {
+        let mut undone = Vec::new();
+        while self.best_block_height > height {
+            match self.undo_last_block() {
+                Some(report) => undone.push(report),
+                None => break,
+            }
+        }
+        undone
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A node that cannot serve the body of one block.
+    struct MissingBody<'a> {
+        node: &'a MockNode,
+        missing: BlockId,
+    }
+
+    impl NodeEndpoint for MissingBody<'_> {
+        fn best_block_at_height(&self, h: u64) -> Option<BlockId> {
+            self.node.best_block_at_height(h)
+        }
+
+        fn entire_block(&self, id: &BlockId) -> Option<Block> {
+            (*id != self.missing).then(|| self.node.entire_block(id)).flatten()
+        }
+    }
+
+    /// A wallet of Alice synced to three blocks paying her 10, 20 and 30 bones. Returns the node,
+    /// the wallet, and the id of the first block.
+    fn three_payments() -> (MockNode, Wallet, BlockId) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![pay_alice(10)]);
+        let b2_id = node.add_block_as_best(b1_id, vec![pay_alice(20)]);
+        node.add_block_as_best(b2_id, vec![pay_alice(30)]);
+        wallet.sync(&node);
+        assert_eq!(wallet.net_worth(), 60);
+        wallet.take_events();
+        (node, wallet, b1_id)
+    }
+
+    #[test]
+    fn forks_of_the_same_length_replace_every_block_above_the_fork_point() {
+        let (mut node, mut wallet, b1_id) = three_payments();
+        let b2_id = node.add_block_as_best(b1_id, vec![pay_alice(1)]);
+        let b3_id = node.add_block_as_best(b2_id, vec![]);
+        wallet.sync(&node);
+        assert_eq!((wallet.best_height(), wallet.best_hash()), (3, b3_id));
+        assert_eq!(wallet.net_worth(), 11);
+        assert_eq!(wallet.history().len(), 2);
+        assert_eq!(wallet.take_events()[0], WalletEvent::RolledBack { to_height: 1 });
+    }
+
+    #[test]
+    fn blocks_the_node_cannot_serve_stop_sync_before_any_of_them_is_applied() {
+        let (mut node, mut wallet, _) = three_payments();
+        let b3_id = wallet.best_hash();
+        let b4_id = node.add_block_as_best(b3_id, vec![pay_alice(5)]);
+        node.add_block_as_best(b4_id, vec![pay_alice(7)]);
+        wallet.sync(&MissingBody { node: &node, missing: b4_id });
+        assert_eq!((wallet.best_height(), wallet.best_hash()), (3, b3_id));
+        assert_eq!(wallet.net_worth(), 60);
+
+        wallet.sync(&node);
+        assert_eq!(wallet.best_height(), 5);
+        assert_eq!(wallet.net_worth(), 72);
+    }
+
+    #[test]
+    fn reorgs_find_the_fork_point_in_one_query() {
+        let mut node = MockNode::new();
+        let mut old_tip = node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+        let fork_id = old_tip;
+        for _ in 0..6 {
+            old_tip = node.add_block_as_best(old_tip, vec![Transaction::coinbase(Address::Alice, 1)]);
+        }
+        let mut wallet = wallet_with_alice();
+        wallet.sync(&node);
+        assert_eq!(wallet.best_height(), 7);
+
+        let new_tip = node.add_block(fork_id, vec![Transaction::coinbase(Address::Bob, BLOCK_REWARD)]);
+        node.set_best(new_tip);
+        let queries = node.how_many_queries();
+        let report = wallet.try_sync(&node).unwrap();
+        assert_eq!(report.reverted.len(), 6);
+        assert_eq!((wallet.best_hash(), wallet.net_worth()), (new_tip, BLOCK_REWARD));
+        // the missing blocks above and at the old tip, the fork point, the new block, and the end of the chain
+        assert_eq!(node.how_many_queries() - queries, 5);
+    }
+}
@@ -0,0 +1,117 @@
+//! Incoming payments listed for crediting by an exchange or other integrator.
+//!
+//! A deposit is a coin the wallet received from a transaction that spent none of its coins, so
+//! change and transfers between the wallet's own addresses are never listed. Once the integrator
+//! has credited a deposit it marks it with `mark_deposit_credited`; the marks are part of the
+//! exported state, so a restarted integrator does not credit a deposit twice.
+
+use bonecoin_core::*;
+
+use crate::{Direction, Wallet};
+
+/// A coin paid to one of the wallet's addresses by someone else.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Deposit {
+    /// The address the coin was paid to.
+    pub address: Address,
+    /// The id of the coin.
+    pub coin_id: CoinId,
+    /// The coin's value.
+    pub value: u64,
+    /// The asset the coin carries, or `None` for bones.
+    pub asset_id: Option<AssetId>,
+    /// The height of the block that paid the coin.
+    pub height: u64,
+    /// The number of blocks on top of that block, counting it, up to the wallet's best block.
+    pub confirmations: u64,
+}
+
+impl Wallet {
+    /// The deposits from blocks above `height` that have not been marked credited, in chain order.
+    ///
+    /// A deposit stays listed after the coin is spent. A reorg removes deposits whose block left the chain.
+    pub fn deposits_since(&self, height: u64) -> Vec<Deposit> {
+        let entries = self.history.entries();
+        entries[entries.partition_point(|entry| entry.height <= height)..]
+            .iter()
+            .filter(|entry| entry.direction == Direction::Incoming)
+            .flat_map(|entry| entry.received.iter().map(move |(coin_id, coin)| (entry.height, coin_id, coin)))
+            .filter(|(_, coin_id, _)| !self.credited_deposits.contains(coin_id))
+            .map(|(height, coin_id, coin)| Deposit {
+                address: coin.owner.clone(),
+                coin_id: *coin_id,
+                value: coin.value,
+                asset_id: coin.asset_id,
+                height,
+                confirmations: self.best_block_height + 1 - height,
+            })
+            .collect()
+    }
+
+    /// Mark a deposit as credited, so `deposits_since` no longer lists it.
+    pub fn mark_deposit_credited(&mut self, coin_id: CoinId) {
+        self.credited_deposits.insert(coin_id);
+    }
+
+    /// Whether the deposit was marked credited.
+    pub fn is_deposit_credited(&self, coin_id: &CoinId) -> bool {
+        self.credited_deposits.contains(coin_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice and Bob synced to three blocks: the first deposits 30 bones to Alice and
+    /// 12 to Bob, the second pays Eve with change back to the wallet, and the third is empty.
+    /// Returns the wallet and the depositing transaction.
+    fn three_blocks_after_two_deposits() -> (Wallet, Transaction) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice_and_bob();
+        let deposit = mint([(Address::Alice, 30), (Address::Bob, 12)]);
+        let b1 = node.add_block_as_best(Block::genesis().id(), vec![deposit.clone()]);
+        wallet.sync(&node);
+        let payment = wallet.create_automatic_transaction(Address::Eve, 10, 1).unwrap();
+        let b2 = node.add_block_as_best(b1, vec![payment]);
+        node.add_block_as_best(b2, vec![]);
+        wallet.sync(&node);
+        (wallet, deposit)
+    }
+
+    fn deposit(deposit: &Transaction, index: usize) -> Deposit {
+        let coin = &deposit.outputs[index];
+        Deposit {
+            address: coin.owner.clone(),
+            coin_id: deposit.coin_id(1, index),
+            value: coin.value,
+            asset_id: None,
+            height: 1,
+            confirmations: 3,
+        }
+    }
+
+    #[test]
+    fn deposits_are_listed_with_their_confirmations() {
+        let (wallet, received) = three_blocks_after_two_deposits();
+        // the change of the payment to Eve is not a deposit
+        assert_eq!(wallet.deposits_since(0), [deposit(&received, 0), deposit(&received, 1)]);
+    }
+
+    #[test]
+    fn deposits_at_or_below_the_given_height_are_left_out() {
+        let (wallet, _) = three_blocks_after_two_deposits();
+        assert!(wallet.deposits_since(1).is_empty());
+    }
+
+    #[test]
+    fn credited_deposits_are_no_longer_listed() {
+        let (mut wallet, received) = three_blocks_after_two_deposits();
+        wallet.mark_deposit_credited(received.coin_id(1, 0));
+        assert!(wallet.is_deposit_credited(&received.coin_id(1, 0)));
+        assert_eq!(wallet.deposits_since(0), [deposit(&received, 1)]);
+
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.deposits_since(0), [deposit(&received, 1)]);
+    }
+}
@@ -0,0 +1,157 @@
+//! Balance changes between an earlier point and the wallet's current state.
+//!
+//! The earlier point is either a `WalletSnapshot`, compared coin by coin, or a block height, answered
+//! from the history. Coins both received and spent after the earlier point appear in neither list.
+
+use std::collections::{BTreeMap, HashMap};
+
+use bonecoin_core::*;
+
+use crate::{Wallet, WalletSnapshot};
+
+/// The point a `BalanceDiff` starts from.
+#[derive(Clone, Debug)]
+pub enum DiffBase {
+    /// The wallet's coins when this snapshot was taken.
+    Snapshot(WalletSnapshot),
+    /// The wallet's coins after syncing the block at this height.
+    Height(u64),
+}
+
+impl From<&WalletSnapshot> for DiffBase {
+    fn from(snapshot: &WalletSnapshot) -> Self {
+        DiffBase::Snapshot(snapshot.clone())
+    }
+}
+
+impl From<u64> for DiffBase {
+    fn from(height: u64) -> Self {
+        DiffBase::Height(height)
+    }
+}
+
+/// How the wallet's coins changed since a `DiffBase`.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct BalanceDiff {
+    /// The height the diff starts from.
+    pub from_height: u64,
+    /// The wallet's best height when the diff was made.
+    pub to_height: u64,
+    /// The change in bones of every address that gained or lost coins. Addresses that broke even are left out.
+    pub net_change: BTreeMap<Address, i128>,
+    /// Coins the wallet holds now but did not hold at the start, ordered by id.
+    pub gained: Vec<(CoinId, Coin)>,
+    /// Coins the wallet held at the start but does not hold now, ordered by id.
+    pub lost: Vec<(CoinId, Coin)>,
+}
+
+impl BalanceDiff {
+    fn new(from_height: u64, to_height: u64, gained: Vec<(CoinId, Coin)>, lost: Vec<(CoinId, Coin)>) -> Self {
+        let mut net_change: BTreeMap<Address, i128> = BTreeMap::new();
+        for (_, coin) in &gained {
+            *net_change.entry(coin.owner.clone()).or_default() += coin.native_value() as i128;
+        }
+        for (_, coin) in &lost {
+            *net_change.entry(coin.owner.clone()).or_default() -= coin.native_value() as i128;
+        }
+        net_change.retain(|_, change| *change != 0);
+        BalanceDiff {
+            from_height,
+            to_height,
+            net_change,
+            gained,
+            lost,
+        }
+    }
+}
+
+impl Wallet {
+    /// Report how the wallet's coins changed since `since`, a snapshot or a height.
+    ///
+    /// A height diff only sees the history the wallet retains, so with `max_history_entries` set it
+    /// cannot reach further back than the oldest kept entry.
+    pub fn diff_since(&self, since: impl Into<DiffBase>) -> BalanceDiff {
+        match since.into() {
+            DiffBase::Snapshot(snapshot) => {
+                let now = self.coins.shared();
+                let before = snapshot.coins();
+                let gained = now.iter().filter(|(coin_id, _)| !before.contains_key(coin_id));
+                let lost = before.iter().filter(|(coin_id, _)| !now.contains_key(coin_id));
+                BalanceDiff::new(
+                    snapshot.best_height(),
+                    self.best_block_height,
+                    gained.map(|(coin_id, coin)| (*coin_id, coin.clone())).collect(),
+                    lost.map(|(coin_id, coin)| (*coin_id, coin.clone())).collect(),
+                )
+            }
+            DiffBase::Height(height) => {
+                let entries = self.history.entries();
+                let after = &entries[entries.partition_point(|entry| entry.height <= height)..];
+                let mut received: HashMap<CoinId, &Coin> = HashMap::new();
+                let mut lost = Vec::new();
+                for entry in after {
+                    for (coin_id, coin) in &entry.received {
+                        received.insert(*coin_id, coin);
+                    }
+                    for (coin_id, coin) in &entry.spent {
+                        if received.remove(coin_id).is_none() {
+                            lost.push((*coin_id, coin.clone()));
+                        }
+                    }
+                }
+                let mut gained: Vec<(CoinId, Coin)> =
+                    received.into_iter().map(|(coin_id, coin)| (coin_id, coin.clone())).collect();
+                gained.sort_by_key(|(coin_id, _)| *coin_id);
+                lost.sort_by_key(|(coin_id, _)| *coin_id);
+                BalanceDiff::new(height.min(self.best_block_height), self.best_block_height, gained, lost)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice and Bob synced past two more blocks after a snapshot at the first: in
+    /// the second Bob pays Eve and gets change, and in the third Alice receives a coin that is
+    /// spent again right away. Returns the wallet, the snapshot and Bob's payment.
+    fn two_blocks_after_a_snapshot() -> (Wallet, WalletSnapshot, Transaction) {
+        let (mut node, mut wallet) = make_one_block_blockchain();
+        let before = wallet.snapshot();
+
+        let payment = wallet.create_automatic_transaction(Address::Eve, 110, 0).unwrap();
+        let b2_id = node.add_block_as_best(wallet.best_hash(), vec![payment.clone()]);
+        let passing = mint([(Address::Alice, 7)]);
+        let passing_on = spend(passing.coin_id(3, 0), Address::Alice, []);
+        node.add_block_as_best(b2_id, vec![passing, passing_on]);
+        wallet.sync(&node);
+        (wallet, before, payment)
+    }
+
+    #[test]
+    fn nothing_changes_since_the_current_state() {
+        let (_, wallet) = make_one_block_blockchain();
+        assert_eq!(wallet.diff_since(&wallet.snapshot()), BalanceDiff { from_height: 1, to_height: 1, ..BalanceDiff::default() });
+    }
+
+    #[test]
+    fn diffs_list_the_coins_lost_and_gained_since_a_snapshot() {
+        let (wallet, before, payment) = two_blocks_after_a_snapshot();
+        let diff = wallet.diff_since(&before);
+        assert_eq!((diff.from_height, diff.to_height), (1, 3));
+        let mut spent: Vec<CoinId> = payment.inputs.iter().map(|input| input.coin_id).collect();
+        spent.sort();
+        assert_eq!(diff.lost.iter().map(|(coin_id, _)| *coin_id).collect::<Vec<_>>(), spent);
+        // the coin received and spent in between is neither
+        assert_eq!(diff.gained, vec![(payment.coin_id(2, 1), payment.outputs[1].clone())]);
+        assert_eq!(diff.net_change.values().sum::<i128>(), -110);
+    }
+
+    #[test]
+    fn diffs_since_a_height_match_diffs_since_a_snapshot_at_it() {
+        let (wallet, before, _) = two_blocks_after_a_snapshot();
+        assert_eq!(wallet.diff_since(1), wallet.diff_since(&before));
+        assert!(wallet.diff_since(3).gained.is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+//! Escrow payments held in a 2-of-3 multisig coin.
+//!
+//! The buyer funds a coin that buyer, seller, and arbiter jointly own. Any two of them can
+//! resolve it: usually the buyer and the seller agree to release the coin to the seller, and
+//! in a dispute the arbiter sides with one of them to either release or refund it.
+
+use bonecoin_core::*;
+
+use crate::{PartialTransaction, Wallet};
+
+/// A funded (or about to be funded) escrow.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Escrow {
+    /// The party paying, who receives the coin back on a refund.
+    pub buyer: Address,
+    /// The party being paid once the escrow is released.
+    pub seller: Address,
+    /// The party that resolves disputes.
+    pub arbiter: Address,
+    /// The number of bones held in escrow.
+    pub amount: u64,
+    /// The transaction creating the escrow coin as its first output.
+    pub funding: Transaction,
+}
+
+impl Escrow {
+    /// The 2-of-3 multisig address holding the escrow coin.
+    pub fn address(&self) -> Address {
+        Address::multisig(2, [self.buyer.clone(), self.seller.clone(), self.arbiter.clone()])
+    }
+
+    /// The id of the escrow coin once the funding transaction is included at `funded_at`.
+    pub fn coin_id(&self, funded_at: u64) -> CoinId {
+        self.funding.coin_id(funded_at, 0)
+    }
+
+    fn coin(&self) -> Coin {
+        Coin {
+            value: self.amount,
+            owner: self.address(),
+            asset_id: None,
+        }
+    }
+
+    /// The unsigned transaction paying the escrow coin, minus the tip, to `recipient`.
+    fn payout(&self, funded_at: u64, recipient: Address, burn_aka_tip: u64) -> WalletResult<PartialTransaction> {
+        let value = self.amount.checked_sub(burn_aka_tip).ok_or(WalletError::InsufficientFunds {
+            needed: burn_aka_tip,
+            available: self.amount,
+        })?;
+        if value == 0 {
+            return Err(WalletError::ZeroCoinValue);
+        }
+        let transaction = Transaction {
+            version: TRANSACTION_VERSION,
+            inputs: vec![Input {
+                coin_id: self.coin_id(funded_at),
+                signature: Signature::Invalid,
+            }],
+            outputs: vec![Coin {
+                value,
+                owner: recipient,
+                asset_id: None,
+            }],
+        };
+        Ok(PartialTransaction::with_input_coins(transaction, vec![self.coin()]))
+    }
+}
+
+impl Wallet {
+    /// Fund a 2-of-3 escrow of `amount` bones from this wallet, which must own the buyer address.
+    pub fn create_escrow(&self, buyer: Address, seller: Address, arbiter: Address, amount: u64) -> WalletResult<Escrow> {
+        if !self.addresses.contains(&buyer) {
+            return Err(WalletError::ForeignAddress(buyer));
+        }
+        let mut escrow = Escrow {
+            buyer,
+            seller,
+            arbiter,
+            amount,
+            funding: Transaction {
+                version: TRANSACTION_VERSION,
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+            },
+        };
+        escrow.funding = self.build_funding_transaction(escrow.address(), amount)?;
+        self.check_policy(&escrow.funding)?;
+        Ok(escrow)
+    }
+
+    /// Construct the transaction releasing the escrow to the seller, signed by this wallet if it is a party.
+    /// It needs the signature of one more party before it can be finalized.
+    pub fn release_escrow(&self, escrow: &Escrow, funded_at: u64, burn_aka_tip: u64) -> WalletResult<PartialTransaction> {
+        let mut partial = escrow.payout(funded_at, escrow.seller.clone(), burn_aka_tip)?;
+        self.sign_partial(&mut partial);
+        Ok(partial)
+    }
+
+    /// Construct the transaction refunding the escrow to the buyer, signed by this wallet if it is a party.
+    /// It needs the signature of one more party before it can be finalized.
+    pub fn refund_escrow(&self, escrow: &Escrow, funded_at: u64, burn_aka_tip: u64) -> WalletResult<PartialTransaction> {
+        let mut partial = escrow.payout(funded_at, escrow.buyer.clone(), burn_aka_tip)?;
+        self.sign_partial(&mut partial);
+        Ok(partial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// Alice, as the buyer, locks 60 of her 100 bones in an escrow with Bob as the seller and
+    /// Charlie as the arbiter, mined in block 2. Returns the node, the buyer, the seller, the
+    /// arbiter, and the escrow.
+    fn funded_escrow() -> (MockNode, Wallet, Wallet, Wallet, Escrow) {
+        let mut node = MockNode::new();
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 100)])]);
+        let mut buyer = wallet_with_alice();
+        let seller = Wallet::new(vec![Address::Bob].into_iter());
+        let arbiter = Wallet::new(vec![Address::Charlie].into_iter());
+        buyer.sync(&node);
+
+        let escrow = buyer.create_escrow(Address::Alice, Address::Bob, Address::Charlie, 60).unwrap();
+        node.add_block_as_best(b1_id, vec![escrow.funding.clone()]);
+        buyer.sync(&node);
+        (node, buyer, seller, arbiter, escrow)
+    }
+
+    #[test]
+    fn only_the_buyer_of_an_escrow_funds_it() {
+        let mut node = MockNode::new();
+        node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 100)])]);
+        let mut buyer = wallet_with_alice();
+        buyer.sync(&node);
+        assert_eq!(
+            buyer.create_escrow(Address::Bob, Address::Alice, Address::Charlie, 60).err(),
+            Some(WalletError::ForeignAddress(Address::Bob))
+        );
+    }
+
+    #[test]
+    fn the_funding_pays_the_escrow_address() {
+        let (_, buyer, _, _, escrow) = funded_escrow();
+        assert_eq!(escrow.funding.outputs[0].owner, escrow.address());
+        assert_eq!(buyer.net_worth(), 40);
+    }
+
+    #[test]
+    fn the_buyer_alone_cannot_take_the_coin_back() {
+        let (_, buyer, _, _, escrow) = funded_escrow();
+        assert!(!buyer.refund_escrow(&escrow, 2, 0).unwrap().is_complete());
+    }
+
+    #[test]
+    fn the_arbiter_can_side_with_the_buyer() {
+        let (_, buyer, _, arbiter, escrow) = funded_escrow();
+        let mut refund = buyer.refund_escrow(&escrow, 2, 0).unwrap();
+        assert_eq!(arbiter.sign_partial(&mut refund), 1);
+        assert!(refund.is_complete());
+    }
+
+    #[test]
+    fn buyer_and_seller_release_the_coin_to_the_seller() {
+        let (mut node, buyer, mut seller, _, escrow) = funded_escrow();
+        let mut release = buyer.release_escrow(&escrow, 2, 1).unwrap();
+        assert_eq!(release.clone().finalize(), None);
+        seller.sign_partial(&mut release);
+        let release = release.finalize().unwrap();
+        node.add_block_as_best(node.best_block_at_height(2).unwrap(), vec![release]);
+        seller.sync(&node);
+        assert_eq!(seller.net_worth(), 59);
+    }
+}
@@ -0,0 +1,155 @@
+//! Events the wallet raises while it syncs, for applications that react to chain activity.
+//!
+//! Events are queued on the wallet until the application collects them with `take_events`.
+//! Applications running the wallet on another thread can instead ask for a `notification_receiver`,
+//! which gets a copy of every event as it is raised.
+//!
+//! Both are bounded by `EVENT_CAPACITY`. When the queue is full the oldest event is dropped, since
+//! whoever polls it is interested in the latest state, unless it is urgent and others are not. When the channel is full the new event is
+//! dropped instead, because a sender cannot take events back out of a channel; the wallet counts
+//! these in `dropped_notifications` so the receiver can tell it missed some and rescan.
+//! Neither is part of the wallet snapshot.
+
+use std::sync::mpsc::{self, Receiver, TrySendError};
+
+use bonecoin_core::*;
+
+use crate::{Alert, Wallet};
+
+/// The most events the queue and the notification channel hold before dropping events.
+pub const EVENT_CAPACITY: usize = 1024;
+
+/// Something the wallet noticed while syncing.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum WalletEvent {
+    /// Sync moved the best block to this height and id.
+    Synced { height: u64, block_id: BlockId },
+    /// A reorg made sync revert the wallet to this height before following the new branch.
+    RolledBack { to_height: u64 },
+    /// The wallet received a coin.
+    CoinReceived { coin_id: CoinId, coin: Coin },
+    /// One of the wallet's coins was spent by this transaction.
+    CoinSpent { coin_id: CoinId, tx_id: TransactionId },
+    /// A registered transaction was included in the block at this height.
+    TransactionConfirmed { tx_id: TransactionId, height: u64 },
+    /// A registered transaction can no longer be expected to confirm, because a conflicting transaction
+    /// was mined or its expected heights passed. `height` is the best height at which this was noticed.
+    TransactionEvicted { tx_id: TransactionId, height: u64 },
+    /// The sync tripped one of the wallet's alert rules.
+    Alert(Alert),
+    /// A coin of a watchtower address was spent by a transaction the wallet did not author.
+    UnauthorizedSpend { coin_id: CoinId, address: Address, tx_id: TransactionId, height: u64 },
+}
+
+impl WalletEvent {
+    /// Whether the event calls for action at once, so a full queue drops other events first.
+    pub fn is_urgent(&self) -> bool {
+        matches!(self, WalletEvent::UnauthorizedSpend { .. })
+    }
+}
+
+impl Wallet {
+    /// Remove and return the queued events, oldest first.
+    pub fn take_events(&mut self) -> Vec<WalletEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// A receiver that gets every event raised from now on. Asking again disconnects the previous receiver.
+    pub fn notification_receiver(&mut self) -> Receiver<WalletEvent> {
+        let (sender, receiver) = mpsc::sync_channel(EVENT_CAPACITY);
+        self.notifier = Some(sender);
+        self.dropped_notifications = 0;
+        receiver
+    }
+
+    /// The number of events the current notification receiver missed because its channel was full.
+    pub fn dropped_notifications(&self) -> u64 {
+        self.dropped_notifications
+    }
+
+    pub(crate) fn emit(&mut self, event: WalletEvent) {
+        if let Some(sender) = &self.notifier {
+            match sender.try_send(event.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => self.dropped_notifications += 1,
+                Err(TrySendError::Disconnected(_)) => self.notifier = None,
+            }
+        }
+        if self.events.len() == EVENT_CAPACITY {
+            let oldest = self.events.iter().position(|queued| !queued.is_urgent()).unwrap_or(0);
+            self.events.remove(oldest);
+        }
+        self.events.push_back(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// Fill both the notification channel and the event queue of `wallet` past their capacity.
+    fn overflow(wallet: &mut Wallet) {
+        for height in 0..EVENT_CAPACITY as u64 + 3 {
+            wallet.emit(WalletEvent::RolledBack { to_height: height });
+        }
+    }
+
+    #[test]
+    fn notifications_reach_a_receiver_on_another_thread() {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+        let receiver = wallet.notification_receiver();
+
+        let received = mint([(Address::Alice, 10)]);
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![received.clone()]);
+        let mut wallet = std::thread::spawn(move || {
+            wallet.sync(&node);
+            wallet
+        })
+        .join()
+        .unwrap();
+
+        let expected = vec![
+            WalletEvent::CoinReceived { coin_id: received.coin_id(1, 0), coin: received.outputs[0].clone() },
+            WalletEvent::Synced { height: 1, block_id: b1_id },
+        ];
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), expected);
+        assert_eq!(wallet.take_events(), expected);
+    }
+
+    #[test]
+    fn a_full_channel_drops_the_newest_notifications() {
+        let mut wallet = wallet_with_alice();
+        let receiver = wallet.notification_receiver();
+        overflow(&mut wallet);
+        assert_eq!(wallet.dropped_notifications(), 3);
+        assert_eq!(receiver.try_iter().last(), Some(WalletEvent::RolledBack { to_height: EVENT_CAPACITY as u64 - 1 }));
+    }
+
+    #[test]
+    fn a_full_queue_drops_the_oldest_events() {
+        let mut wallet = wallet_with_alice();
+        overflow(&mut wallet);
+        let queued = wallet.take_events();
+        assert_eq!(queued.len(), EVENT_CAPACITY);
+        assert_eq!(queued[0], WalletEvent::RolledBack { to_height: 3 });
+    }
+
+    #[test]
+    fn a_new_receiver_disconnects_the_old_one() {
+        let mut wallet = wallet_with_alice();
+        let receiver = wallet.notification_receiver();
+        let replacement = wallet.notification_receiver();
+        assert!(receiver.recv().is_err());
+        wallet.emit(WalletEvent::RolledBack { to_height: 0 });
+        assert_eq!(replacement.try_recv(), Ok(WalletEvent::RolledBack { to_height: 0 }));
+    }
+
+    #[test]
+    fn dropped_receivers_are_forgotten() {
+        let mut wallet = wallet_with_alice();
+        drop(wallet.notification_receiver());
+        wallet.emit(WalletEvent::RolledBack { to_height: 0 });
+        assert!(wallet.notifier.is_none());
+    }
+}
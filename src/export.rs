@@ -0,0 +1,240 @@
+//! Export of the wallet's coins and history for spreadsheets and accounting tools.
+//!
+//! Both formats are produced by hand since the crate has no serialization dependency.
+//! Every row or object carries the same fields, so a CSV export and a JSON export of the same
+//! wallet describe exactly the same data.
+
+use std::collections::HashMap;
+
+use bonecoin_core::*;
+
+use crate::pricing::price_of_block;
+use crate::{Decimal, Direction, PriceSource, Wallet};
+
+/// The output format of an export.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ExportFormat {
+    /// Comma separated values with a header row.
+    Csv,
+    /// A JSON array of objects.
+    Json,
+}
+
+/// A single exported value, kept typed so each format can quote it correctly.
+enum Field {
+    Number(u64),
+    Text(String),
+    OptionalText(Option<String>),
+    OptionalDecimal(Option<Decimal>),
+}
+
+impl Wallet {
+    /// Export the wallet's unspent coins ordered by creation height.
+    ///
+    /// Fields: coin id, value, owner, creation height, confirmations, label.
+    pub fn export_coins(&self, format: ExportFormat) -> String {
+        let created_at: HashMap<CoinId, u64> = self
+            .history
+            .entries()
+            .iter()
+            .flat_map(|entry| entry.received.iter().map(|(coin_id, _)| (*coin_id, entry.height)))
+            .collect();
+
+        let mut coins: Vec<(&CoinId, &Coin, u64)> = self
+            .coins
+            .iter()
+            .map(|(coin_id, coin)| (coin_id, coin, created_at.get(coin_id).copied().unwrap_or(0)))
+            .collect();
+        coins.sort_by_key(|(coin_id, _, height)| (*height, **coin_id));
+
+        let rows = coins
+            .into_iter()
+            .map(|(coin_id, coin, height)| {
+                vec![
+                    ("coin_id", Field::Text(coin_id.to_string())),
+                    ("value", Field::Number(coin.value)),
+                    ("owner", Field::Text(coin.owner.to_string())),
+                    ("height", Field::Number(height)),
+                    ("confirmations", Field::Number(self.best_block_height + 1 - height)),
+                    ("label", Field::OptionalText(self.coin_label(coin_id).map(str::to_owned))),
+                ]
+            })
+            .collect();
+        render(format, &["coin_id", "value", "owner", "height", "confirmations", "label"], rows)
+    }
+
+    /// Export the wallet history oldest first.
+    ///
+    /// Fields: transaction id, block id, height, timestamp, direction, value received, value spent,
+    /// confirmations, label.
+    pub fn export_history(&self, format: ExportFormat) -> String {
+        self.render_history(format, None)
+    }
+
+    /// Export the wallet history like `export_history`, with three extra fields valuing each entry
+    /// at the price of its block: price, fiat value received, fiat value spent.
+    /// The extra fields are empty (or `null`) where the source has no price.
+    pub fn export_history_in_fiat(&self, format: ExportFormat, source: &dyn PriceSource) -> String {
+        self.render_history(format, Some(source))
+    }
+
+    fn render_history(&self, format: ExportFormat, source: Option<&dyn PriceSource>) -> String {
+        let rows = self
+            .history
+            .entries()
+            .iter()
+            .map(|entry| {
+                let direction = match entry.direction {
+                    Direction::Incoming => "incoming",
+                    Direction::Outgoing => "outgoing",
+                    Direction::SelfTransfer => "self",
+                    Direction::Internal => "internal",
+                };
+                let mut row = vec![
+                    ("tx_id", Field::Text(entry.tx_id.to_string())),
+                    ("block_id", Field::Text(entry.block_id.to_string())),
+                    ("height", Field::Number(entry.height)),
+                    ("timestamp", Field::Number(entry.timestamp)),
+                    ("direction", Field::Text(direction.to_owned())),
+                    ("received", Field::Number(entry.value_received())),
+                    ("spent", Field::Number(entry.value_spent())),
+                    ("confirmations", Field::Number(self.best_block_height + 1 - entry.height)),
+                    ("label", Field::OptionalText(self.tx_label(&entry.tx_id).map(str::to_owned))),
+                ];
+                if let Some(source) = source {
+                    let price = price_of_block(source, entry.height, entry.timestamp);
+                    row.push(("price", Field::OptionalDecimal(price)));
+                    row.push(("received_fiat", Field::OptionalDecimal(price.map(|p| p.times(entry.value_received())))));
+                    row.push(("spent_fiat", Field::OptionalDecimal(price.map(|p| p.times(entry.value_spent())))));
+                }
+                row
+            })
+            .collect();
+        let mut header = vec![
+            "tx_id", "block_id", "height", "timestamp", "direction", "received", "spent", "confirmations", "label",
+        ];
+        if source.is_some() {
+            header.extend(["price", "received_fiat", "spent_fiat"]);
+        }
+        render(format, &header, rows)
+    }
+}
+
+fn render(format: ExportFormat, header: &[&str], rows: Vec<Vec<(&str, Field)>>) -> String {
+    match format {
+        ExportFormat::Csv => {
+            let mut out = header.join(",");
+            out.push('\n');
+            for row in rows {
+                let cells: Vec<String> = row.into_iter().map(|(_, field)| csv_cell(field)).collect();
+                out.push_str(&cells.join(","));
+                out.push('\n');
+            }
+            out
+        }
+        ExportFormat::Json => {
+            let objects: Vec<String> = rows
+                .into_iter()
+                .map(|row| {
+                    let members: Vec<String> = row
+                        .into_iter()
+                        .map(|(name, field)| format!("{}:{}", json_string(name), json_value(field)))
+                        .collect();
+                    format!("{{{}}}", members.join(","))
+                })
+                .collect();
+            format!("[{}]", objects.join(","))
+        }
+    }
+}
+
+fn csv_cell(field: Field) -> String {
+    match field {
+        Field::Number(n) => n.to_string(),
+        Field::Text(text) | Field::OptionalText(Some(text)) => {
+            if text.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", text.replace('"', "\"\""))
+            } else {
+                text
+            }
+        }
+        Field::OptionalDecimal(Some(n)) => n.to_string(),
+        Field::OptionalText(None) | Field::OptionalDecimal(None) => String::new(),
+    }
+}
+
+fn json_value(field: Field) -> String {
+    match field {
+        Field::Number(n) => n.to_string(),
+        Field::Text(text) | Field::OptionalText(Some(text)) => json_string(&text),
+        Field::OptionalDecimal(Some(n)) => n.to_string(),
+        Field::OptionalText(None) | Field::OptionalDecimal(None) => "null".to_owned(),
+    }
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// The one-block wallet with one more block on top, and Bob's coin labeled with a comma and
+    /// quotes. Returns the wallet and Bob's coin.
+    fn wallet_with_a_labeled_coin() -> (Wallet, CoinId) {
+        let (mut node, mut wallet) = make_one_block_blockchain();
+        let b1_id = node.best_block_at_height(1).unwrap();
+        node.add_block_as_best(b1_id, vec![]);
+        wallet.sync(&node);
+
+        let (bob_coin, _) = wallet.all_coins_of(Address::Bob).unwrap().into_iter().next().unwrap();
+        wallet.set_coin_label(&bob_coin, "rent, \"march\"").unwrap();
+        (wallet, bob_coin)
+    }
+
+    #[test]
+    fn coins_are_exported_as_csv_with_quoted_labels() {
+        let (wallet, bob_coin) = wallet_with_a_labeled_coin();
+        let csv = wallet.export_coins(ExportFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "coin_id,value,owner,height,confirmations,label");
+        assert_eq!(lines.len(), 4);
+        assert!(lines.contains(&format!("{},120,Bob,1,2,\"rent, \"\"march\"\"\"", bob_coin).as_str()));
+    }
+
+    #[test]
+    fn coins_are_exported_as_json_with_escaped_labels() {
+        let (wallet, bob_coin) = wallet_with_a_labeled_coin();
+        let json = wallet.export_coins(ExportFormat::Json);
+        assert!(json.starts_with('[') && json.ends_with(']'));
+        assert!(json.contains(&format!(
+            "{{\"coin_id\":\"{}\",\"value\":120,\"owner\":\"Bob\",\"height\":1,\"confirmations\":2,\"label\":\"rent, \\\"march\\\"\"}}",
+            bob_coin
+        )));
+        assert_eq!(json.matches("\"label\":null").count(), 2);
+    }
+
+    #[test]
+    fn history_is_exported_as_csv() {
+        let (wallet, _) = wallet_with_a_labeled_coin();
+        let history = wallet.export_history(ExportFormat::Csv);
+        let lines: Vec<&str> = history.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].ends_with(",1,0,incoming,235,0,2,"));
+    }
+}
@@ -0,0 +1,272 @@
+//! Tracking transactions that were created outside the wallet.
+//!
+//! A registered transaction may have been authored on another device or handed over by a
+//! counterparty. Sync reports when it is mined, and evicts it once a conflicting transaction
+//! spends one of its inputs or the best chain grows past the heights it was expected at.
+//! A transaction that is mined after all is confirmed regardless, and a reorg that removes the
+//! deciding block makes the transaction pending again.
+
+use std::ops::RangeInclusive;
+
+use bonecoin_core::*;
+
+use crate::{Wallet, WalletEvent};
+
+/// Where a registered transaction stands on the best chain the wallet synced.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RegisteredStatus {
+    /// Not mined yet, and still expected to be.
+    Pending,
+    /// Mined in the block at this height.
+    Confirmed { height: u64 },
+    /// No longer expected to be mined, as noticed at this height.
+    Evicted { height: u64 },
+}
+
+/// A transaction the wallet was told about, together with what sync found out about it.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct RegisteredTransaction {
+    /// The transaction as registered.
+    pub transaction: Transaction,
+    /// The heights at which the transaction is expected to be mined.
+    pub expected_heights: RangeInclusive<u64>,
+    /// The current status of the transaction.
+    pub status: RegisteredStatus,
+}
+
+impl Wallet {
+    /// Track the confirmation of a transaction created outside the wallet. Returns false if it was already registered.
+    /// Only blocks synced from now on are inspected, so a transaction that was already mined is only
+    /// found by `resolve_registered`.
+    pub fn register_transaction(&mut self, transaction: Transaction, expected_heights: RangeInclusive<u64>) -> bool {
+        let tx_id = transaction.id();
+        if self.registered.contains_key(&tx_id) {
+            return false;
+        }
+        self.registered.insert(
+            tx_id,
+            RegisteredTransaction {
+                transaction,
+                expected_heights,
+                status: RegisteredStatus::Pending,
+            },
+        );
+        true
+    }
+
+    /// Stop tracking a registered transaction. Returns false if it was not registered.
+    pub fn unregister_transaction(&mut self, tx_id: &TransactionId) -> bool {
+        self.registered.remove(tx_id).is_some()
+    }
+
+    /// A registered transaction and its status, or `None` if it is not registered.
+    pub fn registered_transaction(&self, tx_id: &TransactionId) -> Option<&RegisteredTransaction> {
+        self.registered.get(tx_id)
+    }
+
+    /// Look up the registered transactions not confirmed yet in the node's transaction index, and
+    /// confirm those mined at or below the wallet's best height. Nothing is looked up while the
+    /// wallet's best block is not on the node's chain, and nodes without a transaction index find nothing.
+    pub fn resolve_registered<Node: NodeEndpoint>(&mut self, node: &Node) {
+        if node.best_block_at_height(self.best_block_height) != Some(self.best_block_hash) {
+            return;
+        }
+        let mut unconfirmed: Vec<TransactionId> = self
+            .registered
+            .iter()
+            .filter(|(_, registered)| !matches!(registered.status, RegisteredStatus::Confirmed { .. }))
+            .map(|(tx_id, _)| *tx_id)
+            .collect();
+        unconfirmed.sort();
+        for tx_id in unconfirmed {
+            let Some((_, height, _)) = node.transaction(&tx_id).filter(|(_, height, _)| *height <= self.best_block_height) else {
+                continue;
+            };
+            if let Some(registered) = self.registered.get_mut(&tx_id) {
+                registered.status = RegisteredStatus::Confirmed { height };
+                self.emit(WalletEvent::TransactionConfirmed { tx_id, height });
+            }
+        }
+    }
+
+    /// Update the registered transactions for a transaction mined at `height`.
+    pub(crate) fn observe_registered(&mut self, transaction: &Transaction, height: u64) {
+        let tx_id = transaction.id();
+        if let Some(registered) = self.registered.get_mut(&tx_id) {
+            // a late confirmation overrides an eviction
+            if !matches!(registered.status, RegisteredStatus::Confirmed { .. }) {
+                registered.status = RegisteredStatus::Confirmed { height };
+                self.emit(WalletEvent::TransactionConfirmed { tx_id, height });
+            }
+        }
+
+        let mut conflicted: Vec<TransactionId> = self
+            .registered
+            .iter_mut()
+            .filter(|(id, registered)| {
+                **id != tx_id
+                    && registered.status == RegisteredStatus::Pending
+                    && registered
+                        .transaction
+                        .iter_input_coin_ids()
+                        .any(|coin_id| transaction.iter_input_coin_ids().any(|spent| spent == coin_id))
+            })
+            .map(|(id, registered)| {
+                registered.status = RegisteredStatus::Evicted { height };
+                *id
+            })
+            .collect();
+        conflicted.sort();
+        for tx_id in conflicted {
+            self.emit(WalletEvent::TransactionEvicted { tx_id, height });
+        }
+    }
+
+    /// Evict the pending registered transactions whose expected heights are all below the best height.
+    pub(crate) fn expire_registered(&mut self) {
+        let height = self.best_block_height;
+        let mut expired: Vec<TransactionId> = self
+            .registered
+            .iter_mut()
+            .filter(|(_, registered)| {
+                registered.status == RegisteredStatus::Pending && *registered.expected_heights.end() < height
+            })
+            .map(|(id, registered)| {
+                registered.status = RegisteredStatus::Evicted { height };
+                *id
+            })
+            .collect();
+        expired.sort();
+        for tx_id in expired {
+            self.emit(WalletEvent::TransactionEvicted { tx_id, height });
+        }
+    }
+}
+
+impl RegisteredTransaction {
+    /// Make the transaction pending again if it was decided above `height`.
+    pub(crate) fn forget_above(&mut self, height: u64) {
+        match self.status {
+            RegisteredStatus::Confirmed { height: decided } | RegisteredStatus::Evicted { height: decided }
+                if decided > height =>
+            {
+                self.status = RegisteredStatus::Pending;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice synced to an empty first block, with three registered transactions: an
+    /// invoice and a double spend of it, both allowed until height 5, and a `late` transaction
+    /// allowed only at height 1, all of which spend coins the chain never created.
+    fn registered() -> (MockNode, Wallet, BlockId, [Transaction; 3]) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![]);
+        wallet.sync(&node);
+        wallet.take_events();
+
+        // both spend the dummy coin, so mining one evicts the other
+        let invoice = mint([(Address::Custom(1), 1)]);
+        let double_spend = mint([(Address::Custom(2), 2)]);
+        let late = Transaction {
+            inputs: vec![Input {
+                coin_id: marker_tx().coin_id(9, 0),
+                signature: Signature::Invalid,
+            }],
+            ..mint([])
+        };
+        wallet.register_transaction(invoice.clone(), 1..=5);
+        wallet.register_transaction(double_spend.clone(), 1..=5);
+        wallet.register_transaction(late.clone(), 1..=1);
+        (node, wallet, b1_id, [invoice, double_spend, late])
+    }
+
+    fn status(wallet: &Wallet, tx: &Transaction) -> RegisteredStatus {
+        wallet.registered_transaction(&tx.id()).unwrap().status
+    }
+
+    #[test]
+    fn transactions_are_registered_once() {
+        let (_, mut wallet, _, [invoice, ..]) = registered();
+        assert!(!wallet.register_transaction(invoice.clone(), 1..=5));
+        assert_eq!(status(&wallet, &invoice), RegisteredStatus::Pending);
+    }
+
+    #[test]
+    fn mining_confirms_a_transaction_and_evicts_its_double_spends() {
+        let (mut node, mut wallet, b1_id, [invoice, double_spend, late]) = registered();
+        let b2_id = node.add_block_as_best(b1_id, vec![invoice.clone()]);
+        wallet.sync(&node);
+        assert_eq!(
+            wallet.take_events(),
+            vec![
+                WalletEvent::TransactionConfirmed { tx_id: invoice.id(), height: 2 },
+                WalletEvent::TransactionEvicted { tx_id: double_spend.id(), height: 2 },
+                WalletEvent::TransactionEvicted { tx_id: late.id(), height: 2 },
+                WalletEvent::Synced { height: 2, block_id: b2_id },
+            ]
+        );
+        assert!(wallet.take_events().is_empty());
+        assert_eq!(status(&wallet, &invoice), RegisteredStatus::Confirmed { height: 2 });
+    }
+
+    #[test]
+    fn registrations_survive_export() {
+        let (mut node, mut wallet, b1_id, [invoice, ..]) = registered();
+        node.add_block_as_best(b1_id, vec![invoice.clone()]);
+        wallet.sync(&node);
+
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.registered_transaction(&invoice.id()), wallet.registered_transaction(&invoice.id()));
+    }
+
+    #[test]
+    fn reorgs_make_registered_transactions_pending_again() {
+        let (mut node, mut wallet, b1_id, [invoice, double_spend, late]) = registered();
+        node.add_block_as_best(b1_id, vec![invoice.clone()]);
+        wallet.sync(&node);
+        wallet.take_events();
+
+        // a reorg to a branch without the invoice makes both spends of the dummy coin pending again
+        let fork_id = node.add_block(b1_id, vec![]);
+        let fork_id = node.add_block_as_best(fork_id, vec![late.clone()]);
+        wallet.sync(&node);
+        assert_eq!(status(&wallet, &invoice), RegisteredStatus::Pending);
+        assert_eq!(status(&wallet, &double_spend), RegisteredStatus::Pending);
+        // late is evicted again once its heights pass, but still confirms when it is mined after all
+        assert_eq!(
+            wallet.take_events(),
+            vec![
+                WalletEvent::RolledBack { to_height: 1 },
+                WalletEvent::TransactionEvicted { tx_id: late.id(), height: 2 },
+                WalletEvent::TransactionConfirmed { tx_id: late.id(), height: 3 },
+                WalletEvent::Synced { height: 3, block_id: fork_id },
+            ]
+        );
+    }
+
+    #[test]
+    fn unregistered_transactions_are_no_longer_reported() {
+        let (mut node, mut wallet, b1_id, [invoice, double_spend, late]) = registered();
+        assert!(wallet.unregister_transaction(&invoice.id()));
+        assert!(!wallet.unregister_transaction(&invoice.id()));
+
+        let b2_id = node.add_block_as_best(b1_id, vec![invoice.clone()]);
+        wallet.sync(&node);
+        assert!(wallet.registered_transaction(&invoice.id()).is_none());
+        assert_eq!(
+            wallet.take_events(),
+            vec![
+                WalletEvent::TransactionEvicted { tx_id: double_spend.id(), height: 2 },
+                WalletEvent::TransactionEvicted { tx_id: late.id(), height: 2 },
+                WalletEvent::Synced { height: 2, block_id: b2_id },
+            ]
+        );
+    }
+}
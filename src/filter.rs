@@ -0,0 +1,47 @@
+//! Bloom filters describing the wallet to a node without listing its addresses.
+//!
+//! A light wallet can hand `address_filter` to `NodeEndpoint::relevant_transactions` and receive
+//! only the transactions that may concern it. A higher false positive rate reveals less about the
+//! wallet at the cost of downloading more unrelated transactions.
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+impl Wallet {
+    /// A filter matching transactions that pay one of the wallet's addresses or spend one of its coins.
+    ///
+    /// Only the wallet's own addresses are inserted, so coins the address scheme owns through other
+    /// addresses, such as multisig coins, are only matched once the wallet holds them.
+    pub fn address_filter(&self, false_positive_rate: f64) -> BloomFilter {
+        let mut filter = BloomFilter::new(self.addresses.len() + self.coins.len(), false_positive_rate);
+        for address in &self.addresses {
+            filter.insert(address);
+        }
+        for coin_id in self.coins.keys() {
+            filter.insert(coin_id);
+        }
+        filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    #[test]
+    fn address_filter_lets_the_node_pick_the_wallets_transactions() {
+        let (mut node, wallet) = make_one_block_blockchain();
+        let payment = wallet.create_automatic_transaction(Address::Eve, 110, 0).unwrap();
+        let pay = |owner: Address, value: u64| spend(marker_tx().coin_id(value, 0), Address::Charlie, [(owner, value)]);
+        let unrelated: Vec<Transaction> = (1..=20).map(|value| pay(Address::Custom(1_000 + value), value)).collect();
+        let mut body = vec![pay(Address::Alice, 40), payment.clone()];
+        body.extend(unrelated);
+        let b2_id = node.add_block_as_best(wallet.best_hash(), body);
+
+        let filter = wallet.address_filter(0.0001);
+        assert!(filter.contains(&Address::Bob));
+        let relevant = node.relevant_transactions(&b2_id, &filter).unwrap();
+        assert_eq!(relevant, vec![pay(Address::Alice, 40), payment]);
+    }
+}
@@ -0,0 +1,106 @@
+//! A stable identifier for a wallet, for telling apart the state of different wallets.
+//!
+//! The fingerprint of a wallet with a keychain is derived from the keychain's first address, so it
+//! does not change as sync derives more addresses, and a wallet restored from the seed has the same
+//! one. Without a keychain it is derived from the wallet's addresses, so it changes if they do.
+//! Either way it depends on nothing local to the machine or the session.
+//!
+//! Stores remember the fingerprint of the wallet that first committed to them, and refuse to be
+//! attached to another wallet. `Wallet::import_state_of` checks a snapshot the same way.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use bonecoin_core::codec::{Decode, DecodeError, Encode};
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// Identifies a wallet, see `Wallet::fingerprint`.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Ord, PartialOrd)]
+pub struct WalletId(pub u64);
+
+impl fmt::Display for WalletId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl Encode for WalletId {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.0.encode_to(out);
+    }
+}
+
+impl Decode for WalletId {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(WalletId(u64::decode_from(input)?))
+    }
+}
+
+impl Wallet {
+    /// The wallet's fingerprint: derived from its keychain if it has one, and from its addresses otherwise.
+    pub fn fingerprint(&self) -> WalletId {
+        let bytes = match self.keychain() {
+            Some(keychain) => [b"keychain".as_slice(), &keychain.derive(0).encode()].concat(),
+            None => [b"addresses".as_slice(), &self.addresses.iter().cloned().collect::<BTreeSet<_>>().encode()].concat(),
+        };
+        WalletId(hash_preimage(&bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    fn fingerprint_of(addresses: impl IntoIterator<Item = Address>) -> WalletId {
+        Wallet::new(addresses.into_iter()).fingerprint()
+    }
+
+    #[test]
+    fn fingerprints_depend_on_the_addresses_but_not_their_order() {
+        assert_eq!(fingerprint_of([Address::Alice, Address::Bob]), fingerprint_of([Address::Bob, Address::Alice]));
+        assert_ne!(fingerprint_of([Address::Alice, Address::Bob]), fingerprint_of([Address::Alice]));
+    }
+
+    #[test]
+    fn fingerprints_print_as_sixteen_digits() {
+        assert_eq!(fingerprint_of([Address::Alice]).to_string().len(), 16);
+    }
+
+    #[test]
+    fn keychain_fingerprints_depend_only_on_the_seed() {
+        let mut hd = Wallet::from_keychain(HdKeychain::new(7));
+        let hd_id = hd.fingerprint();
+        for _ in 0..=DEFAULT_GAP_LIMIT {
+            hd.next_receive_address();
+        }
+        assert_eq!(hd.fingerprint(), hd_id);
+        assert_eq!(Wallet::from_keychain(HdKeychain { gap_limit: 3, ..HdKeychain::new(7) }).fingerprint(), hd_id);
+        assert_ne!(Wallet::from_keychain(HdKeychain::new(8)).fingerprint(), hd_id);
+    }
+
+    #[test]
+    fn snapshots_of_another_wallet_are_refused() {
+        let id = fingerprint_of([Address::Alice]);
+        let hd_id = Wallet::from_keychain(HdKeychain::new(7)).fingerprint();
+        let snapshot = wallet_with_alice().export_state();
+        assert_eq!(Wallet::import_state_of(&snapshot, id).unwrap().fingerprint(), id);
+        assert_eq!(Wallet::import_state_of(&snapshot, hd_id).err(), Some(StateError::WrongWallet { expected: hd_id, found: id }));
+    }
+
+    #[test]
+    fn stores_of_another_wallet_are_refused() {
+        let id = fingerprint_of([Address::Alice]);
+        assert_eq!(MemoryStore::new().wallet_id(), Ok(None));
+        let mut opened = Wallet::with_store([Address::Alice].into_iter(), Box::new(MemoryStore::new())).unwrap();
+        let store = opened.take_store().unwrap().unwrap();
+        assert_eq!(store.wallet_id(), Ok(Some(id)));
+
+        let store = Wallet::with_store([Address::Alice].into_iter(), store).unwrap().take_store().unwrap().unwrap();
+        assert_eq!(
+            Wallet::with_store([Address::Bob].into_iter(), store).err(),
+            Some(StoreError::WrongWallet { expected: fingerprint_of([Address::Bob]), found: id })
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Graphs of how value flowed between coins, for tracing where funds came from and went.
+//!
+//! A flow graph links coins to the transactions that created and spent them, starting from a coin
+//! or from every coin of an address and following the lineage up to a number of transactions away
+//! in both directions. The wallet only knows its own coins, so its graphs stop wherever value
+//! crossed to or from someone else; the indexer knows every coin on its chain. Graphs render as DOT
+//! for Graphviz or as JSON.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use bonecoin_core::*;
+
+use crate::export::json_string;
+use crate::{Indexer, Wallet};
+
+/// Where a flow graph starts.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum FlowRoot {
+    /// A single coin.
+    Coin(CoinId),
+    /// Every coin the address ever owned.
+    Address(Address),
+}
+
+/// How a flow graph is rendered.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum GraphFormat {
+    /// A Graphviz digraph, coins as ellipses and transactions as boxes.
+    Dot,
+    /// An object with `coins`, `transactions`, and `edges` arrays.
+    Json,
+}
+
+/// A coin or a transaction in a flow graph.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub enum FlowNode {
+    Coin(CoinId),
+    Transaction(TransactionId),
+}
+
+/// The coins and transactions reached from a root, with an edge from every coin to the transaction
+/// that spent it and from every transaction to the coins it created.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct FlowGraph {
+    /// The coins reached, with their values and owners.
+    pub coins: BTreeMap<CoinId, Coin>,
+    /// The transactions reached.
+    pub transactions: BTreeSet<TransactionId>,
+    /// The edges between them, each pointing the way the value flowed.
+    pub edges: BTreeSet<(FlowNode, FlowNode)>,
+}
+
+impl FlowGraph {
+    /// Render the graph in the given format.
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::Json => self.to_json(),
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut out = String::from("digraph flows {\n");
+        for (coin_id, coin) in &self.coins {
+            out.push_str(&format!(
+                "  \"{}\" [shape=ellipse,label=\"{}\\n{} to {}\"];\n",
+                node_id(FlowNode::Coin(*coin_id)),
+                coin_id,
+                coin_value(coin),
+                coin.owner
+            ));
+        }
+        for tx_id in &self.transactions {
+            out.push_str(&format!("  \"{}\" [shape=box,label=\"{}\"];\n", node_id(FlowNode::Transaction(*tx_id)), tx_id));
+        }
+        for (from, to) in &self.edges {
+            out.push_str(&format!("  \"{}\" -> \"{}\";\n", node_id(*from), node_id(*to)));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn to_json(&self) -> String {
+        let coins: Vec<String> = self
+            .coins
+            .iter()
+            .map(|(coin_id, coin)| {
+                format!(
+                    "{{\"id\":{},\"value\":{},\"owner\":{},\"asset_id\":{}}}",
+                    json_string(&coin_id.to_string()),
+                    coin.value,
+                    json_string(&coin.owner.to_string()),
+                    coin.asset_id.map_or("null".to_owned(), |asset_id| json_string(&asset_id.to_string()))
+                )
+            })
+            .collect();
+        let transactions: Vec<String> = self.transactions.iter().map(|tx_id| json_string(&tx_id.to_string())).collect();
+        let edges: Vec<String> = self
+            .edges
+            .iter()
+            .map(|(from, to)| format!("{{\"from\":{},\"to\":{}}}", json_string(&node_id(*from)), json_string(&node_id(*to))))
+            .collect();
+        format!(
+            "{{\"coins\":[{}],\"transactions\":[{}],\"edges\":[{}]}}",
+            coins.join(","),
+            transactions.join(","),
+            edges.join(",")
+        )
+    }
+}
+
+fn node_id(node: FlowNode) -> String {
+    match node {
+        FlowNode::Coin(coin_id) => format!("coin:{coin_id}"),
+        FlowNode::Transaction(tx_id) => format!("tx:{tx_id}"),
+    }
+}
+
+fn coin_value(coin: &Coin) -> String {
+    match coin.asset_id {
+        Some(asset_id) => format!("{} of {}", coin.value, asset_id),
+        None => format!("{} bones", coin.value),
+    }
+}
+
+/// What a flow graph is built from: the coins some party knows and how they are linked.
+pub(crate) trait FlowSource {
+    fn coin(&self, coin_id: &CoinId) -> Option<Coin>;
+
+    /// The transaction that created the coin, and the one that spent it if any.
+    fn links(&self, coin_id: &CoinId) -> (Option<TransactionId>, Option<TransactionId>);
+
+    /// The known coins a transaction spent and created.
+    fn coins_of_transaction(&self, tx_id: &TransactionId) -> (Vec<CoinId>, Vec<CoinId>);
+
+    /// The known coins an address ever owned.
+    fn coins_of_address(&self, address: &Address) -> Vec<CoinId>;
+}
+
+/// Follow the lineage of the root's coins up to `depth` transactions away.
+fn build_graph(source: &dyn FlowSource, root: &FlowRoot, depth: usize) -> FlowGraph {
+    let roots = match root {
+        FlowRoot::Coin(coin_id) => vec![*coin_id],
+        FlowRoot::Address(address) => source.coins_of_address(address),
+    };
+    let mut graph = FlowGraph::default();
+    let mut queue: VecDeque<(CoinId, usize)> = VecDeque::new();
+    for coin_id in roots {
+        if let Some(coin) = source.coin(&coin_id) {
+            graph.coins.insert(coin_id, coin);
+            queue.push_back((coin_id, 0));
+        }
+    }
+
+    while let Some((coin_id, distance)) = queue.pop_front() {
+        if distance == depth {
+            continue;
+        }
+        let (created_by, spent_by) = source.links(&coin_id);
+        for tx_id in created_by.into_iter().chain(spent_by) {
+            if !graph.transactions.insert(tx_id) {
+                continue;
+            }
+            let (spent, created) = source.coins_of_transaction(&tx_id);
+            let linked = spent
+                .into_iter()
+                .map(|coin_id| (coin_id, (FlowNode::Coin(coin_id), FlowNode::Transaction(tx_id))))
+                .chain(created.into_iter().map(|coin_id| (coin_id, (FlowNode::Transaction(tx_id), FlowNode::Coin(coin_id)))));
+            for (linked_id, edge) in linked {
+                let Some(coin) = source.coin(&linked_id) else { continue };
+                graph.edges.insert(edge);
+                if graph.coins.insert(linked_id, coin).is_none() {
+                    queue.push_back((linked_id, distance + 1));
+                }
+            }
+        }
+    }
+    graph
+}
+
+impl FlowSource for Indexer {
+    fn coin(&self, coin_id: &CoinId) -> Option<Coin> {
+        Indexer::coin(self, coin_id).cloned()
+    }
+
+    fn links(&self, coin_id: &CoinId) -> (Option<TransactionId>, Option<TransactionId>) {
+        (self.creating_transaction(coin_id), self.spending_transaction(coin_id))
+    }
+
+    fn coins_of_transaction(&self, tx_id: &TransactionId) -> (Vec<CoinId>, Vec<CoinId>) {
+        let Some((height, tx)) = self.transaction(tx_id) else {
+            return (Vec::new(), Vec::new());
+        };
+        (tx.iter_input_coin_ids().collect(), tx.iter_output_coins_and_ids(height).map(|(coin_id, _)| coin_id).collect())
+    }
+
+    fn coins_of_address(&self, address: &Address) -> Vec<CoinId> {
+        let mut coins: Vec<CoinId> = self
+            .transactions_of(address)
+            .iter()
+            .filter_map(|tx_id| self.transaction(tx_id))
+            .flat_map(|(height, tx)| tx.iter_output_coins_and_ids(height).collect::<Vec<_>>())
+            .filter(|(_, coin)| coin.owner == *address)
+            .map(|(coin_id, _)| coin_id)
+            .collect();
+        coins.sort();
+        coins.dedup();
+        coins
+    }
+}
+
+/// The wallet's coins and their links, gathered from its history and unspent coins.
+pub(crate) struct WalletFlows {
+    coins: HashMap<CoinId, Coin>,
+    created_by: HashMap<CoinId, TransactionId>,
+    spent_by: HashMap<CoinId, TransactionId>,
+    transactions: HashMap<TransactionId, (Vec<CoinId>, Vec<CoinId>)>,
+}
+
+impl WalletFlows {
+    pub(crate) fn new(wallet: &Wallet) -> Self {
+        let mut flows = WalletFlows {
+            coins: wallet.coins.iter().map(|(coin_id, coin)| (*coin_id, coin.clone())).collect(),
+            created_by: HashMap::new(),
+            spent_by: HashMap::new(),
+            transactions: HashMap::new(),
+        };
+        for entry in wallet.history.entries() {
+            for (coin_id, coin) in &entry.received {
+                flows.coins.insert(*coin_id, coin.clone());
+                flows.created_by.insert(*coin_id, entry.tx_id);
+            }
+            for (coin_id, coin) in &entry.spent {
+                flows.coins.insert(*coin_id, coin.clone());
+                flows.spent_by.insert(*coin_id, entry.tx_id);
+            }
+            flows.transactions.insert(
+                entry.tx_id,
+                (entry.spent.iter().map(|(coin_id, _)| *coin_id).collect(), entry.received.iter().map(|(coin_id, _)| *coin_id).collect()),
+            );
+        }
+        flows
+    }
+}
+
+impl FlowSource for WalletFlows {
+    fn coin(&self, coin_id: &CoinId) -> Option<Coin> {
+        self.coins.get(coin_id).cloned()
+    }
+
+    fn links(&self, coin_id: &CoinId) -> (Option<TransactionId>, Option<TransactionId>) {
+        (self.created_by.get(coin_id).copied(), self.spent_by.get(coin_id).copied())
+    }
+
+    fn coins_of_transaction(&self, tx_id: &TransactionId) -> (Vec<CoinId>, Vec<CoinId>) {
+        self.transactions.get(tx_id).cloned().unwrap_or_default()
+    }
+
+    fn coins_of_address(&self, address: &Address) -> Vec<CoinId> {
+        let mut coins: Vec<CoinId> = self.coins.iter().filter(|(_, coin)| coin.owner == *address).map(|(coin_id, _)| *coin_id).collect();
+        coins.sort();
+        coins
+    }
+}
+
+impl Indexer {
+    /// The flow graph of the root's coins up to `depth` transactions away, over the indexed chain.
+    pub fn flow_graph(&self, root: &FlowRoot, depth: usize) -> FlowGraph {
+        build_graph(self, root, depth)
+    }
+
+    /// Render the flow graph of the root's coins up to `depth` transactions away.
+    pub fn export_flow_graph(&self, root: &FlowRoot, depth: usize, format: GraphFormat) -> String {
+        self.flow_graph(root, depth).render(format)
+    }
+}
+
+impl Wallet {
+    /// The flow graph of the root's coins up to `depth` transactions away, over the coins the
+    /// wallet has held. Coins of others never appear, even as inputs or outputs of the wallet's
+    /// transactions.
+    pub fn flow_graph(&self, root: &FlowRoot, depth: usize) -> FlowGraph {
+        build_graph(&WalletFlows::new(self), root, depth)
+    }
+
+    /// Render the flow graph of the root's coins up to `depth` transactions away.
+    pub fn export_flow_graph(&self, root: &FlowRoot, depth: usize, format: GraphFormat) -> String {
+        self.flow_graph(root, depth).render(format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use crate::test_support::*;
+
+    /// Charlie mines block 1 and splits the reward into 30 for Dave and 15 for Eve in block 2,
+    /// and Dave forwards 25 of his to Alice in block 3. Returns an indexer and a wallet of Dave
+    /// and Alice synced to it, the three transactions, and their coins in order.
+    fn dave_forwards_to_alice() -> (Indexer, Wallet, [Transaction; 3], [CoinId; 4]) {
+        let mut node = MockNode::new();
+        let mut indexer = Indexer::new();
+        let mut wallet = Wallet::new([Address::Dave, Address::Alice].into_iter());
+
+        let coinbase = Transaction::coinbase(Address::Charlie, BLOCK_REWARD);
+        let split = spend(coinbase.coin_id(1, 0), Address::Charlie, [(Address::Dave, 30), (Address::Eve, 15)]);
+        let forward = spend(split.coin_id(2, 0), Address::Dave, [(Address::Alice, 25)]);
+
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![coinbase.clone()]);
+        let b2_id = node.add_block_as_best(b1_id, vec![split.clone()]);
+        node.add_block_as_best(b2_id, vec![forward.clone()]);
+        indexer.sync(&node);
+        wallet.sync(&node);
+
+        let coins = [coinbase.coin_id(1, 0), split.coin_id(2, 0), split.coin_id(2, 1), forward.coin_id(3, 0)];
+        (indexer, wallet, [coinbase, split, forward], coins)
+    }
+
+    fn coin(coin_id: CoinId) -> FlowNode {
+        FlowNode::Coin(coin_id)
+    }
+
+    fn tx(tx: &Transaction) -> FlowNode {
+        FlowNode::Transaction(tx.id())
+    }
+
+    #[test]
+    fn indexer_graphs_follow_coin_lineage_up_to_the_depth() {
+        let (indexer, _, [coinbase, split, forward], [minted, dave_coin, eve_coin, alice_coin]) = dave_forwards_to_alice();
+
+        let graph = indexer.flow_graph(&FlowRoot::Coin(minted), 1);
+        assert_eq!(graph.coins.keys().copied().collect::<BTreeSet<_>>(), BTreeSet::from([minted, dave_coin, eve_coin]));
+        assert_eq!(graph.transactions, BTreeSet::from([coinbase.id(), split.id()]));
+        assert_eq!(
+            graph.edges,
+            BTreeSet::from([
+                (tx(&coinbase), coin(minted)),
+                (coin(minted), tx(&split)),
+                (tx(&split), coin(dave_coin)),
+                (tx(&split), coin(eve_coin)),
+            ])
+        );
+
+        let deeper = indexer.flow_graph(&FlowRoot::Coin(minted), 2);
+        assert!(deeper.edges.contains(&(tx(&forward), coin(alice_coin))));
+        assert!(indexer.flow_graph(&FlowRoot::Coin(minted), 0).transactions.is_empty());
+    }
+
+    #[test]
+    fn wallet_graphs_stop_at_coins_the_wallet_does_not_own() {
+        let (_, wallet, [_, split, forward], [_, dave_coin, _, alice_coin]) = dave_forwards_to_alice();
+
+        let graph = wallet.flow_graph(&FlowRoot::Address(Address::Dave), 5);
+        assert_eq!(graph.coins.keys().copied().collect::<BTreeSet<_>>(), BTreeSet::from([dave_coin, alice_coin]));
+        assert_eq!(
+            graph.edges,
+            BTreeSet::from([(tx(&split), coin(dave_coin)), (coin(dave_coin), tx(&forward)), (tx(&forward), coin(alice_coin))])
+        );
+    }
+
+    #[test]
+    fn graphs_render_as_dot_and_json() {
+        let (_, wallet, [_, _, forward], [_, dave_coin, _, alice_coin]) = dave_forwards_to_alice();
+
+        let dot = wallet.export_flow_graph(&FlowRoot::Address(Address::Dave), 5, GraphFormat::Dot);
+        assert!(dot.starts_with("digraph flows {"));
+        assert!(dot.contains(&format!("\"coin:{dave_coin}\" -> \"tx:{}\";", forward.id())));
+
+        let json = wallet.export_flow_graph(&FlowRoot::Coin(alice_coin), 1, GraphFormat::Json);
+        assert!(json.starts_with("{\"coins\":[{\"id\":"));
+        assert!(json.contains(&format!("{{\"from\":\"tx:{}\",\"to\":\"coin:{alice_coin}\"}}", forward.id())));
+    }
+}
@@ -0,0 +1,188 @@
+//! Entry points for fuzzing sync against arbitrary chain histories.
+//!
+//! `sync_mutations` reads a byte string as a sequence of `ChainMutation`s, applies them one by one to
+//! a `MockNode`, syncs a wallet after each, and panics as soon as the wallet disagrees with a ledger
+//! replayed independently from the node's best chain. Every byte string decodes to some sequence, so
+//! a fuzzer can feed its input straight in. The module is only compiled for the crate's own tests and
+//! when the `fuzz` feature is enabled; the cargo-fuzz targets under `fuzz/` enable it.
+
+use std::collections::BTreeMap;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// Addresses the chain pays. The wallet owns the first two.
+const ADDRESSES: [Address; 4] = [Address::Alice, Address::Bob, Address::Charlie, Address::Eve];
+
+/// One change to the node's chain, decoded from fuzzer input.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ChainMutation {
+    /// Mine a block on the known block at `parent`, counted in the order blocks were added and
+    /// wrapping around, paying the coinbase to `miner`. Only a block on the best block includes the
+    /// queued transactions.
+    AddBlock { parent: u8, miner: u8 },
+    /// Make the known block at `block`, counted like `AddBlock::parent`, the node's best.
+    /// Queued transactions are dropped, since they may spend coins the new chain lacks.
+    SetBest { block: u8 },
+    /// Queue a transaction spending the best chain's unspent coin at `coin`, counted in coin id order
+    /// and wrapping around, that pays `amount` wrapped to the coin's value to `recipient` and the rest
+    /// back to the coin's owner. Does nothing when no unspent coin is left.
+    AddTransaction { coin: u8, recipient: u8, amount: u8 },
+}
+
+impl ChainMutation {
+    /// Decode mutations from the bytes, three per mutation. A short last mutation is padded with zeroes.
+    pub fn decode_all(data: &[u8]) -> Vec<ChainMutation> {
+        data.chunks(3)
+            .map(|chunk| {
+                let byte = |i: usize| chunk.get(i).copied().unwrap_or(0);
+                match byte(0) % 3 {
+                    0 => ChainMutation::AddBlock { parent: byte(1), miner: byte(2) },
+                    1 => ChainMutation::SetBest { block: byte(1) },
+                    _ => ChainMutation::AddTransaction { coin: byte(1), recipient: byte(1) ^ byte(2), amount: byte(2) },
+                }
+            })
+            .collect()
+    }
+}
+
+/// Decode the bytes with `ChainMutation::decode_all` and check sync against them with `check_mutations`.
+pub fn sync_mutations(data: &[u8]) {
+    check_mutations(&ChainMutation::decode_all(data));
+}
+
+/// Apply the mutations to a fresh `MockNode`, syncing a wallet after each one, and panic if the
+/// wallet ever disagrees with the node's best chain.
+///
+/// The wallet only keeps undo records for two blocks, so deeper reorgs take the resync from genesis.
+pub fn check_mutations(mutations: &[ChainMutation]) {
+    let mut node = MockNode::new();
+    let mut blocks = vec![Block::genesis().id()];
+    let mut best = Block::genesis().id();
+    let mut queued: Vec<Transaction> = Vec::new();
+    let mut wallet = Wallet::builder()
+        .addresses(ADDRESSES[..2].iter().cloned())
+        .max_reorg_depth(2)
+        .build()
+        .expect("a wallet with addresses and the default scheme builds");
+
+    for mutation in mutations {
+        match *mutation {
+            ChainMutation::AddBlock { parent, miner } => {
+                let parent = blocks[usize::from(parent) % blocks.len()];
+                let body = if parent == best { std::mem::take(&mut queued) } else { Vec::new() };
+                let miner = ADDRESSES[usize::from(miner) % ADDRESSES.len()].clone();
+                let id = node.add_block_with_coinbase(parent, miner, body);
+                if !blocks.contains(&id) {
+                    blocks.push(id);
+                }
+            }
+            ChainMutation::SetBest { block } => {
+                best = blocks[usize::from(block) % blocks.len()];
+                node.set_best(best);
+                queued.clear();
+            }
+            ChainMutation::AddTransaction { coin, recipient, amount } => {
+                let mut unspent = best_chain_coins(&node);
+                for coin_id in queued.iter().flat_map(Transaction::iter_input_coin_ids) {
+                    unspent.remove(&coin_id);
+                }
+                if unspent.is_empty() {
+                    continue;
+                }
+                let index = usize::from(coin) % unspent.len();
+                let (coin_id, coin) = unspent.into_iter().nth(index).expect("the index wraps around the coins");
+                let paid = 1 + u64::from(amount) % coin.value;
+                let mut outputs = vec![Coin { value: paid, owner: ADDRESSES[usize::from(recipient) % ADDRESSES.len()].clone(), ..coin.clone() }];
+                if paid < coin.value {
+                    outputs.push(Coin { value: coin.value - paid, ..coin.clone() });
+                }
+                queued.push(Transaction {
+                    version: TRANSACTION_VERSION,
+                    inputs: vec![Input { coin_id, signature: Signature::Valid(coin.owner.clone()) }],
+                    outputs,
+                });
+            }
+        }
+        wallet.sync(&node);
+        check_invariants(&wallet, &node, best);
+    }
+}
+
+/// Replay the node's best chain into its unspent coins.
+fn best_chain_coins(node: &MockNode) -> BTreeMap<CoinId, Coin> {
+    let mut unspent = BTreeMap::new();
+    let mut height = 1;
+    while let Some(block_id) = node.best_block_at_height(height) {
+        let block = node.entire_block(&block_id).expect("the node serves every block on its best chain");
+        for tx in &block.body {
+            for coin_id in tx.iter_input_coin_ids() {
+                assert!(unspent.remove(&coin_id).is_some(), "the chain only spends unspent coins");
+            }
+            unspent.extend(tx.iter_output_coins_and_ids(height));
+        }
+        height += 1;
+    }
+    unspent
+}
+
+fn check_invariants(wallet: &Wallet, node: &MockNode, best: BlockId) {
+    // fuzz targets usually build with optimizations, where sync skips this check
+    if let Err(violation) = wallet.check_invariants() {
+        panic!("{violation}");
+    }
+    let best_height = node.entire_block(&best).expect("the best block is known").number;
+    assert_eq!(wallet.best_block_height, best_height, "the wallet did not sync to the best height");
+    assert_eq!(wallet.best_block_hash, best, "the wallet did not sync to the best block");
+
+    let expected: BTreeMap<CoinId, Coin> = best_chain_coins(node)
+        .into_iter()
+        .filter(|(_, coin)| wallet.addresses.contains(&coin.owner))
+        .collect();
+    let held: BTreeMap<CoinId, Coin> = wallet.coins.iter().map(|(coin_id, coin)| (*coin_id, coin.clone())).collect();
+    assert_eq!(held, expected, "the wallet's coins differ from the best chain's at height {best_height}");
+
+    let entries = wallet.history.entries();
+    assert!(entries.windows(2).all(|pair| pair[0].height <= pair[1].height), "history is out of chain order");
+    assert!(entries.iter().all(|entry| entry.height <= best_height), "history reaches past the best block");
+    let received: u64 = entries.iter().map(|entry| entry.value_received()).sum();
+    let spent: u64 = entries.iter().map(|entry| entry.value_spent()).sum();
+    assert_eq!(received - spent, expected.values().map(Coin::native_value).sum::<u64>(), "history does not add up to the balance");
+}
+
+#[cfg(test)]
+mod tests {
+    use bonecoin_core::rng::SplitMix64;
+
+    use super::*;
+
+    #[test]
+    fn reorgs_past_a_payment_keep_the_wallet_consistent() {
+        // mine five blocks, pay out of the first coinbase, then switch to a branch forking at genesis
+        let mut mutations: Vec<ChainMutation> = (0..5u8)
+            .flat_map(|i| [ChainMutation::AddBlock { parent: i, miner: i }, ChainMutation::SetBest { block: i + 1 }])
+            .collect();
+        mutations.push(ChainMutation::AddTransaction { coin: 0, recipient: 1, amount: 20 });
+        mutations.push(ChainMutation::AddBlock { parent: 5, miner: 2 });
+        mutations.push(ChainMutation::SetBest { block: 6 });
+        mutations.push(ChainMutation::AddBlock { parent: 0, miner: 1 });
+        mutations.push(ChainMutation::SetBest { block: 7 });
+        check_mutations(&mutations);
+    }
+
+    #[test]
+    fn short_last_mutations_are_padded() {
+        assert_eq!(ChainMutation::decode_all(&[2, 7]), vec![ChainMutation::AddTransaction { coin: 7, recipient: 7, amount: 0 }]);
+    }
+
+    #[test]
+    fn random_input_keeps_the_wallet_consistent() {
+        sync_mutations(&[]);
+        for seed in 0..20 {
+            let mut rng = SplitMix64::new(seed);
+            let data: Vec<u8> = (0..300).map(|_| rng.next_u64() as u8).collect();
+            sync_mutations(&data);
+        }
+    }
+}
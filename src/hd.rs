@@ -0,0 +1,218 @@
+//! Addresses derived from a seed, discovered during sync.
+//!
+//! An `HdKeychain` derives a sequence of addresses from a seed, so a wallet can be restored from the
+//! seed alone. The wallet owns the addresses up to `gap_limit` past the last one that received a coin.
+//! When sync sees a coin paid to one of them, it derives the next addresses to keep the gap, and
+//! looks through the blocks it already synced for payments to the new ones. If there are any, the
+//! wallet rescans from the first such block, which is reported like a reorg with `RolledBack`.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use bonecoin_core::rng::SplitMix64;
+use bonecoin_core::*;
+
+use crate::{SyncReport, Wallet, WalletEvent};
+
+/// The number of unused addresses kept past the last used one by default.
+pub const DEFAULT_GAP_LIMIT: usize = 20;
+
+/// Derives the wallet's addresses from a seed.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct HdKeychain {
+    /// The secret all addresses are derived from.
+    pub seed: u64,
+    /// How many unused addresses the wallet keeps past the last used one.
+    pub gap_limit: usize,
+    /// The height of the first block that can pay the keychain's addresses. Rescans start there.
+    pub birth_height: u64,
+}
+
+impl HdKeychain {
+    /// A keychain with the default gap limit, whose addresses may have been paid since genesis.
+    pub fn new(seed: u64) -> Self {
+        HdKeychain {
+            seed,
+            gap_limit: DEFAULT_GAP_LIMIT,
+            birth_height: 0,
+        }
+    }
+
+    /// The address at `index`. The same seed always derives the same addresses.
+    pub fn derive(&self, index: usize) -> Address {
+        Address::Custom(SplitMix64::new(SplitMix64::new(self.seed).next_u64() ^ index as u64).next_u64())
+    }
+}
+
+/// Leaves out the seed, so logging a keychain does not leak it.
+impl fmt::Debug for HdKeychain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HdKeychain")
+            .field("gap_limit", &self.gap_limit)
+            .field("birth_height", &self.birth_height)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The addresses a wallet has derived from its keychain so far.
+#[derive(Clone, Debug)]
+pub(crate) struct HdAddresses {
+    pub(crate) keychain: HdKeychain,
+    /// Every derived address, in derivation order.
+    pub(crate) derived: Vec<Address>,
+    pub(crate) indexes: HashMap<Address, usize>,
+    /// One past the index of the last address that received a coin.
+    pub(crate) used: usize,
+}
+
+impl HdAddresses {
+    pub(crate) fn new(keychain: HdKeychain) -> Self {
+        let mut addresses = HdAddresses {
+            keychain,
+            derived: Vec::new(),
+            indexes: HashMap::new(),
+            used: 0,
+        };
+        addresses.derive_to(keychain.gap_limit);
+        addresses
+    }
+
+    /// Derive addresses until there are `count`, returning the new ones.
+    pub(crate) fn derive_to(&mut self, count: usize) -> Vec<Address> {
+        let start = self.derived.len();
+        for index in start..count {
+            let address = self.keychain.derive(index);
+            self.indexes.insert(address.clone(), index);
+            self.derived.push(address);
+        }
+        self.derived[start..].to_vec()
+    }
+}
+
+impl Wallet {
+    /// Create a wallet owning the first `gap_limit` addresses of the keychain. Sync derives more as they get used.
+    pub fn from_keychain(keychain: HdKeychain) -> Self {
+        let mut wallet = Wallet::new(std::iter::empty());
+        wallet.attach_keychain(keychain);
+        wallet
+    }
+
+    /// Own the keychain's addresses from now on, alongside the wallet's other addresses.
+    pub(crate) fn attach_keychain(&mut self, keychain: HdKeychain) {
+        let hd = HdAddresses::new(keychain);
+        self.addresses.extend(hd.derived.iter().cloned());
+        self.hd = Some(hd);
+    }
+
+    /// The wallet's keychain, if it derives its addresses from one.
+    pub fn keychain(&self) -> Option<&HdKeychain> {
+        self.hd.as_ref().map(|hd| &hd.keychain)
+    }
+
+    /// The addresses derived from the keychain so far, in derivation order.
+    pub fn derived_addresses(&self) -> &[Address] {
+        self.hd.as_ref().map_or(&[], |hd| &hd.derived)
+    }
+
+    /// Keep the gap after the derived addresses `block` paid, and rescan if the blocks already synced
+    /// paid any of the newly derived addresses. Called by sync right after applying `block`, with
+    /// the sync's report so far, which records the blocks the rescan rolls back.
+    pub(crate) fn discover_addresses<Node: NodeEndpoint>(&mut self, node: &Node, block: &Block, report: &mut SyncReport) {
+        let Some(hd) = self.hd.as_mut() else {
+            return;
+        };
+        let used = block
+            .body
+            .iter()
+            .flat_map(|transaction| &transaction.outputs)
+            .filter_map(|coin| hd.indexes.get(&coin.owner))
+            .map(|index| index + 1)
+            .max();
+        let Some(used) = used.filter(|used| *used > hd.used) else {
+            return;
+        };
+        hd.used = used;
+        let fresh = hd.derive_to(used + hd.keychain.gap_limit);
+        let birth_height = hd.keychain.birth_height;
+        if fresh.is_empty() {
+            return;
+        }
+        self.addresses.extend(fresh.iter().cloned());
+
+        // the blocks synced so far were only checked against the addresses derived back then
+        let first_payment = (birth_height.max(1)..=self.best_block_height).find(|height| {
+            let block = node.best_block_at_height(*height).and_then(|block_id| node.entire_block(&block_id));
+            // a block that cannot be checked is rescanned to be safe
+            block.is_none_or(|block| {
+                block.body.iter().flat_map(|transaction| &transaction.outputs).any(|coin| fresh.contains(&coin.owner))
+            })
+        });
+        if let Some(height) = first_payment {
+            #[cfg(feature = "tracing")]
+            tracing::info!(from_height = height, derived = fresh.len(), "rescanning for derived addresses");
+            while self.best_block_height >= height {
+                let Some(undone) = self.undo_last_block() else {
+                    break;
+                };
+                report.undo(undone);
+            }
+            if self.best_block_height >= height {
+                self.reset_to_genesis();
+                report.reset();
+            }
+            self.emit(WalletEvent::RolledBack { to_height: self.best_block_height });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    fn keychain() -> HdKeychain {
+        HdKeychain {
+            gap_limit: 3,
+            ..HdKeychain::new(42)
+        }
+    }
+
+    /// A node of two blocks paying 7 bones to the keychain's address of index 4, then 5 bones to
+    /// the one of index 2, so the first coin is only reachable once index 2 is known to be used.
+    fn node_paying_past_the_gap() -> MockNode {
+        let pay = |index: usize, value: u64| mint([(keychain().derive(index), value)]);
+        let mut node = MockNode::new();
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![pay(4, 7)]);
+        node.add_block_as_best(b1_id, vec![pay(2, 5)]);
+        node
+    }
+
+    #[test]
+    fn wallets_derive_the_gap_limit_up_front() {
+        assert_eq!(Wallet::from_keychain(keychain()).derived_addresses().len(), 3);
+    }
+
+    #[test]
+    fn addresses_past_the_gap_are_discovered_by_rescanning() {
+        let mut wallet = Wallet::from_keychain(keychain());
+        wallet.sync(&node_paying_past_the_gap());
+        assert_eq!(wallet.net_worth(), 12);
+        assert_eq!(wallet.best_height(), 2);
+        assert_eq!(wallet.derived_addresses().len(), 8);
+        assert_eq!(wallet.derived_addresses()[4], keychain().derive(4));
+        assert!(wallet.take_events().contains(&WalletEvent::RolledBack { to_height: 0 }));
+    }
+
+    #[test]
+    fn the_keychain_and_derived_addresses_survive_export() {
+        let mut wallet = Wallet::from_keychain(keychain());
+        wallet.sync(&node_paying_past_the_gap());
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.keychain(), Some(&keychain()));
+        assert_eq!(restored.derived_addresses(), wallet.derived_addresses());
+    }
+
+    #[test]
+    fn debug_output_leaves_out_the_seed() {
+        assert!(!format!("{:?}", keychain()).contains("42"));
+    }
+}
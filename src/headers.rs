@@ -0,0 +1,181 @@
+//! The headers of the blocks the wallet applied most recently, for spotting reorgs without asking the node.
+//!
+//! Sync starts by fetching the node's block after the wallet's best one. Usually its parent is the
+//! wallet's best block and there is nothing to undo. Otherwise the node's blocks are followed back
+//! through their parents until one of them is in the header window: that is the fork point, found
+//! without another query, and the blocks fetched on the way are applied without being asked for
+//! again. Only a fork below the window, or a node whose chain got shorter than the wallet's, costs a
+//! `common_ancestor` query.
+//!
+//! The window covers the blocks the undo records cover, so every fork point found in it can be
+//! rolled back to. It is not part of the exported state; a restored wallet asks the node until it
+//! has synced a few blocks.
+
+use std::collections::VecDeque;
+
+use bonecoin_core::*;
+
+use crate::delta::StateDelta;
+use crate::Wallet;
+
+/// Where a block sits in the chain.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub(crate) struct BlockHeader {
+    pub(crate) id: BlockId,
+    pub(crate) parent: BlockId,
+    pub(crate) number: u64,
+}
+
+/// The headers of applied blocks, oldest first, with no gaps.
+#[derive(Clone, Default, Debug)]
+pub(crate) struct HeaderWindow {
+    headers: VecDeque<BlockHeader>,
+}
+
+impl HeaderWindow {
+    /// Remember the header of a block applied on top of the newest one.
+    pub(crate) fn push(&mut self, delta: &StateDelta) {
+        if self.headers.back().is_some_and(|newest| newest.id != delta.parent.1) {
+            self.headers.clear(); // the window must stay one chain
+        }
+        self.headers.push_back(BlockHeader {
+            id: delta.block_id,
+            parent: delta.parent.1,
+            number: delta.height,
+        });
+    }
+
+    /// Keep only the newest `len` headers.
+    pub(crate) fn keep_newest(&mut self, len: usize) {
+        let excess = self.headers.len().saturating_sub(len);
+        self.headers.drain(..excess);
+    }
+
+    /// Forget the headers of blocks above `height`, which were rolled back.
+    pub(crate) fn truncate_above(&mut self, height: u64) {
+        while self.headers.back().is_some_and(|header| header.number > height) {
+            self.headers.pop_back();
+        }
+    }
+
+    /// Forget every header, when the undo records are dropped.
+    pub(crate) fn clear(&mut self) {
+        self.headers.clear();
+    }
+
+    /// The height of the block with this id, if it is in the window.
+    fn height_of(&self, id: &BlockId) -> Option<u64> {
+        self.headers.iter().rev().find(|header| header.id == *id).map(|header| header.number)
+    }
+}
+
+/// The node's chain above the point where it forks from the wallet's.
+pub(crate) struct Fork {
+    /// The height of the newest block both chains share.
+    pub(crate) height: u64,
+    /// The node's blocks above the fork point fetched while looking for it, oldest first.
+    pub(crate) blocks: Vec<(BlockId, Block)>,
+    /// Whether the node had no block above the last of them, so there is nothing to ask for after it.
+    pub(crate) at_tip: bool,
+}
+
+impl Wallet {
+    /// Find where the node's chain forks from the wallet's using the header window, querying only
+    /// for the node's block after the wallet's best one, and for the best one if there is none.
+    ///
+    /// Returns `None` if the fork point is below the window or the node's chain ends below the
+    /// wallet's best height, so the node has to be asked.
+    pub(crate) fn local_fork<Node: NodeEndpoint>(&self, node: &Node) -> Option<Fork> {
+        let known = |id: &BlockId| {
+            if *id == self.best_block_hash {
+                Some(self.best_block_height)
+            } else {
+                self.headers.height_of(id)
+            }
+        };
+        let (mut block_id, mut number, at_tip) = match node.best_block_at_height(self.best_block_height + 1) {
+            Some(block_id) => (block_id, self.best_block_height + 1, false),
+            None => match node.best_block_at_height(self.best_block_height)? {
+                block_id if block_id == self.best_block_hash => {
+                    return Some(Fork { height: self.best_block_height, blocks: Vec::new(), at_tip: true });
+                }
+                block_id => (block_id, self.best_block_height, true),
+            },
+        };
+
+        let oldest = self.headers.headers.front().map_or(self.best_block_height, |header| header.number);
+        let mut blocks = Vec::new();
+        loop {
+            // the parent of a block at the oldest height is older than the window
+            if number <= oldest {
+                return None;
+            }
+            let block = node.entire_block(&block_id).filter(|block| block.number == number)?;
+            let parent = block.parent;
+            blocks.push((block_id, block));
+            if let Some(height) = known(&parent) {
+                blocks.reverse();
+                return (height + 1 == number).then_some(Fork { height, blocks, at_tip });
+            }
+            block_id = parent;
+            number -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice synced to four blocks, the first of which rewards her.
+    fn four_blocks() -> (MockNode, Wallet) {
+        let mut node = MockNode::new();
+        let mut tip = node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+        for _ in 0..3 {
+            tip = node.add_block_as_best(tip, vec![]);
+        }
+        let mut wallet = wallet_with_alice();
+        wallet.sync(&node);
+        (node, wallet)
+    }
+
+    /// The number of queries `wallet` makes of `node` to sync.
+    fn queries_to_sync(node: &MockNode, wallet: &mut Wallet) -> u64 {
+        let queries = node.how_many_queries();
+        wallet.sync(node);
+        node.how_many_queries() - queries
+    }
+
+    #[test]
+    fn syncs_without_new_blocks_check_the_next_block_and_the_tip() {
+        let (node, mut wallet) = four_blocks();
+        assert_eq!(queries_to_sync(&node, &mut wallet), 2);
+    }
+
+    #[test]
+    fn new_blocks_cost_a_query_each_and_one_for_the_end_of_the_chain() {
+        let (mut node, mut wallet) = four_blocks();
+        let a5_id = node.add_block_as_best(wallet.best_hash(), vec![]);
+        let a6_id = node.add_block_as_best(a5_id, vec![]);
+        assert_eq!(queries_to_sync(&node, &mut wallet), 3);
+        assert_eq!(wallet.best_hash(), a6_id);
+    }
+
+    #[test]
+    fn shallow_reorgs_are_found_in_the_header_window() {
+        let (mut node, mut wallet) = four_blocks();
+        let a5_id = node.add_block_as_best(wallet.best_hash(), vec![]);
+        node.add_block_as_best(a5_id, vec![]);
+        wallet.sync(&node);
+
+        // a reorg two blocks deep is placed by following the new blocks back to the window
+        let b6_id = node.add_block(a5_id, vec![Transaction::coinbase(Address::Alice, 1)]);
+        let b7_id = node.add_block(b6_id, vec![]);
+        node.set_best(b7_id);
+        let queries = node.how_many_queries();
+        let report = wallet.try_sync(&node).unwrap();
+        assert_eq!(node.how_many_queries() - queries, 2);
+        assert_eq!((report.reverted.len(), report.applied.len()), (1, 2));
+        assert_eq!((wallet.best_hash(), wallet.net_worth()), (b7_id, BLOCK_REWARD + 1));
+    }
+}
@@ -0,0 +1,553 @@
+//! The wallet's record of past transactions that touched its addresses.
+//!
+//! Entries are appended in chain order during sync and dropped again when their block is reorged out.
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use bonecoin_core::{Address, AssetId, BlockId, Coin, CoinId, Transaction, TransactionId};
+
+/// Which way a transaction moved bones relative to the wallet.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(feature = "rest", derive(serde::Serialize))]
+pub enum Direction {
+    /// The transaction paid the wallet without consuming any wallet coins.
+    Incoming,
+    /// The transaction consumed wallet coins and paid at least one foreign address.
+    Outgoing,
+    /// The transaction consumed wallet coins along with coins of others, and every output went back
+    /// to the wallet.
+    SelfTransfer,
+    /// Every input and every output of the transaction belonged to the wallet: change, consolidation,
+    /// or a transfer between accounts. Only the tip left the wallet.
+    Internal,
+}
+
+impl Direction {
+    /// Classify a wallet transaction by whether it consumed wallet coins, whether it consumed coins
+    /// of others, and whether it paid anyone else.
+    pub(crate) fn classify(spends_wallet_coins: bool, spends_foreign_coins: bool, pays_foreign_address: bool) -> Self {
+        match (spends_wallet_coins, spends_foreign_coins, pays_foreign_address) {
+            (false, _, _) => Direction::Incoming,
+            (true, _, true) => Direction::Outgoing,
+            (true, true, false) => Direction::SelfTransfer,
+            (true, false, false) => Direction::Internal,
+        }
+    }
+}
+
+/// A transaction that moved bones into or out of the wallet, as seen during sync.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "rest", derive(serde::Serialize))]
+pub struct HistoryEntry {
+    /// The transaction this entry describes.
+    pub tx_id: TransactionId,
+    /// The block that included the transaction.
+    pub block_id: BlockId,
+    /// The height of that block.
+    pub height: u64,
+    /// The timestamp of that block.
+    pub timestamp: u64,
+    /// Which way the transaction moved bones relative to the wallet.
+    pub direction: Direction,
+    /// Coins created by the transaction that belong to the wallet.
+    pub received: Vec<(CoinId, Coin)>,
+    /// Coins consumed by the transaction that belonged to the wallet.
+    pub spent: Vec<(CoinId, Coin)>,
+    /// The bones the transaction burned as its tip, known when every input was a wallet coin:
+    /// the wallet coins consumed, less the wallet coins created, less the payments to others.
+    pub burned: Option<u64>,
+}
+
+/// The tip `transaction` burned, if `spent`, the wallet coins it consumed, are all of its inputs.
+pub(crate) fn burned_by(transaction: &Transaction, spent: &[(CoinId, Coin)]) -> Option<u64> {
+    if spent.is_empty() || spent.len() < transaction.inputs.len() {
+        return None;
+    }
+    let consumed: u64 = spent.iter().map(|(_, coin)| coin.native_value()).sum();
+    Some(consumed.saturating_sub(transaction.outputs.iter().map(Coin::native_value).sum()))
+}
+
+impl HistoryEntry {
+    /// Total bones the transaction paid into the wallet.
+    pub fn value_received(&self) -> u64 {
+        self.asset_value_received(None)
+    }
+
+    /// Total bones the transaction took out of the wallet.
+    pub fn value_spent(&self) -> u64 {
+        self.asset_value_spent(None)
+    }
+
+    /// Total value of the given asset (`None` for bones) the transaction paid into the wallet.
+    pub fn asset_value_received(&self, asset_id: Option<AssetId>) -> u64 {
+        self.received.iter().filter(|(_, coin)| coin.asset_id == asset_id).map(|(_, coin)| coin.value).sum()
+    }
+
+    /// Total value of the given asset (`None` for bones) the transaction took out of the wallet.
+    pub fn asset_value_spent(&self, asset_id: Option<AssetId>) -> u64 {
+        self.spent.iter().filter(|(_, coin)| coin.asset_id == asset_id).map(|(_, coin)| coin.value).sum()
+    }
+
+    /// Whether the transaction moved any of the given asset into or out of the wallet.
+    pub fn touches_asset(&self, asset_id: AssetId) -> bool {
+        self.received.iter().chain(&self.spent).any(|(_, coin)| coin.asset_id == Some(asset_id))
+    }
+
+    /// How much the transaction changed the wallet's net worth, in either direction.
+    pub fn net_value(&self) -> u64 {
+        self.value_received().abs_diff(self.value_spent())
+    }
+
+    /// The bones the transaction brought into the wallet, net of the wallet coins it consumed.
+    pub fn income(&self) -> u64 {
+        self.value_received().saturating_sub(self.value_spent())
+    }
+
+    /// The bones the transaction took out of the wallet, net of the change it returned. For an
+    /// internal transfer this is the tip burned.
+    pub fn expense(&self) -> u64 {
+        self.value_spent().saturating_sub(self.value_received())
+    }
+
+    /// The wallet addresses whose coins the transaction created or consumed, without duplicates.
+    pub fn addresses(&self) -> Vec<&Address> {
+        let mut addresses: Vec<&Address> = self
+            .received
+            .iter()
+            .chain(self.spent.iter())
+            .map(|(_, coin)| &coin.owner)
+            .collect();
+        addresses.sort();
+        addresses.dedup();
+        addresses
+    }
+}
+
+/// Where a wallet coin came from and, if it has been spent, where it went.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Provenance {
+    /// The transaction that created the coin.
+    pub created_by: TransactionId,
+    /// The height of the block that included the creating transaction.
+    pub created_at_height: u64,
+    /// The transaction that consumed the coin, if any.
+    pub spent_by: Option<TransactionId>,
+    /// The height of the block that included the spending transaction, if any.
+    pub spent_at_height: Option<u64>,
+}
+
+/// Criteria for searching the wallet history. Every criterion left as `None` matches everything.
+///
+/// Results are returned oldest first and paginated with `offset` and `limit`.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct TxFilter {
+    /// Only transactions that created or consumed a coin of this address.
+    pub address: Option<Address>,
+    /// Only transactions moving bones in this direction.
+    pub direction: Option<Direction>,
+    /// Only transactions whose net value (see `HistoryEntry::net_value`) is within this range.
+    pub value: Option<RangeInclusive<u64>>,
+    /// Only transactions included in blocks within this height range.
+    pub height: Option<RangeInclusive<u64>>,
+    /// Only transactions whose label (see `Wallet::set_tx_label`) contains this text.
+    pub memo: Option<String>,
+    /// Number of matching transactions to skip.
+    pub offset: usize,
+    /// Maximum number of transactions to return. Zero means no limit.
+    pub limit: usize,
+}
+
+/// The history store itself: entries in chain order plus secondary indices over them.
+#[derive(Clone, Default)]
+pub(crate) struct History {
+    entries: Vec<HistoryEntry>, // transactions that touched the wallet, in chain order
+    by_address: HashMap<Address, Vec<usize>>, // address -> positions of entries touching it, ascending
+}
+
+impl History {
+    pub(crate) fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub(crate) fn push(&mut self, entry: HistoryEntry) {
+        let position = self.entries.len();
+        for address in entry.addresses() {
+            self.by_address.entry(address.clone()).or_default().push(position);
+        }
+        self.entries.push(entry);
+    }
+
+    /// Drop every entry from blocks above the given height.
+    pub(crate) fn truncate_above(&mut self, height: u64) {
+        let keep = self.entries.partition_point(|entry| entry.height <= height);
+        self.entries.truncate(keep);
+        for positions in self.by_address.values_mut() {
+            let keep_positions = positions.partition_point(|&position| position < keep);
+            positions.truncate(keep_positions);
+        }
+        self.by_address.retain(|_, positions| !positions.is_empty());
+    }
+
+    /// Drop the `count` oldest entries.
+    pub(crate) fn drop_oldest(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        self.entries.drain(..count.min(self.entries.len()));
+        self.reindex();
+    }
+
+    /// Replace the entries from the block at `height` with `entries`, in block order.
+    pub(crate) fn replace_block(&mut self, height: u64, entries: Vec<HistoryEntry>) {
+        let start = self.entries.partition_point(|entry| entry.height < height);
+        let end = self.entries.partition_point(|entry| entry.height <= height);
+        self.entries.splice(start..end, entries);
+        self.reindex();
+    }
+
+    fn reindex(&mut self) {
+        self.by_address.clear();
+        for (position, entry) in self.entries.iter().enumerate() {
+            for address in entry.addresses() {
+                self.by_address.entry(address.clone()).or_default().push(position);
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.by_address.clear();
+    }
+
+    /// Whether the address index lists exactly the entries touching each address.
+    pub(crate) fn index_matches(&self) -> bool {
+        let mut rebuilt: HashMap<Address, Vec<usize>> = HashMap::new();
+        for (position, entry) in self.entries.iter().enumerate() {
+            for address in entry.addresses() {
+                rebuilt.entry(address.clone()).or_default().push(position);
+            }
+        }
+        rebuilt == self.by_address
+    }
+
+    /// Search the entries matching the filter. The address and height criteria are answered from
+    /// the address index and the chain ordering, so only the candidates they leave are scanned.
+    /// `memos` holds the transaction labels the memo criterion is matched against.
+    pub(crate) fn find(&self, filter: &TxFilter, memos: &HashMap<TransactionId, String>) -> Vec<&HistoryEntry> {
+        let (start, end) = match &filter.height {
+            Some(range) => (
+                self.entries.partition_point(|entry| entry.height < *range.start()),
+                self.entries.partition_point(|entry| entry.height <= *range.end()),
+            ),
+            None => (0, self.entries.len()),
+        };
+
+        let candidates: Box<dyn Iterator<Item = &HistoryEntry>> = match &filter.address {
+            Some(address) => match self.by_address.get(address) {
+                Some(positions) => {
+                    let first = positions.partition_point(|&position| position < start);
+                    let last = positions.partition_point(|&position| position < end);
+                    Box::new(positions[first..last.max(first)].iter().map(|&position| &self.entries[position]))
+                }
+                None => Box::new(std::iter::empty()),
+            },
+            None => Box::new(self.entries[start..end.max(start)].iter()),
+        };
+
+        let limit = if filter.limit == 0 { usize::MAX } else { filter.limit };
+        candidates
+            .filter(|entry| filter.direction.is_none_or(|direction| entry.direction == direction))
+            .filter(|entry| filter.value.as_ref().is_none_or(|range| range.contains(&entry.net_value())))
+            .filter(|entry| {
+                filter.memo.as_deref().is_none_or(|memo| memos.get(&entry.tx_id).is_some_and(|label| label.contains(memo)))
+            })
+            .skip(filter.offset)
+            .take(limit)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// Alice is minted 10 bones in a block at time 100 and sends them on to Bob in a block at
+    /// time 200. Returns the node, the synced wallet, the first block, and both transactions.
+    fn minted_then_sent() -> (MockNode, Wallet, BlockId, Transaction, Transaction) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice_and_bob();
+
+        let tx_1 = mint([(Address::Alice, 10)]);
+        let tx_2 = Transaction {
+            version: TRANSACTION_VERSION,
+            inputs: vec![Input {
+                coin_id: tx_1.coin_id(1, 0),
+                signature: Signature::Valid(Address::Alice),
+            }],
+            outputs: vec![Coin {
+                value: 10,
+                owner: Address::Bob,
+                asset_id: None,
+            }],
+        };
+
+        node.set_time(100);
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![tx_1.clone()]);
+        node.set_time(200);
+        node.add_block_as_best(b1_id, vec![tx_2.clone()]);
+        wallet.sync(&node);
+        (node, wallet, b1_id, tx_1, tx_2)
+    }
+
+    #[test]
+    fn history_records_block_timestamps() {
+        let (_, wallet, _, tx_1, tx_2) = minted_then_sent();
+
+        let history = wallet.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!((history[0].tx_id, history[0].timestamp), (tx_1.id(), 100));
+        assert_eq!((history[1].tx_id, history[1].timestamp), (tx_2.id(), 200));
+        assert_eq!(history[1].value_spent(), 10);
+    }
+
+    #[test]
+    fn coins_received_between_counts_coins_spent_since() {
+        let (_, wallet, _, tx_1, _) = minted_then_sent();
+
+        assert_eq!(wallet.coins_received_between(0, 150), HashSet::from([(tx_1.coin_id(1, 0), 10)]));
+        assert_eq!(wallet.coins_received_between(100, 200).len(), 2);
+        assert!(wallet.coins_received_between(201, 300).is_empty());
+    }
+
+    #[test]
+    fn reorged_blocks_leave_the_history() {
+        let (mut node, mut wallet, b1_id, _, _) = minted_then_sent();
+
+        node.set_best(b1_id);
+        wallet.sync(&node);
+        assert_eq!(wallet.history().len(), 1);
+        assert!(wallet.coins_received_between(200, 200).is_empty());
+    }
+
+    /// Alice is minted 30 bones in block 1, and after an empty block 2 sends them to Bob in
+    /// block 3. Returns the node with all three blocks and both transactions.
+    fn minted_then_spent_two_blocks_later() -> (MockNode, Transaction, Transaction) {
+        let mut node = MockNode::new();
+        let minted = mint([(Address::Alice, 30)]);
+        let spent = spend(minted.coin_id(1, 0), Address::Alice, [(Address::Bob, 30)]);
+
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![minted.clone()]);
+        let b2_id = node.add_block_as_best(b1_id, vec![]);
+        node.add_block_as_best(b2_id, vec![spent.clone()]);
+        (node, minted, spent)
+    }
+
+    #[test]
+    fn provenance_of_an_unspent_coin_names_only_its_creator() {
+        let (mut node, minted, _) = minted_then_spent_two_blocks_later();
+        node.set_best(node.best_block_at_height(1).unwrap());
+        let mut wallet = Wallet::new(vec![Address::Alice].into_iter());
+        wallet.sync(&node);
+
+        assert_eq!(
+            wallet.coin_provenance(&minted.coin_id(1, 0)),
+            Ok(Provenance {
+                created_by: minted.id(),
+                created_at_height: 1,
+                spent_by: None,
+                spent_at_height: None,
+            })
+        );
+    }
+
+    #[test]
+    fn provenance_of_a_spent_coin_names_the_spending_transaction() {
+        let (node, minted, spent) = minted_then_spent_two_blocks_later();
+        let mut wallet = Wallet::new(vec![Address::Alice].into_iter());
+        wallet.sync(&node);
+
+        let provenance = wallet.coin_provenance(&minted.coin_id(1, 0)).unwrap();
+        assert_eq!(provenance.created_by, minted.id());
+        assert_eq!(provenance.spent_by, Some(spent.id()));
+        assert_eq!(provenance.spent_at_height, Some(3));
+    }
+
+    #[test]
+    fn coins_that_never_touched_the_wallet_have_no_provenance() {
+        let (node, _, spent) = minted_then_spent_two_blocks_later();
+        let mut wallet = Wallet::new(vec![Address::Alice].into_iter());
+        wallet.sync(&node);
+
+        assert_eq!(
+            wallet.coin_provenance(&spent.coin_id(3, 0)),
+            Err(WalletError::UnknownCoin(spent.coin_id(3, 0)))
+        );
+    }
+
+    /// Alice and Bob are paid 100 and 20 bones in block 1, Alice pays Charlie 60 and keeps 39 as
+    /// change in block 2, and Bob moves his coin to Alice in block 3. Returns the node, the synced
+    /// wallet of Alice and Bob, and the three transactions.
+    fn three_blocks_of_history() -> (MockNode, Wallet, [Transaction; 3]) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice_and_bob();
+
+        let minted = mint([(Address::Alice, 100), (Address::Bob, 20)]);
+        let pay = spend(minted.coin_id(1, 0), Address::Alice, [(Address::Charlie, 60), (Address::Alice, 39)]);
+        let transfer = spend(minted.coin_id(1, 1), Address::Bob, [(Address::Alice, 20)]);
+
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![minted.clone()]);
+        let b2_id = node.add_block_as_best(b1_id, vec![pay.clone()]);
+        node.add_block_as_best(b2_id, vec![transfer.clone()]);
+        wallet.sync(&node);
+        (node, wallet, [minted, pay, transfer])
+    }
+
+    fn ids(entries: Vec<&HistoryEntry>) -> Vec<TransactionId> {
+        entries.iter().map(|entry| entry.tx_id).collect()
+    }
+
+    #[test]
+    fn the_default_filter_finds_every_transaction() {
+        let (_, wallet, [minted, pay, transfer]) = three_blocks_of_history();
+        assert_eq!(ids(wallet.find_transactions(TxFilter::default())), vec![minted.id(), pay.id(), transfer.id()]);
+    }
+
+    #[test]
+    fn transactions_are_found_by_address() {
+        let (_, wallet, [minted, _, transfer]) = three_blocks_of_history();
+        assert_eq!(
+            ids(wallet.find_transactions(TxFilter {
+                address: Some(Address::Bob),
+                ..Default::default()
+            })),
+            vec![minted.id(), transfer.id()]
+        );
+    }
+
+    #[test]
+    fn transactions_are_found_by_direction() {
+        let (_, wallet, [_, _, transfer]) = three_blocks_of_history();
+        assert_eq!(
+            ids(wallet.find_transactions(TxFilter {
+                direction: Some(Direction::Internal),
+                ..Default::default()
+            })),
+            vec![transfer.id()]
+        );
+    }
+
+    #[test]
+    fn transactions_are_found_by_net_value() {
+        let (_, wallet, [_, pay, _]) = three_blocks_of_history();
+        assert_eq!(
+            ids(wallet.find_transactions(TxFilter {
+                direction: Some(Direction::Outgoing),
+                value: Some(61..=61),
+                ..Default::default()
+            })),
+            vec![pay.id()]
+        );
+    }
+
+    #[test]
+    fn height_ranges_combine_with_the_address_index() {
+        let (_, wallet, [_, pay, transfer]) = three_blocks_of_history();
+        assert_eq!(
+            ids(wallet.find_transactions(TxFilter {
+                address: Some(Address::Alice),
+                height: Some(2..=3),
+                ..Default::default()
+            })),
+            vec![pay.id(), transfer.id()]
+        );
+    }
+
+    #[test]
+    fn results_are_paginated_by_offset_and_limit() {
+        let (_, wallet, [_, pay, _]) = three_blocks_of_history();
+        assert_eq!(
+            ids(wallet.find_transactions(TxFilter {
+                offset: 1,
+                limit: 1,
+                ..Default::default()
+            })),
+            vec![pay.id()]
+        );
+    }
+
+    #[test]
+    fn the_address_index_follows_reorgs() {
+        let (mut node, mut wallet, [minted, _, _]) = three_blocks_of_history();
+        node.set_best(node.best_block_at_height(1).unwrap());
+        wallet.sync(&node);
+        assert_eq!(
+            ids(wallet.find_transactions(TxFilter {
+                address: Some(Address::Alice),
+                ..Default::default()
+            })),
+            vec![minted.id()]
+        );
+    }
+
+    #[test]
+    fn transactions_are_found_by_a_substring_of_their_label() {
+        let (_, mut wallet, [minted, pay, transfer]) = three_blocks_of_history();
+        wallet.set_tx_label(minted.id(), "salary for March");
+        wallet.set_tx_label(pay.id(), "rent for March");
+        wallet.set_tx_label(transfer.id(), "savings");
+
+        let by_memo = |wallet: &Wallet, memo: &str| {
+            ids(wallet.find_transactions(TxFilter {
+                memo: Some(memo.to_string()),
+                ..Default::default()
+            }))
+        };
+        assert_eq!(by_memo(&wallet, "March"), vec![minted.id(), pay.id()]);
+        assert_eq!(by_memo(&wallet, "rent"), vec![pay.id()]);
+        assert!(by_memo(&wallet, "march").is_empty());
+
+        // unlabeled transactions never match a memo
+        wallet.set_tx_label(transfer.id(), "");
+        assert!(by_memo(&wallet, "savings").is_empty());
+    }
+
+    /// A wallet of Alice synced to three blocks: she receives 100 bones, pays Eve 40 of them with
+    /// 55 in change and a tip of 5, then spends the change to herself with a tip of 1.
+    fn paying_tips() -> Wallet {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+        let funding = mint([(Address::Alice, 100)]);
+        let payment = spend(funding.coin_id(1, 0), Address::Alice, [(Address::Eve, 40), (Address::Alice, 55)]);
+        let self_spend = spend(payment.coin_id(2, 1), Address::Alice, [(Address::Alice, 54)]);
+        let mut parent = Block::genesis().id();
+        for tx in [funding, payment, self_spend] {
+            parent = node.add_block_as_best(parent, vec![tx]);
+        }
+        wallet.sync(&node);
+        wallet
+    }
+
+    #[test]
+    fn history_entries_record_the_tips_they_burned() {
+        let wallet = paying_tips();
+        let burned: Vec<Option<u64>> = wallet.history().iter().map(|entry| entry.burned).collect();
+        assert_eq!(burned, [None, Some(5), Some(1)]);
+    }
+
+    #[test]
+    fn fees_are_summed_over_a_range_of_heights() {
+        let wallet = paying_tips();
+        assert_eq!(wallet.fees_paid_between(1, 3), 6);
+        assert_eq!(wallet.fees_paid_between(3, 3), 1);
+        assert_eq!(wallet.fees_paid_between(4, 10), 0);
+    }
+
+    #[test]
+    fn burned_tips_survive_export() {
+        let wallet = paying_tips();
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.history(), wallet.history());
+        assert_eq!(restored.fees_paid_between(0, u64::MAX), 6);
+    }
+}
@@ -0,0 +1,241 @@
+//! Hash-time-locked contracts: payments the recipient claims with a secret, or the payer takes back
+//! after a timeout.
+//!
+//! `create_htlc` funds a coin owned by an `Address::HashLock`. Sync watches every hash-locked coin
+//! whose recipient or refund address belongs to the wallet, so both parties find it under
+//! `watched_coin` once it is mined. The recipient redeems it with `redeem_htlc`, revealing the
+//! preimage on chain, and the payer reclaims it with `refund_htlc` once the timeout is reached.
+//! Two HTLCs locked to the same hash make a cross-wallet swap: redeeming one reveals the preimage
+//! that redeems the other.
+
+use std::fmt;
+
+use bonecoin_core::*;
+
+use crate::{signing_digest, Wallet};
+
+/// Errors that can occur while redeeming or refunding an HTLC.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum HtlcError {
+    /// The wallet cannot build the transaction.
+    Wallet(WalletError),
+    /// The coin is not a hash-locked coin the wallet has seen created and still unspent.
+    UnknownHtlc(CoinId),
+    /// The wallet does not own the address that may take this path of the HTLC.
+    NotAParty,
+    /// The preimage does not hash to the lock's hash.
+    WrongPreimage,
+    /// The refund path only opens at this height.
+    TooEarly { timeout_height: u64 },
+    /// The wallet's signer refused to sign.
+    SignerRefused,
+}
+
+impl From<WalletError> for HtlcError {
+    fn from(e: WalletError) -> Self {
+        HtlcError::Wallet(e)
+    }
+}
+
+impl fmt::Display for HtlcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HtlcError::Wallet(e) => write!(f, "{e}"),
+            HtlcError::UnknownHtlc(coin_id) => write!(f, "coin {coin_id} is not a known unspent HTLC"),
+            HtlcError::NotAParty => write!(f, "the wallet is not the party that may spend the HTLC this way"),
+            HtlcError::WrongPreimage => write!(f, "the preimage does not match the HTLC's hash"),
+            HtlcError::TooEarly { timeout_height } => write!(f, "the HTLC cannot be refunded before height {timeout_height}"),
+            HtlcError::SignerRefused => write!(f, "the signer refused to sign"),
+        }
+    }
+}
+
+impl std::error::Error for HtlcError {}
+
+/// The terms of an `Address::HashLock`, unboxed.
+struct Lock {
+    recipient: Address,
+    refund: Address,
+    hash: u64,
+    timeout_height: u64,
+}
+
+impl Wallet {
+    /// Construct a transaction locking `amount` bones to `recipient` under `hash`, refundable to the
+    /// wallet's first address from `timeout_height` on. The HTLC coin is its first output.
+    pub fn create_htlc(&self, recipient: Address, amount: u64, hash: u64, timeout_height: u64) -> WalletResult<Transaction> {
+        let lock = Address::HashLock {
+            recipient: Box::new(recipient),
+            refund: Box::new(self.primary_address()?),
+            hash,
+            timeout_height,
+        };
+        let transaction = self.build_funding_transaction(lock, amount)?;
+        self.check_policy(&transaction)?;
+        Ok(transaction)
+    }
+
+    /// Construct the transaction claiming an HTLC paid to one of the wallet's addresses, revealing `preimage`.
+    pub fn redeem_htlc(&self, coin_id: CoinId, preimage: &[u8]) -> Result<Transaction, HtlcError> {
+        let (coin, lock) = self.unspent_htlc(coin_id)?;
+        if !self.addresses.contains(&lock.recipient) {
+            return Err(HtlcError::NotAParty);
+        }
+        if hash_preimage(preimage) != lock.hash {
+            return Err(HtlcError::WrongPreimage);
+        }
+        let signature = Signature::Preimage {
+            signer: lock.recipient.clone(),
+            preimage: preimage.to_vec(),
+        };
+        self.spend_htlc(coin_id, coin, lock.recipient, signature)
+    }
+
+    /// Construct the transaction taking back an HTLC the wallet funded, once its best block has
+    /// reached the timeout.
+    pub fn refund_htlc(&self, coin_id: CoinId) -> Result<Transaction, HtlcError> {
+        let (coin, lock) = self.unspent_htlc(coin_id)?;
+        if !self.addresses.contains(&lock.refund) {
+            return Err(HtlcError::NotAParty);
+        }
+        if self.best_block_height < lock.timeout_height {
+            return Err(HtlcError::TooEarly { timeout_height: lock.timeout_height });
+        }
+        self.spend_htlc(coin_id, coin, lock.refund.clone(), Signature::Valid(lock.refund))
+    }
+
+    /// Watch the hash-locked coins a transaction mined at `height` creates for one of the wallet's addresses.
+    pub(crate) fn watch_htlcs(&mut self, transaction: &Transaction, height: u64) {
+        for (coin_id, coin) in transaction.iter_output_coins_and_ids(height) {
+            if let Address::HashLock { recipient, refund, .. } = &coin.owner {
+                if self.addresses.contains(recipient) || self.addresses.contains(refund) {
+                    self.watched.entry(coin_id).or_default();
+                }
+            }
+        }
+    }
+
+    /// A watched HTLC that is not spent yet, with its lock.
+    fn unspent_htlc(&self, coin_id: CoinId) -> Result<(Coin, Lock), HtlcError> {
+        let watched = self.watched.get(&coin_id).filter(|watched| !watched.is_spent());
+        match watched.and_then(|watched| watched.coin.as_ref()) {
+            Some(coin @ Coin { owner: Address::HashLock { recipient, refund, hash, timeout_height }, .. }) => {
+                let lock = Lock {
+                    recipient: (**recipient).clone(),
+                    refund: (**refund).clone(),
+                    hash: *hash,
+                    timeout_height: *timeout_height,
+                };
+                Ok((coin.clone(), lock))
+            }
+            _ => Err(HtlcError::UnknownHtlc(coin_id)),
+        }
+    }
+
+    /// Pay the whole HTLC coin to `to` with the given signature, if the signer agrees to sign as `to`.
+    fn spend_htlc(&self, coin_id: CoinId, coin: Coin, to: Address, signature: Signature) -> Result<Transaction, HtlcError> {
+        let mut transaction = Transaction {
+            version: TRANSACTION_VERSION,
+            inputs: vec![Input {
+                coin_id,
+                signature: Signature::Invalid,
+            }],
+            outputs: vec![Coin {
+                value: coin.value,
+                owner: to.clone(),
+                asset_id: coin.asset_id,
+            }],
+        };
+        if self.signer.sign(&signing_digest(&transaction), &to) != Signature::Valid(to) {
+            return Err(HtlcError::SignerRefused);
+        }
+        transaction.inputs[0].signature = signature;
+        self.check_policy(&transaction)?;
+        Ok(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// Alice, funded with 50 bones, locks 20 of them to Bob under the hash of "secret" in block 2
+    /// and 10 more under the hash of "other" in block 3, both timing out at height 4. Returns the
+    /// node, the wallets of Alice and Bob synced to it, and the two HTLC transactions.
+    fn two_htlcs() -> (MockNode, Wallet, Wallet, Transaction, Transaction) {
+        let mut node = MockNode::new();
+        let mut alice = wallet_with_alice();
+        let mut bob = Wallet::new(vec![Address::Bob].into_iter());
+        let b1 = node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 50)])]);
+        alice.sync(&node);
+
+        let paid = alice.create_htlc(Address::Bob, 20, hash_preimage(b"secret"), 4).unwrap();
+        node.add_block_as_best(b1, vec![paid.clone()]);
+        alice.sync(&node);
+        let refunded = alice.create_htlc(Address::Bob, 10, hash_preimage(b"other"), 4).unwrap();
+        node.add_block_as_best(alice.best_hash(), vec![refunded.clone()]);
+        alice.sync(&node);
+        bob.sync(&node);
+        (node, alice, bob, paid, refunded)
+    }
+
+    #[test]
+    fn both_parties_watch_the_htlc() {
+        let (_, alice, bob, paid, refunded) = two_htlcs();
+        assert_eq!(bob.watched_coin(&paid.coin_id(2, 0)).unwrap().coin.as_ref().map(|coin| coin.value), Some(20));
+        assert!(alice.watched_coin(&refunded.coin_id(3, 0)).unwrap().coin.is_some());
+        assert_eq!(bob.net_worth(), 0);
+    }
+
+    #[test]
+    fn wrong_preimages_are_refused() {
+        let (_, _, bob, paid, _) = two_htlcs();
+        assert_eq!(bob.redeem_htlc(paid.coin_id(2, 0), b"guess"), Err(HtlcError::WrongPreimage));
+    }
+
+    #[test]
+    fn only_the_recipient_redeems_and_only_the_funder_refunds() {
+        let (_, alice, bob, paid, _) = two_htlcs();
+        assert_eq!(alice.redeem_htlc(paid.coin_id(2, 0), b"secret"), Err(HtlcError::NotAParty));
+        assert_eq!(bob.refund_htlc(paid.coin_id(2, 0)), Err(HtlcError::NotAParty));
+    }
+
+    #[test]
+    fn refunds_wait_for_the_timeout() {
+        let (_, alice, _, paid, _) = two_htlcs();
+        assert_eq!(alice.refund_htlc(paid.coin_id(2, 0)), Err(HtlcError::TooEarly { timeout_height: 4 }));
+    }
+
+    #[test]
+    fn unknown_htlcs_are_refused() {
+        let (_, _, bob, _, _) = two_htlcs();
+        let unknown = marker_tx().coin_id(1, 0);
+        assert_eq!(bob.redeem_htlc(unknown, b"secret"), Err(HtlcError::UnknownHtlc(unknown)));
+    }
+
+    #[test]
+    fn htlcs_are_redeemed_with_the_preimage() {
+        let (mut node, mut alice, mut bob, paid, _) = two_htlcs();
+        let paid_coin = paid.coin_id(2, 0);
+        let redeem = bob.redeem_htlc(paid_coin, b"secret").unwrap();
+        assert!(paid.outputs[0].owner.is_satisfied_at(&redeem.inputs[0].signature, 3));
+        node.add_block_as_best(bob.best_hash(), vec![redeem]);
+        alice.sync(&node);
+        bob.sync(&node);
+        assert_eq!(bob.net_worth(), 20);
+        // once redeemed, it is gone for the funder too
+        assert_eq!(alice.refund_htlc(paid_coin), Err(HtlcError::UnknownHtlc(paid_coin)));
+    }
+
+    #[test]
+    fn htlcs_go_back_to_the_funder_at_the_timeout() {
+        let (mut node, mut alice, _, _, refunded) = two_htlcs();
+        let b4 = node.add_block_as_best(alice.best_hash(), vec![]);
+        alice.sync(&node);
+        let refund = alice.refund_htlc(refunded.coin_id(3, 0)).unwrap();
+        assert!(refunded.outputs[0].owner.is_satisfied_at(&refund.inputs[0].signature, 5));
+        node.add_block_as_best(b4, vec![refund]);
+        alice.sync(&node);
+        assert_eq!(alice.net_worth(), 30);
+    }
+}
@@ -0,0 +1,193 @@
+//! Payments keyed by a request id chosen by the client, so a retried request never pays twice.
+//!
+//! `send_idempotent` records the transaction it built under the request id before handing it to the
+//! node. A retry with the same id, after a timeout or a restart, finds the record and submits the
+//! original transaction again instead of building a new one. The records are part of the exported
+//! state; a crash after the broadcast but before the state was exported loses the record, like any
+//! other change not yet saved.
+//!
+//! Until sync sees a sent transaction mined, automatic coin selection leaves its coins alone, so a
+//! second payment cannot spend them again and be rejected as a double spend. Sync releases them
+//! once a block spends any of them, by the sent transaction or by one conflicting with it.
+
+use std::collections::HashSet;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+impl Wallet {
+    /// Pay `amount` bones to `recipient`, burning `tip`, and submit the transaction to `node`, once
+    /// per `request_id`.
+    ///
+    /// The first call with a request id builds the transaction like `create_automatic_transaction`
+    /// and records it. Later calls with the same id return the recorded transaction, submitting it
+    /// again unless it is already mined, and ignore `recipient`, `amount`, and `tip`. If the node does
+    /// not accept the transaction, `WalletError::BroadcastFailed` is returned and the record is kept,
+    /// so a retry submits the same transaction.
+    pub fn send_idempotent<Node: NodeEndpoint>(
+        &mut self,
+        request_id: &str,
+        recipient: Address,
+        amount: u64,
+        tip: u64,
+        node: &Node,
+    ) -> WalletResult<Transaction> {
+        let transaction = match self.sent_requests.get(request_id) {
+            Some(transaction) => transaction.clone(),
+            None => {
+                let transaction = self.create_automatic_transaction(recipient, amount, tip)?;
+                self.sent_requests.insert(request_id.to_string(), transaction.clone());
+                transaction
+            }
+        };
+        let tx_id = transaction.id();
+        if self.history.entries().iter().any(|entry| entry.tx_id == tx_id) {
+            return Ok(transaction);
+        }
+        self.reserve_inputs(&transaction);
+        if !node.submit_transaction(&transaction) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(request_id, %tx_id, "node refused idempotent send");
+            return Err(WalletError::BroadcastFailed(tx_id));
+        }
+        Ok(transaction)
+    }
+
+    /// The transaction recorded for a request id by `send_idempotent`, or `None` if there is none.
+    pub fn sent_request(&self, request_id: &str) -> Option<&Transaction> {
+        self.sent_requests.get(request_id)
+    }
+
+    /// Keep automatic coin selection off the coins `transaction` spends until sync sees a block
+    /// spend any of them. `send_idempotent` does this for the transactions it sends; call it for
+    /// transactions submitted to the node some other way.
+    ///
+    /// Nothing is reserved if a coin the transaction spends already left the wallet, as the
+    /// transaction is then mined or conflicted.
+    pub fn reserve_inputs(&mut self, transaction: &Transaction) {
+        if !transaction.iter_input_coin_ids().all(|coin_id| self.coins.contains_key(&coin_id)) {
+            return;
+        }
+        let tx_id = transaction.id();
+        for coin_id in transaction.iter_input_coin_ids() {
+            self.pending_spends.insert(coin_id, tx_id);
+        }
+    }
+
+    /// Whether a sent transaction waiting to be mined spends the coin.
+    pub fn is_pending_spend(&self, coin_id: &CoinId) -> bool {
+        self.pending_spends.contains_key(coin_id)
+    }
+
+    /// Release every coin of the pending spends that sync saw a block spend a coin of, whether the
+    /// pending transaction itself or one conflicting with it.
+    pub(crate) fn settle_pending_spends(&mut self) {
+        let settled: HashSet<TransactionId> =
+            self.pending_spends.iter().filter(|(coin_id, _)| !self.coins.contains_key(coin_id)).map(|(_, tx_id)| *tx_id).collect();
+        self.pending_spends.retain(|_, tx_id| !settled.contains(tx_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice synced to a first block paying her two coins of 50 bones, which has sent
+    /// 10 bones to Bob as request "payout-1". Returns the node, the wallet, the id of the block and
+    /// the sent transaction.
+    fn sent_payout() -> (MockNode, Wallet, BlockId, Transaction) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+        let b1 = node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 50), (Address::Alice, 50)])]);
+        wallet.sync(&node);
+        let sent = wallet.send_idempotent("payout-1", Address::Bob, 10, 1, &node).unwrap();
+        (node, wallet, b1, sent)
+    }
+
+    #[test]
+    fn sends_are_submitted_to_the_node() {
+        let (node, wallet, _, sent) = sent_payout();
+        assert_eq!(node.submitted_transactions(), std::slice::from_ref(&sent));
+        assert_eq!(wallet.sent_request("payout-1"), Some(&sent));
+        assert_eq!(wallet.sent_request("payout-2"), None);
+    }
+
+    #[test]
+    fn retries_resubmit_the_same_transaction_even_after_a_restart() {
+        let (node, wallet, _, sent) = sent_payout();
+        let mut wallet = Wallet::import_state(&wallet.export_state()).unwrap();
+        // the parameters of the retry do not matter
+        assert_eq!(wallet.send_idempotent("payout-1", Address::Charlie, 20, 1, &node), Ok(sent.clone()));
+        assert_eq!(node.submitted_transactions(), [sent.clone(), sent]);
+    }
+
+    #[test]
+    fn retries_of_mined_sends_are_not_submitted_again() {
+        let (mut node, mut wallet, b1, sent) = sent_payout();
+        node.add_block_as_best(b1, vec![sent.clone()]);
+        wallet.sync(&node);
+        assert_eq!(wallet.send_idempotent("payout-1", Address::Bob, 10, 1, &node), Ok(sent));
+        assert_eq!(node.submitted_transactions().len(), 1);
+    }
+
+    #[test]
+    fn refused_broadcasts_keep_the_request_for_the_retry() {
+        let (mut node, mut wallet, _, _) = sent_payout();
+        node.set_call_budget(0, OverBudget::Fail);
+        let Err(WalletError::BroadcastFailed(tx_id)) = wallet.send_idempotent("payout-2", Address::Bob, 5, 1, &node) else {
+            panic!("the broadcast should fail");
+        };
+        assert_eq!(wallet.sent_request("payout-2").map(Transaction::id), Some(tx_id));
+
+        node.clear_call_budget();
+        assert_eq!(wallet.send_idempotent("payout-2", Address::Dave, 7, 1, &node).map(|tx| tx.id()), Ok(tx_id));
+        assert_eq!(node.submitted_transactions().len(), 2);
+    }
+
+    #[test]
+    fn pending_sends_keep_their_coins_from_other_payments() {
+        let (_, wallet, _, sent) = sent_payout();
+        let reserved = sent.iter_input_coin_ids().next().unwrap();
+        assert!(wallet.is_pending_spend(&reserved));
+
+        let next = wallet.create_automatic_transaction(Address::Charlie, 45, 0).unwrap();
+        assert!(next.iter_input_coin_ids().all(|coin_id| coin_id != reserved));
+        assert_eq!(
+            wallet.create_automatic_transaction(Address::Charlie, 60, 0),
+            Err(WalletError::InsufficientFunds { needed: 60, available: 50 })
+        );
+    }
+
+    #[test]
+    fn mined_sends_release_their_coins() {
+        let (mut node, mut wallet, b1, sent) = sent_payout();
+        let reserved = sent.iter_input_coin_ids().next().unwrap();
+
+        node.add_block_as_best(b1, vec![sent]);
+        wallet.sync(&node);
+
+        assert!(!wallet.is_pending_spend(&reserved));
+    }
+
+    #[test]
+    fn conflicted_sends_release_their_coins() {
+        let (mut node, mut wallet, b1, sent) = sent_payout();
+        let reserved = sent.iter_input_coin_ids().next().unwrap();
+
+        node.add_block_as_best(b1, vec![spend(reserved, Address::Alice, [(Address::Charlie, 50)])]);
+        wallet.sync(&node);
+
+        assert!(!wallet.is_pending_spend(&reserved));
+        assert_eq!(wallet.create_automatic_transaction(Address::Charlie, 45, 0).map(|tx| tx.outputs[0].value), Ok(45));
+    }
+
+    #[test]
+    fn pending_sends_keep_their_coins_after_a_restart() {
+        let (_, wallet, _, sent) = sent_payout();
+
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+
+        assert!(sent.iter_input_coin_ids().all(|coin_id| restored.is_pending_spend(&coin_id)));
+    }
+}
@@ -0,0 +1,482 @@
+//! An indexer that follows the chain like the wallet does, but tracks every address.
+//!
+//! Where the wallet only cares about its own coins, the indexer keeps enough data to answer
+//! explorer style questions: which transactions touched an address and which transaction spent a coin.
+//! The unspent balance of every address is kept in an index ordered by balance, updated as blocks
+//! are applied and reverted, so the rich list is read off its front.
+//!
+//! It also keeps the supply of bones at every height. A transaction creating more bones than the
+//! indexed coins it consumes is worth mints them, like a coinbase does, and one creating fewer burns
+//! the difference as its tip. The totals are recorded per block as it is applied and dropped with
+//! it on a reorg, so they always describe the indexed chain.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+
+use bonecoin_core::*;
+
+/// The bones minted and burned on the indexed chain up to a height.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct SupplyStats {
+    /// The height the totals are counted up to, inclusive.
+    pub height: u64,
+    /// The bones ever minted.
+    pub minted: u64,
+    /// The bones ever burned.
+    pub burned: u64,
+}
+
+impl SupplyStats {
+    /// The bones in circulation: those minted and not burned since.
+    pub fn circulating(&self) -> u64 {
+        self.minted - self.burned
+    }
+}
+
+/// Which results of an indexer query to return.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct Page {
+    /// Number of results to skip.
+    pub offset: usize,
+    /// Maximum number of results to return. Zero means no limit.
+    pub limit: usize,
+}
+
+/// A transaction that created or spent a coin of an address, as seen by the address.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct AddressActivity {
+    /// The transaction this entry describes.
+    pub tx_id: TransactionId,
+    /// The height of the block that included the transaction.
+    pub height: u64,
+    /// The bones the transaction paid to the address.
+    pub received: u64,
+    /// The bones of the address's coins the transaction consumed.
+    pub spent: u64,
+    /// The other addresses whose coins the transaction consumed or created, without duplicates.
+    pub counterparties: Vec<Address>,
+}
+
+/// A chain-wide index built by syncing with a node.
+pub struct Indexer {
+    block_ids: Vec<BlockId>, // ids of the applied blocks indexed by height - used to find the fork point on reorgs
+    blocks: Vec<Vec<Transaction>>, // bodies of the applied blocks indexed by height - for lookups and reverting blocks
+    transactions: HashMap<TransactionId, Vec<u64>>, // every applied transaction mapped to the heights of the blocks including it
+    coins: HashMap<CoinId, Coin>, // every coin ever created on the indexed chain, spent or not
+    spent_by: HashMap<CoinId, TransactionId>, // coin id -> the transaction that consumed it
+    created_by: HashMap<CoinId, TransactionId>, // coin id -> the transaction that created it
+    address_txs: HashMap<Address, Vec<(u64, TransactionId)>>, // address -> transactions that paid it or spent from it
+    supply: Vec<SupplyStats>, // the supply after each applied block indexed by height - popped with the block
+    balances: HashMap<Address, u64>, // address -> bones in its unspent coins, for addresses holding any
+    by_balance: BTreeSet<(Reverse<u64>, Address)>, // the same balances, largest first - for the rich list
+}
+
+impl Indexer {
+    /// Create an empty indexer positioned at genesis.
+    pub fn new() -> Self {
+        Indexer {
+            block_ids: vec![Block::genesis().id()],
+            blocks: vec![Vec::new()],
+            transactions: HashMap::new(),
+            coins: HashMap::new(),
+            spent_by: HashMap::new(),
+            created_by: HashMap::new(),
+            address_txs: HashMap::new(),
+            supply: vec![SupplyStats::default()],
+            balances: HashMap::new(),
+            by_balance: BTreeSet::new(),
+        }
+    }
+
+    /// Get the height of the best block that the indexer is aware of.
+    pub fn best_height(&self) -> u64 {
+        self.block_ids.len() as u64 - 1
+    }
+
+    /// Get the hash of the best block that the indexer is aware of.
+    pub fn best_hash(&self) -> BlockId {
+        *self.block_ids.last().expect("genesis is never reverted")
+    }
+
+    /// Look up a transaction on the indexed chain, along with the height of the block that included it.
+    /// If identical transactions were included more than once, the latest inclusion is returned.
+    pub fn transaction(&self, tx_id: &TransactionId) -> Option<(u64, &Transaction)> {
+        let height = *self.transactions.get(tx_id)?.last()?;
+        let tx = self.blocks[height as usize].iter().find(|tx| tx.id() == *tx_id)?;
+        Some((height, tx))
+    }
+
+    /// Look up any coin created on the indexed chain, whether or not it has been spent.
+    pub fn coin(&self, coin_id: &CoinId) -> Option<&Coin> {
+        self.coins.get(coin_id)
+    }
+
+    /// The transaction that consumed the given coin, if it has been spent.
+    pub fn spending_transaction(&self, coin_id: &CoinId) -> Option<TransactionId> {
+        self.spent_by.get(coin_id).copied()
+    }
+
+    /// The transaction that created the given coin, if it was created on the indexed chain.
+    pub fn creating_transaction(&self, coin_id: &CoinId) -> Option<TransactionId> {
+        self.created_by.get(coin_id).copied()
+    }
+
+    /// All transactions that created or spent a coin owned by the given address, oldest first.
+    pub fn transactions_of(&self, address: &Address) -> Vec<TransactionId> {
+        self.address_txs
+            .get(address)
+            .map(|txs| txs.iter().map(|(_, tx_id)| *tx_id).collect())
+            .unwrap_or_default()
+    }
+
+    /// The supply of bones at the best block.
+    pub fn supply(&self) -> SupplyStats {
+        *self.supply.last().expect("genesis is never reverted")
+    }
+
+    /// The supply of bones at the given height, if the indexed chain reaches it.
+    pub fn supply_at(&self, height: u64) -> Option<SupplyStats> {
+        self.supply.get(usize::try_from(height).ok()?).copied()
+    }
+
+    /// The transactions that created or spent a coin of the given address, oldest first, with the
+    /// bones they moved for it and who was on the other side.
+    pub fn address_activity(&self, address: &Address, page: Page) -> Vec<AddressActivity> {
+        let limit = if page.limit == 0 { usize::MAX } else { page.limit };
+        self.address_txs
+            .get(address)
+            .into_iter()
+            .flatten()
+            .skip(page.offset)
+            .take(limit)
+            .map(|&(height, tx_id)| {
+                let tx = self.blocks[height as usize].iter().find(|tx| tx.id() == tx_id).expect("indexed transactions are in their block");
+                let of_address = |coin: &&Coin| coin.owner == *address;
+                AddressActivity {
+                    tx_id,
+                    height,
+                    received: tx.outputs.iter().filter(of_address).map(Coin::native_value).sum(),
+                    spent: tx.iter_input_coin_ids().filter_map(|coin_id| self.coins.get(&coin_id)).filter(of_address).map(Coin::native_value).sum(),
+                    counterparties: self.touched_addresses(tx, height).into_iter().filter(|other| other != address).collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// The bones in the unspent coins of an address.
+    pub fn balance(&self, address: &Address) -> u64 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    /// The `n` addresses with the largest unspent balances, largest first, ties in address order.
+    pub fn top_holders(&self, n: usize) -> Vec<(Address, u64)> {
+        self.by_balance.iter().take(n).map(|(Reverse(balance), address)| (address.clone(), *balance)).collect()
+    }
+
+    /// Synchronizes the index with the node, reverting blocks that are no longer on the node's best chain.
+    pub fn sync<Node: NodeEndpoint>(&mut self, node: &Node) {
+        // walk back until our block at the current height is on the node's best chain again
+        while self.best_height() > 0
+            && node.best_block_at_height(self.best_height()) != Some(self.best_hash())
+        {
+            self.revert_block();
+        }
+
+        // apply the node's blocks from there on
+        while let Some(block_id) = node.best_block_at_height(self.best_height() + 1) {
+            match node.entire_block(&block_id) {
+                Some(block) => self.apply_block(block_id, &block),
+                None => break, // failed to fetch block, stop sync
+            }
+        }
+    }
+
+    fn apply_block(&mut self, block_id: BlockId, block: &Block) {
+        let mut supply = SupplyStats {
+            height: block.number,
+            ..self.supply()
+        };
+        for tx in &block.body {
+            let tx_id = tx.id();
+            // made up inputs were never indexed and count as nothing
+            let consumed: u64 = tx.iter_input_coin_ids().filter_map(|coin_id| self.coins.get(&coin_id)).map(Coin::native_value).sum();
+            let created: u64 = tx.outputs.iter().map(Coin::native_value).sum();
+            supply.minted += created.saturating_sub(consumed);
+            supply.burned += consumed.saturating_sub(created);
+            for coin_id in tx.iter_input_coin_ids() {
+                self.spent_by.insert(coin_id, tx_id);
+                if let Some(coin) = self.coins.get(&coin_id).cloned() {
+                    self.adjust_balance(&coin, false);
+                }
+            }
+            for (coin_id, coin) in tx.iter_output_coins_and_ids(block.number) {
+                self.adjust_balance(&coin, true);
+                self.coins.insert(coin_id, coin);
+                self.created_by.insert(coin_id, tx_id);
+            }
+            for address in self.touched_addresses(tx, block.number) {
+                self.address_txs.entry(address).or_default().push((block.number, tx_id));
+            }
+            self.transactions.entry(tx_id).or_default().push(block.number);
+        }
+
+        self.block_ids.push(block_id);
+        self.blocks.push(block.body.clone());
+        self.supply.push(supply);
+    }
+
+    fn revert_block(&mut self) {
+        let height = self.best_height();
+        self.block_ids.pop();
+        self.supply.pop();
+        let body = self.blocks.pop().expect("every applied block has a body");
+
+        // undo transactions in reverse so coins created and spent within the block are handled correctly
+        for tx in body.iter().rev() {
+            let tx_id = tx.id();
+            for address in self.touched_addresses(tx, height) {
+                let txs = self.address_txs.get_mut(&address).expect("address was indexed when applied");
+                txs.pop();
+                if txs.is_empty() {
+                    self.address_txs.remove(&address);
+                }
+            }
+            for (coin_id, _) in tx.iter_output_coins_and_ids(height) {
+                if let Some(coin) = self.coins.remove(&coin_id) {
+                    self.adjust_balance(&coin, false);
+                }
+                self.created_by.remove(&coin_id);
+            }
+            for coin_id in tx.iter_input_coin_ids() {
+                self.spent_by.remove(&coin_id);
+                if let Some(coin) = self.coins.get(&coin_id).cloned() {
+                    self.adjust_balance(&coin, true);
+                }
+            }
+            // an identical transaction may also appear in an earlier block, so only forget this occurrence
+            let heights = self.transactions.get_mut(&tx_id).expect("transaction was indexed when applied");
+            heights.pop();
+            if heights.is_empty() {
+                self.transactions.remove(&tx_id);
+            }
+        }
+    }
+
+    /// Add a coin's bones to its owner's balance when it is created, or take them off when it is spent.
+    fn adjust_balance(&mut self, coin: &Coin, created: bool) {
+        let value = coin.native_value();
+        if value == 0 {
+            return;
+        }
+        let old = self.balance(&coin.owner);
+        let new = if created { old + value } else { old.saturating_sub(value) };
+        self.by_balance.remove(&(Reverse(old), coin.owner.clone()));
+        if new == 0 {
+            self.balances.remove(&coin.owner);
+        } else {
+            self.balances.insert(coin.owner.clone(), new);
+            self.by_balance.insert((Reverse(new), coin.owner.clone()));
+        }
+    }
+
+    /// The owners of the coins a transaction consumes and creates, without duplicates.
+    /// Consumed coins that were never indexed (e.g. made up inputs) are ignored.
+    fn touched_addresses(&self, tx: &Transaction, height: u64) -> Vec<Address> {
+        let mut touched: Vec<Address> = tx
+            .iter_input_coin_ids()
+            .filter_map(|coin_id| self.coins.get(&coin_id))
+            .map(|coin| coin.owner.clone())
+            .chain(tx.iter_output_coins_and_ids(height).map(|(_, coin)| coin.owner))
+            .collect();
+        touched.sort();
+        touched.dedup();
+        touched
+    }
+}
+
+impl Default for Indexer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// Charlie is minted 50 bones in block 1 and pays 45 of them to Dave in block 2, neither of
+    /// them an address of any wallet. Returns the node, the synced indexer, block 1, and both
+    /// transactions.
+    fn charlie_pays_dave() -> (MockNode, Indexer, BlockId, Transaction, Transaction) {
+        let mut node = MockNode::new();
+        let mut indexer = Indexer::new();
+
+        let minted = mint([(Address::Charlie, 50)]);
+        let pay_dave = spend(minted.coin_id(1, 0), Address::Charlie, [(Address::Dave, 45)]);
+
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![minted.clone()]);
+        node.add_block_as_best(b1_id, vec![pay_dave.clone()]);
+        indexer.sync(&node);
+        (node, indexer, b1_id, minted, pay_dave)
+    }
+
+    #[test]
+    fn indexer_tracks_foreign_addresses() {
+        let (node, indexer, _, minted, pay_dave) = charlie_pays_dave();
+        let charlie_coin = minted.coin_id(1, 0);
+
+        assert_eq!(indexer.best_height(), 2);
+        assert_eq!(Some(indexer.best_hash()), node.best_block_at_height(2));
+        assert_eq!(indexer.transactions_of(&Address::Charlie), vec![minted.id(), pay_dave.id()]);
+        assert_eq!(indexer.transactions_of(&Address::Dave), vec![pay_dave.id()]);
+        assert_eq!(indexer.spending_transaction(&charlie_coin), Some(pay_dave.id()));
+        assert_eq!(indexer.transaction(&pay_dave.id()), Some((2, &pay_dave)));
+    }
+
+    #[test]
+    fn reorged_transactions_leave_the_index() {
+        let (mut node, mut indexer, b1_id, minted, pay_dave) = charlie_pays_dave();
+        let charlie_coin = minted.coin_id(1, 0);
+
+        let new_b2_id = node.add_block_as_best(b1_id, vec![marker_tx()]);
+        indexer.sync(&node);
+
+        assert_eq!(indexer.best_hash(), new_b2_id);
+        assert_eq!(indexer.transactions_of(&Address::Charlie), vec![minted.id()]);
+        assert!(indexer.transactions_of(&Address::Dave).is_empty());
+        assert_eq!(indexer.spending_transaction(&charlie_coin), None);
+        assert_eq!(indexer.transaction(&pay_dave.id()), None);
+        assert_eq!(indexer.coin(&charlie_coin).map(|coin| coin.value), Some(50));
+    }
+
+    /// Charlie mines blocks 1 and 2 and burns 5 bones of his first reward in block 2 paying the
+    /// rest to Dave. Returns the node, the synced indexer, and block 1.
+    fn charlie_burns_five() -> (MockNode, Indexer, BlockId) {
+        let mut node = MockNode::new();
+        let mut indexer = Indexer::new();
+
+        let coinbase = Transaction::coinbase(Address::Charlie, BLOCK_REWARD);
+        let pay_dave = spend(coinbase.coin_id(1, 0), Address::Charlie, [(Address::Dave, BLOCK_REWARD - 5)]);
+
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![coinbase]);
+        node.add_block_as_best(b1_id, vec![Transaction::coinbase(Address::Charlie, BLOCK_REWARD), pay_dave]);
+        indexer.sync(&node);
+        (node, indexer, b1_id)
+    }
+
+    #[test]
+    fn supply_counts_minted_and_burned_bones() {
+        let (_, indexer, _) = charlie_burns_five();
+
+        let supply = indexer.supply();
+        assert_eq!(supply, SupplyStats { height: 2, minted: 2 * BLOCK_REWARD, burned: 5 });
+        assert_eq!(supply.circulating(), 2 * BLOCK_REWARD - 5);
+    }
+
+    #[test]
+    fn supply_is_kept_for_every_height() {
+        let (_, indexer, _) = charlie_burns_five();
+
+        assert_eq!(indexer.supply_at(1), Some(SupplyStats { height: 1, minted: BLOCK_REWARD, burned: 0 }));
+        assert_eq!(indexer.supply_at(0), Some(SupplyStats::default()));
+        assert_eq!(indexer.supply_at(3), None);
+    }
+
+    #[test]
+    fn reorgs_replace_the_supply_of_the_blocks_they_remove() {
+        let (mut node, mut indexer, b1_id) = charlie_burns_five();
+
+        let fork = node.add_block(b1_id, vec![Transaction::coinbase(Address::Eve, BLOCK_REWARD)]);
+        let fork = node.add_block_as_best(fork, vec![Transaction::coinbase(Address::Eve, BLOCK_REWARD)]);
+        indexer.sync(&node);
+
+        assert_eq!(indexer.best_hash(), fork);
+        assert_eq!(indexer.supply(), SupplyStats { height: 3, minted: 3 * BLOCK_REWARD, burned: 0 });
+        assert_eq!(indexer.supply_at(2), Some(SupplyStats { height: 2, minted: 2 * BLOCK_REWARD, burned: 0 }));
+    }
+
+    /// Charlie mines block 1 and Dave block 2, then Charlie pays 30 of his reward to Eve in block
+    /// 3 and keeps 15 as change. Returns the node, the synced indexer, and block 2.
+    fn three_holders() -> (MockNode, Indexer, BlockId) {
+        let mut node = MockNode::new();
+        let mut indexer = Indexer::new();
+
+        let coinbase = Transaction::coinbase(Address::Charlie, BLOCK_REWARD);
+        let payment = spend(coinbase.coin_id(1, 0), Address::Charlie, [(Address::Eve, 30), (Address::Charlie, 15)]);
+
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![coinbase]);
+        let b2_id = node.add_block_as_best(b1_id, vec![Transaction::coinbase(Address::Dave, BLOCK_REWARD)]);
+        node.add_block_as_best(b2_id, vec![payment]);
+        indexer.sync(&node);
+        (node, indexer, b2_id)
+    }
+
+    #[test]
+    fn top_holders_are_listed_by_balance() {
+        let (_, indexer, _) = three_holders();
+
+        assert_eq!(
+            indexer.top_holders(10),
+            [(Address::Dave, BLOCK_REWARD), (Address::Eve, 30), (Address::Charlie, 15)]
+        );
+        assert_eq!(indexer.top_holders(1), [(Address::Dave, BLOCK_REWARD)]);
+        assert_eq!(indexer.balance(&Address::Alice), 0);
+    }
+
+    #[test]
+    fn reorgs_restore_the_balances_of_the_holders() {
+        let (mut node, mut indexer, b2_id) = three_holders();
+
+        node.add_block_as_best(b2_id, vec![marker_tx()]);
+        indexer.sync(&node);
+
+        // ties are listed in address order
+        assert_eq!(
+            indexer.top_holders(3),
+            [(Address::Custom(123), 123), (Address::Charlie, BLOCK_REWARD), (Address::Dave, BLOCK_REWARD)]
+        );
+        assert_eq!(indexer.balance(&Address::Eve), 0);
+    }
+
+    /// Charlie mines block 1, pays 30 of his reward to Eve in block 2 keeping 20 as change, and
+    /// Eve sends the 30 back in block 3. Returns the synced indexer and the three transactions.
+    fn eve_refunds_charlie() -> (Indexer, [Transaction; 3]) {
+        let mut node = MockNode::new();
+        let mut indexer = Indexer::new();
+
+        let coinbase = Transaction::coinbase(Address::Charlie, BLOCK_REWARD);
+        let payment = spend(coinbase.coin_id(1, 0), Address::Charlie, [(Address::Eve, 30), (Address::Charlie, 20)]);
+        let refund = spend(payment.coin_id(2, 0), Address::Eve, [(Address::Charlie, 30)]);
+
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![coinbase.clone()]);
+        let b2_id = node.add_block_as_best(b1_id, vec![payment.clone()]);
+        node.add_block_as_best(b2_id, vec![refund.clone()]);
+        indexer.sync(&node);
+        (indexer, [coinbase, payment, refund])
+    }
+
+    #[test]
+    fn address_activity_lists_what_an_address_received_and_spent() {
+        let (indexer, [coinbase, payment, refund]) = eve_refunds_charlie();
+
+        assert_eq!(
+            indexer.address_activity(&Address::Charlie, Page::default()),
+            [
+                AddressActivity { tx_id: coinbase.id(), height: 1, received: BLOCK_REWARD, spent: 0, counterparties: vec![] },
+                AddressActivity { tx_id: payment.id(), height: 2, received: 20, spent: BLOCK_REWARD, counterparties: vec![Address::Eve] },
+                AddressActivity { tx_id: refund.id(), height: 3, received: 30, spent: 0, counterparties: vec![Address::Eve] },
+            ]
+        );
+        let eve_spent: Vec<_> = indexer.address_activity(&Address::Eve, Page::default()).iter().map(|entry| entry.spent).collect();
+        assert_eq!(eve_spent, [0, 30]);
+        assert!(indexer.address_activity(&Address::Dave, Page::default()).is_empty());
+    }
+
+    #[test]
+    fn address_activity_is_paged() {
+        let (indexer, _) = eve_refunds_charlie();
+
+        let activity = indexer.address_activity(&Address::Charlie, Page::default());
+        assert_eq!(indexer.address_activity(&Address::Charlie, Page { offset: 1, limit: 1 }), activity[1..2]);
+    }
+}
@@ -0,0 +1,162 @@
+//! Consistency checks across the wallet's coin map, its indices, history, and undo records.
+//!
+//! Sync keeps several structures in step with the coin map. `check_invariants` verifies they agree,
+//! and sync runs it after every call in debug builds, so a bookkeeping bug fails the test that
+//! caused it rather than a later, unrelated one.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// A disagreement between the wallet's internal structures, found by `check_invariants`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum InvariantViolation {
+    /// The coin value index does not list exactly the coins in the coin map.
+    CoinIndexOutOfStep,
+    /// The history's address index does not list exactly the entries touching each address.
+    HistoryIndexOutOfStep,
+    /// An unspent coin has no recorded outpoint.
+    MissingOutpoint(CoinId),
+    /// The history shows the coin as unspent, but the wallet does not hold it.
+    UnspentCoinMissing(CoinId),
+    /// The wallet holds the coin, but the history never received it or shows it spent.
+    CoinNotInHistory(CoinId),
+    /// A history entry comes from above the best block or out of chain order.
+    HistoryOutOfOrder { height: u64 },
+    /// The undo record for this height does not follow the one below it, or the newest one is not the best block.
+    UndoChainBroken { height: u64 },
+    /// A history entry names a different block than the undo record for its height.
+    HistoryBlockMismatch { height: u64 },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::CoinIndexOutOfStep => write!(f, "the coin value index is out of step with the coins"),
+            InvariantViolation::HistoryIndexOutOfStep => write!(f, "the history address index is out of step with the entries"),
+            InvariantViolation::MissingOutpoint(coin_id) => write!(f, "unspent coin {coin_id} has no outpoint"),
+            InvariantViolation::UnspentCoinMissing(coin_id) => write!(f, "the history shows coin {coin_id} unspent but the wallet lacks it"),
+            InvariantViolation::CoinNotInHistory(coin_id) => write!(f, "the history does not account for coin {coin_id}"),
+            InvariantViolation::HistoryOutOfOrder { height } => write!(f, "history entry at height {height} is out of order"),
+            InvariantViolation::UndoChainBroken { height } => write!(f, "the undo record at height {height} does not chain up"),
+            InvariantViolation::HistoryBlockMismatch { height } => {
+                write!(f, "history and undo records disagree on the block at height {height}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+impl Wallet {
+    /// Verify that the wallet's internal structures agree with each other, returning the first disagreement.
+    ///
+    /// The indices must match the coin map and history, every unspent coin must have its outpoint,
+    /// and the undo records must chain up to the best block. Unless `max_history_entries` may have
+    /// dropped entries, replaying the history must also yield exactly the unspent coins.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        if !self.coins.index_matches() {
+            return Err(InvariantViolation::CoinIndexOutOfStep);
+        }
+        if !self.history.index_matches() {
+            return Err(InvariantViolation::HistoryIndexOutOfStep);
+        }
+        if let Some(coin_id) = self.coins.keys().find(|coin_id| !self.outpoints.contains_key(coin_id)) {
+            return Err(InvariantViolation::MissingOutpoint(*coin_id));
+        }
+
+        let entries = self.history.entries();
+        let mut previous_height = 0;
+        for entry in entries {
+            if entry.height < previous_height || entry.height > self.best_block_height {
+                return Err(InvariantViolation::HistoryOutOfOrder { height: entry.height });
+            }
+            previous_height = entry.height;
+        }
+        if self.config.max_history_entries.is_none() {
+            let mut unspent = HashSet::new();
+            for entry in entries {
+                unspent.extend(entry.received.iter().map(|(coin_id, _)| *coin_id));
+                for (coin_id, _) in &entry.spent {
+                    unspent.remove(coin_id);
+                }
+            }
+            if let Some(coin_id) = unspent.iter().find(|coin_id| !self.coins.contains_key(coin_id)) {
+                return Err(InvariantViolation::UnspentCoinMissing(*coin_id));
+            }
+            if let Some(coin_id) = self.coins.keys().find(|coin_id| !unspent.contains(coin_id)) {
+                return Err(InvariantViolation::CoinNotInHistory(*coin_id));
+            }
+        }
+
+        let mut expected_parent = self.undo.front().map(|delta| delta.parent);
+        for delta in &self.undo {
+            if Some(delta.parent) != expected_parent {
+                return Err(InvariantViolation::UndoChainBroken { height: delta.height });
+            }
+            expected_parent = Some((delta.height, delta.block_id));
+            let start = entries.partition_point(|entry| entry.height < delta.height);
+            let end = entries.partition_point(|entry| entry.height <= delta.height);
+            if entries[start..end].iter().any(|entry| entry.block_id != delta.block_id) {
+                return Err(InvariantViolation::HistoryBlockMismatch { height: delta.height });
+            }
+        }
+        if let Some(newest) = self.undo.back() {
+            if (newest.height, newest.block_id) != (self.best_block_height, self.best_block_hash) {
+                return Err(InvariantViolation::UndoChainBroken { height: newest.height });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice and Bob synced to the first block, and one of its coins.
+    fn synced_wallet() -> (Wallet, CoinId, Coin) {
+        let (_, wallet) = make_one_block_blockchain();
+        let (coin_id, coin) = wallet.all_coins().next().map(|(coin_id, coin)| (*coin_id, coin.clone())).unwrap();
+        (wallet, coin_id, coin)
+    }
+
+    #[test]
+    fn synced_wallets_hold_every_invariant() {
+        let (wallet, _, _) = synced_wallet();
+        assert_eq!(wallet.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn missing_unspent_coins_are_caught() {
+        let (mut tampered, coin_id, _) = synced_wallet();
+        tampered.coins.remove(&coin_id);
+        assert_eq!(tampered.check_invariants(), Err(InvariantViolation::UnspentCoinMissing(coin_id)));
+    }
+
+    #[test]
+    fn missing_outpoints_are_caught() {
+        let (mut tampered, coin_id, _) = synced_wallet();
+        tampered.outpoints.remove(&coin_id);
+        assert_eq!(tampered.check_invariants(), Err(InvariantViolation::MissingOutpoint(coin_id)));
+    }
+
+    #[test]
+    fn coins_missing_from_the_history_are_caught() {
+        let (mut tampered, coin_id, coin) = synced_wallet();
+        let stray = Transaction::coinbase(Address::Alice, 1).coin_id(9, 0);
+        tampered.outpoints.insert(stray, tampered.outpoints[&coin_id]);
+        tampered.coins.insert(stray, coin);
+        assert_eq!(tampered.check_invariants(), Err(InvariantViolation::CoinNotInHistory(stray)));
+    }
+
+    #[test]
+    fn broken_undo_chains_are_caught() {
+        let (mut tampered, _, _) = synced_wallet();
+        tampered.best_block_hash = Block::genesis().id();
+        assert_eq!(tampered.check_invariants(), Err(InvariantViolation::UndoChainBroken { height: 1 }));
+    }
+}
@@ -0,0 +1,85 @@
+//! User supplied labels for addresses, coins, and transactions.
+//!
+//! A coin id depends on the height of the block that created it, so the same transaction
+//! mined on a different branch creates coins with different ids. Coin labels are therefore
+//! keyed by the creating transaction and output index, which survive a reorg unchanged.
+
+use std::collections::HashMap;
+
+use bonecoin_core::{Address, TransactionId};
+
+/// Identifies a transaction output independently of the block it was included in.
+pub type OutPoint = (TransactionId, usize);
+
+/// The label database.
+#[derive(Clone, Default)]
+pub(crate) struct Labels {
+    pub(crate) addresses: HashMap<Address, String>,
+    pub(crate) coins: HashMap<OutPoint, String>,
+    pub(crate) transactions: HashMap<TransactionId, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice synced to a node whose only block mints her 10 bones, with the coin, the
+    /// minting transaction, and her address labeled.
+    fn labeled_wallet() -> (MockNode, Wallet, Transaction) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+        let minted = mint([(Address::Alice, 10)]);
+        node.add_block_as_best(Block::genesis().id(), vec![minted.clone()]);
+        wallet.sync(&node);
+
+        wallet.set_coin_label(&minted.coin_id(1, 0), "salary").unwrap();
+        wallet.set_tx_label(minted.id(), "payroll");
+        wallet.set_address_label(Address::Alice, "main");
+        (node, wallet, minted)
+    }
+
+    #[test]
+    fn labels_are_read_back() {
+        let (_, wallet, minted) = labeled_wallet();
+        assert_eq!(wallet.coin_label(&minted.coin_id(1, 0)), Some("salary"));
+        assert_eq!(wallet.tx_label(&minted.id()), Some("payroll"));
+        assert_eq!(wallet.address_label(&Address::Alice), Some("main"));
+    }
+
+    #[test]
+    fn coins_the_wallet_never_received_cannot_be_labeled() {
+        let (_, mut wallet, minted) = labeled_wallet();
+        assert_eq!(
+            wallet.set_coin_label(&minted.coin_id(7, 0), "nope"),
+            Err(WalletError::UnknownCoin(minted.coin_id(7, 0)))
+        );
+    }
+
+    #[test]
+    fn coin_labels_survive_reorgs() {
+        let (mut node, mut wallet, minted) = labeled_wallet();
+
+        // the same transaction gets mined one block later on a new branch, changing its coin id
+        let old_b1_id = node.best_block_at_height(1).unwrap();
+        let old_b2_id = node.add_block_as_best(old_b1_id, vec![]);
+        node.add_block_as_best(old_b2_id, vec![]);
+        wallet.sync(&node);
+        let b1_id = node.add_block(Block::genesis().id(), vec![marker_tx()]);
+        node.add_block_as_best(b1_id, vec![minted.clone()]);
+        wallet.sync(&node);
+
+        let new_coin = minted.coin_id(2, 0);
+        assert_eq!(wallet.coin_details(&new_coin).map(|coin| coin.value), Ok(10));
+        assert_eq!(wallet.coin_label(&new_coin), Some("salary"));
+        assert_eq!(wallet.labeled_coins(), vec![(new_coin, 10, "salary")]);
+        assert_eq!(wallet.labeled_transactions()[0].1, "payroll");
+        assert_eq!(wallet.address_label(&Address::Alice), Some("main"));
+    }
+
+    #[test]
+    fn an_empty_label_removes_the_label() {
+        let (_, mut wallet, _) = labeled_wallet();
+        wallet.set_address_label(Address::Alice, "");
+        assert!(wallet.labeled_addresses().is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+//! Syncing without downloading the bodies of blocks that do not concern the wallet.
+//!
+//! With `WalletConfig::lazy_bodies` set, sync first asks the node for the transactions of each new
+//! block that match `sync_filter`. A bloom filter never misses an item inserted into it, so a block
+//! with no matches pays nothing to the wallet's addresses and spends nothing the wallet follows.
+//! Only its header is applied, which keeps its id in the header window and the undo records for
+//! reorg detection, and its body never crosses the wire. A block with matches, false positives
+//! included, is fetched whole and applied as usual.
+//!
+//! Coins the address scheme owns through addresses that are not the wallet's own, such as multisig
+//! coins, never match; wallets expecting them should fetch every body. Strict sync always does,
+//! since it checks every block.
+
+use std::collections::BTreeMap;
+
+use bonecoin_core::*;
+
+use crate::delta::StateDelta;
+use crate::Wallet;
+
+/// The false positive rate of the filter lazy sync matches blocks against.
+pub const LAZY_FILTER_RATE: f64 = 0.001;
+
+/// What sync fetched of a block.
+pub(crate) enum Body {
+    /// The entire block.
+    Full(Block),
+    /// Nothing: the node found no transaction matching the sync filter in it.
+    Skipped,
+}
+
+impl Wallet {
+    /// The filter lazy sync matches blocks against: the wallet's addresses and coins, the coins it
+    /// watches, and the recipients and inputs of the transactions registered with it.
+    pub fn sync_filter(&self) -> BloomFilter {
+        let mut filter = self.address_filter(LAZY_FILTER_RATE);
+        for coin_id in self.watched.keys() {
+            filter.insert(coin_id);
+        }
+        for registered in self.registered.values() {
+            for coin in &registered.transaction.outputs {
+                filter.insert(&coin.owner);
+            }
+            for coin_id in registered.transaction.iter_input_coin_ids() {
+                filter.insert(&coin_id);
+            }
+        }
+        filter
+    }
+
+    /// Fetch what sync needs of the block `block_id`: nothing if lazy sync is on and the node finds
+    /// no transaction for the wallet in it, and the entire block otherwise. Returns `None` if the
+    /// node answers neither.
+    pub(crate) fn fetch_body<Node: NodeEndpoint>(&self, node: &Node, block_id: &BlockId, filter: Option<&BloomFilter>) -> Option<Body> {
+        let skip = filter.and_then(|filter| node.relevant_transactions(block_id, filter)).is_some_and(|matches| matches.is_empty());
+        if skip {
+            return Some(Body::Skipped);
+        }
+        node.entire_block(block_id).map(Body::Full)
+    }
+
+    /// The delta of a block whose body was skipped, which changes nothing but the sync position.
+    pub(crate) fn header_delta(&self, block_id: BlockId) -> StateDelta {
+        StateDelta {
+            height: self.best_block_height + 1,
+            block_id,
+            parent: (self.best_block_height, self.best_block_hash),
+            transactions: 0,
+            spent: BTreeMap::new(),
+            created: BTreeMap::new(),
+            coinbase: Vec::new(),
+            outpoints: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    use std::cell::Cell;
+
+    /// Counts the block bodies it serves.
+    struct Bodies<'a>(&'a MockNode, Cell<u64>);
+
+    impl NodeEndpoint for Bodies<'_> {
+        fn best_block_at_height(&self, h: u64) -> Option<BlockId> {
+            self.0.best_block_at_height(h)
+        }
+
+        fn entire_block(&self, id: &BlockId) -> Option<Block> {
+            self.1.set(self.1.get() + 1);
+            self.0.entire_block(id)
+        }
+
+        fn relevant_transactions(&self, id: &BlockId, filter: &BloomFilter) -> Option<Vec<Transaction>> {
+            self.0.relevant_transactions(id, filter)
+        }
+    }
+
+    /// A node of four blocks in which Alice is rewarded in the first, Bob in the third, and
+    /// Alice pays her reward to Bob in the fourth, and a wallet of Alice skipping the bodies of
+    /// blocks without its transactions, not synced yet. Returns the node, the wallet, the reward
+    /// and the payment.
+    fn lazy_wallet() -> (MockNode, Wallet, Transaction, Transaction) {
+        let mut node = MockNode::new();
+        let mined = Transaction::coinbase(Address::Alice, BLOCK_REWARD);
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![mined.clone()]);
+        let b2_id = node.add_block_as_best(b1_id, vec![]);
+        let b3_id = node.add_block_as_best(b2_id, vec![Transaction::coinbase(Address::Bob, BLOCK_REWARD)]);
+        let payment = spend(mined.coin_id(1, 0), Address::Alice, [(Address::Bob, BLOCK_REWARD)]);
+        node.add_block_as_best(b3_id, vec![payment.clone()]);
+        let wallet = Wallet::builder().address(Address::Alice).lazy_bodies(true).build().unwrap();
+        (node, wallet, mined, payment)
+    }
+
+    #[test]
+    fn only_the_bodies_of_blocks_with_wallet_transactions_are_downloaded() {
+        let (node, mut wallet, _, _) = lazy_wallet();
+        let lazy = Bodies(&node, Cell::new(0));
+        let report = wallet.try_sync(&lazy).unwrap();
+        // the coin was received in one block and spent in another
+        assert_eq!(lazy.1.get(), 2);
+        assert_eq!(report.applied.iter().map(|block| block.transactions_scanned).collect::<Vec<_>>(), [1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn the_wallets_transactions_are_applied() {
+        let (node, mut wallet, mined, payment) = lazy_wallet();
+        wallet.sync(&node);
+        assert_eq!((wallet.best_hash(), wallet.net_worth()), (node.best_block_at_height(4).unwrap(), 0));
+        assert_eq!(wallet.history().iter().map(|entry| entry.tx_id).collect::<Vec<_>>(), [mined.id(), payment.id()]);
+    }
+
+    #[test]
+    fn the_headers_of_skipped_blocks_still_place_reorgs() {
+        let (mut node, mut wallet, _, _) = lazy_wallet();
+        wallet.sync(&node);
+        let b3_id = node.best_block_at_height(3).unwrap();
+        let c4_id = node.add_block(b3_id, vec![Transaction::coinbase(Address::Eve, BLOCK_REWARD)]);
+        node.set_best(c4_id);
+        let report = wallet.try_sync(&Bodies(&node, Cell::new(0))).unwrap();
+        assert_eq!(report.reverted.len(), 1);
+        assert_eq!((wallet.best_hash(), wallet.net_worth()), (c4_id, BLOCK_REWARD));
+    }
+
+    #[test]
+    fn the_lazy_setting_survives_export() {
+        let (_, wallet, _, _) = lazy_wallet();
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert!(restored.config().lazy_bodies);
+    }
+}
@@ -5,30 +5,271 @@
 //! Note: Reorganization handling code is not fully working in complex cases.
 
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
 
 use bonecoin_core::*;
 
+mod accounting;
+mod accounts;
+mod alerts;
+mod audit;
+mod assets;
+mod backup;
+#[cfg(any(test, feature = "bench"))]
+pub mod bench;
+mod builder;
+mod change;
+mod channel;
+mod coins;
+mod cold;
+mod config;
+mod consolidate;
+mod delta;
+mod deposits;
+mod diff;
+mod escrow;
+mod events;
+mod export;
+mod external;
+mod filter;
+mod fingerprint;
+mod flow;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+mod hd;
+mod headers;
+mod history;
+mod htlc;
+mod idempotent;
+mod indexer;
+mod invariants;
+mod labels;
+mod lazy;
+mod merge;
+mod message;
+mod node_cache;
+mod partial;
+mod policy;
+mod preflight;
+mod scheme;
+mod selection;
+mod signer;
+mod snapshot;
+mod spill;
+mod strict;
+mod plugin;
+mod pricing;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(any(test, feature = "raw-transactions"))]
+mod raw;
+mod receive;
+mod receive_only;
+mod reorg;
+mod report;
+mod rescan;
+mod resilient;
+#[cfg(feature = "rest")]
+mod rest;
+mod state;
+mod store;
+mod swap;
+mod taint;
+mod tracker;
+mod uri;
+mod verified;
+mod watch;
+mod watchtower;
+mod withdrawals;
+mod work;
+
+pub use accounting::{IncomeReport, Lot, LotDisposal, LotMatching, LotReport};
+pub use accounts::AccountError;
+pub use alerts::{Alert, AlertRule};
+pub use audit::{AuditError, AuditReport};
+pub use backup::BackupPolicy;
+pub use builder::{BuildError, WalletBuilder};
+pub use change::{ChangeDestination, ChangePolicy, DustPolicy};
+pub use channel::{Channel, ChannelError, ChannelState};
+pub use cold::{ColdSpendError, OfflineSigner, SignatureBundle, SigningRequest};
+pub use config::{SyncError, WalletConfig, DEFAULT_UNDO_DEPTH};
+pub use hd::{HdKeychain, DEFAULT_GAP_LIMIT};
+pub use deposits::Deposit;
+pub use diff::{BalanceDiff, DiffBase};
+pub use escrow::Escrow;
+pub use events::{WalletEvent, EVENT_CAPACITY};
+pub use export::ExportFormat;
+pub use fingerprint::WalletId;
+pub use flow::{FlowGraph, FlowNode, FlowRoot, GraphFormat};
+pub use external::{RegisteredStatus, RegisteredTransaction};
+pub use history::{Direction, HistoryEntry, Provenance, TxFilter};
+pub use htlc::HtlcError;
+pub use indexer::{AddressActivity, Indexer, Page, SupplyStats};
+pub use invariants::InvariantViolation;
+pub use labels::OutPoint;
+pub use lazy::LAZY_FILTER_RATE;
+pub use merge::MergeError;
+pub use node_cache::{NodeCache, NodeCacheStats, NODE_CACHE_BLOCKS, NODE_CACHE_TIP_CHECK};
+pub use partial::PartialTransaction;
+pub use plugin::SyncPlugin;
+pub use policy::{PendingApproval, SpendingPolicy, POLICY_WINDOW};
+pub use scheme::{AddressScheme, KeyScheme, MultisigScheme, WatchOnly};
+pub use selection::{CoinSelectionStrategy, SelectionConstraints, SelectionPlan};
+pub use signer::{signing_digest, Signer, SoftwareSigner};
+pub use snapshot::WalletSnapshot;
+pub use spill::{CoinCacheStats, CoinSpill, DirSpill};
+pub use strict::SyncWarning;
+#[cfg(any(test, feature = "raw-transactions"))]
+pub use raw::SigningMode;
+pub use reorg::{ReorgRecord, ReorgStats};
+pub use report::{BlockReport, SyncReport};
+pub use resilient::{ResilientNode, RetryPolicy};
+#[cfg(feature = "rest")]
+pub use rest::{RestApi, REST_MAX_PAGE_LIMIT, REST_PAGE_LIMIT};
+pub use pricing::{Decimal, ParseDecimalError, PriceAt, PriceSource, DECIMAL_PLACES};
+pub use state::{state_version, Migration, StateError, MIGRATIONS, STATE_MAGIC, STATE_VERSION};
+#[cfg(feature = "sled")]
+pub use store::{SledStore, SLED_CACHE_BYTES};
+pub use store::{MemoryStore, StoreBatch, StoreError, WalletStore};
+pub use swap::{swap_transaction, SwapError, SwapHalf};
+pub use taint::Ancestry;
+pub use tracker::{TransactionStatus, TransactionTracker};
+pub use uri::{PaymentUri, PaymentUriError, MAX_ADDRESS_NESTING, PAYMENT_URI_SCHEME};
+pub use watch::WatchedCoin;
+pub use withdrawals::{Withdrawal, WithdrawalStatus};
+
+use alerts::AlertRules;
+use backup::Backups;
+use coins::CoinStore;
+use delta::StateDelta;
+use hd::HdAddresses;
+use headers::HeaderWindow;
+use lazy::Body;
+use history::History;
+use labels::Labels;
+use receive::ReceiveAddresses;
+use reorg::ReorgLog;
+use withdrawals::WithdrawalQueue;
+
 /// The wallet syncs and keeps a local database of information relevant to its user's addresses.
 pub struct Wallet {
     addresses: HashSet<Address>, // set of addresses owned by wallet - hashset for efficiency
-    coins: HashMap<CoinId, Coin>, // track coins : unspent transaction outputs belonging to wallets address - stored in a map for easier access to individual coins
+    coins: CoinStore, // track coins : unspent transaction outputs belonging to wallets address - stored in a map for easier access to individual coins
     best_block_height: u64, // track height of best block that wallet is aware of - for syncs
     best_block_hash: BlockId, // track hash of best block wallet is aware of
+    coinbase_heights: HashMap<CoinId, u64>, // coinbase coins received (spent or not) mapped to the height that minted them - for maturity checks
+    history: History, // transactions that touched the wallet, in chain order
+    outpoints: HashMap<CoinId, OutPoint>, // every coin the wallet has received mapped to its creating transaction and output index
+    labels: Labels, // user labels for addresses, coins, and transactions
+    policy: SpendingPolicy, // limits on the transactions the wallet authors
+    pending_approvals: HashMap<TransactionId, PendingApproval>, // transactions waiting for a second approval
+    channels: HashMap<TransactionId, Channel>, // open payment channels keyed by funding transaction
+    change_cursor: AtomicUsize, // next round-robin change address
+    scheme: Arc<dyn AddressScheme>, // decides which coins the wallet owns and which keys sign for them
+    signer: Arc<dyn Signer>, // signs each input with the keys the scheme asks for
+    watched: HashMap<CoinId, WatchedCoin>, // individual coins whose creation and spending the wallet follows
+    registered: HashMap<TransactionId, RegisteredTransaction>, // transactions created elsewhere whose confirmation the wallet follows
+    events: VecDeque<WalletEvent>, // raised during sync, waiting for the application to take them
+    notifier: Option<SyncSender<WalletEvent>>, // forwards events to the receiver handed out by notification_receiver
+    dropped_notifications: u64, // events the notification channel had no room for
+    store: Option<Box<dyn WalletStore>>, // durable copy of the coins and sync position, committed per block
+    undo: VecDeque<StateDelta>, // one record per synced block, newest last, for rolling back reorgs
+    headers: HeaderWindow, // headers of the blocks the undo records cover, for finding fork points locally
+    config: WalletConfig, // bounds on the retained undo records and history
+    pruned_spent_to: u64, // spent coins' details are pruned up to this height
+    hd: Option<HdAddresses>, // addresses derived from a seed, extended by sync as they get used
+    receiving: ReceiveAddresses, // receive addresses handed out and addresses that received coins
+    sync_warnings: Vec<SyncWarning>, // suspicious blocks found by strict sync, waiting to be taken
+    reorgs: ReorgLog, // reorgs processed so far, and the one sync is in the middle of
+    credited_deposits: HashSet<CoinId>, // deposits the integrator marked credited
+    withdrawals: WithdrawalQueue, // payout requests, queued or paid by a batch transaction
+    sent_requests: HashMap<String, Transaction>, // transactions sent by send_idempotent keyed by request id
+    pending_spends: HashMap<CoinId, TransactionId>, // coins spent by sent transactions not mined yet, mapped to the spending transaction
+    followed_work: Option<u64>, // cumulative work the node reported for the best block at the last sync
+    plugins: Vec<Box<dyn SyncPlugin>>, // called by sync for every block it applies or rolls back
+    alerts: AlertRules, // conditions checked after every sync, raising alert events
+    receive_only: HashSet<Address>, // owned addresses whose coins are only spent when explicitly asked to
+    accounts: BTreeMap<String, BTreeSet<Address>>, // named groups of owned addresses, disjoint
+    watchtower: HashSet<Address>, // owned addresses whose coins only transactions the wallet authored may spend
+    backups: Option<Backups>, // where and when sync writes backups, not part of the snapshot
 }
 
-impl WalletApi for Wallet {
-    fn new(addresses: impl Iterator<Item = Address>) -> Self {
-        let address_set: HashSet<Address> = addresses.collect(); // convert iterator into hashset
-
+// a wallet can be shared between threads behind an `Arc`, so nothing in it may use unsynchronized interior mutability
+fn _assert_sync<T: Sync>() {}
+const _: fn() = _assert_sync::<Wallet>;
+
+/// The clone is an independent wallet with the same state. It has no store, no notification
+/// receiver, no sync plugins, no backup policy, and no queued events or sync warnings, so nothing
+/// is committed, delivered, or backed up twice.
+/// It has no coin cache either and holds all of its coins in memory.
+impl Clone for Wallet {
+    fn clone(&self) -> Self {
+        let mut coins = self.coins.clone();
+        coins.stop_staging();
+        coins.detach_cache();
         Wallet {
-            addresses: address_set,
-            coins: HashMap::<CoinId, Coin>::new(), // initial empty map of coins
-            best_block_height: 0,                    // initial height
-            best_block_hash: Block::genesis().id(),  // initial block hash (genesis default)
+            addresses: self.addresses.clone(),
+            coins,
+            best_block_height: self.best_block_height,
+            best_block_hash: self.best_block_hash,
+            coinbase_heights: self.coinbase_heights.clone(),
+            history: self.history.clone(),
+            outpoints: self.outpoints.clone(),
+            labels: self.labels.clone(),
+            policy: self.policy.clone(),
+            pending_approvals: self.pending_approvals.clone(),
+            channels: self.channels.clone(),
+            change_cursor: AtomicUsize::new(self.change_cursor.load(Ordering::Relaxed)),
+            scheme: self.scheme.clone(),
+            signer: self.signer.clone(),
+            watched: self.watched.clone(),
+            registered: self.registered.clone(),
+            events: VecDeque::new(),
+            notifier: None,
+            dropped_notifications: 0,
+            store: None,
+            undo: self.undo.clone(),
+            headers: self.headers.clone(),
+            config: self.config.clone(),
+            pruned_spent_to: self.pruned_spent_to,
+            hd: self.hd.clone(),
+            receiving: self.receiving.clone(),
+            sync_warnings: Vec::new(),
+            reorgs: self.reorgs.clone(),
+            credited_deposits: self.credited_deposits.clone(),
+            withdrawals: self.withdrawals.clone(),
+            sent_requests: self.sent_requests.clone(),
+            pending_spends: self.pending_spends.clone(),
+            followed_work: self.followed_work,
+            plugins: Vec::new(),
+            alerts: self.alerts.clone(),
+            receive_only: self.receive_only.clone(),
+            accounts: self.accounts.clone(),
+            watchtower: self.watchtower.clone(),
+            backups: None,
         }
     }
+}
+
+/// Only shows sizes and the sync position, so logging a wallet does not leak its addresses, labels, or balances.
+impl fmt::Debug for Wallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wallet")
+            .field("addresses", &self.addresses.len())
+            .field("best_block_height", &self.best_block_height)
+            .field("best_block_hash", &self.best_block_hash)
+            .field("coins", &self.coins.len())
+            .field("history_entries", &self.history.entries().len())
+            .field("has_store", &self.store.is_some())
+            .finish_non_exhaustive()
+    }
+}
 
+impl WalletReader for Wallet {
     fn best_height(&self) -> u64 {
         self.best_block_height
     }
@@ -40,14 +281,14 @@ impl WalletApi for Wallet {
     fn total_assets_of(&self, address: Address) -> WalletResult<u64> {
         if !self.addresses.contains(&address) {
             // check if wallet owns the given address
-            return Err(WalletError::ForeignAddress);
+            return Err(WalletError::ForeignAddress(address));
         }
 
         // filter wallet's coins by the provided address and sums their values
         let total: u64 = self
             .coins
             .values()
-            .filter(|coin| coin.owner == address)
+            .filter(|coin| coin.owner == address && coin.is_native())
             .map(|coin| coin.value)
             .sum();
 
@@ -55,21 +296,21 @@ impl WalletApi for Wallet {
     }
 
     fn net_worth(&self) -> u64 {
-        self.coins.values().map(|coin| coin.value).sum() // total value of all coins in the wallet regardless of the owner
+        self.coins.native_total() // total bones in the wallet regardless of the owner
     }
 
     fn all_coins_of(&self, address: Address) -> WalletResult<HashSet<(CoinId, u64)>> {
         // returns all coins owned by a given address
         if !self.addresses.contains(&address) {
             // check if wallet owns the given address
-            return Err(WalletError::ForeignAddress);
+            return Err(WalletError::ForeignAddress(address));
         }
 
         // collect all coins owned by the given address into a HashSet
         let coins: HashSet<(CoinId, u64)> = self
             .coins
             .iter()
-            .filter(|(_, coin)| coin.owner == address)
+            .filter(|(_, coin)| coin.owner == address && coin.is_native())
             .map(|(coin_id, coin)| (*coin_id, coin.value))
             .collect();
 
@@ -82,19 +323,256 @@ impl WalletApi for Wallet {
         if let Some(coin) = self.coins.get(coin_id) {
             Ok(coin.clone())
         } else {
-            Err(WalletError::UnknownCoin)
+            Err(WalletError::UnknownCoin(*coin_id))
         }
     }
 
+}
+
+impl TransactionAuthor for Wallet {
     fn create_manual_transaction(
         &self,
         input_coin_ids: Vec<CoinId>,
         output_coins: Vec<Coin>,
     ) -> WalletResult<Transaction> {
-        // Ensure all input coins exist in the wallet
+        self.build_manual_transaction(input_coin_ids, output_coins, false)
+    }
+
+    fn create_automatic_transaction(
+        &self,
+        recipient: Address,
+        payment_amount: u64,
+        burn_aka_tip: u64,
+    ) -> WalletResult<Transaction> {
+        let transaction = self.build_automatic_transaction(recipient, payment_amount, burn_aka_tip)?;
+        self.check_policy(&transaction)?;
+        Ok(transaction)
+    }
+
+}
+
+impl WalletSync for Wallet {
+    fn new(addresses: impl Iterator<Item = Address>) -> Self {
+        Wallet::with_scheme(addresses, KeyScheme)
+    }
+
+    fn sync<Node: NodeEndpoint>(&mut self, node: &Node) {
+        let start_height = self.best_block_height;
+        if let Err(SyncError::ReorgTooDeep { .. }) = self.try_sync(node) {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(from_height = start_height, "reorg below the undo records, resyncing from genesis");
+            let held: Vec<CoinId> = self.coins.keys().copied().collect();
+            self.reset_to_genesis();
+            self.note_rollback((self.best_block_height, self.best_block_hash), held);
+            if start_height > 0 {
+                self.emit(WalletEvent::RolledBack { to_height: 0 });
+            }
+            // a wallet at genesis is always on the node's chain
+            let _ = self.try_sync(node);
+        }
+    }
+}
+
+impl Wallet {
+    /// Create a wallet owning the given addresses under a custom address scheme.
+    pub fn with_scheme(addresses: impl Iterator<Item = Address>, scheme: impl AddressScheme + 'static) -> Self {
+        let address_set: HashSet<Address> = addresses.collect(); // convert iterator into hashset
+
+        Wallet {
+            addresses: address_set,
+            coins: CoinStore::default(), // initial empty map of coins
+            best_block_height: 0,                    // initial height
+            best_block_hash: Block::genesis().id(),  // initial block hash (genesis default)
+            coinbase_heights: HashMap::new(),
+            history: History::default(),
+            outpoints: HashMap::new(),
+            labels: Labels::default(),
+            policy: SpendingPolicy::default(),
+            pending_approvals: HashMap::new(),
+            channels: HashMap::new(),
+            change_cursor: AtomicUsize::new(0),
+            scheme: Arc::new(scheme),
+            signer: Arc::new(SoftwareSigner),
+            watched: HashMap::new(),
+            registered: HashMap::new(),
+            events: VecDeque::new(),
+            notifier: None,
+            dropped_notifications: 0,
+            store: None,
+            undo: VecDeque::new(),
+            headers: HeaderWindow::default(),
+            config: WalletConfig::default(),
+            pruned_spent_to: 0,
+            hd: None,
+            receiving: ReceiveAddresses::default(),
+            sync_warnings: Vec::new(),
+            reorgs: ReorgLog::default(),
+            credited_deposits: HashSet::new(),
+            withdrawals: WithdrawalQueue::default(),
+            sent_requests: HashMap::new(),
+            pending_spends: HashMap::new(),
+            followed_work: None,
+            plugins: Vec::new(),
+            alerts: AlertRules::default(),
+            receive_only: HashSet::new(),
+            accounts: BTreeMap::new(),
+            watchtower: HashSet::new(),
+            backups: None,
+        }
+    }
+
+    /// Sync with the node like `WalletSync::sync`, but report a reorg that reaches below the wallet's
+    /// undo records instead of resyncing from genesis.
+    /// On success, returns which blocks the sync rolled back and applied, see `SyncReport`.
+    pub fn try_sync<Node: NodeEndpoint>(&mut self, node: &Node) -> Result<SyncReport, SyncError> {
+        let start = (self.best_block_height, self.best_block_hash);
+        let reorgs_before = self.reorgs.records.len();
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("sync", wallet = %self.fingerprint(), from_height = start.0).entered();
+        self.check_work(node)?;
+
+        // undo the blocks that left the node's chain, finding the fork point in the header window if it is there
+        let fork = self.local_fork(node);
+        let (reverted, on_chain) = match &fork {
+            Some(fork) => (self.undo_to(fork.height), self.best_block_height == fork.height),
+            None => self.undo_orphaned_blocks(node),
+        };
+        let mut report = SyncReport {
+            reverted,
+            ..SyncReport::default()
+        };
+        let reverted: Vec<CoinId> =
+            report.reverted.iter().flat_map(|block| &block.credited).map(|(coin_id, _)| *coin_id).collect();
+        #[cfg(feature = "tracing")]
+        if self.best_block_height < start.0 {
+            tracing::warn!(from_height = start.0, fork_height = self.best_block_height, "reorg detected");
+        }
+
+        // without undo records reaching the fork point, the wallet cannot tell which of its coins are still valid
+        if !on_chain
+            && self.best_block_hash
+                != node
+                    .best_block_at_height(self.best_block_height)
+                    .unwrap_or(Block::genesis().id())
+        {
+            // keep the rollback so far, a later attempt continues from here
+            self.note_rollback(start, reverted);
+            let _ = self.flush_store();
+            return Err(SyncError::ReorgTooDeep { undone_to: self.best_block_height });
+        }
+        if self.best_block_height < start.0 {
+            #[cfg(feature = "tracing")]
+            tracing::info!(to_height = self.best_block_height, coins = self.coins.len(), "rolled back");
+            self.emit(WalletEvent::RolledBack { to_height: self.best_block_height });
+            self.note_rollback(start, reverted);
+        }
+
+        // sync forward from the detected height, applying each block as a whole, starting with the ones already fetched
+        let mut tip_timestamp = None;
+        let lazy = self.config.lazy_bodies && !self.config.strict_sync;
+        let mut filter = lazy.then(|| self.sync_filter());
+        let at_tip = fork.as_ref().is_some_and(|fork| fork.at_tip);
+        let mut fetched = fork.into_iter().flat_map(|fork| fork.blocks).map(|(block_id, block)| (block_id, Some(Body::Full(block))));
+        while let Some((block_id, body)) = fetched.next().or_else(|| {
+            if at_tip {
+                return None;
+            }
+            let block_id = node.best_block_at_height(self.best_block_height + 1)?;
+            Some((block_id, self.fetch_body(node, &block_id, filter.as_ref())))
+        }) {
+            if let Some(Body::Full(block)) = body {
+                #[cfg(feature = "tracing")]
+                let _span = tracing::debug_span!("apply_block", height = block.number).entered();
+                if self.config.strict_sync {
+                    self.check_block(block_id, &block);
+                }
+                let delta = self.block_delta(block_id, &block);
+                report.applied.push(BlockReport::new(&delta));
+                self.apply_delta(&block.body, delta);
+                self.discover_addresses(node, &block, &mut report);
+                tip_timestamp = Some(block.timestamp);
+                if lazy {
+                    filter = Some(self.sync_filter()); // the block may have given the wallet coins to follow
+                }
+                #[cfg(feature = "tracing")]
+                tracing::debug!(transactions = block.body.len(), coins = self.coins.len(), "block applied");
+            } else if let Some(Body::Skipped) = body {
+                let delta = self.header_delta(block_id);
+                report.applied.push(BlockReport::new(&delta));
+                self.apply_delta(&[], delta);
+                tip_timestamp = None; // only known from the body
+                #[cfg(feature = "tracing")]
+                tracing::debug!(height = self.best_block_height, "block body skipped");
+            } else {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(height = self.best_block_height + 1, "node did not return the block body, stopping sync");
+                break; // failed to fetch block, stop sync
+            }
+            if let Err(_e) = self.flush_store() {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(height = self.best_block_height, error = %_e, "store commit failed, stopping sync");
+                break; // the block stays staged and is committed by the next sync
+            }
+        }
+        // commits a rollback that no new block followed; a failure is retried by the next sync
+        let _ = self.flush_store();
+        self.finish_reorg(node, tip_timestamp);
+        self.settle_pending_spends();
+        if self.config.check_work {
+            self.followed_work = node.cumulative_work(&self.best_block_hash).or(self.followed_work);
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(height = self.best_block_height, coins = self.coins.len(), "sync finished");
+        #[cfg(debug_assertions)]
+        if let Err(violation) = self.check_invariants() {
+            panic!("wallet invariant violated after sync: {violation}");
+        }
+        self.coins.trim(); // sync reads coins beyond the ones it changes
+        if (self.best_block_height, self.best_block_hash) != start {
+            self.emit(WalletEvent::Synced { height: self.best_block_height, block_id: self.best_block_hash });
+        }
+        self.raise_alerts(&report, reorgs_before);
+        self.backup_if_due();
+        Ok(report)
+    }
+
+    /// Forget everything learned from the chain, so the next sync starts over from genesis.
+    fn reset_to_genesis(&mut self) {
+        self.coins.clear();
+        self.coinbase_heights.clear();
+        self.history.clear();
+        self.undo.clear();
+        self.headers.clear();
+        self.pruned_spent_to = 0;
+        self.forget_tracking_above(0);
+        self.best_block_height = 0;
+        self.best_block_hash = Block::genesis().id();
+        self.notify_rewound(0);
+    }
+
+    /// Construct the transaction behind `create_manual_transaction`, spending coins of receive-only
+    /// addresses only if `spend_receive_only` is set.
+    fn build_manual_transaction(
+        &self,
+        input_coin_ids: Vec<CoinId>,
+        output_coins: Vec<Coin>,
+        spend_receive_only: bool,
+    ) -> WalletResult<Transaction> {
+        if self.is_empty() {
+            return Err(WalletError::NoOwnedAddresses);
+        }
+
+        // Ensure all input coins exist in the wallet and can already be spent
         for &coin_id in &input_coin_ids {
             if !self.coins.contains_key(&coin_id) {
-                return Err(WalletError::UnknownCoin);
+                return Err(WalletError::UnknownCoin(coin_id));
+            }
+            if !self.is_mature(&coin_id) {
+                return Err(WalletError::ImmatureCoin(coin_id));
+            }
+            if !spend_receive_only {
+                self.check_spending_enabled(&coin_id)?;
             }
         }
 
@@ -110,126 +588,335 @@ impl WalletApi for Wallet {
         // Create transaction inputs from the specified coin IDs
         let inputs = input_coin_ids.into_iter().map(|coin_id| Input {
             coin_id,
-            signature: Signature::Valid(self.addresses.iter().next().unwrap().clone()), // Placeholder for signature
+            signature: Signature::Invalid, // signed by the coin's owner below
         }).collect();
 
-        let transaction = Transaction {
+        let mut transaction = Transaction {
+            version: TRANSACTION_VERSION,
             // create transaction with provided inputs and outputs
             inputs,
             outputs: output_coins,
         };
+        self.sign_inputs(&mut transaction);
 
+        if !transaction.conserves_assets(|coin_id| self.coins.get(coin_id).cloned()) {
+            return Err(WalletError::AssetNotConserved);
+        }
+
+        self.check_policy(&transaction)?;
         Ok(transaction)
     }
 
-    fn create_automatic_transaction(
+    /// Select coins and construct the transaction behind `create_automatic_transaction`, without applying the spending policy.
+    fn build_automatic_transaction(
+        &self,
+        recipient: Address,
+        payment_amount: u64,
+        burn_aka_tip: u64,
+    ) -> WalletResult<Transaction> {
+        self.build_restricted_transaction(recipient, payment_amount, burn_aka_tip, &|_, _| true, None, false)
+    }
+
+    /// Like `build_automatic_transaction`, but paying exactly `amount` without a tip, for funding a
+    /// coin whose value the contract it is locked under depends on. Dust change goes to the tip
+    /// even if the dust policy would add it to the payment.
+    fn build_funding_transaction(&self, recipient: Address, amount: u64) -> WalletResult<Transaction> {
+        self.build_restricted_transaction(recipient, amount, 0, &|_, _| true, None, true)
+    }
+
+    /// Like `build_automatic_transaction`, but only selecting coins for which `eligible` holds,
+    /// sending the change to `change_to` if given rather than where the change policy says, and
+    /// never adding dust to the payment if `exact_payment` is set.
+    fn build_restricted_transaction(
         &self,
         recipient: Address,
         payment_amount: u64,
         burn_aka_tip: u64,
+        eligible: &dyn Fn(&CoinId, &Coin) -> bool,
+        change_to: Option<&Address>,
+        exact_payment: bool,
     ) -> WalletResult<Transaction> {
+        if self.is_empty() {
+            return Err(WalletError::NoOwnedAddresses);
+        }
+
         // validate payment amount and tip
         if payment_amount == 0 {
             return Err(WalletError::ZeroCoinValue);
         }
 
-        let total_needed = payment_amount + burn_aka_tip; // calculate total needed amount
-        let mut selected_coins: Vec<(CoinId, Coin)> = Vec::new();
-        let mut total_selected: u64 = 0;
+        // calculate total needed amount, which no wallet can cover if it overflows
+        let total_needed = payment_amount.checked_add(burn_aka_tip).ok_or(WalletError::InsufficientFunds {
+            needed: u64::MAX,
+            available: self.net_worth(),
+        })?;
 
-        // select coins to cover total amount needed
-        for (&coin_id, coin) in &self.coins {
-            if total_selected >= total_needed {
-                break;
-            }
-            selected_coins.push((coin_id, coin.clone()));
-            total_selected += coin.value;
-        }
-
-        if total_selected < total_needed {
-            return Err(WalletError::InsufficientFunds);
-        }
+        // select coins to cover total amount needed, skipping coinbase coins that can't be spent yet, coins of
+        // receive-only addresses, and issued assets
+        let candidates = self
+            .coins
+            .iter()
+            .filter(|(&coin_id, coin)| self.is_auto_spendable(&coin_id, coin) && coin.is_native() && eligible(&coin_id, coin))
+            .map(|(&coin_id, coin)| (coin_id, coin.clone()))
+            .collect();
+        let selected_coins = self.select_coins(candidates, total_needed)?;
+        let total_selected: u64 = selected_coins.iter().map(|(_, coin)| coin.value).sum();
 
         // Prepare inputs and outputs
-        let inputs = selected_coins.into_iter().map(|(coin_id, coin)| Input {
+        let inputs = selected_coins.into_iter().map(|(coin_id, _)| Input {
             coin_id,
-            signature: Signature::Valid(coin.owner),
+            signature: Signature::Invalid,
         }).collect::<Vec<_>>();
 
         let mut outputs = vec![Coin {
             value: payment_amount,
-            owner: recipient
+            owner: recipient,
+            asset_id: None,
         }];
 
-        // add change output if there is remaining value
+        // add change output if there is remaining value, unless it is within the selection's tolerance
+        // or the change policy keeps it out of change as dust
         let change_value = total_selected - total_needed;
-        if change_value > 0 {
-            let change_address = self.addresses.iter().next().unwrap().clone(); // Or handle change address more appropriately
-            outputs.push(Coin {
-                value: change_value,
-                owner: change_address
+        if change_value > self.config.selection.change_tolerance() {
+            outputs.extend(match change_to {
+                Some(owner) => self.change_output_to(change_value, None, owner),
+                None => self.change_output(change_value, None)?,
             });
+            if !exact_payment {
+                outputs[0].value += self.dust_for_payment(change_value, None);
+            }
         }
+        self.check_change_outputs(outputs.len() - 1)?;
 
-        let transaction = Transaction { inputs, outputs }; // create the transaction
+        let mut transaction = Transaction { version: TRANSACTION_VERSION, inputs, outputs }; // create the transaction
+        self.sign_inputs(&mut transaction);
         Ok(transaction)
     }
 
-    fn sync<Node: NodeEndpoint>(&mut self, node: &Node) {
-        // rollback if reorganization is detected
-        while let Some(block_id) = node.best_block_at_height(self.best_block_height) {
-            if block_id == self.best_block_hash {
-                break; // block_id matches, no reorganization detected, end rollback
-            }
-            if self.best_block_height == 0 {
-                break; // reached genesis block, end rollback
+    /// Construct a transaction splitting one coin into several coins of the given values, all owned
+    /// by the original owner. Whatever the parts leave of the coin's value is burned as the tip.
+    /// Coins carrying an issued asset must be split exactly, since asset value cannot be burned.
+    pub fn create_split_transaction(&self, coin_id: CoinId, parts: Vec<u64>) -> WalletResult<Transaction> {
+        let coin = self.coins.get(&coin_id).ok_or(WalletError::UnknownCoin(coin_id))?;
+        if !self.is_mature(&coin_id) {
+            return Err(WalletError::ImmatureCoin(coin_id));
+        }
+        self.check_spending_enabled(&coin_id)?;
+        if parts.is_empty() || parts.contains(&0) {
+            return Err(WalletError::ZeroCoinValue);
+        }
+        let total = parts.iter().try_fold(0u64, |total, part| total.checked_add(*part));
+        match total {
+            Some(total) if total == coin.value => {}
+            Some(total) if total < coin.value && coin.is_native() => {}
+            Some(total) if total < coin.value => return Err(WalletError::AssetNotConserved),
+            _ => {
+                return Err(WalletError::InsufficientFunds {
+                    needed: total.unwrap_or(u64::MAX),
+                    available: coin.value,
+                })
             }
-            // move one step back
-            self.best_block_height -= 1;
-            self.best_block_hash = node
-                .best_block_at_height(self.best_block_height)
-                .unwrap_or(Block::genesis().id());
-        }
-
-        // clear UTXO set for resync if reorganization detected
-        if self.best_block_hash
-            != node
-                .best_block_at_height(self.best_block_height)
-                .unwrap_or(Block::genesis().id())
-        {
-            self.coins.clear();
-            self.best_block_height = 0;
-            self.best_block_hash = Block::genesis().id();
-        }
-
-        // sync forward from the detected height
-        while let Some(block_id) = node.best_block_at_height(self.best_block_height + 1) {
-            if let Some(block) = node.entire_block(&block_id) {
-                for transaction in &block.body {
-                    // process transactions in the block
-                    for input in &transaction.inputs {
-                        self.coins.remove(&input.coin_id); // removes entries whose CoinId matches the input.coin_id
-                    }
-
-                    // add new coins created by the transaction to the wallet's UTXO set
-                    for (index, coin) in transaction.outputs.iter().enumerate() {
-                        let coin_id = transaction.coin_id(block.number, index);
-                        if self.addresses.contains(&coin.owner) {
-                            self.coins.insert(coin_id, coin.clone());
-                        }
-                    }
-                }
+        }
 
-                // update the wallet's best block height and hash
-                self.best_block_height = block.number;
-                self.best_block_hash = block_id;
-            } else {
-                break; // failed to fetch block, stop sync
+        let mut transaction = Transaction {
+            version: TRANSACTION_VERSION,
+            inputs: vec![Input {
+                coin_id,
+                signature: Signature::Invalid,
+            }],
+            outputs: parts
+                .into_iter()
+                .map(|value| Coin {
+                    value,
+                    ..coin.clone()
+                })
+                .collect(),
+        };
+        self.sign_inputs(&mut transaction);
+        self.check_policy(&transaction)?;
+        Ok(transaction)
+    }
+
+    /// The transaction versions the wallet can author and sign. It builds every transaction with
+    /// `TRANSACTION_VERSION`, and leaves partial transactions of other versions unsigned.
+    pub fn authorable_versions(&self) -> &'static [u16] {
+        SUPPORTED_TRANSACTION_VERSIONS
+    }
+
+    /// Whether the wallet owns no addresses at all. Such a wallet can be queried and synced,
+    /// but every attempt to author a transaction fails with `NoOwnedAddresses`.
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Whether the given coin may be spent at the wallet's current best height.
+    /// Only coinbase coins can be immature; every other coin (including unknown ones) is considered mature.
+    pub fn is_mature(&self, coin_id: &CoinId) -> bool {
+        match self.coinbase_heights.get(coin_id) {
+            Some(&minted_at) => self.best_block_height >= minted_at + self.config.coinbase_maturity,
+            None => true,
+        }
+    }
+
+    /// Return the coinbase coins owned by the wallet that cannot be spent yet, along with their amounts.
+    pub fn immature_coins(&self) -> HashSet<(CoinId, u64)> {
+        self.coinbase_heights
+            .keys()
+            .filter(|coin_id| !self.is_mature(coin_id))
+            .filter_map(|coin_id| Some((*coin_id, self.coins.get(coin_id)?.value)))
+            .collect()
+    }
+
+    /// Undo every history entry above `height` and move the sync position back to `(height, hash)`.
+    ///
+    /// The history records every coin the wallet gained or lost, so replaying it backwards
+    /// restores the exact UTXO set the wallet had at that height.
+    pub(crate) fn rewind_to(&mut self, height: u64, hash: BlockId) {
+        let keep = self.history.entries().partition_point(|entry| entry.height <= height);
+        for entry in self.history.entries()[keep..].iter().rev() {
+            for (coin_id, _) in &entry.received {
+                self.coins.remove(coin_id);
+                self.coinbase_heights.remove(coin_id);
+                self.outpoints.remove(coin_id);
             }
+            for (coin_id, coin) in &entry.spent {
+                self.coins.insert(*coin_id, coin.clone());
+            }
+        }
+        self.history.truncate_above(height);
+        self.forget_tracking_above(height);
+        while self.undo.back().is_some_and(|delta| delta.height > height) {
+            self.undo.pop_back();
+        }
+        self.headers.truncate_above(height);
+        self.pruned_spent_to = self.pruned_spent_to.min(height);
+        self.best_block_height = height;
+        self.best_block_hash = hash;
+        self.notify_rewound(height);
+    }
+
+    /// Forget what watched coins and registered transactions went through above `height`.
+    fn forget_tracking_above(&mut self, height: u64) {
+        for watched in self.watched.values_mut() {
+            watched.forget_above(height);
         }
+        for registered in self.registered.values_mut() {
+            registered.forget_above(height);
+        }
+        self.forget_withdrawals_above(height);
+    }
+
+    /// Return the transactions that touched the wallet, oldest first.
+    pub fn history(&self) -> &[HistoryEntry] {
+        self.history.entries()
+    }
+
+    /// Search the wallet history, oldest first. See `TxFilter` for the supported criteria and pagination.
+    pub fn find_transactions(&self, filter: TxFilter) -> Vec<&HistoryEntry> {
+        self.history.find(&filter, &self.labels.transactions)
+    }
+
+    /// Trace which transactions created and spent a coin that belongs (or belonged) to the wallet.
+    pub fn coin_provenance(&self, coin_id: &CoinId) -> WalletResult<Provenance> {
+        let history = self.history.entries();
+        let created_at = history
+            .iter()
+            .position(|entry| entry.received.iter().any(|(id, _)| id == coin_id))
+            .ok_or(WalletError::UnknownCoin(*coin_id))?;
+        let created = &history[created_at];
+
+        // a coin can only be spent after it was created
+        let spent = history[created_at + 1..]
+            .iter()
+            .find(|entry| entry.spent.iter().any(|(id, _)| id == coin_id));
+
+        Ok(Provenance {
+            created_by: created.tx_id,
+            created_at_height: created.height,
+            spent_by: spent.map(|entry| entry.tx_id),
+            spent_at_height: spent.map(|entry| entry.height),
+        })
+    }
+
+    /// Label one of the wallet's addresses (or a contact's). An empty label removes it.
+    pub fn set_address_label(&mut self, address: Address, label: impl Into<String>) {
+        set_label(&mut self.labels.addresses, address, label.into());
+    }
+
+    /// Label a coin the wallet has received. An empty label removes it.
+    ///
+    /// The label is attached to the creating transaction and output index, so it is retained
+    /// when a reorg re-includes that transaction on the new branch under a different coin id.
+    pub fn set_coin_label(&mut self, coin_id: &CoinId, label: impl Into<String>) -> WalletResult<()> {
+        let outpoint = *self.outpoints.get(coin_id).ok_or(WalletError::UnknownCoin(*coin_id))?;
+        set_label(&mut self.labels.coins, outpoint, label.into());
+        Ok(())
+    }
+
+    /// Label a transaction, whether or not it has been seen on chain yet. An empty label removes it.
+    pub fn set_tx_label(&mut self, tx_id: TransactionId, label: impl Into<String>) {
+        set_label(&mut self.labels.transactions, tx_id, label.into());
+    }
+
+    /// The label of the given address, if any.
+    pub fn address_label(&self, address: &Address) -> Option<&str> {
+        self.labels.addresses.get(address).map(String::as_str)
+    }
+
+    /// The label of the given coin, if any.
+    pub fn coin_label(&self, coin_id: &CoinId) -> Option<&str> {
+        let outpoint = self.outpoints.get(coin_id)?;
+        self.labels.coins.get(outpoint).map(String::as_str)
+    }
+
+    /// The label of the given transaction, if any.
+    pub fn tx_label(&self, tx_id: &TransactionId) -> Option<&str> {
+        self.labels.transactions.get(tx_id).map(String::as_str)
+    }
+
+    /// All labeled addresses along with their labels.
+    pub fn labeled_addresses(&self) -> Vec<(&Address, &str)> {
+        self.labels.addresses.iter().map(|(address, label)| (address, label.as_str())).collect()
+    }
+
+    /// The wallet's unspent coins that carry a label, along with their amounts and labels.
+    pub fn labeled_coins(&self) -> Vec<(CoinId, u64, &str)> {
+        self.coins
+            .iter()
+            .filter_map(|(coin_id, coin)| Some((*coin_id, coin.value, self.coin_label(coin_id)?)))
+            .collect()
+    }
+
+    /// The history entries whose transaction carries a label, oldest first, along with their labels.
+    pub fn labeled_transactions(&self) -> Vec<(&HistoryEntry, &str)> {
+        self.history
+            .entries()
+            .iter()
+            .filter_map(|entry| Some((entry, self.tx_label(&entry.tx_id)?)))
+            .collect()
+    }
+
+    /// Return every coin the wallet received in a block timestamped within `t0..=t1`, along with its amount.
+    /// Coins that have been spent since are included.
+    pub fn coins_received_between(&self, t0: u64, t1: u64) -> HashSet<(CoinId, u64)> {
+        self.history
+            .entries()
+            .iter()
+            .filter(|entry| (t0..=t1).contains(&entry.timestamp))
+            .flat_map(|entry| entry.received.iter())
+            .map(|(coin_id, coin)| (*coin_id, coin.value))
+            .collect()
     }
 }
 
+/// Store a label, treating an empty label as a removal.
+fn set_label<K: std::hash::Hash + Eq>(labels: &mut HashMap<K, String>, key: K, label: String) {
+    if label.is_empty() {
+        labels.remove(&key);
+    } else {
+        labels.insert(key, label);
+    }
+}
 
 #[cfg(test)]
 mod simple_tests;
@@ -240,3 +927,12 @@ mod tests;
 #[cfg(test)]
 mod adv_tests;
 
+#[cfg(test)]
+mod simulation;
+
+#[cfg(test)]
+mod state_fixtures;
+
+#[cfg(test)]
+mod test_support;
+
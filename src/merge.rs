@@ -0,0 +1,239 @@
+//! Combining the databases of two wallets into one.
+//!
+//! Both wallets are first rewound to a common height whose blocks they agree on with the node.
+//! Their UTXO sets, labels, and policies are then combined, and the history of the combined
+//! address set is re-derived from the blocks either wallet had recorded. Finally the merged
+//! wallet syncs the remainder of the chain on its own.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use bonecoin_core::*;
+
+use crate::history::burned_by;
+use crate::{Direction, EVENT_CAPACITY, HistoryEntry, SpendingPolicy, Wallet};
+
+/// Errors that can occur while merging two wallets.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum MergeError {
+    /// Both wallets track this address but disagree about its coins at the common height.
+    ConflictingCoins(Address),
+    /// Both wallets have a non-default spending policy and the policies differ.
+    ConflictingPolicies,
+    /// The node could not provide a block needed to rebuild the merged history.
+    NodeUnavailable,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::ConflictingCoins(address) => write!(f, "the wallets disagree about the coins of {address}"),
+            MergeError::ConflictingPolicies => write!(f, "the wallets have different spending policies"),
+            MergeError::NodeUnavailable => write!(f, "the node could not provide a block needed for the merge"),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+impl Wallet {
+    /// Merge `other` into this wallet, producing a wallet that owns the addresses of both.
+    ///
+    /// When both wallets label the same item, this wallet's label wins.
+    /// The merged wallet is synced with `node` before it is returned.
+    pub fn merge<Node: NodeEndpoint>(mut self, mut other: Wallet, node: &Node) -> Result<Wallet, MergeError> {
+        // the wallets' states are only trustworthy up to their first block that left the node's chain
+        let mut height = self.best_block_height.min(other.best_block_height);
+        for wallet in [&self, &other] {
+            let stale = wallet
+                .history
+                .entries()
+                .iter()
+                .find(|entry| node.best_block_at_height(entry.height) != Some(entry.block_id));
+            if let Some(entry) = stale {
+                height = height.min(entry.height.saturating_sub(1));
+            }
+        }
+        let hash = loop {
+            match node.best_block_at_height(height) {
+                Some(hash) => break hash,
+                None => height -= 1, // the node's chain is shorter than both wallets
+            }
+        };
+        self.rewind_to(height, hash);
+        other.rewind_to(height, hash);
+
+        for address in self.addresses.intersection(&other.addresses) {
+            let coins_of = |wallet: &Wallet| -> HashSet<CoinId> {
+                wallet
+                    .coins
+                    .iter()
+                    .filter(|(_, coin)| coin.owner == *address)
+                    .map(|(coin_id, _)| *coin_id)
+                    .collect()
+            };
+            if coins_of(&self) != coins_of(&other) {
+                return Err(MergeError::ConflictingCoins(address.clone()));
+            }
+        }
+
+        let default_policy = SpendingPolicy::default();
+        if self.policy != default_policy && other.policy != default_policy && self.policy != other.policy {
+            return Err(MergeError::ConflictingPolicies);
+        }
+        if self.policy == default_policy {
+            self.policy = other.policy;
+        }
+
+        // every coin either wallet ever held, so spends between the two wallets can be valued
+        let known_coins: HashMap<CoinId, Coin> = self
+            .history
+            .entries()
+            .iter()
+            .chain(other.history.entries())
+            .flat_map(|entry| entry.received.iter().chain(entry.spent.iter()))
+            .cloned()
+            .collect();
+        let heights: BTreeSet<u64> = self
+            .history
+            .entries()
+            .iter()
+            .chain(other.history.entries())
+            .map(|entry| entry.height)
+            .collect();
+
+        // the undo records only cover this wallet's coins, so a later reorg resyncs from genesis instead
+        self.undo.clear();
+        self.headers.clear();
+        self.addresses.extend(other.addresses);
+        self.coins.extend(other.coins);
+        self.coinbase_heights.extend(other.coinbase_heights);
+        self.outpoints.extend(other.outpoints);
+        self.pending_approvals.extend(other.pending_approvals);
+        self.channels.extend(other.channels);
+        self.watched.extend(other.watched);
+        self.registered.extend(other.registered);
+        self.events.extend(other.events);
+        let overflow = self.events.len().saturating_sub(EVENT_CAPACITY);
+        self.events.drain(..overflow);
+        for (address, label) in other.labels.addresses {
+            self.labels.addresses.entry(address).or_insert(label);
+        }
+        for (outpoint, label) in other.labels.coins {
+            self.labels.coins.entry(outpoint).or_insert(label);
+        }
+        for (tx_id, label) in other.labels.transactions {
+            self.labels.transactions.entry(tx_id).or_insert(label);
+        }
+
+        // a payment from one wallet to the other is now a self transfer, so entries are re-derived from the blocks
+        self.history.clear();
+        for height in heights {
+            let block = node
+                .best_block_at_height(height)
+                .and_then(|block_id| Some((block_id, node.entire_block(&block_id)?)))
+                .ok_or(MergeError::NodeUnavailable)?;
+            self.rebuild_history_of_block(block.0, &block.1, &known_coins);
+        }
+
+        self.sync(node);
+        Ok(self)
+    }
+
+    fn rebuild_history_of_block(&mut self, block_id: BlockId, block: &Block, known_coins: &HashMap<CoinId, Coin>) {
+        for transaction in &block.body {
+            let spent: Vec<(CoinId, Coin)> = transaction
+                .iter_input_coin_ids()
+                .filter_map(|coin_id| Some((coin_id, known_coins.get(&coin_id)?.clone())))
+                .filter(|(_, coin)| self.owns(&coin.owner))
+                .collect();
+            let received: Vec<(CoinId, Coin)> = transaction
+                .iter_output_coins_and_ids(block.number)
+                .filter(|(_, coin)| self.owns(&coin.owner))
+                .collect();
+            let pays_foreign_address = transaction.outputs.iter().any(|coin| !self.owns(&coin.owner));
+
+            if !spent.is_empty() || !received.is_empty() {
+                self.history.push(HistoryEntry {
+                    tx_id: transaction.id(),
+                    block_id,
+                    height: block.number,
+                    timestamp: block.timestamp,
+                    direction: Direction::classify(!spent.is_empty(), spent.len() < transaction.inputs.len(), pays_foreign_address),
+                    burned: burned_by(transaction, &spent),
+                    received,
+                    spent,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A chain where Alice is minted 100 bones and pays Bob 30 of them, and wallets of Alice and
+    /// of Bob with labels of their own. Bob has synced one more block, with a marker, than Alice.
+    fn alice_and_bob_apart() -> (MockNode, Wallet, Wallet, Transaction) {
+        let mut node = MockNode::new();
+        let minted = mint([(Address::Alice, 100)]);
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![minted.clone()]);
+        let payment = spend(minted.coin_id(1, 0), Address::Alice, [(Address::Bob, 30), (Address::Alice, 65)]);
+        let b2_id = node.add_block_as_best(b1_id, vec![payment.clone()]);
+
+        let mut alice = wallet_with_alice();
+        alice.sync(&node);
+        alice.set_tx_label(payment.id(), "rent");
+        alice.set_address_label(Address::Alice, "mine");
+        let mut bob = Wallet::new(vec![Address::Bob].into_iter());
+        bob.sync(&node);
+        bob.set_address_label(Address::Alice, "landlord");
+        bob.set_address_label(Address::Bob, "me");
+
+        // alice lags behind and only sees the rest of the chain through the merge
+        node.add_block_as_best(b2_id, vec![marker_tx()]);
+        bob.sync(&node);
+        (node, alice, bob, payment)
+    }
+
+    #[test]
+    fn merging_wallets_matches_a_wallet_owning_both() {
+        let (node, alice, bob, _) = alice_and_bob_apart();
+        let merged = alice.merge(bob, &node).unwrap();
+        let mut both = wallet_with_alice_and_bob();
+        both.sync(&node);
+
+        assert_eq!(merged.best_hash(), node.best_block_at_height(3).unwrap());
+        assert_eq!(merged.net_worth(), 95);
+        assert_eq!(merged.history(), both.history());
+        assert_eq!(merged.history()[1].direction, Direction::Internal);
+        assert_eq!(merged.all_coins_of(Address::Bob), both.all_coins_of(Address::Bob));
+    }
+
+    #[test]
+    fn labels_of_the_merging_wallet_win_and_the_others_fill_in() {
+        let (node, alice, bob, payment) = alice_and_bob_apart();
+        let merged = alice.merge(bob, &node).unwrap();
+
+        assert_eq!(merged.tx_label(&payment.id()), Some("rent"));
+        assert_eq!(merged.address_label(&Address::Alice), Some("mine"));
+        assert_eq!(merged.address_label(&Address::Bob), Some("me"));
+    }
+
+    #[test]
+    fn wallets_with_different_policies_cannot_be_merged() {
+        let node = MockNode::new();
+        let mut strict = wallet_with_alice();
+        strict.set_spending_policy(SpendingPolicy {
+            max_per_transaction: Some(1),
+            ..Default::default()
+        });
+        let mut other = wallet_with_alice();
+        other.set_spending_policy(SpendingPolicy {
+            max_per_transaction: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(strict.merge(other, &node).err(), Some(MergeError::ConflictingPolicies));
+    }
+}
@@ -0,0 +1,72 @@
+//! Signing messages to prove control of the wallet's addresses off-chain.
+//!
+//! The address scheme decides which keys sign for an address, as for an input spending its coins,
+//! and the signer is asked for each of them with `message_digest`. Anyone can check the result with
+//! `verify_message` from core, without a wallet.
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+impl Wallet {
+    /// Sign `message` as `address`, which the wallet must own.
+    ///
+    /// A signer declining, or a multisig address the wallet holds fewer keys of than its threshold,
+    /// leaves the message short of signatures, so check it with `verify_message` before sending it.
+    pub fn sign_message(&self, address: &Address, message: &[u8]) -> WalletResult<SignedMessage> {
+        if !self.owns(address) {
+            return Err(WalletError::ForeignAddress(address.clone()));
+        }
+        Ok(SignedMessage {
+            address: address.clone(),
+            message: message.to_vec(),
+            signature: self.sign_with(&message_digest(address, message), address),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    use bonecoin_core::codec::{Decode, Encode};
+
+    /// A device that signs only the digest of one message as Alice.
+    struct Device(TransactionId);
+
+    impl Signer for Device {
+        fn sign(&self, tx_digest: &TransactionId, address: &Address) -> Signature {
+            match address {
+                Address::Alice if *tx_digest == self.0 => Signature::Valid(address.clone()),
+                _ => Signature::Invalid,
+            }
+        }
+    }
+
+    #[test]
+    fn messages_signed_by_an_owned_address_verify() {
+        let signed = wallet_with_alice().sign_message(&Address::Alice, b"ticket 42").unwrap();
+        assert_eq!(signed.signature, Signature::Valid(Address::Alice));
+        assert!(verify_message(&signed));
+    }
+
+    #[test]
+    fn signed_messages_survive_encoding() {
+        let signed = wallet_with_alice().sign_message(&Address::Alice, b"ticket 42").unwrap();
+        assert_eq!(SignedMessage::decode(&signed.encode()), Ok(signed));
+    }
+
+    #[test]
+    fn foreign_addresses_cannot_sign() {
+        assert_eq!(wallet_with_alice().sign_message(&Address::Bob, b"ticket 42"), Err(WalletError::ForeignAddress(Address::Bob)));
+    }
+
+    #[test]
+    fn the_signer_is_asked_with_the_message_digest_and_may_decline() {
+        let mut wallet = wallet_with_alice();
+        let signed = wallet.sign_message(&Address::Alice, b"ticket 42").unwrap();
+        wallet.set_signer(Device(message_digest(&Address::Alice, b"ticket 42")));
+        assert_eq!(wallet.sign_message(&Address::Alice, b"ticket 42"), Ok(signed));
+        assert!(!verify_message(&wallet.sign_message(&Address::Alice, b"ticket 43").unwrap()));
+    }
+}
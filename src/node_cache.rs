@@ -0,0 +1,315 @@
+//! Sharing one node between many wallets without repeating their queries.
+//!
+//! A service running a wallet per customer syncs every wallet against the same node, so each of
+//! them asks for the same block ids and bodies. `NodeCache` wraps the node and answers repeated
+//! `best_block_at_height` and `entire_block` calls from memory.
+//!
+//! Block bodies are cached by id, which never goes stale, up to a number of blocks; the oldest
+//! cached block is evicted first. The block ids of the best chain, and the height it ends at, are
+//! only valid until the node's tip changes. At most once per tip check interval, the cache asks the
+//! node whether the highest block it cached and the height past the tip are still what they were,
+//! and forgets every cached height if not. Between checks a wallet may be served a tip that just
+//! changed, which its next sync after the check corrects like any reorg; `invalidate` forgets the
+//! heights at once. Every other call goes straight to the node.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bonecoin_core::*;
+
+/// The blocks a `NodeCache` holds by default.
+pub const NODE_CACHE_BLOCKS: usize = 1024;
+
+/// How often a `NodeCache` checks the node's tip by default.
+pub const NODE_CACHE_TIP_CHECK: Duration = Duration::from_secs(1);
+
+/// How a `NodeCache` has served the calls so far.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct NodeCacheStats {
+    /// Calls answered from memory.
+    pub hits: u64,
+    /// Calls passed on to the node, tip checks included.
+    pub misses: u64,
+    /// The blocks held now.
+    pub blocks: usize,
+}
+
+impl NodeCacheStats {
+    /// The share of calls answered from memory, or 1 if there was none yet.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 1.0,
+            calls => self.hits as f64 / calls as f64,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Cached {
+    /// Block ids of the best chain by height, as of the last tip check.
+    heights: BTreeMap<u64, BlockId>,
+    /// The lowest height the node had no best block at, as of the last tip check.
+    end: Option<u64>,
+    /// When the tip was last found unchanged.
+    checked: Option<Instant>,
+    blocks: HashMap<BlockId, Block>,
+    /// The cached blocks' ids, oldest first, for eviction.
+    order: VecDeque<BlockId>,
+    hits: u64,
+    misses: u64,
+}
+
+/// A node endpoint answering repeated block queries of many wallets from memory.
+pub struct NodeCache<Node> {
+    node: Node,
+    capacity: usize,
+    tip_check: Duration,
+    cached: Mutex<Cached>,
+}
+
+impl<Node: NodeEndpoint> NodeCache<Node> {
+    /// Cache the answers of `node`, holding up to `NODE_CACHE_BLOCKS` blocks and checking the tip
+    /// every `NODE_CACHE_TIP_CHECK`.
+    pub fn new(node: Node) -> Self {
+        NodeCache {
+            node,
+            capacity: NODE_CACHE_BLOCKS,
+            tip_check: NODE_CACHE_TIP_CHECK,
+            cached: Mutex::new(Cached::default()),
+        }
+    }
+
+    /// Hold up to `blocks` blocks.
+    pub fn with_capacity(mut self, blocks: usize) -> Self {
+        self.capacity = blocks;
+        self
+    }
+
+    /// Check the node's tip at most once per `interval`. A zero interval checks it on every call
+    /// for a block id.
+    pub fn with_tip_check_interval(mut self, interval: Duration) -> Self {
+        self.tip_check = interval;
+        self
+    }
+
+    /// The node behind the cache.
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    /// The node behind the cache, to change. Cached block ids stay until the next tip check or
+    /// `invalidate`.
+    pub fn node_mut(&mut self) -> &mut Node {
+        &mut self.node
+    }
+
+    /// Stop caching and return the node.
+    pub fn into_inner(self) -> Node {
+        self.node
+    }
+
+    /// Forget the cached block ids of the best chain, for a tip known to have changed. Cached
+    /// blocks are kept, since a block id always names the same block.
+    pub fn invalidate(&self) {
+        let mut cached = self.lock();
+        cached.heights.clear();
+        cached.end = None;
+        cached.checked = None;
+    }
+
+    /// How the cache has served the calls so far.
+    pub fn stats(&self) -> NodeCacheStats {
+        let cached = self.lock();
+        NodeCacheStats {
+            hits: cached.hits,
+            misses: cached.misses,
+            blocks: cached.blocks.len(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Cached> {
+        self.cached.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Forget the cached heights if the tip check is due and finds the tip changed.
+    fn check_tip(&self, cached: &mut Cached) {
+        if cached.checked.is_some_and(|checked| checked.elapsed() < self.tip_check) {
+            return;
+        }
+        let top = cached.heights.last_key_value().map(|(height, block_id)| (*height, *block_id));
+        if let Some((height, block_id)) = top {
+            cached.misses += 1;
+            if self.node.best_block_at_height(height) != Some(block_id) {
+                cached.heights.clear();
+                cached.end = None;
+            }
+        }
+        if let Some(end) = cached.end {
+            cached.misses += 1;
+            if self.node.best_block_at_height(end).is_some() {
+                cached.heights.clear();
+                cached.end = None;
+            }
+        }
+        #[cfg(feature = "tracing")]
+        if cached.heights.is_empty() && top.is_some() {
+            tracing::debug!("node tip changed, cached block ids dropped");
+        }
+        cached.checked = Some(Instant::now());
+    }
+}
+
+impl<Node: NodeEndpoint> NodeEndpoint for NodeCache<Node> {
+    fn best_block_at_height(&self, h: u64) -> Option<BlockId> {
+        let mut cached = self.lock();
+        self.check_tip(&mut cached);
+        if cached.end.is_some_and(|end| h >= end) {
+            cached.hits += 1;
+            return None;
+        }
+        if let Some(block_id) = cached.heights.get(&h).copied() {
+            cached.hits += 1;
+            return Some(block_id);
+        }
+        cached.misses += 1;
+        let answer = self.node.best_block_at_height(h);
+        match answer {
+            Some(block_id) => {
+                cached.heights.insert(h, block_id);
+            }
+            // the chain ends here unless a higher block id was cached; a node failing to answer
+            // looks the same until the next tip check
+            None if cached.heights.range(h..).next().is_none() => cached.end = Some(cached.end.map_or(h, |end| end.min(h))),
+            None => {}
+        }
+        answer
+    }
+
+    fn entire_block(&self, id: &BlockId) -> Option<Block> {
+        let mut cached = self.lock();
+        if let Some(block) = cached.blocks.get(id).cloned() {
+            cached.hits += 1;
+            return Some(block);
+        }
+        cached.misses += 1;
+        let block = self.node.entire_block(id)?;
+        if self.capacity > 0 {
+            if cached.blocks.len() >= self.capacity {
+                if let Some(oldest) = cached.order.pop_front() {
+                    cached.blocks.remove(&oldest);
+                }
+            }
+            cached.blocks.insert(*id, block.clone());
+            cached.order.push_back(*id);
+        }
+        Some(block)
+    }
+
+    fn relevant_transactions(&self, id: &BlockId, filter: &BloomFilter) -> Option<Vec<Transaction>> {
+        let mut cached = self.lock();
+        if let Some(block) = cached.blocks.get(id) {
+            let matches = block.body.iter().filter(|tx| filter.matches_transaction(tx)).cloned().collect();
+            cached.hits += 1;
+            return Some(matches);
+        }
+        cached.misses += 1;
+        drop(cached);
+        self.node.relevant_transactions(id, filter)
+    }
+
+    fn submit_transaction(&self, transaction: &Transaction) -> bool {
+        self.node.submit_transaction(transaction)
+    }
+
+    fn cumulative_work(&self, id: &BlockId) -> Option<u64> {
+        self.node.cumulative_work(id)
+    }
+
+    fn common_ancestor(&self, known: &[BlockId]) -> Option<(BlockId, u64)> {
+        self.node.common_ancestor(known)
+    }
+
+    fn transaction(&self, id: &TransactionId) -> Option<(BlockId, u64, Transaction)> {
+        self.node.transaction(id)
+    }
+
+    fn is_unspent(&self, coin_id: &CoinId) -> Option<bool> {
+        self.node.is_unspent(coin_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::test_support::*;
+
+    /// A cache that never checks the tip again on its own, in front of a node where Alice, Bob,
+    /// Charlie and Alice mine blocks 1 to 4. Returns the cache, the tip, and wallets of Alice,
+    /// Bob and Charlie, of which only Alice's is synced.
+    fn shared_cache() -> (NodeCache<MockNode>, BlockId, Vec<Wallet>) {
+        let mut node = MockNode::new();
+        let mut parent = Block::genesis().id();
+        for owner in [Address::Alice, Address::Bob, Address::Charlie, Address::Alice] {
+            parent = node.add_block_as_best(parent, vec![Transaction::coinbase(owner, BLOCK_REWARD)]);
+        }
+        let cache = NodeCache::new(node).with_tip_check_interval(Duration::MAX);
+        let mut wallets: Vec<Wallet> =
+            [Address::Alice, Address::Bob, Address::Charlie].into_iter().map(|owner| Wallet::new([owner].into_iter())).collect();
+        wallets[0].sync(&cache);
+        (cache, parent, wallets)
+    }
+
+    /// Switches the node to a five block fork on genesis whose first block Bob mined, and returns
+    /// its tip.
+    fn reorg_to_bob(cache: &mut NodeCache<MockNode>) -> BlockId {
+        let mut tip = cache.node_mut().add_block(Block::genesis().id(), vec![Transaction::coinbase(Address::Bob, BLOCK_REWARD)]);
+        for _ in 0..4 {
+            tip = cache.node_mut().add_block(tip, vec![]);
+        }
+        cache.node_mut().set_best(tip);
+        tip
+    }
+
+    #[test]
+    fn wallets_sharing_a_cache_do_not_repeat_queries() {
+        let (cache, _, mut wallets) = shared_cache();
+
+        let calls = cache.node().how_many_calls();
+        for wallet in &mut wallets[1..] {
+            wallet.sync(&cache);
+        }
+
+        assert_eq!(cache.node().how_many_calls(), calls);
+        assert_eq!(wallets.iter().map(|wallet| wallet.net_worth()).collect::<Vec<_>>(), vec![100, 50, 50]);
+        assert_eq!(cache.stats().blocks, 4);
+    }
+
+    #[test]
+    fn the_cached_tip_stands_until_it_is_invalidated() {
+        let (mut cache, old_tip, mut wallets) = shared_cache();
+        let tip = reorg_to_bob(&mut cache);
+
+        wallets[1].sync(&cache);
+        assert_eq!(wallets[1].best_hash(), old_tip);
+
+        cache.invalidate();
+        for wallet in &mut wallets {
+            wallet.sync(&cache);
+        }
+        let synced: Vec<_> = wallets.iter().map(|wallet| (wallet.best_hash(), wallet.net_worth())).collect();
+        assert_eq!(synced, vec![(tip, 0), (tip, 50), (tip, 0)]);
+    }
+
+    #[test]
+    fn without_an_interval_every_sync_sees_the_new_tip() {
+        let (cache, old_tip, mut wallets) = shared_cache();
+        let mut cache = cache.with_tip_check_interval(Duration::ZERO);
+
+        let next = cache.node_mut().add_block_as_best(old_tip, vec![Transaction::coinbase(Address::Bob, BLOCK_REWARD)]);
+        wallets[1].sync(&cache);
+
+        assert_eq!((wallets[1].best_hash(), wallets[1].net_worth()), (next, 100));
+    }
+}
@@ -0,0 +1,157 @@
+//! A container for transactions that need signatures from more than one wallet.
+//!
+//! The transaction is passed from signer to signer. Each wallet only signs the inputs spending
+//! its own coins, and the transaction can only be extracted once every input is signed.
+//! Inputs spending multisig coins collect one signature per signer until the threshold is met;
+//! since the signers may not track the multisig coin themselves, the container can carry the
+//! coins being spent.
+//!
+//! Signatures made with `sign_partial_with` commit only to the parts their sighash flags select, so
+//! inputs and outputs can still be added with `add_input` and `add_output` after signing. A
+//! signature that no longer matches the transaction counts as missing.
+
+use bonecoin_core::*;
+
+use crate::{signing_digest, Wallet};
+
+/// A transaction whose inputs are being signed by several wallets.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PartialTransaction {
+    pub(crate) transaction: Transaction,
+    /// The coin spent by each input, where known to the container.
+    pub(crate) coins: Vec<Option<Coin>>,
+}
+
+impl PartialTransaction {
+    /// Wrap a transaction, discarding any signatures it already carries.
+    pub fn new(mut transaction: Transaction) -> Self {
+        for input in &mut transaction.inputs {
+            input.signature = Signature::Invalid;
+        }
+        let coins = vec![None; transaction.inputs.len()];
+        PartialTransaction { transaction, coins }
+    }
+
+    /// Wrap a transaction together with the coins its inputs spend, in input order.
+    pub fn with_input_coins(transaction: Transaction, coins: Vec<Coin>) -> Self {
+        let mut partial = PartialTransaction::new(transaction);
+        for (slot, coin) in partial.coins.iter_mut().zip(coins) {
+            *slot = Some(coin);
+        }
+        partial
+    }
+
+    /// The transaction being signed, including the signatures collected so far.
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    /// The transaction with every signature stripped, i.e. the part all signers must agree on.
+    pub fn unsigned(&self) -> Transaction {
+        PartialTransaction::new(self.transaction.clone()).transaction
+    }
+
+    /// Add an input, unsigned, optionally with the coin it spends. Signatures made so far stay
+    /// valid only if their sighash flags do not cover the inputs.
+    pub fn add_input(&mut self, coin_id: CoinId, coin: Option<Coin>) {
+        self.transaction.inputs.push(Input {
+            coin_id,
+            signature: Signature::Invalid,
+        });
+        self.coins.push(coin);
+    }
+
+    /// Add an output. Signatures made so far stay valid only if their sighash flags do not cover it.
+    pub fn add_output(&mut self, coin: Coin) {
+        self.transaction.outputs.push(coin);
+    }
+
+    /// The indices of the inputs that still lack a (sufficient) signature, including inputs whose
+    /// committed signature no longer matches the transaction.
+    pub fn unsigned_inputs(&self) -> Vec<usize> {
+        (0..self.transaction.inputs.len())
+            .filter(|&index| {
+                let signature = &self.transaction.inputs[index].signature;
+                let signed = match &self.coins[index] {
+                    Some(coin) => coin.owner.is_satisfied_by(signature),
+                    None => *signature != Signature::Invalid,
+                };
+                !signed || !self.transaction.signature_commits(index)
+            })
+            .collect()
+    }
+
+    /// Whether every input has been signed.
+    pub fn is_complete(&self) -> bool {
+        self.unsigned_inputs().is_empty()
+    }
+
+    /// Extract the fully signed transaction, or `None` while inputs are still unsigned.
+    pub fn finalize(self) -> Option<Transaction> {
+        self.is_complete().then_some(self.transaction)
+    }
+}
+
+impl Wallet {
+    /// Sign every input of the partial transaction that spends one of this wallet's coins,
+    /// or a multisig coin one of this wallet's addresses is a signer of.
+    /// Returns the number of inputs signed. Keys the wallet's signer refuses are left out, and a
+    /// transaction of a version the wallet cannot author is not signed at all.
+    pub fn sign_partial(&self, partial: &mut PartialTransaction) -> usize {
+        self.sign_partial_inputs(partial, None)
+    }
+
+    /// Sign like `sign_partial`, but commit the signatures of single-key inputs to only the parts of
+    /// the transaction `sighash` selects. Multisig inputs are signed as by `sign_partial`.
+    pub fn sign_partial_with(&self, partial: &mut PartialTransaction, sighash: SigHash) -> usize {
+        self.sign_partial_inputs(partial, Some(sighash))
+    }
+
+    fn sign_partial_inputs(&self, partial: &mut PartialTransaction, sighash: Option<SigHash>) -> usize {
+        if !partial.transaction.has_supported_version() {
+            return 0;
+        }
+        let whole = signing_digest(&partial.transaction);
+        let digests: Vec<TransactionId> = (0..partial.transaction.inputs.len())
+            .map(|index| sighash.map_or(whole, |sighash| partial.transaction.signature_digest(index, sighash)))
+            .collect();
+        let mut signed = 0;
+        for ((input, attached), digest) in partial.transaction.inputs.iter_mut().zip(&partial.coins).zip(&digests) {
+            let signs = |key: &Address| self.signer.sign(digest, key) == Signature::Valid(key.clone());
+            let Some(coin) = self.coins.get(&input.coin_id).or(attached.as_ref()) else {
+                continue;
+            };
+            match &coin.owner {
+                Address::Multisig { signers, .. } => {
+                    let mine: Vec<&Address> = signers.iter().filter(|s| self.addresses.contains(s) && signs(s)).collect();
+                    if mine.is_empty() {
+                        continue;
+                    }
+                    if !matches!(input.signature, Signature::Multi(_)) {
+                        input.signature = Signature::Multi(Vec::new());
+                    }
+                    if let Signature::Multi(signed_by) = &mut input.signature {
+                        for signer in mine {
+                            if !signed_by.contains(signer) {
+                                signed_by.push(signer.clone());
+                            }
+                        }
+                    }
+                }
+                owner if (self.coins.contains_key(&input.coin_id) || self.addresses.contains(owner)) && signs(owner) => {
+                    input.signature = match sighash {
+                        Some(sighash) => Signature::Committed {
+                            signer: owner.clone(),
+                            sighash,
+                            digest: *digest,
+                        },
+                        None => Signature::Valid(owner.clone()),
+                    };
+                }
+                _ => continue,
+            }
+            signed += 1;
+        }
+        signed
+    }
+}
@@ -0,0 +1,193 @@
+//! Hooks into sync for code that follows the wallet's chain, such as indexers, alerting, or accounting.
+//!
+//! A `SyncPlugin` registered with `add_sync_plugin` is called inside sync as each block is applied
+//! or rolled back, right after the wallet's own state changed, so it sees the same blocks and coins
+//! in the same order. Plugins are called in registration order. Unlike events, hooks are not
+//! queued: a plugin runs before sync moves on to the next block.
+//!
+//! Rolling back through the undo records reports each block. A rescan from genesis after a reorg
+//! below the undo records, or from the first block paying newly derived keychain addresses, drops
+//! the blocks above the rescan height at once and calls `on_rewound` instead.
+
+use bonecoin_core::*;
+
+use crate::delta::StateDelta;
+use crate::{BlockReport, Wallet};
+
+/// Code called by sync for every change to the wallet's chain. Every hook does nothing by default.
+pub trait SyncPlugin: Send + Sync {
+    /// The block was applied. Called after the hooks of the coins it added and spent.
+    fn on_block_applied(&mut self, _block: &BlockReport) {}
+
+    /// The block was rolled back, undoing what it credited and debited.
+    fn on_block_reverted(&mut self, _block: &BlockReport) {}
+
+    /// A block at `height` gave the wallet the coin.
+    fn on_coin_added(&mut self, _coin_id: &CoinId, _coin: &Coin, _height: u64) {}
+
+    /// The transaction `tx_id` in a block at `height` spent one of the wallet's coins.
+    fn on_coin_spent(&mut self, _coin_id: &CoinId, _coin: &Coin, _tx_id: &TransactionId, _height: u64) {}
+
+    /// Every block above `to_height` was dropped at once to rescan from there.
+    fn on_rewound(&mut self, _to_height: u64) {}
+}
+
+impl Wallet {
+    /// Register a plugin, called by every sync from now on after the plugins registered before it.
+    pub fn add_sync_plugin(&mut self, plugin: impl SyncPlugin + 'static) {
+        self.plugins.push(Box::new(plugin));
+    }
+
+    /// Unregister and return every plugin, in registration order.
+    pub fn take_sync_plugins(&mut self) -> Vec<Box<dyn SyncPlugin>> {
+        std::mem::take(&mut self.plugins)
+    }
+
+    /// Tell the plugins about a block applied with `delta`.
+    pub(crate) fn notify_applied(&mut self, delta: &StateDelta) {
+        if self.plugins.is_empty() {
+            return;
+        }
+        let block = BlockReport::new(delta);
+        for plugin in &mut self.plugins {
+            for entry in &delta.history {
+                for (coin_id, coin) in &entry.spent {
+                    plugin.on_coin_spent(coin_id, coin, &entry.tx_id, delta.height);
+                }
+                for (coin_id, coin) in &entry.received {
+                    plugin.on_coin_added(coin_id, coin, delta.height);
+                }
+            }
+            plugin.on_block_applied(&block);
+        }
+    }
+
+    /// Tell the plugins about a rolled back block.
+    pub(crate) fn notify_reverted(&mut self, block: &BlockReport) {
+        for plugin in &mut self.plugins {
+            plugin.on_block_reverted(block);
+        }
+    }
+
+    /// Tell the plugins that every block above `to_height` was dropped.
+    pub(crate) fn notify_rewound(&mut self, to_height: u64) {
+        for plugin in &mut self.plugins {
+            plugin.on_rewound(to_height);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    use std::sync::{Arc, Mutex};
+
+    type Calls = Arc<Mutex<Vec<String>>>;
+
+    /// Logs every hook call, prefixed with its name.
+    struct Log(&'static str, Calls);
+
+    impl SyncPlugin for Log {
+        fn on_block_applied(&mut self, block: &BlockReport) {
+            self.1.lock().unwrap().push(format!("{} applied {}", self.0, block.height));
+        }
+
+        fn on_block_reverted(&mut self, block: &BlockReport) {
+            self.1.lock().unwrap().push(format!("{} reverted {}", self.0, block.height));
+        }
+
+        fn on_coin_added(&mut self, _coin_id: &CoinId, coin: &Coin, height: u64) {
+            self.1.lock().unwrap().push(format!("{} added {} at {height}", self.0, coin.value));
+        }
+
+        fn on_coin_spent(&mut self, _coin_id: &CoinId, coin: &Coin, _tx_id: &TransactionId, height: u64) {
+            self.1.lock().unwrap().push(format!("{} spent {} at {height}", self.0, coin.value));
+        }
+
+        fn on_rewound(&mut self, to_height: u64) {
+            self.1.lock().unwrap().push(format!("{} rewound to {to_height}", self.0));
+        }
+    }
+
+    fn take(calls: &Calls) -> Vec<String> {
+        std::mem::take(&mut *calls.lock().unwrap())
+    }
+
+    /// A wallet of Alice with two undo records and one `Log` plugin for each name, and a node
+    /// whose first block rewards her 50 bones. The wallet has not synced yet.
+    fn logging(names: &[&'static str]) -> (MockNode, Wallet, Calls) {
+        let calls = Calls::default();
+        let mut wallet = Wallet::builder().address(Address::Alice).coinbase_maturity(0).max_reorg_depth(2).build().unwrap();
+        for name in names {
+            wallet.add_sync_plugin(Log(name, calls.clone()));
+        }
+        let mut node = MockNode::new();
+        node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, 50)]);
+        (node, wallet, calls)
+    }
+
+    /// Sync `wallet` to a second block in which Alice pays 20 bones of her reward to herself,
+    /// and a third rewarding Bob. Returns the id of the second block.
+    fn pay_and_sync(node: &mut MockNode, wallet: &mut Wallet) -> BlockId {
+        wallet.sync(node);
+        let reward = Transaction::coinbase(Address::Alice, 50).coin_id(1, 0);
+        let b2 = node.add_block_as_best(wallet.best_hash(), vec![spend(reward, Address::Alice, [(Address::Alice, 20)])]);
+        node.add_block_as_best(b2, vec![Transaction::coinbase(Address::Bob, 50)]);
+        wallet.sync(node);
+        b2
+    }
+
+    #[test]
+    fn plugins_are_called_in_registration_order() {
+        let (node, mut wallet, calls) = logging(&["first", "second"]);
+        wallet.sync(&node);
+        assert_eq!(take(&calls), ["first added 50 at 1", "first applied 1", "second added 50 at 1", "second applied 1"]);
+    }
+
+    #[test]
+    fn taken_plugins_are_no_longer_called() {
+        let (node, mut wallet, calls) = logging(&["first", "second"]);
+        assert_eq!(wallet.take_sync_plugins().len(), 2);
+        wallet.sync(&node);
+        assert!(take(&calls).is_empty());
+    }
+
+    #[test]
+    fn plugins_see_spent_and_added_coins_before_their_block() {
+        let (mut node, mut wallet, calls) = logging(&["only"]);
+        wallet.sync(&node);
+        take(&calls);
+        pay_and_sync(&mut node, &mut wallet);
+        assert_eq!(take(&calls), ["only spent 50 at 2", "only added 20 at 2", "only applied 2", "only applied 3"]);
+    }
+
+    #[test]
+    fn reorgs_within_the_undo_records_revert_block_by_block() {
+        let (mut node, mut wallet, calls) = logging(&["only"]);
+        let b2 = pay_and_sync(&mut node, &mut wallet);
+        take(&calls);
+
+        let c3 = node.add_block(b2, vec![Transaction::coinbase(Address::Charlie, 50)]);
+        node.add_block_as_best(c3, vec![Transaction::coinbase(Address::Dave, 50)]);
+        wallet.sync(&node);
+        assert_eq!(take(&calls), ["only reverted 3", "only applied 3", "only applied 4"]);
+    }
+
+    #[test]
+    fn deeper_reorgs_rewind() {
+        let (mut node, mut wallet, calls) = logging(&["only"]);
+        pay_and_sync(&mut node, &mut wallet);
+        take(&calls);
+
+        let mut tip = node.add_block(Block::genesis().id(), vec![Transaction::coinbase(Address::Eve, 50)]);
+        for _ in 0..3 {
+            tip = node.add_block(tip, vec![]);
+        }
+        node.set_best(tip);
+        wallet.sync(&node);
+        let calls = take(&calls);
+        assert!(calls.starts_with(&["only reverted 3".to_string(), "only reverted 2".to_string(), "only rewound to 0".to_string()]), "{calls:?}");
+        assert_eq!(calls.last().map(String::as_str), Some("only applied 4"));
+    }
+}
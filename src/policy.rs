@@ -0,0 +1,246 @@
+//! Spending policies that limit what transactions the wallet is willing to author.
+//!
+//! Hard limits (caps and recipient lists) make transaction creation fail with `PolicyViolation`.
+//! Transactions above the approval threshold fail with `ApprovalRequired` through the regular
+//! `TransactionAuthor` methods, and must instead be proposed as a `PendingApproval` and approved in a
+//! second call by one of the policy's approvers.
+
+use std::collections::HashSet;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// The number of blocks over which `SpendingPolicy::max_per_window` is enforced.
+pub const POLICY_WINDOW: u64 = 1000;
+
+/// Limits on the transactions a wallet will author. The default policy allows everything.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct SpendingPolicy {
+    /// The most bones a single transaction may send out of the wallet, tip included.
+    pub max_per_transaction: Option<u64>,
+    /// The most bones the wallet may send out over the last `POLICY_WINDOW` blocks, including the new transaction.
+    pub max_per_window: Option<u64>,
+    /// If set, foreign addresses outside this set may not be paid.
+    pub allowlist: Option<HashSet<Address>>,
+    /// Foreign addresses that may never be paid.
+    pub denylist: HashSet<Address>,
+    /// Transactions sending out more than this many bones need a second approval.
+    pub approval_threshold: Option<u64>,
+    /// The addresses allowed to approve pending transactions.
+    pub approvers: HashSet<Address>,
+}
+
+/// A transaction that passed the hard limits of the policy but awaits a second approval.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PendingApproval {
+    /// The id of the transaction, used to approve or reject it.
+    pub id: TransactionId,
+    /// The transaction that will be released once approved.
+    pub transaction: Transaction,
+    /// The number of bones the transaction sends out of the wallet, tip included.
+    pub outgoing_value: u64,
+}
+
+impl Wallet {
+    /// Replace the wallet's spending policy. Pending approvals are kept.
+    pub fn set_spending_policy(&mut self, policy: SpendingPolicy) {
+        self.policy = policy;
+    }
+
+    /// The wallet's current spending policy.
+    pub fn spending_policy(&self) -> &SpendingPolicy {
+        &self.policy
+    }
+
+    /// Construct an automatic transaction that needs a second approval before it is released.
+    /// The hard limits of the policy are still enforced.
+    pub fn propose_automatic_transaction(
+        &mut self,
+        recipient: Address,
+        payment_amount: u64,
+        burn_aka_tip: u64,
+    ) -> WalletResult<PendingApproval> {
+        let transaction = self.build_automatic_transaction(recipient, payment_amount, burn_aka_tip)?;
+        let outgoing_value = self.outgoing_value(&transaction);
+        self.check_hard_limits(&transaction, outgoing_value)?;
+
+        let pending = PendingApproval {
+            id: transaction.id(),
+            transaction,
+            outgoing_value,
+        };
+        self.pending_approvals.insert(pending.id, pending.clone());
+        Ok(pending)
+    }
+
+    /// Return the transactions currently waiting for approval.
+    pub fn pending_approvals(&self) -> Vec<&PendingApproval> {
+        self.pending_approvals.values().collect()
+    }
+
+    /// Approve a pending transaction on behalf of `approver`, releasing it to the caller.
+    pub fn approve(&mut self, id: &TransactionId, approver: &Address) -> WalletResult<Transaction> {
+        if !self.policy.approvers.contains(approver) {
+            return Err(WalletError::PolicyViolation);
+        }
+        let pending = self.pending_approvals.remove(id).ok_or(WalletError::UnknownTransaction(*id))?;
+        Ok(pending.transaction)
+    }
+
+    /// Drop a pending transaction without releasing it.
+    pub fn reject(&mut self, id: &TransactionId) -> WalletResult<()> {
+        self.pending_approvals
+            .remove(id)
+            .map(|_| ())
+            .ok_or(WalletError::UnknownTransaction(*id))
+    }
+
+    /// Check a freshly built transaction against the transaction limits and the whole policy,
+    /// including the approval threshold.
+    pub(crate) fn check_policy(&self, transaction: &Transaction) -> WalletResult<()> {
+        if !transaction.is_within_limits() {
+            return Err(WalletError::TooLarge);
+        }
+        let outgoing_value = self.outgoing_value(transaction);
+        self.check_hard_limits(transaction, outgoing_value)?;
+        if self.policy.approval_threshold.is_some_and(|threshold| outgoing_value > threshold) {
+            return Err(WalletError::ApprovalRequired);
+        }
+        Ok(())
+    }
+
+    fn check_hard_limits(&self, transaction: &Transaction, outgoing_value: u64) -> WalletResult<()> {
+        for coin in &transaction.outputs {
+            if self.owns(&coin.owner) {
+                continue;
+            }
+            let allowed = self.policy.allowlist.as_ref().is_none_or(|allowed| allowed.contains(&coin.owner));
+            if !allowed || self.policy.denylist.contains(&coin.owner) {
+                return Err(WalletError::PolicyViolation);
+            }
+        }
+
+        if self.policy.max_per_transaction.is_some_and(|cap| outgoing_value > cap) {
+            return Err(WalletError::PolicyViolation);
+        }
+
+        if let Some(cap) = self.policy.max_per_window {
+            if self.spent_in_window() + outgoing_value > cap {
+                return Err(WalletError::PolicyViolation);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The number of bones a transaction takes out of the wallet: wallet inputs minus outputs paid back to the wallet.
+    /// Issued assets are not bones and are not counted.
+    fn outgoing_value(&self, transaction: &Transaction) -> u64 {
+        let consumed: u64 = transaction
+            .iter_input_coin_ids()
+            .filter_map(|coin_id| self.coins.get(&coin_id))
+            .map(Coin::native_value)
+            .sum();
+        let kept: u64 = transaction
+            .outputs
+            .iter()
+            .filter(|coin| self.owns(&coin.owner))
+            .map(Coin::native_value)
+            .sum();
+        consumed.saturating_sub(kept)
+    }
+
+    /// The number of bones that left the wallet in the last `POLICY_WINDOW` blocks.
+    fn spent_in_window(&self) -> u64 {
+        let window_start = self.best_block_height.saturating_sub(POLICY_WINDOW);
+        self.history
+            .entries()
+            .iter()
+            .rev()
+            .take_while(|entry| entry.height > window_start)
+            .map(|entry| entry.value_spent().saturating_sub(entry.value_received()))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// The one-block wallet under a policy capping single payments at 100 and payments per window
+    /// at 150, denying Eve, and having Dave approve payments above 50.
+    fn wallet_under_policy() -> (MockNode, Wallet) {
+        let (node, mut wallet) = make_one_block_blockchain();
+        wallet.set_spending_policy(SpendingPolicy {
+            max_per_transaction: Some(100),
+            max_per_window: Some(150),
+            denylist: HashSet::from([Address::Eve]),
+            approval_threshold: Some(50),
+            approvers: HashSet::from([Address::Dave]),
+            ..Default::default()
+        });
+        (node, wallet)
+    }
+
+    #[test]
+    fn payments_to_denied_addresses_are_refused() {
+        let (_, wallet) = wallet_under_policy();
+        assert_eq!(
+            wallet.create_automatic_transaction(Address::Eve, 10, 0),
+            Err(WalletError::PolicyViolation)
+        );
+    }
+
+    #[test]
+    fn payments_above_the_transaction_cap_are_refused() {
+        let (_, wallet) = wallet_under_policy();
+        assert_eq!(
+            wallet.create_automatic_transaction(Address::Charlie, 101, 0),
+            Err(WalletError::PolicyViolation)
+        );
+    }
+
+    #[test]
+    fn payments_above_the_approval_threshold_need_approval() {
+        let (_, wallet) = wallet_under_policy();
+        assert_eq!(
+            wallet.create_automatic_transaction(Address::Charlie, 60, 0),
+            Err(WalletError::ApprovalRequired)
+        );
+        // paying the wallet's own addresses does not count as spending
+        assert!(wallet.create_automatic_transaction(Address::Bob, 60, 0).is_ok());
+    }
+
+    #[test]
+    fn proposed_payments_are_released_by_an_approver_only() {
+        let (_, mut wallet) = wallet_under_policy();
+
+        let pending = wallet.propose_automatic_transaction(Address::Charlie, 60, 1).unwrap();
+        assert_eq!(pending.outgoing_value, 61);
+        assert_eq!(wallet.pending_approvals().len(), 1);
+        assert_eq!(
+            wallet.approve(&pending.id, &Address::Charlie),
+            Err(WalletError::PolicyViolation)
+        );
+        let tx = wallet.approve(&pending.id, &Address::Dave).unwrap();
+        assert_eq!(tx, pending.transaction);
+        assert!(wallet.pending_approvals().is_empty());
+    }
+
+    #[test]
+    fn mined_payments_count_against_the_window_cap() {
+        let (mut node, mut wallet) = wallet_under_policy();
+        let pending = wallet.propose_automatic_transaction(Address::Charlie, 60, 1).unwrap();
+        let tx = wallet.approve(&pending.id, &Address::Dave).unwrap();
+
+        let b1_id = node.best_block_at_height(1).unwrap();
+        node.add_block_as_best(b1_id, vec![tx]);
+        wallet.sync(&node);
+        assert!(wallet.create_automatic_transaction(Address::Charlie, 40, 0).is_ok());
+        assert_eq!(
+            wallet.propose_automatic_transaction(Address::Charlie, 90, 0),
+            Err(WalletError::PolicyViolation)
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Checking with the node that the coins a transaction spends still exist before it is built.
+//!
+//! The wallet's coins are only as current as its last sync. A coin spent from another device since,
+//! or removed by a reorg the wallet has not seen yet, makes the transaction it funds invalid.
+//! `create_checked_transaction` asks the node about every coin it selects, and selects again
+//! without the coins the node reports spent.
+
+use std::collections::HashSet;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+impl Wallet {
+    /// Construct a transaction like `create_automatic_transaction`, but only spending coins the node
+    /// does not report as spent or missing on its best chain. Coins the node has no answer for are
+    /// spent as usual, so a node without a UTXO set does not change the result.
+    pub fn create_checked_transaction<Node: NodeEndpoint>(
+        &self,
+        recipient: Address,
+        amount: u64,
+        tip: u64,
+        node: &Node,
+    ) -> WalletResult<Transaction> {
+        let mut gone = HashSet::new();
+        loop {
+            let transaction =
+                self.build_restricted_transaction(recipient.clone(), amount, tip, &|coin_id, _| !gone.contains(coin_id), None, false)?;
+            let newly_gone: Vec<CoinId> =
+                transaction.iter_input_coin_ids().filter(|coin_id| node.is_unspent(coin_id) == Some(false)).collect();
+            if newly_gone.is_empty() {
+                self.check_policy(&transaction)?;
+                return Ok(transaction);
+            }
+            #[cfg(feature = "tracing")]
+            tracing::warn!(coins = newly_gone.len(), "node reports selected coins spent, selecting again");
+            gone.extend(newly_gone);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice synced to a block rewarding her 30 and 40 bones, whose 40 bones another
+    /// device has since spent in a block the wallet has not synced. Returns the node, the wallet
+    /// and the spent coin.
+    fn spent_elsewhere() -> (MockNode, Wallet, CoinId) {
+        let mut node = MockNode::new();
+        let b1_id = node.add_block_as_best(
+            Block::genesis().id(),
+            vec![Transaction::coinbase(Address::Alice, 30), Transaction::coinbase(Address::Alice, 40)],
+        );
+        let mut wallet = Wallet::builder().address(Address::Alice).coinbase_maturity(0).build().unwrap();
+        wallet.sync(&node);
+
+        let larger = Transaction::coinbase(Address::Alice, 40).coin_id(1, 0);
+        let elsewhere = wallet
+            .create_manual_transaction(vec![larger], vec![Coin { value: 40, owner: Address::Bob, asset_id: None }])
+            .unwrap();
+        node.add_block_as_best(b1_id, vec![elsewhere]);
+        (node, wallet, larger)
+    }
+
+    #[test]
+    fn lenient_nodes_cannot_report_spent_coins() {
+        let (mut node, wallet, larger) = spent_elsewhere();
+        let unchecked = wallet.create_checked_transaction(Address::Bob, 35, 0, &node).unwrap();
+        assert!(unchecked.iter_input_coin_ids().any(|coin_id| coin_id == larger));
+
+        node.set_strict(true);
+        assert_eq!(node.is_unspent(&larger), Some(false));
+        assert!(!node.submit_transaction(&unchecked));
+    }
+
+    #[test]
+    fn checked_transactions_spend_only_coins_the_node_reports_unspent() {
+        let (mut node, wallet, _) = spent_elsewhere();
+        node.set_strict(true);
+        let checked = wallet.create_checked_transaction(Address::Bob, 25, 0, &node).unwrap();
+        assert!(checked.iter_input_coin_ids().all(|coin_id| node.is_unspent(&coin_id) == Some(true)));
+        assert!(node.submit_transaction(&checked));
+    }
+
+    #[test]
+    fn coins_the_node_reports_spent_do_not_count_towards_the_funds() {
+        let (mut node, wallet, _) = spent_elsewhere();
+        node.set_strict(true);
+        assert!(matches!(
+            wallet.create_checked_transaction(Address::Bob, 35, 0, &node),
+            Err(WalletError::InsufficientFunds { .. })
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+//! Valuing bones in a reference currency.
+//!
+//! The wallet does not know any data provider. Reporting tools implement `PriceSource` for
+//! whatever feed they use and hand it to the wallet when they need fiat values. Prices are
+//! fixed point decimals so that sums and exports never pick up floating point noise.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Wallet;
+
+/// The number of fractional digits a `Decimal` carries.
+pub const DECIMAL_PLACES: u32 = 8;
+
+const ONE: i128 = 10i128.pow(DECIMAL_PLACES);
+
+/// A signed fixed point number with `DECIMAL_PLACES` fractional digits.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+pub struct Decimal(i128);
+
+impl Decimal {
+    /// The decimal `mantissa * 10^-scale`. Digits beyond `DECIMAL_PLACES` are truncated.
+    pub fn new(mantissa: i128, scale: u32) -> Self {
+        if scale <= DECIMAL_PLACES {
+            Decimal(mantissa * 10i128.pow(DECIMAL_PLACES - scale))
+        } else {
+            Decimal(mantissa / 10i128.pow(scale - DECIMAL_PLACES))
+        }
+    }
+
+    /// The value of `bones` bones at this price per bone.
+    pub fn times(self, bones: u64) -> Decimal {
+        Decimal(self.0 * bones as i128)
+    }
+}
+
+impl From<u64> for Decimal {
+    fn from(n: u64) -> Self {
+        Decimal(n as i128 * ONE)
+    }
+}
+
+impl fmt::Display for Decimal {
+    /// Print the shortest exact representation, e.g. `12.5` or `-0.00000001`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let whole = self.0.unsigned_abs() / ONE as u128;
+        let fraction = self.0.unsigned_abs() % ONE as u128;
+        if fraction == 0 {
+            return write!(f, "{}{}", sign, whole);
+        }
+        let digits = format!("{:0width$}", fraction, width = DECIMAL_PLACES as usize);
+        write!(f, "{}{}.{}", sign, whole, digits.trim_end_matches('0'))
+    }
+}
+
+/// The error returned when a string is not a decimal number.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ParseDecimalError;
+
+impl fmt::Display for ParseDecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid decimal number")
+    }
+}
+
+impl std::error::Error for ParseDecimalError {}
+
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    /// Parse a plain decimal such as `42`, `-0.5` or `1234.56789`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() || !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+            return Err(ParseDecimalError);
+        }
+        let mantissa: i128 = format!("{}{}", whole, fraction).parse().map_err(|_| ParseDecimalError)?;
+        let value = Decimal::new(mantissa, fraction.len() as u32);
+        Ok(if negative { Decimal(-value.0) } else { value })
+    }
+}
+
+/// The point in chain history at which a price is requested.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PriceAt {
+    /// The price when the block at this height was mined.
+    Height(u64),
+    /// The price at this block timestamp.
+    Timestamp(u64),
+}
+
+/// A feed of historical bone prices in some reference currency.
+pub trait PriceSource {
+    /// The price of a single bone at the given point, or `None` if the source has no data for it.
+    fn price_at(&self, at: PriceAt) -> Option<Decimal>;
+}
+
+impl<F: Fn(PriceAt) -> Option<Decimal>> PriceSource for F {
+    fn price_at(&self, at: PriceAt) -> Option<Decimal> {
+        self(at)
+    }
+}
+
+impl Wallet {
+    /// The wallet's net worth valued at the price of its best block.
+    pub fn net_worth_in_fiat(&self, source: &dyn PriceSource) -> Option<Decimal> {
+        let net_worth = self.coins.values().map(|coin| coin.value).sum();
+        source.price_at(PriceAt::Height(self.best_block_height)).map(|price| price.times(net_worth))
+    }
+}
+
+/// The price for a block, preferring its timestamp and falling back to its height.
+pub(crate) fn price_of_block(source: &dyn PriceSource, height: u64, timestamp: u64) -> Option<Decimal> {
+    source
+        .price_at(PriceAt::Timestamp(timestamp))
+        .or_else(|| source.price_at(PriceAt::Height(height)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    fn price() -> Decimal {
+        "1.25".parse().unwrap()
+    }
+
+    /// A price source knowing only the price at height 1.
+    fn source(at: PriceAt) -> Option<Decimal> {
+        match at {
+            PriceAt::Height(1) => Some(price()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn net_worth_is_valued_at_the_price_of_the_best_block() {
+        let (_, wallet) = make_one_block_blockchain();
+        assert_eq!(wallet.net_worth_in_fiat(&source), Some(price().times(wallet.net_worth())));
+    }
+
+    #[test]
+    fn net_worth_has_no_value_without_a_price_for_the_best_block() {
+        let (mut node, mut wallet) = make_one_block_blockchain();
+        let b1_id = node.best_block_at_height(1).unwrap();
+        node.add_block_as_best(b1_id, vec![]);
+        wallet.sync(&node);
+        assert_eq!(wallet.net_worth_in_fiat(&source), None);
+    }
+
+    #[test]
+    fn history_is_exported_with_the_price_of_each_block() {
+        let (_, wallet) = make_one_block_blockchain();
+        let csv = wallet.export_history_in_fiat(ExportFormat::Csv, &source);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines[0].ends_with(",label,price,received_fiat,spent_fiat"));
+        assert!(lines[1].ends_with(&format!(",1.25,{},0", price().times(wallet.net_worth()))));
+        let json = wallet.export_history_in_fiat(ExportFormat::Json, &|_| None);
+        assert!(json.contains("\"price\":null"));
+    }
+
+    #[test]
+    fn decimals_are_printed_and_parsed_exactly() {
+        assert_eq!(Decimal::new(-1, 8).to_string(), "-0.00000001");
+        assert_eq!(Decimal::from(3).to_string(), "3");
+        assert_eq!("12.50".parse::<Decimal>().map(|d| d.to_string()), Ok("12.5".to_owned()));
+        assert_eq!("1.2.3".parse::<Decimal>(), Err(ParseDecimalError));
+    }
+}
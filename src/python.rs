@@ -0,0 +1,412 @@
+//! Python bindings for scripting chain simulations from notebooks.
+//!
+//! The `utxo_wallet` Python module wraps `Wallet` and `MockNode` along with the ids and transactions
+//! passed between them, so a chain can be built, synced, and spent from without writing Rust:
+//!
+//! ```python
+//! import utxo_wallet as uw
+//!
+//! node = uw.MockNode()
+//! b1 = node.add_block_as_best(uw.BlockId.genesis(), [uw.Transaction.coinbase("Alice", 100)])
+//! wallet = uw.Wallet(["Alice"])
+//! wallet.sync(node)
+//! wallet.send(node, "Bob", 30, tip=1)
+//! ```
+//!
+//! Addresses are given as their names, `"Alice"` through `"Eve"`, or as integers for custom
+//! addresses, and come back as they display. Coin and transaction ids come back as hex strings.
+//! Failures of the wallet raise `utxo_wallet.WalletError` with the error's message. The module is
+//! only compiled with the `python` feature; `maturin build` builds it as an extension module.
+
+use std::sync::{Mutex, MutexGuard};
+
+use bonecoin_core::{
+    Address, Block, BlockId, Coin, Input, MockNode, NodeEndpoint, Transaction, TransactionAuthor, WalletReader, WalletSync, TRANSACTION_VERSION,
+};
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use crate::Wallet;
+
+pyo3::create_exception!(utxo_wallet, WalletError, PyException, "Raised when the wallet cannot do what was asked.");
+
+fn wallet_error(e: impl ToString) -> PyErr {
+    WalletError::new_err(e.to_string())
+}
+
+/// The address named by a Python value: a name like `"Alice"`, `"Custom(5)"`, or an integer.
+fn parse_address(value: &Bound<'_, PyAny>) -> PyResult<Address> {
+    if let Ok(id) = value.extract::<u64>() {
+        return Ok(Address::Custom(id));
+    }
+    let name: String = value.extract()?;
+    let custom = name.strip_prefix("Custom(").and_then(|rest| rest.strip_suffix(')')).and_then(|id| id.parse().ok());
+    match (name.as_str(), custom) {
+        ("Alice", _) => Ok(Address::Alice),
+        ("Bob", _) => Ok(Address::Bob),
+        ("Charlie", _) => Ok(Address::Charlie),
+        ("Dave", _) => Ok(Address::Dave),
+        ("Eve", _) => Ok(Address::Eve),
+        (_, Some(id)) => Ok(Address::Custom(id)),
+        _ => Err(PyValueError::new_err(format!("{name:?} is not an address"))),
+    }
+}
+
+fn parse_addresses(values: &[Bound<'_, PyAny>]) -> PyResult<Vec<Address>> {
+    values.iter().map(parse_address).collect()
+}
+
+/// The id of a block.
+#[pyclass(name = "BlockId", module = "utxo_wallet", frozen, eq, hash, str, skip_from_py_object)]
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub struct PyBlockId(BlockId);
+
+impl std::fmt::Display for PyBlockId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[pymethods]
+impl PyBlockId {
+    /// The id of the genesis block, the parent of the first block of every chain.
+    #[staticmethod]
+    fn genesis() -> Self {
+        PyBlockId(Block::genesis().id())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("BlockId({})", self.0)
+    }
+}
+
+/// A transaction, built here to put in blocks or by a wallet to send.
+#[pyclass(name = "Transaction", module = "utxo_wallet", frozen, skip_from_py_object)]
+#[derive(Clone)]
+pub struct PyTransaction(Transaction);
+
+#[pymethods]
+impl PyTransaction {
+    /// A coinbase paying `value` bones to `owner`, to put first in a block.
+    #[staticmethod]
+    fn coinbase(owner: &Bound<'_, PyAny>, value: u64) -> PyResult<Self> {
+        Ok(PyTransaction(Transaction::coinbase(parse_address(owner)?, value)))
+    }
+
+    /// A transaction creating coins out of nothing, one per `(address, value)` pair, for seeding a
+    /// chain with coins.
+    #[staticmethod]
+    fn payment(outputs: Vec<(Bound<'_, PyAny>, u64)>) -> PyResult<Self> {
+        let outputs = outputs
+            .iter()
+            .map(|(owner, value)| Ok(Coin { value: *value, owner: parse_address(owner)?, asset_id: None }))
+            .collect::<PyResult<_>>()?;
+        Ok(PyTransaction(Transaction { version: TRANSACTION_VERSION, inputs: vec![Input::dummy()], outputs }))
+    }
+
+    /// The transaction's id, as hex.
+    #[getter]
+    fn id(&self) -> String {
+        self.0.id().to_string()
+    }
+
+    /// The ids of the coins the transaction consumes, as hex.
+    #[getter]
+    fn inputs(&self) -> Vec<String> {
+        self.0.iter_input_coin_ids().map(|coin_id| coin_id.to_string()).collect()
+    }
+
+    /// The `(owner, value)` of each coin the transaction creates.
+    #[getter]
+    fn outputs(&self) -> Vec<(String, u64)> {
+        self.0.outputs.iter().map(|coin| (coin.owner.to_string(), coin.value)).collect()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Transaction({}, {} inputs, {} outputs)", self.0.id(), self.0.inputs.len(), self.0.outputs.len())
+    }
+}
+
+fn to_transactions(values: Vec<PyRef<'_, PyTransaction>>) -> Vec<Transaction> {
+    values.iter().map(|transaction| transaction.0.clone()).collect()
+}
+
+/// An in-memory node whose chain the script builds block by block.
+#[pyclass(name = "MockNode", module = "utxo_wallet")]
+pub struct PyMockNode(Mutex<MockNode>);
+
+impl PyMockNode {
+    fn lock(&self) -> MutexGuard<'_, MockNode> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[pymethods]
+impl PyMockNode {
+    /// A node holding only the genesis block.
+    #[new]
+    fn new() -> Self {
+        PyMockNode(Mutex::new(MockNode::new()))
+    }
+
+    /// Add a block of the transactions on top of `parent`, leaving the best chain as it is.
+    fn add_block(&self, parent: &PyBlockId, transactions: Vec<PyRef<'_, PyTransaction>>) -> PyBlockId {
+        PyBlockId(self.lock().add_block(parent.0, to_transactions(transactions)))
+    }
+
+    /// Add a block of the transactions on top of `parent` and make it the tip of the best chain.
+    fn add_block_as_best(&self, parent: &PyBlockId, transactions: Vec<PyRef<'_, PyTransaction>>) -> PyBlockId {
+        PyBlockId(self.lock().add_block_as_best(parent.0, to_transactions(transactions)))
+    }
+
+    /// Make the block the tip of the best chain, reorganizing the chain if it is on another branch.
+    fn set_best(&self, block: &PyBlockId) {
+        self.lock().set_best(block.0);
+    }
+
+    /// Extend the best chain with `blocks` random blocks paying the addresses, with up to
+    /// `txs_per_block` transactions each after the coinbase. The same seed builds the same chain.
+    fn generate_chain(&self, seed: u64, blocks: usize, txs_per_block: usize, addresses: Vec<Bound<'_, PyAny>>) -> PyResult<PyBlockId> {
+        let addresses = parse_addresses(&addresses)?;
+        if addresses.is_empty() {
+            return Err(PyValueError::new_err("generating a chain needs at least one address"));
+        }
+        Ok(PyBlockId(self.lock().generate_chain(seed, blocks, txs_per_block, &addresses)))
+    }
+
+    /// The id of the best chain's block at the height, or `None` past its tip.
+    fn best_block_at_height(&self, height: u64) -> Option<PyBlockId> {
+        self.lock().best_block_at_height(height).map(PyBlockId)
+    }
+
+    /// The transactions submitted to the node so far, in order.
+    fn submitted_transactions(&self) -> Vec<PyTransaction> {
+        self.lock().submitted_transactions().into_iter().map(PyTransaction).collect()
+    }
+
+    /// How many queries the node has answered so far.
+    fn how_many_queries(&self) -> u64 {
+        self.lock().how_many_queries()
+    }
+}
+
+/// A wallet tracking the coins of its addresses on a `MockNode`'s chain.
+#[pyclass(name = "Wallet", module = "utxo_wallet")]
+pub struct PyWallet(Mutex<Wallet>);
+
+impl PyWallet {
+    fn lock(&self) -> MutexGuard<'_, Wallet> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[pymethods]
+impl PyWallet {
+    /// A wallet owning the addresses, synced to genesis.
+    #[new]
+    fn new(addresses: Vec<Bound<'_, PyAny>>) -> PyResult<Self> {
+        Ok(PyWallet(Mutex::new(Wallet::new(parse_addresses(&addresses)?.into_iter()))))
+    }
+
+    /// Restore a wallet from bytes written by `export_state`.
+    #[staticmethod]
+    fn import_state(state: &[u8]) -> PyResult<Self> {
+        Ok(PyWallet(Mutex::new(Wallet::import_state(state).map_err(wallet_error)?)))
+    }
+
+    /// The wallet's state, to restore later with `import_state`.
+    fn export_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.lock().export_state())
+    }
+
+    /// Sync to the node's best chain. Returns a dict counting the blocks `applied` and `reverted`,
+    /// and whether the wallet `rescanned_from_genesis`.
+    fn sync<'py>(&self, py: Python<'py>, node: &PyMockNode) -> PyResult<Bound<'py, PyDict>> {
+        let report = self.lock().try_sync(&*node.lock()).map_err(wallet_error)?;
+        let summary = PyDict::new(py);
+        summary.set_item("applied", report.applied.len())?;
+        summary.set_item("reverted", report.reverted.len())?;
+        summary.set_item("rescanned_from_genesis", report.rescanned_from_genesis)?;
+        Ok(summary)
+    }
+
+    /// The height of the best block the wallet synced to.
+    #[getter]
+    fn best_height(&self) -> u64 {
+        self.lock().best_height()
+    }
+
+    /// The best block the wallet synced to.
+    #[getter]
+    fn best_block(&self) -> PyBlockId {
+        PyBlockId(self.lock().best_hash())
+    }
+
+    /// The bones held by the address, or by the whole wallet if none is given.
+    #[pyo3(signature = (address=None))]
+    fn balance(&self, address: Option<&Bound<'_, PyAny>>) -> PyResult<u64> {
+        let wallet = self.lock();
+        match address {
+            Some(owner) => wallet.total_assets_of(parse_address(owner)?).map_err(wallet_error),
+            None => Ok(wallet.net_worth()),
+        }
+    }
+
+    /// The wallet's unspent coins as `(coin_id, value, owner)`, ordered by coin id.
+    fn coins(&self) -> Vec<(String, u64, String)> {
+        let wallet = self.lock();
+        let mut coins: Vec<(String, u64, String)> =
+            wallet.coins.iter().map(|(coin_id, coin)| (coin_id.to_string(), coin.value, coin.owner.to_string())).collect();
+        coins.sort();
+        coins
+    }
+
+    /// The transactions that touched the wallet, oldest first, as dicts with the `tx_id`,
+    /// `height`, `direction`, the bones `received` and `spent`, and the bones `burned` if known.
+    fn history<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.lock()
+            .history()
+            .iter()
+            .map(|entry| {
+                let row = PyDict::new(py);
+                row.set_item("tx_id", entry.tx_id.to_string())?;
+                row.set_item("height", entry.height)?;
+                row.set_item("direction", format!("{:?}", entry.direction))?;
+                row.set_item("received", entry.received.iter().map(|(_, coin)| coin.value).sum::<u64>())?;
+                row.set_item("spent", entry.spent.iter().map(|(_, coin)| coin.value).sum::<u64>())?;
+                row.set_item("burned", entry.burned)?;
+                Ok(row)
+            })
+            .collect()
+    }
+
+    /// Build a transaction paying `amount` bones to `recipient` and burning `tip`, without
+    /// submitting it.
+    #[pyo3(signature = (recipient, amount, tip=0))]
+    fn create_transaction(&self, recipient: &Bound<'_, PyAny>, amount: u64, tip: u64) -> PyResult<PyTransaction> {
+        let transaction = self.lock().create_automatic_transaction(parse_address(recipient)?, amount, tip).map_err(wallet_error)?;
+        Ok(PyTransaction(transaction))
+    }
+
+    /// Build a transaction like `create_transaction` and submit it to the node. The wallet sees
+    /// it spent once a block including it is synced.
+    #[pyo3(signature = (node, recipient, amount, tip=0))]
+    fn send(&self, node: &PyMockNode, recipient: &Bound<'_, PyAny>, amount: u64, tip: u64) -> PyResult<PyTransaction> {
+        let transaction = self.create_transaction(recipient, amount, tip)?;
+        if !node.lock().submit_transaction(&transaction.0) {
+            return Err(wallet_error(bonecoin_core::WalletError::BroadcastFailed(transaction.0.id())));
+        }
+        Ok(transaction)
+    }
+}
+
+/// The `utxo_wallet` Python module.
+#[pymodule]
+pub fn utxo_wallet(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyBlockId>()?;
+    m.add_class::<PyTransaction>()?;
+    m.add_class::<PyMockNode>()?;
+    m.add_class::<PyWallet>()?;
+    m.add("WalletError", m.py().get_type::<WalletError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+
+    /// Sets up `node` with a block paying 70 bones to Alice and 30 to `Custom(7)`, and `wallet`
+    /// of both synced to it, for the scripts below to start from.
+    const SETUP: &str = "
+import utxo_wallet as uw
+
+node = uw.MockNode()
+b1 = node.add_block_as_best(uw.BlockId.genesis(), [uw.Transaction.payment([('Alice', 70), (7, 30)])])
+wallet = uw.Wallet(['Alice', 'Custom(7)'])
+synced = wallet.sync(node)
+";
+
+    /// Runs `script` after [`SETUP`] with the module registered, and fails the test if it raises.
+    fn run(script: &str) {
+        Python::initialize();
+        Python::attach(|py| {
+            let module = PyModule::new(py, "utxo_wallet").unwrap();
+            utxo_wallet(&module).unwrap();
+            py.import("sys").unwrap().getattr("modules").unwrap().set_item("utxo_wallet", module).unwrap();
+            let script = CString::new(format!("{SETUP}{script}")).unwrap();
+            if let Err(e) = py.run(&script, None, None) {
+                e.print(py);
+                panic!("the script failed");
+            }
+        });
+    }
+
+    #[test]
+    fn wallets_sync_and_report_balances() {
+        run("
+assert synced == {'applied': 1, 'reverted': 0, 'rescanned_from_genesis': False}
+assert wallet.best_height == 1 and wallet.best_block == b1
+assert wallet.balance() == 100 and wallet.balance('Alice') == 70 and wallet.balance(7) == 30
+assert sorted(value for _, value, _ in wallet.coins()) == [30, 70]
+");
+    }
+
+    #[test]
+    fn sends_are_submitted_and_recorded_in_the_history() {
+        run("
+sent = wallet.send(node, 'Bob', 60, tip=1)
+assert [tx.id for tx in node.submitted_transactions()] == [sent.id]
+assert ('Bob', 60) in sent.outputs and len(sent.inputs) >= 1
+node.add_block_as_best(b1, [sent])
+wallet.sync(node)
+assert wallet.balance() == 39
+assert [entry['direction'] for entry in wallet.history()] == ['Incoming', 'Outgoing']
+assert wallet.history()[1]['burned'] == 1
+");
+    }
+
+    #[test]
+    fn wallet_state_round_trips() {
+        run("
+restored = uw.Wallet.import_state(wallet.export_state())
+assert restored.coins() == wallet.coins()
+");
+    }
+
+    #[test]
+    fn errors_are_raised_as_python_exceptions() {
+        run("
+try:
+    wallet.create_transaction('Bob', 1000)
+    raise AssertionError('overspending must fail')
+except uw.WalletError:
+    pass
+try:
+    uw.Wallet(['Mallory'])
+    raise AssertionError('unknown names must fail')
+except ValueError:
+    pass
+");
+    }
+
+    #[test]
+    fn forks_replace_the_chain_the_wallet_synced() {
+        run("
+fork = node.add_block(uw.BlockId.genesis(), [uw.Transaction.coinbase('Alice', 50)])
+node.set_best(node.add_block(fork, []))
+assert wallet.sync(node)['reverted'] == 1 and wallet.balance() == 50
+");
+    }
+
+    #[test]
+    fn generated_chains_are_the_same_for_the_same_seed() {
+        run("
+other = uw.MockNode()
+assert other.generate_chain(3, 5, 2, ['Alice', 'Bob']) == uw.MockNode().generate_chain(3, 5, 2, ['Alice', 'Bob'])
+assert other.best_block_at_height(5) is not None and other.best_block_at_height(6) is None
+");
+    }
+}
@@ -0,0 +1,86 @@
+//! Unvalidated transaction construction, for exercising rejection paths in tests.
+//!
+//! Nothing here checks that the inputs exist, that the values add up, or that the signatures
+//! are right. The module is only compiled for the crate's own tests and when the
+//! `raw-transactions` feature is enabled, so production builds cannot reach it by accident.
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// How `create_raw_transaction` signs the inputs.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SigningMode {
+    /// Sign like the wallet would, as the owner of each known coin. Unknown coins get an invalid signature.
+    Owner,
+    /// Sign every input as the given address, whether or not it owns the coin.
+    As(Address),
+    /// Put an invalid signature on every input.
+    Invalid,
+}
+
+impl Wallet {
+    /// Assemble a transaction from exactly the given inputs and outputs without validating anything.
+    /// Zero inputs, zero value coins, overspending, and foreign or unknown coins are all accepted.
+    pub fn create_raw_transaction(&self, inputs: Vec<CoinId>, outputs: Vec<Coin>, signing: SigningMode) -> Transaction {
+        let inputs = inputs
+            .into_iter()
+            .map(|coin_id| {
+                let signature = match &signing {
+                    SigningMode::As(address) => Signature::Valid(address.clone()),
+                    SigningMode::Owner | SigningMode::Invalid => Signature::Invalid,
+                };
+                Input { coin_id, signature }
+            })
+            .collect();
+        let mut transaction = Transaction { version: TRANSACTION_VERSION, inputs, outputs };
+        if signing == SigningMode::Owner {
+            self.sign_inputs(&mut transaction);
+        }
+        transaction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    fn huge() -> Coin {
+        Coin {
+            value: 1_000,
+            owner: Address::Eve,
+            asset_id: None,
+        }
+    }
+
+    fn alice_coin(wallet: &Wallet) -> CoinId {
+        *wallet.coins.iter().find(|(_, coin)| coin.value == 15).unwrap().0
+    }
+
+    #[test]
+    fn raw_transactions_may_create_bones_from_nothing() {
+        let (_, wallet) = make_one_block_blockchain();
+        let tx = wallet.create_raw_transaction(vec![], vec![huge()], SigningMode::Owner);
+        assert!(tx.inputs.is_empty());
+        assert_eq!(tx.outputs, vec![huge()]);
+        assert_eq!(wallet.create_manual_transaction(vec![], vec![huge()]), Err(WalletError::ZeroInputs));
+    }
+
+    #[test]
+    fn owner_signing_signs_the_wallet_coins_and_leaves_the_rest_invalid() {
+        let (_, wallet) = make_one_block_blockchain();
+        let unknown = marker_tx().coin_id(1, 0);
+        let tx = wallet.create_raw_transaction(vec![alice_coin(&wallet), unknown], vec![huge()], SigningMode::Owner);
+        assert_eq!(tx.inputs[0].signature, Signature::Valid(Address::Alice));
+        assert_eq!(tx.inputs[1].signature, Signature::Invalid);
+    }
+
+    #[test]
+    fn raw_inputs_can_be_signed_as_anyone_or_not_at_all() {
+        let (_, wallet) = make_one_block_blockchain();
+        let tx = wallet.create_raw_transaction(vec![alice_coin(&wallet)], vec![], SigningMode::As(Address::Eve));
+        assert_eq!(tx.inputs[0].signature, Signature::Valid(Address::Eve));
+        let tx = wallet.create_raw_transaction(vec![alice_coin(&wallet)], vec![], SigningMode::Invalid);
+        assert_eq!(tx.inputs[0].signature, Signature::Invalid);
+    }
+}
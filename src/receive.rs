@@ -0,0 +1,150 @@
+//! Handing out fresh addresses to receive payments on.
+//!
+//! `next_receive_address` issues each address at most once, and never one that has received a coin,
+//! so every invoice or payment request gets its own address. Candidates are the keychain's derived
+//! addresses, in derivation order, followed by the addresses registered with `add_receive_addresses`.
+//! An address counts as used as soon as sync sees a coin paid to it, even if a reorg later removes
+//! that coin, since the payer may pay again.
+
+use std::collections::HashSet;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// The receive addresses handed out so far and which addresses have received coins.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReceiveAddresses {
+    /// Addresses registered for issuing, in registration order.
+    pub(crate) pool: Vec<Address>,
+    /// Addresses handed out, in the order they were issued.
+    pub(crate) issued: Vec<Address>,
+    /// Every address sync has seen receive a coin.
+    pub(crate) used: HashSet<Address>,
+}
+
+impl Wallet {
+    /// Register owned addresses that `next_receive_address` may hand out after the keychain's addresses.
+    /// Addresses already registered are skipped.
+    pub fn add_receive_addresses(&mut self, addresses: impl IntoIterator<Item = Address>) -> WalletResult<()> {
+        let addresses: Vec<Address> = addresses.into_iter().collect();
+        if let Some(address) = addresses.iter().find(|address| !self.addresses.contains(address)) {
+            return Err(WalletError::ForeignAddress(address.clone()));
+        }
+        for address in addresses {
+            if !self.receiving.pool.contains(&address) {
+                self.receiving.pool.push(address);
+            }
+        }
+        Ok(())
+    }
+
+    /// Hand out an address that was never issued before and has not received any coin.
+    ///
+    /// A wallet with a keychain derives another address once all derived ones are taken, so it always
+    /// returns one; addresses issued that far past the last used one are not found again by a wallet
+    /// restored from the seed until an address before them gets used. Without a keychain, returns
+    /// `None` once the registered addresses run out.
+    pub fn next_receive_address(&mut self) -> Option<Address> {
+        let fresh = |address: &&Address| {
+            !self.receiving.issued.contains(address) && !self.is_address_used(address)
+        };
+        let candidate = self.derived_addresses().iter().chain(&self.receiving.pool).find(fresh).cloned();
+        let address = match (candidate, self.hd.as_mut()) {
+            (Some(address), _) => address,
+            (None, Some(hd)) => {
+                let next = hd.derived.len() + 1;
+                let address = hd.derive_to(next).pop()?;
+                self.addresses.insert(address.clone());
+                address
+            }
+            (None, None) => return None,
+        };
+        self.receiving.issued.push(address.clone());
+        Some(address)
+    }
+
+    /// The addresses handed out by `next_receive_address` in the order they were issued, each with
+    /// whether it has received a coin since.
+    pub fn issued_addresses(&self) -> Vec<(Address, bool)> {
+        self.receiving
+            .issued
+            .iter()
+            .map(|address| (address.clone(), self.is_address_used(address)))
+            .collect()
+    }
+
+    /// Whether sync has seen a coin paid to the address.
+    pub fn is_address_used(&self, address: &Address) -> bool {
+        self.receiving.used.contains(address)
+    }
+
+    /// Remember the owners of coins the wallet just received as used.
+    pub(crate) fn mark_used<'a>(&mut self, coins: impl IntoIterator<Item = &'a Coin>) {
+        self.receiving.used.extend(coins.into_iter().map(|coin| coin.owner.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    fn keychain() -> HdKeychain {
+        HdKeychain {
+            gap_limit: 2,
+            ..HdKeychain::new(7)
+        }
+    }
+
+    /// A wallet of Alice, Bob and a keychain with a gap of two, handing out Alice and Bob as
+    /// receive addresses besides the derived ones.
+    fn receiving_wallet() -> Wallet {
+        let mut wallet = Wallet::builder().address(Address::Alice).address(Address::Bob).keychain(keychain()).build().unwrap();
+        wallet.add_receive_addresses([Address::Alice, Address::Bob]).unwrap();
+        wallet
+    }
+
+    /// Issue the first receive address of `wallet`, then sync it to a block paying the second
+    /// derived address before it is issued.
+    fn issue_one_and_pay_the_next(wallet: &mut Wallet) {
+        let mut node = MockNode::new();
+        node.add_block_as_best(Block::genesis().id(), vec![mint([(keychain().derive(1), 3)])]);
+        assert_eq!(wallet.next_receive_address(), Some(keychain().derive(0)));
+        wallet.sync(&node);
+        assert!(wallet.is_address_used(&keychain().derive(1)));
+    }
+
+    #[test]
+    fn only_owned_addresses_are_handed_out() {
+        let mut wallet = receiving_wallet();
+        assert_eq!(wallet.add_receive_addresses([Address::Eve]), Err(WalletError::ForeignAddress(Address::Eve)));
+    }
+
+    #[test]
+    fn addresses_are_issued_once_and_never_after_use() {
+        let mut wallet = receiving_wallet();
+        issue_one_and_pay_the_next(&mut wallet);
+
+        // the derived addresses come first, and the keychain derives more once the others run out
+        let issued: Vec<Address> = std::iter::from_fn(|| wallet.next_receive_address()).take(5).collect();
+        assert_eq!(issued, vec![keychain().derive(2), keychain().derive(3), Address::Alice, Address::Bob, keychain().derive(4)]);
+        assert_eq!(wallet.issued_addresses().len(), 6);
+        assert!(wallet.issued_addresses().iter().all(|(_, used)| !used));
+    }
+
+    #[test]
+    fn issued_addresses_survive_export() {
+        let mut wallet = receiving_wallet();
+        issue_one_and_pay_the_next(&mut wallet);
+        wallet.next_receive_address();
+        assert_eq!(Wallet::import_state(&wallet.export_state()).unwrap().issued_addresses(), wallet.issued_addresses());
+    }
+
+    #[test]
+    fn issuing_stops_when_the_addresses_run_out_without_a_keychain() {
+        let mut plain = wallet_with_alice();
+        plain.add_receive_addresses([Address::Alice]).unwrap();
+        assert_eq!(plain.next_receive_address(), Some(Address::Alice));
+        assert_eq!(plain.next_receive_address(), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! Addresses the wallet only receives on, such as a donations address.
+//!
+//! Automatic coin selection, consolidation, and withdrawal batches skip the coins of a receive-only
+//! address, and change is not sent to one while the wallet has other addresses. Transactions naming
+//! such a coin explicitly fail with `WalletError::AddressSpendingDisabled`; `spend_receive_only`
+//! builds them anyway, for when spending one is intended. The flags are part of the exported state.
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+impl Wallet {
+    /// Mark an owned address receive-only, or allow spending its coins again.
+    pub fn set_receive_only(&mut self, address: Address, receive_only: bool) -> WalletResult<()> {
+        if !self.addresses.contains(&address) {
+            return Err(WalletError::ForeignAddress(address));
+        }
+        if receive_only {
+            self.receive_only.insert(address);
+        } else {
+            self.receive_only.remove(&address);
+        }
+        Ok(())
+    }
+
+    /// Whether the address is marked receive-only.
+    pub fn is_receive_only(&self, address: &Address) -> bool {
+        self.receive_only.contains(address)
+    }
+
+    /// Like `create_manual_transaction`, but also spending coins of receive-only addresses.
+    pub fn spend_receive_only(&self, input_coin_ids: Vec<CoinId>, output_coins: Vec<Coin>) -> WalletResult<Transaction> {
+        self.build_manual_transaction(input_coin_ids, output_coins, true)
+    }
+
+    /// Whether automatic selection may spend the coin: it is mature, not of a receive-only address,
+    /// and not spent by a sent transaction waiting to be mined.
+    pub(crate) fn is_auto_spendable(&self, coin_id: &CoinId, coin: &Coin) -> bool {
+        self.is_mature(coin_id) && !self.receive_only.contains(&coin.owner) && !self.is_pending_spend(coin_id)
+    }
+
+    /// Fail if the wallet's coin belongs to a receive-only address.
+    pub(crate) fn check_spending_enabled(&self, coin_id: &CoinId) -> WalletResult<()> {
+        match self.coins.get(coin_id) {
+            Some(coin) if self.receive_only.contains(&coin.owner) => Err(WalletError::AddressSpendingDisabled(coin.owner.clone())),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice and Bob, with Bob's address receive-only, synced to a block paying Alice
+    /// 10 bones and Bob 100 in donations. Returns the wallet and the funding transaction.
+    fn with_donations() -> (Wallet, Transaction) {
+        let mut node = MockNode::new();
+        let mut wallet = Wallet::new([Address::Alice, Address::Bob].into_iter());
+        let funding = mint([(Address::Alice, 10), (Address::Bob, 100)]);
+        node.add_block_as_best(Block::genesis().id(), vec![funding.clone()]);
+        wallet.sync(&node);
+        wallet.set_receive_only(Address::Bob, true).unwrap();
+        (wallet, funding)
+    }
+
+    fn payout() -> Vec<Coin> {
+        vec![Coin { value: 100, owner: Address::Charlie, asset_id: None }]
+    }
+
+    #[test]
+    fn only_owned_addresses_are_made_receive_only() {
+        let (mut wallet, _) = with_donations();
+        assert!(wallet.is_receive_only(&Address::Bob));
+        assert!(!wallet.is_receive_only(&Address::Alice));
+        assert_eq!(wallet.set_receive_only(Address::Eve, true), Err(WalletError::ForeignAddress(Address::Eve)));
+    }
+
+    #[test]
+    fn automatic_selection_leaves_receive_only_coins_alone() {
+        let (wallet, funding) = with_donations();
+        assert_eq!(
+            wallet.create_automatic_transaction(Address::Charlie, 20, 0),
+            Err(WalletError::InsufficientFunds { needed: 20, available: 10 })
+        );
+        // and sends the change elsewhere
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 5, 0).unwrap();
+        assert_eq!(tx.iter_input_coin_ids().collect::<Vec<_>>(), [funding.coin_id(1, 0)]);
+        assert_eq!(tx.outputs[1].owner, Address::Alice);
+    }
+
+    #[test]
+    fn consolidation_leaves_receive_only_coins_alone() {
+        let (wallet, _) = with_donations();
+        assert!(wallet.consolidate_coins(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn naming_receive_only_coins_is_refused() {
+        let (wallet, funding) = with_donations();
+        let donations = funding.coin_id(1, 1);
+        assert_eq!(wallet.create_manual_transaction(vec![donations], payout()), Err(WalletError::AddressSpendingDisabled(Address::Bob)));
+        assert_eq!(wallet.create_split_transaction(donations, vec![50, 50]), Err(WalletError::AddressSpendingDisabled(Address::Bob)));
+    }
+
+    #[test]
+    fn receive_only_coins_are_spent_on_request() {
+        let (wallet, funding) = with_donations();
+        let tx = wallet.spend_receive_only(vec![funding.coin_id(1, 1)], payout()).unwrap();
+        assert_eq!(tx.inputs[0].signature, Signature::Valid(Address::Bob));
+    }
+
+    #[test]
+    fn the_flag_survives_export_and_can_be_lifted() {
+        let (wallet, funding) = with_donations();
+        let mut restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert!(restored.is_receive_only(&Address::Bob));
+        restored.set_receive_only(Address::Bob, false).unwrap();
+        assert!(restored.create_manual_transaction(vec![funding.coin_id(1, 1)], payout()).is_ok());
+    }
+}
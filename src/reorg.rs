@@ -0,0 +1,211 @@
+//! A log of the reorgs sync has processed.
+//!
+//! A reorg starts when sync finds the wallet's best block has left the node's chain and rolls the
+//! wallet back, and ends when the same or a later sync has caught up with the new branch. A reorg
+//! below the undo records, which makes `sync` resync from genesis, is logged as a rollback to height
+//! zero. The log is part of the exported state, so it survives restarts.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// One reorg the wallet went through.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ReorgRecord {
+    /// The wallet's best height before the reorg.
+    pub from_height: u64,
+    /// The wallet's best block before the reorg.
+    pub old_tip: BlockId,
+    /// The height the wallet rolled back to before following the new branch.
+    pub rolled_back_to: u64,
+    /// The wallet's best height once it caught up with the new branch.
+    pub new_height: u64,
+    /// The wallet's best block once it caught up with the new branch.
+    pub new_tip: BlockId,
+    /// Coins the wallet held from the reverted blocks, ordered by id.
+    pub coins_reverted: Vec<CoinId>,
+    /// The reverted coins the new branch gave the wallet again, ordered by id.
+    pub coins_readded: Vec<CoinId>,
+    /// The timestamp of the new tip block.
+    pub timestamp: u64,
+}
+
+impl ReorgRecord {
+    /// How many blocks the wallet rolled back.
+    pub fn depth(&self) -> u64 {
+        self.from_height - self.rolled_back_to
+    }
+}
+
+/// How often and how deeply the wallet's view of the chain changed.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct ReorgStats {
+    /// The number of reorgs logged.
+    pub count: usize,
+    /// The depth of the deepest reorg.
+    pub max_depth: u64,
+    /// The depths of all reorgs added up, for averaging.
+    pub total_depth: u64,
+    /// The number of reorgs of each depth.
+    pub by_depth: BTreeMap<u64, usize>,
+}
+
+/// The logged reorgs and the one sync is in the middle of.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReorgLog {
+    pub(crate) records: Vec<ReorgRecord>,
+    /// Started by a rollback, finished once sync catches up. Not part of the exported state.
+    pub(crate) in_progress: Option<ReorgRecord>,
+}
+
+impl Wallet {
+    /// Every reorg the wallet has processed, oldest first.
+    pub fn reorg_history(&self) -> &[ReorgRecord] {
+        &self.reorgs.records
+    }
+
+    /// Count and depth statistics over `reorg_history`.
+    pub fn reorg_stats(&self) -> ReorgStats {
+        let mut stats = ReorgStats::default();
+        for record in &self.reorgs.records {
+            stats.count += 1;
+            stats.max_depth = stats.max_depth.max(record.depth());
+            stats.total_depth += record.depth();
+            *stats.by_depth.entry(record.depth()).or_default() += 1;
+        }
+        stats
+    }
+
+    /// Start logging a reorg from the sync position `start`, or extend the one already started,
+    /// after rolling back and dropping the `reverted` coins.
+    pub(crate) fn note_rollback(&mut self, start: (u64, BlockId), reverted: impl IntoIterator<Item = CoinId>) {
+        let record = self.reorgs.in_progress.get_or_insert_with(|| ReorgRecord {
+            from_height: start.0,
+            old_tip: start.1,
+            rolled_back_to: start.0,
+            new_height: start.0,
+            new_tip: start.1,
+            coins_reverted: Vec::new(),
+            coins_readded: Vec::new(),
+            timestamp: 0,
+        });
+        record.rolled_back_to = record.rolled_back_to.min(self.best_block_height);
+        record.coins_reverted.extend(reverted);
+    }
+
+    /// Finish the reorg in progress, if any, now that sync caught up with the node's chain.
+    /// `tip_timestamp` is the timestamp of the block sync applied last, if it applied any.
+    pub(crate) fn finish_reorg<Node: NodeEndpoint>(&mut self, node: &Node, tip_timestamp: Option<u64>) {
+        let Some(mut record) = self.reorgs.in_progress.take() else {
+            return;
+        };
+        let reverted: BTreeSet<CoinId> = record.coins_reverted.drain(..).collect();
+        let entries = self.history.entries();
+        let start = entries.partition_point(|entry| entry.height <= record.rolled_back_to);
+        let readded: BTreeSet<CoinId> = entries[start..]
+            .iter()
+            .flat_map(|entry| &entry.received)
+            .map(|(coin_id, _)| *coin_id)
+            .filter(|coin_id| reverted.contains(coin_id))
+            .collect();
+        record.coins_reverted = reverted.into_iter().collect();
+        record.coins_readded = readded.into_iter().collect();
+        record.new_height = self.best_block_height;
+        record.new_tip = self.best_block_hash;
+        record.timestamp = tip_timestamp
+            .or_else(|| node.entire_block(&self.best_block_hash).map(|block| block.timestamp))
+            .unwrap_or(0);
+        #[cfg(feature = "tracing")]
+        tracing::info!(depth = record.depth(), reverted = record.coins_reverted.len(), "reorg logged");
+        self.reorgs.records.push(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    use std::collections::{BTreeMap, BTreeSet};
+
+    /// A wallet of Alice with three undo records, synced to three blocks: an empty one, one paying
+    /// her 5 bones, and one rewarding her. Then a longer branch from the first block mines the
+    /// payment again at the same height but not the reward, and the wallet syncs to it at time
+    /// 1,000. Returns the node, the wallet and the record that reorg should leave.
+    fn reorged() -> (MockNode, Wallet, ReorgRecord) {
+        let mut node = MockNode::new();
+        let pay_alice = mint([(Address::Alice, 5)]);
+        let b1 = node.add_block_as_best(Block::genesis().id(), vec![]);
+        let b2 = node.add_block_as_best(b1, vec![pay_alice.clone()]);
+        let b3 = node.add_block_with_coinbase(b2, Address::Alice, vec![]);
+        node.set_best(b3);
+        let mut wallet = Wallet::builder().address(Address::Alice).max_reorg_depth(3).build().unwrap();
+        wallet.sync(&node);
+        assert!(wallet.reorg_history().is_empty());
+        let kept = pay_alice.coin_id(2, 0);
+        let lost = node.entire_block(&b3).unwrap().body[0].coin_id(3, 0);
+
+        let c2 = node.add_block(b1, vec![marker_tx(), pay_alice]);
+        node.set_time(1_000);
+        let c3 = node.add_block(c2, vec![]);
+        let c4 = node.add_block_as_best(c3, vec![]);
+        wallet.sync(&node);
+        let record = ReorgRecord {
+            from_height: 3,
+            old_tip: b3,
+            rolled_back_to: 1,
+            new_height: 4,
+            new_tip: c4,
+            coins_reverted: BTreeSet::from([kept, lost]).into_iter().collect(),
+            coins_readded: vec![kept],
+            timestamp: 1_000,
+        };
+        (node, wallet, record)
+    }
+
+    /// Switch to a branch from genesis one block longer than the best one, after cutting the
+    /// wallet's undo records to one so it resyncs from genesis.
+    fn reorg_past_the_undo_records(node: &mut MockNode, wallet: &mut Wallet) {
+        wallet.set_config(WalletConfig { undo_depth: 1, ..wallet.config().clone() }).unwrap();
+        let mut parent = node.add_block(Block::genesis().id(), vec![mint([(Address::Custom(7), 7)])]);
+        for _ in 0..3 {
+            parent = node.add_block(parent, vec![]);
+        }
+        node.add_block_as_best(parent, vec![]);
+        wallet.sync(node);
+    }
+
+    #[test]
+    fn reorgs_record_their_depth_and_coins() {
+        let (_, wallet, record) = reorged();
+        assert_eq!(wallet.reorg_history(), [record]);
+    }
+
+    #[test]
+    fn reorgs_past_the_undo_records_are_logged_from_genesis() {
+        let (mut node, mut wallet, record) = reorged();
+        reorg_past_the_undo_records(&mut node, &mut wallet);
+        assert_eq!(wallet.reorg_history().len(), 2);
+        let deep = &wallet.reorg_history()[1];
+        assert_eq!((deep.from_height, deep.rolled_back_to, deep.new_height), (4, 0, 5));
+        assert_eq!(deep.coins_reverted, record.coins_readded);
+        assert!(deep.coins_readded.is_empty());
+    }
+
+    #[test]
+    fn reorg_stats_sum_up_the_log() {
+        let (mut node, mut wallet, _) = reorged();
+        reorg_past_the_undo_records(&mut node, &mut wallet);
+        let stats = wallet.reorg_stats();
+        assert_eq!((stats.count, stats.max_depth, stats.total_depth), (2, 4, 6));
+        assert_eq!(stats.by_depth, BTreeMap::from([(2, 1), (4, 1)]));
+    }
+
+    #[test]
+    fn the_reorg_log_survives_export() {
+        let (_, wallet, _) = reorged();
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.reorg_history(), wallet.reorg_history());
+    }
+}
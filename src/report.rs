@@ -0,0 +1,148 @@
+//! Per-block reports of what a sync changed.
+//!
+//! `try_sync` returns a `SyncReport` listing every block it rolled back and every block it applied,
+//! each with the wallet's coins it credited and debited. Applying the reverted reports in reverse and
+//! then the applied ones replays exactly the sync's effect, so a back end can credit deposits once per
+//! block without diffing balances.
+
+use bonecoin_core::*;
+
+use crate::delta::StateDelta;
+
+/// What one block changed in the wallet.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct BlockReport {
+    /// The height of the block.
+    pub height: u64,
+    /// The id of the block.
+    pub block_id: BlockId,
+    /// The number of transactions in the block, relevant or not.
+    pub transactions_scanned: usize,
+    /// Coins the block gave the wallet, in block order, including coins it spent again.
+    pub credited: Vec<(CoinId, Coin)>,
+    /// Wallet coins the block spent, in block order.
+    pub debited: Vec<(CoinId, Coin)>,
+    /// The block's transactions that touched the wallet, in block order.
+    pub transactions: Vec<TransactionId>,
+}
+
+impl BlockReport {
+    pub(crate) fn new(delta: &StateDelta) -> Self {
+        BlockReport {
+            height: delta.height,
+            block_id: delta.block_id,
+            transactions_scanned: delta.transactions,
+            credited: delta.history.iter().flat_map(|entry| entry.received.iter().cloned()).collect(),
+            debited: delta.history.iter().flat_map(|entry| entry.spent.iter().cloned()).collect(),
+            transactions: delta.history.iter().map(|entry| entry.tx_id).collect(),
+        }
+    }
+}
+
+/// The blocks a sync rolled back and applied.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct SyncReport {
+    /// Blocks applied by earlier syncs that this sync rolled back, newest first.
+    pub reverted: Vec<BlockReport>,
+    /// Blocks this sync applied and kept, oldest first.
+    pub applied: Vec<BlockReport>,
+    /// Whether the wallet dropped its coins to rescan from genesis. Blocks applied before the sync
+    /// that were dropped this way are missing from `reverted`.
+    pub rescanned_from_genesis: bool,
+}
+
+impl SyncReport {
+    /// Whether the sync changed nothing.
+    pub fn is_empty(&self) -> bool {
+        self.reverted.is_empty() && self.applied.is_empty() && !self.rescanned_from_genesis
+    }
+
+    /// Record a block rolled back during the sync. A block this sync applied itself is simply
+    /// taken out of `applied`, so the report never lists it twice.
+    pub(crate) fn undo(&mut self, block: BlockReport) {
+        if self.applied.last().is_some_and(|applied| applied.block_id == block.block_id) {
+            self.applied.pop();
+        } else {
+            self.reverted.push(block);
+        }
+    }
+
+    /// Record that the wallet dropped its coins to rescan from genesis, taking the blocks this sync
+    /// applied so far with it.
+    pub(crate) fn reset(&mut self) {
+        self.applied.clear();
+        self.rescanned_from_genesis = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A node whose first block rewards Alice and holds the marker transaction, and a wallet of
+    /// Alice that has not synced yet.
+    fn one_block() -> (MockNode, Wallet, BlockId) {
+        let mut node = MockNode::new();
+        let b1 = node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD), marker_tx()]);
+        (node, wallet_with_alice(), b1)
+    }
+
+    #[test]
+    fn applied_blocks_are_reported() {
+        let (node, mut wallet, b1) = one_block();
+        let coinbase = Transaction::coinbase(Address::Alice, BLOCK_REWARD);
+        let report = wallet.try_sync(&node).unwrap();
+        assert!(report.reverted.is_empty());
+        assert_eq!(
+            report.applied,
+            [BlockReport {
+                height: 1,
+                block_id: b1,
+                transactions_scanned: 2,
+                credited: vec![(coinbase.coin_id(1, 0), coinbase.outputs[0].clone())],
+                debited: vec![],
+                transactions: vec![coinbase.id()],
+            }]
+        );
+    }
+
+    #[test]
+    fn syncs_without_new_blocks_report_nothing() {
+        let (node, mut wallet, _) = one_block();
+        wallet.try_sync(&node).unwrap();
+        assert!(wallet.try_sync(&node).unwrap().is_empty());
+    }
+
+    #[test]
+    fn coins_paid_and_spent_in_the_same_block_are_credited_and_debited() {
+        let (mut node, mut wallet, b1) = one_block();
+        wallet.try_sync(&node).unwrap();
+        let payment = mint([(Address::Alice, 4)]);
+        let paid = (payment.coin_id(2, 0), payment.outputs[0].clone());
+        let spent = spend(paid.0, Address::Alice, [(Address::Bob, 4)]);
+        node.add_block_as_best(b1, vec![payment.clone(), spent.clone(), mint([(Address::Custom(9), 9)])]);
+
+        let report = wallet.try_sync(&node).unwrap();
+        assert_eq!(report.applied.len(), 1);
+        assert_eq!(report.applied[0].transactions_scanned, 3);
+        assert_eq!(report.applied[0].credited, std::slice::from_ref(&paid));
+        assert_eq!(report.applied[0].debited, [paid]);
+        assert_eq!(report.applied[0].transactions, [payment.id(), spent.id()]);
+    }
+
+    #[test]
+    fn reorgs_report_the_reverted_blocks_newest_first_then_the_new_branch() {
+        let (mut node, mut wallet, b1) = one_block();
+        let b2 = node.add_block_as_best(b1, vec![mint([(Address::Custom(9), 9)])]);
+        wallet.try_sync(&node).unwrap();
+
+        let c2 = node.add_block(b1, vec![]);
+        let c3 = node.add_block_as_best(c2, vec![]);
+        let report = wallet.try_sync(&node).unwrap();
+        let reverted: Vec<BlockId> = report.reverted.iter().map(|block| block.block_id).collect();
+        let applied: Vec<BlockId> = report.applied.iter().map(|block| block.block_id).collect();
+        assert_eq!(reverted, [b2]);
+        assert_eq!(applied, [c2, c3]);
+        assert!(!report.rescanned_from_genesis);
+    }
+}
@@ -0,0 +1,226 @@
+//! Rescanning the chain for a few addresses instead of resyncing the whole wallet.
+//!
+//! `rescan_addresses` adds addresses to the wallet and replays the blocks from a height up to the
+//! wallet's best block for them alone. Like lazy sync, it first asks the node for the transactions
+//! of each block matching a bloom filter of the addresses and of the coins found for them so far, and
+//! only fetches the blocks with matches. Coins the wallet already knows are left as they are, so
+//! rescanning an address twice changes nothing.
+//!
+//! The history entries and undo records of the blocks with matches are rewritten in place, so a later
+//! reorg rolls the found coins back like any other. Events, watchtower checks, and sync plugins only
+//! see blocks as sync applies them and are not run for the rescanned ones.
+
+use std::collections::{BTreeMap, HashSet};
+
+use bonecoin_core::*;
+
+use crate::history::burned_by;
+use crate::lazy::LAZY_FILTER_RATE;
+use crate::{AuditError, Direction, HistoryEntry, Wallet};
+
+impl Wallet {
+    /// Add the addresses to the wallet and find the coins they received and spent from `from_height`
+    /// up to the wallet's best block, without resyncing the rest of the wallet.
+    ///
+    /// Returns the transactions whose history entries the rescan added or changed, in chain order.
+    /// Fails if the wallet's best block is not on the node's chain, which sync handles, or if the
+    /// node cannot provide a block; the blocks rescanned before the failure keep what they found.
+    pub fn rescan_addresses<Node: NodeEndpoint>(
+        &mut self,
+        node: &Node,
+        addresses: &[Address],
+        from_height: u64,
+    ) -> Result<Vec<TransactionId>, AuditError> {
+        let height = self.best_block_height;
+        if node.best_block_at_height(height).unwrap_or(Block::genesis().id()) != self.best_block_hash {
+            return Err(AuditError::NotOnNodeChain { height });
+        }
+        let subset: HashSet<Address> = addresses.iter().cloned().collect();
+        self.addresses.extend(subset.iter().cloned());
+
+        // the unspent coins found so far, whose spending the filter must match too
+        let mut found: BTreeMap<CoinId, Coin> = BTreeMap::new();
+        let mut touched = Vec::new();
+        for block_height in from_height.max(1)..=height {
+            let block_id = node.best_block_at_height(block_height).ok_or(AuditError::BlockUnavailable { height: block_height })?;
+            let mut filter = BloomFilter::new(subset.len() + found.len(), LAZY_FILTER_RATE);
+            for address in &subset {
+                filter.insert(address);
+            }
+            for coin_id in found.keys() {
+                filter.insert(coin_id);
+            }
+            if node.relevant_transactions(&block_id, &filter).is_some_and(|matches| matches.is_empty()) {
+                continue;
+            }
+            let block = node.entire_block(&block_id).ok_or(AuditError::BlockUnavailable { height: block_height })?;
+            touched.extend(self.rescan_block(block_id, &block, &subset, &mut found));
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(from_height, addresses = subset.len(), transactions = touched.len(), "addresses rescanned");
+        Ok(touched)
+    }
+
+    /// Apply what the block did to coins of the rescanned addresses the wallet did not know, and
+    /// rewrite the block's history entries and undo record to match. Returns the transactions whose
+    /// entries changed.
+    fn rescan_block(&mut self, block_id: BlockId, block: &Block, subset: &HashSet<Address>, found: &mut BTreeMap<CoinId, Coin>) -> Vec<TransactionId> {
+        let coinbase = block.coinbase().map(|tx| tx.id());
+        let existing: BTreeMap<TransactionId, HistoryEntry> = self
+            .history
+            .entries()
+            .iter()
+            .filter(|entry| entry.height == block.number)
+            .map(|entry| (entry.tx_id, entry.clone()))
+            .collect();
+        let mut entries = Vec::new();
+        let mut touched = Vec::new();
+        let mut spent_here = BTreeMap::new();
+        let mut created_here = BTreeMap::new();
+        let mut outpoints = Vec::new();
+        let mut minted = Vec::new();
+
+        for transaction in &block.body {
+            let tx_id = transaction.id();
+            let mut spent_new = Vec::new();
+            for coin_id in transaction.iter_input_coin_ids() {
+                if let Some(coin) = found.remove(&coin_id) {
+                    self.coins.remove(&coin_id);
+                    if created_here.remove(&coin_id).is_none() {
+                        spent_here.insert(coin_id, coin.clone());
+                    }
+                    spent_new.push((coin_id, coin));
+                }
+            }
+            let mut received_new = false;
+            for (index, (coin_id, coin)) in transaction.iter_output_coins_and_ids(block.number).enumerate() {
+                if !subset.contains(&coin.owner) || self.outpoints.contains_key(&coin_id) {
+                    continue;
+                }
+                received_new = true;
+                self.coins.insert(coin_id, coin.clone());
+                self.outpoints.insert(coin_id, (tx_id, index));
+                self.mark_used([&coin]);
+                outpoints.push((coin_id, (tx_id, index)));
+                if coinbase == Some(tx_id) {
+                    self.coinbase_heights.insert(coin_id, block.number);
+                    minted.push(coin_id);
+                }
+                found.insert(coin_id, coin.clone());
+                created_here.insert(coin_id, coin);
+            }
+
+            let previous = existing.get(&tx_id);
+            if spent_new.is_empty() && !received_new {
+                entries.extend(previous.cloned());
+                continue;
+            }
+            // the inputs the wallet already knew plus the ones found, in input order
+            let mut known_spent: BTreeMap<CoinId, Coin> = previous.map(|entry| entry.spent.iter().cloned().collect()).unwrap_or_default();
+            known_spent.extend(spent_new);
+            let spent: Vec<(CoinId, Coin)> = transaction
+                .iter_input_coin_ids()
+                .filter_map(|coin_id| Some((coin_id, known_spent.get(&coin_id)?.clone())))
+                .collect();
+            let received: Vec<(CoinId, Coin)> =
+                transaction.iter_output_coins_and_ids(block.number).filter(|(_, coin)| self.owns(&coin.owner)).collect();
+            let pays_foreign_address = transaction.outputs.iter().any(|coin| !self.owns(&coin.owner));
+            entries.push(HistoryEntry {
+                tx_id,
+                block_id,
+                height: block.number,
+                timestamp: block.timestamp,
+                direction: Direction::classify(!spent.is_empty(), spent.len() < transaction.inputs.len(), pays_foreign_address),
+                burned: burned_by(transaction, &spent),
+                received,
+                spent,
+            });
+            touched.push(tx_id);
+        }
+
+        if touched.is_empty() {
+            return touched;
+        }
+        if let Some(delta) = self.undo.iter_mut().find(|delta| delta.height == block.number) {
+            delta.spent.extend(spent_here);
+            delta.created.extend(created_here);
+            delta.outpoints.extend(outpoints);
+            delta.coinbase.extend(minted);
+            delta.history = entries.clone();
+        }
+        self.history.replace_block(block.number, entries);
+        touched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::test_support::*;
+
+    /// A wallet of Alice synced to four blocks: Alice mines 50 bones and Charlie is paid 30 in
+    /// block 1, Bob mines block 2, Charlie pays 10 to Alice and 20 to Eve in block 3, and is paid
+    /// 5 more in block 4. Returns the node, the wallet, block 2, and Charlie's transactions.
+    fn charlie_not_yet_added() -> (MockNode, Wallet, BlockId, [Transaction; 3]) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+
+        let pay_charlie = mint([(Address::Charlie, 30)]);
+        let charlie_pays = spend(pay_charlie.coin_id(1, 0), Address::Charlie, [(Address::Alice, 10), (Address::Eve, 20)]);
+        let pay_again = mint([(Address::Charlie, 5)]);
+
+        let b1 = node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, 50), pay_charlie.clone()]);
+        let b2 = node.add_block_as_best(b1, vec![Transaction::coinbase(Address::Bob, 50)]);
+        let b3 = node.add_block_as_best(b2, vec![charlie_pays.clone()]);
+        node.add_block_as_best(b3, vec![pay_again.clone()]);
+        wallet.sync(&node);
+        (node, wallet, b2, [pay_charlie, charlie_pays, pay_again])
+    }
+
+    /// Asserts that `wallet` holds what a wallet of Alice and Charlie synced from scratch does.
+    fn assert_same_as_fresh(wallet: &Wallet, node: &MockNode) {
+        let mut fresh = Wallet::new(vec![Address::Alice, Address::Charlie].into_iter());
+        fresh.sync(node);
+        assert_eq!(wallet.history.entries(), fresh.history.entries());
+        assert_eq!(wallet.coins.iter().collect::<HashMap<_, _>>(), fresh.coins.iter().collect::<HashMap<_, _>>());
+        assert_eq!(wallet.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn rescans_return_the_transactions_they_found_once() {
+        let (node, mut wallet, _, [pay_charlie, charlie_pays, pay_again]) = charlie_not_yet_added();
+        assert_eq!(wallet.net_worth(), 60);
+
+        let touched = wallet.rescan_addresses(&node, &[Address::Charlie], 1).unwrap();
+        assert_eq!(touched, vec![pay_charlie.id(), charlie_pays.id(), pay_again.id()]);
+        assert_eq!(wallet.rescan_addresses(&node, &[Address::Charlie], 1), Ok(vec![]));
+    }
+
+    #[test]
+    fn rescanned_wallets_match_a_wallet_that_owned_the_address_all_along() {
+        let (node, mut wallet, _, _) = charlie_not_yet_added();
+
+        wallet.rescan_addresses(&node, &[Address::Charlie], 1).unwrap();
+
+        assert_same_as_fresh(&wallet, &node);
+        assert_eq!(wallet.net_worth(), 65);
+        assert_eq!(wallet.history.entries()[2].direction, Direction::Outgoing);
+    }
+
+    #[test]
+    fn reorgs_roll_the_rescanned_coins_back() {
+        let (mut node, mut wallet, b2, _) = charlie_not_yet_added();
+        wallet.rescan_addresses(&node, &[Address::Charlie], 1).unwrap();
+
+        let fork = node.add_block(b2, vec![]);
+        let fork = node.add_block(fork, vec![]);
+        let tip = node.add_block(fork, vec![]);
+        node.set_best(tip);
+        wallet.sync(&node);
+
+        assert_same_as_fresh(&wallet, &node);
+        assert_eq!(wallet.net_worth(), 80);
+    }
+}
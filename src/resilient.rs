@@ -0,0 +1,335 @@
+//! Retrying and circuit breaking for an unreliable node.
+//!
+//! A node endpoint answers `None` both when it has nothing to give and when it failed to answer. A
+//! `ResilientNode` tells the two apart by asking the node something it must know: a missing block id
+//! is only the end of the chain if the block below it is answered and asking again still finds none,
+//! and any other missing answer only counts if the node still answers the genesis block.
+//!
+//! A failed call is retried with exponential backoff. Calls that fail every retry open the circuit
+//! once enough of them follow each other: until the cooldown passes, every call fails at once
+//! without reaching the node, and the first call after it closes the circuit again only if it
+//! succeeds.
+//!
+//! A call cannot be interrupted while the node is answering it, so a node that never answers still
+//! blocks the call. Bounding how long a request may take is up to the endpoint's transport, which
+//! then fails the call. `Wallet::sync_resilient` turns failed calls into
+//! `SyncError::NodeUnavailable`; since sync applies every block whole, the wallet is left at the
+//! last block it could fetch.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bonecoin_core::*;
+
+use crate::{SyncError, SyncReport, Wallet};
+
+/// How a `ResilientNode` retries failed calls and when it stops calling the node.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RetryPolicy {
+    /// How many times a failed call is retried.
+    pub max_retries: u32,
+    /// The wait before the first retry, doubled before each further one.
+    pub initial_backoff: Duration,
+    /// The longest wait between retries.
+    pub max_backoff: Duration,
+    /// How many calls in a row must fail every retry to open the circuit.
+    pub failure_threshold: u32,
+    /// How long an open circuit fails calls without reaching the node.
+    pub cooldown: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The wait before the given retry, counting from 1.
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff.saturating_mul(2u32.saturating_pow(retry - 1)).min(self.max_backoff)
+    }
+}
+
+#[derive(Default)]
+struct Breaker {
+    /// Calls in a row that failed every retry.
+    consecutive_failures: u32,
+    /// Until when the circuit is open.
+    open_until: Option<Instant>,
+    /// Whether a call failed since the last `take_failure`.
+    failed: bool,
+    /// Calls that failed every retry or met an open circuit.
+    failed_calls: u64,
+}
+
+/// A node endpoint retrying the calls an unreliable node fails and failing fast while it is down.
+pub struct ResilientNode<E: NodeEndpoint> {
+    node: E,
+    policy: RetryPolicy,
+    breaker: Mutex<Breaker>,
+}
+
+impl<E: NodeEndpoint> ResilientNode<E> {
+    /// Wrap the node with the default retry policy.
+    pub fn new(node: E) -> Self {
+        Self::with_policy(node, RetryPolicy::default())
+    }
+
+    /// Wrap the node with the given retry policy.
+    pub fn with_policy(node: E, policy: RetryPolicy) -> Self {
+        ResilientNode {
+            node,
+            policy,
+            breaker: Mutex::new(Breaker::default()),
+        }
+    }
+
+    /// The node behind the wrapper.
+    pub fn node(&self) -> &E {
+        &self.node
+    }
+
+    /// The node behind the wrapper, to change.
+    pub fn node_mut(&mut self) -> &mut E {
+        &mut self.node
+    }
+
+    /// Stop retrying and return the node.
+    pub fn into_inner(self) -> E {
+        self.node
+    }
+
+    /// Whether the circuit is open, failing calls without reaching the node.
+    pub fn is_open(&self) -> bool {
+        self.lock().open_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// The calls that failed every retry or met an open circuit so far.
+    pub fn failed_calls(&self) -> u64 {
+        self.lock().failed_calls
+    }
+
+    /// Whether a call failed since the last time this was asked.
+    pub(crate) fn take_failure(&self) -> bool {
+        std::mem::take(&mut self.lock().failed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Breaker> {
+        self.breaker.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Make the call, retrying until it is answered. A `None` counts as an answer if
+    /// `nothing_to_give` confirms the node is up.
+    fn call<T>(&self, attempt: impl Fn(&E) -> Option<T>, nothing_to_give: impl Fn(&E) -> bool) -> Option<T> {
+        if self.is_open() {
+            let mut breaker = self.lock();
+            breaker.failed = true;
+            breaker.failed_calls += 1;
+            return None;
+        }
+        for retry in 0..=self.policy.max_retries {
+            if retry > 0 {
+                thread::sleep(self.policy.backoff(retry));
+            }
+            let answer = attempt(&self.node);
+            if answer.is_some() || nothing_to_give(&self.node) {
+                let mut breaker = self.lock();
+                breaker.consecutive_failures = 0;
+                breaker.open_until = None;
+                return answer;
+            }
+        }
+
+        let mut breaker = self.lock();
+        breaker.failed = true;
+        breaker.failed_calls += 1;
+        breaker.consecutive_failures += 1;
+        if breaker.consecutive_failures >= self.policy.failure_threshold {
+            breaker.open_until = Some(Instant::now() + self.policy.cooldown);
+            #[cfg(feature = "tracing")]
+            tracing::warn!(failures = breaker.consecutive_failures, "node keeps failing, circuit opened");
+        }
+        None
+    }
+}
+
+/// Whether the node is up, judged by whether it answers the genesis block.
+fn answers_genesis<E: NodeEndpoint>(node: &E) -> bool {
+    node.best_block_at_height(0).is_some()
+}
+
+impl<E: NodeEndpoint> NodeEndpoint for ResilientNode<E> {
+    fn best_block_at_height(&self, h: u64) -> Option<BlockId> {
+        // the chain ends below `h` if the node answers the height below it, and then `h` once more
+        let attempt = |node: &E| match node.best_block_at_height(h) {
+            Some(block_id) => Some(Some(block_id)),
+            None => (h > 0 && node.best_block_at_height(h - 1).is_some()).then(|| node.best_block_at_height(h)),
+        };
+        self.call(attempt, |_| false).flatten()
+    }
+
+    fn entire_block(&self, id: &BlockId) -> Option<Block> {
+        self.call(|node| node.entire_block(id), |_| false)
+    }
+
+    fn relevant_transactions(&self, id: &BlockId, filter: &BloomFilter) -> Option<Vec<Transaction>> {
+        self.call(|node| node.relevant_transactions(id, filter), |_| false)
+    }
+
+    fn submit_transaction(&self, transaction: &Transaction) -> bool {
+        self.call(|node| node.submit_transaction(transaction).then_some(()), answers_genesis).is_some()
+    }
+
+    fn cumulative_work(&self, id: &BlockId) -> Option<u64> {
+        self.call(|node| node.cumulative_work(id), answers_genesis)
+    }
+
+    fn common_ancestor(&self, known: &[BlockId]) -> Option<(BlockId, u64)> {
+        self.call(|node| node.common_ancestor(known), answers_genesis)
+    }
+
+    fn transaction(&self, id: &TransactionId) -> Option<(BlockId, u64, Transaction)> {
+        self.call(|node| node.transaction(id), answers_genesis)
+    }
+
+    fn is_unspent(&self, coin_id: &CoinId) -> Option<bool> {
+        self.call(|node| node.is_unspent(coin_id), answers_genesis)
+    }
+}
+
+impl Wallet {
+    /// Sync like `try_sync` through a node wrapped in a `ResilientNode`, returning
+    /// `SyncError::NodeUnavailable` if a call failed every retry or met an open circuit.
+    ///
+    /// The node is first asked for the wallet's best block, and the wallet is left untouched if it
+    /// fails to answer, so a node that is down is not taken for a reorg. The blocks applied before a
+    /// later failure are kept, and a later sync continues from there.
+    pub fn sync_resilient<E: NodeEndpoint>(&mut self, node: &ResilientNode<E>) -> Result<SyncReport, SyncError> {
+        node.take_failure();
+        node.best_block_at_height(self.best_block_height);
+        if node.take_failure() {
+            return Err(SyncError::NodeUnavailable { height: self.best_block_height });
+        }
+        let result = self.try_sync(node);
+        if node.take_failure() {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(height = self.best_block_height, "node unavailable, sync stopped");
+            return Err(SyncError::NodeUnavailable { height: self.best_block_height });
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::test_support::*;
+
+    /// A node failing every third call.
+    struct Flaky<'a> {
+        node: &'a MockNode,
+        calls: Cell<u64>,
+    }
+
+    impl Flaky<'_> {
+        fn fails(&self) -> bool {
+            self.calls.set(self.calls.get() + 1);
+            self.calls.get() % 3 == 1
+        }
+    }
+
+    impl NodeEndpoint for Flaky<'_> {
+        fn best_block_at_height(&self, h: u64) -> Option<BlockId> {
+            (!self.fails()).then(|| self.node.best_block_at_height(h)).flatten()
+        }
+
+        fn entire_block(&self, id: &BlockId) -> Option<Block> {
+            (!self.fails()).then(|| self.node.entire_block(id)).flatten()
+        }
+    }
+
+    /// Retries once, and opens the circuit on the first failed call for 50ms.
+    const POLICY: RetryPolicy = RetryPolicy {
+        max_retries: 1,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(1),
+        failure_threshold: 1,
+        cooldown: Duration::from_millis(50),
+    };
+
+    /// A node where Alice mines blocks 1 to 5. Returns the node and its tip.
+    fn five_blocks() -> (MockNode, BlockId) {
+        let mut node = MockNode::new();
+        let mut parent = Block::genesis().id();
+        for _ in 0..5 {
+            parent = node.add_block_as_best(parent, vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+        }
+        (node, parent)
+    }
+
+    /// A wallet of Alice that synced from a node which stopped answering after six calls.
+    /// Returns the node, the tip of its chain, the wallet, and the height the wallet got to.
+    fn down_after_six_calls() -> (ResilientNode<MockNode>, BlockId, Wallet, u64) {
+        let (mut node, tip) = five_blocks();
+        node.set_call_budget(6, OverBudget::Fail);
+        let down = ResilientNode::with_policy(node, POLICY);
+        let mut wallet = wallet_with_alice();
+        let Err(SyncError::NodeUnavailable { height }) = wallet.sync_resilient(&down) else {
+            panic!("the sync should fail");
+        };
+        (down, tip, wallet, height)
+    }
+
+    #[test]
+    fn failures_are_retried() {
+        let (node, tip) = five_blocks();
+        let flaky = ResilientNode::with_policy(Flaky { node: &node, calls: Cell::new(0) }, POLICY);
+        let mut wallet = wallet_with_alice();
+
+        // the end of the chain is not taken for a failure either
+        assert_eq!(wallet.sync_resilient(&flaky).map(|report| report.applied.len()), Ok(5));
+        assert_eq!((wallet.best_hash(), flaky.failed_calls()), (tip, 0));
+    }
+
+    #[test]
+    fn a_node_that_stops_answering_leaves_the_wallet_at_the_last_block_it_served() {
+        let (_, _, wallet, height) = down_after_six_calls();
+
+        assert!(height < 5);
+        assert_eq!(wallet.best_height(), height);
+        assert_eq!(wallet.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn an_open_circuit_stops_calling_the_node() {
+        let (down, _, mut wallet, height) = down_after_six_calls();
+        assert!(down.is_open());
+
+        let calls = down.node().how_many_calls();
+        assert_eq!(wallet.sync_resilient(&down), Err(SyncError::NodeUnavailable { height }));
+        assert_eq!(down.node().how_many_calls(), calls);
+    }
+
+    #[test]
+    fn the_circuit_closes_once_the_node_answers_after_the_cooldown() {
+        let (mut down, tip, mut wallet, _) = down_after_six_calls();
+
+        down.node_mut().clear_call_budget();
+        thread::sleep(Duration::from_millis(60));
+
+        assert!(wallet.sync_resilient(&down).is_ok());
+        assert!(!down.is_open());
+        assert_eq!(wallet.best_hash(), tip);
+    }
+}
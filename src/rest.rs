@@ -0,0 +1,350 @@
+//! An HTTP layer over a wallet shared with the rest of an application.
+//!
+//! `RestApi` serves a wallet and the node it sends through, each behind a mutex so the application
+//! can keep syncing the wallet while requests are answered. Bodies are JSON, using the serde derives
+//! of the chain types:
+//!
+//! - `GET /balance/{address}`: the bones held by an owned address, named as it displays.
+//! - `GET /coins?page=&limit=`: the wallet's coins in coin id order.
+//! - `GET /history?from=&to=&page=&limit=`: the history entries between two heights, oldest first.
+//! - `POST /send`: pay `amount` bones to `recipient` burning `tip`, once per `request_id`, like
+//!   `send_idempotent`.
+//! - `GET /sync/status`: the wallet's best block and whether the node has anything newer.
+//!
+//! Lists are paged by cursor: a page that is not the last carries a `next` token, passed back as
+//! `page` for the following one. A cursor names the last item returned rather than a position, so
+//! coins or entries added meanwhile do not shift the pages. Errors are `{"error": ...}` with a 4xx
+//! status. The module is only compiled with the `rest` feature.
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bonecoin_core::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{HistoryEntry, Wallet};
+
+/// The items a page holds unless the request asks for fewer.
+pub const REST_PAGE_LIMIT: usize = 100;
+/// The most items a page holds, whatever the request asks for.
+pub const REST_MAX_PAGE_LIMIT: usize = 1000;
+
+/// A wallet and its node, served over HTTP.
+pub struct RestApi<Node> {
+    wallet: Arc<Mutex<Wallet>>,
+    node: Arc<Mutex<Node>>,
+}
+
+impl<Node> Clone for RestApi<Node> {
+    fn clone(&self) -> Self {
+        RestApi {
+            wallet: Arc::clone(&self.wallet),
+            node: Arc::clone(&self.node),
+        }
+    }
+}
+
+impl<Node: NodeEndpoint + Send + 'static> RestApi<Node> {
+    /// Serve the wallet, sending its transactions through the node.
+    pub fn new(wallet: Arc<Mutex<Wallet>>, node: Arc<Mutex<Node>>) -> Self {
+        RestApi { wallet, node }
+    }
+
+    /// The routes of the API, to serve or nest in a larger router.
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/balance/{address}", get(balance::<Node>))
+            .route("/coins", get(coins::<Node>))
+            .route("/history", get(history::<Node>))
+            .route("/send", post(send::<Node>))
+            .route("/sync/status", get(sync_status::<Node>))
+            .with_state(self)
+    }
+
+    /// Serve the API on the listener until the server fails.
+    pub async fn serve(self, listener: tokio::net::TcpListener) -> std::io::Result<()> {
+        axum::serve(listener, self.router()).await
+    }
+
+    fn wallet(&self) -> MutexGuard<'_, Wallet> {
+        self.wallet.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn node(&self) -> MutexGuard<'_, Node> {
+        self.node.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+fn error(status: StatusCode, message: impl ToString) -> Response {
+    (status, Json(ErrorBody { error: message.to_string() })).into_response()
+}
+
+/// A page of items and the cursor of the page after it, if any.
+#[derive(Serialize)]
+struct Listing<T> {
+    items: Vec<T>,
+    next: Option<String>,
+}
+
+impl<T> Listing<T> {
+    /// Take up to `limit` items, with the cursor of the last one if more follow.
+    fn of(mut items: impl Iterator<Item = T>, limit: Option<usize>, cursor: impl Fn(&T) -> String) -> Self {
+        let limit = limit.unwrap_or(REST_PAGE_LIMIT).clamp(1, REST_MAX_PAGE_LIMIT);
+        let page: Vec<T> = items.by_ref().take(limit).collect();
+        let next = match items.next() {
+            Some(_) => page.last().map(cursor),
+            None => None,
+        };
+        Listing { items: page, next }
+    }
+}
+
+#[derive(Serialize)]
+struct Balance {
+    address: Address,
+    balance: u64,
+}
+
+async fn balance<Node: NodeEndpoint + Send + 'static>(State(api): State<RestApi<Node>>, Path(address): Path<String>) -> Response {
+    let wallet = api.wallet();
+    let Some(owned) = wallet.addresses.iter().find(|owned| owned.to_string() == address).cloned() else {
+        return error(StatusCode::NOT_FOUND, format!("the wallet does not own {address}"));
+    };
+    match wallet.total_assets_of(owned.clone()) {
+        Ok(balance) => Json(Balance { address: owned, balance }).into_response(),
+        Err(e) => error(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+#[derive(Deserialize)]
+struct CoinsQuery {
+    page: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct CoinItem {
+    coin_id: CoinId,
+    coin: Coin,
+}
+
+async fn coins<Node: NodeEndpoint + Send + 'static>(State(api): State<RestApi<Node>>, Query(query): Query<CoinsQuery>) -> Response {
+    let wallet = api.wallet();
+    let mut coins: Vec<(String, CoinId, Coin)> =
+        wallet.coins.iter().map(|(coin_id, coin)| (coin_id.to_string(), *coin_id, coin.clone())).collect();
+    // coin ids display as fixed width hex, which sorts like the ids
+    coins.sort_by(|a, b| a.0.cmp(&b.0));
+    let after = query.page.unwrap_or_default();
+    let items = coins.into_iter().filter(|(key, _, _)| *key > after).map(|(_, coin_id, coin)| CoinItem { coin_id, coin });
+    Json(Listing::of(items, query.limit, |item| item.coin_id.to_string())).into_response()
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+    page: Option<String>,
+    limit: Option<usize>,
+}
+
+/// The cursor of a history entry: its height and transaction id.
+fn entry_cursor(entry: &HistoryEntry) -> String {
+    format!("{}-{}", entry.height, entry.tx_id)
+}
+
+async fn history<Node: NodeEndpoint + Send + 'static>(State(api): State<RestApi<Node>>, Query(query): Query<HistoryQuery>) -> Response {
+    let wallet = api.wallet();
+    let entries = wallet.history.entries();
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or(u64::MAX);
+    let mut start = entries.partition_point(|entry| entry.height < from);
+    if let Some(cursor) = &query.page {
+        let Some(height) = cursor.split('-').next().and_then(|height| height.parse::<u64>().ok()) else {
+            return error(StatusCode::BAD_REQUEST, format!("{cursor:?} is not a history cursor"));
+        };
+        // resume after the entry, or after its height if a reorg removed it
+        start = match entries.iter().position(|entry| entry_cursor(entry) == *cursor) {
+            Some(position) => position + 1,
+            None => entries.partition_point(|entry| entry.height <= height),
+        }
+        .max(start);
+    }
+    let items = entries[start..].iter().take_while(|entry| entry.height <= to).cloned();
+    Json(Listing::of(items, query.limit, entry_cursor)).into_response()
+}
+
+#[derive(Deserialize)]
+struct SendRequest {
+    request_id: String,
+    recipient: Address,
+    amount: u64,
+    #[serde(default)]
+    tip: u64,
+}
+
+#[derive(Serialize)]
+struct Sent {
+    tx_id: TransactionId,
+    transaction: Transaction,
+}
+
+async fn send<Node: NodeEndpoint + Send + 'static>(State(api): State<RestApi<Node>>, Json(request): Json<SendRequest>) -> Response {
+    let mut wallet = api.wallet();
+    let node = api.node();
+    match wallet.send_idempotent(&request.request_id, request.recipient, request.amount, request.tip, &*node) {
+        Ok(transaction) => Json(Sent { tx_id: transaction.id(), transaction }).into_response(),
+        Err(e) => error(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+#[derive(Serialize)]
+struct SyncStatus {
+    height: u64,
+    block_id: BlockId,
+    /// Whether the wallet's best block is the node's.
+    synced: bool,
+}
+
+async fn sync_status<Node: NodeEndpoint + Send + 'static>(State(api): State<RestApi<Node>>) -> Response {
+    let wallet = api.wallet();
+    let node = api.node();
+    let height = wallet.best_block_height;
+    let block_id = wallet.best_block_hash;
+    let on_chain = node.best_block_at_height(height).unwrap_or(Block::genesis().id()) == block_id;
+    let synced = on_chain && node.best_block_at_height(height + 1).is_none();
+    Json(SyncStatus { height, block_id, synced }).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use crate::test_support::*;
+
+    /// Serves a wallet of Alice, synced to a node whose block 1 pays her 50, 100 and 150 bones, on
+    /// a free local port. Returns the address and the node.
+    fn serving() -> (SocketAddr, Arc<Mutex<MockNode>>) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+        node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 50), (Address::Alice, 100), (Address::Alice, 150)])]);
+        wallet.sync(&node);
+
+        let node = Arc::new(Mutex::new(node));
+        let api = RestApi::new(Arc::new(Mutex::new(wallet)), Arc::clone(&node));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        listener.set_nonblocking(true).unwrap();
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+            runtime.block_on(async { api.serve(tokio::net::TcpListener::from_std(listener).unwrap()).await })
+        });
+        (addr, node)
+    }
+
+    /// Sends one request and returns the status and body of the response.
+    fn rest_request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    #[test]
+    fn balances_are_served_for_own_addresses() {
+        let (addr, _) = serving();
+
+        let (status, body) = rest_request(addr, "GET", "/balance/Alice", "");
+        assert_eq!(status, 200);
+        assert!(body.contains("\"balance\":300"), "{body}");
+        assert_eq!(rest_request(addr, "GET", "/balance/Bob", "").0, 404);
+    }
+
+    #[test]
+    fn coins_are_served_in_pages() {
+        let (addr, _) = serving();
+
+        let (status, first) = rest_request(addr, "GET", "/coins?limit=2", "");
+        assert_eq!(status, 200);
+        assert_eq!(first.matches("\"coin_id\"").count(), 2);
+
+        // the second page resumes after the cursor of the first
+        let cursor = first.split("\"next\":\"").nth(1).unwrap().split('"').next().unwrap().to_string();
+        let (_, second) = rest_request(addr, "GET", &format!("/coins?limit=2&page={cursor}"), "");
+        assert_eq!(second.matches("\"coin_id\"").count(), 1);
+        assert!(second.contains("\"next\":null"), "{second}");
+    }
+
+    #[test]
+    fn history_is_served_by_height() {
+        let (addr, _) = serving();
+
+        let (_, history) = rest_request(addr, "GET", "/history?from=1&to=1", "");
+        assert_eq!(history.matches("\"tx_id\"").count(), 1);
+        assert!(history.contains("\"direction\":\"Incoming\""), "{history}");
+        let (_, later) = rest_request(addr, "GET", "/history?from=2", "");
+        assert!(later.contains("\"items\":[]"), "{later}");
+        assert_eq!(rest_request(addr, "GET", "/history?page=nope", "").0, 400);
+    }
+
+    #[test]
+    fn the_sync_status_is_served() {
+        let (addr, _) = serving();
+
+        let (status, body) = rest_request(addr, "GET", "/sync/status", "");
+        assert_eq!(status, 200);
+        assert!(body.contains("\"height\":1") && body.contains("\"synced\":true"), "{body}");
+    }
+
+    #[test]
+    fn sends_are_idempotent_per_request_id() {
+        let (addr, node) = serving();
+
+        let send = r#"{"request_id":"payout-1","recipient":"Bob","amount":60,"tip":1}"#;
+        let (status, sent) = rest_request(addr, "POST", "/send", send);
+        assert_eq!(status, 200, "{sent}");
+        assert_eq!(rest_request(addr, "POST", "/send", send).1, sent);
+        assert_eq!(node.lock().unwrap().submitted_transactions().len(), 2);
+    }
+
+    #[test]
+    fn later_sends_do_not_spend_the_coins_of_pending_ones() {
+        let (addr, node) = serving();
+
+        for request_id in ["payout-1", "payout-2"] {
+            let send = format!(r#"{{"request_id":"{request_id}","recipient":"Bob","amount":60,"tip":1}}"#);
+            assert_eq!(rest_request(addr, "POST", "/send", &send).0, 200);
+        }
+
+        let submitted = node.lock().unwrap().submitted_transactions();
+        let first: Vec<CoinId> = submitted[0].iter_input_coin_ids().collect();
+        assert!(submitted[1].iter_input_coin_ids().all(|coin_id| !first.contains(&coin_id)));
+    }
+
+    #[test]
+    fn failed_sends_are_reported() {
+        let (addr, _) = serving();
+
+        let (status, body) = rest_request(addr, "POST", "/send", r#"{"request_id":"payout-2","recipient":"Bob","amount":1000}"#);
+        assert_eq!(status, 400);
+        assert!(body.contains("\"error\""), "{body}");
+    }
+}
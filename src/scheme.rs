@@ -0,0 +1,117 @@
+//! Address schemes decide which coins the wallet owns and how it signs for them.
+//!
+//! Coins and transactions are defined in core in terms of `Address`, so a scheme does not change
+//! the address type itself. It changes the ownership model on top of it: sync only tracks the
+//! coins the scheme says the wallet owns, and every input the wallet authors is signed by the
+//! scheme. The wallet uses `KeyScheme` unless it is created with `Wallet::with_scheme`.
+
+use std::collections::HashSet;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// An ownership and signing model for the addresses a wallet holds.
+pub trait AddressScheme: Send + Sync {
+    /// Whether a wallet holding `addresses` can spend coins owned by `owner`.
+    fn owns(&self, addresses: &HashSet<Address>, owner: &Address) -> bool;
+
+    /// The signature a wallet holding `addresses` puts on an input spending a coin owned by `owner`.
+    fn sign(&self, addresses: &HashSet<Address>, owner: &Address) -> Signature;
+}
+
+/// Every address is a key: the wallet owns exactly the coins paid to its addresses.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct KeyScheme;
+
+impl AddressScheme for KeyScheme {
+    fn owns(&self, addresses: &HashSet<Address>, owner: &Address) -> bool {
+        addresses.contains(owner)
+    }
+
+    fn sign(&self, _addresses: &HashSet<Address>, owner: &Address) -> Signature {
+        Signature::Valid(owner.clone())
+    }
+}
+
+/// Script-like ownership: besides the coins of its own addresses, the wallet owns the multisig
+/// coins whose threshold its addresses meet without help from anyone else.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct MultisigScheme;
+
+impl AddressScheme for MultisigScheme {
+    fn owns(&self, addresses: &HashSet<Address>, owner: &Address) -> bool {
+        owner.is_satisfied_by(&self.sign(addresses, owner))
+    }
+
+    fn sign(&self, addresses: &HashSet<Address>, owner: &Address) -> Signature {
+        match owner {
+            Address::Multisig { signers, .. } => {
+                Signature::Multi(signers.iter().filter(|signer| addresses.contains(signer)).cloned().collect())
+            }
+            owner if addresses.contains(owner) => Signature::Valid(owner.clone()),
+            _ => Signature::Invalid,
+        }
+    }
+}
+
+/// Watch-only ownership: the wallet owns what the wrapped scheme says it owns, but signs nothing,
+/// so every transaction it builds needs its signatures from elsewhere.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct WatchOnly<S>(pub S);
+
+impl<S: AddressScheme> AddressScheme for WatchOnly<S> {
+    fn owns(&self, addresses: &HashSet<Address>, owner: &Address) -> bool {
+        self.0.owns(addresses, owner)
+    }
+
+    fn sign(&self, _addresses: &HashSet<Address>, _owner: &Address) -> Signature {
+        Signature::Invalid
+    }
+}
+
+impl Wallet {
+    /// Whether the wallet's address scheme lets it spend coins owned by `owner`.
+    pub(crate) fn owns(&self, owner: &Address) -> bool {
+        self.scheme.owns(&self.addresses, owner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A node whose only block pays 50 bones to a 2-of-3 multisig of Alice, Bob, and Charlie and
+    /// 10 bones to Alice, and a wallet of `addresses` under `scheme` synced to it.
+    fn synced_wallet<S: AddressScheme + 'static>(addresses: Vec<Address>, scheme: S) -> Wallet {
+        let shared = Address::multisig(2, [Address::Alice, Address::Bob, Address::Charlie]);
+        let mut node = MockNode::new();
+        node.add_block_as_best(Block::genesis().id(), vec![mint([(shared, 50), (Address::Alice, 10)])]);
+        let mut wallet = Wallet::with_scheme(addresses.into_iter(), scheme);
+        wallet.sync(&node);
+        wallet
+    }
+
+    #[test]
+    fn the_key_scheme_owns_only_the_keys_themselves() {
+        let wallet = synced_wallet(vec![Address::Alice, Address::Bob], KeyScheme);
+        assert_eq!(wallet.net_worth(), 10);
+    }
+
+    #[test]
+    fn the_multisig_scheme_owns_multisigs_its_keys_satisfy() {
+        assert_eq!(synced_wallet(vec![Address::Alice, Address::Bob], MultisigScheme).net_worth(), 60);
+        assert_eq!(synced_wallet(vec![Address::Alice], MultisigScheme).net_worth(), 10);
+    }
+
+    #[test]
+    fn the_multisig_scheme_signs_every_input_it_spends() {
+        let wallet = synced_wallet(vec![Address::Alice, Address::Bob], MultisigScheme);
+        let tx = wallet.create_automatic_transaction(Address::Eve, 55, 0).unwrap();
+        assert_eq!(tx.inputs.len(), 2);
+        for input in &tx.inputs {
+            let owner = wallet.coin_details(&input.coin_id).unwrap().owner;
+            assert!(owner.is_satisfied_by(&input.signature), "{owner} not satisfied by {:?}", input.signature);
+        }
+    }
+}
@@ -0,0 +1,646 @@
+//! Coin selection: which of the wallet's coins fund a payment.
+//!
+//! The strategy is a wallet setting used by every automatic transaction. Candidates are put in a
+//! canonical order first, so the same wallet state always yields the same transaction unless the
+//! strategy is deliberately randomized without a seed. On top of the strategy, coin
+//! control restricts the candidates to a set of addresses or coins, so users can keep coins of
+//! different origin apart, and the selection constraints bound how many coins are spent and how
+//! many change coins are returned. `InsufficientFunds` is always reported against the candidates
+//! the strategy was allowed to use.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::Ordering;
+
+use bonecoin_core::rng::SplitMix64;
+use bonecoin_core::*;
+
+use crate::{DustPolicy, Wallet};
+
+/// How automatic transactions pick their inputs.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum CoinSelectionStrategy {
+    /// Take coins in no particular order until the payment is covered.
+    #[default]
+    FirstFit,
+    /// Never combine coins of different wallet addresses in one transaction, since that reveals they
+    /// belong together. Within an address, prefer the single coin closest to the amount, then as few
+    /// coins as possible. Fails with `InsufficientFunds` if no single address can cover the payment.
+    PrivacyPreserving,
+    /// Search for a set of coins worth exactly the amount, up to `tolerance` bones more, which are
+    /// added to the tip so the transaction needs no change output. Falls back to `FirstFit` when
+    /// there is no such set.
+    BranchAndBound { tolerance: u64 },
+    /// Take coins in random order until the payment is covered, so payments reveal less about the
+    /// wallet's coins. With a seed, the order only depends on the seed and the wallet's coins, which
+    /// makes the selection reproducible; without one, it differs every time.
+    Random { seed: Option<u64> },
+}
+
+impl CoinSelectionStrategy {
+    /// Leftover value up to this amount is added to the tip rather than returned as change.
+    pub fn change_tolerance(&self) -> u64 {
+        match self {
+            CoinSelectionStrategy::BranchAndBound { tolerance } => *tolerance,
+            _ => 0,
+        }
+    }
+}
+
+/// Limits on the coins automatic transactions and withdrawal batches spend and the change they
+/// return, on top of the selection strategy. The default sets none.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct SelectionConstraints {
+    /// Spend at least this many coins, adding the smallest candidates the strategy left out, so
+    /// small coins are merged as the wallet pays. `PrivacyPreserving` only adds coins of the address
+    /// it spends from. Fewer are spent when there are not as many candidates, or `max_inputs` is lower.
+    pub min_inputs: usize,
+    /// Spend at most this many coins, for signers that cannot handle more. When the strategy picks
+    /// more, the fewest coins covering the payment are spent instead.
+    pub max_inputs: Option<usize>,
+    /// Return at most this many change coins, failing with `TooManyChangeOutputs` otherwise. `Some(0)`
+    /// together with `BranchAndBound` only pays with coins matching the amount.
+    pub max_change_outputs: Option<usize>,
+}
+
+/// What an automatic transaction would look like, for showing the user before committing to it.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SelectionPlan {
+    /// The coins that would be spent, with their values.
+    pub inputs: Vec<(CoinId, u64)>,
+    /// The amount paid to the recipient, including dust the dust policy adds to the payment.
+    pub payment_amount: u64,
+    /// The change coin returned to the wallet, if any.
+    pub change: Option<Coin>,
+    /// The leftover bones if they are below the dust threshold, and what the dust policy does with them.
+    pub dust: Option<(u64, DustPolicy)>,
+    /// The bones actually burned, which exceeds the requested tip when leftover value is added to it.
+    pub effective_tip: u64,
+    /// The number of outputs the transaction would have.
+    pub output_count: usize,
+}
+
+/// The number of branches the branch-and-bound search explores before giving up.
+const BNB_MAX_TRIES: usize = 100_000;
+
+impl Wallet {
+    /// Set the strategy used to select the inputs of automatic transactions.
+    pub fn set_coin_selection(&mut self, strategy: CoinSelectionStrategy) {
+        self.config.selection = strategy;
+    }
+
+    /// The strategy used to select the inputs of automatic transactions.
+    pub fn coin_selection(&self) -> CoinSelectionStrategy {
+        self.config.selection
+    }
+
+    /// Set the limits on the coins automatic transactions spend and the change they return.
+    pub fn set_selection_constraints(&mut self, constraints: SelectionConstraints) {
+        self.config.selection_constraints = constraints;
+    }
+
+    /// The limits on the coins automatic transactions spend and the change they return.
+    pub fn selection_constraints(&self) -> SelectionConstraints {
+        self.config.selection_constraints
+    }
+
+    /// Report what `create_automatic_transaction` would do with these arguments, without affecting
+    /// the wallet. Fails exactly when `create_automatic_transaction` would.
+    pub fn plan_automatic_transaction(
+        &self,
+        recipient: Address,
+        payment_amount: u64,
+        burn_aka_tip: u64,
+    ) -> WalletResult<SelectionPlan> {
+        // building the transaction would move the round-robin change address along
+        let cursor = self.change_cursor.load(Ordering::Relaxed);
+        let transaction = self.create_automatic_transaction(recipient, payment_amount, burn_aka_tip);
+        self.change_cursor.store(cursor, Ordering::Relaxed);
+        let transaction = transaction?;
+
+        let inputs: Vec<(CoinId, u64)> = transaction
+            .iter_input_coin_ids()
+            .map(|coin_id| (coin_id, self.coins[&coin_id].value))
+            .collect();
+        let consumed: u64 = inputs.iter().map(|(_, value)| value).sum();
+        let produced: u64 = transaction.outputs.iter().map(|coin| coin.value).sum();
+        // leftover within the selection's tolerance is part of the tip whatever its size
+        let leftover = consumed - payment_amount - burn_aka_tip;
+        let dust = (leftover > self.config.selection.change_tolerance() && self.is_dust(leftover, None))
+            .then_some((leftover, self.config.change_policy.dust_policy));
+        Ok(SelectionPlan {
+            inputs,
+            payment_amount: transaction.outputs[0].value,
+            change: transaction.outputs.get(1).cloned(),
+            dust,
+            effective_tip: consumed - produced,
+            output_count: transaction.outputs.len(),
+        })
+    }
+
+    /// Like `create_automatic_transaction`, but only spending coins owned by the given addresses,
+    /// which must all belong to the wallet.
+    pub fn create_automatic_transaction_from(
+        &self,
+        addresses: &[Address],
+        recipient: Address,
+        payment_amount: u64,
+        burn_aka_tip: u64,
+    ) -> WalletResult<Transaction> {
+        if let Some(foreign) = addresses.iter().find(|address| !self.addresses.contains(address)) {
+            return Err(WalletError::ForeignAddress(foreign.clone()));
+        }
+        let transaction = self.build_restricted_transaction(recipient, payment_amount, burn_aka_tip, &|_, coin| {
+            addresses.contains(&coin.owner)
+        }, None, false)?;
+        self.check_policy(&transaction)?;
+        Ok(transaction)
+    }
+
+    /// Like `create_automatic_transaction`, but only spending coins from `allowed_coins`.
+    pub fn create_automatic_transaction_using(
+        &self,
+        allowed_coins: &HashSet<CoinId>,
+        recipient: Address,
+        payment_amount: u64,
+        burn_aka_tip: u64,
+    ) -> WalletResult<Transaction> {
+        let transaction = self.build_restricted_transaction(recipient, payment_amount, burn_aka_tip, &|coin_id, _| {
+            allowed_coins.contains(coin_id)
+        }, None, false)?;
+        self.check_policy(&transaction)?;
+        Ok(transaction)
+    }
+
+    /// Pick coins worth at least `target` from the spendable candidates, according to the wallet's strategy.
+    /// The result only depends on the candidates, not on the order they are passed in.
+    pub(crate) fn select_coins(&self, mut candidates: Vec<(CoinId, Coin)>, target: u64) -> WalletResult<Vec<(CoinId, Coin)>> {
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::debug_span!("select_coins", strategy = ?self.config.selection, candidates = candidates.len(), target).entered();
+        candidates.sort_by_key(|(coin_id, _)| *coin_id);
+        let pools = pools(self.config.selection, &candidates);
+        // what the strategy could spend at most, reported when the target is out of reach
+        let mut available = total(&candidates);
+        let selected = match self.config.selection {
+            CoinSelectionStrategy::FirstFit => accumulate(candidates, target),
+            CoinSelectionStrategy::PrivacyPreserving => {
+                available = pools.iter().map(|coins| total(coins)).max().unwrap_or(0);
+                pools
+                    .iter()
+                    .filter_map(|coins| single_address_selection(coins.clone(), target))
+                    .min_by_key(|selection| (selection.len(), total(selection)))
+            }
+            CoinSelectionStrategy::BranchAndBound { tolerance } => {
+                branch_and_bound(&candidates, target, tolerance).or_else(|| accumulate(candidates, target))
+            }
+            CoinSelectionStrategy::Random { seed } => {
+                let mut rng = SplitMix64::new(seed.unwrap_or_else(|| RandomState::new().build_hasher().finish()));
+                // Fisher-Yates shuffle
+                for i in (1..candidates.len()).rev() {
+                    let j = (rng.next_u64() % (i as u64 + 1)) as usize;
+                    candidates.swap(i, j);
+                }
+                accumulate(candidates, target)
+            }
+        };
+
+        let constraints = self.config.selection_constraints;
+        let selected = match (selected, constraints.max_inputs) {
+            // the strategy's pick spends too many coins, but the largest coins may cover the target with fewer
+            (Some(selected), Some(max)) if selected.len() > max => {
+                let largest: Vec<Vec<(CoinId, Coin)>> = pools.iter().map(|coins| largest(coins, max)).collect();
+                available = largest.iter().map(|coins| total(coins)).max().unwrap_or(0);
+                largest
+                    .into_iter()
+                    .filter_map(|coins| accumulate(coins, target))
+                    .min_by_key(|selection| (selection.len(), total(selection)))
+            }
+            (selected, _) => selected,
+        };
+        let min_inputs = constraints.min_inputs.min(constraints.max_inputs.unwrap_or(usize::MAX));
+        let selected = selected.map(|selected| pad(selected, &pools, min_inputs));
+        #[cfg(feature = "tracing")]
+        match &selected {
+            Some(selected) => tracing::debug!(selected = selected.len(), value = total(selected), "coins selected"),
+            None => tracing::debug!(available, "target out of reach"),
+        }
+        selected.ok_or(WalletError::InsufficientFunds { needed: target, available })
+    }
+
+    /// Fail with `TooManyChangeOutputs` if a transaction returning `change` coins breaks the selection constraints.
+    pub(crate) fn check_change_outputs(&self, change: usize) -> WalletResult<()> {
+        match self.config.selection_constraints.max_change_outputs {
+            Some(allowed) if change > allowed => Err(WalletError::TooManyChangeOutputs { allowed }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The candidates one selection may draw from: each address's for `PrivacyPreserving`, which never
+/// combines coins of different addresses, and all of them together for the other strategies.
+fn pools(strategy: CoinSelectionStrategy, candidates: &[(CoinId, Coin)]) -> Vec<Vec<(CoinId, Coin)>> {
+    if strategy != CoinSelectionStrategy::PrivacyPreserving {
+        return vec![candidates.to_vec()];
+    }
+    let mut by_owner: BTreeMap<Address, Vec<(CoinId, Coin)>> = BTreeMap::new();
+    for (coin_id, coin) in candidates {
+        by_owner.entry(coin.owner.clone()).or_default().push((*coin_id, coin.clone()));
+    }
+    by_owner.into_values().collect()
+}
+
+/// The `max` most valuable coins, largest first.
+fn largest(coins: &[(CoinId, Coin)], max: usize) -> Vec<(CoinId, Coin)> {
+    let mut coins = coins.to_vec();
+    coins.sort_by_key(|(coin_id, coin)| (std::cmp::Reverse(coin.value), *coin_id));
+    coins.truncate(max);
+    coins
+}
+
+/// Add the smallest coins of the selection's pool it does not spend yet, until it spends `min` coins.
+fn pad(mut selected: Vec<(CoinId, Coin)>, pools: &[Vec<(CoinId, Coin)>], min: usize) -> Vec<(CoinId, Coin)> {
+    if selected.len() >= min {
+        return selected;
+    }
+    let spent: HashSet<CoinId> = selected.iter().map(|(coin_id, _)| *coin_id).collect();
+    let Some(pool) = pools.iter().find(|coins| coins.iter().any(|(coin_id, _)| spent.contains(coin_id))) else {
+        return selected;
+    };
+    let mut rest: Vec<&(CoinId, Coin)> = pool.iter().filter(|(coin_id, _)| !spent.contains(coin_id)).collect();
+    rest.sort_by_key(|(coin_id, coin)| (coin.value, *coin_id));
+    selected.extend(rest.into_iter().take(min - selected.len()).cloned());
+    selected
+}
+
+/// Take coins in the given order until they cover the target.
+fn accumulate(candidates: Vec<(CoinId, Coin)>, target: u64) -> Option<Vec<(CoinId, Coin)>> {
+    let mut selected = Vec::new();
+    let mut selected_value = 0;
+    for (coin_id, coin) in candidates {
+        if selected_value >= target {
+            break;
+        }
+        selected_value += coin.value;
+        selected.push((coin_id, coin));
+    }
+    (selected_value >= target).then_some(selected)
+}
+
+/// The smallest single coin covering the target, or else the largest coins until the target is covered.
+fn single_address_selection(mut coins: Vec<(CoinId, Coin)>, target: u64) -> Option<Vec<(CoinId, Coin)>> {
+    coins.sort_by_key(|(coin_id, coin)| (coin.value, *coin_id));
+    if let Some(index) = coins.iter().position(|(_, coin)| coin.value >= target) {
+        return Some(vec![coins.swap_remove(index)]);
+    }
+    coins.reverse();
+    accumulate(coins, target)
+}
+
+/// Find the subset of candidates worth between `target` and `target + tolerance` with the least excess.
+///
+/// Explores include/exclude decisions over the coins from largest to smallest, cutting branches
+/// that overshoot the window or can no longer reach the target. Stops at an exact match or after
+/// `BNB_MAX_TRIES` branches.
+fn branch_and_bound(candidates: &[(CoinId, Coin)], target: u64, tolerance: u64) -> Option<Vec<(CoinId, Coin)>> {
+    let mut coins: Vec<&(CoinId, Coin)> = candidates.iter().collect();
+    coins.sort_by_key(|(coin_id, coin)| (std::cmp::Reverse(coin.value), *coin_id));
+    // remaining[i] is the value of coins[i..]
+    let mut remaining = vec![0u64; coins.len() + 1];
+    for i in (0..coins.len()).rev() {
+        remaining[i] = remaining[i + 1] + coins[i].1.value;
+    }
+    let upper = target.saturating_add(tolerance);
+
+    let mut best: Option<(u64, Vec<usize>)> = None;
+    let mut included = Vec::new();
+    let mut tries = 0;
+    // each stack frame is (next coin to decide, value selected so far, number of included coins)
+    let mut stack = vec![(0usize, 0u64, 0usize)];
+    while let Some((index, value, depth)) = stack.pop() {
+        tries += 1;
+        if tries > BNB_MAX_TRIES || best.as_ref().is_some_and(|(excess, _)| *excess == 0) {
+            break;
+        }
+        included.truncate(depth);
+        if value > upper || value + remaining[index] < target {
+            continue;
+        }
+        if value >= target {
+            if best.as_ref().is_none_or(|(excess, _)| value - target < *excess) {
+                best = Some((value - target, included.clone()));
+            }
+            continue;
+        }
+        if index == coins.len() {
+            continue;
+        }
+        // explore including the coin first, so large coins are tried before many small ones
+        stack.push((index + 1, value, depth));
+        included.push(index);
+        stack.push((index + 1, value + coins[index].1.value, depth + 1));
+    }
+
+    best.map(|(_, indices)| indices.into_iter().map(|i| coins[i].clone()).collect())
+}
+
+fn total(coins: &[(CoinId, Coin)]) -> u64 {
+    coins.iter().map(|(_, coin)| coin.value).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    fn coins_of(wallet: &Wallet, address: Address) -> HashSet<CoinId> {
+        wallet.all_coins_of(address).unwrap().into_iter().map(|(id, _)| id).collect()
+    }
+
+    #[test]
+    fn selection_from_addresses_spends_only_their_coins() {
+        let (_, wallet) = make_one_block_blockchain();
+        let bob_coins = coins_of(&wallet, Address::Bob);
+        let tx = wallet.create_automatic_transaction_from(&[Address::Bob], Address::Charlie, 100, 0).unwrap();
+        assert!(tx.iter_input_coin_ids().all(|id| bob_coins.contains(&id)));
+    }
+
+    #[test]
+    fn selection_from_addresses_counts_only_their_funds() {
+        let (_, wallet) = make_one_block_blockchain();
+        assert_eq!(
+            wallet.create_automatic_transaction_from(&[Address::Bob], Address::Charlie, 121, 0),
+            Err(WalletError::InsufficientFunds {
+                needed: 121,
+                available: 120,
+            })
+        );
+        // the unrestricted wallet could afford it
+        assert!(wallet.create_automatic_transaction(Address::Charlie, 121, 0).is_ok());
+    }
+
+    #[test]
+    fn selection_from_foreign_addresses_is_refused() {
+        let (_, wallet) = make_one_block_blockchain();
+        assert_eq!(
+            wallet.create_automatic_transaction_from(&[Address::Eve], Address::Charlie, 1, 0),
+            Err(WalletError::ForeignAddress(Address::Eve))
+        );
+    }
+
+    #[test]
+    fn selection_using_chosen_coins_spends_only_those() {
+        let (_, wallet) = make_one_block_blockchain();
+        let bob_coins = coins_of(&wallet, Address::Bob);
+        let tx = wallet.create_automatic_transaction_using(&bob_coins, Address::Charlie, 110, 10).unwrap();
+        assert_eq!(tx.iter_input_coin_ids().collect::<HashSet<_>>(), bob_coins);
+        assert_eq!(
+            wallet.create_automatic_transaction_using(&HashSet::new(), Address::Charlie, 1, 0),
+            Err(WalletError::InsufficientFunds {
+                needed: 1,
+                available: 0,
+            })
+        );
+    }
+
+    /// A wallet of Alice and Bob holding coins of 30, 30, and 200 bones for Alice and 45 and 80 for
+    /// Bob, selecting privately. Returns the wallet and the minting transaction.
+    fn private_wallet() -> (Wallet, Transaction) {
+        let mut node = MockNode::new();
+        let minted = mint([
+            (Address::Alice, 30),
+            (Address::Alice, 30),
+            (Address::Bob, 45),
+            (Address::Bob, 80),
+            (Address::Alice, 200),
+        ]);
+        node.add_block_as_best(Block::genesis().id(), vec![minted.clone()]);
+        let mut wallet = wallet_with_alice_and_bob();
+        wallet.sync(&node);
+        wallet.set_coin_selection(CoinSelectionStrategy::PrivacyPreserving);
+        (wallet, minted)
+    }
+
+    #[test]
+    fn private_selection_prefers_the_single_coin_closest_to_the_amount() {
+        let (wallet, minted) = private_wallet();
+        let inputs_of = |tx: Transaction| tx.iter_input_coin_ids().collect::<Vec<_>>();
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 40, 2).unwrap();
+        assert_eq!(inputs_of(tx), vec![minted.coin_id(1, 2)]);
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 81, 0).unwrap();
+        assert_eq!(inputs_of(tx), vec![minted.coin_id(1, 4)]);
+    }
+
+    #[test]
+    fn private_selection_draws_on_a_single_address() {
+        let (wallet, _) = private_wallet();
+        for amount in [1, 50, 100, 125, 200, 250, 260] {
+            let tx = wallet.create_automatic_transaction(Address::Charlie, amount, 0).unwrap();
+            let owners: HashSet<Address> = tx.iter_input_coin_ids().map(|id| wallet.coins[&id].owner.clone()).collect();
+            assert_eq!(owners.len(), 1, "amount {amount}");
+        }
+    }
+
+    #[test]
+    fn private_selection_refuses_payments_that_need_mixing() {
+        let (mut wallet, _) = private_wallet();
+        // 261 bones are only available by combining alice's and bob's coins
+        assert_eq!(
+            wallet.create_automatic_transaction(Address::Charlie, 261, 0),
+            Err(WalletError::InsufficientFunds {
+                needed: 261,
+                available: 260,
+            })
+        );
+        wallet.set_coin_selection(CoinSelectionStrategy::FirstFit);
+        assert!(wallet.create_automatic_transaction(Address::Charlie, 261, 0).is_ok());
+    }
+
+    /// A wallet of Alice holding coins of 50, 30, 20, 7, and 3 bones, selecting by branch and bound
+    /// within `tolerance`.
+    fn branch_and_bound_wallet(tolerance: u64) -> Wallet {
+        let mut node = MockNode::new();
+        node.add_block_as_best(Block::genesis().id(), vec![mint([50, 30, 20, 7, 3].map(|value| (Address::Alice, value)))]);
+        let mut wallet = wallet_with_alice();
+        wallet.sync(&node);
+        wallet.set_coin_selection(CoinSelectionStrategy::BranchAndBound { tolerance });
+        wallet
+    }
+
+    fn input_values(wallet: &Wallet, tx: &Transaction) -> Vec<u64> {
+        let mut values: Vec<u64> = tx.iter_input_coin_ids().map(|id| wallet.coins[&id].value).collect();
+        values.sort();
+        values
+    }
+
+    #[test]
+    fn branch_and_bound_finds_an_exact_match() {
+        let wallet = branch_and_bound_wallet(2);
+        // 30 + 7 pays 36 and the tip of 1
+        let tx = wallet.create_automatic_transaction(Address::Bob, 36, 1).unwrap();
+        assert_eq!(input_values(&wallet, &tx), vec![7, 30]);
+        assert_eq!(tx.outputs.len(), 1);
+    }
+
+    #[test]
+    fn branch_and_bound_tips_the_excess_within_the_tolerance() {
+        let wallet = branch_and_bound_wallet(2);
+        // nothing sums to 35, but 37 is within the tolerance and the extra 2 bones go to the tip
+        let tx = wallet.create_automatic_transaction(Address::Bob, 35, 0).unwrap();
+        assert_eq!(input_values(&wallet, &tx), vec![7, 30]);
+        assert_eq!(tx.outputs.len(), 1);
+    }
+
+    #[test]
+    fn branch_and_bound_falls_back_to_change() {
+        let wallet = branch_and_bound_wallet(0);
+        let tx = wallet.create_automatic_transaction(Address::Bob, 12, 0).unwrap();
+        assert_eq!(tx.outputs.len(), 2);
+        assert_eq!(
+            wallet.create_automatic_transaction(Address::Bob, 111, 0),
+            Err(WalletError::InsufficientFunds {
+                needed: 111,
+                available: 110,
+            })
+        );
+    }
+
+    #[test]
+    fn plans_list_the_inputs_and_change_of_the_transaction() {
+        let (_, wallet) = make_one_block_blockchain();
+        let plan = wallet.plan_automatic_transaction(Address::Charlie, 90, 3).unwrap();
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 90, 3).unwrap();
+
+        assert_eq!(plan.inputs.iter().map(|(id, _)| *id).collect::<Vec<_>>(), tx.iter_input_coin_ids().collect::<Vec<_>>());
+        assert_eq!(plan.change.as_ref(), tx.outputs.get(1));
+        assert_eq!(plan.output_count, tx.outputs.len());
+        let consumed: u64 = plan.inputs.iter().map(|(_, value)| value).sum();
+        assert_eq!(consumed, 90 + plan.effective_tip + plan.change.map_or(0, |coin| coin.value));
+        assert_eq!(plan.effective_tip, 3);
+    }
+
+    #[test]
+    fn planning_does_not_move_the_round_robin_change_address() {
+        let (_, mut wallet) = make_one_block_blockchain();
+        wallet
+            .set_change_policy(ChangePolicy {
+                destination: ChangeDestination::RoundRobin,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let plan = wallet.plan_automatic_transaction(Address::Charlie, 90, 3).unwrap();
+        assert_eq!(wallet.plan_automatic_transaction(Address::Charlie, 90, 3).unwrap().change, plan.change);
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 90, 3).unwrap();
+        assert_eq!(plan.change.as_ref(), tx.outputs.get(1));
+    }
+
+    #[test]
+    fn plans_fail_as_the_transaction_would() {
+        let (_, wallet) = make_one_block_blockchain();
+        assert_eq!(
+            wallet.plan_automatic_transaction(Address::Charlie, 1_000, 0),
+            Err(WalletError::InsufficientFunds {
+                needed: 1_000,
+                available: 235,
+            })
+        );
+    }
+
+    #[test]
+    fn transaction_construction_is_reproducible() {
+        let (node, wallet) = make_one_block_blockchain();
+        // a second wallet with the same state, whose hash maps iterate in a different order
+        let mut twin = Wallet::new(vec![Address::Bob, Address::Alice].into_iter());
+        twin.sync(&node);
+
+        for strategy in [
+            CoinSelectionStrategy::FirstFit,
+            CoinSelectionStrategy::PrivacyPreserving,
+            CoinSelectionStrategy::BranchAndBound { tolerance: 5 },
+            CoinSelectionStrategy::Random { seed: Some(7) },
+        ] {
+            let mut wallet = Wallet::import_state(&wallet.export_state()).unwrap();
+            wallet.set_coin_selection(strategy);
+            twin.set_coin_selection(strategy);
+            for amount in [10, 101, 200] {
+                assert_eq!(
+                    wallet.create_automatic_transaction(Address::Charlie, amount, 1),
+                    twin.create_automatic_transaction(Address::Charlie, amount, 1),
+                    "{strategy:?} paying {amount}"
+                );
+            }
+        }
+    }
+
+    /// A wallet of Alice and Bob holding eight 5 bone coins and a 40 bone coin of Alice and a 30
+    /// bone coin of Bob, spending at most two of them at once.
+    fn at_most_two_inputs() -> Wallet {
+        let mut node = MockNode::new();
+        let mut outputs = vec![(Address::Alice, 5); 8];
+        outputs.extend([(Address::Alice, 40), (Address::Bob, 30)]);
+        node.add_block_as_best(Block::genesis().id(), vec![mint(outputs)]);
+        let constraints = SelectionConstraints { max_inputs: Some(2), ..Default::default() };
+        let mut wallet = Wallet::builder().addresses([Address::Alice, Address::Bob]).selection_constraints(constraints).build().unwrap();
+        wallet.sync(&node);
+        wallet
+    }
+
+    #[test]
+    fn a_maximum_bounds_the_inputs() {
+        let wallet = at_most_two_inputs();
+
+        // only the two largest coins cover 65 bones, and nothing covers 71
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 65, 0).unwrap();
+        assert_eq!(input_values(&wallet, &tx), [30, 40]);
+        assert_eq!(
+            wallet.create_automatic_transaction(Address::Charlie, 71, 0),
+            Err(WalletError::InsufficientFunds { needed: 71, available: 70 })
+        );
+    }
+
+    #[test]
+    fn a_minimum_pads_the_exact_match_with_the_smallest_coins() {
+        let mut wallet = at_most_two_inputs();
+        wallet.set_coin_selection(CoinSelectionStrategy::BranchAndBound { tolerance: 0 });
+        wallet.set_selection_constraints(SelectionConstraints { min_inputs: 3, ..Default::default() });
+
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 40, 0).unwrap();
+        assert_eq!(input_values(&wallet, &tx), [5, 5, 40]);
+        assert_eq!(tx.outputs[1].value, 10);
+        assert_eq!(wallet.plan_automatic_transaction(Address::Charlie, 40, 0).unwrap().inputs.len(), 3);
+    }
+
+    #[test]
+    fn a_minimum_never_pads_with_coins_of_another_address_when_preserving_privacy() {
+        let mut wallet = at_most_two_inputs();
+        wallet.set_coin_selection(CoinSelectionStrategy::PrivacyPreserving);
+        wallet.set_selection_constraints(SelectionConstraints { min_inputs: 3, ..Default::default() });
+
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 20, 0).unwrap();
+        assert_eq!(input_values(&wallet, &tx), [30]);
+    }
+
+    #[test]
+    fn without_change_only_exact_matches_are_paid() {
+        let mut wallet = at_most_two_inputs();
+        wallet.set_coin_selection(CoinSelectionStrategy::BranchAndBound { tolerance: 0 });
+        wallet.set_selection_constraints(SelectionConstraints { max_change_outputs: Some(0), ..Default::default() });
+
+        assert_eq!(wallet.create_automatic_transaction(Address::Charlie, 45, 0).unwrap().outputs.len(), 1);
+        assert_eq!(
+            wallet.create_automatic_transaction(Address::Charlie, 41, 0),
+            Err(WalletError::TooManyChangeOutputs { allowed: 0 })
+        );
+        wallet.queue_withdrawal(Address::Charlie, 41).unwrap();
+        assert_eq!(wallet.flush_withdrawals(10, 0), Err(WalletError::TooManyChangeOutputs { allowed: 0 }));
+    }
+
+    #[test]
+    fn selection_constraints_survive_export() {
+        let wallet = at_most_two_inputs();
+
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.selection_constraints(), wallet.selection_constraints());
+    }
+}
@@ -0,0 +1,134 @@
+//! Signers produce the signatures the wallet puts on the inputs it authors.
+//!
+//! The address scheme decides which keys must sign an input; the signer is then asked for each of
+//! those keys, with the digest of the transaction being signed. `SoftwareSigner` signs in process.
+//! A hardware wallet or HSM implements `Signer` to keep the keys on the device and let it see what
+//! it signs. A key the signer refuses is left out, so the input stays unsigned for that key.
+
+use std::sync::Arc;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// Signs transaction digests with the keys of individual addresses.
+pub trait Signer: Send + Sync {
+    /// Sign the transaction with digest `tx_digest` as `address`. Returns `Signature::Invalid` if
+    /// the signer does not hold the key or declines to sign.
+    fn sign(&self, tx_digest: &TransactionId, address: &Address) -> Signature;
+}
+
+/// Signs in process with the keys of the wallet's own addresses, which the address scheme already
+/// restricts the requests to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct SoftwareSigner;
+
+impl Signer for SoftwareSigner {
+    fn sign(&self, _tx_digest: &TransactionId, address: &Address) -> Signature {
+        Signature::Valid(address.clone())
+    }
+}
+
+/// The digest a signer signs: the id of the transaction with every signature stripped.
+pub fn signing_digest(transaction: &Transaction) -> TransactionId {
+    let mut unsigned = transaction.clone();
+    for input in &mut unsigned.inputs {
+        input.signature = Signature::Invalid;
+    }
+    unsigned.id()
+}
+
+impl Wallet {
+    /// Replace the signer, which defaults to `SoftwareSigner`.
+    pub fn set_signer(&mut self, signer: impl Signer + 'static) {
+        self.signer = Arc::new(signer);
+    }
+
+    /// Sign every input spending one of the wallet's coins. Inputs spending other coins keep their signature.
+    pub(crate) fn sign_inputs(&self, transaction: &mut Transaction) {
+        let digest = signing_digest(transaction);
+        for input in &mut transaction.inputs {
+            if let Some(coin) = self.coins.get(&input.coin_id) {
+                input.signature = self.sign_with(&digest, &coin.owner);
+            }
+        }
+    }
+
+    /// Sign the transaction with digest `digest` for a coin owned by `owner`, asking the signer for
+    /// every key the address scheme wants to sign with.
+    pub(crate) fn sign_with(&self, digest: &TransactionId, owner: &Address) -> Signature {
+        let signs = |key: &Address| self.signer.sign(digest, key) == Signature::Valid(key.clone());
+        match self.scheme.sign(&self.addresses, owner) {
+            Signature::Valid(key) if signs(&key) => Signature::Valid(key),
+            Signature::Multi(keys) => Signature::Multi(keys.into_iter().filter(|key| signs(key)).collect()),
+            _ => Signature::Invalid,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    use std::sync::{Arc, Mutex};
+
+    type Requests = Arc<Mutex<Vec<(TransactionId, Address)>>>;
+
+    /// A device that records what it is asked to sign and refuses to sign as Bob.
+    struct Device(Requests);
+
+    impl Signer for Device {
+        fn sign(&self, tx_digest: &TransactionId, address: &Address) -> Signature {
+            self.0.lock().unwrap().push((*tx_digest, address.clone()));
+            match address {
+                Address::Bob => Signature::Invalid,
+                address => Signature::Valid(address.clone()),
+            }
+        }
+    }
+
+    /// A wallet of Alice and Bob signing with a `Device`, which has paid Charlie a coin of 10
+    /// bones of each of them. Returns the wallet, the device's requests and the payment.
+    fn signed_by_a_device() -> (Wallet, Requests, Transaction) {
+        let requests = Requests::default();
+        let mut node = MockNode::new();
+        let mut wallet = Wallet::builder()
+            .addresses([Address::Alice, Address::Bob])
+            .signer(Device(requests.clone()))
+            .build()
+            .unwrap();
+        let funding = mint([(Address::Alice, 10), (Address::Bob, 10)]);
+        node.add_block_as_best(Block::genesis().id(), vec![funding.clone()]);
+        wallet.sync(&node);
+
+        let inputs = vec![funding.coin_id(1, 0), funding.coin_id(1, 1)];
+        let tx = wallet.create_manual_transaction(inputs, vec![Coin { value: 20, owner: Address::Charlie, asset_id: None }]).unwrap();
+        (wallet, requests, tx)
+    }
+
+    #[test]
+    fn the_signer_signs_the_digest_for_every_input() {
+        let (_, requests, tx) = signed_by_a_device();
+        let digest = signing_digest(&tx);
+        assert_eq!(*requests.lock().unwrap(), [(digest, Address::Alice), (digest, Address::Bob)]);
+        assert_eq!(tx.inputs[0].signature, Signature::Valid(Address::Alice));
+        assert_eq!(tx.inputs[1].signature, Signature::Invalid);
+    }
+
+    #[test]
+    fn the_digest_does_not_depend_on_the_signatures() {
+        let (_, _, tx) = signed_by_a_device();
+        assert_eq!(signing_digest(&PartialTransaction::new(tx.clone()).unsigned()), signing_digest(&tx));
+    }
+
+    #[test]
+    fn a_replaced_signer_is_no_longer_asked() {
+        let (mut wallet, requests, tx) = signed_by_a_device();
+        // the software signer signs everything
+        wallet.set_signer(SoftwareSigner);
+        let mut partial = PartialTransaction::new(tx);
+        assert_eq!(wallet.sign_partial(&mut partial), 2);
+        assert!(partial.is_complete());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
@@ -12,10 +12,12 @@ fn wallet_with_alice_and_bob() -> Wallet {
 
 fn marker_tx() -> Transaction {
     Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![Coin {
             value: 123,
             owner: Address::Custom(123),
+            asset_id: None,
         }],
     }
 }
@@ -57,3 +59,40 @@ fn test_best() {
 // test_create_manual_transaction
 // test_create_automatic_transaction
 
+
+// test_coinbase_maturity
+#[test]
+fn test_coinbase_maturity() {
+    let mut node = MockNode::new();
+    let mut wallet = wallet_with_alice_and_bob();
+
+    let b1_id = node.add_block_with_coinbase(Block::genesis().id(), Address::Alice, vec![]);
+    node.set_best(b1_id);
+    wallet.sync(&node);
+
+    let reward = node.entire_block(&b1_id).unwrap().body[0].coin_id(1, 0);
+    assert_eq!(wallet.net_worth(), BLOCK_REWARD);
+    assert_eq!(wallet.immature_coins(), HashSet::from([(reward, BLOCK_REWARD)]));
+    assert_eq!(
+        wallet.create_manual_transaction(vec![reward], vec![]),
+        Err(WalletError::ImmatureCoin(reward))
+    );
+    assert_eq!(
+        wallet.create_automatic_transaction(Address::Bob, 10, 0),
+        Err(WalletError::InsufficientFunds {
+            needed: 10,
+            available: 0,
+        })
+    );
+
+    // build enough blocks on top for the reward to mature, the first with an unrelated transaction
+    let mut tip = node.add_block_as_best(b1_id, vec![marker_tx()]);
+    for _ in 1..COINBASE_MATURITY {
+        tip = node.add_block_as_best(tip, vec![]);
+    }
+    wallet.sync(&node);
+
+    assert!(wallet.is_mature(&reward));
+    assert!(wallet.immature_coins().is_empty());
+    assert!(wallet.create_automatic_transaction(Address::Bob, 10, 0).is_ok());
+}
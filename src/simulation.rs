@@ -0,0 +1,135 @@
+//! Many wallets syncing against one evolving chain, with global invariants checked every round.
+//!
+//! Each simulated wallet owns its own addresses. Every round a driver has some wallets pay each
+//! other or burn bones, mines the payments with a coinbase for a random wallet, and now and then
+//! replaces the last few blocks with a longer branch. After every round all wallets sync, and their
+//! coins are compared with a ledger replayed independently from the node's best chain.
+
+use std::collections::HashMap;
+
+use bonecoin_core::rng::SplitMix64;
+
+use super::*;
+
+/// Bones paid here are gone for good; no wallet owns it.
+const BURN_ADDRESS: Address = Address::Custom(0);
+
+struct Simulation {
+    node: MockNode,
+    wallets: Vec<Wallet>,
+    rng: SplitMix64,
+}
+
+impl Simulation {
+    /// `wallets` empty wallets, wallet `i` owning addresses `Custom(1000 * (i + 1) + 1)` and `+ 2`.
+    fn new(seed: u64, wallets: u64) -> Self {
+        Simulation {
+            node: MockNode::new(),
+            wallets: (1..=wallets)
+                .map(|i| Wallet::new([Address::Custom(1000 * i + 1), Address::Custom(1000 * i + 2)].into_iter()))
+                .collect(),
+            rng: SplitMix64::new(seed),
+        }
+    }
+
+    fn pick(&mut self, n: usize) -> usize {
+        (self.rng.next_u64() % n as u64) as usize
+    }
+
+    fn random_address(&mut self, wallet: usize) -> Address {
+        let index = self.pick(2) as u64;
+        Address::Custom(1000 * (wallet as u64 + 1) + 1 + index)
+    }
+
+    /// Mine one block with payments between the wallets, or sometimes replace the tip with a longer branch,
+    /// then sync every wallet and check the invariants.
+    fn round(&mut self) {
+        let tip = self.node.best_block_at_height(self.wallets[0].best_height()).expect("wallets are synced");
+        let height = self.wallets[0].best_height();
+
+        if height > 3 && self.pick(5) == 0 {
+            // a competing branch that forks a few blocks back and only mints
+            let depth = 1 + self.pick(3) as u64;
+            let mut parent = self.node.best_block_at_height(height - depth).unwrap();
+            for _ in 0..=depth {
+                let miner = self.pick(self.wallets.len());
+                let miner = self.random_address(miner);
+                parent = self.node.add_block_with_coinbase(parent, miner, vec![]);
+            }
+            self.node.set_best(parent);
+        } else {
+            let mut body = Vec::new();
+            for payer in 0..self.wallets.len() {
+                if self.pick(2) == 0 {
+                    continue;
+                }
+                let recipient = match self.pick(self.wallets.len() + 1) {
+                    i if i == self.wallets.len() => BURN_ADDRESS,
+                    i => self.random_address(i),
+                };
+                let amount = 1 + self.rng.next_u64() % 40;
+                let tip_paid = self.rng.next_u64() % 3;
+                // payments the wallet cannot afford yet are simply skipped
+                if let Ok(tx) = self.wallets[payer].create_automatic_transaction(recipient, amount, tip_paid) {
+                    body.push(tx);
+                }
+            }
+            let miner = self.pick(self.wallets.len());
+            let miner = self.random_address(miner);
+            let block_id = self.node.add_block_with_coinbase(tip, miner, body);
+            self.node.set_best(block_id);
+        }
+
+        for wallet in &mut self.wallets {
+            wallet.sync(&self.node);
+        }
+        self.check_invariants();
+    }
+
+    /// Replay the best chain and compare every wallet with the resulting ledger.
+    fn check_invariants(&self) {
+        let best_height = self.wallets[0].best_height();
+        let mut unspent: HashMap<CoinId, Coin> = HashMap::new();
+        let mut minted = 0;
+        for height in 1..=best_height {
+            let block = self.node.entire_block(&self.node.best_block_at_height(height).unwrap()).unwrap();
+            if block.coinbase().is_some() {
+                minted += BLOCK_REWARD;
+            }
+            for tx in &block.body {
+                for coin_id in tx.iter_input_coin_ids() {
+                    unspent.remove(&coin_id).expect("the node's chain only spends existing coins");
+                }
+                unspent.extend(tx.iter_output_coins_and_ids(height));
+            }
+        }
+        let burned: u64 = unspent.values().filter(|coin| coin.owner == BURN_ADDRESS).map(|coin| coin.value).sum();
+
+        let mut total = 0;
+        for (i, wallet) in self.wallets.iter().enumerate() {
+            assert_eq!(wallet.best_height(), best_height, "wallet {i} is not synced");
+            assert_eq!(wallet.best_hash(), self.node.best_block_at_height(best_height).unwrap());
+            let owned: u64 = unspent
+                .values()
+                .filter(|coin| wallet.addresses.contains(&coin.owner))
+                .map(|coin| coin.value)
+                .sum();
+            assert_eq!(wallet.net_worth(), owned, "wallet {i} disagrees with the chain at height {best_height}");
+            total += wallet.net_worth();
+        }
+        assert_eq!(total, minted - burned, "the wallets do not hold the circulating supply");
+    }
+}
+
+#[test]
+fn wallets_account_for_the_whole_supply() {
+    for seed in 0..4 {
+        let mut simulation = Simulation::new(seed, 4);
+        for _ in 0..60 {
+            simulation.round();
+        }
+        // the wallets did pay each other, not only mine
+        let payments = simulation.wallets.iter().flat_map(|wallet| wallet.history()).filter(|entry| entry.direction == Direction::Outgoing);
+        assert!(payments.count() > 10);
+    }
+}
@@ -0,0 +1,148 @@
+//! Immutable point-in-time copies of the wallet's balances and coins.
+//!
+//! A snapshot shares its coin map with the wallet until the wallet's coins change, so taking
+//! snapshots between syncs is cheap. A service can answer reads from a snapshot on other threads
+//! while the live wallet keeps syncing.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// The wallet's addresses, sync position, and coins at the moment `Wallet::snapshot` was called.
+#[derive(Clone, Debug)]
+pub struct WalletSnapshot {
+    addresses: Arc<HashSet<Address>>,
+    height: u64,
+    block_id: BlockId,
+    coins: Arc<BTreeMap<CoinId, Coin>>,
+}
+
+impl WalletSnapshot {
+    /// Every coin in the snapshot, ordered by id.
+    pub fn coins(&self) -> &BTreeMap<CoinId, Coin> {
+        &self.coins
+    }
+}
+
+impl WalletReader for WalletSnapshot {
+    fn best_height(&self) -> u64 {
+        self.height
+    }
+
+    fn best_hash(&self) -> BlockId {
+        self.block_id
+    }
+
+    fn total_assets_of(&self, address: Address) -> WalletResult<u64> {
+        if !self.addresses.contains(&address) {
+            return Err(WalletError::ForeignAddress(address));
+        }
+        Ok(self
+            .coins
+            .values()
+            .filter(|coin| coin.owner == address && coin.is_native())
+            .map(|coin| coin.value)
+            .sum())
+    }
+
+    fn net_worth(&self) -> u64 {
+        self.coins.values().map(Coin::native_value).sum()
+    }
+
+    fn all_coins_of(&self, address: Address) -> WalletResult<HashSet<(CoinId, u64)>> {
+        if !self.addresses.contains(&address) {
+            return Err(WalletError::ForeignAddress(address));
+        }
+        Ok(self
+            .coins
+            .iter()
+            .filter(|(_, coin)| coin.owner == address && coin.is_native())
+            .map(|(coin_id, coin)| (*coin_id, coin.value))
+            .collect())
+    }
+
+    fn coin_details(&self, coin_id: &CoinId) -> WalletResult<Coin> {
+        self.coins.get(coin_id).cloned().ok_or(WalletError::UnknownCoin(*coin_id))
+    }
+}
+
+impl Wallet {
+    /// An immutable copy of the wallet's balances and coins as they are now.
+    pub fn snapshot(&self) -> WalletSnapshot {
+        WalletSnapshot {
+            addresses: Arc::new(self.addresses.clone()),
+            height: self.best_block_height,
+            block_id: self.best_block_hash,
+            coins: self.coins.shared(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Receiver;
+
+    use crate::test_support::*;
+
+    /// A snapshot and a clone of a wallet of Alice and Bob at the first block, with Alice's
+    /// address labelled "savings" and a receiver of the wallet's notifications, after which the
+    /// wallet pays Eve 110 bones in a second block and syncs it. Returns the node, the wallet,
+    /// the snapshot, the clone and the receiver.
+    fn after_a_payment() -> (MockNode, Wallet, WalletSnapshot, Wallet, Receiver<WalletEvent>) {
+        let (mut node, mut wallet) = make_one_block_blockchain();
+        wallet.set_address_label(Address::Alice, "savings");
+        wallet.take_events();
+        let receiver = wallet.notification_receiver();
+
+        let snapshot = wallet.snapshot();
+        let clone = wallet.clone();
+        let payment = wallet.create_automatic_transaction(Address::Eve, 110, 0).unwrap();
+        node.add_block_as_best(wallet.best_hash(), vec![payment]);
+        wallet.sync(&node);
+        assert_eq!(wallet.net_worth(), 125);
+        (node, wallet, snapshot, clone, receiver)
+    }
+
+    #[test]
+    fn snapshots_share_the_coins_until_the_wallet_changes() {
+        let (_, wallet) = make_one_block_blockchain();
+        assert!(std::ptr::eq(wallet.snapshot().coins(), wallet.snapshot().coins()));
+
+        let (_, wallet, snapshot, clone, _) = after_a_payment();
+        assert!(!std::ptr::eq(wallet.snapshot().coins(), snapshot.coins()));
+        assert!(!std::ptr::eq(wallet.snapshot().coins(), clone.snapshot().coins()));
+    }
+
+    #[test]
+    fn snapshots_are_read_on_another_thread_as_they_were_taken() {
+        let (_, _, snapshot, _, _) = after_a_payment();
+        let reader = std::thread::spawn(move || (snapshot.best_height(), snapshot.net_worth(), snapshot.total_assets_of(Address::Bob)));
+        assert_eq!(reader.join().unwrap(), (1, 235, Ok(120)));
+    }
+
+    #[test]
+    fn clones_keep_the_state_they_were_cloned_with() {
+        let (_, _, _, clone, _) = after_a_payment();
+        assert_eq!((clone.best_height(), clone.net_worth()), (1, 235));
+        assert_eq!(clone.address_label(&Address::Alice), Some("savings"));
+    }
+
+    #[test]
+    fn the_events_of_a_clone_are_its_own() {
+        let (node, mut wallet, _, mut clone, receiver) = after_a_payment();
+        clone.sync(&node);
+        assert_eq!(clone.best_height(), 2);
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), wallet.take_events());
+    }
+
+    #[test]
+    fn debug_output_leaves_out_addresses_labels_and_amounts() {
+        let (_, wallet, _, _, _) = after_a_payment();
+        let debug = format!("{wallet:?}");
+        assert!(debug.contains("best_block_height: 2"));
+        assert!(!debug.contains("Alice") && !debug.contains("savings") && !debug.contains("125"));
+    }
+}
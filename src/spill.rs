@@ -0,0 +1,232 @@
+//! Bounding the memory the wallet's coins take, for constrained devices.
+//!
+//! A wallet with a coin cache, set up with `Wallet::set_coin_cache`, writes every coin it receives
+//! through to a `CoinSpill` and holds only the most recently read ones in memory. A coin that was
+//! evicted is faulted back in from the spill when it is read, by `coin_details`, coin selection, or
+//! anything else, so the rest of the wallet works as before. The value index stays in memory, so
+//! balances and value range queries only fault in the coins they return.
+//!
+//! Reading more coins than the cache holds, such as listing every coin, keeps them in memory until
+//! the next change to the coins evicts them again. A clone of the wallet holds all of its coins in
+//! memory and has no cache.
+//!
+//! A coin is only evicted once the spill hands it back as it was written, and one that does not stays
+//! in memory. A spill that later loses an evicted coin breaks its contract, and reading the coin panics.
+//!
+//! `DirSpill` keeps one file per coin in a directory. The spill is a cache, not a store: it is not
+//! read back when the wallet is reopened, which a `WalletStore` is for.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use bonecoin_core::codec::{Decode, Encode};
+use bonecoin_core::*;
+
+use crate::{StoreError, Wallet};
+
+/// Holds every coin of a wallet with a coin cache, for the ones evicted from memory to be read back.
+pub trait CoinSpill: Send + Sync {
+    /// Write a coin, replacing any coin written under the same id.
+    fn put(&self, coin_id: &CoinId, coin: &Coin) -> Result<(), StoreError>;
+
+    /// Read a coin written earlier, or `None` if there is none. A coin read back once must stay
+    /// readable until it is replaced or removed.
+    fn get(&self, coin_id: &CoinId) -> Result<Option<Coin>, StoreError>;
+
+    /// Forget a coin. Forgetting a coin that was never written is not an error.
+    fn remove(&self, coin_id: &CoinId) -> Result<(), StoreError>;
+}
+
+/// A spill keeping each coin in its own file, named by coin id, in a directory.
+#[derive(Clone, Debug)]
+pub struct DirSpill {
+    dir: PathBuf,
+}
+
+impl DirSpill {
+    /// Spill into `dir`, creating it if it does not exist.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StoreError> {
+        fs::create_dir_all(&dir).map_err(io)?;
+        Ok(DirSpill {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, coin_id: &CoinId) -> PathBuf {
+        self.dir.join(coin_id.to_string())
+    }
+}
+
+fn io(e: std::io::Error) -> StoreError {
+    StoreError::Backend(e.to_string())
+}
+
+impl CoinSpill for DirSpill {
+    fn put(&self, coin_id: &CoinId, coin: &Coin) -> Result<(), StoreError> {
+        fs::write(self.path(coin_id), coin.encode()).map_err(io)
+    }
+
+    fn get(&self, coin_id: &CoinId) -> Result<Option<Coin>, StoreError> {
+        match fs::read(self.path(coin_id)) {
+            Ok(bytes) => Ok(Some(Coin::decode(&bytes)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io(e)),
+        }
+    }
+
+    fn remove(&self, coin_id: &CoinId) -> Result<(), StoreError> {
+        match fs::remove_file(self.path(coin_id)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(io(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How the coin cache has served the coins read so far.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CoinCacheStats {
+    /// The most coins held in memory between changes to the coins.
+    pub capacity: usize,
+    /// The coins held in memory now.
+    pub cached: usize,
+    /// Reads of coins that were in memory.
+    pub hits: u64,
+    /// Reads of coins faulted in from the spill.
+    pub misses: u64,
+}
+
+impl CoinCacheStats {
+    /// The share of reads served from memory, or 1 if no coin was read yet.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 1.0,
+            reads => self.hits as f64 / reads as f64,
+        }
+    }
+}
+
+impl Wallet {
+    /// Hold at most `max_cached_coins` coins in memory, writing every coin through to `spill` and
+    /// faulting evicted coins back in when they are read. Fails if the coins held now cannot be
+    /// written to the spill, leaving them all in memory.
+    pub fn set_coin_cache(&mut self, spill: impl CoinSpill + 'static, max_cached_coins: usize) -> Result<(), StoreError> {
+        self.coins.attach_cache(Arc::new(spill), max_cached_coins)
+    }
+
+    /// How the coin cache has served the coins read so far, or `None` without one.
+    pub fn coin_cache_stats(&self) -> Option<CoinCacheStats> {
+        self.coins.cache_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice caching 3 coins in memory and spilling the rest to a directory, synced
+    /// to a block paying her ten coins of 1 to 10 bones. Returns the node, the wallet, the payout
+    /// and the directory, which the test removes.
+    fn spilling_wallet() -> (MockNode, Wallet, Transaction, std::path::PathBuf) {
+        let path = unique_temp_path("spill");
+        let mut node = MockNode::new();
+        let payout = mint((1..=10).map(|value| (Address::Alice, value)));
+        node.add_block_as_best(Block::genesis().id(), vec![payout.clone()]);
+        let mut wallet = Wallet::builder()
+            .address(Address::Alice)
+            .coin_cache(DirSpill::open(&path).unwrap(), 3)
+            .build()
+            .unwrap();
+        wallet.sync(&node);
+        (node, wallet, payout, path)
+    }
+
+    #[test]
+    fn sync_leaves_the_cache_at_its_capacity() {
+        let (_, wallet, _, path) = spilling_wallet();
+        let stats = wallet.coin_cache_stats().unwrap();
+        assert_eq!((stats.capacity, stats.cached), (3, 3));
+        assert_eq!(wallet.net_worth(), 55);
+        drop(wallet);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn spilled_coins_are_read_back_and_held_until_the_next_sync() {
+        let (node, mut wallet, payout, path) = spilling_wallet();
+        for index in 0..10 {
+            assert_eq!(wallet.coin_details(&payout.coin_id(1, index)).unwrap().value, index as u64 + 1);
+        }
+        assert_eq!(wallet.coin_cache_stats().unwrap().cached, 10);
+        wallet.sync(&node);
+        assert_eq!(wallet.coin_cache_stats().unwrap().cached, 3);
+        drop(wallet);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn the_cache_keeps_the_most_recently_read_coins() {
+        let (node, mut wallet, payout, path) = spilling_wallet();
+        for index in 0..10 {
+            wallet.coin_details(&payout.coin_id(1, index)).unwrap();
+        }
+        wallet.sync(&node);
+        let before = wallet.coin_cache_stats().unwrap();
+        assert_eq!(wallet.coin_details(&payout.coin_id(1, 9)).unwrap().value, 10);
+        assert_eq!(wallet.coin_details(&payout.coin_id(1, 0)).unwrap().value, 1);
+        let stats = wallet.coin_cache_stats().unwrap();
+        assert_eq!((stats.hits - before.hits, stats.misses - before.misses), (1, 1));
+        assert!(stats.hit_rate() > 0.0 && stats.hit_rate() < 1.0);
+        drop(wallet);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn spending_evicts_down_to_the_capacity() {
+        let (mut node, mut wallet, _, path) = spilling_wallet();
+        // selection reads spilled coins back too
+        let tx = wallet.create_automatic_transaction(Address::Bob, 50, 0).unwrap();
+        node.add_block_as_best(wallet.best_hash(), vec![tx]);
+        wallet.sync(&node);
+        assert_eq!(wallet.net_worth(), 5);
+        assert!(wallet.coin_cache_stats().unwrap().cached <= 3);
+        drop(wallet);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn coins_the_spill_cannot_read_back_stay_in_memory() {
+        struct WriteOnly;
+        impl CoinSpill for WriteOnly {
+            fn put(&self, _: &CoinId, _: &Coin) -> Result<(), StoreError> {
+                Ok(())
+            }
+            fn get(&self, _: &CoinId) -> Result<Option<Coin>, StoreError> {
+                Err(StoreError::Backend("unreadable".to_string()))
+            }
+            fn remove(&self, _: &CoinId) -> Result<(), StoreError> {
+                Ok(())
+            }
+        }
+        let mut node = MockNode::new();
+        let payout = mint((1..=10).map(|value| (Address::Alice, value)));
+        node.add_block_as_best(Block::genesis().id(), vec![payout.clone()]);
+        let mut wallet = Wallet::builder().address(Address::Alice).coin_cache(WriteOnly, 3).build().unwrap();
+        wallet.sync(&node);
+        for index in 0..10 {
+            assert_eq!(wallet.coin_details(&payout.coin_id(1, index)).unwrap().value, index as u64 + 1);
+        }
+        assert_eq!(wallet.all_coins().map(|(_, coin)| coin.value).sum::<u64>(), wallet.net_worth());
+    }
+
+    #[test]
+    fn clones_hold_every_coin_in_memory() {
+        let (_, wallet, _, path) = spilling_wallet();
+        let clone = wallet.clone();
+        assert_eq!(clone.coin_cache_stats(), None);
+        assert_eq!(clone.all_coins().count(), wallet.all_coins().count());
+        drop(wallet);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
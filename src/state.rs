@@ -0,0 +1,889 @@
+//! Versioned snapshots of the complete wallet state, for moving a wallet between machines.
+//!
+//! A snapshot is a four byte magic, a `u16` format version, and the canonically encoded payload.
+//! Snapshots written by older versions are upgraded by the registered `MIGRATIONS` before being
+//! decoded, so a newer wallet can always import what an older one exported. `Wallet::save` writes a
+//! snapshot to a file, and `Wallet::load` reads one back, rewriting files of older versions in the
+//! current format.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use bonecoin_core::codec::{Decode, DecodeError, Encode};
+use bonecoin_core::*;
+
+use crate::{AlertRule, ChangeDestination, ChangePolicy, DustPolicy, Channel, ChannelState, CoinSelectionStrategy, Direction, SelectionConstraints, HdAddresses, HdKeychain, HistoryEntry, PartialTransaction, SignatureBundle, SigningRequest, RegisteredStatus, ReorgRecord, RegisteredTransaction, SpendingPolicy, Wallet, WalletConfig, WalletId, WatchedCoin, Withdrawal, WithdrawalStatus};
+
+/// Marks the start of every wallet snapshot.
+pub const STATE_MAGIC: &[u8; 4] = b"BONW";
+
+/// The snapshot format version written by this build.
+pub const STATE_VERSION: u16 = 1;
+
+/// Errors that can occur while importing a wallet snapshot.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum StateError {
+    /// The bytes do not start with `STATE_MAGIC`, so they are not a wallet snapshot.
+    BadMagic,
+    /// The snapshot was written by a newer (or unknown) format version.
+    UnsupportedVersion(u16),
+    /// The payload could not be decoded.
+    Decode(DecodeError),
+    /// The snapshot is of the wallet `found`, not of the wallet `expected`.
+    WrongWallet { expected: WalletId, found: WalletId },
+    /// The snapshot file could not be read or written, with the error message.
+    Io(String),
+    /// The backup's checksum does not match its contents.
+    ChecksumMismatch,
+    /// A backup was asked for, but no backup policy is set.
+    NoBackupPolicy,
+}
+
+impl From<DecodeError> for StateError {
+    fn from(e: DecodeError) -> Self {
+        StateError::Decode(e)
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a wallet snapshot"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported snapshot version {version}"),
+            StateError::Decode(e) => write!(f, "malformed snapshot: {e}"),
+            StateError::WrongWallet { expected, found } => write!(f, "snapshot of wallet {found}, expected wallet {expected}"),
+            StateError::Io(message) => write!(f, "could not access the snapshot file: {message}"),
+            StateError::ChecksumMismatch => write!(f, "the backup is damaged: its checksum does not match"),
+            StateError::NoBackupPolicy => write!(f, "no backup policy is set"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl Wallet {
+    /// Serialize the complete wallet state: addresses, sync position, coins, history, labels, policies, channels, watched coins, registered transactions, the reorg log, the credited deposits, the withdrawal queue, the requests sent by id, the followed work, the alert rules, the receive-only addresses, the accounts, the tips burned by history entries, the watchtower addresses, and the config.
+    /// Undo records are not included, so a reorg below the exported height makes the imported wallet resync from genesis.
+    /// Events that have not been taken yet are not included.
+    /// Pending approvals are not included; they must be approved in the session that proposed them.
+    pub fn export_state(&self) -> Vec<u8> {
+        let mut out = STATE_MAGIC.to_vec();
+        STATE_VERSION.encode_to(&mut out);
+
+        self.addresses.iter().cloned().collect::<BTreeSet<_>>().encode_to(&mut out);
+        self.best_block_height.encode_to(&mut out);
+        self.best_block_hash.encode_to(&mut out);
+        self.coins.iter().map(|(k, v)| (*k, v.clone())).collect::<BTreeMap<_, _>>().encode_to(&mut out);
+        sorted(&self.coinbase_heights).encode_to(&mut out);
+        self.history.entries().to_vec().encode_to(&mut out);
+        sorted(&self.outpoints).encode_to(&mut out);
+        sorted(&self.labels.addresses).encode_to(&mut out);
+        sorted(&self.labels.coins).encode_to(&mut out);
+        sorted(&self.labels.transactions).encode_to(&mut out);
+        encode_policy(&self.policy, &mut out);
+        sorted(&self.channels).encode_to(&mut out);
+        self.config.change_policy.encode_to(&mut out);
+        self.config.selection.encode_to(&mut out);
+        self.config.selection_constraints.encode_to(&mut out);
+        sorted(&self.watched).encode_to(&mut out);
+        sorted(&self.registered).encode_to(&mut out);
+        encode_retention(&self.config, &mut out);
+        encode_hd(self.hd.as_ref(), &mut out);
+        self.receiving.pool.encode_to(&mut out);
+        self.receiving.issued.encode_to(&mut out);
+        self.receiving.used.iter().cloned().collect::<BTreeSet<_>>().encode_to(&mut out);
+        self.config.strict_sync.encode_to(&mut out);
+        self.reorgs.records.encode_to(&mut out);
+        self.credited_deposits.iter().copied().collect::<BTreeSet<_>>().encode_to(&mut out);
+        self.withdrawals.next_id.encode_to(&mut out);
+        self.withdrawals.requests.values().cloned().collect::<Vec<_>>().encode_to(&mut out);
+        self.sent_requests.clone().into_iter().collect::<BTreeMap<_, _>>().encode_to(&mut out);
+        self.config.check_work.encode_to(&mut out);
+        self.followed_work.encode_to(&mut out);
+        self.config.lazy_bodies.encode_to(&mut out);
+        self.alerts.rules.encode_to(&mut out);
+        self.receive_only.iter().cloned().collect::<BTreeSet<_>>().encode_to(&mut out);
+        self.accounts.encode_to(&mut out);
+        self.watchtower.iter().cloned().collect::<BTreeSet<_>>().encode_to(&mut out);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(height = self.best_block_height, coins = self.coins.len(), bytes = out.len(), "wallet state exported");
+        out
+    }
+
+    /// Write the wallet's snapshot to `path`, replacing the file only once the snapshot is written in full.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), StateError> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        fs::write(&partial, self.export_state()).map_err(io)?;
+        fs::rename(&partial, path).map_err(io)
+    }
+
+    /// Read the wallet snapshot at `path`. A snapshot written by an older version is upgraded, and the
+    /// file is rewritten in the current format, so the migrations run only once per file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Wallet, StateError> {
+        let bytes = fs::read(path.as_ref()).map_err(io)?;
+        let wallet = Wallet::import_state(&bytes)?;
+        if state_version(&bytes)? < STATE_VERSION {
+            wallet.save(path)?;
+        }
+        Ok(wallet)
+    }
+
+    /// Import a snapshot like `import_state`, failing unless it is of the wallet with fingerprint `expected`.
+    pub fn import_state_of(bytes: &[u8], expected: WalletId) -> Result<Wallet, StateError> {
+        let wallet = Wallet::import_state(bytes)?;
+        match wallet.fingerprint() {
+            found if found != expected => Err(StateError::WrongWallet { expected, found }),
+            _ => Ok(wallet),
+        }
+    }
+
+    /// Rebuild a wallet from a snapshot produced by `export_state`, upgrading older formats.
+    /// The address scheme is not part of the snapshot, so the imported wallet uses `KeyScheme`.
+    pub fn import_state(bytes: &[u8]) -> Result<Wallet, StateError> {
+        let version = state_version(bytes)?;
+        let input = &bytes[STATE_MAGIC.len() + 2..];
+        #[cfg(feature = "tracing")]
+        if version < STATE_VERSION {
+            tracing::info!(from_version = version, to_version = STATE_VERSION, "migrating wallet state");
+        }
+        let payload = migrate(version, input.to_vec())?;
+        let mut input = payload.as_slice();
+
+        let addresses = BTreeSet::<Address>::decode_from(&mut input)?;
+        let mut wallet = Wallet::new(addresses.into_iter());
+        wallet.best_block_height = u64::decode_from(&mut input)?;
+        wallet.best_block_hash = BlockId::decode_from(&mut input)?;
+        wallet.coins = BTreeMap::<CoinId, Coin>::decode_from(&mut input)?.into_iter().collect();
+        wallet.coinbase_heights = BTreeMap::<CoinId, u64>::decode_from(&mut input)?.into_iter().collect();
+        for entry in Vec::<HistoryEntry>::decode_from(&mut input)? {
+            wallet.history.push(entry);
+        }
+        wallet.outpoints = BTreeMap::decode_from(&mut input)?.into_iter().collect();
+        wallet.labels.addresses = BTreeMap::decode_from(&mut input)?.into_iter().collect();
+        wallet.labels.coins = BTreeMap::decode_from(&mut input)?.into_iter().collect();
+        wallet.labels.transactions = BTreeMap::decode_from(&mut input)?.into_iter().collect();
+        wallet.policy = decode_policy(&mut input)?;
+        wallet.channels = BTreeMap::decode_from(&mut input)?.into_iter().collect();
+        wallet.config.change_policy = ChangePolicy::decode_from(&mut input)?;
+        wallet.config.selection = CoinSelectionStrategy::decode_from(&mut input)?;
+        wallet.config.selection_constraints = SelectionConstraints::decode_from(&mut input)?;
+        wallet.watched = BTreeMap::decode_from(&mut input)?.into_iter().collect();
+        wallet.registered = BTreeMap::decode_from(&mut input)?.into_iter().collect();
+        decode_retention(&mut input, &mut wallet.config)?;
+        wallet.hd = decode_hd(&mut input)?;
+        wallet.receiving.pool = Vec::decode_from(&mut input)?;
+        wallet.receiving.issued = Vec::decode_from(&mut input)?;
+        wallet.receiving.used = BTreeSet::decode_from(&mut input)?.into_iter().collect();
+        wallet.config.strict_sync = bool::decode_from(&mut input)?;
+        wallet.reorgs.records = Vec::decode_from(&mut input)?;
+        wallet.credited_deposits = BTreeSet::decode_from(&mut input)?.into_iter().collect();
+        wallet.withdrawals.next_id = u64::decode_from(&mut input)?;
+        wallet.withdrawals.requests =
+            Vec::<Withdrawal>::decode_from(&mut input)?.into_iter().map(|request| (request.id, request)).collect();
+        wallet.withdrawals.reindex();
+        wallet.sent_requests = BTreeMap::decode_from(&mut input)?.into_iter().collect();
+        for transaction in wallet.sent_requests.values().cloned().collect::<Vec<_>>() {
+            wallet.reserve_inputs(&transaction);
+        }
+        wallet.config.check_work = bool::decode_from(&mut input)?;
+        wallet.followed_work = Option::decode_from(&mut input)?;
+        wallet.config.lazy_bodies = bool::decode_from(&mut input)?;
+        wallet.alerts.rules = Vec::decode_from(&mut input)?;
+        wallet.receive_only = BTreeSet::decode_from(&mut input)?.into_iter().collect();
+        wallet.accounts = BTreeMap::decode_from(&mut input)?;
+        wallet.watchtower = BTreeSet::decode_from(&mut input)?.into_iter().collect();
+
+        if !input.is_empty() {
+            return Err(StateError::Decode(DecodeError::TrailingBytes));
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(version, height = wallet.best_block_height, coins = wallet.coins.len(), "wallet state imported");
+        Ok(wallet)
+    }
+}
+
+/// One step of upgrading a snapshot: rewrites a payload of format version `from` into one of version `to`.
+#[derive(Copy, Clone, Debug)]
+pub struct Migration {
+    pub from: u16,
+    pub to: u16,
+    pub apply: fn(Vec<u8>) -> Result<Vec<u8>, DecodeError>,
+}
+
+/// Every migration, oldest first. Following them from any older version leads to `STATE_VERSION`.
+/// There are none yet, since version 1 is the only released format; test builds register one from
+/// a made-up version 0, so the upgrade path is exercised.
+///
+/// When the format changes, bump `STATE_VERSION` and register a migration from the previous
+/// version, which usually appends the defaults of the new fields to the payload. Add a fixture
+/// exported by the previous version to the state fixtures as well, so it keeps being tested.
+pub const MIGRATIONS: &[Migration] = &[
+    #[cfg(test)]
+    tests::V0_TO_V1,
+];
+
+/// The format version of a snapshot, read from its header.
+pub fn state_version(bytes: &[u8]) -> Result<u16, StateError> {
+    let mut input = bytes.strip_prefix(STATE_MAGIC.as_slice()).ok_or(StateError::BadMagic)?;
+    Ok(u16::decode_from(&mut input)?)
+}
+
+fn io(e: std::io::Error) -> StateError {
+    StateError::Io(e.to_string())
+}
+
+/// Upgrade a payload written by format `version` to `STATE_VERSION` by applying `MIGRATIONS` in turn.
+fn migrate(mut version: u16, mut payload: Vec<u8>) -> Result<Vec<u8>, StateError> {
+    while version != STATE_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from == version)
+            .ok_or(StateError::UnsupportedVersion(version))?;
+        payload = (migration.apply)(payload)?;
+        version = migration.to;
+    }
+    Ok(payload)
+}
+
+fn sorted<K: Ord + Clone, V: Clone>(map: &HashMap<K, V>) -> BTreeMap<K, V> {
+    map.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+}
+
+/// The config fields not stored elsewhere in the snapshot: the retention limits and the coinbase maturity.
+fn encode_retention(config: &WalletConfig, out: &mut Vec<u8>) {
+    config.undo_depth.encode_to(out);
+    config.max_history_entries.encode_to(out);
+    config.prune_spent_after.encode_to(out);
+    config.coinbase_maturity.encode_to(out);
+}
+
+fn decode_retention(input: &mut &[u8], config: &mut WalletConfig) -> Result<(), DecodeError> {
+    config.undo_depth = u64::decode_from(input)?;
+    config.max_history_entries = Option::decode_from(input)?;
+    config.prune_spent_after = Option::decode_from(input)?;
+    config.coinbase_maturity = u64::decode_from(input)?;
+    Ok(())
+}
+
+/// The keychain and how far it was derived and used. The derived addresses are already among the wallet's addresses.
+fn encode_hd(hd: Option<&HdAddresses>, out: &mut Vec<u8>) {
+    hd.is_some().encode_to(out);
+    if let Some(hd) = hd {
+        hd.keychain.seed.encode_to(out);
+        hd.keychain.gap_limit.encode_to(out);
+        hd.keychain.birth_height.encode_to(out);
+        hd.derived.len().encode_to(out);
+        hd.used.encode_to(out);
+    }
+}
+
+fn decode_hd(input: &mut &[u8]) -> Result<Option<HdAddresses>, DecodeError> {
+    if !bool::decode_from(input)? {
+        return Ok(None);
+    }
+    let mut hd = HdAddresses::new(HdKeychain {
+        seed: u64::decode_from(input)?,
+        gap_limit: usize::decode_from(input)?,
+        birth_height: u64::decode_from(input)?,
+    });
+    hd.derive_to(usize::decode_from(input)?);
+    hd.used = usize::decode_from(input)?;
+    Ok(Some(hd))
+}
+
+fn encode_policy(policy: &SpendingPolicy, out: &mut Vec<u8>) {
+    policy.max_per_transaction.encode_to(out);
+    policy.max_per_window.encode_to(out);
+    policy
+        .allowlist
+        .as_ref()
+        .map(|allowed| allowed.iter().cloned().collect::<BTreeSet<_>>())
+        .encode_to(out);
+    policy.denylist.iter().cloned().collect::<BTreeSet<_>>().encode_to(out);
+    policy.approval_threshold.encode_to(out);
+    policy.approvers.iter().cloned().collect::<BTreeSet<_>>().encode_to(out);
+}
+
+fn decode_policy(input: &mut &[u8]) -> Result<SpendingPolicy, DecodeError> {
+    Ok(SpendingPolicy {
+        max_per_transaction: Option::decode_from(input)?,
+        max_per_window: Option::decode_from(input)?,
+        allowlist: Option::<BTreeSet<Address>>::decode_from(input)?.map(|allowed| allowed.into_iter().collect()),
+        denylist: BTreeSet::<Address>::decode_from(input)?.into_iter().collect(),
+        approval_threshold: Option::decode_from(input)?,
+        approvers: BTreeSet::<Address>::decode_from(input)?.into_iter().collect(),
+    })
+}
+
+impl Encode for Direction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            Direction::Incoming => 0,
+            Direction::Outgoing => 1,
+            Direction::SelfTransfer => 2,
+            Direction::Internal => 3,
+        });
+    }
+}
+
+impl Decode for Direction {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(Direction::Incoming),
+            1 => Ok(Direction::Outgoing),
+            2 => Ok(Direction::SelfTransfer),
+            3 => Ok(Direction::Internal),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for HistoryEntry {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.tx_id.encode_to(out);
+        self.block_id.encode_to(out);
+        self.height.encode_to(out);
+        self.timestamp.encode_to(out);
+        self.direction.encode_to(out);
+        self.received.encode_to(out);
+        self.spent.encode_to(out);
+        self.burned.encode_to(out);
+    }
+}
+
+impl Decode for HistoryEntry {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(HistoryEntry {
+            tx_id: TransactionId::decode_from(input)?,
+            block_id: BlockId::decode_from(input)?,
+            height: u64::decode_from(input)?,
+            timestamp: u64::decode_from(input)?,
+            direction: Direction::decode_from(input)?,
+            received: Vec::decode_from(input)?,
+            spent: Vec::decode_from(input)?,
+            burned: Option::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for ReorgRecord {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.from_height.encode_to(out);
+        self.old_tip.encode_to(out);
+        self.rolled_back_to.encode_to(out);
+        self.new_height.encode_to(out);
+        self.new_tip.encode_to(out);
+        self.coins_reverted.encode_to(out);
+        self.coins_readded.encode_to(out);
+        self.timestamp.encode_to(out);
+    }
+}
+
+impl Decode for ReorgRecord {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(ReorgRecord {
+            from_height: u64::decode_from(input)?,
+            old_tip: BlockId::decode_from(input)?,
+            rolled_back_to: u64::decode_from(input)?,
+            new_height: u64::decode_from(input)?,
+            new_tip: BlockId::decode_from(input)?,
+            coins_reverted: Vec::decode_from(input)?,
+            coins_readded: Vec::decode_from(input)?,
+            timestamp: u64::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for Withdrawal {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.id.encode_to(out);
+        self.recipient.encode_to(out);
+        self.amount.encode_to(out);
+        match self.status {
+            WithdrawalStatus::Queued => out.push(0),
+            WithdrawalStatus::InFlight { tx_id } => {
+                out.push(1);
+                tx_id.encode_to(out);
+            }
+            WithdrawalStatus::Confirmed { tx_id, height } => {
+                out.push(2);
+                tx_id.encode_to(out);
+                height.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Withdrawal {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let id = u64::decode_from(input)?;
+        let recipient = Address::decode_from(input)?;
+        let amount = u64::decode_from(input)?;
+        let status = match u8::decode_from(input)? {
+            0 => WithdrawalStatus::Queued,
+            1 => WithdrawalStatus::InFlight { tx_id: TransactionId::decode_from(input)? },
+            2 => WithdrawalStatus::Confirmed {
+                tx_id: TransactionId::decode_from(input)?,
+                height: u64::decode_from(input)?,
+            },
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        };
+        Ok(Withdrawal { id, recipient, amount, status })
+    }
+}
+
+impl Encode for PartialTransaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.transaction.encode_to(out);
+        self.coins.encode_to(out);
+    }
+}
+
+impl Decode for PartialTransaction {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(PartialTransaction {
+            transaction: Transaction::decode_from(input)?,
+            coins: Vec::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for SigningRequest {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.partial.encode_to(out);
+    }
+}
+
+impl Decode for SigningRequest {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(SigningRequest {
+            partial: PartialTransaction::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for SignatureBundle {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.unsigned_id.encode_to(out);
+        self.signatures.encode_to(out);
+    }
+}
+
+impl Decode for SignatureBundle {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(SignatureBundle {
+            unsigned_id: TransactionId::decode_from(input)?,
+            signatures: Vec::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for ChannelState {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.channel_id.encode_to(out);
+        self.sequence.encode_to(out);
+        self.opener_balance.encode_to(out);
+        self.acceptor_balance.encode_to(out);
+        self.transaction.encode_to(out);
+    }
+}
+
+impl Decode for ChannelState {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(ChannelState {
+            channel_id: TransactionId::decode_from(input)?,
+            sequence: u64::decode_from(input)?,
+            opener_balance: u64::decode_from(input)?,
+            acceptor_balance: u64::decode_from(input)?,
+            transaction: PartialTransaction::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for Channel {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.id.encode_to(out);
+        self.opener.encode_to(out);
+        self.acceptor.encode_to(out);
+        self.capacity.encode_to(out);
+        self.funding.encode_to(out);
+        self.funded_at.encode_to(out);
+        self.latest.encode_to(out);
+        self.proposed.encode_to(out);
+    }
+}
+
+impl Decode for Channel {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Channel {
+            id: TransactionId::decode_from(input)?,
+            opener: Address::decode_from(input)?,
+            acceptor: Address::decode_from(input)?,
+            capacity: u64::decode_from(input)?,
+            funding: Transaction::decode_from(input)?,
+            funded_at: Option::decode_from(input)?,
+            latest: Option::decode_from(input)?,
+            proposed: Option::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for AlertRule {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            AlertRule::BalanceBelow(threshold) => {
+                out.push(0);
+                threshold.encode_to(out);
+            }
+            AlertRule::IncomingAbove(limit) => {
+                out.push(1);
+                limit.encode_to(out);
+            }
+            AlertRule::SpendFrom(address) => {
+                out.push(2);
+                address.encode_to(out);
+            }
+            AlertRule::ReorgDeeperThan(depth) => {
+                out.push(3);
+                depth.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for AlertRule {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(AlertRule::BalanceBelow(u64::decode_from(input)?)),
+            1 => Ok(AlertRule::IncomingAbove(u64::decode_from(input)?)),
+            2 => Ok(AlertRule::SpendFrom(Address::decode_from(input)?)),
+            3 => Ok(AlertRule::ReorgDeeperThan(u64::decode_from(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for ChangePolicy {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match &self.destination {
+            ChangeDestination::Default => out.push(0),
+            ChangeDestination::Fixed(address) => {
+                out.push(1);
+                address.encode_to(out);
+            }
+            ChangeDestination::RoundRobin => out.push(2),
+            ChangeDestination::Fresh => out.push(3),
+        }
+        self.dust_threshold.encode_to(out);
+        self.dust_policy.encode_to(out);
+    }
+}
+
+impl Decode for ChangePolicy {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let destination = match u8::decode_from(input)? {
+            0 => ChangeDestination::Default,
+            1 => ChangeDestination::Fixed(Address::decode_from(input)?),
+            2 => ChangeDestination::RoundRobin,
+            3 => ChangeDestination::Fresh,
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        };
+        Ok(ChangePolicy {
+            destination,
+            dust_threshold: u64::decode_from(input)?,
+            dust_policy: DustPolicy::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for DustPolicy {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            DustPolicy::AddToTip => 0,
+            DustPolicy::AddToPayment => 1,
+            DustPolicy::CreateChange => 2,
+        });
+    }
+}
+
+impl Decode for DustPolicy {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(DustPolicy::AddToTip),
+            1 => Ok(DustPolicy::AddToPayment),
+            2 => Ok(DustPolicy::CreateChange),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for SelectionConstraints {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.min_inputs.encode_to(out);
+        self.max_inputs.encode_to(out);
+        self.max_change_outputs.encode_to(out);
+    }
+}
+
+impl Decode for SelectionConstraints {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(SelectionConstraints {
+            min_inputs: usize::decode_from(input)?,
+            max_inputs: Option::decode_from(input)?,
+            max_change_outputs: Option::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for CoinSelectionStrategy {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            CoinSelectionStrategy::FirstFit => 0,
+            CoinSelectionStrategy::PrivacyPreserving => 1,
+            CoinSelectionStrategy::BranchAndBound { .. } => 2,
+            CoinSelectionStrategy::Random { .. } => 3,
+        });
+        match self {
+            CoinSelectionStrategy::BranchAndBound { tolerance } => tolerance.encode_to(out),
+            CoinSelectionStrategy::Random { seed } => seed.encode_to(out),
+            _ => {}
+        }
+    }
+}
+
+impl Decode for CoinSelectionStrategy {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode_from(input)? {
+            0 => Ok(CoinSelectionStrategy::FirstFit),
+            1 => Ok(CoinSelectionStrategy::PrivacyPreserving),
+            2 => Ok(CoinSelectionStrategy::BranchAndBound {
+                tolerance: u64::decode_from(input)?,
+            }),
+            3 => Ok(CoinSelectionStrategy::Random {
+                seed: Option::decode_from(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for WatchedCoin {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.coin.encode_to(out);
+        self.created_at_height.encode_to(out);
+        self.spent_by.encode_to(out);
+        self.spent_at_height.encode_to(out);
+    }
+}
+
+impl Decode for WatchedCoin {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(WatchedCoin {
+            coin: Option::decode_from(input)?,
+            created_at_height: Option::decode_from(input)?,
+            spent_by: Option::decode_from(input)?,
+            spent_at_height: Option::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for RegisteredTransaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.transaction.encode_to(out);
+        self.expected_heights.start().encode_to(out);
+        self.expected_heights.end().encode_to(out);
+        match self.status {
+            RegisteredStatus::Pending => out.push(0),
+            RegisteredStatus::Confirmed { height } => {
+                out.push(1);
+                height.encode_to(out);
+            }
+            RegisteredStatus::Evicted { height } => {
+                out.push(2);
+                height.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for RegisteredTransaction {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let transaction = Transaction::decode_from(input)?;
+        let expected_heights = u64::decode_from(input)?..=u64::decode_from(input)?;
+        let status = match u8::decode_from(input)? {
+            0 => RegisteredStatus::Pending,
+            1 => RegisteredStatus::Confirmed {
+                height: u64::decode_from(input)?,
+            },
+            2 => RegisteredStatus::Evicted {
+                height: u64::decode_from(input)?,
+            },
+            tag => return Err(DecodeError::InvalidTag(tag)),
+        };
+        Ok(RegisteredTransaction {
+            transaction,
+            expected_heights,
+            status,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bonecoin_core::codec::Encode;
+
+    use crate::state_fixtures::{fixture_bytes, STATE_FIXTURES};
+    use crate::test_support::*;
+
+    /// Upgrades the test-only version 0, which is version 1 without the watchtower addresses.
+    pub(super) const V0_TO_V1: Migration = Migration {
+        from: 0,
+        to: 1,
+        apply: |mut payload| {
+            BTreeSet::<Address>::new().encode_to(&mut payload);
+            Ok(payload)
+        },
+    };
+
+    /// The one-block wallet after paying Charlie, with a coin and a transaction labeled and a
+    /// spending policy set. Returns the node, the wallet, and the payment.
+    fn wallet_with_state() -> (MockNode, Wallet, Transaction) {
+        let (mut node, mut wallet) = make_one_block_blockchain();
+        let b1_id = node.best_block_at_height(1).unwrap();
+        let tx = wallet.create_automatic_transaction(Address::Charlie, 26, 2).unwrap();
+        node.add_block_as_best(b1_id, vec![tx.clone()]);
+        wallet.sync(&node);
+
+        let coin_id = *wallet.coins.keys().next().unwrap();
+        wallet.set_coin_label(&coin_id, "savings").unwrap();
+        wallet.set_tx_label(tx.id(), "coffee");
+        wallet.set_spending_policy(SpendingPolicy {
+            max_per_transaction: Some(500),
+            denylist: HashSet::from([Address::Eve]),
+            ..Default::default()
+        });
+        (node, wallet, tx)
+    }
+
+    #[test]
+    fn state_export_import_round_trip() {
+        let (_, wallet, tx) = wallet_with_state();
+        let coin_id = *wallet.coins.keys().next().unwrap();
+
+        let bytes = wallet.export_state();
+        assert!(bytes.starts_with(STATE_MAGIC));
+        let restored = Wallet::import_state(&bytes).unwrap();
+
+        assert_eq!(restored.best_height(), wallet.best_height());
+        assert_eq!(restored.best_hash(), wallet.best_hash());
+        assert_eq!(restored.net_worth(), wallet.net_worth());
+        assert_eq!(restored.all_coins_of(Address::Alice), wallet.all_coins_of(Address::Alice));
+        assert_eq!(restored.history(), wallet.history());
+        assert_eq!(restored.coin_label(&coin_id), Some("savings"));
+        assert_eq!(restored.tx_label(&tx.id()), Some("coffee"));
+        assert_eq!(restored.spending_policy(), wallet.spending_policy());
+    }
+
+    #[test]
+    fn exporting_is_deterministic_regardless_of_hash_map_ordering() {
+        let (_, wallet, _) = wallet_with_state();
+        let bytes = wallet.export_state();
+        assert_eq!(Wallet::import_state(&bytes).unwrap().export_state(), bytes);
+    }
+
+    #[test]
+    fn imported_wallets_keep_syncing_from_where_the_original_left_off() {
+        let (node, wallet, _) = wallet_with_state();
+        let mut restored = Wallet::import_state(&wallet.export_state()).unwrap();
+
+        let queries = node.how_many_queries();
+        restored.sync(&node);
+        assert!(node.how_many_queries() - queries < 5);
+    }
+
+    #[test]
+    fn foreign_bytes_and_unknown_versions_are_refused() {
+        let (_, wallet, _) = wallet_with_state();
+        assert_eq!(Wallet::import_state(b"nope").err(), Some(StateError::BadMagic));
+        let mut future = wallet.export_state();
+        future[4] = 99;
+        assert_eq!(Wallet::import_state(&future).err(), Some(StateError::UnsupportedVersion(99)));
+    }
+
+    /// The chain every snapshot in `STATE_FIXTURES` was taken on: two blocks rewarding Alice, then
+    /// Bob, with 50 bones each. Returns the node and a wallet of Alice and Bob synced to it.
+    fn fixture_chain() -> (MockNode, Wallet) {
+        let mut node = MockNode::new();
+        let b1 = node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, 50)]);
+        node.add_block_as_best(b1, vec![Transaction::coinbase(Address::Bob, 50)]);
+        node.set_strict(true);
+        let mut wallet = Wallet::new([Address::Alice, Address::Bob].into_iter());
+        wallet.sync(&node);
+        (node, wallet)
+    }
+
+    /// Write each fixture to a file, load it, and hand the version, the loaded wallet and the
+    /// file to `check`. The file is removed afterwards.
+    fn load_every_fixture(mut check: impl FnMut(u16, Wallet, &std::path::Path)) {
+        let path = unique_temp_path("state");
+        for (version, hex) in STATE_FIXTURES {
+            let bytes = fixture_bytes(hex);
+            assert_eq!(state_version(&bytes), Ok(*version));
+            std::fs::write(&path, &bytes).unwrap();
+            check(*version, Wallet::load(&path).unwrap(), &path);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn there_is_a_fixture_for_every_version_and_a_migration_between_each() {
+        let oldest = MIGRATIONS.first().map_or(STATE_VERSION, |migration| migration.from);
+        let versions: Vec<u16> = STATE_FIXTURES.iter().map(|(version, _)| *version).collect();
+        assert_eq!(versions, (oldest..=STATE_VERSION).collect::<Vec<_>>());
+        for (migration, version) in MIGRATIONS.iter().zip(oldest..) {
+            assert_eq!((migration.from, migration.to), (version, version + 1));
+        }
+    }
+
+    #[test]
+    fn snapshots_of_every_version_are_loaded() {
+        let (node, _) = fixture_chain();
+        let alice_coin = Transaction::coinbase(Address::Alice, 50).coin_id(1, 0);
+        let bob_coin = Transaction::coinbase(Address::Bob, 50).coin_id(2, 0);
+        let coinbases = [Transaction::coinbase(Address::Alice, 50).id(), Transaction::coinbase(Address::Bob, 50).id()];
+        load_every_fixture(|version, loaded, _| {
+            // the ids in the snapshot are the ones the node computes for the same chain today
+            assert_eq!(loaded.best_height(), 2, "version {version}");
+            assert_eq!(loaded.best_hash(), node.best_block_at_height(2).unwrap(), "version {version}");
+            assert_eq!(loaded.all_coins_of(Address::Alice).unwrap(), HashSet::from([(alice_coin, 50)]), "version {version}");
+            assert_eq!(loaded.all_coins_of(Address::Bob).unwrap(), HashSet::from([(bob_coin, 50)]), "version {version}");
+            assert_eq!(loaded.net_worth(), 100, "version {version}");
+            let history: Vec<(u64, TransactionId)> = loaded.history().iter().map(|entry| (entry.height, entry.tx_id)).collect();
+            assert_eq!(history, [(1, coinbases[0]), (2, coinbases[1])], "version {version}");
+        });
+    }
+
+    #[test]
+    fn loaded_snapshots_are_rewritten_in_the_current_format() {
+        load_every_fixture(|version, loaded, path| {
+            let rewritten = std::fs::read(path).unwrap();
+            assert_eq!(state_version(&rewritten), Ok(STATE_VERSION), "version {version}");
+            assert_eq!(rewritten, loaded.export_state(), "version {version}");
+        });
+    }
+
+    #[test]
+    fn the_current_version_exports_the_newest_fixture() {
+        let (_, wallet) = fixture_chain();
+        assert_eq!(fixture_bytes(STATE_FIXTURES.last().unwrap().1), wallet.export_state());
+    }
+
+    #[test]
+    fn loading_a_missing_file_fails() {
+        assert!(matches!(Wallet::load(unique_temp_path("state")), Err(StateError::Io(_))));
+    }
+}
@@ -0,0 +1,18 @@
+//! Snapshots of every state format version, for testing the migrations.
+//!
+//! Each was exported by the last build writing its version, from a wallet owning Alice and Bob
+//! synced over two blocks whose coinbases pay 50 bones to Alice and then to Bob. When the format
+//! changes, export the same wallet with the build before the change and add it here. Version 0 was
+//! never released: it is version 1 without the watchtower addresses, for testing the migration that
+//! test builds register from it.
+
+/// The format version of each snapshot, and the snapshot in hex.
+pub(crate) const STATE_FIXTURES: &[(u16, &str)] = &[
+    (0, "424f4e5700000200000000000000000102000000000000002e57cfcf331d3f69020000000000000057c974aab70d41963200000000000000010049155aa6fc6e0ef432000000000000000000020000000000000057c974aab70d4196020000000000000049155aa6fc6e0ef401000000000000000200000000000000895de62a61a662f1bbd0579092acf9160100000000000000000000000000000000010000000000000049155aa6fc6e0ef432000000000000000000000000000000000000ac499fcbbaf78c4f2e57cfcf331d3f690200000000000000000000000000000000010000000000000057c974aab70d419632000000000000000100000000000000000000020000000000000057c974aab70d4196ac499fcbbaf78c4f000000000000000049155aa6fc6e0ef4895de62a61a662f100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000640000000000000000000a000000000000000000000000000000000000000000000000020000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000"),
+    (1, "424f4e5701000200000000000000000102000000000000002e57cfcf331d3f69020000000000000057c974aab70d41963200000000000000010049155aa6fc6e0ef432000000000000000000020000000000000057c974aab70d4196020000000000000049155aa6fc6e0ef401000000000000000200000000000000895de62a61a662f1bbd0579092acf9160100000000000000000000000000000000010000000000000049155aa6fc6e0ef432000000000000000000000000000000000000ac499fcbbaf78c4f2e57cfcf331d3f690200000000000000000000000000000000010000000000000057c974aab70d419632000000000000000100000000000000000000020000000000000057c974aab70d4196ac499fcbbaf78c4f000000000000000049155aa6fc6e0ef4895de62a61a662f100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000640000000000000000000a0000000000000000000000000000000000000000000000000200000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000"),
+];
+
+/// The bytes of a snapshot in `STATE_FIXTURES`.
+pub(crate) fn fixture_bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+}
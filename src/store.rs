@@ -0,0 +1,465 @@
+//! Durable storage for the wallet's coins and sync position.
+//!
+//! The wallet works on the coins it holds in memory. A `WalletStore` attached with `Wallet::with_store`
+//! receives every change to them as well, batched per synced block and committed together with the
+//! new sync position, so a wallet reopened after a crash resumes from the last block it fully applied.
+//! Only coins, the sync position, and the wallet's fingerprint are stored; history, labels, and the
+//! rest of the wallet state are persisted with `export_state`. A store records the fingerprint of the
+//! first wallet attached to it, and attaching it to a wallet with another one fails.
+//!
+//! `MemoryStore` keeps the committed state in memory. `SledStore`, behind the `sled` feature, keeps
+//! it on disk.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bonecoin_core::codec::DecodeError;
+#[cfg(feature = "sled")]
+use bonecoin_core::codec::{Decode, Encode};
+use bonecoin_core::*;
+
+use crate::{Wallet, WalletId};
+
+/// Errors reported by a `WalletStore`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum StoreError {
+    /// The storage backend failed, with its error message.
+    Backend(String),
+    /// Stored bytes could not be decoded.
+    Decode(DecodeError),
+    /// The store belongs to the wallet `found`, not to the wallet `expected` it was attached to.
+    WrongWallet { expected: WalletId, found: WalletId },
+}
+
+impl From<DecodeError> for StoreError {
+    fn from(e: DecodeError) -> Self {
+        StoreError::Decode(e)
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Backend(message) => write!(f, "storage backend failed: {message}"),
+            StoreError::Decode(e) => write!(f, "stored data is corrupt: {e}"),
+            StoreError::WrongWallet { expected, found } => {
+                write!(f, "the store belongs to wallet {found}, not to wallet {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+/// Coin changes to commit to a store in one step.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct StoreBatch {
+    /// Remove every stored coin before applying `changes`.
+    pub clear: bool,
+    /// The new state of every changed coin: `Some` for added coins, `None` for removed ones.
+    pub changes: BTreeMap<CoinId, Option<Coin>>,
+}
+
+impl StoreBatch {
+    pub(crate) fn insert(&mut self, coin_id: CoinId, coin: Coin) {
+        self.changes.insert(coin_id, Some(coin));
+    }
+
+    pub(crate) fn remove(&mut self, coin_id: CoinId) {
+        self.changes.insert(coin_id, None);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.clear = true;
+        self.changes.clear();
+    }
+
+    /// True if committing the batch would not change any coin.
+    pub fn is_empty(&self) -> bool {
+        !self.clear && self.changes.is_empty()
+    }
+}
+
+/// Storage the wallet commits its coins and sync position to.
+pub trait WalletStore: Send + Sync {
+    /// The sync position of the last commit, if anything was ever committed.
+    fn position(&self) -> Result<Option<(u64, BlockId)>, StoreError>;
+
+    /// A single committed coin.
+    fn get(&self, coin_id: &CoinId) -> Result<Option<Coin>, StoreError>;
+
+    /// Every committed coin, ordered by id.
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(CoinId, Coin), StoreError>> + '_>;
+
+    /// Apply the batch and move the sync position to `position`, either completely or not at all.
+    fn commit(&mut self, batch: &StoreBatch, position: (u64, BlockId)) -> Result<(), StoreError>;
+
+    /// The fingerprint of the wallet the store belongs to, if it records one.
+    fn wallet_id(&self) -> Result<Option<WalletId>, StoreError> {
+        Ok(None)
+    }
+
+    /// Record the fingerprint of the wallet the store belongs to. Stores that do not record one ignore it.
+    fn set_wallet_id(&mut self, _wallet_id: WalletId) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+/// A store that keeps the committed state in memory, for tests and short-lived wallets.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    coins: BTreeMap<CoinId, Coin>,
+    position: Option<(u64, BlockId)>,
+    wallet_id: Option<WalletId>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl WalletStore for MemoryStore {
+    fn position(&self) -> Result<Option<(u64, BlockId)>, StoreError> {
+        Ok(self.position)
+    }
+
+    fn get(&self, coin_id: &CoinId) -> Result<Option<Coin>, StoreError> {
+        Ok(self.coins.get(coin_id).cloned())
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(CoinId, Coin), StoreError>> + '_> {
+        Box::new(self.coins.iter().map(|(coin_id, coin)| Ok((*coin_id, coin.clone()))))
+    }
+
+    fn commit(&mut self, batch: &StoreBatch, position: (u64, BlockId)) -> Result<(), StoreError> {
+        if batch.clear {
+            self.coins.clear();
+        }
+        for (coin_id, change) in &batch.changes {
+            match change {
+                Some(coin) => self.coins.insert(*coin_id, coin.clone()),
+                None => self.coins.remove(coin_id),
+            };
+        }
+        self.position = Some(position);
+        Ok(())
+    }
+
+    fn wallet_id(&self) -> Result<Option<WalletId>, StoreError> {
+        Ok(self.wallet_id)
+    }
+
+    fn set_wallet_id(&mut self, wallet_id: WalletId) -> Result<(), StoreError> {
+        self.wallet_id = Some(wallet_id);
+        Ok(())
+    }
+}
+
+/// A store that keeps the committed state in a sled database on disk.
+///
+/// Every commit is applied as a single sled batch and flushed before it returns.
+#[cfg(feature = "sled")]
+pub struct SledStore {
+    db: sled::Db,
+}
+
+/// The default page cache size of a `SledStore`, small enough for constrained devices.
+#[cfg(feature = "sled")]
+pub const SLED_CACHE_BYTES: u64 = 8 * 1024 * 1024;
+#[cfg(feature = "sled")]
+const COIN_PREFIX: &[u8] = b"c";
+#[cfg(feature = "sled")]
+const POSITION_KEY: &[u8] = b"p";
+#[cfg(feature = "sled")]
+const WALLET_ID_KEY: &[u8] = b"w";
+
+#[cfg(feature = "sled")]
+impl SledStore {
+    /// Open the database at `path`, creating it if it does not exist, with a page cache of `SLED_CACHE_BYTES`.
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, StoreError> {
+        Self::open_with_cache(path, SLED_CACHE_BYTES)
+    }
+
+    /// Open the database at `path` with a page cache of at most `cache_bytes`.
+    pub fn open_with_cache(path: impl AsRef<std::path::Path>, cache_bytes: u64) -> Result<Self, StoreError> {
+        let config = sled::Config::new().path(path).cache_capacity(cache_bytes);
+        Ok(SledStore {
+            db: config.open().map_err(backend)?,
+        })
+    }
+
+    /// Coins are keyed by their id in big endian, so sled's key order is coin id order.
+    fn coin_key(coin_id: &CoinId) -> Vec<u8> {
+        let mut id = coin_id.encode();
+        id.reverse();
+        [COIN_PREFIX, &id].concat()
+    }
+}
+
+#[cfg(feature = "sled")]
+fn backend(e: sled::Error) -> StoreError {
+    StoreError::Backend(e.to_string())
+}
+
+#[cfg(feature = "sled")]
+impl WalletStore for SledStore {
+    fn position(&self) -> Result<Option<(u64, BlockId)>, StoreError> {
+        match self.db.get(POSITION_KEY).map_err(backend)? {
+            Some(bytes) => {
+                let mut input = &bytes[..];
+                let height = u64::decode_from(&mut input)?;
+                let hash = BlockId::decode(input)?;
+                Ok(Some((height, hash)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn get(&self, coin_id: &CoinId) -> Result<Option<Coin>, StoreError> {
+        match self.db.get(Self::coin_key(coin_id)).map_err(backend)? {
+            Some(bytes) => Ok(Some(Coin::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(CoinId, Coin), StoreError>> + '_> {
+        Box::new(self.db.scan_prefix(COIN_PREFIX).map(|entry| {
+            let (key, value) = entry.map_err(backend)?;
+            let mut id = key[COIN_PREFIX.len()..].to_vec();
+            id.reverse();
+            Ok((CoinId::decode(&id)?, Coin::decode(&value)?))
+        }))
+    }
+
+    fn commit(&mut self, batch: &StoreBatch, position: (u64, BlockId)) -> Result<(), StoreError> {
+        let mut writes = sled::Batch::default();
+        if batch.clear {
+            for key in self.db.scan_prefix(COIN_PREFIX).keys() {
+                writes.remove(key.map_err(backend)?);
+            }
+        }
+        for (coin_id, change) in &batch.changes {
+            match change {
+                Some(coin) => writes.insert(Self::coin_key(coin_id), coin.encode()),
+                None => writes.remove(Self::coin_key(coin_id)),
+            }
+        }
+        let mut encoded_position = position.0.encode();
+        position.1.encode_to(&mut encoded_position);
+        writes.insert(POSITION_KEY, encoded_position);
+
+        self.db.apply_batch(writes).map_err(backend)?;
+        self.db.flush().map_err(backend)?;
+        Ok(())
+    }
+
+    fn wallet_id(&self) -> Result<Option<WalletId>, StoreError> {
+        match self.db.get(WALLET_ID_KEY).map_err(backend)? {
+            Some(bytes) => Ok(Some(WalletId::decode(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set_wallet_id(&mut self, wallet_id: WalletId) -> Result<(), StoreError> {
+        self.db.insert(WALLET_ID_KEY, wallet_id.encode()).map_err(backend)?;
+        self.db.flush().map_err(backend)?;
+        Ok(())
+    }
+}
+
+impl Wallet {
+    /// Open a wallet owning the given addresses on top of `store`, resuming from the coins and sync position
+    /// of its last commit. Every later change to the wallet's coins is committed to the store.
+    /// Fails with `StoreError::WrongWallet` if the store belongs to a wallet with other addresses.
+    pub fn with_store(addresses: impl Iterator<Item = Address>, store: Box<dyn WalletStore>) -> Result<Self, StoreError> {
+        let mut wallet = Wallet::new(addresses);
+        wallet.attach_store(store)?;
+        Ok(wallet)
+    }
+
+    /// Replace the wallet's coins and sync position with the store's, and commit to it from now on.
+    /// Fails if the store belongs to another wallet, and records the wallet's fingerprint if it belongs to none.
+    pub(crate) fn attach_store(&mut self, mut store: Box<dyn WalletStore>) -> Result<(), StoreError> {
+        let expected = self.fingerprint();
+        match store.wallet_id()? {
+            Some(found) if found != expected => return Err(StoreError::WrongWallet { expected, found }),
+            Some(_) => {}
+            None => store.set_wallet_id(expected)?,
+        }
+        self.coins = store.iter().collect::<Result<_, _>>()?;
+        if let Some((height, hash)) = store.position()? {
+            self.best_block_height = height;
+            self.best_block_hash = hash;
+        }
+        self.coins.start_staging();
+        self.store = Some(store);
+        Ok(())
+    }
+
+    /// Commit the coin changes made since the last commit, along with the current sync position.
+    ///
+    /// Sync commits after every block on its own. Changes made outside sync, such as merging another
+    /// wallet in, are committed by the next sync or by calling this. Does nothing without a store.
+    pub fn flush_store(&mut self) -> Result<(), StoreError> {
+        let Some(store) = self.store.as_mut() else {
+            return Ok(());
+        };
+        let position = (self.best_block_height, self.best_block_hash);
+        let batch = self.coins.take_staged();
+        if batch.is_empty() && store.position()? == Some(position) {
+            return Ok(());
+        }
+        if let Err(e) = store.commit(&batch, position) {
+            // keep the changes so the next commit retries them
+            self.coins.restage(batch);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Detach and return the wallet's store, committing pending changes first.
+    /// The wallet keeps working from memory afterwards.
+    pub fn take_store(&mut self) -> Result<Option<Box<dyn WalletStore>>, StoreError> {
+        self.flush_store()?;
+        self.coins.stop_staging();
+        Ok(self.store.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// A store that fails its next commit when asked to.
+    struct FlakyStore {
+        inner: MemoryStore,
+        fail_next: Arc<AtomicBool>,
+    }
+
+    impl WalletStore for FlakyStore {
+        fn position(&self) -> Result<Option<(u64, BlockId)>, StoreError> {
+            self.inner.position()
+        }
+
+        fn get(&self, coin_id: &CoinId) -> Result<Option<Coin>, StoreError> {
+            self.inner.get(coin_id)
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = Result<(CoinId, Coin), StoreError>> + '_> {
+            self.inner.iter()
+        }
+
+        fn commit(&mut self, batch: &StoreBatch, position: (u64, BlockId)) -> Result<(), StoreError> {
+            if self.fail_next.swap(false, Ordering::SeqCst) {
+                return Err(StoreError::Backend("disk full".to_string()));
+            }
+            self.inner.commit(batch, position)
+        }
+    }
+
+    /// A wallet of Alice on a flaky store, synced to a first block minting her 40 bones, and
+    /// the switch that fails the store's next commit.
+    fn wallet_on_a_flaky_store() -> (MockNode, Wallet, Arc<AtomicBool>) {
+        let fail_next = Arc::new(AtomicBool::new(false));
+        let store = FlakyStore {
+            inner: MemoryStore::new(),
+            fail_next: fail_next.clone(),
+        };
+        let mut wallet = Wallet::with_store(vec![Address::Alice].into_iter(), Box::new(store)).unwrap();
+        let mut node = MockNode::new();
+        node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 40)])]);
+        wallet.sync(&node);
+        (node, wallet, fail_next)
+    }
+
+    /// Mine a payment of 30 bones to Eve and an empty block on top, and sync the wallet to them.
+    /// Returns the id of the last block.
+    fn pay_eve(node: &mut MockNode, wallet: &mut Wallet) -> BlockId {
+        let payment = wallet.create_automatic_transaction(Address::Eve, 30, 0).unwrap();
+        let b2_id = node.add_block_as_best(wallet.best_hash(), vec![payment]);
+        let b3_id = node.add_block_as_best(b2_id, vec![]);
+        wallet.sync(node);
+        b3_id
+    }
+
+    #[test]
+    fn failed_commits_stop_sync_at_the_last_committed_block() {
+        let (mut node, mut wallet, fail_next) = wallet_on_a_flaky_store();
+        fail_next.store(true, Ordering::SeqCst);
+        let payment = wallet.create_automatic_transaction(Address::Eve, 30, 0).unwrap();
+        let b2_id = node.add_block_as_best(wallet.best_hash(), vec![payment]);
+        node.add_block_as_best(b2_id, vec![]);
+        wallet.sync(&node);
+        assert_eq!(wallet.best_height(), 2);
+
+        // the next sync retries the block
+        wallet.sync(&node);
+        assert_eq!(wallet.best_height(), 3);
+    }
+
+    #[test]
+    fn the_store_holds_the_unspent_coins_at_the_synced_position() {
+        let (mut node, mut wallet, _) = wallet_on_a_flaky_store();
+        let funding = wallet.all_coins().map(|(coin_id, _)| *coin_id).next().unwrap();
+        let b3_id = pay_eve(&mut node, &mut wallet);
+
+        let store = wallet.take_store().unwrap().unwrap();
+        assert_eq!(store.position(), Ok(Some((3, b3_id))));
+        assert_eq!(store.get(&funding), Ok(None));
+        let stored: Vec<_> = store.iter().collect::<Result<_, _>>().unwrap();
+        let mut expected: Vec<_> = wallet.all_coins().map(|(coin_id, coin)| (*coin_id, coin.clone())).collect();
+        expected.sort_by_key(|(coin_id, _)| *coin_id);
+        assert_eq!(stored, expected);
+    }
+
+    #[test]
+    fn the_store_is_taken_once() {
+        let (_, mut wallet, _) = wallet_on_a_flaky_store();
+        assert!(wallet.take_store().unwrap().is_some());
+        assert!(wallet.take_store().unwrap().is_none());
+    }
+
+    #[test]
+    fn wallets_reopened_on_a_store_resume_from_it() {
+        let (mut node, mut wallet, _) = wallet_on_a_flaky_store();
+        let b3_id = pay_eve(&mut node, &mut wallet);
+
+        let store = wallet.take_store().unwrap().unwrap();
+        let reopened = Wallet::with_store(vec![Address::Alice].into_iter(), store).unwrap();
+        assert_eq!((reopened.best_height(), reopened.best_hash()), (3, b3_id));
+        assert_eq!(reopened.net_worth(), 10);
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn sled_store_survives_reopening() {
+        // sled's background flusher holds the database lock for a moment after the last handle is dropped
+        fn open(path: &std::path::Path) -> SledStore {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+            loop {
+                match SledStore::open(path) {
+                    Err(StoreError::Backend(e)) if e.contains("could not acquire lock") && std::time::Instant::now() < deadline => {
+                        std::thread::sleep(std::time::Duration::from_millis(20));
+                    }
+                    opened => return opened.unwrap(),
+                }
+            }
+        }
+
+        let path = unique_temp_path("sled");
+        let (node, _) = make_one_block_blockchain();
+        let mut wallet = Wallet::with_store(vec![Address::Alice].into_iter(), Box::new(open(&path))).unwrap();
+        wallet.sync(&node);
+        drop(wallet);
+
+        let reopened = Wallet::with_store(vec![Address::Alice].into_iter(), Box::new(open(&path))).unwrap();
+        assert_eq!(reopened.best_height(), 1);
+        assert_eq!(reopened.net_worth(), 115);
+        drop(reopened);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
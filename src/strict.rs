@@ -0,0 +1,233 @@
+//! Checking what the node serves against what the wallet already knows.
+//!
+//! With `WalletConfig::strict_sync` set, sync checks every block before applying it and records a
+//! `SyncWarning` for anything a correct chain would not contain. The checks only use information the
+//! wallet has: its own coins, and the block it synced last. The block is still applied, since the
+//! wallet cannot tell a lying node from a chain that really is invalid; the warnings tell the
+//! application to stop trusting the node.
+
+use bonecoin_core::*;
+
+use crate::{Wallet, EVENT_CAPACITY};
+
+/// Something suspicious strict sync found in a block served by the node.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SyncWarning {
+    /// The block does not extend the block the wallet synced last.
+    NotSequential {
+        /// The height and id of the block the wallet synced last.
+        expected_parent: (u64, BlockId),
+        /// The number and parent the block claims.
+        found: (u64, BlockId),
+    },
+    /// The block's contents do not hash to the id the node gave for it.
+    WrongBlockId { requested: BlockId, found: BlockId },
+    /// The block's transactions weigh more than `MAX_BLOCK_WEIGHT` together.
+    BlockTooLarge { weight: usize },
+    /// A transaction consumes more inputs, creates more outputs, or weighs more than a transaction may.
+    TooLarge { tx_id: TransactionId },
+    /// A transaction touching the wallet has a version a valid chain may not contain.
+    UnsupportedVersion { tx_id: TransactionId, version: u16 },
+    /// An input spends a wallet coin without a signature that satisfies the coin's owner.
+    InvalidSignature { tx_id: TransactionId, coin_id: CoinId },
+    /// A transaction creates more bones than the wallet coins it consumes hold, or mints bones without being the block's coinbase.
+    /// Only checked for transactions whose inputs are all wallet coins.
+    Unbalanced { tx_id: TransactionId, consumed: u64, produced: u64 },
+}
+
+impl Wallet {
+    /// Take the warnings strict sync recorded since the last call, oldest first.
+    /// At most `EVENT_CAPACITY` are kept; older ones are dropped.
+    pub fn take_sync_warnings(&mut self) -> Vec<SyncWarning> {
+        self.sync_warnings.drain(..).collect()
+    }
+
+    /// Check a block the node served for height `best_block_height + 1` before applying it.
+    pub(crate) fn check_block(&mut self, block_id: BlockId, block: &Block) {
+        let mut warnings = Vec::new();
+        if block.id() != block_id {
+            warnings.push(SyncWarning::WrongBlockId { requested: block_id, found: block.id() });
+        }
+        if block.number != self.best_block_height + 1 || block.parent != self.best_block_hash {
+            warnings.push(SyncWarning::NotSequential {
+                expected_parent: (self.best_block_height, self.best_block_hash),
+                found: (block.number, block.parent),
+            });
+        }
+
+        if block.weight() > MAX_BLOCK_WEIGHT {
+            warnings.push(SyncWarning::BlockTooLarge { weight: block.weight() });
+        }
+
+        for (index, transaction) in block.body.iter().enumerate() {
+            if !transaction.is_within_limits() {
+                warnings.push(SyncWarning::TooLarge { tx_id: transaction.id() });
+            }
+            let pays_wallet = transaction.outputs.iter().any(|coin| self.owns(&coin.owner));
+            let produced: u64 = transaction.outputs.iter().map(Coin::native_value).sum();
+            let spends_wallet = transaction.iter_input_coin_ids().any(|coin_id| self.coins.contains_key(&coin_id));
+            if !transaction.has_supported_version() && (pays_wallet || spends_wallet) {
+                warnings.push(SyncWarning::UnsupportedVersion { tx_id: transaction.id(), version: transaction.version });
+            }
+            if transaction.is_coinbase() {
+                if index > 0 && pays_wallet {
+                    warnings.push(SyncWarning::Unbalanced { tx_id: transaction.id(), consumed: 0, produced });
+                }
+                continue;
+            }
+
+            let mut consumed = Some(0u64);
+            for input in &transaction.inputs {
+                match self.coins.get(&input.coin_id) {
+                    Some(coin) => {
+                        if !coin.owner.is_satisfied_by(&input.signature) {
+                            warnings.push(SyncWarning::InvalidSignature { tx_id: transaction.id(), coin_id: input.coin_id });
+                        }
+                        consumed = consumed.map(|consumed| consumed.saturating_add(coin.native_value()));
+                    }
+                    None => consumed = None,
+                }
+            }
+            if let Some(consumed) = consumed.filter(|consumed| *consumed < produced) {
+                warnings.push(SyncWarning::Unbalanced { tx_id: transaction.id(), consumed, produced });
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        for warning in &warnings {
+            tracing::warn!(height = block.number, ?warning, "suspicious block");
+        }
+        self.sync_warnings.extend(warnings);
+        let excess = self.sync_warnings.len().saturating_sub(EVENT_CAPACITY);
+        self.sync_warnings.drain(..excess);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// Serves the block above the requested height.
+    struct Skipping<'a>(&'a MockNode);
+
+    impl NodeEndpoint for Skipping<'_> {
+        fn best_block_at_height(&self, h: u64) -> Option<BlockId> {
+            self.0.best_block_at_height(if h == 0 { 0 } else { h + 1 })
+        }
+
+        fn entire_block(&self, id: &BlockId) -> Option<Block> {
+            self.0.entire_block(id)
+        }
+    }
+
+    /// A node whose first block rewards Alice, and whose second holds a forged spend of that
+    /// reward to Eve and a transaction minting Alice 1,000 bones out of nothing. Returns the node,
+    /// the forged spend and the minting transaction.
+    fn node_with_a_bad_block() -> (MockNode, Transaction, Transaction) {
+        let mut node = MockNode::new();
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+        let reward = Transaction::coinbase(Address::Alice, BLOCK_REWARD).coin_id(1, 0);
+        let forged = Transaction {
+            inputs: vec![Input {
+                coin_id: reward,
+                signature: Signature::Invalid,
+            }],
+            ..mint([(Address::Eve, 80)])
+        };
+        let minted = Transaction::coinbase(Address::Alice, 1_000);
+        node.add_block_as_best(b1_id, vec![Transaction::coinbase(Address::Eve, BLOCK_REWARD), forged.clone(), minted.clone()]);
+        (node, forged, minted)
+    }
+
+    fn strict_wallet() -> Wallet {
+        Wallet::builder().address(Address::Alice).strict_sync(true).build().unwrap()
+    }
+
+    #[test]
+    fn lenient_sync_raises_no_warnings() {
+        let (node, _, _) = node_with_a_bad_block();
+        let mut lenient = wallet_with_alice();
+        lenient.sync(&node);
+        assert!(lenient.take_sync_warnings().is_empty());
+    }
+
+    #[test]
+    fn strict_sync_warns_about_bad_signatures_and_unbalanced_transactions() {
+        let (node, forged, minted) = node_with_a_bad_block();
+        let mut wallet = strict_wallet();
+        wallet.sync(&node);
+        assert_eq!(
+            wallet.take_sync_warnings(),
+            vec![
+                SyncWarning::InvalidSignature { tx_id: forged.id(), coin_id: forged.inputs[0].coin_id },
+                SyncWarning::Unbalanced { tx_id: forged.id(), consumed: BLOCK_REWARD, produced: 80 },
+                SyncWarning::Unbalanced { tx_id: minted.id(), consumed: 0, produced: 1_000 },
+            ]
+        );
+        assert!(wallet.take_sync_warnings().is_empty());
+    }
+
+    #[test]
+    fn strict_sync_warns_about_blocks_out_of_sequence() {
+        let (node, _, _) = node_with_a_bad_block();
+        let mut skipped = strict_wallet();
+        skipped.sync(&Skipping(&node));
+        assert_eq!(
+            skipped.take_sync_warnings()[0],
+            SyncWarning::NotSequential {
+                expected_parent: (0, Block::genesis().id()),
+                found: (2, node.best_block_at_height(1).unwrap()),
+            }
+        );
+    }
+
+    /// A strict wallet of Alice spending coinbases right away, synced to a block rewarding her,
+    /// and a payment of 10 bones to Bob out of the reward. Returns the node, the wallet, the id
+    /// of the block and the payment.
+    fn paying_out_of_a_reward() -> (MockNode, Wallet, BlockId, Transaction) {
+        let mut node = MockNode::new();
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+        let mut wallet = Wallet::builder().address(Address::Alice).strict_sync(true).coinbase_maturity(0).build().unwrap();
+        wallet.sync(&node);
+        let payment = wallet.create_automatic_transaction(Address::Bob, 10, 0).unwrap();
+        (node, wallet, b1_id, payment)
+    }
+
+    #[test]
+    fn wallets_author_the_current_version() {
+        let (_, wallet, _, payment) = paying_out_of_a_reward();
+        assert_eq!(wallet.authorable_versions(), &[TRANSACTION_VERSION]);
+        assert_eq!(payment.version, TRANSACTION_VERSION);
+    }
+
+    #[test]
+    fn nodes_refuse_transactions_of_unknown_versions() {
+        let (node, _, _, payment) = paying_out_of_a_reward();
+        let future = Transaction { version: 2, ..payment.clone() };
+        assert_ne!(future.id(), payment.id());
+        assert!(!node.submit_transaction(&future));
+        assert!(node.submit_transaction(&payment));
+    }
+
+    #[test]
+    fn transactions_of_unknown_versions_are_not_signed() {
+        let (_, wallet, _, payment) = paying_out_of_a_reward();
+        let mut partial = PartialTransaction::new(Transaction { version: 2, ..payment });
+        for input in &mut partial.transaction.inputs {
+            input.signature = Signature::Invalid;
+        }
+        assert_eq!(wallet.sign_partial(&mut partial), 0);
+    }
+
+    #[test]
+    fn strict_sync_flags_mined_transactions_of_unknown_versions() {
+        let (mut node, mut wallet, b1_id, payment) = paying_out_of_a_reward();
+        let future = Transaction { version: 2, ..payment };
+        node.add_block_as_best(b1_id, vec![Transaction::coinbase(Address::Eve, BLOCK_REWARD), future.clone()]);
+        wallet.sync(&node);
+        assert_eq!(
+            wallet.take_sync_warnings(),
+            vec![SyncWarning::UnsupportedVersion { tx_id: future.id(), version: 2 }]
+        );
+    }
+}
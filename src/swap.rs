@@ -0,0 +1,342 @@
+//! Coin-for-coin swaps between two wallets.
+//!
+//! Each party describes its half of the swap: the coins it gives and the address it wants to be
+//! paid at. Both halves determine a single transaction consuming the coins of both parties and
+//! paying each party the other's coins, value for value and asset for asset. The transaction is
+//! assembled as a `PartialTransaction`, and each wallet only signs it after checking that it is
+//! exactly the agreed swap, so neither side can complete it alone or alter the terms.
+//!
+//! A swap can also start from a one-sided offer, when the taker is not known yet. `offer_swap` signs
+//! each offered coin with `SINGLE | ANYONECANPAY` together with the output it wants in return, which
+//! fixes the maker's terms while leaving the taker free to add its own inputs and outputs.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bonecoin_core::*;
+
+use crate::{PartialTransaction, Wallet};
+
+/// One party's side of a swap.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SwapHalf {
+    /// The coins this party gives up, with their details so the other party can check them.
+    pub coins: Vec<(CoinId, Coin)>,
+    /// The address at which this party receives the other party's coins.
+    pub receive_address: Address,
+}
+
+/// Errors that can occur while preparing or signing a swap.
+#[derive(Eq, PartialEq, Debug)]
+pub enum SwapError {
+    /// The wallet cannot offer the requested coins.
+    Wallet(WalletError),
+    /// The partial transaction is not the swap described by the two halves.
+    TermsMismatch,
+}
+
+impl From<WalletError> for SwapError {
+    fn from(e: WalletError) -> Self {
+        SwapError::Wallet(e)
+    }
+}
+
+impl fmt::Display for SwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwapError::Wallet(e) => write!(f, "{e}"),
+            SwapError::TermsMismatch => write!(f, "the transaction is not the agreed swap"),
+        }
+    }
+}
+
+impl std::error::Error for SwapError {}
+
+/// Build the unsigned swap transaction for two halves. Swapping the arguments gives a different
+/// (but equally valid) transaction, so both parties must agree on who goes first.
+pub fn swap_transaction(first: &SwapHalf, second: &SwapHalf) -> PartialTransaction {
+    let inputs = first
+        .coins
+        .iter()
+        .chain(&second.coins)
+        .map(|(coin_id, _)| Input {
+            coin_id: *coin_id,
+            signature: Signature::Invalid,
+        })
+        .collect();
+    let pay = |half: &SwapHalf, to: &Address| {
+        half.coins
+            .iter()
+            .map(|(_, coin)| Coin {
+                owner: to.clone(),
+                ..coin.clone()
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut outputs = pay(first, &second.receive_address);
+    outputs.extend(pay(second, &first.receive_address));
+    PartialTransaction::new(Transaction { version: TRANSACTION_VERSION, inputs, outputs })
+}
+
+impl Wallet {
+    /// Offer the given coins for a swap, to be paid at the wallet's first address.
+    pub fn swap_half(&self, coin_ids: Vec<CoinId>) -> WalletResult<SwapHalf> {
+        if coin_ids.is_empty() {
+            return Err(WalletError::ZeroInputs);
+        }
+        let receive_address = self.primary_address()?;
+        let coins = coin_ids
+            .into_iter()
+            .map(|coin_id| {
+                let coin = self.coins.get(&coin_id).ok_or(WalletError::UnknownCoin(coin_id))?;
+                if !self.is_mature(&coin_id) {
+                    return Err(WalletError::ImmatureCoin(coin_id));
+                }
+                self.check_spending_enabled(&coin_id)?;
+                Ok((coin_id, coin.clone()))
+            })
+            .collect::<WalletResult<_>>()?;
+        Ok(SwapHalf { coins, receive_address })
+    }
+
+    /// Sign this wallet's inputs of a swap after checking that the partial transaction is exactly
+    /// the swap of `first` and `second`, one of which must be this wallet's own half.
+    pub fn sign_swap(
+        &self,
+        partial: &mut PartialTransaction,
+        first: &SwapHalf,
+        second: &SwapHalf,
+    ) -> Result<(), SwapError> {
+        let own_half = |half: &SwapHalf| {
+            self.addresses.contains(&half.receive_address)
+                && half.coins.iter().all(|(coin_id, coin)| self.coins.get(coin_id) == Some(coin))
+        };
+        if !own_half(first) && !own_half(second) {
+            return Err(SwapError::TermsMismatch);
+        }
+        if partial.unsigned() != swap_transaction(first, second).unsigned() {
+            return Err(SwapError::TermsMismatch);
+        }
+        self.sign_partial(partial);
+        Ok(())
+    }
+
+    /// Offer the given coins for a swap before the other party is known, asking for one coin of the
+    /// given value and asset (`None` for bones) in return for each, paid at the wallet's first address.
+    ///
+    /// Each offered input is signed with `SINGLE | ANYONECANPAY`, so it commits only to itself and to
+    /// the wanted coin at its index. Anyone can take the offer with `take_swap_offer`.
+    pub fn offer_swap(&self, give: Vec<CoinId>, want: Vec<(u64, Option<AssetId>)>) -> Result<PartialTransaction, SwapError> {
+        if give.len() != want.len() {
+            return Err(SwapError::TermsMismatch);
+        }
+        let half = self.swap_half(give)?;
+        let transaction = Transaction {
+            version: TRANSACTION_VERSION,
+            inputs: half
+                .coins
+                .iter()
+                .map(|(coin_id, _)| Input {
+                    coin_id: *coin_id,
+                    signature: Signature::Invalid,
+                })
+                .collect(),
+            outputs: want
+                .into_iter()
+                .map(|(value, asset_id)| Coin {
+                    value,
+                    owner: half.receive_address.clone(),
+                    asset_id,
+                })
+                .collect(),
+        };
+        let mut offer = PartialTransaction::with_input_coins(transaction, half.coins.into_iter().map(|(_, coin)| coin).collect());
+        self.sign_partial_with(&mut offer, SigHash::SINGLE | SigHash::ANYONECANPAY);
+        Ok(offer)
+    }
+
+    /// Take a swap offer made with `offer_swap`: pay the wanted coins from this wallet, take the
+    /// offered coins at the wallet's first address, and sign. The offer is then complete.
+    ///
+    /// Fails with `SwapError::TermsMismatch` unless every offered input carries its coin and a
+    /// signature that still holds once the taker's inputs and outputs are added.
+    pub fn take_swap_offer(&self, offer: &mut PartialTransaction) -> Result<(), SwapError> {
+        let offered = offer.coins.len();
+        if offer.transaction.outputs.len() != offered || !offer.is_complete() || offer.coins.iter().any(Option::is_none) {
+            return Err(SwapError::TermsMismatch);
+        }
+        let mut wanted: BTreeMap<Option<AssetId>, u64> = BTreeMap::new();
+        for coin in &offer.transaction.outputs {
+            *wanted.entry(coin.asset_id).or_default() += coin.value;
+        }
+        let receive_address = self.primary_address()?;
+        let mut taken = offer.clone();
+        for (asset_id, needed) in wanted {
+            let (inputs, selected) = self.select_asset_coins(asset_id, needed)?;
+            for input in inputs {
+                taken.add_input(input.coin_id, self.coins.get(&input.coin_id).cloned());
+            }
+            if let Some(change) = self.change_output(selected - needed, asset_id)? {
+                taken.add_output(change);
+            }
+        }
+        for coin in offer.coins.iter().flatten() {
+            taken.add_output(Coin {
+                owner: receive_address.clone(),
+                ..coin.clone()
+            });
+        }
+        self.sign_partial(&mut taken);
+        if taken.unsigned_inputs().iter().any(|&index| index < offered) {
+            return Err(SwapError::TermsMismatch);
+        }
+        *offer = taken;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A node whose only block mints 70 bones to Alice and 30 to Bob, and a wallet of each synced
+    /// to it. Returns the node, both wallets, and the minting transaction.
+    fn alice_and_bob_with_coins() -> (MockNode, Wallet, Wallet, Transaction) {
+        let mut node = MockNode::new();
+        let minted = mint([(Address::Alice, 70), (Address::Bob, 30)]);
+        node.add_block_as_best(Block::genesis().id(), vec![minted.clone()]);
+        let mut alice = wallet_with_alice();
+        let mut bob = Wallet::new(vec![Address::Bob].into_iter());
+        alice.sync(&node);
+        bob.sync(&node);
+        (node, alice, bob, minted)
+    }
+
+    #[test]
+    fn swap_halves_only_offer_coins_of_the_wallet() {
+        let (_, _, bob, minted) = alice_and_bob_with_coins();
+        assert_eq!(bob.swap_half(vec![minted.coin_id(1, 0)]), Err(WalletError::UnknownCoin(minted.coin_id(1, 0))));
+    }
+
+    #[test]
+    fn a_swap_signed_by_one_side_is_not_final() {
+        let (_, alice, bob, minted) = alice_and_bob_with_coins();
+        let alice_half = alice.swap_half(vec![minted.coin_id(1, 0)]).unwrap();
+        let bob_half = bob.swap_half(vec![minted.coin_id(1, 1)]).unwrap();
+
+        let mut partial = swap_transaction(&alice_half, &bob_half);
+        alice.sign_swap(&mut partial, &alice_half, &bob_half).unwrap();
+        assert_eq!(partial.unsigned_inputs(), vec![1]);
+        assert_eq!(partial.finalize(), None);
+    }
+
+    #[test]
+    fn swaps_paying_less_than_agreed_are_not_signed() {
+        let (_, alice, bob, minted) = alice_and_bob_with_coins();
+        let alice_half = alice.swap_half(vec![minted.coin_id(1, 0)]).unwrap();
+        let bob_half = bob.swap_half(vec![minted.coin_id(1, 1)]).unwrap();
+
+        let cheaper = SwapHalf {
+            coins: vec![(
+                minted.coin_id(1, 0),
+                Coin {
+                    value: 69,
+                    owner: Address::Alice,
+                    asset_id: None,
+                },
+            )],
+            ..alice_half.clone()
+        };
+        let mut tampered = swap_transaction(&cheaper, &bob_half);
+        assert_eq!(bob.sign_swap(&mut tampered, &alice_half, &bob_half), Err(SwapError::TermsMismatch));
+    }
+
+    #[test]
+    fn swaps_signed_by_both_sides_exchange_the_coins() {
+        let (mut node, mut alice, mut bob, minted) = alice_and_bob_with_coins();
+        let alice_half = alice.swap_half(vec![minted.coin_id(1, 0)]).unwrap();
+        let bob_half = bob.swap_half(vec![minted.coin_id(1, 1)]).unwrap();
+
+        let mut partial = swap_transaction(&alice_half, &bob_half);
+        alice.sign_swap(&mut partial, &alice_half, &bob_half).unwrap();
+        bob.sign_swap(&mut partial, &alice_half, &bob_half).unwrap();
+        let swap = partial.finalize().unwrap();
+        node.add_block_as_best(node.best_block_at_height(1).unwrap(), vec![swap]);
+        alice.sync(&node);
+        bob.sync(&node);
+        assert_eq!(alice.net_worth(), 30);
+        assert_eq!(bob.net_worth(), 70);
+    }
+
+    /// A node whose only block pays Alice a coin of 10 bones and Bob two of 6, a wallet of each
+    /// synced to it, and Alice's offer of her coin for 8 bones. Returns the node, both wallets
+    /// and the offer.
+    fn alice_offering_10_for_8() -> (MockNode, Wallet, Wallet, PartialTransaction) {
+        let mut node = MockNode::new();
+        let funding = mint([(Address::Alice, 10), (Address::Bob, 6), (Address::Bob, 6)]);
+        node.add_block_as_best(Block::genesis().id(), vec![funding.clone()]);
+        let mut alice = wallet_with_alice();
+        let mut bob = Wallet::new(vec![Address::Bob].into_iter());
+        alice.sync(&node);
+        bob.sync(&node);
+        let offer = alice.offer_swap(vec![funding.coin_id(1, 0)], vec![(8, None)]).unwrap();
+        (node, alice, bob, offer)
+    }
+
+    #[test]
+    fn offers_must_want_something() {
+        let (_, alice, _, offer) = alice_offering_10_for_8();
+        assert_eq!(alice.offer_swap(vec![offer.transaction().inputs[0].coin_id], vec![]), Err(SwapError::TermsMismatch));
+    }
+
+    #[test]
+    fn offers_commit_only_to_their_own_input_and_output() {
+        let (_, _, _, offer) = alice_offering_10_for_8();
+        assert!(offer.is_complete());
+        assert!(matches!(
+            offer.transaction().inputs[0].signature,
+            Signature::Committed { sighash, .. } if sighash == SigHash::SINGLE | SigHash::ANYONECANPAY
+        ));
+    }
+
+    #[test]
+    fn offers_with_a_changed_wanted_coin_cannot_be_taken() {
+        let (_, _, bob, mut offer) = alice_offering_10_for_8();
+        offer.transaction.outputs[0].value = 1;
+        assert_eq!(bob.take_swap_offer(&mut offer), Err(SwapError::TermsMismatch));
+    }
+
+    #[test]
+    fn taken_offers_pay_the_maker_and_the_taker() {
+        let (mut node, mut alice, mut bob, mut offer) = alice_offering_10_for_8();
+        bob.take_swap_offer(&mut offer).unwrap();
+        let swap = offer.finalize().unwrap();
+        assert_eq!(swap.inputs.len(), 3);
+        assert_eq!(
+            swap.outputs,
+            [
+                Coin { value: 8, owner: Address::Alice, asset_id: None },
+                Coin { value: 4, owner: Address::Bob, asset_id: None },
+                Coin { value: 10, owner: Address::Bob, asset_id: None },
+            ]
+        );
+
+        node.add_block_as_best(alice.best_hash(), vec![swap]);
+        alice.sync(&node);
+        bob.sync(&node);
+        assert_eq!((alice.net_worth(), bob.net_worth()), (8, 14));
+    }
+
+    #[test]
+    fn signatures_over_all_outputs_do_not_survive_another_output() {
+        let (_, alice, _, offer) = alice_offering_10_for_8();
+        let mut partial = PartialTransaction::new(Transaction {
+            inputs: vec![Input { coin_id: offer.transaction().inputs[0].coin_id, signature: Signature::Invalid }],
+            ..mint([(Address::Eve, 8)])
+        });
+        assert_eq!(alice.sign_partial_with(&mut partial, SigHash::ALL), 1);
+        assert!(partial.is_complete());
+        partial.add_output(Coin { value: 1, owner: Address::Eve, asset_id: None });
+        assert_eq!(partial.unsigned_inputs(), [0]);
+    }
+}
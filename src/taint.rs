@@ -0,0 +1,174 @@
+//! Where a coin's value came from, and how much of it passed through addresses the user distrusts.
+//!
+//! `coin_ancestry` walks back from a coin through the transactions that created it and its
+//! ancestors, as far as the wallet's history or the indexer knows them, up to `max_depth`
+//! transactions. The walk stops at coins of denylisted addresses, at minted coins, at coins whose
+//! origin is unknown, and at the depth limit; the owners of those coins are the sources.
+//!
+//! The taint score spreads each transaction's tainted share over all of its outputs: a coin of a
+//! denylisted address is fully tainted, and a transaction's outputs are as tainted as the
+//! value-weighted mean of its known inputs. Inputs the walk cannot see, such as coins of others in
+//! a wallet's transactions, count for nothing, so the wallet scores only what its history shows.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use bonecoin_core::*;
+
+use crate::flow::{FlowSource, WalletFlows};
+use crate::{Indexer, Wallet};
+
+/// The origins of a coin's value.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Ancestry {
+    /// The owners of the coins the walk stopped at.
+    pub sources: BTreeSet<Address>,
+    /// The denylisted addresses the coin's value passed through, its own owner included.
+    pub flagged: BTreeSet<Address>,
+    /// The share of the coin's value traced to denylisted addresses, from 0 (none) to 1 (all of it).
+    pub taint: f64,
+}
+
+impl Ancestry {
+    /// Whether any of the coin's value passed through a denylisted address.
+    pub fn is_tainted(&self) -> bool {
+        !self.flagged.is_empty()
+    }
+}
+
+/// Walks back from one coin, remembering the taint of every coin visited at each depth.
+struct Walk<'a> {
+    source: &'a dyn FlowSource,
+    denylist: &'a HashSet<Address>,
+    max_depth: usize,
+    ancestry: Ancestry,
+    taints: HashMap<(CoinId, usize), f64>,
+}
+
+impl Walk<'_> {
+    /// The taint of a coin reached `depth` transactions back from the start.
+    fn taint(&mut self, coin_id: CoinId, coin: &Coin, depth: usize) -> f64 {
+        if let Some(taint) = self.taints.get(&(coin_id, depth)) {
+            return *taint;
+        }
+        let inputs: Vec<(CoinId, Coin)> = match self.source.links(&coin_id).0 {
+            Some(tx_id) if depth < self.max_depth && !self.denylist.contains(&coin.owner) => {
+                let (spent, _) = self.source.coins_of_transaction(&tx_id);
+                spent.into_iter().filter_map(|input| Some((input, self.source.coin(&input)?))).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        let taint = if self.denylist.contains(&coin.owner) {
+            self.ancestry.flagged.insert(coin.owner.clone());
+            self.ancestry.sources.insert(coin.owner.clone());
+            1.0
+        } else if inputs.is_empty() {
+            self.ancestry.sources.insert(coin.owner.clone());
+            0.0
+        } else {
+            let total: u64 = inputs.iter().map(|(_, input)| input.value).sum();
+            let tainted: f64 = inputs.iter().map(|(input_id, input)| self.taint(*input_id, input, depth + 1) * input.value as f64).sum();
+            if total == 0 { 0.0 } else { tainted / total as f64 }
+        };
+        self.taints.insert((coin_id, depth), taint);
+        taint
+    }
+}
+
+fn ancestry_of(source: &dyn FlowSource, coin_id: CoinId, coin: &Coin, max_depth: usize, denylist: &HashSet<Address>) -> Ancestry {
+    let mut walk = Walk {
+        source,
+        denylist,
+        max_depth,
+        ancestry: Ancestry {
+            sources: BTreeSet::new(),
+            flagged: BTreeSet::new(),
+            taint: 0.0,
+        },
+        taints: HashMap::new(),
+    };
+    let taint = walk.taint(coin_id, coin, 0);
+    Ancestry { taint, ..walk.ancestry }
+}
+
+impl Wallet {
+    /// Trace a coin the wallet holds or has held back through the wallet's history, at most
+    /// `max_depth` transactions, scoring how much of its value came from `denylist`.
+    pub fn coin_ancestry(&self, coin_id: &CoinId, max_depth: usize, denylist: &HashSet<Address>) -> WalletResult<Ancestry> {
+        let flows = WalletFlows::new(self);
+        let coin = flows.coin(coin_id).ok_or(WalletError::UnknownCoin(*coin_id))?;
+        Ok(ancestry_of(&flows, *coin_id, &coin, max_depth, denylist))
+    }
+}
+
+impl Indexer {
+    /// Trace a coin of the indexed chain back at most `max_depth` transactions, scoring how much
+    /// of its value came from `denylist`. Returns `None` for a coin that was never indexed.
+    pub fn coin_ancestry(&self, coin_id: &CoinId, max_depth: usize, denylist: &HashSet<Address>) -> Option<Ancestry> {
+        let coin = self.coin(coin_id)?;
+        Some(ancestry_of(self, *coin_id, coin, max_depth, denylist))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashSet};
+
+    use crate::test_support::*;
+
+    /// Eve and Charlie are minted 50 bones each in block 1 and mix them into a single 80 bone coin
+    /// for Dave in block 2. Returns an indexer and a wallet of Dave synced to it, and Dave's coin.
+    fn dave_receives_a_mix() -> (Indexer, Wallet, CoinId) {
+        let mut node = MockNode::new();
+        let mut indexer = Indexer::new();
+        let mut wallet = Wallet::new([Address::Dave].into_iter());
+
+        let (dirty, clean) = (mint([(Address::Eve, 50)]), mint([(Address::Charlie, 50)]));
+        let mix = Transaction {
+            inputs: vec![
+                Input { coin_id: dirty.coin_id(1, 0), signature: Signature::Valid(Address::Eve) },
+                Input { coin_id: clean.coin_id(1, 0), signature: Signature::Valid(Address::Charlie) },
+            ],
+            ..mint([(Address::Dave, 80)])
+        };
+
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![dirty, clean]);
+        node.add_block_as_best(b1_id, vec![mix.clone()]);
+        indexer.sync(&node);
+        wallet.sync(&node);
+        (indexer, wallet, mix.coin_id(2, 0))
+    }
+
+    #[test]
+    fn ancestry_scores_the_share_of_value_from_denylisted_addresses() {
+        let (indexer, _, received) = dave_receives_a_mix();
+
+        let ancestry = indexer.coin_ancestry(&received, 5, &HashSet::from([Address::Eve])).unwrap();
+        assert!(ancestry.is_tainted());
+        assert_eq!(ancestry.taint, 0.5);
+        assert_eq!(ancestry.flagged, BTreeSet::from([Address::Eve]));
+        assert_eq!(ancestry.sources, BTreeSet::from([Address::Charlie, Address::Eve]));
+    }
+
+    #[test]
+    fn the_depth_limit_stops_the_walk() {
+        let (indexer, _, received) = dave_receives_a_mix();
+
+        let shallow = indexer.coin_ancestry(&received, 0, &HashSet::from([Address::Eve])).unwrap();
+        assert_eq!((shallow.taint, shallow.sources), (0.0, BTreeSet::from([Address::Dave])));
+    }
+
+    #[test]
+    fn wallets_only_see_the_ancestry_of_their_own_coins() {
+        let (_, wallet, received) = dave_receives_a_mix();
+        let denylist = HashSet::from([Address::Eve]);
+
+        // the mix's inputs belonged to others
+        let seen = wallet.coin_ancestry(&received, 5, &denylist).unwrap();
+        assert!(!seen.is_tainted());
+        assert_eq!(seen.sources, BTreeSet::from([Address::Dave]));
+
+        let unknown = Input::dummy().coin_id;
+        assert_eq!(wallet.coin_ancestry(&unknown, 5, &denylist), Err(WalletError::UnknownCoin(unknown)));
+    }
+}
@@ -0,0 +1,77 @@
+//! Fixtures shared by the tests of the feature modules.
+//!
+//! Each feature keeps its tests in a `tests` module at the end of its own file, which starts with
+//! `use crate::test_support::*;` to see everything the crate root does along with these helpers.
+
+pub(crate) use crate::*;
+
+pub(crate) fn wallet_with_alice() -> Wallet {
+    Wallet::new(vec![Address::Alice].into_iter())
+}
+
+pub(crate) fn wallet_with_alice_and_bob() -> Wallet {
+    Wallet::new(vec![Address::Alice, Address::Bob].into_iter())
+}
+
+/// A transaction minting native coins to the given owners. Its dummy input keeps it from being a
+/// coinbase, so the coins are spendable right away.
+pub(crate) fn mint(outputs: impl IntoIterator<Item = (Address, u64)>) -> Transaction {
+    Transaction {
+        version: TRANSACTION_VERSION,
+        inputs: vec![Input::dummy()],
+        outputs: outputs
+            .into_iter()
+            .map(|(owner, value)| Coin {
+                value,
+                owner,
+                asset_id: None,
+            })
+            .collect(),
+    }
+}
+
+/// This marker transaction can be useful to place on the new side of the fork.
+pub(crate) fn marker_tx() -> Transaction {
+    mint([(Address::Custom(123), 123)])
+}
+
+/// A transaction spending one coin of `owner`, validly signed, into native coins.
+pub(crate) fn spend(coin_id: CoinId, owner: Address, outputs: impl IntoIterator<Item = (Address, u64)>) -> Transaction {
+    Transaction {
+        inputs: vec![Input {
+            coin_id,
+            signature: Signature::Valid(owner),
+        }],
+        ..mint(outputs)
+    }
+}
+
+/// A node with one block minting 100 and 15 bones to Alice and 120 to Bob, and a wallet of
+/// Alice and Bob synced to it.
+pub(crate) fn make_one_block_blockchain() -> (MockNode, Wallet) {
+    let mut node = MockNode::new();
+    node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 100), (Address::Alice, 15), (Address::Bob, 120)])]);
+    let mut wallet = wallet_with_alice_and_bob();
+    wallet.sync(&node);
+    (node, wallet)
+}
+
+/// A path in the temporary directory that no other test, or other run of the tests, uses. Nothing is created there.
+pub(crate) fn unique_temp_path(name: &str) -> std::path::PathBuf {
+    static NEXT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let n = NEXT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().subsec_nanos();
+    std::env::temp_dir().join(format!("utxo_wallet_{name}_{}_{n}_{nanos}", std::process::id()))
+}
+
+/// A transaction paying `value` bones to Alice out of a coin the chain never created. Each value
+/// gives a distinct transaction.
+pub(crate) fn pay_alice(value: u64) -> Transaction {
+    Transaction {
+        inputs: vec![Input {
+            coin_id: marker_tx().coin_id(value, 0),
+            signature: Signature::Invalid,
+        }],
+        ..mint([(Address::Alice, value)])
+    }
+}
@@ -21,10 +21,12 @@ fn wallet_with_alice() -> Wallet {
 /// This marker transaction can be useful to place on the new side of the fork.
 fn marker_tx() -> Transaction {
     Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![Coin {
             value: 123,
             owner: Address::Custom(123),
+            asset_id: None,
         }],
     }
 }
@@ -46,11 +48,11 @@ fn foreign_address_error() {
 
     assert_eq!(
         wallet.total_assets_of(Address::Bob),
-        Err(WalletError::ForeignAddress)
+        Err(WalletError::ForeignAddress(Address::Bob))
     );
     assert_eq!(
         wallet.all_coins_of(Address::Bob),
-        Err(WalletError::ForeignAddress)
+        Err(WalletError::ForeignAddress(Address::Bob))
     );
 }
 
@@ -148,8 +150,10 @@ fn tracks_single_utxo() {
     let coin = Coin {
         value: COIN_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     let tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin.clone()],
     };
@@ -181,8 +185,10 @@ fn consumes_own_utxo() {
     let coin = Coin {
         value: COIN_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     let tx_mint = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![],
         outputs: vec![coin.clone()],
     };
@@ -197,6 +203,7 @@ fn consumes_own_utxo() {
         signature: Signature::Invalid,
     };
     let tx_burn = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![input],
         outputs: vec![],
     };
@@ -216,7 +223,7 @@ fn consumes_own_utxo() {
     // Pedagogy: It is reasonable that the wallet could provide details about
     // the coin even after it was spent. But requiring that gives away the trick of
     // tracking spent coins so you can revert them later.
-    assert_eq!(wallet.coin_details(&coin_id), Err(WalletError::UnknownCoin));
+    assert_eq!(wallet.coin_details(&coin_id), Err(WalletError::UnknownCoin(coin_id)));
 }
 
 // Track UTXOs from two transactions in a single block
@@ -229,24 +236,30 @@ fn extra_track_two_utxo() {
     let coin = Coin {
         value: COIN_0_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin_1 = Coin {
         value: COIN_1_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin_2 = Coin {
         value: COIN_2_VALUE,
         owner: Address::Bob,
+        asset_id: None,
     };
     let tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin.clone()],
     };
     let tx_1 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin_1.clone()],
     };
     let tx_2 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin_2.clone()],
     };
@@ -291,16 +304,20 @@ fn extra_utxo_to_multiple_users() {
     let coin = Coin {
         value: COIN_0_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     let coin_1 = Coin {
         value: COIN_1_VALUE,
         owner: Address::Bob,
+        asset_id: None,
     };
     let tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin.clone()],
     };
     let tx_1 = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin_1.clone()],
     };
@@ -339,15 +356,17 @@ fn check_manual_transaction_with_missing_input() {
     let coin = Coin {
         value: COIN_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     let tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![],
     };
 
     assert_eq!(
         wallet.create_manual_transaction(vec![tx.coin_id(1, 0)], vec![coin]),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(tx.coin_id(1, 0)))
     );
 }
 
@@ -358,15 +377,17 @@ fn check_manual_transaction_with_too_much_output() {
     let coin = Coin {
         value: 100,
         owner: Address::Alice,
+        asset_id: None,
     };
     let tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin.clone(), coin.clone()],
     };
 
     assert_eq!(
         wallet.create_manual_transaction(vec![tx.coin_id(1, 0)], vec![coin]),
-        Err(WalletError::UnknownCoin)
+        Err(WalletError::UnknownCoin(tx.coin_id(1, 0)))
     );
 }
 
@@ -377,8 +398,10 @@ fn check_manual_transaction_with_zero_output_value() {
     let coin = Coin {
         value: COIN_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     let tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin.clone()],
     };
@@ -391,6 +414,7 @@ fn check_manual_transaction_with_zero_output_value() {
     let coin_output = Coin {
         value: 0,
         owner: Address::Alice,
+        asset_id: None,
     };
 
     let mut wallet: Wallet = wallet_with_alice();
@@ -410,8 +434,10 @@ fn check_automatic_transaction_with_too_much_output() {
     let coin1 = Coin {
         value: COIN_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     let tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin1.clone()],
     };
@@ -424,7 +450,13 @@ fn check_automatic_transaction_with_too_much_output() {
     wallet.sync(&node);
 
     let transaction_auto = wallet.create_automatic_transaction(Address::Bob, COIN_VALUE + 1, 0);
-    assert_eq!(transaction_auto, Err(WalletError::InsufficientFunds));
+    assert_eq!(
+        transaction_auto,
+        Err(WalletError::InsufficientFunds {
+            needed: COIN_VALUE + 1,
+            available: COIN_VALUE,
+        })
+    );
 }
 
 // ... with zero change
@@ -434,9 +466,11 @@ fn check_automatic_transaction_with_zero_change() {
     let coin1 = Coin {
         value: COIN_VALUE,
         owner: Address::Alice,
+        asset_id: None,
     };
     //minting a coin to alice
     let tx = Transaction {
+        version: TRANSACTION_VERSION,
         inputs: vec![Input::dummy()],
         outputs: vec![coin1.clone()],
     };
@@ -0,0 +1,286 @@
+//! Following submitted transactions until they are buried deep enough.
+//!
+//! The tracker syncs with the node on its own, like the indexer, and checks every block on the
+//! best chain for the transactions it was given. The node interface has no mempool, so whether a
+//! transaction reached the mempool is reported by the caller with `mark_in_mempool`.
+
+use std::collections::HashMap;
+
+use bonecoin_core::*;
+
+/// Where a tracked transaction stands.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TransactionStatus {
+    /// Submitted, but neither seen in the mempool nor mined.
+    Submitted,
+    /// Reported to be in the node's mempool, but not mined yet.
+    InMempool,
+    /// Mined with this many confirmations, counting the including block, up to the tracker's target.
+    Confirmed(u64),
+    /// Not mined within the tracker's expiry window after submission.
+    Evicted,
+    /// Another transaction spending one of the same coins was mined instead.
+    Conflicted,
+}
+
+struct Tracked {
+    transaction: Transaction,
+    submitted_at: u64, // best height of the tracker when the transaction was tracked
+    in_mempool: bool, // reported by the caller
+    included_at: Option<u64>, // height of the block that mined the transaction
+    conflicted_at: Option<u64>, // height of the block that mined a conflicting transaction
+}
+
+/// Counts confirmations of submitted transactions by following the best chain.
+pub struct TransactionTracker {
+    target_confirmations: u64,
+    expiry: u64,
+    block_ids: Vec<BlockId>, // ids of the applied blocks indexed by height - used to find the fork point on reorgs
+    tracked: HashMap<TransactionId, Tracked>,
+}
+
+impl TransactionTracker {
+    /// Create a tracker positioned at genesis. Confirmations are counted up to `target_confirmations`,
+    /// and a transaction that is not mined within `expiry` blocks of being tracked is considered evicted.
+    pub fn new(target_confirmations: u64, expiry: u64) -> Self {
+        TransactionTracker {
+            target_confirmations,
+            expiry,
+            block_ids: vec![Block::genesis().id()],
+            tracked: HashMap::new(),
+        }
+    }
+
+    /// Get the height of the best block that the tracker is aware of.
+    pub fn best_height(&self) -> u64 {
+        self.block_ids.len() as u64 - 1
+    }
+
+    /// Start tracking a transaction that was just submitted. Tracking it again has no effect.
+    pub fn track(&mut self, transaction: Transaction) {
+        let submitted_at = self.best_height();
+        self.tracked.entry(transaction.id()).or_insert(Tracked {
+            transaction,
+            submitted_at,
+            in_mempool: false,
+            included_at: None,
+            conflicted_at: None,
+        });
+    }
+
+    /// Stop tracking a transaction. Returns false if it was not tracked.
+    pub fn untrack(&mut self, tx_id: &TransactionId) -> bool {
+        self.tracked.remove(tx_id).is_some()
+    }
+
+    /// Record that the node accepted a tracked transaction into its mempool.
+    pub fn mark_in_mempool(&mut self, tx_id: &TransactionId) {
+        if let Some(tracked) = self.tracked.get_mut(tx_id) {
+            tracked.in_mempool = true;
+        }
+    }
+
+    /// The status of a tracked transaction, or `None` if it is not tracked.
+    pub fn status(&self, tx_id: &TransactionId) -> Option<TransactionStatus> {
+        let tracked = self.tracked.get(tx_id)?;
+        let status = if let Some(height) = tracked.included_at {
+            TransactionStatus::Confirmed((self.best_height() - height + 1).min(self.target_confirmations))
+        } else if tracked.conflicted_at.is_some() {
+            TransactionStatus::Conflicted
+        } else if self.best_height() >= tracked.submitted_at + self.expiry {
+            TransactionStatus::Evicted
+        } else if tracked.in_mempool {
+            TransactionStatus::InMempool
+        } else {
+            TransactionStatus::Submitted
+        };
+        Some(status)
+    }
+
+    /// Whether a tracked transaction has reached the target number of confirmations.
+    pub fn is_final(&self, tx_id: &TransactionId) -> bool {
+        self.status(tx_id) == Some(TransactionStatus::Confirmed(self.target_confirmations))
+    }
+
+    /// Synchronizes the tracker with the node, reverting blocks that are no longer on the node's best chain.
+    pub fn sync<Node: NodeEndpoint>(&mut self, node: &Node) {
+        while self.best_height() > 0 && node.best_block_at_height(self.best_height()) != self.block_ids.last().copied() {
+            let height = self.best_height();
+            self.block_ids.pop();
+            for tracked in self.tracked.values_mut() {
+                if tracked.included_at == Some(height) {
+                    tracked.included_at = None;
+                }
+                if tracked.conflicted_at == Some(height) {
+                    tracked.conflicted_at = None;
+                }
+            }
+        }
+
+        while let Some(block_id) = node.best_block_at_height(self.best_height() + 1) {
+            match node.entire_block(&block_id) {
+                Some(block) => self.apply_block(block_id, &block),
+                None => break, // failed to fetch block, stop sync
+            }
+        }
+    }
+
+    /// Look up the tracked transactions not known to be mined in the node's transaction index, and
+    /// mark those included in a block the tracker has synced. Sync only inspects new blocks, so a
+    /// transaction tracked after its block was synced is only found this way. Nodes without a
+    /// transaction index find nothing.
+    pub fn resolve<Node: NodeEndpoint>(&mut self, node: &Node) {
+        for (tx_id, tracked) in self.tracked.iter_mut().filter(|(_, tracked)| tracked.included_at.is_none()) {
+            if let Some((block_id, height, _)) = node.transaction(tx_id) {
+                if self.block_ids.get(height as usize) == Some(&block_id) {
+                    tracked.included_at = Some(height);
+                }
+            }
+        }
+    }
+
+    fn apply_block(&mut self, block_id: BlockId, block: &Block) {
+        for tx in &block.body {
+            let tx_id = tx.id();
+            for (id, tracked) in self.tracked.iter_mut() {
+                if tracked.included_at.is_some() {
+                    continue;
+                }
+                if *id == tx_id {
+                    tracked.included_at = Some(block.number);
+                } else if tracked.conflicted_at.is_none()
+                    && tracked.transaction.iter_input_coin_ids().any(|coin_id| tx.iter_input_coin_ids().any(|spent| spent == coin_id))
+                {
+                    tracked.conflicted_at = Some(block.number);
+                }
+            }
+        }
+        self.block_ids.push(block_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A tracker needing 3 confirmations and expiring after 2 blocks, synced to an empty first
+    /// block and tracking a payment in the mempool, a conflicting spend of the same dummy coin, and
+    /// a `forgotten` transaction spending a coin the chain never created.
+    fn tracking() -> (MockNode, BlockId, TransactionTracker, [Transaction; 3]) {
+        let mut node = MockNode::new();
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![]);
+        let mut tracker = TransactionTracker::new(3, 2);
+        tracker.sync(&node);
+
+        // both spend the dummy coin
+        let payment = mint([(Address::Custom(1), 1)]);
+        let conflicting = mint([(Address::Custom(2), 2)]);
+        let forgotten = Transaction {
+            inputs: vec![Input {
+                coin_id: marker_tx().coin_id(9, 0),
+                signature: Signature::Invalid,
+            }],
+            ..mint([])
+        };
+        for tx in [&payment, &conflicting, &forgotten] {
+            tracker.track(tx.clone());
+        }
+        tracker.mark_in_mempool(&payment.id());
+        (node, b1_id, tracker, [payment, conflicting, forgotten])
+    }
+
+    /// Mine `count` empty blocks on top of `parent`, returning the id of the last one.
+    fn extend(node: &mut MockNode, mut parent: BlockId, count: usize) -> BlockId {
+        for _ in 0..count {
+            parent = node.add_block_as_best(parent, vec![]);
+        }
+        parent
+    }
+
+    #[test]
+    fn unmined_transactions_are_submitted_or_in_the_mempool() {
+        let (_, _, tracker, [payment, _, forgotten]) = tracking();
+        assert_eq!(tracker.status(&payment.id()), Some(TransactionStatus::InMempool));
+        assert_eq!(tracker.status(&forgotten.id()), Some(TransactionStatus::Submitted));
+        assert_eq!(tracker.status(&marker_tx().id()), None);
+    }
+
+    #[test]
+    fn mining_confirms_a_transaction_and_conflicts_its_double_spends() {
+        let (mut node, b1_id, mut tracker, [payment, conflicting, forgotten]) = tracking();
+        node.add_block_as_best(b1_id, vec![payment.clone()]);
+        tracker.sync(&node);
+        assert_eq!(tracker.status(&payment.id()), Some(TransactionStatus::Confirmed(1)));
+        assert_eq!(tracker.status(&conflicting.id()), Some(TransactionStatus::Conflicted));
+        assert_eq!(tracker.status(&forgotten.id()), Some(TransactionStatus::Submitted));
+        assert!(!tracker.is_final(&payment.id()));
+    }
+
+    #[test]
+    fn confirmations_grow_until_final_and_stale_transactions_are_evicted() {
+        let (mut node, b1_id, mut tracker, [payment, _, forgotten]) = tracking();
+        let b2_id = node.add_block_as_best(b1_id, vec![payment.clone()]);
+        extend(&mut node, b2_id, 3);
+        tracker.sync(&node);
+        assert_eq!(tracker.status(&payment.id()), Some(TransactionStatus::Confirmed(3)));
+        assert!(tracker.is_final(&payment.id()));
+        assert_eq!(tracker.status(&forgotten.id()), Some(TransactionStatus::Evicted));
+    }
+
+    #[test]
+    fn reorgs_undo_confirmations_and_conflicts() {
+        let (mut node, b1_id, mut tracker, [payment, conflicting, _]) = tracking();
+        let b2_id = node.add_block_as_best(b1_id, vec![payment.clone()]);
+        extend(&mut node, b2_id, 3);
+        tracker.sync(&node);
+
+        // a longer branch from the first block mines the conflicting spend instead
+        let fork_id = (0..4).fold(b1_id, |parent, _| node.add_block(parent, vec![]));
+        node.add_block_as_best(fork_id, vec![conflicting.clone()]);
+        tracker.sync(&node);
+        assert_eq!(tracker.status(&payment.id()), Some(TransactionStatus::Conflicted));
+        assert_eq!(tracker.status(&conflicting.id()), Some(TransactionStatus::Confirmed(1)));
+    }
+
+    #[test]
+    fn untracked_transactions_have_no_status() {
+        let (_, _, mut tracker, [payment, ..]) = tracking();
+        assert!(tracker.untrack(&payment.id()));
+        assert!(!tracker.untrack(&payment.id()));
+        assert_eq!(tracker.status(&payment.id()), None);
+    }
+
+    /// A node of three blocks: the first rewards Alice, the second pays 20 bones of the reward to
+    /// Bob, and the third is empty. Returns the node and the payment.
+    fn paid_before_tracking() -> (MockNode, Transaction) {
+        let mut node = MockNode::new();
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+        let reward = Transaction::coinbase(Address::Alice, BLOCK_REWARD).coin_id(1, 0);
+        let payment = spend(reward, Address::Alice, [(Address::Bob, 20)]);
+        let b2_id = node.add_block_as_best(b1_id, vec![payment.clone()]);
+        node.add_block_as_best(b2_id, vec![]);
+        (node, payment)
+    }
+
+    #[test]
+    fn trackers_resolve_transactions_mined_before_they_were_tracked() {
+        let (node, payment) = paid_before_tracking();
+        let mut tracker = TransactionTracker::new(3, 10);
+        tracker.sync(&node);
+        tracker.track(payment.clone());
+        assert_eq!(tracker.status(&payment.id()), Some(TransactionStatus::Submitted));
+        tracker.resolve(&node);
+        assert_eq!(tracker.status(&payment.id()), Some(TransactionStatus::Confirmed(2)));
+    }
+
+    #[test]
+    fn wallets_resolve_transactions_mined_before_they_were_registered() {
+        let (node, payment) = paid_before_tracking();
+        let mut wallet = Wallet::new([Address::Bob].into_iter());
+        wallet.sync(&node);
+        assert!(wallet.register_transaction(payment.clone(), 1..=5));
+        wallet.resolve_registered(&node);
+        assert_eq!(wallet.registered_transaction(&payment.id()).unwrap().status, RegisteredStatus::Confirmed { height: 2 });
+        assert!(wallet.take_events().contains(&WalletEvent::TransactionConfirmed { tx_id: payment.id(), height: 2 }));
+    }
+}
@@ -0,0 +1,365 @@
+//! `bone:` payment URIs, for sharing a payment request as one string or QR code.
+//!
+//! A payment URI names the address to pay and optionally the amount, a memo for the payer, and the
+//! id of the invoice it settles:
+//!
+//! ```text
+//! bone:Custom(42)?amount=250&memo=Order%20%2317&invoice=inv-17
+//! ```
+//!
+//! The address is written as it displays. Characters other than letters, digits, and `-._~(),` are
+//! percent-encoded as UTF-8, in the address and in parameter values alike, so a `+` stays a `+`.
+//! The scheme is matched without regard to case, so a URI may be upper-cased for the denser
+//! alphanumeric mode of QR codes, but the address and values are case-sensitive. Unknown parameters
+//! are ignored, unless their name starts with `req-`, which marks a parameter the payer must
+//! understand to pay correctly. Addresses nested more than `MAX_ADDRESS_NESTING` deep are rejected,
+//! so parsing an untrusted URI takes bounded stack and time.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// The scheme of payment URIs.
+pub const PAYMENT_URI_SCHEME: &str = "bone";
+
+/// How deep addresses may be nested in multisig and hash lock addresses of a payment URI.
+pub const MAX_ADDRESS_NESTING: usize = 16;
+
+/// Errors that can occur while parsing a payment URI.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum PaymentUriError {
+    /// The text does not start with `bone:`.
+    WrongScheme,
+    /// The part after the scheme does not name an address.
+    InvalidAddress(String),
+    /// The amount is not a positive whole number of bones.
+    InvalidAmount(String),
+    /// A parameter appears more than once.
+    DuplicateParameter(String),
+    /// A `req-` parameter this parser does not know.
+    UnknownRequiredParameter(String),
+    /// A percent sign is not followed by two hex digits, or the decoded bytes are not UTF-8.
+    InvalidEncoding,
+}
+
+impl fmt::Display for PaymentUriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentUriError::WrongScheme => write!(f, "a payment URI starts with {PAYMENT_URI_SCHEME}:"),
+            PaymentUriError::InvalidAddress(address) => write!(f, "{address:?} is not an address"),
+            PaymentUriError::InvalidAmount(amount) => write!(f, "{amount:?} is not a positive number of bones"),
+            PaymentUriError::DuplicateParameter(name) => write!(f, "the parameter {name} appears more than once"),
+            PaymentUriError::UnknownRequiredParameter(name) => write!(f, "the required parameter {name} is not supported"),
+            PaymentUriError::InvalidEncoding => write!(f, "the URI is not percent-encoded UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for PaymentUriError {}
+
+/// A request to pay an address, written as a `bone:` URI by `Display` and read back by `parse`.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PaymentUri {
+    /// The address to pay.
+    pub address: Address,
+    /// The bones requested, if the request names an amount.
+    pub amount: Option<u64>,
+    /// A note for the payer, such as what the payment is for.
+    pub memo: Option<String>,
+    /// The id of the invoice the payment settles.
+    pub invoice_id: Option<String>,
+}
+
+impl PaymentUri {
+    /// A request to pay the address any amount.
+    pub fn new(address: Address) -> Self {
+        PaymentUri {
+            address,
+            amount: None,
+            memo: None,
+            invoice_id: None,
+        }
+    }
+
+    /// Request this many bones.
+    pub fn with_amount(mut self, amount: u64) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    /// Attach a note for the payer.
+    pub fn with_memo(mut self, memo: impl Into<String>) -> Self {
+        self.memo = Some(memo.into());
+        self
+    }
+
+    /// Name the invoice the payment settles.
+    pub fn with_invoice_id(mut self, invoice_id: impl Into<String>) -> Self {
+        self.invoice_id = Some(invoice_id.into());
+        self
+    }
+
+    /// Read a payment URI, as written by `Display`.
+    pub fn parse(uri: &str) -> Result<Self, PaymentUriError> {
+        let rest = match uri.split_once(':') {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case(PAYMENT_URI_SCHEME) => rest,
+            _ => return Err(PaymentUriError::WrongScheme),
+        };
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let address = decode(address)?;
+        let mut request = PaymentUri::new(parse_address(&address, 0).ok_or(PaymentUriError::InvalidAddress(address))?);
+
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let value = decode(value)?;
+            let duplicate = match name {
+                "amount" => {
+                    let amount = value.parse().ok().filter(|amount| *amount > 0).ok_or(PaymentUriError::InvalidAmount(value))?;
+                    request.amount.replace(amount).is_some()
+                }
+                "memo" => request.memo.replace(value).is_some(),
+                "invoice" => request.invoice_id.replace(value).is_some(),
+                name if name.starts_with("req-") => return Err(PaymentUriError::UnknownRequiredParameter(name.to_string())),
+                _ => false,
+            };
+            if duplicate {
+                return Err(PaymentUriError::DuplicateParameter(name.to_string()));
+            }
+        }
+        Ok(request)
+    }
+}
+
+impl FromStr for PaymentUri {
+    type Err = PaymentUriError;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        PaymentUri::parse(uri)
+    }
+}
+
+impl fmt::Display for PaymentUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{PAYMENT_URI_SCHEME}:{}", encode(&self.address.to_string()))?;
+        let mut separator = '?';
+        let mut parameter = |f: &mut fmt::Formatter<'_>, name: &str, value: &str| {
+            let written = write!(f, "{separator}{name}={}", encode(value));
+            separator = '&';
+            written
+        };
+        if let Some(amount) = self.amount {
+            parameter(f, "amount", &amount.to_string())?;
+        }
+        if let Some(memo) = &self.memo {
+            parameter(f, "memo", memo)?;
+        }
+        if let Some(invoice_id) = &self.invoice_id {
+            parameter(f, "invoice", invoice_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Percent-encode every byte but letters, digits, and `-._~(),`.
+fn encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'(' | b')' | b',' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn decode(text: &str) -> Result<String, PaymentUriError> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).and_then(|hex| std::str::from_utf8(hex).ok()).ok_or(PaymentUriError::InvalidEncoding)?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| PaymentUriError::InvalidEncoding)?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| PaymentUriError::InvalidEncoding)
+}
+
+/// Split the text at every `separator` outside parentheses.
+fn split_outside_parentheses<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut index = 0;
+    while index < text.len() {
+        let rest = &text[index..];
+        if depth == 0 && rest.starts_with(separator) {
+            parts.push(&text[start..index]);
+            index += separator.len();
+            start = index;
+            continue;
+        }
+        match rest.as_bytes()[0] {
+            b'(' => depth += 1,
+            b')' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        index += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// The address an address displays as, found `depth` addresses deep in another.
+fn parse_address(text: &str, depth: usize) -> Option<Address> {
+    if depth > MAX_ADDRESS_NESTING {
+        return None;
+    }
+    let inner = |prefix: &str| text.strip_prefix(prefix).and_then(|rest| rest.strip_suffix(')'));
+    if let Some(id) = inner("Custom(") {
+        return id.parse().ok().map(Address::Custom);
+    }
+    if let Some(multisig) = inner("Multisig(") {
+        let (threshold, signers) = multisig.split_once(" of ")?;
+        let signers = split_outside_parentheses(signers, " ").into_iter().map(|signer| parse_address(signer, depth + 1)).collect::<Option<Vec<_>>>()?;
+        let threshold: usize = threshold.parse().ok()?;
+        // signers listed in any order name the same address the wallets compute
+        let address = Address::multisig(threshold, signers);
+        return matches!(&address, Address::Multisig { signers, .. } if (1..=signers.len()).contains(&threshold)).then_some(address);
+    }
+    if let Some(lock) = inner("HashLock(") {
+        let [recipient, rest] = split_outside_parentheses(lock, " with ")[..] else { return None };
+        let (hash, rest) = rest.split_once(", ")?;
+        let [refund, timeout_height] = split_outside_parentheses(rest, " from ")[..] else { return None };
+        return Some(Address::HashLock {
+            recipient: Box::new(parse_address(recipient, depth + 1)?),
+            refund: Box::new(parse_address(refund, depth + 1)?),
+            hash: u64::from_str_radix(hash, 16).ok()?,
+            timeout_height: timeout_height.parse().ok()?,
+        });
+    }
+    match text {
+        "Alice" => Some(Address::Alice),
+        "Bob" => Some(Address::Bob),
+        "Charlie" => Some(Address::Charlie),
+        "Dave" => Some(Address::Dave),
+        "Eve" => Some(Address::Eve),
+        _ => None,
+    }
+}
+
+impl Wallet {
+    /// Issue a fresh address with `next_receive_address` and request payment to it, for the
+    /// caller to add the amount, memo, and invoice id to. Returns `None` when `next_receive_address`
+    /// does.
+    pub fn request_payment(&mut self) -> Option<PaymentUri> {
+        self.next_receive_address().map(PaymentUri::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A request for 250 bones with a memo that needs escaping and an invoice id.
+    fn request_to(address: Address) -> PaymentUri {
+        PaymentUri::new(address).with_amount(250).with_memo("Order #17 & more: 5+ bones, ünïcode").with_invoice_id("inv-17")
+    }
+
+    /// An address `depth` single-signer multisigs deep.
+    fn nested(depth: usize) -> Address {
+        (0..depth).fold(Address::Alice, |address, _| Address::multisig(1, [address]))
+    }
+
+    #[test]
+    fn uris_round_trip_every_kind_of_address() {
+        let escrow = Address::multisig(2, [Address::Alice, Address::Custom(9), Address::multisig(1, [Address::Bob, Address::Eve])]);
+        let lock = Address::HashLock {
+            recipient: Box::new(escrow.clone()),
+            refund: Box::new(Address::Custom(3)),
+            hash: hash_preimage(b"secret"),
+            timeout_height: 120,
+        };
+
+        for address in [Address::Alice, Address::Custom(42), escrow, lock] {
+            let request = request_to(address);
+            let uri = request.to_string();
+            assert!(!uri.contains(' ') && uri.matches('&').count() == 2, "{uri}");
+            assert_eq!(PaymentUri::parse(&uri), Ok(request));
+        }
+        assert_eq!(PaymentUri::new(Address::Custom(42)).with_amount(250).to_string(), "bone:Custom(42)?amount=250");
+    }
+
+    #[test]
+    fn the_scheme_is_case_insensitive() {
+        let request = request_to(Address::Alice);
+        let uri = request.to_string();
+
+        assert_eq!(format!("BONE{}", &uri[PAYMENT_URI_SCHEME.len()..]).parse(), Ok(request));
+        assert_eq!(PaymentUri::parse("bitcoin:Bob"), Err(PaymentUriError::WrongScheme));
+    }
+
+    #[test]
+    fn unknown_parameters_are_ignored_unless_required() {
+        let bare = PaymentUri::parse("BONE:Bob?label=shop").unwrap();
+        assert_eq!(bare, PaymentUri::new(Address::Bob));
+        assert_eq!(bare.to_string(), "bone:Bob");
+        assert_eq!(PaymentUri::parse("bone:Bob?req-expiry=9"), Err(PaymentUriError::UnknownRequiredParameter("req-expiry".to_string())));
+    }
+
+    #[test]
+    fn malformed_uris_are_rejected() {
+        assert_eq!(PaymentUri::parse("bone:Mallory"), Err(PaymentUriError::InvalidAddress("Mallory".to_string())));
+        assert_eq!(PaymentUri::parse("bone:Bob?amount=1.5"), Err(PaymentUriError::InvalidAmount("1.5".to_string())));
+        assert_eq!(PaymentUri::parse("bone:Bob?amount=0"), Err(PaymentUriError::InvalidAmount("0".to_string())));
+        assert_eq!(PaymentUri::parse("bone:Bob?memo=a&memo=b"), Err(PaymentUriError::DuplicateParameter("memo".to_string())));
+        assert_eq!(PaymentUri::parse("bone:Bob?memo=%E"), Err(PaymentUriError::InvalidEncoding));
+        assert_eq!(PaymentUri::parse("bone:Bob?memo=%FF"), Err(PaymentUriError::InvalidEncoding));
+    }
+
+    #[test]
+    fn multisig_signers_are_normalized_and_thresholds_checked() {
+        let escrow = Address::multisig(2, [Address::Alice, Address::Bob]);
+        assert_eq!(PaymentUri::parse("bone:Multisig(2%20of%20Bob%20Alice)"), Ok(PaymentUri::new(escrow)));
+
+        for invalid in ["Multisig(0 of Alice Bob)", "Multisig(3 of Alice Bob)", "Multisig(2 of Alice Alice)"] {
+            let uri = format!("bone:{}", invalid.replace(' ', "%20"));
+            assert_eq!(PaymentUri::parse(&uri), Err(PaymentUriError::InvalidAddress(invalid.to_string())));
+        }
+    }
+
+    #[test]
+    fn addresses_nested_beyond_the_limit_are_rejected() {
+        let deepest = PaymentUri::new(nested(MAX_ADDRESS_NESTING));
+        assert_eq!(PaymentUri::parse(&deepest.to_string()), Ok(deepest));
+
+        let too_deep = PaymentUri::new(nested(MAX_ADDRESS_NESTING + 1)).to_string();
+        assert!(matches!(PaymentUri::parse(&too_deep), Err(PaymentUriError::InvalidAddress(_))));
+    }
+
+    #[test]
+    fn parsing_hostile_nesting_stops_at_the_limit() {
+        // far too deep to recurse through without overflowing the stack
+        let levels = 50_000;
+        let hostile = format!("bone:{}Alice{}", "Multisig(1%20of%20".repeat(levels), ")".repeat(levels));
+        assert!(matches!(PaymentUri::parse(&hostile), Err(PaymentUriError::InvalidAddress(_))));
+    }
+
+    #[test]
+    fn payment_requests_get_a_fresh_address_each() {
+        let mut wallet = wallet_with_alice_and_bob();
+        wallet.add_receive_addresses([Address::Alice, Address::Bob]).unwrap();
+
+        let first = wallet.request_payment().unwrap().with_amount(10).with_invoice_id("inv-1");
+        let second = wallet.request_payment().unwrap();
+        assert_ne!(first.address, second.address);
+        assert_eq!(wallet.request_payment(), None);
+        assert_eq!(PaymentUri::parse(&first.to_string()).unwrap().address, first.address);
+    }
+}
@@ -0,0 +1,115 @@
+//! Syncing from several nodes at once, applying only what they agree on.
+//!
+//! A single node can hide transactions or invent a chain for a wallet that trusts it. With
+//! `sync_verified`, every block id comes from all configured nodes, and a block is only applied if
+//! at least two of them report it and none reports a different block at its height. Block bodies are
+//! checked against their ids, so any one node can serve them.
+
+use std::cell::Cell;
+
+use bonecoin_core::*;
+
+use crate::{SyncError, Wallet};
+
+/// The nodes behind `sync_verified`, seen as a single node that only knows the blocks they agree on.
+struct AgreeingNodes<'a> {
+    nodes: &'a [&'a dyn NodeEndpoint],
+    /// The lowest height at which two nodes reported different blocks.
+    disagreement: Cell<Option<u64>>,
+}
+
+impl NodeEndpoint for AgreeingNodes<'_> {
+    fn best_block_at_height(&self, h: u64) -> Option<BlockId> {
+        let mut answers = self.nodes.iter().filter_map(|node| node.best_block_at_height(h));
+        let first = answers.next()?;
+        let mut confirmations = 1;
+        for answer in answers {
+            if answer != first {
+                let lowest = self.disagreement.get().map_or(h, |height| height.min(h));
+                self.disagreement.set(Some(lowest));
+                return None;
+            }
+            confirmations += 1;
+        }
+        (confirmations >= 2).then_some(first)
+    }
+
+    fn entire_block(&self, id: &BlockId) -> Option<Block> {
+        self.nodes.iter().filter_map(|node| node.entire_block(id)).find(|block| block.id() == *id)
+    }
+}
+
+impl Wallet {
+    /// Sync like `try_sync`, applying only blocks that at least two of `nodes` report and none contradicts.
+    ///
+    /// If two nodes report different blocks at a height, sync stops below it and returns
+    /// `SyncError::NodesDisagree` with the lowest such height. A disagreement about a block the wallet
+    /// already applied rolls it back like a reorg, since the wallet can no longer tell it is final.
+    pub fn sync_verified(&mut self, nodes: &[&dyn NodeEndpoint]) -> Result<(), SyncError> {
+        if nodes.len() < 2 {
+            return Err(SyncError::TooFewNodes { configured: nodes.len() });
+        }
+        let agreeing = AgreeingNodes {
+            nodes,
+            disagreement: Cell::new(None),
+        };
+        let result = self.try_sync(&agreeing);
+        match agreeing.disagreement.get() {
+            Some(height) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(height, synced_to = self.best_block_height, "nodes disagree");
+                Err(SyncError::NodesDisagree { height })
+            }
+            None => result.map(|_| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// An honest node of two blocks rewarding Alice then Charlie, a lying node whose second block
+    /// rewards Alice a million bones, and a lagging node that has only seen the first block.
+    fn honest_lying_and_lagging() -> (MockNode, MockNode, MockNode) {
+        let block_one = vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)];
+        let mut honest = MockNode::new();
+        let b1_id = honest.add_block_as_best(Block::genesis().id(), block_one.clone());
+        honest.add_block_as_best(b1_id, vec![Transaction::coinbase(Address::Charlie, BLOCK_REWARD)]);
+        let mut lying = MockNode::new();
+        lying.add_block_as_best(Block::genesis().id(), block_one.clone());
+        lying.add_block_as_best(b1_id, vec![Transaction::coinbase(Address::Alice, 1_000_000)]);
+        let mut lagging = MockNode::new();
+        lagging.add_block_as_best(Block::genesis().id(), block_one);
+        (honest, lying, lagging)
+    }
+
+    #[test]
+    fn one_node_is_too_few() {
+        let (_, lying, _) = honest_lying_and_lagging();
+        let mut wallet = wallet_with_alice();
+        assert_eq!(wallet.sync_verified(&[&lying]), Err(SyncError::TooFewNodes { configured: 1 }));
+        assert_eq!(wallet.best_height(), 0);
+    }
+
+    #[test]
+    fn sync_stops_where_the_nodes_disagree() {
+        let (honest, lying, _) = honest_lying_and_lagging();
+        let mut wallet = wallet_with_alice();
+        assert_eq!(wallet.sync_verified(&[&honest, &lying]), Err(SyncError::NodesDisagree { height: 2 }));
+        assert_eq!((wallet.best_height(), wallet.net_worth()), (1, BLOCK_REWARD));
+    }
+
+    #[test]
+    fn blocks_only_one_node_has_seen_are_not_applied_yet() {
+        let (honest, _, mut lagging) = honest_lying_and_lagging();
+        let mut wallet = wallet_with_alice();
+        assert_eq!(wallet.sync_verified(&[&honest, &lagging]), Ok(()));
+        assert_eq!(wallet.best_height(), 1);
+
+        let b1_id = honest.best_block_at_height(1).unwrap();
+        lagging.add_block_as_best(b1_id, vec![Transaction::coinbase(Address::Charlie, BLOCK_REWARD)]);
+        assert_eq!(wallet.sync_verified(&[&honest, &lagging]), Ok(()));
+        assert_eq!((wallet.best_height(), wallet.best_hash()), (2, honest.best_block_at_height(2).unwrap()));
+    }
+}
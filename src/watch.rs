@@ -0,0 +1,187 @@
+//! Following the lifecycle of individual coins, including coins the wallet does not own.
+//!
+//! A watched coin is usually created before it is watched (an escrow output, a customer's payment),
+//! so the wallet may never see its creation. Sync records the creation if it does see it, and
+//! records the spending transaction when the coin is consumed. Both are forgotten again when a
+//! reorg removes the block that contained them.
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// What the wallet has seen happen to a watched coin.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct WatchedCoin {
+    /// The coin, if sync has seen the transaction that created it.
+    pub coin: Option<Coin>,
+    /// The height of the block that created the coin, if sync has seen it.
+    pub created_at_height: Option<u64>,
+    /// The transaction that spent the coin, if it has been spent on the best chain.
+    pub spent_by: Option<TransactionId>,
+    /// The height of the block that included `spent_by`.
+    pub spent_at_height: Option<u64>,
+}
+
+impl WatchedCoin {
+    /// Whether the coin has been spent on the best chain the wallet synced.
+    pub fn is_spent(&self) -> bool {
+        self.spent_by.is_some()
+    }
+
+    /// Forget whatever happened above `height`.
+    pub(crate) fn forget_above(&mut self, height: u64) {
+        if self.created_at_height.is_some_and(|created| created > height) {
+            self.coin = None;
+            self.created_at_height = None;
+        }
+        if self.spent_at_height.is_some_and(|spent| spent > height) {
+            self.spent_by = None;
+            self.spent_at_height = None;
+        }
+    }
+}
+
+impl Wallet {
+    /// Start following a coin. Returns false if the coin was already watched.
+    /// Only blocks synced from now on are inspected; the coin's past is not looked up.
+    pub fn watch_coin(&mut self, coin_id: CoinId) -> bool {
+        if self.watched.contains_key(&coin_id) {
+            return false;
+        }
+        self.watched.insert(coin_id, WatchedCoin::default());
+        true
+    }
+
+    /// Stop following a coin. Returns false if the coin was not watched.
+    pub fn unwatch_coin(&mut self, coin_id: &CoinId) -> bool {
+        self.watched.remove(coin_id).is_some()
+    }
+
+    /// What the wallet has seen of a watched coin, or `None` if the coin is not watched.
+    pub fn watched_coin(&self, coin_id: &CoinId) -> Option<&WatchedCoin> {
+        self.watched.get(coin_id)
+    }
+
+    /// Every watched coin, ordered by coin id.
+    pub fn watched_coins(&self) -> Vec<(CoinId, &WatchedCoin)> {
+        let mut watched: Vec<(CoinId, &WatchedCoin)> = self.watched.iter().map(|(id, watched)| (*id, watched)).collect();
+        watched.sort_by_key(|(coin_id, _)| *coin_id);
+        watched
+    }
+
+    /// Record what a transaction mined at `height` does to the watched coins.
+    pub(crate) fn observe_watched(&mut self, transaction: &Transaction, height: u64) {
+        if self.watched.is_empty() {
+            return;
+        }
+        for input in &transaction.inputs {
+            if let Some(watched) = self.watched.get_mut(&input.coin_id) {
+                watched.spent_by = Some(transaction.id());
+                watched.spent_at_height = Some(height);
+            }
+        }
+        for (index, coin) in transaction.outputs.iter().enumerate() {
+            if let Some(watched) = self.watched.get_mut(&transaction.coin_id(height, index)) {
+                watched.coin = Some(coin.clone());
+                watched.created_at_height = Some(height);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// Charlie is minted 30 bones in block 1 and a wallet of Alice starts watching the coin after
+    /// syncing that block. Returns the node, the wallet, the watched coin, and a transaction in
+    /// which Charlie pays the coin on to Eve, not mined yet.
+    fn watching_charlies_coin() -> (MockNode, Wallet, CoinId, Transaction) {
+        let mut node = MockNode::new();
+        let minted = mint([(Address::Charlie, 30)]);
+        node.add_block_as_best(Block::genesis().id(), vec![minted.clone()]);
+        let mut wallet = wallet_with_alice();
+        wallet.sync(&node);
+
+        let promised = minted.coin_id(1, 0);
+        wallet.watch_coin(promised);
+        let spent = spend(promised, Address::Charlie, [(Address::Eve, 30)]);
+        (node, wallet, promised, spent)
+    }
+
+    /// Mine `tx` in a new best block on top of the best one, and sync the wallet.
+    fn mine(node: &mut MockNode, wallet: &mut Wallet, tx: &Transaction) {
+        node.add_block_as_best(node.best_block_at_height(wallet.best_height()).unwrap(), vec![tx.clone()]);
+        wallet.sync(node);
+    }
+
+    #[test]
+    fn coins_are_watched_once() {
+        let (_, mut wallet, promised, _) = watching_charlies_coin();
+        assert!(!wallet.watch_coin(promised));
+        // the coin was created before it was watched, so its creation is never seen
+        assert_eq!(wallet.watched_coin(&promised), Some(&WatchedCoin::default()));
+    }
+
+    #[test]
+    fn spends_of_watched_coins_are_recorded() {
+        let (mut node, mut wallet, promised, spent) = watching_charlies_coin();
+        mine(&mut node, &mut wallet, &spent);
+
+        let watched = wallet.watched_coin(&promised).unwrap();
+        assert!(watched.is_spent());
+        assert_eq!((watched.spent_by, watched.spent_at_height), (Some(spent.id()), Some(2)));
+        assert_eq!(wallet.net_worth(), 0);
+    }
+
+    #[test]
+    fn reorgs_undo_the_spend_of_a_watched_coin() {
+        let (mut node, mut wallet, promised, spent) = watching_charlies_coin();
+        mine(&mut node, &mut wallet, &spent);
+
+        let b1_id = node.best_block_at_height(1).unwrap();
+        let fork_id = node.add_block(b1_id, vec![]);
+        let fork_id = node.add_block_as_best(fork_id, vec![]);
+        wallet.sync(&node);
+        assert!(!wallet.watched_coin(&promised).unwrap().is_spent());
+
+        // until the spend is mined again on the new branch
+        node.add_block_as_best(fork_id, vec![spent.clone()]);
+        wallet.sync(&node);
+        let watched = wallet.watched_coin(&promised).unwrap();
+        assert_eq!((watched.spent_by, watched.spent_at_height), (Some(spent.id()), Some(4)));
+    }
+
+    #[test]
+    fn creations_of_watched_coins_are_recorded() {
+        let (mut node, mut wallet, _, spent) = watching_charlies_coin();
+        let paid = spent.coin_id(2, 0);
+        wallet.watch_coin(paid);
+        mine(&mut node, &mut wallet, &spent);
+
+        let created = wallet.watched_coin(&paid).unwrap();
+        assert_eq!(created.coin.as_ref().map(|coin| coin.owner.clone()), Some(Address::Eve));
+        assert_eq!(created.created_at_height, Some(2));
+    }
+
+    #[test]
+    fn watched_coins_survive_export() {
+        let (mut node, mut wallet, _, spent) = watching_charlies_coin();
+        wallet.watch_coin(spent.coin_id(2, 0));
+        mine(&mut node, &mut wallet, &spent);
+
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.watched_coins(), wallet.watched_coins());
+    }
+
+    #[test]
+    fn unwatched_coins_are_forgotten() {
+        let (_, mut wallet, promised, spent) = watching_charlies_coin();
+        let paid = spent.coin_id(2, 0);
+        wallet.watch_coin(paid);
+        assert!(wallet.unwatch_coin(&paid));
+        assert!(!wallet.unwatch_coin(&paid));
+        assert_eq!(wallet.watched_coins().len(), 1);
+        assert!(wallet.watched_coin(&promised).is_some());
+    }
+}
@@ -0,0 +1,134 @@
+//! Watchtower mode: noticing when coins of cold-storage addresses move without the wallet's say-so.
+//!
+//! Coins of a watchtower address are only expected to be spent by transactions the wallet holds a
+//! pending record of: transactions registered with `register_transaction`, withdrawal batches in
+//! flight, and payments sent with `send_idempotent`. When sync applies a block in which any other
+//! transaction spends such a coin, it raises `WalletEvent::UnauthorizedSpend`. The event is urgent:
+//! a full event queue drops every other event before it. The addresses are part of the exported state.
+
+use bonecoin_core::*;
+
+use crate::{HistoryEntry, Wallet, WalletEvent};
+
+impl Wallet {
+    /// Watch an owned address for spends the wallet did not author, or stop watching it.
+    pub fn set_watchtower(&mut self, address: Address, watch: bool) -> WalletResult<()> {
+        if !self.addresses.contains(&address) {
+            return Err(WalletError::ForeignAddress(address));
+        }
+        if watch {
+            self.watchtower.insert(address);
+        } else {
+            self.watchtower.remove(&address);
+        }
+        Ok(())
+    }
+
+    /// Whether the address is watched for spends the wallet did not author.
+    pub fn is_watchtower(&self, address: &Address) -> bool {
+        self.watchtower.contains(address)
+    }
+
+    /// Whether the wallet holds a pending record of the transaction, which authorizes it to spend
+    /// coins of watchtower addresses. Must be asked before sync observes the transaction, since
+    /// observing a withdrawal batch settles its record.
+    pub(crate) fn is_authored(&self, tx_id: &TransactionId) -> bool {
+        self.registered.contains_key(tx_id)
+            || self.withdrawals.in_flight.contains_key(tx_id)
+            || self.sent_requests.values().any(|transaction| transaction.id() == *tx_id)
+    }
+
+    /// Raise an `UnauthorizedSpend` for every coin of a watchtower address the entry's transaction
+    /// spent, unless the wallet authored it.
+    pub(crate) fn check_spend_authorized(&mut self, entry: &HistoryEntry, authored: bool) {
+        if authored {
+            return;
+        }
+        for (coin_id, coin) in &entry.spent {
+            if self.watchtower.contains(&coin.owner) {
+                self.emit(WalletEvent::UnauthorizedSpend {
+                    coin_id: *coin_id,
+                    address: coin.owner.clone(),
+                    tx_id: entry.tx_id,
+                    height: entry.height,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice and Bob, synced to a node whose block 1 pays 10 bones to Alice and 20 and
+    /// 30 to Bob, with Bob's address watched. Returns the node, the wallet, block 1 and its
+    /// transaction.
+    fn watching_bob() -> (MockNode, Wallet, BlockId, Transaction) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice_and_bob();
+
+        let funding = mint([(Address::Alice, 10), (Address::Bob, 20), (Address::Bob, 30)]);
+        let b1_id = node.add_block_as_best(Block::genesis().id(), vec![funding.clone()]);
+        wallet.sync(&node);
+        wallet.set_watchtower(Address::Bob, true).unwrap();
+        (node, wallet, b1_id, funding)
+    }
+
+    /// Mines Bob's registered spend of his 20 bone coin, Alice's spend of her coin, and an
+    /// unregistered spend of Bob's 30 bone coin in block 2. Returns block 2 and the theft.
+    fn steal_from_bob(node: &mut MockNode, wallet: &mut Wallet, b1_id: BlockId, funding: &Transaction) -> (BlockId, Transaction) {
+        let authorized = spend(funding.coin_id(1, 1), Address::Bob, [(Address::Eve, 5)]);
+        wallet.register_transaction(authorized.clone(), 2..=5);
+        let hot = spend(funding.coin_id(1, 0), Address::Alice, [(Address::Eve, 5)]);
+        let theft = spend(funding.coin_id(1, 2), Address::Bob, [(Address::Eve, 5)]);
+
+        let b2_id = node.add_block_as_best(b1_id, vec![authorized, hot, theft.clone()]);
+        wallet.sync(node);
+        (b2_id, theft)
+    }
+
+    #[test]
+    fn only_own_addresses_are_watched() {
+        let (_, mut wallet, _, _) = watching_bob();
+
+        assert_eq!(wallet.set_watchtower(Address::Eve, true), Err(WalletError::ForeignAddress(Address::Eve)));
+        assert!(wallet.is_watchtower(&Address::Bob));
+        assert!(!wallet.is_watchtower(&Address::Alice));
+    }
+
+    #[test]
+    fn only_unregistered_spends_of_watched_coins_are_flagged() {
+        let (mut node, mut wallet, b1_id, funding) = watching_bob();
+        let (_, theft) = steal_from_bob(&mut node, &mut wallet, b1_id, &funding);
+
+        let unauthorized: Vec<WalletEvent> = wallet.take_events().into_iter().filter(|event| event.is_urgent()).collect();
+        assert_eq!(
+            unauthorized,
+            [WalletEvent::UnauthorizedSpend { coin_id: funding.coin_id(1, 2), address: Address::Bob, tx_id: theft.id(), height: 2 }]
+        );
+    }
+
+    #[test]
+    fn unauthorized_spends_outlive_a_flood_of_events() {
+        let (mut node, mut wallet, b1_id, funding) = watching_bob();
+        let (b2_id, theft) = steal_from_bob(&mut node, &mut wallet, b1_id, &funding);
+
+        let payouts: Vec<Transaction> = (0..EVENT_CAPACITY / MAX_TX_OUTPUTS)
+            .map(|index| mint((0..MAX_TX_OUTPUTS).map(|_| (Address::Alice, index as u64 + 1))))
+            .collect();
+        node.add_block_as_best(b2_id, payouts);
+        wallet.sync(&node);
+
+        let events = wallet.take_events();
+        assert_eq!(events.len(), EVENT_CAPACITY);
+        assert!(events.iter().any(|event| matches!(event, WalletEvent::UnauthorizedSpend { tx_id, .. } if *tx_id == theft.id())));
+    }
+
+    #[test]
+    fn watches_survive_an_export() {
+        let (_, wallet, _, _) = watching_bob();
+
+        assert!(Wallet::import_state(&wallet.export_state()).unwrap().is_watchtower(&Address::Bob));
+    }
+}
@@ -0,0 +1,359 @@
+//! Withdrawals queued by an exchange or other integrator and paid out in batches.
+//!
+//! `queue_withdrawal` only records a request. `flush_withdrawals` pays all queued requests with as
+//! few transactions as the output limit allows, and marks them in flight under the transaction that
+//! pays them. Sync marks them confirmed once that transaction is mined, and back in flight if a reorg
+//! removes it again. The queue is part of the exported state, so a restart never pays a request twice.
+//!
+//! The coins of a batch in flight are reserved like those of `send_idempotent`, so a later flush or
+//! payment does not spend them again. A batch the node dropped, or that a conflicting transaction
+//! replaced, is put back in the queue with `requeue_withdrawal`.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use bonecoin_core::*;
+
+use crate::Wallet;
+
+/// Where a withdrawal request stands.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum WithdrawalStatus {
+    /// Waiting for the next flush.
+    Queued,
+    /// Paid by this transaction, which is not mined on the synced chain yet.
+    InFlight { tx_id: TransactionId },
+    /// Paid by this transaction, mined at this height.
+    Confirmed { tx_id: TransactionId, height: u64 },
+}
+
+/// A request to pay bones out of the wallet.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Withdrawal {
+    /// The id `queue_withdrawal` returned, increasing in queueing order.
+    pub id: u64,
+    /// Who to pay.
+    pub recipient: Address,
+    /// How many bones to pay.
+    pub amount: u64,
+    /// Where the request stands.
+    pub status: WithdrawalStatus,
+}
+
+/// The withdrawal requests, with the in-flight ones indexed by their transaction.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WithdrawalQueue {
+    pub(crate) next_id: u64,
+    pub(crate) requests: BTreeMap<u64, Withdrawal>,
+    pub(crate) in_flight: HashMap<TransactionId, Vec<u64>>,
+}
+
+impl WithdrawalQueue {
+    /// Rebuild the in-flight index from the requests.
+    pub(crate) fn reindex(&mut self) {
+        self.in_flight.clear();
+        for request in self.requests.values() {
+            if let WithdrawalStatus::InFlight { tx_id } = request.status {
+                self.in_flight.entry(tx_id).or_default().push(request.id);
+            }
+        }
+    }
+}
+
+impl Wallet {
+    /// Queue a payment of `amount` bones to `recipient` for the next `flush_withdrawals`, returning its id.
+    pub fn queue_withdrawal(&mut self, recipient: Address, amount: u64) -> WalletResult<u64> {
+        if amount == 0 {
+            return Err(WalletError::ZeroCoinValue);
+        }
+        let id = self.withdrawals.next_id;
+        self.withdrawals.next_id += 1;
+        self.withdrawals.requests.insert(
+            id,
+            Withdrawal {
+                id,
+                recipient,
+                amount,
+                status: WithdrawalStatus::Queued,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Pay every queued withdrawal, oldest first, in transactions of at most `max_outputs` payments
+    /// plus change, each burning `tip`. Dust change the dust policy adds to the payment goes to the
+    /// batch's oldest request. A batch never has more payments than `MAX_TX_OUTPUTS` leaves
+    /// room for beside the change. The transactions spend disjoint coins, none of them spent by a
+    /// batch still in flight, so they can all be broadcast at once. Returns no transactions if
+    /// nothing is queued.
+    ///
+    /// The coins a batch spends stay reserved until sync sees a block spend one of them, or the
+    /// batch is requeued. The reservations are not part of the exported state.
+    ///
+    /// Either every batch is built and its requests are marked in flight, or an error is returned and
+    /// all requests stay queued.
+    pub fn flush_withdrawals(&mut self, max_outputs: usize, tip: u64) -> WalletResult<Vec<Transaction>> {
+        let queued: Vec<&Withdrawal> = self
+            .withdrawals
+            .requests
+            .values()
+            .filter(|request| request.status == WithdrawalStatus::Queued)
+            .collect();
+        let mut spent = HashSet::new();
+        let mut batches = Vec::new();
+        for chunk in queued.chunks(max_outputs.clamp(1, MAX_TX_OUTPUTS - 1)) {
+            let total_needed = chunk
+                .iter()
+                .try_fold(tip, |total, request| total.checked_add(request.amount))
+                .ok_or(WalletError::InsufficientFunds {
+                    needed: u64::MAX,
+                    available: self.net_worth(),
+                })?;
+            let candidates = self
+                .coins
+                .iter()
+                .filter(|(coin_id, coin)| !spent.contains(*coin_id) && self.is_auto_spendable(coin_id, coin) && coin.is_native())
+                .map(|(coin_id, coin)| (*coin_id, coin.clone()))
+                .collect();
+            let selected = self.select_coins(candidates, total_needed)?;
+            let selected_value: u64 = selected.iter().map(|(_, coin)| coin.value).sum();
+            spent.extend(selected.iter().map(|(coin_id, _)| *coin_id));
+
+            let inputs = selected
+                .iter()
+                .map(|(coin_id, _)| Input {
+                    coin_id: *coin_id,
+                    signature: Signature::Invalid,
+                })
+                .collect();
+            let mut outputs: Vec<Coin> = chunk
+                .iter()
+                .map(|request| Coin {
+                    value: request.amount,
+                    owner: request.recipient.clone(),
+                    asset_id: None,
+                })
+                .collect();
+            let change_value = selected_value - total_needed;
+            if change_value > self.config.selection.change_tolerance() {
+                outputs.extend(self.change_output(change_value, None)?);
+                outputs[0].value += self.dust_for_payment(change_value, None);
+            }
+            self.check_change_outputs(outputs.len() - chunk.len())?;
+            let mut transaction = Transaction { version: TRANSACTION_VERSION, inputs, outputs };
+            self.sign_inputs(&mut transaction);
+            self.check_policy(&transaction)?;
+            batches.push((chunk.iter().map(|request| request.id).collect::<Vec<u64>>(), transaction));
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(requests = queued.len(), batches = batches.len(), "withdrawals flushed");
+        let mut transactions = Vec::with_capacity(batches.len());
+        for (ids, transaction) in batches {
+            let tx_id = transaction.id();
+            for id in &ids {
+                if let Some(request) = self.withdrawals.requests.get_mut(id) {
+                    request.status = WithdrawalStatus::InFlight { tx_id };
+                }
+            }
+            self.withdrawals.in_flight.insert(tx_id, ids);
+            self.reserve_inputs(&transaction);
+            transactions.push(transaction);
+        }
+        Ok(transactions)
+    }
+
+    /// A withdrawal request by id, or `None` if there is none.
+    pub fn withdrawal(&self, id: u64) -> Option<&Withdrawal> {
+        self.withdrawals.requests.get(&id)
+    }
+
+    /// Every withdrawal request, oldest first.
+    pub fn withdrawals(&self) -> impl Iterator<Item = &Withdrawal> {
+        self.withdrawals.requests.values()
+    }
+
+    /// Put the in-flight withdrawal `id` back in the queue, together with every other request its
+    /// batch pays, and release the coins the batch spends, so the next flush pays them again. For
+    /// batches the node dropped or a conflicting transaction replaced: if the old batch is mined
+    /// after all, its requests are paid twice.
+    /// Returns the ids put back in the queue, oldest first, which are none unless `id` is in flight.
+    pub fn requeue_withdrawal(&mut self, id: u64) -> Vec<u64> {
+        let Some(WithdrawalStatus::InFlight { tx_id }) = self.withdrawals.requests.get(&id).map(|request| request.status) else {
+            return Vec::new();
+        };
+        let mut ids = self.withdrawals.in_flight.remove(&tx_id).unwrap_or_default();
+        ids.sort_unstable();
+        for id in &ids {
+            if let Some(request) = self.withdrawals.requests.get_mut(id) {
+                request.status = WithdrawalStatus::Queued;
+            }
+        }
+        self.pending_spends.retain(|_, spender| *spender != tx_id);
+        ids
+    }
+
+    /// Confirm the withdrawals paid by a transaction mined at `height`.
+    pub(crate) fn observe_withdrawals(&mut self, transaction: &Transaction, height: u64) {
+        if self.withdrawals.in_flight.is_empty() {
+            return;
+        }
+        let tx_id = transaction.id();
+        for id in self.withdrawals.in_flight.remove(&tx_id).unwrap_or_default() {
+            if let Some(request) = self.withdrawals.requests.get_mut(&id) {
+                request.status = WithdrawalStatus::Confirmed { tx_id, height };
+            }
+        }
+    }
+
+    /// Put withdrawals confirmed above `height` back in flight.
+    pub(crate) fn forget_withdrawals_above(&mut self, height: u64) {
+        let mut reverted = false;
+        for request in self.withdrawals.requests.values_mut() {
+            if let WithdrawalStatus::Confirmed { tx_id, height: confirmed } = request.status {
+                if confirmed > height {
+                    request.status = WithdrawalStatus::InFlight { tx_id };
+                    reverted = true;
+                }
+            }
+        }
+        if reverted {
+            self.withdrawals.reindex();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice synced to a first block paying her two coins of 60 bones. Returns the
+    /// node, the wallet and the id of the block.
+    fn funded() -> (MockNode, Wallet, BlockId) {
+        let mut node = MockNode::new();
+        let mut wallet = wallet_with_alice();
+        let b1 = node.add_block_as_best(Block::genesis().id(), vec![mint([(Address::Alice, 60), (Address::Alice, 60)])]);
+        wallet.sync(&node);
+        (node, wallet, b1)
+    }
+
+    /// Queue withdrawals of 10, 20 and 30 bones to Bob, Charlie and Dave, and flush them in
+    /// batches of two. Returns the ids of the withdrawals and the batches.
+    fn queue_and_flush(wallet: &mut Wallet) -> (Vec<u64>, Vec<Transaction>) {
+        let ids = [(Address::Bob, 10), (Address::Charlie, 20), (Address::Dave, 30)]
+            .into_iter()
+            .map(|(recipient, amount)| wallet.queue_withdrawal(recipient, amount).unwrap())
+            .collect();
+        (ids, wallet.flush_withdrawals(2, 1).unwrap())
+    }
+
+    fn status(wallet: &Wallet, id: u64) -> WithdrawalStatus {
+        wallet.withdrawal(id).unwrap().status
+    }
+
+    #[test]
+    fn empty_queues_flush_nothing() {
+        let (_, mut wallet, _) = funded();
+        assert_eq!(wallet.flush_withdrawals(2, 1), Ok(vec![]));
+    }
+
+    #[test]
+    fn withdrawals_of_nothing_are_refused() {
+        let (_, mut wallet, _) = funded();
+        assert_eq!(wallet.queue_withdrawal(Address::Bob, 0), Err(WalletError::ZeroCoinValue));
+    }
+
+    #[test]
+    fn withdrawals_are_flushed_in_batches_spending_different_coins() {
+        let (_, mut wallet, _) = funded();
+        let (ids, batches) = queue_and_flush(&mut wallet);
+        // two payments fit in a batch, so the third gets its own, spending the other coin
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].outputs[..2].iter().map(|coin| coin.value).collect::<Vec<_>>(), [10, 20]);
+        assert_eq!(batches[1].outputs[0].value, 30);
+        assert!(batches[0].iter_input_coin_ids().all(|coin_id| !batches[1].iter_input_coin_ids().any(|other| other == coin_id)));
+        assert_eq!(status(&wallet, ids[2]), WithdrawalStatus::InFlight { tx_id: batches[1].id() });
+    }
+
+    #[test]
+    fn flushed_withdrawals_are_not_paid_twice() {
+        let (_, mut wallet, _) = funded();
+        queue_and_flush(&mut wallet);
+        assert_eq!(wallet.flush_withdrawals(2, 1), Ok(vec![]));
+    }
+
+    #[test]
+    fn mined_batches_confirm_their_withdrawals() {
+        let (mut node, mut wallet, b1) = funded();
+        let (ids, batches) = queue_and_flush(&mut wallet);
+        node.add_block_as_best(b1, vec![batches[0].clone()]);
+        wallet.sync(&node);
+        assert_eq!(status(&wallet, ids[0]), WithdrawalStatus::Confirmed { tx_id: batches[0].id(), height: 2 });
+        assert_eq!(status(&wallet, ids[2]), WithdrawalStatus::InFlight { tx_id: batches[1].id() });
+    }
+
+    #[test]
+    fn the_queue_survives_export() {
+        let (_, mut wallet, _) = funded();
+        queue_and_flush(&mut wallet);
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!(restored.withdrawals().collect::<Vec<_>>(), wallet.withdrawals().collect::<Vec<_>>());
+        assert_eq!(restored.withdrawals().count(), 3);
+    }
+
+    #[test]
+    fn reorgs_put_confirmed_batches_back_in_flight() {
+        let (mut node, mut wallet, b1) = funded();
+        let (ids, batches) = queue_and_flush(&mut wallet);
+        let b2 = node.add_block_as_best(b1, vec![batches[0].clone()]);
+        wallet.sync(&node);
+
+        let c2 = node.add_block(b1, vec![batches[1].clone()]);
+        node.add_block_as_best(c2, vec![]);
+        wallet.sync(&node);
+        assert_ne!(wallet.best_hash(), b2);
+        assert_eq!(status(&wallet, ids[0]), WithdrawalStatus::InFlight { tx_id: batches[0].id() });
+        assert_eq!(status(&wallet, ids[2]), WithdrawalStatus::Confirmed { tx_id: batches[1].id(), height: 2 });
+    }
+
+    #[test]
+    fn later_flushes_do_not_spend_the_coins_of_batches_in_flight() {
+        let (_, mut wallet, _) = funded();
+        wallet.queue_withdrawal(Address::Bob, 10).unwrap();
+        let first = wallet.flush_withdrawals(2, 1).unwrap();
+
+        wallet.queue_withdrawal(Address::Charlie, 20).unwrap();
+        let second = wallet.flush_withdrawals(2, 1).unwrap();
+        assert!(second[0].iter_input_coin_ids().all(|coin_id| !first[0].iter_input_coin_ids().any(|other| other == coin_id)));
+
+        // both coins are in flight now
+        wallet.queue_withdrawal(Address::Dave, 30).unwrap();
+        assert_eq!(wallet.flush_withdrawals(2, 1), Err(WalletError::InsufficientFunds { needed: 31, available: 0 }));
+    }
+
+    #[test]
+    fn requeued_batches_are_paid_again_by_the_next_flush() {
+        let (_, mut wallet, _) = funded();
+        let (ids, batches) = queue_and_flush(&mut wallet);
+
+        assert_eq!(wallet.requeue_withdrawal(ids[1]), ids[..2]);
+        assert_eq!(status(&wallet, ids[0]), WithdrawalStatus::Queued);
+        assert_eq!(status(&wallet, ids[2]), WithdrawalStatus::InFlight { tx_id: batches[1].id() });
+
+        let again = wallet.flush_withdrawals(2, 1).unwrap();
+        assert_eq!(again[0].iter_input_coin_ids().collect::<Vec<_>>(), batches[0].iter_input_coin_ids().collect::<Vec<_>>());
+        assert_eq!(status(&wallet, ids[0]), WithdrawalStatus::InFlight { tx_id: again[0].id() });
+    }
+
+    #[test]
+    fn only_withdrawals_in_flight_are_requeued() {
+        let (mut node, mut wallet, b1) = funded();
+        let (ids, batches) = queue_and_flush(&mut wallet);
+        node.add_block_as_best(b1, vec![batches[0].clone()]);
+        wallet.sync(&node);
+
+        assert!(wallet.requeue_withdrawal(ids[0]).is_empty());
+        assert!(wallet.requeue_withdrawal(99).is_empty());
+        let queued = wallet.queue_withdrawal(Address::Eve, 5).unwrap();
+        assert!(wallet.requeue_withdrawal(queued).is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+//! Checking the node's choice of chain against the work of the chain the wallet followed.
+//!
+//! With `WalletConfig::check_work` set, sync remembers the cumulative work the node reported for the
+//! wallet's best block. When the node later serves a chain that does not extend that block, sync first
+//! asks for the work of the node's new tip, and refuses to follow a chain with less work: an honest
+//! node never leaves a chain for a lighter one.
+
+use bonecoin_core::*;
+
+use crate::{SyncError, Wallet};
+
+impl Wallet {
+    /// The cumulative work the node reported for the wallet's best block at the last sync with
+    /// `WalletConfig::check_work` set, or `None` if it never reported any.
+    pub fn followed_work(&self) -> Option<u64> {
+        self.followed_work
+    }
+
+    /// Refuse a node whose best chain leaves the wallet's best block for a chain with less work.
+    pub(crate) fn check_work<Node: NodeEndpoint>(&self, node: &Node) -> Result<(), SyncError> {
+        let Some(followed) = self.followed_work.filter(|_| self.config.check_work) else {
+            return Ok(());
+        };
+        // extending the followed chain only adds work
+        if node.best_block_at_height(self.best_block_height) == Some(self.best_block_hash) {
+            return Ok(());
+        }
+        match node_tip(node, self.best_block_height).and_then(|tip| node.cumulative_work(&tip)) {
+            Some(offered) if offered < followed => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(followed, offered, "node switched to a chain with less work");
+                Err(SyncError::LessWork { followed, offered })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The node's best block, searched for from `height` down and then up.
+fn node_tip<Node: NodeEndpoint>(node: &Node, mut height: u64) -> Option<BlockId> {
+    let mut tip = node.best_block_at_height(height);
+    while tip.is_none() && height > 0 {
+        height -= 1;
+        tip = node.best_block_at_height(height);
+    }
+    while let Some(next) = node.best_block_at_height(height + 1) {
+        tip = Some(next);
+        height += 1;
+    }
+    tip
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    /// A wallet of Alice checking work, synced to a chain of two blocks, and a node that has
+    /// since switched to a fork of one easier block rewarding Alice. Returns the node, the wallet
+    /// and the id of the fork's block.
+    fn offered_a_lighter_fork() -> (MockNode, Wallet, BlockId) {
+        let mut node = MockNode::new();
+        let a1_id = node.add_block_as_best(Block::genesis().id(), vec![]);
+        node.add_block_as_best(a1_id, vec![]);
+        let mut wallet = Wallet::builder().address(Address::Alice).check_work(true).build().unwrap();
+        wallet.sync(&node);
+        assert_eq!(wallet.followed_work(), Some(3));
+
+        let b1_id = node.add_block_with_difficulty(Block::genesis().id(), 1, vec![Transaction::coinbase(Address::Alice, BLOCK_REWARD)]);
+        node.set_best(b1_id);
+        (node, wallet, b1_id)
+    }
+
+    /// Extend the fork past the work of the wallet's chain and sync to it.
+    fn outgrow(node: &mut MockNode, wallet: &mut Wallet, b1_id: BlockId) -> BlockId {
+        let b2_id = node.add_block_with_difficulty(b1_id, 5, vec![]);
+        node.set_best(b2_id);
+        assert!(wallet.try_sync(node).is_ok());
+        b2_id
+    }
+
+    #[test]
+    fn switches_to_a_lighter_chain_are_refused() {
+        let (node, mut wallet, _) = offered_a_lighter_fork();
+        let tip = wallet.best_hash();
+        assert_eq!(wallet.try_sync(&node), Err(SyncError::LessWork { followed: 3, offered: 2 }));
+        wallet.sync(&node);
+        assert_eq!((wallet.best_height(), wallet.best_hash()), (2, tip));
+    }
+
+    #[test]
+    fn switches_to_a_heavier_chain_are_followed() {
+        let (mut node, mut wallet, b1_id) = offered_a_lighter_fork();
+        let b2_id = outgrow(&mut node, &mut wallet, b1_id);
+        assert_eq!((wallet.best_hash(), wallet.followed_work(), wallet.net_worth()), (b2_id, Some(7), BLOCK_REWARD));
+    }
+
+    #[test]
+    fn the_followed_work_survives_export() {
+        let (mut node, mut wallet, b1_id) = offered_a_lighter_fork();
+        outgrow(&mut node, &mut wallet, b1_id);
+        let restored = Wallet::import_state(&wallet.export_state()).unwrap();
+        assert_eq!((restored.followed_work(), restored.config().check_work), (Some(7), true));
+    }
+}